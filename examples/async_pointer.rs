@@ -0,0 +1,295 @@
+extern crate smithay_client_toolkit as sctk;
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
+
+use sctk::reexports::client::protocol::{wl_pointer, wl_shm, wl_surface};
+use sctk::shm::MemPool;
+use sctk::window::{ConceptFrame, Event as WEvent};
+use sctk::{AsyncEventQueue, FdReadiness};
+
+sctk::default_environment!(AsyncExample, desktop);
+
+// With a real async runtime, the reactor is provided by the runtime, for example
+// `tokio::io::unix::AsyncFd` can implement `FdReadiness`. To keep this example free
+// of dependencies, it uses a minimal executor polling the futures after each `poll(2)`.
+#[derive(Default)]
+struct Reactor {
+    fds: RefCell<Vec<(RawFd, PollFlags)>>,
+    deadlines: RefCell<Vec<Instant>>,
+}
+
+impl Reactor {
+    // block until one of the registered fds is ready or a deadline is reached
+    fn wait(&self) {
+        let mut fds = self
+            .fds
+            .borrow_mut()
+            .drain(..)
+            .map(|(fd, flags)| PollFd::new(fd, flags))
+            .collect::<Vec<_>>();
+        let timeout = self
+            .deadlines
+            .borrow_mut()
+            .drain(..)
+            .min()
+            .map(|deadline| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining.as_millis() as i32 + 1
+            })
+            .unwrap_or(-1);
+        poll(&mut fds, timeout).expect("Failed to poll");
+    }
+}
+
+struct PollReadiness {
+    fd: RawFd,
+    reactor: Rc<Reactor>,
+}
+
+impl PollReadiness {
+    fn poll_flags(&self, flags: PollFlags) -> Poll<std::io::Result<()>> {
+        let mut fds = [PollFd::new(self.fd, flags)];
+        match poll(&mut fds, 0) {
+            Ok(0) => {
+                self.reactor.fds.borrow_mut().push((self.fd, flags));
+                Poll::Pending
+            }
+            Ok(_) => Poll::Ready(Ok(())),
+            Err(err) => Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, err))),
+        }
+    }
+}
+
+impl FdReadiness for PollReadiness {
+    fn poll_readable(&mut self, _: &mut Context) -> Poll<std::io::Result<()>> {
+        self.poll_flags(PollFlags::POLLIN)
+    }
+
+    fn poll_writable(&mut self, _: &mut Context) -> Poll<std::io::Result<()>> {
+        self.poll_flags(PollFlags::POLLOUT)
+    }
+}
+
+struct Sleep {
+    deadline: Instant,
+    reactor: Rc<Reactor>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            self.reactor.deadlines.borrow_mut().push(self.deadline);
+            Poll::Pending
+        }
+    }
+}
+
+enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+// Wait for the first of two futures, dropping the other
+struct Select<A, B>(Option<(A, B)>);
+
+impl<A: Future + Unpin, B: Future + Unpin> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let (ref mut a, ref mut b) = self.0.as_mut().expect("Polled after completion");
+        let ret = if let Poll::Ready(ret) = Pin::new(a).poll(cx) {
+            Either::Left(ret)
+        } else if let Poll::Ready(ret) = Pin::new(b).poll(cx) {
+            Either::Right(ret)
+        } else {
+            return Poll::Pending;
+        };
+        self.0 = None;
+        Poll::Ready(ret)
+    }
+}
+
+fn block_on<F: Future>(reactor: &Reactor, future: F) -> F::Output {
+    // the futures are polled again after each wakeup of the reactor, they don't
+    // need to be woken up
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+
+    let mut future = Box::pin(future);
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(ret) = future.as_mut().poll(&mut cx) {
+            return ret;
+        }
+        reactor.wait();
+    }
+}
+
+#[derive(Default)]
+struct State {
+    dimensions: (u32, u32),
+    redraw: bool,
+    exit: bool,
+}
+
+fn main() {
+    let (env, _display, queue) = sctk::init_default_environment!(AsyncExample, desktop)
+        .expect("Unable to connect to a Wayland compositor");
+
+    let surface = env.create_surface();
+
+    let mut window = env
+        .create_window::<ConceptFrame, _>(surface, (320, 240), |event, mut ddata| {
+            let state = ddata.get::<State>().unwrap();
+            match event {
+                WEvent::Configure { new_size, .. } => {
+                    if let Some(size) = new_size {
+                        state.dimensions = size;
+                    }
+                    state.redraw = true;
+                }
+                WEvent::Refresh => state.redraw = true,
+                WEvent::Close => state.exit = true,
+            }
+        })
+        .expect("Failed to create a window !");
+    window.set_title("Async pointer".into());
+
+    let mut pools = env
+        .create_double_pool(|_| {})
+        .expect("Failed to create a memory pool !");
+
+    // print the events of the pointers of the seats existing at startup
+    for seat in env.get_all_seats() {
+        let has_ptr = sctk::seat::with_seat_data(&seat, |seat_data| seat_data.has_pointer);
+        if has_ptr == Some(true) {
+            let pointer = seat.get_pointer();
+            let main_surface = window.surface().clone();
+            pointer.quick_assign(move |_, event, _| print_pointer_event(event, &main_surface));
+        }
+    }
+
+    let reactor = Rc::new(Reactor::default());
+    let mut queue = AsyncEventQueue::new(queue, |fd| {
+        Ok(PollReadiness {
+            fd,
+            reactor: reactor.clone(),
+        })
+    })
+    .expect("Failed to register the Wayland connection");
+
+    let mut state = State {
+        dimensions: (320, 240),
+        redraw: !env.get_shell().unwrap().needs_configure(),
+        exit: false,
+    };
+
+    block_on(&reactor, async {
+        let start = Instant::now();
+        let mut next_tick = start + Duration::from_secs(1);
+        loop {
+            if state.redraw {
+                state.redraw = false;
+                window.resize(state.dimensions.0, state.dimensions.1);
+                window.refresh();
+                if let Some(pool) = pools.pool() {
+                    redraw(pool, window.surface(), state.dimensions).expect("Failed to draw");
+                }
+            }
+
+            // await the Wayland events and a timer concurrently, dropping the
+            // dispatching future if the timer fires first is fine
+            let dispatch = Box::pin(queue.dispatch(&mut state, |_, _, _| {}));
+            let sleep = Sleep {
+                deadline: next_tick,
+                reactor: reactor.clone(),
+            };
+            match (Select(Some((dispatch, sleep)))).await {
+                Either::Left(ret) => {
+                    ret.expect("Failed to dispatch the Wayland events");
+                }
+                Either::Right(()) => {
+                    println!("Running for {}s", start.elapsed().as_secs());
+                    next_tick += Duration::from_secs(1);
+                }
+            }
+
+            if state.exit {
+                break;
+            }
+        }
+    });
+}
+
+fn redraw(
+    pool: &mut MemPool,
+    surface: &wl_surface::WlSurface,
+    (buf_x, buf_y): (u32, u32),
+) -> Result<(), ::std::io::Error> {
+    pool.resize((4 * buf_x * buf_y) as usize)
+        .expect("Failed to resize the memory pool.");
+    pool.seek(SeekFrom::Start(0))?;
+    {
+        let mut writer = BufWriter::new(&mut *pool);
+        for _ in 0..(buf_x * buf_y) {
+            writer.write_all(&0xFF_20_40_60u32.to_le_bytes())?;
+        }
+        writer.flush()?;
+    }
+    let new_buffer = pool.buffer(
+        0,
+        buf_x as i32,
+        buf_y as i32,
+        4 * buf_x as i32,
+        wl_shm::Format::Argb8888,
+    );
+    surface.attach(Some(&new_buffer), 0, 0);
+    surface.commit();
+    Ok(())
+}
+
+fn print_pointer_event(event: wl_pointer::Event, main_surface: &wl_surface::WlSurface) {
+    match event {
+        wl_pointer::Event::Enter {
+            surface,
+            surface_x,
+            surface_y,
+            ..
+        } => {
+            if main_surface == &surface {
+                println!("Pointer entered at ({}, {})", surface_x, surface_y);
+            }
+        }
+        wl_pointer::Event::Leave { surface, .. } => {
+            if main_surface == &surface {
+                println!("Pointer left");
+            }
+        }
+        wl_pointer::Event::Button { button, state, .. } => {
+            println!("Button {:?} was {:?}", button, state);
+        }
+        wl_pointer::Event::Motion {
+            surface_x,
+            surface_y,
+            ..
+        } => println!("Pointer motion to ({}, {})", surface_x, surface_y),
+        _ => {}
+    }
+}
@@ -5,7 +5,7 @@ extern crate smithay_client_toolkit as sctk;
 use std::env;
 use std::io::{BufWriter, Seek, SeekFrom, Write};
 
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sctk::reexports::client::protocol::{wl_shm, wl_surface};
 use sctk::shm::MemPool;
@@ -170,7 +170,9 @@ fn main() {
             }
             // We received a configure event, our action depends on its
             // contents
-            Some(WEvent::Configure { new_size, states }) => {
+            Some(WEvent::Configure {
+                new_size, states, ..
+            }) => {
                 // the configure event contains a suggested size,
                 // if it is different from our current size, we need to
                 // update it and redraw
@@ -294,13 +296,13 @@ fn redraw(
                 let b = ::std::cmp::min(0xFF, (0xFF * (0xFF - a) + a * b) / 0xFF);
                 // write the pixel
                 // We use byteorder, as the wayland protocol explicitly specifies
-                // that the pixels must be written in native endianness
-                writer.write_u32::<NativeEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
+                // that the pixels must be written in little endian
+                writer.write_u32::<LittleEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
             }
         } else {
             // We do not have any image to draw, so we draw black contents
             for _ in 0..(buf_x * buf_y) {
-                writer.write_u32::<NativeEndian>(0xFF000000)?;
+                writer.write_u32::<LittleEndian>(0xFF000000)?;
             }
         }
         // Don't forget to flush the writer, to make sure all the contents are
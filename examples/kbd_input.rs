@@ -4,7 +4,7 @@ extern crate smithay_client_toolkit as sctk;
 use std::cmp::min;
 use std::io::{BufWriter, Seek, SeekFrom, Write};
 
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sctk::reexports::calloop;
 use sctk::reexports::client::protocol::{wl_keyboard, wl_shm, wl_surface};
@@ -165,7 +165,9 @@ fn main() {
                 window.refresh();
                 window.surface().commit();
             }
-            Some(WEvent::Configure { new_size, states }) => {
+            Some(WEvent::Configure {
+                new_size, states, ..
+            }) => {
                 if let Some((w, h)) = new_size {
                     window.resize(w, h);
                     dimensions = (w, h)
@@ -204,7 +206,7 @@ fn print_keyboard_event(event: KbEvent, seat_name: &str) {
             utf8,
             ..
         } => {
-            println!("Key {:?}: {:x} on seat '{}'.", state, keysym, seat_name);
+            println!("Key {:?}: {:?} on seat '{}'.", state, keysym, seat_name);
             if let Some(txt) = utf8 {
                 println!(" -> Received text \"{}\".", txt);
             }
@@ -216,11 +218,14 @@ fn print_keyboard_event(event: KbEvent, seat_name: &str) {
             );
         }
         KbEvent::Repeat { keysym, utf8, .. } => {
-            println!("Key repetition {:x} on seat '{}'.", keysym, seat_name);
+            println!("Key repetition {:?} on seat '{}'.", keysym, seat_name);
             if let Some(txt) = utf8 {
                 println!(" -> Received text \"{}\".", txt);
             }
         }
+        KbEvent::KeymapError { error } => {
+            println!("Invalid keymap on seat '{}': {}", seat_name, error);
+        }
     }
 }
 
@@ -242,7 +247,7 @@ fn redraw(
             let r: u32 = min(((buf_x - x) * 0xFF) / buf_x, ((buf_y - y) * 0xFF) / buf_y);
             let g: u32 = min((x * 0xFF) / buf_x, ((buf_y - y) * 0xFF) / buf_y);
             let b: u32 = min(((buf_x - x) * 0xFF) / buf_x, (y * 0xFF) / buf_y);
-            writer.write_u32::<NativeEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
+            writer.write_u32::<LittleEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
         }
         writer.flush()?;
     }
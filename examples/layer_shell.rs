@@ -1,21 +1,19 @@
 use smithay_client_toolkit::{
     default_environment,
-    environment::SimpleGlobal,
+    environment::{Environment, SimpleGlobal},
     init_default_environment,
     output::{with_output_info, OutputInfo},
     reexports::{
         calloop,
         client::protocol::{wl_output, wl_shm, wl_surface},
-        client::{Attached, Main},
-        protocols::wlr::unstable::layer_shell::v1::client::{
-            zwlr_layer_shell_v1, zwlr_layer_surface_v1,
-        },
+        protocols::wlr::unstable::layer_shell::v1::client::zwlr_layer_shell_v1,
     },
+    shell::layer::{self, Anchor, ExclusiveZone, Layer, LayerSurface},
     shm::DoubleMemPool,
     WaylandSource,
 };
 
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use std::cell::{Cell, RefCell};
 use std::io::{BufWriter, Seek, SeekFrom, Write};
@@ -38,7 +36,8 @@ enum RenderEvent {
 
 struct Surface {
     surface: wl_surface::WlSurface,
-    layer_surface: Main<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
+    // destroyed when dropped, so it is kept alive with the surface
+    _layer_surface: LayerSurface,
     next_render_event: Rc<Cell<Option<RenderEvent>>>,
     pools: DoubleMemPool,
     dimensions: (u32, u32),
@@ -48,49 +47,47 @@ impl Surface {
     fn new(
         output: &wl_output::WlOutput,
         surface: wl_surface::WlSurface,
-        layer_shell: &Attached<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+        env: &Environment<Env>,
         pools: DoubleMemPool,
     ) -> Self {
-        let layer_surface = layer_shell.get_layer_surface(
-            &surface,
-            Some(&output),
-            zwlr_layer_shell_v1::Layer::Overlay,
-            "example".to_owned(),
-        );
+        let next_render_event = Rc::new(Cell::new(None::<RenderEvent>));
+        let next_render_event_handle = Rc::clone(&next_render_event);
+        let layer_surface = env
+            .create_layer_surface(
+                surface.clone(),
+                Some(output),
+                Layer::Overlay,
+                "example".to_owned(),
+                move |event, _| match (event, next_render_event_handle.get()) {
+                    (layer::Event::Closed, _) => {
+                        next_render_event_handle.set(Some(RenderEvent::Closed));
+                    }
+                    (
+                        layer::Event::Configure {
+                            new_size: (width, height),
+                        },
+                        next,
+                    ) if next != Some(RenderEvent::Closed) => {
+                        next_render_event_handle
+                            .set(Some(RenderEvent::Configure { width, height }));
+                    }
+                    (_, _) => {}
+                },
+            )
+            .expect("The surface already has a role");
 
         layer_surface.set_size(32, 32);
         // Anchor to the top left corner of the output
-        layer_surface
-            .set_anchor(zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Left);
-
-        let next_render_event = Rc::new(Cell::new(None::<RenderEvent>));
-        let next_render_event_handle = Rc::clone(&next_render_event);
-        layer_surface.quick_assign(move |layer_surface, event, _| {
-            match (event, next_render_event_handle.get()) {
-                (zwlr_layer_surface_v1::Event::Closed, _) => {
-                    next_render_event_handle.set(Some(RenderEvent::Closed));
-                }
-                (
-                    zwlr_layer_surface_v1::Event::Configure {
-                        serial,
-                        width,
-                        height,
-                    },
-                    next,
-                ) if next != Some(RenderEvent::Closed) => {
-                    layer_surface.ack_configure(serial);
-                    next_render_event_handle.set(Some(RenderEvent::Configure { width, height }));
-                }
-                (_, _) => {}
-            }
-        });
+        layer_surface.set_anchor(Anchor::Top | Anchor::Left);
+        // Being anchored to a corner, the surface does not reserve any space
+        layer_surface.set_exclusive_zone(ExclusiveZone::Auto);
 
         // Commit so that the server will send a configure event
         surface.commit();
 
         Self {
             surface,
-            layer_surface,
+            _layer_surface: layer_surface,
             next_render_event,
             pools,
             dimensions: (0, 0),
@@ -132,7 +129,7 @@ impl Surface {
         {
             let mut writer = BufWriter::new(&mut *pool);
             for _ in 0..(width * height) {
-                writer.write_u32::<NativeEndian>(0xff00ff00).unwrap();
+                writer.write_u32::<LittleEndian>(0xff00ff00).unwrap();
             }
             writer.flush().unwrap();
         }
@@ -149,7 +146,8 @@ impl Surface {
 
 impl Drop for Surface {
     fn drop(&mut self) {
-        self.layer_surface.destroy();
+        // the layer surface is destroyed by its own destructor, which runs
+        // before the one of the wl_surface
         self.surface.destroy();
     }
 }
@@ -161,8 +159,6 @@ fn main() {
 
     let surfaces = Rc::new(RefCell::new(Vec::new()));
 
-    let layer_shell = env.require_global::<zwlr_layer_shell_v1::ZwlrLayerShellV1>();
-
     let env_handle = env.clone();
     let surfaces_handle = Rc::clone(&surfaces);
     let output_handler = move |output: wl_output::WlOutput, info: &OutputInfo| {
@@ -176,10 +172,8 @@ fn main() {
             let pools = env_handle
                 .create_double_pool(|_| {})
                 .expect("Failed to create a memory pool!");
-            (*surfaces_handle.borrow_mut()).push((
-                info.id,
-                Surface::new(&output, surface, &layer_shell.clone(), pools),
-            ));
+            (*surfaces_handle.borrow_mut())
+                .push((info.id, Surface::new(&output, surface, &env_handle, pools)));
         }
     };
 
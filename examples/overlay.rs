@@ -0,0 +1,131 @@
+use smithay_client_toolkit::{
+    default_environment,
+    environment::SimpleGlobal,
+    init_default_environment,
+    reexports::{
+        calloop,
+        client::protocol::{wl_pointer, wl_shm},
+        protocols::wlr::unstable::layer_shell::v1::client::zwlr_layer_shell_v1,
+    },
+    shell::layer::{self, Anchor, ExclusiveZone, Layer},
+    WaylandSource,
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use std::cell::Cell;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+default_environment!(Env,
+    fields = [
+        layer_shell: SimpleGlobal<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    ],
+    singles = [
+        zwlr_layer_shell_v1::ZwlrLayerShellV1 => layer_shell
+    ],
+);
+
+// the size of the close button, in the top right corner of the overlay
+const BUTTON_SIZE: i32 = 48;
+
+fn main() {
+    let (env, display, queue) =
+        init_default_environment!(Env, fields = [layer_shell: SimpleGlobal::new(),])
+            .expect("Initial roundtrip failed!");
+
+    let configured = Rc::new(Cell::new(None));
+    let exit = Rc::new(Cell::new(false));
+
+    // A translucent overlay covering the top of the output, which lets all the clicks
+    // go through it except on its close button
+    let surface = env.create_surface();
+    let configured_handle = configured.clone();
+    let exit_handle = exit.clone();
+    let layer_surface = env
+        .create_layer_surface(
+            surface.clone(),
+            None,
+            Layer::Overlay,
+            "overlay".to_owned(),
+            move |event, _| match event {
+                layer::Event::Configure { new_size } => configured_handle.set(Some(new_size)),
+                layer::Event::Closed => exit_handle.set(true),
+            },
+        )
+        .expect("The surface already has a role");
+    layer_surface.set_size(0, 200);
+    layer_surface.set_anchor(Anchor::Top | Anchor::Left | Anchor::Right);
+    layer_surface.set_exclusive_zone(ExclusiveZone::Ignore);
+
+    let mut shape = env.create_input_shape(surface.clone());
+    // anchored to the right edge of the surface, whatever its width
+    shape.add_rect(-BUTTON_SIZE, 0, BUTTON_SIZE, BUTTON_SIZE);
+    shape.commit();
+
+    // the pointer events are only received over the button
+    for seat in env.get_all_seats() {
+        let has_ptr =
+            smithay_client_toolkit::seat::with_seat_data(&seat, |seat_data| seat_data.has_pointer);
+        if has_ptr == Some(true) {
+            let pointer = seat.get_pointer();
+            let exit_handle = exit.clone();
+            pointer.quick_assign(move |_, event, _| {
+                if let wl_pointer::Event::Button {
+                    state: wl_pointer::ButtonState::Pressed,
+                    ..
+                } = event
+                {
+                    println!("Close button clicked");
+                    exit_handle.set(true);
+                }
+            });
+        }
+    }
+
+    let mut pools = env
+        .create_double_pool(|_| {})
+        .expect("Failed to create a memory pool!");
+
+    let mut event_loop = calloop::EventLoop::<()>::new().unwrap();
+    WaylandSource::new(queue)
+        .quick_insert(event_loop.handle())
+        .unwrap();
+
+    while !exit.get() {
+        if let Some((width, height)) = configured.take() {
+            // the button follows the right edge of the surface
+            shape.set_size(width, height);
+            if let Some(pool) = pools.pool() {
+                let stride = 4 * width as i32;
+                pool.resize((stride * height as i32) as usize).unwrap();
+                pool.seek(SeekFrom::Start(0)).unwrap();
+                {
+                    let mut writer = BufWriter::new(&mut *pool);
+                    for y in 0..height as i32 {
+                        for x in 0..width as i32 {
+                            let on_button = x >= width as i32 - BUTTON_SIZE && y < BUTTON_SIZE;
+                            // premultiplied translucent blue, and an opaque red button
+                            let color = if on_button { 0xffff0000 } else { 0x40000040 };
+                            writer.write_u32::<LittleEndian>(color).unwrap();
+                        }
+                    }
+                    writer.flush().unwrap();
+                }
+                let buffer = pool.buffer(
+                    0,
+                    width as i32,
+                    height as i32,
+                    stride,
+                    wl_shm::Format::Argb8888,
+                );
+                surface.attach(Some(&buffer), 0, 0);
+                surface.damage_buffer(0, 0, width as i32, height as i32);
+            }
+            shape.commit();
+        }
+
+        display.flush().unwrap();
+        event_loop.dispatch(None, &mut ()).unwrap();
+    }
+}
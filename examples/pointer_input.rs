@@ -4,11 +4,12 @@ extern crate smithay_client_toolkit as sctk;
 use std::cmp::min;
 use std::io::{BufWriter, Seek, SeekFrom, Write};
 
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sctk::reexports::client::protocol::{wl_pointer, wl_shm, wl_surface};
 use sctk::shm::MemPool;
 use sctk::window::{ConceptFrame, Event as WEvent};
+use sctk::ScaleFactor;
 
 #[derive(Debug)]
 enum NextAction {
@@ -20,7 +21,7 @@ enum NextAction {
 struct WindowConfig {
     width: u32,
     height: u32,
-    dpi_scale: i32,
+    dpi_scale: ScaleFactor,
     next_action: Option<NextAction>,
     has_drawn_once: bool,
 }
@@ -30,7 +31,7 @@ impl WindowConfig {
         WindowConfig {
             width: 320,
             height: 240,
-            dpi_scale: 1,
+            dpi_scale: ScaleFactor::ONE,
             next_action: None,
             has_drawn_once: false,
         }
@@ -38,8 +39,8 @@ impl WindowConfig {
 
     pub fn dimensions(&self) -> (u32, u32) {
         (
-            self.width * self.dpi_scale as u32,
-            self.height * self.dpi_scale as u32,
+            self.width * self.dpi_scale.get(),
+            self.height * self.dpi_scale.get(),
         )
     }
 
@@ -73,7 +74,7 @@ fn main() {
 
     let surface = env.create_surface_with_scale_callback(move |dpi, surface, mut dispatch_data| {
         let config = dispatch_data.get::<WindowConfig>().unwrap();
-        surface.set_buffer_scale(dpi);
+        surface.set_buffer_scale(dpi.to_i32());
         config.dpi_scale = dpi;
         config.handle_action(NextAction::Redraw);
     });
@@ -220,7 +221,7 @@ fn redraw(
             let r: u32 = min(((buf_x - x) * 0xFF) / buf_x, ((buf_y - y) * 0xFF) / buf_y);
             let g: u32 = min((x * 0xFF) / buf_x, ((buf_y - y) * 0xFF) / buf_y);
             let b: u32 = min(((buf_x - x) * 0xFF) / buf_x, (y * 0xFF) / buf_y);
-            writer.write_u32::<NativeEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
+            writer.write_u32::<LittleEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
         }
         writer.flush()?;
     }
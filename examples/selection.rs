@@ -3,7 +3,7 @@ extern crate smithay_client_toolkit as sctk;
 
 use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sctk::{
     data_device::{DataSourceEvent, ReadPipe},
@@ -360,7 +360,7 @@ fn redraw(
     {
         let mut writer = BufWriter::new(&mut *pool);
         for _ in 0..(buf_x * buf_y) {
-            writer.write_u32::<NativeEndian>(0xFF000000)?;
+            writer.write_u32::<LittleEndian>(0xFF000000)?;
         }
         writer.flush()?;
     }
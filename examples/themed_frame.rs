@@ -4,7 +4,7 @@ extern crate smithay_client_toolkit as sctk;
 use std::cmp::min;
 use std::io::{BufWriter, Seek, SeekFrom, Write};
 
-use byteorder::{NativeEndian, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sctk::reexports::client::protocol::{wl_shm, wl_surface};
 use sctk::shm::MemPool;
@@ -65,6 +65,11 @@ fn create_frame_config() -> ConceptConfig {
         title_font: Some(("sans".into(), 17.0)),
         // clear text over dark background
         title_color: ColorSpec::identical([0xFF, 0xD0, 0xD0, 0xD0].into()),
+        // compact titlebar with spaced buttons
+        header_height: 26,
+        button_size: 22,
+        button_spacing: 4,
+        border_width: 12,
     }
 }
 
@@ -131,7 +136,9 @@ fn main() {
                 window.refresh();
                 window.surface().commit();
             }
-            Some(WEvent::Configure { new_size, states }) => {
+            Some(WEvent::Configure {
+                new_size, states, ..
+            }) => {
                 if let Some((w, h)) = new_size {
                     window.resize(w, h);
                     dimensions = (w, h)
@@ -167,7 +174,7 @@ fn redraw(
             let r: u32 = min(((buf_x - x) * 0xFF) / buf_x, ((buf_y - y) * 0xFF) / buf_y);
             let g: u32 = min((x * 0xFF) / buf_x, ((buf_y - y) * 0xFF) / buf_y);
             let b: u32 = min(((buf_x - x) * 0xFF) / buf_x, (y * 0xFF) / buf_y);
-            writer.write_u32::<NativeEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
+            writer.write_u32::<LittleEndian>((0xFF << 24) + (r << 16) + (g << 8) + b)?;
         }
         writer.flush()?;
     }
@@ -0,0 +1,180 @@
+extern crate smithay_client_toolkit as sctk;
+
+use std::cell::Cell;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::thread;
+use std::time::Duration;
+
+use sctk::reexports::client::protocol::{wl_shm, wl_surface};
+use sctk::reexports::client::{Display, EventQueue};
+use sctk::shm::DoubleMemPool;
+use sctk::window::{ConceptFrame, Event as WEvent};
+
+sctk::default_environment!(ThreadedExample, desktop);
+
+struct State {
+    dimensions: Option<(u32, u32)>,
+    configured: bool,
+    exit: bool,
+}
+
+fn main() {
+    let (env, display, mut queue) = sctk::init_default_environment!(ThreadedExample, desktop)
+        .expect("Unable to connect to a Wayland compositor");
+
+    let surface = env.create_surface();
+
+    // the main thread handles the window, and sends the configured size to the
+    // rendering thread
+    let (size_sender, size_receiver) = mpsc::channel::<(u32, u32)>();
+    let mut dimensions = (320u32, 240u32);
+
+    let mut window = env
+        .create_window::<ConceptFrame, _>(surface, dimensions, |event, mut ddata| {
+            let state = ddata.get::<State>().unwrap();
+            match event {
+                WEvent::Configure { new_size, .. } => {
+                    if let Some(size) = new_size {
+                        state.dimensions = Some(size);
+                    }
+                    state.configured = true;
+                }
+                WEvent::Close => state.exit = true,
+                WEvent::Refresh => {}
+            }
+        })
+        .expect("Failed to create a window !");
+    window.set_title("Threaded rendering".into());
+
+    // the proxies are Send, the rendering thread attaches them to its own event queue
+    let shm = env.require_global::<wl_shm::WlShm>().detach();
+    let render_surface = window.surface().clone();
+    let render_display = display.clone();
+    let stop = Arc::new(AtomicBool::new(false));
+    let render_stop = stop.clone();
+    let renderer = thread::spawn(move || {
+        render(
+            render_display,
+            shm,
+            render_surface,
+            size_receiver,
+            render_stop,
+        )
+    });
+
+    let mut state = State {
+        dimensions: None,
+        configured: !env.get_shell().unwrap().needs_configure(),
+        exit: false,
+    };
+
+    loop {
+        if state.configured {
+            state.configured = false;
+            if let Some(size) = state.dimensions.take() {
+                dimensions = size;
+            }
+            window.resize(dimensions.0, dimensions.1);
+            window.refresh();
+            if size_sender.send(dimensions).is_err() {
+                break;
+            }
+        }
+        if state.exit {
+            break;
+        }
+        queue.dispatch(&mut state, |_, _, _| {}).unwrap();
+    }
+
+    stop.store(true, Ordering::SeqCst);
+    // wake up the rendering thread if it waits for a size
+    drop(size_sender);
+    renderer.join().unwrap();
+}
+
+// Draw an animation in the window, independently from the main thread
+fn render(
+    display: Display,
+    shm: wl_shm::WlShm,
+    surface: wl_surface::WlSurface,
+    sizes: mpsc::Receiver<(u32, u32)>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut queue: EventQueue = display.create_event_queue();
+    // the pools and buffers are created on this queue, their release events are
+    // received by this thread
+    let shm = shm.as_ref().attach(queue.token());
+    let surface = surface.as_ref().attach(queue.token());
+    let mut pools = DoubleMemPool::new(shm, |_| {}).expect("Failed to create a memory pool !");
+
+    // wait for the window to be configured before drawing anything
+    let mut dimensions = match sizes.recv() {
+        Ok(size) => size,
+        Err(_) => return,
+    };
+    let mut frame = 0u32;
+    while !stop.load(Ordering::SeqCst) {
+        while let Ok(size) = sizes.try_recv() {
+            dimensions = size;
+        }
+
+        if let Some(pool) = pools.pool() {
+            redraw(pool, &surface, dimensions, frame).expect("Failed to draw");
+            frame = frame.wrapping_add(1);
+        }
+
+        // the frame callback is also handled by this queue
+        let done = Rc::new(Cell::new(false));
+        let my_done = done.clone();
+        surface
+            .frame()
+            .quick_assign(move |_, _, _| my_done.set(true));
+        surface.commit();
+        // the compositor stops sending frame callbacks once the window is hidden, so the
+        // stop flag is checked periodically rather than blocking until the next event
+        while !done.get() && !stop.load(Ordering::SeqCst) {
+            sctk::dispatch_with_timeout(
+                &mut queue,
+                Duration::from_millis(100),
+                &mut (),
+                |_, _, _| {},
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn redraw(
+    pool: &mut sctk::shm::MemPool,
+    surface: &wl_surface::WlSurface,
+    (buf_x, buf_y): (u32, u32),
+    frame: u32,
+) -> Result<(), ::std::io::Error> {
+    pool.resize((4 * buf_x * buf_y) as usize)
+        .expect("Failed to resize the memory pool.");
+    pool.seek(SeekFrom::Start(0))?;
+    {
+        let mut writer = BufWriter::new(&mut *pool);
+        let shade = frame % 0x100;
+        let color = 0xFF00_0000 | (shade << 16) | (0x40 << 8) | (0xFF - shade);
+        for _ in 0..(buf_x * buf_y) {
+            writer.write_all(&color.to_le_bytes())?;
+        }
+        writer.flush()?;
+    }
+    let new_buffer = pool.buffer(
+        0,
+        buf_x as i32,
+        buf_y as i32,
+        4 * buf_x as i32,
+        wl_shm::Format::Argb8888,
+    );
+    surface.attach(Some(&new_buffer), 0, 0);
+    surface.damage_buffer(0, 0, buf_x as i32, buf_y as i32);
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use std::{
+    future::Future,
+    io,
+    os::unix::io::RawFd,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use wayland_client::{AnonymousObject, DispatchData, EventQueue, Main, RawEvent};
+
+use crate::environment::with_protocol_error;
+
+/// The readiness of the Wayland connection, as reported by an async reactor
+///
+/// This is the glue between an [`AsyncEventQueue`](struct.AsyncEventQueue.html) and the
+/// reactor of your async runtime, which is notified when the Wayland socket is
+/// readable or writable. It is typically implemented on top of `tokio::io::unix::AsyncFd`
+/// or `async_io::Async`.
+///
+/// Reactors usually cache the readiness of a file descriptor until an operation on it
+/// fails with `WouldBlock`. The `clear_*` methods are invoked when that happens, so that
+/// the next `poll_*` call waits for a new readiness notification.
+pub trait FdReadiness {
+    /// Poll for the socket to be readable
+    fn poll_readable(&mut self, cx: &mut Context) -> Poll<io::Result<()>>;
+    /// Poll for the socket to be writable
+    fn poll_writable(&mut self, cx: &mut Context) -> Poll<io::Result<()>>;
+    /// Reading the socket failed with `WouldBlock`
+    fn clear_readable(&mut self) {}
+    /// Writing to the socket failed with `WouldBlock`
+    fn clear_writable(&mut self) {}
+}
+
+/// An adapter to drive a Wayland `EventQueue` from an async runtime
+///
+/// Rather than blocking a thread in `EventQueue::dispatch()`, you can await
+/// [`dispatch`](#method.dispatch) concurrently with the other futures of your app. The
+/// connection socket is registered with the reactor of your runtime through a
+/// [`FdReadiness`](trait.FdReadiness.html) implementation.
+///
+/// Both `dispatch` and [`flush`](#method.flush) are cancellation-safe: if their
+/// future is dropped before completion (for example because another branch of a
+/// `select!` completed first), no event is lost, and the events already read are
+/// dispatched by the next call. In particular the connection is never left prepared
+/// for reading across an await point, which would prevent the other readers of the
+/// connection from reading it.
+///
+/// If the connection fails because of a protocol error, the errors they return carry a
+/// [`ConnectionError`](environment/enum.ConnectionError.html) describing it.
+///
+/// **Note:** This requires the `async` cargo feature.
+pub struct AsyncEventQueue<R> {
+    queue: EventQueue,
+    readiness: R,
+}
+
+impl<R: FdReadiness> AsyncEventQueue<R> {
+    /// Wrap an `EventQueue`
+    ///
+    /// The `register` closure is given the connection socket, and should register it
+    /// with the reactor of your runtime.
+    pub fn new<F>(queue: EventQueue, register: F) -> io::Result<AsyncEventQueue<R>>
+    where
+        F: FnOnce(RawFd) -> io::Result<R>,
+    {
+        let readiness = register(queue.display().get_connection_fd())?;
+        Ok(AsyncEventQueue { queue, readiness })
+    }
+
+    /// Access the wrapped `EventQueue`
+    pub fn queue(&self) -> &EventQueue {
+        &self.queue
+    }
+
+    /// Unwrap the `EventQueue`
+    pub fn into_inner(self) -> EventQueue {
+        self.queue
+    }
+
+    /// Dispatch the events of this queue, waiting for some if none is pending
+    ///
+    /// This is the async equivalent of `EventQueue::dispatch()`: the requests are
+    /// flushed to the compositor, and the events are read from the connection once it
+    /// is readable. It returns the number of dispatched events, which is never zero,
+    /// orphan events are given to the `fallback` closure.
+    pub async fn dispatch<T, F>(&mut self, data: &mut T, mut fallback: F) -> io::Result<u32>
+    where
+        T: std::any::Any,
+        F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData),
+    {
+        loop {
+            // the events may have been read by a previous call or another queue
+            let dispatched = self
+                .queue
+                .dispatch_pending(data, &mut fallback)
+                .map_err(|err| with_protocol_error(self.queue.display(), err))?;
+            if dispatched > 0 {
+                return Ok(dispatched);
+            }
+
+            self.flush().await?;
+            Ready {
+                readiness: &mut self.readiness,
+                writable: false,
+            }
+            .await?;
+
+            // there is no await point between preparing the read and reading, so this
+            // can not be left half-done if the future is dropped
+            if let Some(guard) = self.queue.prepare_read() {
+                match guard.read_events() {
+                    Ok(()) => {}
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        self.readiness.clear_readable();
+                    }
+                    Err(err) => return Err(with_protocol_error(self.queue.display(), err)),
+                }
+            }
+        }
+    }
+
+    /// Flush the requests to the compositor
+    ///
+    /// If the socket is full, this waits for it to be writable again rather than
+    /// failing with `WouldBlock`.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        loop {
+            match self.queue.display().flush() {
+                Ok(()) => return Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.readiness.clear_writable();
+                    Ready {
+                        readiness: &mut self.readiness,
+                        writable: true,
+                    }
+                    .await?;
+                }
+                Err(err) => return Err(with_protocol_error(self.queue.display(), err)),
+            }
+        }
+    }
+}
+
+// Wait for the socket to be readable or writable
+struct Ready<'a, R> {
+    readiness: &'a mut R,
+    writable: bool,
+}
+
+impl<'a, R: FdReadiness> Future for Ready<'a, R> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        if me.writable {
+            me.readiness.poll_writable(cx)
+        } else {
+            me.readiness.poll_readable(cx)
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::{Arc, Mutex};
+
+use wayland_client::{protocol::wl_seat, Attached, DispatchData};
+
+use wayland_protocols::wlr::unstable::data_control::v1::client::{
+    zwlr_data_control_device_v1, zwlr_data_control_manager_v1, zwlr_data_control_offer_v1,
+};
+
+use super::{DataControlOffer, DataControlSource};
+
+/// Possible events generated by a data control device
+pub enum DataControlEvent {
+    /// The selection of the seat changed
+    ///
+    /// The offer is complete: all its mime types have already been received. It is
+    /// `None` if the selection was cleared.
+    Selection {
+        /// The new selection offer
+        offer: Option<DataControlOffer>,
+    },
+    /// The primary selection of the seat changed
+    ///
+    /// This requires version 2 of the `zwlr_data_control_manager_v1` global. The offer
+    /// is complete, and is `None` if the primary selection was cleared.
+    PrimarySelection {
+        /// The new primary selection offer
+        offer: Option<DataControlOffer>,
+    },
+    /// This device is no longer valid
+    ///
+    /// This happens for example if its seat was removed, you should drop it.
+    Finished,
+}
+
+struct Inner {
+    // offers introduced by the server, each of them is referenced by the
+    // next selection or primary selection event
+    pending: Vec<DataControlOffer>,
+    selection: Option<DataControlOffer>,
+    primary_selection: Option<DataControlOffer>,
+}
+
+impl Inner {
+    fn take_offer(
+        &mut self,
+        offer: Option<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1>,
+    ) -> Option<DataControlOffer> {
+        let offer = offer?;
+        match self.pending.iter().position(|o| o.offer == offer) {
+            Some(id) => Some(self.pending.swap_remove(id)),
+            None => {
+                // an offer can be used for both the selection and the primary selection
+                self.selection
+                    .iter()
+                    .chain(self.primary_selection.iter())
+                    .find(|o| o.offer == offer)
+                    .cloned()
+            }
+        }
+    }
+}
+
+/// Handle to control the selections of a seat
+///
+/// Unlike a `DataDevice`, it gives access to the selections of the seat even if none of
+/// your surfaces has the keyboard focus, and lets you set them without any input serial.
+/// It is meant for privileged clients like clipboard managers.
+pub struct DataControlDevice {
+    device: zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl DataControlDevice {
+    /// Create the data control device for this seat
+    ///
+    /// The callback is invoked whenever a selection of the seat changes, starting with
+    /// the current ones.
+    pub fn init_for_seat<F>(
+        manager: &Attached<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>,
+        seat: &wl_seat::WlSeat,
+        mut callback: F,
+    ) -> DataControlDevice
+    where
+        F: FnMut(DataControlEvent, DispatchData) + 'static,
+    {
+        let inner = Arc::new(Mutex::new(Inner {
+            pending: Vec::new(),
+            selection: None,
+            primary_selection: None,
+        }));
+        let inner_2 = inner.clone();
+        let device = manager.get_data_device(seat);
+        device.quick_assign(move |device, event, ddata| {
+            use self::zwlr_data_control_device_v1::Event;
+            let mut inner = inner_2.lock().unwrap();
+            let event = match event {
+                Event::DataOffer { id } => {
+                    inner.pending.push(DataControlOffer::new(id));
+                    return;
+                }
+                Event::Selection { id } => {
+                    let offer = inner.take_offer(id);
+                    inner.selection = offer.clone();
+                    DataControlEvent::Selection { offer }
+                }
+                Event::PrimarySelection { id } => {
+                    let offer = inner.take_offer(id);
+                    inner.primary_selection = offer.clone();
+                    DataControlEvent::PrimarySelection { offer }
+                }
+                Event::Finished => {
+                    device.destroy();
+                    DataControlEvent::Finished
+                }
+                _ => unreachable!(),
+            };
+            // release the lock so that the callback can access the device
+            drop(inner);
+            callback(event, ddata);
+        });
+
+        DataControlDevice {
+            device: device.detach(),
+            inner,
+        }
+    }
+
+    /// Set the selection of the seat
+    ///
+    /// Setting the source to `None` will clear the selection.
+    pub fn set_selection(&self, source: &Option<DataControlSource>) {
+        self.device
+            .set_selection(source.as_ref().map(|s| &s.source));
+    }
+
+    /// Whether the primary selection can be accessed with this device
+    ///
+    /// This requires version 2 of the `zwlr_data_control_manager_v1` global.
+    pub fn supports_primary_selection(&self) -> bool {
+        self.device.as_ref().version() >= 2
+    }
+
+    /// Set the primary selection of the seat
+    ///
+    /// Setting the source to `None` will clear the primary selection. This request is
+    /// ignored if the primary selection is not supported.
+    pub fn set_primary_selection(&self, source: &Option<DataControlSource>) {
+        if !self.supports_primary_selection() {
+            log::warn!("[SCTK] The compositor does not support controlling the primary selection");
+            return;
+        }
+        self.device
+            .set_primary_selection(source.as_ref().map(|s| &s.source));
+    }
+
+    /// The current selection of the seat
+    pub fn selection(&self) -> Option<DataControlOffer> {
+        self.inner.lock().unwrap().selection.clone()
+    }
+
+    /// The current primary selection of the seat
+    pub fn primary_selection(&self) -> Option<DataControlOffer> {
+        self.inner.lock().unwrap().primary_selection.clone()
+    }
+}
+
+impl Drop for DataControlDevice {
+    fn drop(&mut self) {
+        self.device.destroy();
+    }
+}
@@ -0,0 +1,96 @@
+//! Helpers to control the selections of a seat with `zwlr_data_control_manager_v1`
+//!
+//! This protocol gives privileged clients, like clipboard managers, access to the
+//! selection and primary selection of seats without requiring the keyboard focus or
+//! any input serial. It is not part of the
+//! [`default_environment!`](../macro.default_environment.html), you need to add the
+//! `zwlr_data_control_manager_v1` global to your environment, for example as a
+//! [`SimpleGlobal`](../environment/struct.SimpleGlobal.html).
+//!
+//! The offers and sources use the same [`ReadPipe`](../data_device/struct.ReadPipe.html)
+//! and [`WritePipe`](../data_device/struct.WritePipe.html) as the data device.
+//!
+//! With the `calloop` cargo feature, a [`PersistentClipboard`](struct.PersistentClipboard.html)
+//! keeps the selections of a seat available after the client owning them exited.
+
+use wayland_client::{protocol::wl_seat, DispatchData};
+
+use wayland_protocols::wlr::unstable::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+
+use crate::environment::{Environment, GlobalHandler};
+
+mod device;
+mod offer;
+#[cfg(feature = "calloop")]
+mod persist;
+mod source;
+
+pub use self::device::{DataControlDevice, DataControlEvent};
+pub use self::offer::DataControlOffer;
+#[cfg(feature = "calloop")]
+pub use self::persist::{PersistConfig, PersistentClipboard, PERSIST_MIME_TYPE};
+pub use self::source::{DataControlSource, DataControlSourceEvent};
+
+impl<E: GlobalHandler<ZwlrDataControlManagerV1>> Environment<E> {
+    /// Create a data control device for this seat
+    ///
+    /// See [`DataControlDevice::init_for_seat`](struct.DataControlDevice.html#method.init_for_seat)
+    /// for details.
+    ///
+    /// Returns `None` if the compositor does not support `zwlr_data_control_manager_v1`.
+    pub fn new_data_control_device<F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        callback: F,
+    ) -> Option<DataControlDevice>
+    where
+        F: FnMut(DataControlEvent, DispatchData) + 'static,
+    {
+        let manager = self.get_global::<ZwlrDataControlManagerV1>()?;
+        Some(DataControlDevice::init_for_seat(&manager, seat, callback))
+    }
+
+    /// Create a new data control source
+    ///
+    /// Returns `None` if the compositor does not support `zwlr_data_control_manager_v1`.
+    pub fn new_data_control_source<F>(
+        &self,
+        mime_types: Vec<String>,
+        callback: F,
+    ) -> Option<DataControlSource>
+    where
+        F: FnMut(DataControlSourceEvent, DispatchData) + 'static,
+    {
+        let manager = self.get_global::<ZwlrDataControlManagerV1>()?;
+        Some(DataControlSource::new(&manager, mime_types, callback))
+    }
+
+    /// Start persisting the selections of a seat
+    ///
+    /// See [`PersistentClipboard::new`](struct.PersistentClipboard.html#method.new) for details.
+    ///
+    /// Returns `None` if the compositor does not support `zwlr_data_control_manager_v1`.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn new_persistent_clipboard<Data, F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        loop_handle: &calloop::LoopHandle<Data>,
+        config: PersistConfig,
+        callback: F,
+    ) -> Option<PersistentClipboard>
+    where
+        Data: 'static,
+        F: FnMut(DataControlEvent, DispatchData) + 'static,
+    {
+        let manager = self.get_global::<ZwlrDataControlManagerV1>()?;
+        Some(PersistentClipboard::new(
+            &manager,
+            seat,
+            loop_handle,
+            config,
+            callback,
+        ))
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use wayland_client::Main;
+
+use wayland_protocols::wlr::unstable::data_control::v1::client::zwlr_data_control_offer_v1;
+
+use crate::data_device::{receive_pipe, ReadPipe};
+
+// Destroys the offer once the last handle to it is dropped
+struct OfferGuard(zwlr_data_control_offer_v1::ZwlrDataControlOfferV1);
+
+impl Drop for OfferGuard {
+    fn drop(&mut self) {
+        self.0.destroy();
+    }
+}
+
+/// A data control offer, for receiving the contents of a selection
+///
+/// This handle can be cloned, the offer is destroyed once all of its clones
+/// are dropped.
+#[derive(Clone)]
+pub struct DataControlOffer {
+    pub(crate) offer: zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+    mime_types: Arc<Mutex<Vec<String>>>,
+    _guard: Arc<OfferGuard>,
+}
+
+impl DataControlOffer {
+    pub(crate) fn new(offer: Main<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1>) -> Self {
+        let mime_types = Arc::new(Mutex::new(Vec::new()));
+        let mime_types_2 = mime_types.clone();
+        offer.quick_assign(move |_, event, _| {
+            use self::zwlr_data_control_offer_v1::Event;
+            match event {
+                Event::Offer { mime_type } => mime_types_2.lock().unwrap().push(mime_type),
+                _ => unreachable!(),
+            }
+        });
+
+        let offer = offer.detach();
+        DataControlOffer {
+            _guard: Arc::new(OfferGuard(offer.clone())),
+            offer,
+            mime_types,
+        }
+    }
+
+    /// Access the list of mime types proposed by this offer
+    pub fn with_mime_types<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&[String]) -> T,
+    {
+        f(&self.mime_types.lock().unwrap())
+    }
+
+    /// Request to receive the data of a given mime type
+    ///
+    /// Note that you should **not** read the contents right away in a blocking way,
+    /// as you may deadlock your application.
+    ///
+    /// Fails if too many file descriptors were already open and a pipe
+    /// could not be created.
+    pub fn receive(&self, mime_type: String) -> io::Result<ReadPipe> {
+        receive_pipe(|fd| self.offer.receive(mime_type, fd))
+    }
+}
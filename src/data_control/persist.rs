@@ -0,0 +1,347 @@
+use std::{
+    cell::RefCell,
+    io, mem,
+    rc::{Rc, Weak},
+};
+
+use wayland_client::{protocol::wl_seat, Attached, DispatchData};
+
+use wayland_protocols::wlr::unstable::data_control::v1::client::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1;
+
+use super::{
+    DataControlDevice, DataControlEvent, DataControlOffer, DataControlSource,
+    DataControlSourceEvent,
+};
+
+/// The private mime type offered by the sources of a `PersistentClipboard`
+///
+/// The offers proposing it are never captured, so that several persistent clipboards
+/// running at once do not keep taking the selection from each other.
+pub const PERSIST_MIME_TYPE: &str = "application/x-sctk-persisted";
+
+/// Configuration of a `PersistentClipboard`
+#[derive(Clone, Debug)]
+pub struct PersistConfig {
+    /// The mime types to capture
+    ///
+    /// All the mime types of the offers are captured if this is empty.
+    pub mime_types: Vec<String>,
+    /// The maximum total size of the captured contents of a selection, in bytes
+    ///
+    /// The mime types whose contents exceed it are skipped, and nothing is captured
+    /// if their total does.
+    pub size_limit: usize,
+    /// Whether to persist the primary selection as well
+    ///
+    /// This requires version 2 of the `zwlr_data_control_manager_v1` global.
+    pub primary_selection: bool,
+}
+
+impl Default for PersistConfig {
+    fn default() -> PersistConfig {
+        PersistConfig {
+            mime_types: Vec::new(),
+            size_limit: 16 * 1024 * 1024,
+            primary_selection: false,
+        }
+    }
+}
+
+impl PersistConfig {
+    fn allows(&self, mime_type: &str) -> bool {
+        mime_type != PERSIST_MIME_TYPE
+            && (self.mime_types.is_empty() || self.mime_types.iter().any(|m| m == mime_type))
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Kind {
+    Selection = 0,
+    PrimarySelection = 1,
+}
+
+type Payload = Vec<(String, Rc<[u8]>)>;
+
+#[derive(Default)]
+struct Slot {
+    // bumped on every selection event, to discard the captures and restorations
+    // superseded by a newer selection
+    generation: u64,
+    payload: Option<Rc<Payload>>,
+}
+
+struct State {
+    config: PersistConfig,
+    manager: Attached<ZwlrDataControlManagerV1>,
+    device: Weak<DataControlDevice>,
+    slots: [Slot; 2],
+}
+
+// The contents of an offer being received
+struct Capture {
+    remaining: usize,
+    total: usize,
+    contents: Vec<(usize, String, Rc<[u8]>)>,
+}
+
+/// A clipboard keeping the selections of a seat alive after their source went away
+///
+/// It captures the contents of the selection of the seat each time it changes, and when the
+/// selection is cleared, typically because the client owning it exited, sets it again from
+/// these contents, so that they can still be pasted. The primary selection can be persisted
+/// as well, see [`PersistConfig`](struct.PersistConfig.html).
+///
+/// The persisted selections offer the [`PERSIST_MIME_TYPE`](constant.PERSIST_MIME_TYPE.html)
+/// in addition to the captured mime types. Such offers are not captured again, so that two
+/// persistent clipboards do not fight over the selection. The restoration is delayed until the
+/// pending events are processed, and abandoned if a new selection was set in the meantime.
+///
+/// Note that a client clearing the selection on purpose cannot be told apart from one exiting,
+/// its selection is restored as well.
+///
+/// **Note:** This requires the `calloop` cargo feature.
+pub struct PersistentClipboard {
+    device: Rc<DataControlDevice>,
+    state: Rc<RefCell<State>>,
+}
+
+impl PersistentClipboard {
+    /// Start persisting the selections of a seat
+    ///
+    /// The contents are received and served from the given event loop. The callback is
+    /// invoked with the events of the underlying data control device, after they were
+    /// processed.
+    pub fn new<Data, F>(
+        manager: &Attached<ZwlrDataControlManagerV1>,
+        seat: &wl_seat::WlSeat,
+        loop_handle: &calloop::LoopHandle<Data>,
+        config: PersistConfig,
+        mut callback: F,
+    ) -> PersistentClipboard
+    where
+        Data: 'static,
+        F: FnMut(DataControlEvent, DispatchData) + 'static,
+    {
+        let state = Rc::new(RefCell::new(State {
+            config,
+            manager: manager.clone(),
+            device: Weak::new(),
+            slots: Default::default(),
+        }));
+        let state_2 = state.clone();
+        let handle = loop_handle.clone();
+        let device = DataControlDevice::init_for_seat(manager, seat, move |event, ddata| {
+            match event {
+                DataControlEvent::Selection { ref offer } => {
+                    selection_changed(&state_2, &handle, Kind::Selection, offer.as_ref());
+                }
+                DataControlEvent::PrimarySelection { ref offer } => {
+                    if state_2.borrow().config.primary_selection {
+                        selection_changed(
+                            &state_2,
+                            &handle,
+                            Kind::PrimarySelection,
+                            offer.as_ref(),
+                        );
+                    }
+                }
+                DataControlEvent::Finished => {}
+            }
+            callback(event, ddata);
+        });
+        let device = Rc::new(device);
+        state.borrow_mut().device = Rc::downgrade(&device);
+        PersistentClipboard { device, state }
+    }
+
+    /// Access the underlying data control device
+    pub fn device(&self) -> &DataControlDevice {
+        &self.device
+    }
+
+    /// Forget the captured contents of the selections
+    ///
+    /// They are no longer restored until the next selections are captured.
+    pub fn forget(&self) {
+        for slot in self.state.borrow_mut().slots.iter_mut() {
+            slot.payload = None;
+        }
+    }
+}
+
+fn selection_changed<Data: 'static>(
+    state: &Rc<RefCell<State>>,
+    handle: &calloop::LoopHandle<Data>,
+    kind: Kind,
+    offer: Option<&DataControlOffer>,
+) {
+    let mut guard = state.borrow_mut();
+    let slot = &mut guard.slots[kind as usize];
+    slot.generation = slot.generation.wrapping_add(1);
+    let generation = slot.generation;
+    match offer {
+        Some(offer) => {
+            // our own selection, or the one of another persistent clipboard: its
+            // contents were already captured
+            if offer.with_mime_types(|mimes| mimes.iter().any(|m| m == PERSIST_MIME_TYPE)) {
+                return;
+            }
+            // the previous contents are outdated even if the capture fails
+            slot.payload = None;
+            let mime_types = offer.with_mime_types(|mimes| {
+                mimes
+                    .iter()
+                    .filter(|m| guard.config.allows(m))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            });
+            let size_limit = guard.config.size_limit;
+            drop(guard);
+            capture(
+                state, handle, kind, generation, offer, mime_types, size_limit,
+            );
+        }
+        None => {
+            if slot.payload.is_none() {
+                return;
+            }
+            // let the events already received be processed first, a new selection
+            // may have been set in the meantime
+            let state = state.clone();
+            let handle_2 = handle.clone();
+            handle.insert_idle(move |_| restore(&state, &handle_2, kind, generation));
+        }
+    }
+}
+
+fn capture<Data: 'static>(
+    state: &Rc<RefCell<State>>,
+    handle: &calloop::LoopHandle<Data>,
+    kind: Kind,
+    generation: u64,
+    offer: &DataControlOffer,
+    mime_types: Vec<String>,
+    size_limit: usize,
+) {
+    let capture = Rc::new(RefCell::new(Capture {
+        remaining: mime_types.len(),
+        total: 0,
+        contents: Vec::new(),
+    }));
+    for (index, mime_type) in mime_types.into_iter().enumerate() {
+        let received = match offer.receive(mime_type.clone()) {
+            Ok(pipe) => {
+                let state = state.clone();
+                let capture = capture.clone();
+                let mime_type = mime_type.clone();
+                pipe.read_to_end_async(handle, Some(size_limit), move |result, _| {
+                    captured(&state, kind, generation, &capture, index, mime_type, result)
+                })
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = received {
+            captured(
+                state,
+                kind,
+                generation,
+                &capture,
+                index,
+                mime_type,
+                Err(err),
+            );
+        }
+    }
+}
+
+fn captured(
+    state: &Rc<RefCell<State>>,
+    kind: Kind,
+    generation: u64,
+    capture: &Rc<RefCell<Capture>>,
+    index: usize,
+    mime_type: String,
+    result: io::Result<Vec<u8>>,
+) {
+    let mut capture = capture.borrow_mut();
+    capture.remaining -= 1;
+    match result {
+        Ok(contents) => {
+            capture.total += contents.len();
+            capture.contents.push((index, mime_type, contents.into()));
+        }
+        Err(err) => log::warn!(
+            "[SCTK] Failed to capture the selection as {}: {}",
+            mime_type,
+            err
+        ),
+    }
+    if capture.remaining > 0 {
+        return;
+    }
+
+    let mut state = state.borrow_mut();
+    let size_limit = state.config.size_limit;
+    let slot = &mut state.slots[kind as usize];
+    if slot.generation != generation || capture.contents.is_empty() {
+        return;
+    }
+    if capture.total > size_limit {
+        log::warn!(
+            "[SCTK] The selection is too large to be persisted ({} bytes)",
+            capture.total
+        );
+        return;
+    }
+    let mut contents = mem::replace(&mut capture.contents, Vec::new());
+    // keep the order of preference of the offer
+    contents.sort_by_key(|&(index, _, _)| index);
+    let payload = contents
+        .into_iter()
+        .map(|(_, mime, data)| (mime, data))
+        .collect();
+    slot.payload = Some(Rc::new(payload));
+}
+
+fn restore<Data: 'static>(
+    state: &Rc<RefCell<State>>,
+    handle: &calloop::LoopHandle<Data>,
+    kind: Kind,
+    generation: u64,
+) {
+    let state = state.borrow();
+    let slot = &state.slots[kind as usize];
+    let (payload, device) = match (&slot.payload, state.device.upgrade()) {
+        (Some(payload), Some(device)) if slot.generation == generation => (payload.clone(), device),
+        _ => return,
+    };
+    log::debug!("[SCTK] Restoring the persisted selection");
+
+    let mime_types = payload
+        .iter()
+        .map(|(mime, _)| mime.clone())
+        .chain(std::iter::once(PERSIST_MIME_TYPE.into()))
+        .collect::<Vec<_>>();
+    let handle = handle.clone();
+    let source = DataControlSource::new(&state.manager, mime_types, move |event, _| {
+        if let DataControlSourceEvent::Send { mime_type, pipe } = event {
+            let contents = payload
+                .iter()
+                .find(|(mime, _)| *mime == mime_type)
+                .map(|(_, data)| data.clone())
+                .unwrap_or_else(|| Rc::from(Vec::new()));
+            let sent = pipe.write_all_async(&handle, contents, |result, _| {
+                if let Err(err) = result {
+                    log::warn!("[SCTK] Failed to send the persisted selection: {}", err);
+                }
+            });
+            if let Err(err) = sent {
+                log::warn!("[SCTK] Failed to send the persisted selection: {}", err);
+            }
+        }
+    });
+    match kind {
+        Kind::Selection => device.set_selection(&Some(source)),
+        Kind::PrimarySelection => device.set_primary_selection(&Some(source)),
+    }
+}
@@ -0,0 +1,71 @@
+use std::os::unix::io::FromRawFd;
+
+use wayland_client::{Attached, DispatchData};
+
+use wayland_protocols::wlr::unstable::data_control::v1::client::{
+    zwlr_data_control_manager_v1, zwlr_data_control_source_v1,
+};
+
+use crate::data_device::WritePipe;
+
+/// A data control source, for setting the contents of a selection
+pub struct DataControlSource {
+    pub(crate) source: zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+}
+
+/// Possible events a data control source needs to react to
+pub enum DataControlSourceEvent {
+    /// Write the offered data for selected mime type
+    Send {
+        /// Requested mime type
+        mime_type: String,
+        /// Pipe to write into
+        pipe: WritePipe,
+    },
+    /// The source was replaced by another one
+    ///
+    /// Once this event is received, the `DataControlSource` can not be used any more,
+    /// and you should drop it for cleanup.
+    Cancelled,
+}
+
+impl DataControlSource {
+    /// Create a new data control source
+    ///
+    /// You'll then need to provide it to a data control device to set it as a selection.
+    pub fn new<F, S, It>(
+        manager: &Attached<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>,
+        mime_types: It,
+        mut callback: F,
+    ) -> DataControlSource
+    where
+        F: FnMut(DataControlSourceEvent, DispatchData) + 'static,
+        S: Into<String>,
+        It: IntoIterator<Item = S>,
+    {
+        let source = manager.create_data_source();
+        source.quick_assign(move |source, event, ddata| {
+            use self::zwlr_data_control_source_v1::Event;
+            let event = match event {
+                Event::Send { mime_type, fd } => DataControlSourceEvent::Send {
+                    mime_type,
+                    pipe: unsafe { FromRawFd::from_raw_fd(fd) },
+                },
+                Event::Cancelled => {
+                    source.destroy();
+                    DataControlSourceEvent::Cancelled
+                }
+                _ => unreachable!(),
+            };
+            callback(event, ddata);
+        });
+
+        for mime in mime_types {
+            source.offer(mime.into());
+        }
+
+        DataControlSource {
+            source: source.detach(),
+        }
+    }
+}
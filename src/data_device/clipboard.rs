@@ -0,0 +1,298 @@
+//! High-level copy/paste helpers
+//!
+//! These helpers take care of the data source, mime type and pipe handling needed
+//! for simple clipboard interactions. The transfers are done in a non-blocking way
+//! from your calloop event loop, so large payloads will not stall the dispatching
+//! of your events.
+//!
+//! If the data is expensive to produce, or offered in several representations,
+//! `copy_with` lets you produce it lazily, only for the mime types actually requested.
+
+#[cfg(feature = "calloop")]
+use std::{io::Write, sync::Arc};
+
+use wayland_client::protocol::{wl_data_device_manager, wl_seat};
+
+#[cfg(feature = "calloop")]
+use super::PlainText;
+use super::{DataDeviceError, DataDeviceHandling, DataSourceEvent, LocalData, WritePipe};
+use crate::environment::{Environment, GlobalHandler};
+
+impl<E> Environment<E>
+where
+    E: DataDeviceHandling + GlobalHandler<wl_data_device_manager::WlDataDeviceManager>,
+{
+    /// Put some data into the clipboard of a seat
+    ///
+    /// The data is offered with all the given mime types, and will be sent to
+    /// any client requesting it until another client (or you) replaces the
+    /// contents of the clipboard. The transfers are done in a non-blocking way
+    /// from the provided event loop.
+    ///
+    /// The serial must be the one of the input event that triggered this action (for
+    /// example a key press), the compositor will ignore the request otherwise.
+    ///
+    /// Returns an error if there is no data device for this seat.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn copy<Data: 'static>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        mime_types: Vec<String>,
+        data: Vec<u8>,
+    ) -> Result<(), DataDeviceError> {
+        let data: Arc<[u8]> = data.into();
+        let local_data = data.clone();
+        let local_mime_types = mime_types.clone();
+        // pasting this data into our own application does not need a pipe
+        let local = LocalData::new(move |mime| {
+            if local_mime_types.iter().any(|m| m == mime) {
+                Some(local_data.to_vec())
+            } else {
+                None
+            }
+        });
+        let handle = loop_handle.clone();
+        self.set_selection_with(seat, serial, mime_types, Some(local), move |_, pipe| {
+            let ret = pipe.write_all_async(&handle, data.clone(), |ret, _| {
+                if let Err(err) = ret {
+                    log::warn!("[SCTK] Failed to send clipboard data: {}", err);
+                }
+            });
+            if let Err(err) = ret {
+                log::warn!("[SCTK] Failed to send clipboard data: {}", err);
+            }
+        })
+    }
+
+    /// Put some lazily produced data into the clipboard of a seat
+    ///
+    /// This is similar to [`copy`](#method.copy), but rather than providing the data
+    /// upfront, the `provider` is invoked each time a client requests the contents of the
+    /// clipboard, with the requested mime type and the pipe to write the data into. It can be
+    /// invoked several times, for the same or different mime types, before previous transfers
+    /// are complete.
+    ///
+    /// The provider runs on your event loop, so it should not block. You can instead write
+    /// into the pipe from your event loop using `WritePipe::write_all_async`, or move the
+    /// pipe to another thread and write into it from there. The transfer is complete once
+    /// the pipe is dropped.
+    ///
+    /// If the requesting client goes away in the middle of a transfer, writing to the pipe
+    /// fails with an error of kind `BrokenPipe`, and you should just drop it. Once the contents
+    /// of the clipboard are replaced, the provider is dropped, ongoing transfers are not
+    /// interrupted.
+    ///
+    /// Returns an error if there is no data device for this seat.
+    pub fn copy_with<F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        mime_types: Vec<String>,
+        provider: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(&str, WritePipe) + 'static,
+    {
+        self.set_selection_with(seat, serial, mime_types, None, provider)
+    }
+
+    fn set_selection_with<F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        mime_types: Vec<String>,
+        local: Option<LocalData>,
+        mut provider: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(&str, WritePipe) + 'static,
+    {
+        // once cancelled, the source is destroyed automatically and this
+        // closure is dropped
+        let mut source = self.new_data_source(mime_types, move |event, _| {
+            if let DataSourceEvent::Send { mime_type, pipe } = event {
+                provider(&mime_type, pipe);
+            }
+        });
+        if let Some(local) = local {
+            source.set_local_data(local);
+        }
+        let source = Some(source);
+        self.with_data_device(seat, |device| device.set_selection(&source, serial))
+            .map_err(|()| {
+                if let Some(source) = source {
+                    source.source.destroy();
+                }
+                DataDeviceError::NoDataDevice
+            })
+    }
+
+    /// Request the contents of the clipboard of a seat
+    ///
+    /// The first of the provided mime types offered by the current selection is
+    /// requested, and the callback is invoked with this mime type and the received
+    /// data once the transfer is complete. The transfer is done in a non-blocking way
+    /// from the provided event loop. The request will only be sent to the server on
+    /// the next flush of your connection.
+    ///
+    /// Returns an error without invoking the callback if the clipboard is empty, if
+    /// it does not offer any of the requested mime types, or if there is no data device
+    /// for this seat. If the transfer itself fails, the callback is invoked with an error.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn request_paste<Data, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        mime_types: &[&str],
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        Data: 'static,
+        F: FnOnce(Result<(String, Vec<u8>), DataDeviceError>, &mut Data) + 'static,
+    {
+        let mut result = Err(DataDeviceError::NoDataDevice);
+        let _ = self.with_data_device(seat, |device| {
+            result = device.with_selection(|offer| {
+                let offer = offer.ok_or(DataDeviceError::NoSelection)?;
+                let mime_type = offer
+                    .with_mime_types(|offered| select_mime_type(offered, mime_types))
+                    .ok_or(DataDeviceError::UnsupportedMimeType)?;
+                let ret_mime_type = mime_type.clone();
+                offer
+                    .read_to_end_async(loop_handle, mime_type, move |contents, data| {
+                        callback(
+                            contents
+                                .map(|c| (ret_mime_type, c))
+                                .map_err(DataDeviceError::Io),
+                            data,
+                        )
+                    })
+                    .map_err(DataDeviceError::Io)
+            });
+        });
+        result
+    }
+
+    /// Stream the contents of the clipboard of a seat into a sink
+    ///
+    /// This is similar to [`request_paste`](#method.request_paste), but the data is written
+    /// to `sink` as it is received and `progress` is invoked with the number of bytes
+    /// received so far, see
+    /// [`DataOffer::read_into_async`](../data_device/struct.DataOffer.html#method.read_into_async).
+    /// Once the transfer is complete, the callback is invoked with the mime type and the sink.
+    ///
+    /// If a `limit` is given, the transfer is aborted as soon as more than `limit` bytes are
+    /// received, and the callback is invoked with `DataDeviceError::TooLarge`. This does not
+    /// affect the selection, which can still be pasted again.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    #[allow(clippy::too_many_arguments)]
+    pub fn request_paste_into<Data, W, P, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        mime_types: &[&str],
+        sink: W,
+        limit: Option<usize>,
+        progress: P,
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        Data: 'static,
+        W: Write + 'static,
+        P: FnMut(usize) + 'static,
+        F: FnOnce(Result<(String, W), DataDeviceError>, &mut Data) + 'static,
+    {
+        let mut result = Err(DataDeviceError::NoDataDevice);
+        let _ = self.with_data_device(seat, |device| {
+            result = device.with_selection(|offer| {
+                let offer = offer.ok_or(DataDeviceError::NoSelection)?;
+                let mime_type = offer
+                    .with_mime_types(|offered| select_mime_type(offered, mime_types))
+                    .ok_or(DataDeviceError::UnsupportedMimeType)?;
+                let ret_mime_type = mime_type.clone();
+                offer
+                    .read_into_async(
+                        loop_handle,
+                        mime_type,
+                        sink,
+                        limit,
+                        progress,
+                        move |result, data| {
+                            callback(
+                                result
+                                    .map(|sink| (ret_mime_type, sink))
+                                    .map_err(DataDeviceError::from_transfer),
+                                data,
+                            )
+                        },
+                    )
+                    .map_err(DataDeviceError::Io)
+            });
+        });
+        result
+    }
+
+    /// Put some text into the clipboard of a seat
+    ///
+    /// The text is offered with all the [`PlainText`](../data_device/struct.PlainText.html)
+    /// mime types, see [`copy`](#method.copy) for details.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn copy_text<Data: 'static>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        text: String,
+    ) -> Result<(), DataDeviceError> {
+        let data = PlainText(text).encode();
+        self.copy(loop_handle, seat, serial, PlainText::mime_types(), data)
+    }
+
+    /// Request the text contents of the clipboard of a seat
+    ///
+    /// The preferred [`PlainText`](../data_device/struct.PlainText.html) mime type
+    /// offered by the clipboard is requested and decoded, see
+    /// [`request_paste`](#method.request_paste) for details.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn request_paste_text<Data, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        Data: 'static,
+        F: FnOnce(Result<String, DataDeviceError>, &mut Data) + 'static,
+    {
+        self.request_paste(
+            loop_handle,
+            seat,
+            PlainText::MIME_TYPES,
+            move |result, data| {
+                let text = result.map(|(mime, contents)| PlainText::decode(&contents, &mime).0);
+                callback(text, data)
+            },
+        )
+    }
+}
+
+/// Pick the first of the requested mime types that is offered
+#[cfg(feature = "calloop")]
+pub(crate) fn select_mime_type(offered: &[String], requested: &[&str]) -> Option<String> {
+    requested
+        .iter()
+        .find(|mime| offered.iter().any(|m| m == *mime))
+        .map(|mime| (*mime).to_owned())
+}
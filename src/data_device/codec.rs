@@ -0,0 +1,321 @@
+//! Encoding and decoding of common data formats
+//!
+//! Most clipboard and drag'n'drop contents are either plain text or lists of files,
+//! which come with their own quirks: mime type aliases inherited from X11, CRLF line
+//! endings and percent-encoding of `file://` URIs.
+
+use std::{
+    ffi::OsString,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+/// Plain text, as transferred through the clipboard or a drag'n'drop
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlainText(pub String);
+
+impl PlainText {
+    /// The mime types plain text is exchanged as, by order of preference
+    ///
+    /// The ones after `text/plain;charset=utf-8` are legacy aliases still used by
+    /// some clients, notably through Xwayland.
+    pub const MIME_TYPES: &'static [&'static str] = &[
+        "text/plain;charset=utf-8",
+        "UTF8_STRING",
+        "text/plain",
+        "TEXT",
+        "STRING",
+    ];
+
+    /// The mime types to offer when providing some plain text
+    pub fn mime_types() -> Vec<String> {
+        PlainText::MIME_TYPES
+            .iter()
+            .map(|&mime| mime.to_owned())
+            .collect()
+    }
+
+    /// Pick the preferred plain text mime type among the offered ones
+    pub fn select_mime_type(offered: &[String]) -> Option<String> {
+        PlainText::MIME_TYPES
+            .iter()
+            .find(|&&mime| offered.iter().any(|m| m == mime))
+            .map(|&mime| mime.to_owned())
+    }
+
+    /// Encode this text, for any of the plain text mime types
+    pub fn encode(&self) -> Vec<u8> {
+        self.0.as_bytes().to_vec()
+    }
+
+    /// Decode some plain text received with the given mime type
+    ///
+    /// `STRING` is decoded as Latin-1, any other mime type as UTF-8, replacing invalid
+    /// sequences. A trailing NUL character sent by some clients is removed.
+    pub fn decode(data: &[u8], mime_type: &str) -> PlainText {
+        let data = match data.split_last() {
+            Some((0, rest)) => rest,
+            _ => data,
+        };
+        if mime_type == "STRING" {
+            PlainText(data.iter().map(|&b| b as char).collect())
+        } else {
+            PlainText(String::from_utf8_lossy(data).into_owned())
+        }
+    }
+}
+
+/// A list of URIs, typically of files, as transferred with `text/uri-list`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UriList {
+    /// The local files of the list
+    pub paths: Vec<PathBuf>,
+    /// The URIs of the list which are not local files
+    pub other_uris: Vec<String>,
+}
+
+impl UriList {
+    /// The mime type of URI lists
+    pub const MIME_TYPE: &'static str = "text/uri-list";
+
+    /// The mime types to offer when providing an URI list
+    pub fn mime_types() -> Vec<String> {
+        vec![UriList::MIME_TYPE.to_owned()]
+    }
+
+    /// Pick the URI list mime type, if offered
+    pub fn select_mime_type(offered: &[String]) -> Option<String> {
+        if offered.iter().any(|m| m == UriList::MIME_TYPE) {
+            Some(UriList::MIME_TYPE.to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Encode this list
+    ///
+    /// The paths are encoded as `file://` URIs, and must be absolute.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for path in &self.paths {
+            data.extend_from_slice(b"file://");
+            percent_encode(path, &mut data);
+            data.extend_from_slice(b"\r\n");
+        }
+        for uri in &self.other_uris {
+            data.extend_from_slice(uri.as_bytes());
+            data.extend_from_slice(b"\r\n");
+        }
+        data
+    }
+
+    /// Decode an URI list
+    ///
+    /// Empty lines and comment lines are ignored, local `file://` URIs are decoded
+    /// into paths and the other URIs are returned as-is.
+    ///
+    /// ```
+    /// # use smithay_client_toolkit::data_device::UriList;
+    /// # use std::path::PathBuf;
+    /// let list = UriList::decode(b"# a comment\r\nfile:///tmp/my%20file\r\nhttps://example.com\r\n\r\n");
+    /// assert_eq!(list.paths, vec![PathBuf::from("/tmp/my file")]);
+    /// assert_eq!(list.other_uris, vec!["https://example.com".to_owned()]);
+    /// ```
+    pub fn decode(data: &[u8]) -> UriList {
+        let mut list = UriList::default();
+        let text = String::from_utf8_lossy(data);
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match file_uri_path(line) {
+                Some(path) => list.paths.push(path),
+                None => list.other_uris.push(line.to_owned()),
+            }
+        }
+        list
+    }
+}
+
+// Decode the path of a local file:// URI
+fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    let rest = &uri["file://".len()..];
+    // skip the host, only local files are of interest
+    let path = if rest.starts_with('/') {
+        rest
+    } else {
+        let (host, path) = rest.split_at(rest.find('/')?);
+        if host != "localhost" {
+            return None;
+        }
+        path
+    };
+    let bytes = percent_decode(path.as_bytes())?;
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+fn percent_encode(path: &Path, out: &mut Vec<u8>) {
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(byte)
+            }
+            _ => out.extend_from_slice(format!("%{:02X}", byte).as_bytes()),
+        }
+    }
+}
+
+fn percent_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut iter = data.iter();
+    while let Some(&byte) = iter.next() {
+        if byte == b'%' {
+            let high = hex_value(*iter.next()?)?;
+            let low = hex_value(*iter.next()?)?;
+            out.push(high << 4 | low);
+        } else {
+            out.push(byte);
+        }
+    }
+    Some(out)
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mimes(mimes: &[&str]) -> Vec<String> {
+        mimes.iter().map(|&m| m.to_owned()).collect()
+    }
+
+    #[test]
+    fn plain_text_mime_type_preference() {
+        let offered = mimes(&["STRING", "text/plain", "text/html"]);
+        assert_eq!(
+            PlainText::select_mime_type(&offered).as_deref(),
+            Some("text/plain")
+        );
+        let offered = mimes(&["STRING", "text/plain;charset=utf-8"]);
+        assert_eq!(
+            PlainText::select_mime_type(&offered).as_deref(),
+            Some("text/plain;charset=utf-8")
+        );
+        assert_eq!(PlainText::select_mime_type(&mimes(&["text/html"])), None);
+        assert_eq!(PlainText::select_mime_type(&[]), None);
+    }
+
+    #[test]
+    fn plain_text_round_trip() {
+        let text = PlainText("héllo\r\nwörld".into());
+        assert_eq!(
+            PlainText::decode(&text.encode(), "text/plain;charset=utf-8"),
+            text
+        );
+        assert_eq!(PlainText::decode(b"", "text/plain"), PlainText("".into()));
+    }
+
+    #[test]
+    fn plain_text_trailing_nul() {
+        assert_eq!(PlainText::decode(b"abc\0", "UTF8_STRING").0, "abc");
+        // only one terminator is removed
+        assert_eq!(PlainText::decode(b"abc\0\0", "UTF8_STRING").0, "abc\0");
+        assert_eq!(PlainText::decode(b"\0", "text/plain").0, "");
+    }
+
+    #[test]
+    fn plain_text_encodings() {
+        // Latin-1
+        assert_eq!(PlainText::decode(b"caf\xe9", "STRING").0, "café");
+        // invalid UTF-8 is replaced rather than rejected
+        assert_eq!(PlainText::decode(b"caf\xe9", "text/plain").0, "caf\u{fffd}");
+    }
+
+    #[test]
+    fn uri_list_mime_type() {
+        assert_eq!(
+            UriList::select_mime_type(&mimes(&["text/plain", "text/uri-list"])).as_deref(),
+            Some("text/uri-list")
+        );
+        assert_eq!(UriList::select_mime_type(&mimes(&["text/plain"])), None);
+    }
+
+    #[test]
+    fn uri_list_round_trip() {
+        let list = UriList {
+            paths: vec![
+                PathBuf::from("/tmp/plain"),
+                PathBuf::from("/tmp/with space/100%#?.txt"),
+                PathBuf::from("/home/été/日本"),
+                PathBuf::from(OsString::from_vec(b"/tmp/not\xffutf8".to_vec())),
+            ],
+            other_uris: vec!["https://example.com/a%20b".into()],
+        };
+        let data = list.encode();
+        // the encoded list is ASCII, with CRLF line endings
+        assert!(data.is_ascii());
+        assert!(data.ends_with(b"\r\n"));
+        assert_eq!(UriList::decode(&data), list);
+    }
+
+    #[test]
+    fn uri_list_percent_encoding() {
+        let list = UriList {
+            paths: vec![PathBuf::from("/a b/c%d/é")],
+            other_uris: vec![],
+        };
+        assert_eq!(list.encode(), b"file:///a%20b/c%25d/%C3%A9\r\n".to_vec());
+        // lowercase escapes are decoded as well
+        let list = UriList::decode(b"file:///a%20b/%c3%a9");
+        assert_eq!(list.paths, vec![PathBuf::from("/a b/é")]);
+    }
+
+    #[test]
+    fn uri_list_hosts() {
+        let list = UriList::decode(b"file://localhost/tmp/a\r\nfile:///tmp/b\r\n");
+        assert_eq!(
+            list.paths,
+            vec![PathBuf::from("/tmp/a"), PathBuf::from("/tmp/b")]
+        );
+        assert!(list.other_uris.is_empty());
+
+        // files of other hosts are not local
+        let list = UriList::decode(b"file://otherhost/tmp/a\r\nfile://localhost\r\n");
+        assert!(list.paths.is_empty());
+        assert_eq!(
+            list.other_uris,
+            vec![
+                "file://otherhost/tmp/a".to_owned(),
+                "file://localhost".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn uri_list_malformed_escapes() {
+        let list = UriList::decode(b"file:///tmp/a%zz\r\nfile:///tmp/b%2\r\nfile:///tmp/c%\r\n");
+        assert!(list.paths.is_empty());
+        assert_eq!(list.other_uris.len(), 3);
+    }
+
+    #[test]
+    fn uri_list_lines() {
+        // LF line endings, comments, blank lines and surrounding whitespace
+        let list = UriList::decode(b"#comment\n\n  file:///tmp/a  \n\r\nhttps://example.com");
+        assert_eq!(list.paths, vec![PathBuf::from("/tmp/a")]);
+        assert_eq!(list.other_uris, vec!["https://example.com".to_owned()]);
+        assert_eq!(UriList::decode(b""), UriList::default());
+    }
+}
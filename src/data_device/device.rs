@@ -3,14 +3,27 @@ use wayland_client::{
     DispatchData, Main,
 };
 
-use std::sync::{Arc, Mutex};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use super::{local::LocalSource, DataOffer, DataSource, DndAction};
 
-use super::{DataOffer, DataSource, DndAction};
+type LostCallback = dyn FnMut(DispatchData);
 
 struct Inner {
     selection: Option<DataOffer>,
     current_dnd: Option<DataOffer>,
     known_offers: Vec<DataOffer>,
+    // the sources we set for the selection and drag'n'drop of this seat, while
+    // they are alive the offers of this seat are necessarily created for them
+    local_selection: Option<LocalSource>,
+    local_dnd: Option<LocalSource>,
+    // the generation of the selection we set, until its source is cancelled
+    owned_selection: Option<u64>,
+    selection_generation: u64,
 }
 
 impl Inner {
@@ -25,10 +38,14 @@ impl Inner {
                 .iter()
                 .position(|o| o.offer.as_ref().equals(&offer.as_ref()))
             {
-                self.selection = Some(self.known_offers.swap_remove(id));
+                let offer = self.known_offers.swap_remove(id);
+                offer.set_local(local_data(&self.local_selection));
+                self.selection = Some(offer);
             } else {
                 panic!("Compositor set an unknown data_offer for selection.");
             }
+            // any other known offer was superseded without ever being used
+            self.known_offers.clear();
         } else {
             // drop the current offer if any
             self.selection = None;
@@ -42,10 +59,14 @@ impl Inner {
                 .iter()
                 .position(|o| o.offer.as_ref().equals(&offer.as_ref()))
             {
-                self.current_dnd = Some(self.known_offers.swap_remove(id));
+                let offer = self.known_offers.swap_remove(id);
+                offer.set_local(local_data(&self.local_dnd));
+                self.current_dnd = Some(offer);
             } else {
                 panic!("Compositor set an unknown data_offer for selection.");
             }
+            // any other known offer was superseded without ever being used
+            self.known_offers.clear();
         } else {
             // drop the current offer if any
             self.current_dnd = None;
@@ -53,6 +74,16 @@ impl Inner {
     }
 }
 
+fn local_source(source: Option<&DataSource>) -> Option<LocalSource> {
+    let source = source?;
+    let local = source.local.clone()?;
+    Some(LocalSource::new(source.source.clone(), local))
+}
+
+fn local_data(local: &Option<LocalSource>) -> Option<super::LocalData> {
+    local.as_ref().and_then(LocalSource::data).cloned()
+}
+
 /// Handle to support data exchange on a given seat
 ///
 /// This type provides you with functionality to send and receive
@@ -61,6 +92,7 @@ impl Inner {
 pub struct DataDevice {
     device: wl_data_device::WlDataDevice,
     inner: Arc<Mutex<Inner>>,
+    lost_callback: Rc<RefCell<LostCallback>>,
 }
 
 /// Possible events generated during a drag'n'drop session
@@ -126,7 +158,11 @@ fn data_device_implem<F>(
             y,
             id,
         } => {
+            log::debug!("[SCTK] Drag'n'drop entered a surface");
             inner.set_dnd(id);
+            if let Some(ref offer) = inner.current_dnd {
+                offer.set_serial(serial);
+            }
             implem(
                 DndEvent::Enter {
                     serial,
@@ -139,6 +175,7 @@ fn data_device_implem<F>(
             );
         }
         Event::Motion { time, x, y } => {
+            log::trace!("[SCTK] Drag'n'drop moved to ({}, {})", x, y);
             implem(
                 DndEvent::Motion {
                     x,
@@ -149,8 +186,23 @@ fn data_device_implem<F>(
                 ddata,
             );
         }
-        Event::Leave => implem(DndEvent::Leave, ddata),
+        Event::Leave => {
+            log::debug!("[SCTK] Drag'n'drop left the surface");
+            implem(DndEvent::Leave, ddata);
+            // Only keep the offer of a drop whose data may still be transferred,
+            // any other is now superseded. In particular a drop still waiting for
+            // the "ask" action to be resolved is only kept alive by the clones the
+            // app made of its offer, so that it is cancelled rather than left
+            // hanging if the app never resolves it.
+            if let Some(false) = inner.current_dnd.as_ref().map(DataOffer::in_transfer) {
+                inner.current_dnd = None;
+            }
+        }
         Event::Drop => {
+            log::debug!("[SCTK] Drag'n'drop dropped");
+            if let Some(ref offer) = inner.current_dnd {
+                offer.set_dropped();
+            }
             implem(
                 DndEvent::Drop {
                     offer: inner.current_dnd.as_ref(),
@@ -158,7 +210,10 @@ fn data_device_implem<F>(
                 ddata,
             );
         }
-        Event::Selection { id } => inner.set_selection(id),
+        Event::Selection { id } => {
+            log::debug!("[SCTK] Selection changed");
+            inner.set_selection(id)
+        }
         _ => unreachable!(),
     }
 }
@@ -169,29 +224,68 @@ impl DataDevice {
     /// You need to provide an implementation that will handle drag'n'drop
     /// events.
     pub fn init_for_seat<F>(
+        manager: &wl_data_device_manager::WlDataDeviceManager,
+        seat: &wl_seat::WlSeat,
+        callback: F,
+    ) -> DataDevice
+    where
+        for<'a> F: FnMut(DndEvent<'a>, DispatchData) + 'static,
+    {
+        DataDevice::init_with_selection_callbacks(manager, seat, callback, |_, _| {}, |_| {})
+    }
+
+    /// Create the DataDevice helper for this seat, with selection callbacks
+    ///
+    /// The selection callback is invoked with the mime types of the new selection
+    /// whenever it changes, or `None` if the clipboard is now empty. The lost callback
+    /// is invoked when another client replaces a selection set by this device.
+    pub(crate) fn init_with_selection_callbacks<F, S, L>(
         manager: &wl_data_device_manager::WlDataDeviceManager,
         seat: &wl_seat::WlSeat,
         mut callback: F,
+        mut selection_callback: S,
+        lost_callback: L,
     ) -> DataDevice
     where
         for<'a> F: FnMut(DndEvent<'a>, DispatchData) + 'static,
+        S: FnMut(Option<&[String]>, DispatchData) + 'static,
+        L: FnMut(DispatchData) + 'static,
     {
         let inner = Arc::new(Mutex::new(Inner {
             selection: None,
             current_dnd: None,
             known_offers: Vec::new(),
+            local_selection: None,
+            local_dnd: None,
+            owned_selection: None,
+            selection_generation: 0,
         }));
 
         let inner2 = inner.clone();
         let device = manager.get_data_device(seat);
         device.quick_assign(move |_, evt, ddata| {
-            let mut inner = inner2.lock().unwrap();
-            data_device_implem(evt, &mut *inner, &mut callback, ddata);
+            if let wl_data_device::Event::Selection { id } = evt {
+                let mime_types = {
+                    let mut inner = inner2.lock().unwrap();
+                    inner.set_selection(id);
+                    inner
+                        .selection
+                        .as_ref()
+                        .map(|offer| offer.with_mime_types(|mimes| mimes.to_vec()))
+                };
+                // the selection callback is invoked without any lock held, so
+                // that it can access the new selection
+                selection_callback(mime_types.as_ref().map(|m| &m[..]), ddata);
+            } else {
+                let mut inner = inner2.lock().unwrap();
+                data_device_implem(evt, &mut *inner, &mut callback, ddata);
+            }
         });
 
         DataDevice {
             device: device.detach(),
             inner,
+            lost_callback: Rc::new(RefCell::new(lost_callback)),
         }
     }
 
@@ -218,6 +312,7 @@ impl DataDevice {
         icon: Option<&wl_surface::WlSurface>,
         serial: u32,
     ) {
+        self.inner.lock().unwrap().local_dnd = local_source(source.as_ref());
         if let Some(source) = source {
             source.source.set_actions(actions.to_raw());
             self.device
@@ -231,12 +326,54 @@ impl DataDevice {
     ///
     /// Correspond to traditional copy/paste behavior. Setting the
     /// source to `None` will clear the selection.
+    ///
+    /// While the source is not cancelled, this device [owns](#method.owns_selection)
+    /// the selection.
     pub fn set_selection(&self, source: &Option<DataSource>, serial: u32) {
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.local_selection = local_source(source.as_ref());
+            inner.selection_generation += 1;
+            inner.owned_selection = source.as_ref().map(|_| inner.selection_generation);
+            inner.selection_generation
+        };
+        if let Some(ref source) = *source {
+            let inner = self.inner.clone();
+            let lost_callback = self.lost_callback.clone();
+            *source.cancel_hook.borrow_mut() = Some(Box::new(move |ddata| {
+                let lost = {
+                    let mut inner = inner.lock().unwrap();
+                    if inner.owned_selection == Some(generation) {
+                        inner.owned_selection = None;
+                        true
+                    } else {
+                        // this selection was already replaced by a newer one of ours
+                        false
+                    }
+                };
+                if lost {
+                    (&mut *lost_callback.borrow_mut())(ddata);
+                }
+            }));
+        }
         self.device
             .set_selection(source.as_ref().map(|s| &s.source), serial);
     }
 
+    /// Whether the selection of this seat is the one set by this device
+    ///
+    /// This is the case from the moment a data source is given to
+    /// [`set_selection`](#method.set_selection), until this source is cancelled because
+    /// another client (or this device) replaced the selection.
+    pub fn owns_selection(&self) -> bool {
+        self.inner.lock().unwrap().owned_selection.is_some()
+    }
+
     /// Access the `DataOffer` currently associated with the selection buffer
+    ///
+    /// The offers are managed by the data device: the previous selection offer is
+    /// destroyed once the selection changes, so always access the current one through
+    /// this method rather than keeping it around.
     pub fn with_selection<F, T>(&self, f: F) -> T
     where
         F: FnOnce(Option<&DataOffer>) -> T,
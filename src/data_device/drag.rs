@@ -0,0 +1,236 @@
+use std::{cell::Cell, rc::Rc};
+
+use wayland_client::{
+    protocol::{wl_buffer, wl_data_device_manager, wl_seat, wl_surface},
+    DispatchData,
+};
+
+use super::{
+    DataDeviceError, DataDeviceHandling, DataSourceEvent, DndAction, LocalData, SelectedAction,
+    WritePipe,
+};
+use crate::{
+    environment::{Environment, GlobalHandler},
+    surface::{assign_role, CompositorCaps, RoleConflict, SurfaceRole},
+};
+
+/// Description of the content offered by a drag'n'drop
+#[derive(Clone, Debug)]
+pub struct DragSource {
+    /// The mime types the content is offered as
+    pub mime_types: Vec<String>,
+    /// The actions supported for this drag'n'drop (copy, move and/or ask)
+    pub actions: DndAction,
+    /// Direct access to the data, for drops onto your own surfaces
+    ///
+    /// See [`DataSource::set_local_data`](struct.DataSource.html#method.set_local_data).
+    pub local_data: Option<LocalData>,
+}
+
+/// How a drag'n'drop ended, from the point of view of its source
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DragResult {
+    /// The target accepted the drop and finished the transfer
+    ///
+    /// If the action is `DndAction::Move`, you can now delete the original content.
+    Accepted {
+        /// The final action selected for the drag'n'drop
+        action: DndAction,
+    },
+    /// The drag'n'drop was cancelled before the drop
+    ///
+    /// This happens if the user cancelled it, if the compositor replaced it by another
+    /// drag'n'drop, or if the content was dropped where no target accepted it.
+    Cancelled,
+    /// The content was dropped, but the target rejected it without finishing the transfer
+    TargetRejected,
+}
+
+/// An icon following the pointer during a drag'n'drop
+///
+/// This wraps the `wl_surface` that will be given the drag'n'drop icon role. You can
+/// keep a clone of it to update its contents during the drag'n'drop, using
+/// [`set_buffer`](#method.set_buffer).
+///
+/// The surface is destroyed once the drag'n'drop is finished or cancelled, any
+/// further update of the icon will then be ignored.
+#[derive(Clone)]
+pub struct DragIcon {
+    surface: wl_surface::WlSurface,
+    hotspot: Rc<Cell<(i32, i32)>>,
+}
+
+impl DragIcon {
+    /// Create a drag'n'drop icon from this surface
+    ///
+    /// The surface must not have any role, or already be a drag icon. Fails if it was
+    /// given another role by SCTK.
+    pub fn new(surface: wl_surface::WlSurface) -> Result<DragIcon, RoleConflict> {
+        assign_role(&surface, SurfaceRole::DragIcon)?;
+        Ok(DragIcon {
+            surface,
+            hotspot: Rc::new(Cell::new((0, 0))),
+        })
+    }
+
+    /// Access the underlying `wl_surface`
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    /// Set the contents of the icon and commit it
+    ///
+    /// The `hotspot` is the point of the buffer that is placed under the pointer,
+    /// in surface-local coordinates. The whole buffer of given size is damaged.
+    pub fn set_buffer(
+        &self,
+        buffer: Option<&wl_buffer::WlBuffer>,
+        (width, height): (i32, i32),
+        hotspot: (i32, i32),
+    ) {
+        // the attach offsets of a drag'n'drop icon are cumulative, and move the
+        // icon relative to the pointer
+        let (old_x, old_y) = self.hotspot.replace(hotspot);
+        self.surface
+            .attach(buffer, old_x - hotspot.0, old_y - hotspot.1);
+        CompositorCaps::of_surface(&self.surface).damage_buffer(
+            &self.surface,
+            0,
+            0,
+            width,
+            height,
+            1,
+        );
+        self.surface.commit();
+    }
+}
+
+impl<E> Environment<E>
+where
+    E: DataDeviceHandling + GlobalHandler<wl_data_device_manager::WlDataDeviceManager>,
+{
+    /// Start a drag'n'drop from one of your surfaces
+    ///
+    /// The serial must be the one of the input event that started the implicit grab
+    /// on the origin surface (typically a pointer button press).
+    ///
+    /// The callback receives the events of the data source of this drag'n'drop: you need
+    /// to send the data on `Send`, and once it is `Finished` check the final `Action`
+    /// to know whether the original content needs to be deleted (move) or kept (copy).
+    /// Nothing needs to be done on `Cancelled`. Note that the data may still be requested
+    /// after `Dropped`, only `Finished` marks the end of a successful drag'n'drop.
+    ///
+    /// If an icon is provided, it follows the pointer during the drag'n'drop, and its
+    /// surface is destroyed once the drag'n'drop is finished or cancelled.
+    ///
+    /// The returned handle tracks the action currently selected for this drag'n'drop.
+    ///
+    /// Returns an error if there is no data device for this seat.
+    pub fn start_drag<F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        origin: &wl_surface::WlSurface,
+        serial: u32,
+        source: DragSource,
+        icon: Option<DragIcon>,
+        mut callback: F,
+    ) -> Result<SelectedAction, DataDeviceError>
+    where
+        F: FnMut(DataSourceEvent, DispatchData) + 'static,
+    {
+        let DragSource {
+            mime_types,
+            actions,
+            local_data,
+        } = source;
+        let icon_surface = icon.as_ref().map(|icon| icon.surface.clone());
+        let mut icon = icon;
+        let mut data_source = self.new_data_source(mime_types, move |event, ddata| {
+            match event {
+                DataSourceEvent::Finished | DataSourceEvent::Cancelled => {
+                    callback(event, ddata);
+                    // the icon must live until the end of the drag'n'drop
+                    if let Some(icon) = icon.take() {
+                        icon.surface.destroy();
+                    }
+                }
+                event => callback(event, ddata),
+            }
+        });
+        if let Some(local_data) = local_data {
+            data_source.set_local_data(local_data);
+        }
+        let action = data_source.selected_action();
+        let mut data_source = Some(data_source);
+        self.with_data_device(seat, |device| {
+            device.start_drag(
+                origin,
+                data_source.take(),
+                actions,
+                icon_surface.as_ref(),
+                serial,
+            )
+        })
+        .map_err(|()| {
+            if let Some(data_source) = data_source {
+                data_source.source.destroy();
+            }
+            DataDeviceError::NoDataDevice
+        })?;
+        Ok(action)
+    }
+
+    /// Start a drag'n'drop, and get notified of how it ended
+    ///
+    /// This is similar to [`start_drag`](#method.start_drag), but rather than the raw events
+    /// of the data source, `send` is invoked with the requests to write the data, and `result`
+    /// once the drag'n'drop ended. The result is only `Accepted` once the target finished the
+    /// transfer, not as soon as the content is dropped, so the content can still be requested
+    /// in between and must not be deleted before.
+    ///
+    /// This requires version 3 of the data device manager, which all current compositors
+    /// support: with older versions, the drag'n'drop can never be reported as `Accepted`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn start_drag_with_result<F, R>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        origin: &wl_surface::WlSurface,
+        serial: u32,
+        source: DragSource,
+        icon: Option<DragIcon>,
+        mut send: F,
+        result: R,
+    ) -> Result<SelectedAction, DataDeviceError>
+    where
+        F: FnMut(String, WritePipe, DispatchData) + 'static,
+        R: FnOnce(DragResult, DispatchData) + 'static,
+    {
+        let mut result = Some(result);
+        let mut dropped = false;
+        let mut action = DndAction::None;
+        self.start_drag(seat, origin, serial, source, icon, move |event, ddata| {
+            let drag_result = match event {
+                DataSourceEvent::Send { mime_type, pipe } => {
+                    send(mime_type, pipe, ddata);
+                    return;
+                }
+                DataSourceEvent::Action { action: new_action } => {
+                    action = new_action;
+                    return;
+                }
+                DataSourceEvent::Dropped => {
+                    dropped = true;
+                    return;
+                }
+                DataSourceEvent::Target { .. } => return,
+                DataSourceEvent::Finished => DragResult::Accepted { action },
+                // the drop is cancelled if the target destroys its offer without finishing it
+                DataSourceEvent::Cancelled if dropped => DragResult::TargetRejected,
+                DataSourceEvent::Cancelled => DragResult::Cancelled,
+            };
+            if let Some(result) = result.take() {
+                result(drag_result, ddata);
+            }
+        })
+    }
+}
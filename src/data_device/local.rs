@@ -0,0 +1,66 @@
+use std::{fmt, sync::Arc};
+
+use wayland_client::protocol::wl_data_source;
+
+type Provider = dyn Fn(&str) -> Option<Vec<u8>> + Send + Sync;
+
+/// Direct access to the data of one of your data sources
+///
+/// When the data of a drag'n'drop or of the clipboard is provided by your own
+/// application, transferring it through a pipe is wasteful, and can deadlock a
+/// single-threaded application writing and reading both ends of the same pipe.
+///
+/// Attaching a `LocalData` to a data source lets the receiving helpers of this module
+/// get the data directly by invoking it with the requested mime type instead. It should
+/// return `None` if it cannot provide this mime type.
+#[derive(Clone)]
+pub struct LocalData {
+    provider: Arc<Provider>,
+}
+
+impl LocalData {
+    /// Create a new local data provider
+    pub fn new<F>(provider: F) -> LocalData
+    where
+        F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        LocalData {
+            provider: Arc::new(provider),
+        }
+    }
+
+    /// Get the data for a mime type
+    pub fn get(&self, mime_type: &str) -> Option<Vec<u8>> {
+        (self.provider)(mime_type)
+    }
+}
+
+impl fmt::Debug for LocalData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalData").finish()
+    }
+}
+
+/// The local data of a data source given to the compositor
+///
+/// It is only valid as long as the source is alive: a data source is destroyed once it
+/// is cancelled or its drag'n'drop is finished.
+#[derive(Clone)]
+pub(crate) struct LocalSource {
+    source: wl_data_source::WlDataSource,
+    data: LocalData,
+}
+
+impl LocalSource {
+    pub(crate) fn new(source: wl_data_source::WlDataSource, data: LocalData) -> LocalSource {
+        LocalSource { source, data }
+    }
+
+    pub(crate) fn data(&self) -> Option<&LocalData> {
+        if self.source.as_ref().is_alive() {
+            Some(&self.data)
+        } else {
+            None
+        }
+    }
+}
@@ -1,35 +1,149 @@
 //! Helpers to handle data device related actions
 
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, fmt, io, rc::Rc};
 
 use wayland_client::{
-    protocol::{wl_data_device_manager, wl_registry, wl_seat},
+    protocol::{wl_data_device_manager, wl_registry, wl_seat, wl_surface},
     Attached, DispatchData,
 };
 
 pub use wayland_client::protocol::wl_data_device_manager::DndAction;
 
+mod clipboard;
+mod codec;
 mod device;
+mod drag;
+mod local;
 mod offer;
+mod offload;
+mod pipe;
 mod source;
+mod target;
 
+pub use self::codec::{PlainText, UriList};
 pub use self::device::{DataDevice, DndEvent};
+pub use self::drag::{DragIcon, DragResult, DragSource};
+pub use self::local::LocalData;
 pub use self::offer::{DataOffer, ReadPipe};
-pub use self::source::{DataSource, DataSourceEvent, WritePipe};
+pub use self::offload::OffloadedSend;
+#[cfg(feature = "calloop")]
+pub use self::pipe::WriteTransfer;
+pub use self::source::{DataSource, DataSourceEvent, SelectedAction, WritePipe};
+pub use self::target::{DropEvent, DropTarget};
+
+#[cfg(feature = "calloop")]
+pub(crate) use self::clipboard::select_mime_type;
+#[cfg(feature = "calloop")]
+use self::pipe::is_size_limit_exceeded;
+pub(crate) use self::pipe::receive_pipe;
+pub(crate) use self::source::CancelHook;
+use self::target::{route_dnd_event, DndRouter};
+
+/// Possible errors of the data device helpers
+#[derive(Debug)]
+pub enum DataDeviceError {
+    /// No data device is available for this seat
+    ///
+    /// Either the seat does not exist any longer, or the compositor does
+    /// not support data devices.
+    NoDataDevice,
+    /// The clipboard is currently empty
+    NoSelection,
+    /// The current selection does not offer any of the requested mime types
+    UnsupportedMimeType,
+    /// An I/O error occured while transferring the data
+    Io(io::Error),
+    /// The transfer was aborted because the data exceeds the requested size limit
+    TooLarge,
+    /// The requested operation is not valid in the current state of the offer
+    ///
+    /// For example, finishing a drag'n'drop offer that was not dropped yet.
+    InvalidOfferState,
+}
+
+impl fmt::Display for DataDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataDeviceError::NoDataDevice => f.write_str("no data device available for this seat"),
+            DataDeviceError::NoSelection => f.write_str("the clipboard is empty"),
+            DataDeviceError::UnsupportedMimeType => {
+                f.write_str("the clipboard does not offer any of the requested mime types")
+            }
+            DataDeviceError::Io(ref err) => write!(f, "I/O error during transfer: {}", err),
+            DataDeviceError::TooLarge => f.write_str("the data exceeds the size limit"),
+            DataDeviceError::InvalidOfferState => {
+                f.write_str("invalid operation in the current state of the offer")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "calloop")]
+impl DataDeviceError {
+    // The error of a failed transfer
+    fn from_transfer(err: io::Error) -> DataDeviceError {
+        if is_size_limit_exceeded(&err) {
+            DataDeviceError::TooLarge
+        } else {
+            DataDeviceError::Io(err)
+        }
+    }
+}
+
+impl std::error::Error for DataDeviceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DataDeviceError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 type DDCallback = dyn FnMut(wl_seat::WlSeat, DndEvent, DispatchData);
 
+type SelectionCallback = dyn FnMut(wl_seat::WlSeat, Option<&[String]>, DispatchData);
+
+type SelectionLostCallback = dyn FnMut(wl_seat::WlSeat, DispatchData);
+
 enum DDInner {
     Ready {
         mgr: Attached<wl_data_device_manager::WlDataDeviceManager>,
         devices: Vec<(wl_seat::WlSeat, DataDevice)>,
-        callback: Rc<RefCell<Box<DDCallback>>>,
+        router: Rc<RefCell<DndRouter>>,
     },
     Pending {
         seats: Vec<wl_seat::WlSeat>,
     },
 }
 
+fn init_device(
+    mgr: &wl_data_device_manager::WlDataDeviceManager,
+    seat: &wl_seat::WlSeat,
+    router: Rc<RefCell<DndRouter>>,
+) -> DataDevice {
+    let my_seat = seat.clone();
+    let selection_seat = seat.clone();
+    let lost_seat = seat.clone();
+    let selection_router = router.clone();
+    let lost_router = router.clone();
+    let mut current = None;
+    DataDevice::init_with_selection_callbacks(
+        mgr,
+        seat,
+        move |event, dispatch_data| {
+            route_dnd_event(&router, &mut current, &my_seat, event, dispatch_data);
+        },
+        move |mime_types, dispatch_data| {
+            let mut router = selection_router.borrow_mut();
+            (*router.selection_callback)(selection_seat.clone(), mime_types, dispatch_data);
+        },
+        move |dispatch_data| {
+            let mut router = lost_router.borrow_mut();
+            (*router.selection_lost_callback)(lost_seat.clone(), dispatch_data);
+        },
+    )
+}
+
 impl DDInner {
     fn init_dd_mgr(&mut self, mgr: Attached<wl_data_device_manager::WlDataDeviceManager>) {
         let seats = if let DDInner::Pending { seats } = self {
@@ -41,22 +155,17 @@ impl DDInner {
 
         let mut devices = Vec::new();
 
-        let callback = Rc::new(RefCell::new(Box::new(|_, _: DndEvent, _: DispatchData| {})
-            as Box<dyn FnMut(_, DndEvent, DispatchData)>));
+        let router = Rc::new(RefCell::new(DndRouter::new()));
 
         for seat in seats {
-            let cb = callback.clone();
-            let my_seat = seat.clone();
-            let device = DataDevice::init_for_seat(&mgr, &seat, move |event, dispatch_data| {
-                (&mut *cb.borrow_mut())(my_seat.clone(), event, dispatch_data);
-            });
+            let device = init_device(&mgr, &seat, router.clone());
             devices.push((seat.clone(), device));
         }
 
         *self = DDInner::Ready {
             mgr,
             devices,
-            callback,
+            router,
         };
     }
 
@@ -68,17 +177,13 @@ impl DDInner {
             DDInner::Ready {
                 mgr,
                 devices,
-                callback,
+                router,
             } => {
                 if devices.iter().any(|(s, _)| s == seat) {
                     // the seat already exists, nothing to do
                     return;
                 }
-                let cb = callback.clone();
-                let my_seat = seat.clone();
-                let device = DataDevice::init_for_seat(mgr, seat, move |event, dispatch_data| {
-                    (&mut *cb.borrow_mut())(my_seat.clone(), event, dispatch_data);
-                });
+                let device = init_device(mgr, seat, router.clone());
                 devices.push((seat.clone(), device));
             }
             DDInner::Pending { seats } => {
@@ -106,14 +211,54 @@ impl DDInner {
         cb: F,
     ) -> Result<(), ()> {
         match self {
-            DDInner::Ready { callback, .. } => {
-                *(callback.borrow_mut()) = Box::new(cb);
+            DDInner::Ready { router, .. } => {
+                router.borrow_mut().set_callback(Box::new(cb));
                 Ok(())
             }
             DDInner::Pending { .. } => Err(()),
         }
     }
 
+    fn set_selection_callback<F>(&mut self, cb: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, Option<&[String]>, DispatchData) + 'static,
+    {
+        match self {
+            DDInner::Ready { router, .. } => {
+                router.borrow_mut().selection_callback = Box::new(cb);
+                Ok(())
+            }
+            DDInner::Pending { .. } => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+
+    fn set_selection_lost_callback<F>(&mut self, cb: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DispatchData) + 'static,
+    {
+        match self {
+            DDInner::Ready { router, .. } => {
+                router.borrow_mut().selection_lost_callback = Box::new(cb);
+                Ok(())
+            }
+            DDInner::Pending { .. } => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+
+    fn register_drop_target<F>(
+        &mut self,
+        surface: wl_surface::WlSurface,
+        cb: F,
+    ) -> Result<DropTarget, DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DropEvent, DispatchData) + 'static,
+    {
+        match self {
+            DDInner::Ready { router, .. } => Ok(router.borrow_mut().register(surface, cb)),
+            DDInner::Pending { .. } => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+
     fn with_device<F: FnOnce(&DataDevice)>(&self, seat: &wl_seat::WlSeat, f: F) -> Result<(), ()> {
         match self {
             DDInner::Pending { .. } => Err(()),
@@ -207,6 +352,29 @@ pub trait DataDeviceHandling {
     /// Returns an error if the seat is not found (for example if it has since been removed by
     /// the server) or if the `wl_data_device_manager` global is missing.
     fn with_device<F: FnOnce(&DataDevice)>(&self, seat: &wl_seat::WlSeat, f: F) -> Result<(), ()>;
+
+    /// Register a drop target for a surface
+    ///
+    /// Returns an error if the `wl_data_device_manager` global is missing.
+    fn register_drop_target<F: FnMut(wl_seat::WlSeat, DropEvent, DispatchData) + 'static>(
+        &mut self,
+        surface: wl_surface::WlSurface,
+        callback: F,
+    ) -> Result<DropTarget, DataDeviceError>;
+
+    /// Set the selection callback
+    ///
+    /// Returns an error if the `wl_data_device_manager` global is missing.
+    fn set_selection_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, Option<&[String]>, DispatchData) + 'static;
+
+    /// Set the callback invoked when another client replaces the selection you set
+    ///
+    /// Returns an error if the `wl_data_device_manager` global is missing.
+    fn set_selection_lost_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DispatchData) + 'static;
 }
 
 impl DataDeviceHandling for DataDeviceHandler {
@@ -220,6 +388,32 @@ impl DataDeviceHandling for DataDeviceHandler {
     fn with_device<F: FnOnce(&DataDevice)>(&self, seat: &wl_seat::WlSeat, f: F) -> Result<(), ()> {
         self.inner.borrow().with_device(seat, f)
     }
+
+    fn register_drop_target<F: FnMut(wl_seat::WlSeat, DropEvent, DispatchData) + 'static>(
+        &mut self,
+        surface: wl_surface::WlSurface,
+        callback: F,
+    ) -> Result<DropTarget, DataDeviceError> {
+        self.inner
+            .borrow_mut()
+            .register_drop_target(surface, callback)
+    }
+
+    fn set_selection_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, Option<&[String]>, DispatchData) + 'static,
+    {
+        self.inner.borrow_mut().set_selection_callback(callback)
+    }
+
+    fn set_selection_lost_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DispatchData) + 'static,
+    {
+        self.inner
+            .borrow_mut()
+            .set_selection_lost_callback(callback)
+    }
 }
 
 impl<E> crate::environment::Environment<E>
@@ -273,4 +467,71 @@ where
     ) -> Result<(), ()> {
         self.with_inner(|inner| inner.with_device(seat, f))
     }
+
+    /// Register a drop target for one of your surfaces
+    ///
+    /// The provided closure will be invoked for the drag'n'drop events targeting this
+    /// surface, instead of the global data device callback. Internal drag'n'drops (started
+    /// without any data source) are still delivered to the global callback.
+    ///
+    /// The returned [`DropTarget`](../data_device/struct.DropTarget.html) keeps your callback
+    /// alive, dropping it will unregister the drop target.
+    ///
+    /// Returns an error if the compositor did not advertise a data device capability.
+    pub fn register_drop_target<F: FnMut(wl_seat::WlSeat, DropEvent, DispatchData) + 'static>(
+        &self,
+        surface: wl_surface::WlSurface,
+        callback: F,
+    ) -> Result<DropTarget, DataDeviceError> {
+        self.with_inner(|inner| inner.register_drop_target(surface, callback))
+    }
+
+    /// Set the selection callback
+    ///
+    /// This callback is invoked whenever the clipboard of a seat changes, with the mime
+    /// types offered by its new content, or `None` if the clipboard is now empty. You can
+    /// use it for example to update the sensitivity of your "Paste" menu entry.
+    ///
+    /// The selection offers are managed by the data device, use
+    /// [`request_paste`](#method.request_paste) or
+    /// [`DataDevice::with_selection`](../data_device/struct.DataDevice.html#method.with_selection)
+    /// to access the current one.
+    ///
+    /// Returns an error if the compositor did not advertise a data device capability.
+    pub fn set_selection_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, Option<&[String]>, DispatchData) + 'static,
+    {
+        self.with_inner(|inner| inner.set_selection_callback(callback))
+    }
+
+    /// Set the callback invoked when you lose the ownership of the clipboard of a seat
+    ///
+    /// After you put some data into the clipboard of a seat, with the helpers like
+    /// [`copy`](#method.copy) or with a
+    /// [`DataDevice`](../data_device/struct.DataDevice.html#method.set_selection), this
+    /// callback is invoked once another client replaces it. Replacing the clipboard
+    /// yourself does not invoke it.
+    ///
+    /// You can set the clipboard again from this callback, for example to keep providing
+    /// its contents like clipboard managers do. The compositor still requires the serial
+    /// of a recent input event, like the one given by
+    /// [`latest_serial`](../seat/fn.latest_serial.html).
+    ///
+    /// Returns an error if the compositor did not advertise a data device capability.
+    pub fn set_clipboard_lost_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DispatchData) + 'static,
+    {
+        self.with_inner(|inner| inner.set_selection_lost_callback(callback))
+    }
+
+    /// Whether the clipboard of a seat currently holds data you put there
+    ///
+    /// Returns `false` if there is no data device for this seat.
+    pub fn owns_clipboard(&self, seat: &wl_seat::WlSeat) -> bool {
+        let mut owned = false;
+        let _ = self.with_data_device(seat, |device| owned = device.owns_selection());
+        owned
+    }
 }
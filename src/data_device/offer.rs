@@ -8,18 +8,39 @@ use wayland_client::protocol::wl_data_device_manager::DndAction;
 use wayland_client::protocol::wl_data_offer;
 use wayland_client::Main;
 
+use super::{DataDeviceError, LocalData};
+use crate::environment::has_version;
+
 struct Inner {
     mime_types: Vec<String>,
     actions: DndAction,
     current_action: DndAction,
     serial: u32,
+    accepted: bool,
+    dropped: bool,
+    finished: bool,
+    local: Option<LocalData>,
+}
+
+// Destroys the offer once the last handle to it is dropped
+struct OfferGuard(wl_data_offer::WlDataOffer);
+
+impl Drop for OfferGuard {
+    fn drop(&mut self) {
+        self.0.destroy();
+    }
 }
 
 /// A data offer for receiving data though copy/paste or
 /// drag and drop
+///
+/// This handle can be cloned, the offer is destroyed once all of its clones
+/// are dropped (or once it is finished).
+#[derive(Clone)]
 pub struct DataOffer {
     pub(crate) offer: wl_data_offer::WlDataOffer,
     inner: Arc<Mutex<Inner>>,
+    _guard: Arc<OfferGuard>,
 }
 
 impl DataOffer {
@@ -29,6 +50,10 @@ impl DataOffer {
             actions: DndAction::None,
             current_action: DndAction::None,
             serial: 0,
+            accepted: false,
+            dropped: false,
+            finished: false,
+            local: None,
         }));
         let inner2 = inner.clone();
         offer.quick_assign(move |_, event, _| {
@@ -48,12 +73,34 @@ impl DataOffer {
             }
         });
 
+        let offer = offer.detach();
         DataOffer {
-            offer: offer.detach(),
+            _guard: Arc::new(OfferGuard(offer.clone())),
+            offer,
             inner,
         }
     }
 
+    pub(crate) fn set_serial(&self, serial: u32) {
+        self.inner.lock().unwrap().serial = serial;
+    }
+
+    pub(crate) fn set_dropped(&self) {
+        self.inner.lock().unwrap().dropped = true;
+    }
+
+    pub(crate) fn set_local(&self, local: Option<LocalData>) {
+        self.inner.lock().unwrap().local = local;
+    }
+
+    /// Whether this offer was dropped and the target may still be receiving its data
+    ///
+    /// This is not the case of a drop still waiting for the "ask" action to be resolved.
+    pub(crate) fn in_transfer(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.dropped && !inner.finished && inner.current_action != DndAction::Ask
+    }
+
     /// Access the list of mime types proposed by this offer
     pub fn with_mime_types<F, T>(&self, f: F) -> T
     where
@@ -74,9 +121,28 @@ impl DataOffer {
     }
 
     /// Accept a mime type for receiving data through this offer
+    ///
+    /// During a drag'n'drop, this tells the source whether the data can be dropped
+    /// on your surface. Setting it to `None` means you will not accept the drop.
     pub fn accept(&self, mime_type: Option<String>) {
-        let serial = self.inner.lock().unwrap().serial;
-        self.offer.accept(serial, mime_type);
+        let mut inner = self.inner.lock().unwrap();
+        inner.accepted = mime_type.is_some();
+        self.offer.accept(inner.serial, mime_type);
+    }
+
+    /// Get the data of a given mime type directly, if it comes from your application
+    ///
+    /// This returns `Some` if this offer was created for a data source of your own, to
+    /// which you attached a [`LocalData`](struct.LocalData.html) providing this mime type.
+    /// The data can then be obtained without going through a pipe. You still need
+    /// to perform the rest of the drag'n'drop negotiation (`accept`, `set_actions` and
+    /// `finish`) as usual.
+    pub fn local_data(&self, mime_type: &str) -> Option<Vec<u8>> {
+        let inner = self.inner.lock().unwrap();
+        if !inner.mime_types.iter().any(|m| m == mime_type) {
+            return None;
+        }
+        inner.local.as_ref().and_then(|local| local.get(mime_type))
     }
 
     /// Request to receive the data of a given mime type
@@ -93,18 +159,7 @@ impl DataOffer {
     /// Fails if too many file descriptors were already open and a pipe
     /// could not be created.
     pub fn receive(&self, mime_type: String) -> Result<ReadPipe, ()> {
-        use nix::fcntl::OFlag;
-        use nix::unistd::{close, pipe2};
-        // create a pipe
-        let (readfd, writefd) = pipe2(OFlag::O_CLOEXEC).map_err(|_| ())?;
-
-        self.offer.receive(mime_type, writefd);
-
-        if let Err(err) = close(writefd) {
-            log::warn!("Failed to close write pipe: {}", err);
-        }
-
-        Ok(unsafe { FromRawFd::from_raw_fd(readfd) })
+        super::receive_pipe(|fd| self.offer.receive(mime_type, fd)).map_err(|_| ())
     }
 
     /// Notify the send and compositor of the dnd actions you accept
@@ -116,20 +171,68 @@ impl DataOffer {
             .set_actions(supported.to_raw(), preferred.to_raw());
     }
 
-    /// Notify that you are finished with this offer, and will no longer
-    /// be using it
+    /// Resolve the "ask" action of a dropped drag'n'drop
+    ///
+    /// When the action selected at drop time is `DndAction::Ask`, you are expected to
+    /// let the user choose the final action (typically from a popup menu), and then
+    /// notify it with this method. Once the compositor confirms it with a new value
+    /// of [`get_current_action`](#method.get_current_action), you can receive the data
+    /// and [`finish`](#method.finish) the offer.
+    ///
+    /// If you need to wait for the user at the end of your drop callback, keep a clone
+    /// of this offer: the drag'n'drop is cancelled once all handles to an offer that
+    /// is still waiting for a choice are dropped.
+    ///
+    /// The action must be either `DndAction::Copy` or `DndAction::Move`, and be
+    /// supported by the source. Returns an error if the offer is not dropped with
+    /// "ask" selected, or if the action is not valid.
+    pub fn choose_action(&self, action: DndAction) -> Result<(), DataDeviceError> {
+        let inner = self.inner.lock().unwrap();
+        let valid = (action == DndAction::Copy || action == DndAction::Move)
+            && inner.actions.contains(action);
+        if !valid || !inner.dropped || inner.finished || inner.current_action != DndAction::Ask {
+            return Err(DataDeviceError::InvalidOfferState);
+        }
+        self.offer.set_actions(action.to_raw(), action.to_raw());
+        Ok(())
+    }
+
+    /// Cancel this drag'n'drop
     ///
-    /// Note that it is a protocol error to finish if no action or mime
-    /// type was accepted.
-    pub fn finish(&self) {
-        self.offer.finish();
+    /// This destroys the offer, the source is then notified that the drag'n'drop was
+    /// cancelled. Any further use of this offer is ignored.
+    pub fn cancel(&self) {
+        self.inner.lock().unwrap().finished = true;
         self.offer.destroy();
     }
-}
 
-impl Drop for DataOffer {
-    fn drop(&mut self) {
+    /// Notify that you are finished with this offer, and will no longer
+    /// be using it
+    ///
+    /// This must be called exactly once at the end of a successful drag'n'drop
+    /// onto one of your surfaces, once you have received the data. It is only
+    /// valid after the drop, if a mime type was accepted and an action was
+    /// selected. An error is returned instead of sending an invalid request if
+    /// these conditions are not met.
+    ///
+    /// On compositors that do not support version 3 of the data device protocol,
+    /// this simply destroys the offer.
+    pub fn finish(&self) -> Result<(), DataDeviceError> {
+        let mut inner = self.inner.lock().unwrap();
+        if has_version(&self.offer, 3) {
+            if inner.finished
+                || !inner.dropped
+                || !inner.accepted
+                || inner.current_action.is_empty()
+                || inner.current_action == DndAction::Ask
+            {
+                return Err(DataDeviceError::InvalidOfferState);
+            }
+            self.offer.finish();
+        }
+        inner.finished = true;
         self.offer.destroy();
+        Ok(())
     }
 }
 
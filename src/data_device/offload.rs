@@ -0,0 +1,172 @@
+//! Clipboard contents produced by worker threads
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::io::{FromRawFd, IntoRawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use wayland_client::protocol::{wl_data_device_manager, wl_data_source, wl_seat};
+
+use super::{DataDeviceError, DataDeviceHandling, DataSource, DataSourceEvent};
+use crate::environment::{Environment, GlobalHandler};
+
+/// A request for the contents of the clipboard, handled by a worker thread
+///
+/// It is given to the provider of
+/// [`Environment::copy_offloaded`](../environment/struct.Environment.html#method.copy_offloaded),
+/// which writes the data of the requested mime type into it. The pipe is closed once it is
+/// dropped, which completes the transfer.
+///
+/// Once the data source is cancelled, writing into it fails with an error of kind
+/// `BrokenPipe`, so that the worker stops producing the data.
+pub struct OffloadedSend {
+    mime_type: String,
+    file: fs::File,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl OffloadedSend {
+    /// The requested mime type
+    pub fn mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /// Whether the data source was cancelled, in which case the data is no longer needed
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+impl Write for OffloadedSend {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_cancelled() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the data source was cancelled",
+            ));
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// The transfers of a data source still running on workers
+struct Transfers {
+    pending: usize,
+    ended: bool,
+    source: Option<wl_data_source::WlDataSource>,
+}
+
+impl Transfers {
+    // The source is destroyed once it ended and all its transfers are complete
+    fn destroy_if_done(&mut self) {
+        if self.ended && self.pending == 0 {
+            if let Some(source) = self.source.take() {
+                source.destroy();
+            }
+        }
+    }
+}
+
+// Marks a transfer complete when dropped, be it run or not by the spawn function
+struct TransferGuard(Arc<Mutex<Transfers>>);
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        let mut transfers = self.0.lock().unwrap();
+        transfers.pending -= 1;
+        transfers.destroy_if_done();
+    }
+}
+
+impl<E> Environment<E>
+where
+    E: DataDeviceHandling + GlobalHandler<wl_data_device_manager::WlDataDeviceManager>,
+{
+    /// Put some data produced by worker threads into the clipboard of a seat
+    ///
+    /// This is similar to [`copy_with`](#method.copy_with), but for data expensive to
+    /// produce, like an image to encode: each time a client requests the contents of the
+    /// clipboard, a job running `provider` is given to `spawn`, which needs to run it on
+    /// another thread, for example with `|job| { std::thread::spawn(job); }` or in a thread
+    /// pool. The provider writes the data of the requested mime type into the
+    /// [`OffloadedSend`](../data_device/struct.OffloadedSend.html) it is given, and the
+    /// transfer is complete once it returns.
+    ///
+    /// Once the contents of the clipboard are replaced, the running providers are signaled
+    /// to stop, their writes failing with an error of kind `BrokenPipe`, and the data source
+    /// is only destroyed once all of them returned.
+    ///
+    /// Returns an error if there is no data device for this seat.
+    pub fn copy_offloaded<S, F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        mime_types: Vec<String>,
+        mut spawn: S,
+        provider: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        S: FnMut(Box<dyn FnOnce() + Send>) + 'static,
+        F: Fn(&mut OffloadedSend) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let provider = Arc::new(provider);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let transfers = Arc::new(Mutex::new(Transfers {
+            pending: 0,
+            ended: false,
+            source: None,
+        }));
+        let my_transfers = transfers.clone();
+        let ddmgr = self.require_global::<wl_data_device_manager::WlDataDeviceManager>();
+        let source = DataSource::new_inner(&ddmgr, mime_types, false, move |event, _| {
+            match event {
+                DataSourceEvent::Send { mime_type, pipe } => {
+                    my_transfers.lock().unwrap().pending += 1;
+                    let guard = TransferGuard(my_transfers.clone());
+                    let mut send = OffloadedSend {
+                        mime_type,
+                        // the worker only needs a plain file
+                        file: unsafe { fs::File::from_raw_fd(pipe.into_raw_fd()) },
+                        cancelled: cancelled.clone(),
+                    };
+                    let provider = provider.clone();
+                    spawn(Box::new(move || {
+                        match provider(&mut send) {
+                            Ok(()) => {}
+                            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+                            Err(err) => log::warn!("[SCTK] Failed to send clipboard data: {}", err),
+                        }
+                        // close the pipe before the transfer is marked complete
+                        drop(send);
+                        drop(guard);
+                    }));
+                }
+                DataSourceEvent::Cancelled | DataSourceEvent::Finished => {
+                    cancelled.store(true, Ordering::Release);
+                    let mut transfers = my_transfers.lock().unwrap();
+                    transfers.ended = true;
+                    transfers.destroy_if_done();
+                }
+                _ => {}
+            }
+        });
+        transfers.lock().unwrap().source = Some(source.source.clone());
+        let source = Some(source);
+        self.with_data_device(seat, |device| device.set_selection(&source, serial))
+            .map_err(|()| {
+                if let Some(source) = source {
+                    source.source.destroy();
+                }
+                DataDeviceError::NoDataDevice
+            })
+    }
+}
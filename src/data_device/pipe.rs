@@ -0,0 +1,424 @@
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+#[cfg(feature = "calloop")]
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+#[cfg(feature = "calloop")]
+use super::DataOffer;
+use super::{ReadPipe, WritePipe};
+
+/// Create a pipe and send its write end to the peer through `request`
+///
+/// The write end is closed once the request is sent, and the read end returned.
+pub(crate) fn receive_pipe<F: FnOnce(RawFd)>(request: F) -> io::Result<ReadPipe> {
+    use nix::fcntl::OFlag;
+    use nix::unistd::{close, pipe2};
+    use std::os::unix::io::FromRawFd;
+    // create a pipe
+    let (readfd, writefd) = pipe2(OFlag::O_CLOEXEC).map_err(nix_to_io)?;
+
+    request(writefd);
+
+    if let Err(err) = close(writefd) {
+        log::warn!("[SCTK] Failed to close write pipe: {}", err);
+    }
+
+    Ok(unsafe { FromRawFd::from_raw_fd(readfd) })
+}
+
+// Size of the chunks read or written each time a pipe is ready
+#[cfg(feature = "calloop")]
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// The error of a transfer aborted because the data exceeds its size limit
+#[cfg(feature = "calloop")]
+#[derive(Debug)]
+struct SizeLimitExceeded;
+
+#[cfg(feature = "calloop")]
+impl fmt::Display for SizeLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("received data exceeds the size limit")
+    }
+}
+
+#[cfg(feature = "calloop")]
+impl Error for SizeLimitExceeded {}
+
+#[cfg(feature = "calloop")]
+fn size_limit_exceeded() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, SizeLimitExceeded)
+}
+
+/// Whether this error comes from a transfer aborted because of its size limit
+#[cfg(feature = "calloop")]
+pub(crate) fn is_size_limit_exceeded(err: &io::Error) -> bool {
+    err.get_ref()
+        .map(|err| err.is::<SizeLimitExceeded>())
+        .unwrap_or(false)
+}
+
+fn nix_to_io(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err.as_errno().unwrap_or(nix::errno::Errno::EINVAL) as i32)
+}
+
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+    let mut flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).map_err(nix_to_io)?);
+    flags.set(OFlag::O_NONBLOCK, nonblocking);
+    fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(nix_to_io)?;
+    Ok(())
+}
+
+impl ReadPipe {
+    /// Set whether reading from this pipe should block
+    ///
+    /// Pipes are blocking by default. Reading from a blocking pipe in the same thread as
+    /// your event loop can deadlock your application if the other end is not ready,
+    /// typically if it is your own application.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        set_nonblocking(self.as_raw_fd(), nonblocking)
+    }
+
+    /// Read the whole contents of this pipe from a calloop event loop
+    ///
+    /// The pipe is made non-blocking, and its contents are read by chunks whenever it
+    /// is ready, until the writer closes it. The callback is then invoked with the
+    /// contents, and the pipe is closed.
+    ///
+    /// If a `limit` is given, the transfer is aborted and the callback receives an
+    /// error of kind `InvalidData` as soon as more than `limit` bytes are received.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn read_to_end_async<Data, F>(
+        self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        limit: Option<usize>,
+        callback: F,
+    ) -> io::Result<()>
+    where
+        Data: 'static,
+        F: FnOnce(io::Result<Vec<u8>>, &mut Data) + 'static,
+    {
+        self.read_into_async(loop_handle, Vec::new(), limit, |_| (), callback)
+    }
+
+    /// Stream the whole contents of this pipe into a sink from a calloop event loop
+    ///
+    /// This is similar to [`read_to_end_async`](#method.read_to_end_async), but each chunk is
+    /// written to `sink` as soon as it is received, and `progress` is then invoked with the
+    /// total number of bytes received so far. Once the writer closes the pipe, the sink is
+    /// flushed and given back to the callback.
+    ///
+    /// If a `limit` is given, the pipe is closed and the callback receives an error of kind
+    /// `InvalidData` as soon as more than `limit` bytes are received, the data written to the
+    /// sink until then being left in it. A write error of the sink also aborts the transfer.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn read_into_async<Data, W, P, F>(
+        self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        sink: W,
+        limit: Option<usize>,
+        mut progress: P,
+        callback: F,
+    ) -> io::Result<()>
+    where
+        Data: 'static,
+        W: Write + 'static,
+        P: FnMut(usize) + 'static,
+        F: FnOnce(io::Result<W>, &mut Data) + 'static,
+    {
+        self.set_nonblocking(true)?;
+        let token = Rc::new(RefCell::new(None));
+        let token_2 = token.clone();
+        let handle = loop_handle.clone();
+        let mut callback = Some(callback);
+        let mut sink = Some(sink);
+        let mut received = 0;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let inserted = loop_handle
+            .insert_source(self, move |(), file, data| {
+                let result = match file.read(&mut buffer) {
+                    Ok(0) => Some(Ok(())),
+                    Ok(n) if limit.map(|l| received + n > l).unwrap_or(false) => {
+                        Some(Err(size_limit_exceeded()))
+                    }
+                    Ok(n) => match sink.as_mut().map(|sink| sink.write_all(&buffer[..n])) {
+                        Some(Err(err)) => Some(Err(err)),
+                        _ => {
+                            received += n;
+                            progress(received);
+                            None
+                        }
+                    },
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => None,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => None,
+                    Err(err) => Some(Err(err)),
+                };
+                if let Some(result) = result {
+                    // dropping the source closes the pipe, the offer itself can still
+                    // be received again
+                    if let Some(token) = token_2.borrow_mut().take() {
+                        handle.kill(token);
+                    }
+                    if let (Some(cb), Some(mut sink)) = (callback.take(), sink.take()) {
+                        let result = result.and_then(|()| sink.flush()).map(|()| sink);
+                        cb(result, data);
+                        crate::event_loop::flush_connections();
+                    }
+                }
+            })
+            .map_err(|e| e.error)?;
+        *token.borrow_mut() = Some(inserted);
+        Ok(())
+    }
+}
+
+impl WritePipe {
+    /// Set whether writing to this pipe should block
+    ///
+    /// Pipes are blocking by default. Writing to a blocking pipe in the same thread as
+    /// your event loop can deadlock your application if the other end does not read
+    /// it, typically if it is your own application.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        set_nonblocking(self.as_raw_fd(), nonblocking)
+    }
+
+    /// Write all of `data` into this pipe from a calloop event loop
+    ///
+    /// The pipe is made non-blocking, and the data is written by chunks whenever it is
+    /// ready. Once everything is written, or if the reader went away (the error is then
+    /// of kind `BrokenPipe`), the pipe is closed and the callback is invoked.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn write_all_async<Data, T, F>(
+        self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        data: T,
+        callback: F,
+    ) -> io::Result<()>
+    where
+        Data: 'static,
+        T: AsRef<[u8]> + 'static,
+        F: FnOnce(io::Result<()>, &mut Data) + 'static,
+    {
+        self.write_all_async_with(loop_handle, data, CHUNK_SIZE, callback)
+            .map(|_| ())
+    }
+
+    /// Write all of `data` into this pipe from a calloop event loop, by chunks of at most
+    /// `chunk_size` bytes
+    ///
+    /// This is like [`write_all_async`](#method.write_all_async), but the returned
+    /// [`WriteTransfer`](struct.WriteTransfer.html) lets you follow the progress of the
+    /// transfer, or cancel it.
+    ///
+    /// A single chunk is written each time the pipe is ready, after which your event loop
+    /// dispatches its other events, so large payloads sent to a slow reader do not stall
+    /// your application. The chunk size bounds the time spent on each of them. Several
+    /// transfers, for example of different mime types, can run at once and progress
+    /// independently.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn write_all_async_with<Data, T, F>(
+        self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        data: T,
+        chunk_size: usize,
+        callback: F,
+    ) -> io::Result<WriteTransfer>
+    where
+        Data: 'static,
+        T: AsRef<[u8]> + 'static,
+        F: FnOnce(io::Result<()>, &mut Data) + 'static,
+    {
+        self.set_nonblocking(true)?;
+        let chunk_size = std::cmp::max(chunk_size, 1);
+        let len = data.as_ref().len();
+        let written = Rc::new(Cell::new(0));
+        let my_written = written.clone();
+        // the source and the callback, taken by whichever ends the transfer first
+        let state: Rc<RefCell<Option<(Option<calloop::Source<WritePipe>>, F)>>> =
+            Rc::new(RefCell::new(None));
+        let my_state = state.clone();
+        let handle = loop_handle.clone();
+        let inserted = loop_handle
+            .insert_source(self, move |(), file, ddata| {
+                let data = data.as_ref();
+                let start = my_written.get();
+                let end = std::cmp::min(start + chunk_size, data.len());
+                let result = match file.write(&data[start..end]) {
+                    Ok(n) => {
+                        my_written.set(start + n);
+                        if start + n >= data.len() {
+                            Some(Ok(()))
+                        } else {
+                            None
+                        }
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => None,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => None,
+                    Err(err) => Some(Err(err)),
+                };
+                if let Some(result) = result {
+                    if let Some((token, cb)) = my_state.borrow_mut().take() {
+                        // dropping the source closes the pipe
+                        if let Some(token) = token {
+                            handle.kill(token);
+                        }
+                        cb(result, ddata);
+                        crate::event_loop::flush_connections();
+                    }
+                }
+            })
+            .map_err(|e| e.error)?;
+        *state.borrow_mut() = Some((Some(inserted), callback));
+        let handle = loop_handle.clone();
+        let cancel = move || {
+            if let Some((token, cb)) = state.borrow_mut().take() {
+                if let Some(token) = token {
+                    handle.kill(token);
+                }
+                // the callback needs the data of the event loop
+                handle.insert_idle(move |ddata| {
+                    cb(
+                        Err(io::Error::new(
+                            io::ErrorKind::Interrupted,
+                            "the transfer was cancelled",
+                        )),
+                        ddata,
+                    );
+                    crate::event_loop::flush_connections();
+                });
+            }
+        };
+        Ok(WriteTransfer {
+            written,
+            total: len,
+            cancel: Rc::new(cancel),
+        })
+    }
+}
+
+/// A transfer started with
+/// [`WritePipe::write_all_async_with`](struct.WritePipe.html#method.write_all_async_with)
+///
+/// Dropping it does not interrupt the transfer.
+///
+/// **Note:** This requires the `calloop` cargo feature.
+#[cfg(feature = "calloop")]
+#[derive(Clone)]
+pub struct WriteTransfer {
+    written: Rc<Cell<usize>>,
+    total: usize,
+    cancel: Rc<dyn Fn()>,
+}
+
+#[cfg(feature = "calloop")]
+impl WriteTransfer {
+    /// The number of bytes written so far
+    pub fn written(&self) -> usize {
+        self.written.get()
+    }
+
+    /// The total number of bytes to write
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Cancel the transfer
+    ///
+    /// The pipe is closed, so the reader gets truncated data, and the callback is invoked
+    /// from the event loop with an error of kind `Interrupted`. This does nothing if the
+    /// transfer is already complete.
+    pub fn cancel(&self) {
+        (self.cancel)()
+    }
+}
+
+#[cfg(feature = "calloop")]
+impl DataOffer {
+    /// Receive the whole data of a given mime type from a calloop event loop
+    ///
+    /// This requests the data and reads it with
+    /// [`ReadPipe::read_to_end_async`](struct.ReadPipe.html#method.read_to_end_async).
+    /// If the offer comes from one of your own data sources which has some
+    /// [`LocalData`](struct.LocalData.html), the data is obtained from it directly
+    /// instead. In both cases the callback is invoked from the event loop once the
+    /// data is available.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    pub fn read_to_end_async<Data, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        mime_type: String,
+        callback: F,
+    ) -> io::Result<()>
+    where
+        Data: 'static,
+        F: FnOnce(io::Result<Vec<u8>>, &mut Data) + 'static,
+    {
+        self.read_into_async(loop_handle, mime_type, Vec::new(), None, |_| (), callback)
+    }
+
+    /// Stream the data of a given mime type into a sink from a calloop event loop
+    ///
+    /// This requests the data and reads it with
+    /// [`ReadPipe::read_into_async`](struct.ReadPipe.html#method.read_into_async), see it
+    /// for the meaning of the parameters. As with
+    /// [`read_to_end_async`](#method.read_to_end_async), the data of your own data sources is
+    /// obtained directly, and written to the sink at once.
+    ///
+    /// Aborting a transfer only closes its pipe: the offer can still be received again.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    pub fn read_into_async<Data, W, P, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        mime_type: String,
+        mut sink: W,
+        limit: Option<usize>,
+        mut progress: P,
+        callback: F,
+    ) -> io::Result<()>
+    where
+        Data: 'static,
+        W: Write + 'static,
+        P: FnMut(usize) + 'static,
+        F: FnOnce(io::Result<W>, &mut Data) + 'static,
+    {
+        if let Some(contents) = self.local_data(&mime_type) {
+            loop_handle.insert_idle(move |data| {
+                let result = if limit.map(|l| contents.len() > l).unwrap_or(false) {
+                    Err(size_limit_exceeded())
+                } else {
+                    sink.write_all(&contents).and_then(|()| {
+                        progress(contents.len());
+                        sink.flush()
+                    })
+                };
+                callback(result.map(|()| sink), data);
+                crate::event_loop::flush_connections();
+            });
+            return Ok(());
+        }
+        let pipe = self
+            .receive(mime_type)
+            .map_err(|()| io::Error::last_os_error())?;
+        pipe.read_into_async(loop_handle, sink, limit, progress, callback)
+    }
+}
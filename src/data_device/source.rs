@@ -4,12 +4,46 @@ use wayland_client::{
 };
 
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
-use std::{fs, io};
+use std::{
+    cell::{Cell, RefCell},
+    fs, io,
+    rc::Rc,
+};
+
+use super::LocalData;
+
+// Invoked by SCTK when a source is cancelled, before its callback
+pub(crate) type CancelHook = Rc<RefCell<Option<Box<dyn FnOnce(DispatchData)>>>>;
 
 /// A data source for sending data though copy/paste or
 /// drag and drop
 pub struct DataSource {
     pub(crate) source: wl_data_source::WlDataSource,
+    pub(crate) local: Option<LocalData>,
+    pub(crate) cancel_hook: CancelHook,
+    action: SelectedAction,
+}
+
+/// The drag'n'drop action currently selected for a data source
+///
+/// This tracks the `Action` events of the source, so that you can check which action
+/// the target and compositor settled on at any time, for example to update the cursor
+/// or to know whether to delete the data once the drag'n'drop is finished.
+///
+/// An action of `DndAction::Ask` means the target will let the user choose the final
+/// action after the drop, a new action will then be selected before `Finished`.
+#[derive(Clone, Debug)]
+pub struct SelectedAction {
+    action: Rc<Cell<wl_data_device_manager::DndAction>>,
+}
+
+impl SelectedAction {
+    /// The currently selected action
+    ///
+    /// This is `DndAction::None` until the target selected an action.
+    pub fn get(&self) -> wl_data_device_manager::DndAction {
+        self.action.get()
+    }
 }
 
 /// Possible events a data source needs to react to
@@ -80,8 +114,11 @@ pub enum DataSourceEvent {
 fn data_source_impl<Impl>(
     evt: wl_data_source::Event,
     source: &wl_data_source::WlDataSource,
+    action: &SelectedAction,
+    cancel_hook: &CancelHook,
+    destroy_on_end: bool,
     implem: &mut Impl,
-    ddata: DispatchData,
+    mut ddata: DispatchData,
 ) where
     Impl: FnMut(DataSourceEvent, DispatchData),
 {
@@ -92,16 +129,27 @@ fn data_source_impl<Impl>(
             mime_type,
             pipe: unsafe { FromRawFd::from_raw_fd(fd) },
         },
-        Event::Action { dnd_action } => DataSourceEvent::Action {
-            action: wl_data_device_manager::DndAction::from_bits_truncate(dnd_action),
-        },
+        Event::Action { dnd_action } => {
+            let dnd_action = wl_data_device_manager::DndAction::from_bits_truncate(dnd_action);
+            action.action.set(dnd_action);
+            DataSourceEvent::Action { action: dnd_action }
+        }
         Event::Cancelled => {
-            source.destroy();
+            if destroy_on_end {
+                source.destroy();
+            }
+            // the hook is taken out first, so that it can set a new selection
+            let hook = cancel_hook.borrow_mut().take();
+            if let Some(hook) = hook {
+                hook(ddata.reborrow());
+            }
             DataSourceEvent::Cancelled
         }
         Event::DndDropPerformed => DataSourceEvent::Dropped,
         Event::DndFinished => {
-            source.destroy();
+            if destroy_on_end {
+                source.destroy();
+            }
             DataSourceEvent::Finished
         }
         _ => unreachable!(),
@@ -117,6 +165,22 @@ impl DataSource {
     pub fn new<F, S, It>(
         mgr: &Attached<wl_data_device_manager::WlDataDeviceManager>,
         mime_types: It,
+        callback: F,
+    ) -> DataSource
+    where
+        F: FnMut(DataSourceEvent, DispatchData) + 'static,
+        S: Into<String>,
+        It: IntoIterator<Item = S>,
+    {
+        DataSource::new_inner(mgr, mime_types, true, callback)
+    }
+
+    // Create a data source, which is only destroyed on `Cancelled` and `Finished` if
+    // `destroy_on_end` is set, otherwise the callback is responsible for it
+    pub(crate) fn new_inner<F, S, It>(
+        mgr: &Attached<wl_data_device_manager::WlDataDeviceManager>,
+        mime_types: It,
+        destroy_on_end: bool,
         mut callback: F,
     ) -> DataSource
     where
@@ -124,9 +188,23 @@ impl DataSource {
         S: Into<String>,
         It: IntoIterator<Item = S>,
     {
+        let action = SelectedAction {
+            action: Rc::new(Cell::new(wl_data_device_manager::DndAction::None)),
+        };
+        let action_2 = action.clone();
+        let cancel_hook: CancelHook = Rc::new(RefCell::new(None));
+        let cancel_hook_2 = cancel_hook.clone();
         let source = mgr.create_data_source();
         source.quick_assign(move |source, evt, dispatch_data| {
-            data_source_impl(evt, &source, &mut callback, dispatch_data)
+            data_source_impl(
+                evt,
+                &source,
+                &action_2,
+                &cancel_hook_2,
+                destroy_on_end,
+                &mut callback,
+                dispatch_data,
+            )
         });
 
         for mime in mime_types {
@@ -135,15 +213,53 @@ impl DataSource {
 
         DataSource {
             source: source.detach(),
+            local: None,
+            cancel_hook,
+            action,
         }
     }
+
+    /// Provide direct access to the data of this source
+    ///
+    /// If the data is then received by your own application, through a drop onto one
+    /// of your surfaces or a paste, it is obtained from this `LocalData` rather than
+    /// through a pipe, when using `DataOffer::read_to_end_async` or
+    /// `Environment::request_paste`. The source still needs to handle `Send` events
+    /// for the other clients.
+    pub fn set_local_data(&mut self, data: LocalData) {
+        self.local = Some(data);
+    }
+
+    /// Get a handle tracking the drag'n'drop action selected for this source
+    ///
+    /// It remains valid after the source was given to a data device.
+    pub fn selected_action(&self) -> SelectedAction {
+        self.action.clone()
+    }
 }
 
 /// A file descriptor that can only be written to
+///
+/// If the `calloop` cargo feature is enabled, this can be used
+/// as an `EventSource` in a calloop event loop.
 pub struct WritePipe {
+    #[cfg(feature = "calloop")]
+    file: calloop::generic::Generic<fs::File>,
+    #[cfg(not(feature = "calloop"))]
     file: fs::File,
 }
 
+#[cfg(feature = "calloop")]
+impl io::Write for WritePipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.file.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.file.flush()
+    }
+}
+
+#[cfg(not(feature = "calloop"))]
 impl io::Write for WritePipe {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.file.write(buf)
@@ -153,6 +269,20 @@ impl io::Write for WritePipe {
     }
 }
 
+#[cfg(feature = "calloop")]
+impl FromRawFd for WritePipe {
+    unsafe fn from_raw_fd(fd: RawFd) -> WritePipe {
+        WritePipe {
+            file: calloop::generic::Generic::new(
+                FromRawFd::from_raw_fd(fd),
+                calloop::Interest::Writable,
+                calloop::Mode::Level,
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "calloop"))]
 impl FromRawFd for WritePipe {
     unsafe fn from_raw_fd(fd: RawFd) -> WritePipe {
         WritePipe {
@@ -161,14 +291,68 @@ impl FromRawFd for WritePipe {
     }
 }
 
+#[cfg(feature = "calloop")]
+impl AsRawFd for WritePipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.file.as_raw_fd()
+    }
+}
+
+#[cfg(not(feature = "calloop"))]
 impl AsRawFd for WritePipe {
     fn as_raw_fd(&self) -> RawFd {
         self.file.as_raw_fd()
     }
 }
 
+#[cfg(feature = "calloop")]
+impl IntoRawFd for WritePipe {
+    fn into_raw_fd(self) -> RawFd {
+        self.file.file.into_raw_fd()
+    }
+}
+
+#[cfg(not(feature = "calloop"))]
 impl IntoRawFd for WritePipe {
     fn into_raw_fd(self) -> RawFd {
         self.file.into_raw_fd()
     }
 }
+
+#[cfg(feature = "calloop")]
+impl calloop::EventSource for WritePipe {
+    type Event = ();
+    type Metadata = fs::File;
+    type Ret = ();
+
+    fn process_events<F>(
+        &mut self,
+        readiness: calloop::Readiness,
+        token: calloop::Token,
+        mut callback: F,
+    ) -> std::io::Result<()>
+    where
+        F: FnMut((), &mut fs::File),
+    {
+        self.file.process_events(readiness, token, |_, file| {
+            callback((), file);
+            Ok(())
+        })
+    }
+
+    fn register(&mut self, poll: &mut calloop::Poll, token: calloop::Token) -> std::io::Result<()> {
+        self.file.register(poll, token)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut calloop::Poll,
+        token: calloop::Token,
+    ) -> std::io::Result<()> {
+        self.file.reregister(poll, token)
+    }
+
+    fn unregister(&mut self, poll: &mut calloop::Poll) -> std::io::Result<()> {
+        self.file.unregister(poll)
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use wayland_client::{
+    protocol::{wl_seat, wl_surface},
+    DispatchData,
+};
+
+use super::{DDCallback, DataOffer, DndEvent, SelectionCallback, SelectionLostCallback};
+
+/// Possible events received by a drop target
+pub enum DropEvent<'a> {
+    /// A drag'n'drop entered the surface
+    ///
+    /// You should use `DataOffer::accept` and `DataOffer::set_actions` to
+    /// notify the source of whether you would accept a drop.
+    Enter {
+        /// The associated data offer
+        offer: &'a DataOffer,
+        /// A serial associated with the entry of this dnd
+        serial: u32,
+        /// Location on the surface
+        position: (f64, f64),
+        /// The mime types proposed by the offer
+        mime_types: Vec<String>,
+    },
+    /// The drag'n'drop moved on the surface
+    Motion {
+        /// The associated data offer
+        offer: &'a DataOffer,
+        /// The time of this motion
+        time: u32,
+        /// New location on the surface
+        position: (f64, f64),
+    },
+    /// The drag'n'drop left the surface
+    Leave,
+    /// The drag'n'drop was dropped on the surface
+    ///
+    /// You can now receive the data from the offer, and need to call
+    /// `DataOffer::finish` once done. If the selected action is `DndAction::Ask`,
+    /// you first need to resolve it with `DataOffer::choose_action`.
+    Drop {
+        /// The associated data offer
+        offer: &'a DataOffer,
+    },
+}
+
+type DropCallback = dyn FnMut(wl_seat::WlSeat, DropEvent, DispatchData);
+
+/// A handle to a drop target callback
+///
+/// Dropping it unregisters the drop target and frees the closure.
+pub struct DropTarget {
+    _cb: Rc<RefCell<DropCallback>>,
+}
+
+/// Dispatches the drag'n'drop events of the data devices between the global
+/// callback and the registered drop targets
+pub(crate) struct DndRouter {
+    callback: Box<DDCallback>,
+    // whether the callback is taken out while it is invoked
+    callback_taken: bool,
+    pub(crate) selection_callback: Box<SelectionCallback>,
+    pub(crate) selection_lost_callback: Box<SelectionLostCallback>,
+    targets: Vec<(wl_surface::WlSurface, Weak<RefCell<DropCallback>>)>,
+}
+
+impl DndRouter {
+    pub(crate) fn new() -> DndRouter {
+        DndRouter {
+            callback: Box::new(|_, _: DndEvent, _: DispatchData| {}),
+            callback_taken: false,
+            selection_callback: Box::new(|_, _, _| {}),
+            selection_lost_callback: Box::new(|_, _| {}),
+            targets: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_callback(&mut self, callback: Box<DDCallback>) {
+        self.callback = callback;
+        // the callback being invoked must not be restored over this one
+        self.callback_taken = false;
+    }
+
+    pub(crate) fn register<F>(&mut self, surface: wl_surface::WlSurface, f: F) -> DropTarget
+    where
+        F: FnMut(wl_seat::WlSeat, DropEvent, DispatchData) + 'static,
+    {
+        let rc = Rc::new(RefCell::new(f)) as Rc<_>;
+        self.targets.push((surface, Rc::downgrade(&rc)));
+        DropTarget { _cb: rc }
+    }
+
+    fn find_target(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+    ) -> Option<Weak<RefCell<DropCallback>>> {
+        self.targets
+            .retain(|(s, cb)| cb.upgrade().is_some() && s.as_ref().is_alive());
+        self.targets
+            .iter()
+            .find(|(s, _)| s == surface)
+            .map(|(_, cb)| cb.clone())
+    }
+}
+
+/// Route an event of the data device of this seat
+///
+/// `current` tracks the drop target the drag'n'drop is currently over, if any.
+pub(crate) fn route_dnd_event(
+    router: &RefCell<DndRouter>,
+    current: &mut Option<Weak<RefCell<DropCallback>>>,
+    seat: &wl_seat::WlSeat,
+    event: DndEvent,
+    ddata: DispatchData,
+) {
+    if let DndEvent::Enter {
+        ref surface,
+        ref offer,
+        ..
+    } = event
+    {
+        // internal drag'n'drop without any offer are not routed to drop targets
+        *current = if offer.is_some() {
+            router.borrow_mut().find_target(surface)
+        } else {
+            None
+        };
+    }
+
+    let target = current.as_ref().and_then(Weak::upgrade);
+    let target = match target {
+        Some(target) => target,
+        None => {
+            call_global(router, seat, event, ddata);
+            return;
+        }
+    };
+    // the target may dispatch the event queue, for example while receiving a drop
+    let mut target = match target.try_borrow_mut() {
+        Ok(target) => target,
+        Err(_) => {
+            log::warn!("[SCTK] Drop target invoked re-entrantly, dropping a drag'n'drop event");
+            return;
+        }
+    };
+    match event {
+        DndEvent::Enter {
+            offer: Some(offer),
+            serial,
+            x,
+            y,
+            ..
+        } => {
+            let mime_types = offer.with_mime_types(|m| m.to_vec());
+            (*target)(
+                seat.clone(),
+                DropEvent::Enter {
+                    offer,
+                    serial,
+                    position: (x, y),
+                    mime_types,
+                },
+                ddata,
+            );
+        }
+        DndEvent::Motion {
+            offer: Some(offer),
+            time,
+            x,
+            y,
+        } => (*target)(
+            seat.clone(),
+            DropEvent::Motion {
+                offer,
+                time,
+                position: (x, y),
+            },
+            ddata,
+        ),
+        DndEvent::Leave => {
+            *current = None;
+            (*target)(seat.clone(), DropEvent::Leave, ddata)
+        }
+        DndEvent::Drop { offer: Some(offer) } => {
+            (*target)(seat.clone(), DropEvent::Drop { offer }, ddata)
+        }
+        event => {
+            drop(target);
+            call_global(router, seat, event, ddata);
+        }
+    }
+}
+
+// Invoke the global callback without keeping the router borrowed, so that it can register
+// drop targets or replace itself
+fn call_global(
+    router: &RefCell<DndRouter>,
+    seat: &wl_seat::WlSeat,
+    event: DndEvent,
+    ddata: DispatchData,
+) {
+    let mut callback = {
+        let mut router = match router.try_borrow_mut() {
+            Ok(router) if !router.callback_taken => router,
+            _ => {
+                log::warn!("[SCTK] Data device callback invoked re-entrantly, dropping an event");
+                return;
+            }
+        };
+        router.callback_taken = true;
+        std::mem::replace(
+            &mut router.callback,
+            Box::new(|_, _: DndEvent, _: DispatchData| {}),
+        )
+    };
+    (*callback)(seat.clone(), event, ddata);
+    let mut router = router.borrow_mut();
+    if router.callback_taken {
+        router.callback_taken = false;
+        router.callback = callback;
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use nix::poll::{poll, PollFd, PollFlags};
+
+use wayland_client::{AnonymousObject, DispatchData, EventQueue, Main, RawEvent};
+
+use crate::environment::with_protocol_error;
+
+/// The result of [`dispatch_with_timeout`](fn.dispatch_with_timeout.html)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimedDispatch {
+    /// The number of dispatched events
+    ///
+    /// It is 0 if the timeout elapsed without any event for this queue.
+    pub dispatched: u32,
+    /// The time left before the timeout
+    pub remaining: Duration,
+}
+
+/// Dispatch the events of an event queue, waiting for some at most `timeout`
+///
+/// This is similar to `EventQueue::dispatch()`, except that it returns after `timeout`
+/// even if no event was received, which is typically what a game loop needs to render
+/// its next frame in time. The requests are flushed to the compositor, then the events
+/// already queued are dispatched. If there are none, the connection is polled until some
+/// events are received for this queue, or until the timeout elapses. A zero timeout only
+/// dispatches the events that can be read without waiting.
+///
+/// Orphan events are given to the `fallback` closure, and the time left before the
+/// timeout is returned alongside the number of dispatched events.
+///
+/// It can be used while other threads read the connection for their own event queues.
+///
+/// If the connection fails because of a protocol error, the returned error carries a
+/// [`ConnectionError`](environment/enum.ConnectionError.html) describing it.
+pub fn dispatch_with_timeout<T, F>(
+    queue: &mut EventQueue,
+    timeout: Duration,
+    data: &mut T,
+    mut fallback: F,
+) -> io::Result<TimedDispatch>
+where
+    T: std::any::Any,
+    F: FnMut(RawEvent, Main<AnonymousObject>, DispatchData),
+{
+    let deadline = Instant::now() + timeout;
+    let fd = queue.display().get_connection_fd();
+    loop {
+        // the compositor may not accept all of our requests right away, in which
+        // case we wait for the socket to be writable too
+        let flushed = match queue.display().flush() {
+            Ok(()) => true,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => false,
+            Err(err) => return Err(with_protocol_error(queue.display(), err)),
+        };
+
+        let dispatched = queue
+            .dispatch_pending(data, &mut fallback)
+            .map_err(|err| with_protocol_error(queue.display(), err))?;
+        if dispatched > 0 {
+            return finish(queue, dispatched, deadline);
+        }
+
+        // the read can not be prepared if another thread queued events for
+        // this queue in the meantime, dispatch them first
+        let guard = match queue.prepare_read() {
+            Some(guard) => guard,
+            None => continue,
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut flags = PollFlags::POLLIN;
+        if !flushed {
+            flags |= PollFlags::POLLOUT;
+        }
+        let mut fds = [PollFd::new(fd, flags)];
+        match poll(&mut fds, timeout_millis(remaining)) {
+            Ok(_) => {}
+            // interrupted by a signal, poll again for the remaining time
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(err) => {
+                let errno = err.as_errno().unwrap_or(nix::errno::Errno::EINVAL);
+                return Err(io::Error::from_raw_os_error(errno as i32));
+            }
+        }
+
+        let revents = fds[0].revents().unwrap_or_else(PollFlags::empty);
+        if revents.intersects(PollFlags::POLLIN | PollFlags::POLLERR | PollFlags::POLLHUP) {
+            if let Err(err) = guard.read_events() {
+                // spurious wakeup, or the events were read by another thread
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    return Err(with_protocol_error(queue.display(), err));
+                }
+            }
+            let dispatched = queue
+                .dispatch_pending(data, &mut fallback)
+                .map_err(|err| with_protocol_error(queue.display(), err))?;
+            if dispatched > 0 {
+                return finish(queue, dispatched, deadline);
+            }
+        } else {
+            // cancel the read
+            drop(guard);
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(TimedDispatch {
+                dispatched: 0,
+                remaining: Duration::from_secs(0),
+            });
+        }
+    }
+}
+
+// Flush the requests sent by the dispatched callbacks
+fn finish(queue: &EventQueue, dispatched: u32, deadline: Instant) -> io::Result<TimedDispatch> {
+    match queue.display().flush() {
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(with_protocol_error(queue.display(), err)),
+        Ok(()) => {}
+    }
+    Ok(TimedDispatch {
+        dispatched,
+        remaining: deadline.saturating_duration_since(Instant::now()),
+    })
+}
+
+// Convert a duration to a poll(2) timeout, rounding up so that we don't wake up early
+fn timeout_millis(duration: Duration) -> i32 {
+    // longer timeouts are handled by polling again, rather than overflowing
+    let duration = std::cmp::min(duration, Duration::from_secs(3600));
+    let mut millis = duration.as_millis() as i32;
+    if Duration::from_millis(millis as u64) < duration {
+        millis += 1;
+    }
+    millis
+}
@@ -0,0 +1,237 @@
+//! Helpers to share dmabufs with the compositor with `zwp_linux_dmabuf_v1`
+//!
+//! The [`DmabufHandler`](struct.DmabufHandler.html) binds the global and tracks the formats
+//! and modifiers the compositor can import, and [`DmabufParams`](struct.DmabufParams.html)
+//! creates `wl_buffer`s from the planes of a dmabuf allocated by your renderer. This module does
+//! not depend on any graphics API, you get the file descriptors from your GL or Vulkan driver.
+//!
+//! It is not part of the [`default_environment!`](../macro.default_environment.html), you need to
+//! add the handler to your environment, and implement
+//! [`DmabufHandling`](trait.DmabufHandling.html) by delegating to it:
+//!
+//! ```no_run
+//! # use smithay_client_toolkit::default_environment;
+//! use smithay_client_toolkit::dmabuf::{DmabufHandler, DmabufHandling, Fourcc, Modifier};
+//! use smithay_client_toolkit::reexports::protocols::unstable::linux_dmabuf::v1::client::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1;
+//!
+//! default_environment!(MyEnv,
+//!     fields=[dmabuf: DmabufHandler],
+//!     singles=[ZwpLinuxDmabufV1 => dmabuf],
+//! );
+//!
+//! impl DmabufHandling for MyEnv {
+//!     fn dmabuf_formats(&self) -> Vec<(Fourcc, Modifier)> {
+//!         self.dmabuf.dmabuf_formats()
+//!     }
+//! }
+//! ```
+//!
+//! Only versions 1 to 3 of the protocol are supported: the feedback of version 4, with its main
+//! device and format tranches, is not available in the protocol bindings used by this crate yet.
+
+use std::{cell::RefCell, os::unix::io::RawFd, rc::Rc};
+
+use wayland_client::{
+    protocol::{wl_buffer, wl_registry},
+    Attached, DispatchData, Main,
+};
+
+use wayland_protocols::unstable::linux_dmabuf::v1::client::{
+    zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
+    zwp_linux_dmabuf_v1::{self, ZwpLinuxDmabufV1},
+};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler};
+
+pub use self::zwp_linux_buffer_params_v1::Flags;
+
+/// A DRM format code, as defined in `drm_fourcc.h`
+pub type Fourcc = u32;
+
+/// A DRM format modifier, as defined in `drm_fourcc.h`
+pub type Modifier = u64;
+
+/// The modifier of buffers whose layout is implicitly negotiated with the driver
+pub const DRM_FORMAT_MOD_INVALID: Modifier = 0x00ff_ffff_ffff_ffff;
+
+/// A handler for the `zwp_linux_dmabuf_v1` global
+///
+/// It binds the global as soon as it is advertised, and collects the formats it reports.
+pub struct DmabufHandler {
+    dmabuf: Option<Attached<ZwpLinuxDmabufV1>>,
+    formats: Rc<RefCell<Vec<(Fourcc, Modifier)>>>,
+}
+
+impl DmabufHandler {
+    /// Create a new handler
+    pub fn new() -> DmabufHandler {
+        DmabufHandler {
+            dmabuf: None,
+            formats: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+}
+
+impl GlobalHandler<ZwpLinuxDmabufV1> for DmabufHandler {
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        _: DispatchData,
+    ) {
+        if self.dmabuf.is_some() {
+            return;
+        }
+        // We currently support the global up to version 3
+        let version = std::cmp::min(version, 3);
+        let dmabuf = registry.bind::<ZwpLinuxDmabufV1>(version, id);
+        let formats = self.formats.clone();
+        dmabuf.quick_assign(move |_, event, _| {
+            use self::zwp_linux_dmabuf_v1::Event;
+            let entry = match event {
+                // since version 3, the modifiers of each format are always advertised
+                Event::Format { format } if version < 3 => (format, DRM_FORMAT_MOD_INVALID),
+                Event::Format { .. } => return,
+                Event::Modifier {
+                    format,
+                    modifier_hi,
+                    modifier_lo,
+                } => (
+                    format,
+                    (Modifier::from(modifier_hi) << 32) | Modifier::from(modifier_lo),
+                ),
+                _ => unreachable!(),
+            };
+            let mut formats = formats.borrow_mut();
+            if !formats.contains(&entry) {
+                formats.push(entry);
+            }
+        });
+        self.dmabuf = Some((*dmabuf).clone());
+    }
+    fn get(&self) -> Option<Attached<ZwpLinuxDmabufV1>> {
+        self.dmabuf.clone()
+    }
+}
+
+/// An interface trait to forward the dmabuf handler capability
+///
+/// You need to implement this trait for your environment struct, by delegating it
+/// to its `DmabufHandler` field in order to get the associated methods on
+/// your [`Environment`](../environment/struct.Environment.html).
+pub trait DmabufHandling {
+    /// Access the formats and modifiers supported by the compositor
+    fn dmabuf_formats(&self) -> Vec<(Fourcc, Modifier)>;
+}
+
+impl DmabufHandling for DmabufHandler {
+    fn dmabuf_formats(&self) -> Vec<(Fourcc, Modifier)> {
+        self.formats.borrow().clone()
+    }
+}
+
+impl<E: DmabufHandling> Environment<E> {
+    /// Access the formats and modifiers supported by the compositor
+    ///
+    /// With versions 1 and 2 of the global, the compositor does not advertise modifiers
+    /// and all formats are reported with `DRM_FORMAT_MOD_INVALID`.
+    pub fn dmabuf_formats(&self) -> Vec<(Fourcc, Modifier)> {
+        self.with_inner(|inner| inner.dmabuf_formats())
+    }
+}
+
+/// A builder for `wl_buffer`s backed by a dmabuf
+///
+/// Add each plane of the dmabuf with `add_plane()`, and create the buffer with `create()`
+/// or `create_immed()`.
+pub struct DmabufParams {
+    params: Main<ZwpLinuxBufferParamsV1>,
+    version: u32,
+}
+
+impl DmabufParams {
+    /// Start describing a new dmabuf
+    pub fn new<E>(env: &Environment<E>) -> Result<DmabufParams, GlobalError>
+    where
+        E: GlobalHandler<ZwpLinuxDmabufV1>,
+    {
+        let dmabuf = env.try_get_global::<ZwpLinuxDmabufV1>()?;
+        Ok(DmabufParams {
+            params: dmabuf.create_params(),
+            version: dmabuf.as_ref().version(),
+        })
+    }
+
+    /// Add a plane to the dmabuf
+    ///
+    /// The file descriptor is duplicated when sent to the compositor, you remain responsible
+    /// for closing it. All planes must use the same modifier.
+    pub fn add_plane(
+        &mut self,
+        fd: RawFd,
+        plane_idx: u32,
+        offset: u32,
+        stride: u32,
+        modifier: Modifier,
+    ) -> &mut DmabufParams {
+        self.params.add(
+            fd,
+            plane_idx,
+            offset,
+            stride,
+            (modifier >> 32) as u32,
+            modifier as u32,
+        );
+        self
+    }
+
+    /// Create the buffer, once the compositor has imported the dmabuf
+    ///
+    /// The callback is invoked with the new buffer, or `None` if the compositor failed to
+    /// import it. You need to assign the buffer a filter to handle its `release` events.
+    pub fn create<F>(self, width: i32, height: i32, format: Fourcc, flags: Flags, callback: F)
+    where
+        F: FnOnce(Option<Main<wl_buffer::WlBuffer>>, DispatchData) + 'static,
+    {
+        let mut callback = Some(callback);
+        self.params.quick_assign(move |params, event, ddata| {
+            use self::zwp_linux_buffer_params_v1::Event;
+            let buffer = match event {
+                Event::Created { buffer } => Some(buffer),
+                Event::Failed => None,
+                _ => unreachable!(),
+            };
+            params.destroy();
+            if let Some(callback) = callback.take() {
+                callback(buffer, ddata);
+            }
+        });
+        self.params.create(width, height, format, flags.to_raw());
+    }
+
+    /// Create the buffer immediately
+    ///
+    /// If the compositor fails to import the dmabuf, it either sends a `failed` event ignored by
+    /// SCTK, after which using the buffer is a protocol error, or kills the connection. Prefer
+    /// `create()` unless you know the dmabuf can be imported, for example because you already
+    /// imported a similar one.
+    ///
+    /// Returns `None` if the global is older than version 2, which does not support it.
+    pub fn create_immed(
+        self,
+        width: i32,
+        height: i32,
+        format: Fourcc,
+        flags: Flags,
+    ) -> Option<Main<wl_buffer::WlBuffer>> {
+        if self.version < 2 {
+            self.params.destroy();
+            return None;
+        }
+        self.params.quick_assign(|_, _, _| {});
+        let buffer = self.params.create_immed(width, height, format, flags.to_raw());
+        self.params.destroy();
+        Some(buffer)
+    }
+}
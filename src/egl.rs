@@ -0,0 +1,138 @@
+//! EGL windows for surfaces
+//!
+//! Applications using EGL directly render to a `wl_egl_window` created for their
+//! surface, whose pointer is given to `eglCreatePlatformWindowSurface` (or
+//! `eglCreateWindowSurface`). [`EglWindow`](struct.EglWindow.html) creates it for
+//! a surface, keeps its size in sync with the configure events of a window, and
+//! destroys it before the surface.
+//!
+//! **Note:** This requires the `egl` cargo feature, which makes `wayland-client` use
+//! the system libwayland.
+
+use std::os::raw::c_void;
+
+use wayland_client::protocol::wl_surface;
+use wayland_egl::WlEglSurface;
+
+use crate::surface::ScaleFactor;
+
+/// A `wl_egl_window` for a surface
+///
+/// The size of the EGL window is the size of the surface in logical pixels
+/// multiplied by the scale factor, which you need to also set as the buffer scale
+/// of the surface. Its initial scale factor is 1.
+///
+/// The `EglWindow` owns the surface: when it is dropped, the EGL window is destroyed
+/// and then the surface. Any role object of the surface (like a
+/// [`Window`](../window/struct.Window.html)) must be dropped before it, and the EGL
+/// surface created from it must be destroyed before that.
+pub struct EglWindow {
+    egl: Option<WlEglSurface>,
+    surface: Option<wl_surface::WlSurface>,
+    size: (u32, u32),
+    scale: ScaleFactor,
+}
+
+impl EglWindow {
+    /// Create an EGL window for this surface, with this initial size in logical pixels
+    ///
+    /// Returns `None` if the `libwayland-egl` library could not be loaded.
+    pub fn new(surface: wl_surface::WlSurface, width: u32, height: u32) -> Option<EglWindow> {
+        if !wayland_egl::is_available() {
+            return None;
+        }
+        let width = std::cmp::max(width, 1);
+        let height = std::cmp::max(height, 1);
+        let egl = WlEglSurface::new(&surface, width as i32, height as i32);
+        Some(EglWindow {
+            egl: Some(egl),
+            surface: Some(surface),
+            size: (width, height),
+            scale: ScaleFactor::ONE,
+        })
+    }
+
+    /// Access the surface
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        self.surface.as_ref().unwrap()
+    }
+
+    /// The native window pointer, to create an EGL surface from
+    pub fn native_window(&self) -> *const c_void {
+        self.egl.as_ref().unwrap().ptr()
+    }
+
+    /// The size of the surface in logical pixels
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Process the new size of a window configure event
+    ///
+    /// This is the `new_size` of the
+    /// [`window::Event::Configure`](../window/enum.Event.html) event, which is already
+    /// the size of the content, without the decorations. If it is `None`, the size is
+    /// not changed.
+    ///
+    /// Returns `true` if the size changed, in which case you need to redraw.
+    pub fn handle_configure(&mut self, new_size: Option<(u32, u32)>) -> bool {
+        match new_size {
+            Some((width, height)) if (width, height) != self.size => {
+                // xdg_shell compositors keep the opposite edge in place during
+                // interactive resizes, offsetting the content would move it twice
+                self.resize(width, height, 0, 0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Resize the EGL window
+    ///
+    /// The size is in logical pixels. `dx` and `dy` are the offset of the new content
+    /// relative to the old one, in buffer pixels, as in `wl_surface::attach`: they are
+    /// needed for surfaces whose position is managed by the client, like subsurfaces,
+    /// to keep the content in place when resizing from the top or left edges.
+    ///
+    /// The new size takes effect on the next buffer swap.
+    pub fn resize(&mut self, width: u32, height: u32, dx: i32, dy: i32) {
+        self.size = (std::cmp::max(width, 1), std::cmp::max(height, 1));
+        self.update(dx, dy);
+    }
+
+    /// Set the scale factor of the surface
+    ///
+    /// The EGL window is resized to keep the same logical size. You also need to set
+    /// the buffer scale of the surface.
+    pub fn set_scale_factor(&mut self, scale: ScaleFactor) {
+        if scale != self.scale {
+            self.scale = scale;
+            self.update(0, 0);
+        }
+    }
+
+    /// Destroy the EGL window and get the surface back
+    pub fn into_surface(mut self) -> wl_surface::WlSurface {
+        self.egl.take();
+        self.surface.take().unwrap()
+    }
+
+    fn update(&self, dx: i32, dy: i32) {
+        let scale = self.scale.get();
+        let (width, height) = self.size;
+        self.egl
+            .as_ref()
+            .unwrap()
+            .resize((width * scale) as i32, (height * scale) as i32, dx, dy);
+    }
+}
+
+impl Drop for EglWindow {
+    fn drop(&mut self) {
+        // the EGL window must be destroyed before its surface
+        self.egl.take();
+        if let Some(surface) = self.surface.take() {
+            surface.destroy();
+        }
+    }
+}
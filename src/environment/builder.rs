@@ -0,0 +1,564 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use wayland_client::{
+    protocol::{
+        wl_compositor, wl_data_device_manager, wl_output, wl_registry, wl_seat, wl_shell, wl_shm,
+        wl_subcompositor, wl_surface,
+    },
+    Attached, DispatchData, Display, EventQueue, GlobalEvent, Interface, Proxy,
+};
+
+use wayland_protocols::{
+    misc::gtk_primary_selection::client::gtk_primary_selection_device_manager::GtkPrimarySelectionDeviceManager,
+    unstable::primary_selection::v1::client::zwp_primary_selection_device_manager_v1::ZwpPrimarySelectionDeviceManagerV1,
+    unstable::xdg_decoration::v1::client::zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+    unstable::xdg_shell::v6::client::zxdg_shell_v6, xdg_shell::client::xdg_wm_base,
+};
+
+use super::{
+    Environment, EnvironmentError, GlobalError, GlobalHandler, InnerEnv, MultiGlobalHandler,
+    SimpleGlobal,
+};
+use crate::{
+    data_device::{
+        DataDevice, DataDeviceError, DataDeviceHandler, DataDeviceHandling, DndEvent, DropEvent,
+        DropTarget,
+    },
+    output::{OutputHandler, OutputHandling, OutputInfo, OutputStatusListener},
+    primary_selection::{
+        PrimarySelectionDevice, PrimarySelectionDeviceManager, PrimarySelectionHandler,
+        PrimarySelectionHandling,
+    },
+    seat::{SeatData, SeatHandler, SeatHandling, SeatListener},
+    shell::{Shell, ShellHandler, ShellHandling},
+    shm::{ShmHandler, ShmHandling},
+};
+
+type CreatedCallback = dyn FnMut(Attached<wl_registry::WlRegistry>, u32, u32, DispatchData);
+type RemovedCallback = dyn FnMut(u32, DispatchData);
+type SingleGetter<I> = Box<dyn Fn() -> Result<Attached<I>, GlobalError>>;
+type MultiGetter<I> = Box<dyn Fn() -> Vec<Attached<I>>>;
+
+struct Route {
+    interface: &'static str,
+    created: Box<CreatedCallback>,
+    removed: Box<RemovedCallback>,
+    // a `SingleGetter<I>` or a `MultiGetter<I>`, depending on the kind of the global
+    getter: Box<dyn Any>,
+}
+
+/// An environment assembled at runtime
+///
+/// This is the inner environment of the `Environment` created by an
+/// [`EnvironmentBuilder`](struct.EnvironmentBuilder.html). Unlike the environments declared
+/// with the [`environment!`](../macro.environment.html) macro, it gives access to any global
+/// and to the methods of all SCTK modules, but checks at runtime that they were added to the
+/// environment: accessing a global that is not routed to a module returns `None`, like a
+/// missing global.
+pub struct DynamicEnv {
+    modules: Vec<Rc<dyn Any>>,
+    routes: Vec<Route>,
+}
+
+impl DynamicEnv {
+    /// Access a module of this environment by its type
+    ///
+    /// Returns `None` if no module of this type was added to the environment.
+    pub fn module<H: 'static>(&self) -> Option<Rc<RefCell<H>>> {
+        self.modules
+            .iter()
+            .find_map(|module| module.clone().downcast::<RefCell<H>>().ok())
+    }
+
+    fn require_module<H: 'static>(&self, method: &str) -> Rc<RefCell<H>> {
+        match self.module::<H>() {
+            Some(module) => module,
+            None => panic!(
+                "[SCTK] The environment has no {}, add it with `EnvironmentBuilder::{}()`",
+                std::any::type_name::<H>(),
+                method
+            ),
+        }
+    }
+
+    fn getter<T: 'static>(&self, interface: &str) -> Option<&T> {
+        self.routes
+            .iter()
+            .find(|route| route.interface == interface)
+            .and_then(|route| route.getter.downcast_ref::<T>())
+    }
+
+    fn route_mut(&mut self, interface: &str) -> Option<&mut Route> {
+        self.routes
+            .iter_mut()
+            .find(|route| route.interface == interface)
+    }
+}
+
+impl InnerEnv for DynamicEnv {
+    fn process_event(
+        &mut self,
+        event: GlobalEvent,
+        registry: Attached<wl_registry::WlRegistry>,
+        ddata: DispatchData,
+    ) {
+        match event {
+            GlobalEvent::New {
+                id,
+                interface,
+                version,
+            } => {
+                if let Some(route) = self.route_mut(&interface) {
+                    (route.created)(registry, id, version, ddata);
+                }
+            }
+            GlobalEvent::Removed { id, interface } => {
+                if let Some(route) = self.route_mut(&interface) {
+                    (route.removed)(id, ddata);
+                }
+            }
+        }
+    }
+
+    fn handles(&self, interface: &str) -> bool {
+        self.routes.iter().any(|route| route.interface == interface)
+    }
+}
+
+impl<I: Interface> GlobalHandler<I> for DynamicEnv {
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        ddata: DispatchData,
+    ) {
+        if let Some(route) = self.route_mut(I::NAME) {
+            (route.created)(registry, id, version, ddata);
+        }
+    }
+    fn get(&self) -> Option<Attached<I>> {
+        GlobalHandler::<I>::try_get(self).ok()
+    }
+    fn try_get(&self) -> Result<Attached<I>, GlobalError> {
+        match self.getter::<SingleGetter<I>>(I::NAME) {
+            Some(getter) => getter(),
+            None => Err(GlobalError::Missing(I::NAME)),
+        }
+    }
+    fn removed(&mut self, id: u32, ddata: DispatchData) {
+        if let Some(route) = self.route_mut(I::NAME) {
+            (route.removed)(id, ddata);
+        }
+    }
+}
+
+impl<I: Interface> MultiGlobalHandler<I> for DynamicEnv {
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        ddata: DispatchData,
+    ) {
+        GlobalHandler::<I>::created(self, registry, id, version, ddata)
+    }
+    fn removed(&mut self, id: u32, ddata: DispatchData) {
+        GlobalHandler::<I>::removed(self, id, ddata)
+    }
+    fn get_all(&self) -> Vec<Attached<I>> {
+        match self.getter::<MultiGetter<I>>(I::NAME) {
+            Some(getter) => getter(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// A builder for environments assembled at runtime
+///
+/// It is an alternative to the [`environment!`](../macro.environment.html) and
+/// [`default_environment!`](../macro.default_environment.html) macros, which does not require
+/// declaring a type and lets you choose the modules of the environment at runtime. It creates an
+/// `Environment<DynamicEnv>`, see [`DynamicEnv`](struct.DynamicEnv.html).
+///
+/// Your own modules can be added with `with_single()` and `with_multi()`, or with `with_module()`
+/// followed by `route_single()` and `route_multi()` for modules handling several globals.
+///
+/// ```no_run
+/// # use smithay_client_toolkit::environment::EnvironmentBuilder;
+/// # use smithay_client_toolkit::reexports::client::Display;
+/// let display = Display::connect_to_env().unwrap();
+/// let mut queue = display.create_event_queue();
+/// let env = EnvironmentBuilder::new(&display)
+///     .with_compositor()
+///     .with_outputs()
+///     .with_seats()
+///     .with_shm()
+///     .with_shell()
+///     .build(&mut queue)
+///     .expect("Initial roundtrip failed!");
+/// ```
+pub struct EnvironmentBuilder {
+    display: Display,
+    env: DynamicEnv,
+}
+
+impl EnvironmentBuilder {
+    /// Create a new builder for an environment without any module
+    pub fn new(display: &Display) -> EnvironmentBuilder {
+        EnvironmentBuilder {
+            display: display.clone(),
+            env: DynamicEnv {
+                modules: Vec::new(),
+                routes: Vec::new(),
+            },
+        }
+    }
+
+    /// Add a module to the environment
+    ///
+    /// The globals it handles then need to be routed to it with `route_single()` or
+    /// `route_multi()`. Modules are identified by their type, and there can only be one
+    /// module of each type in an environment.
+    pub fn with_module<H: 'static>(mut self, handler: H) -> EnvironmentBuilder {
+        if self.env.module::<H>().is_some() {
+            panic!(
+                "[SCTK] The environment already has a {}",
+                std::any::type_name::<H>()
+            );
+        }
+        self.env.modules.push(Rc::new(RefCell::new(handler)));
+        self
+    }
+
+    /// Route a "single" global to the module of given type
+    pub fn route_single<I, H>(mut self) -> EnvironmentBuilder
+    where
+        I: Interface,
+        H: GlobalHandler<I> + 'static,
+    {
+        let module = self.env.require_module::<H>("with_module");
+        let (created_module, removed_module) = (module.clone(), module.clone());
+        let getter: SingleGetter<I> =
+            Box::new(move || GlobalHandler::<I>::try_get(&*module.borrow()));
+        self.add_route(Route {
+            interface: I::NAME,
+            created: Box::new(move |registry, id, version, ddata| {
+                GlobalHandler::<I>::created(
+                    &mut *created_module.borrow_mut(),
+                    registry,
+                    id,
+                    version,
+                    ddata,
+                )
+            }),
+            removed: Box::new(move |id, ddata| {
+                GlobalHandler::<I>::removed(&mut *removed_module.borrow_mut(), id, ddata)
+            }),
+            getter: Box::new(getter),
+        });
+        self
+    }
+
+    /// Route a "multi" global to the module of given type
+    pub fn route_multi<I, H>(mut self) -> EnvironmentBuilder
+    where
+        I: Interface,
+        H: MultiGlobalHandler<I> + 'static,
+    {
+        let module = self.env.require_module::<H>("with_module");
+        let (created_module, removed_module) = (module.clone(), module.clone());
+        let getter: MultiGetter<I> =
+            Box::new(move || MultiGlobalHandler::<I>::get_all(&*module.borrow()));
+        self.add_route(Route {
+            interface: I::NAME,
+            created: Box::new(move |registry, id, version, ddata| {
+                MultiGlobalHandler::<I>::created(
+                    &mut *created_module.borrow_mut(),
+                    registry,
+                    id,
+                    version,
+                    ddata,
+                )
+            }),
+            removed: Box::new(move |id, ddata| {
+                MultiGlobalHandler::<I>::removed(&mut *removed_module.borrow_mut(), id, ddata)
+            }),
+            getter: Box::new(getter),
+        });
+        self
+    }
+
+    /// Add a module handling a "single" global
+    pub fn with_single<I, H>(self, handler: H) -> EnvironmentBuilder
+    where
+        I: Interface,
+        H: GlobalHandler<I> + 'static,
+    {
+        self.with_module(handler).route_single::<I, H>()
+    }
+
+    /// Add a module handling a "multi" global
+    pub fn with_multi<I, H>(self, handler: H) -> EnvironmentBuilder
+    where
+        I: Interface,
+        H: MultiGlobalHandler<I> + 'static,
+    {
+        self.with_module(handler).route_multi::<I, H>()
+    }
+
+    /// Add the modules of the [`default_environment!`](../macro.default_environment.html)
+    ///
+    /// These are the compositor, shm, outputs, seats, data device and primary selection modules.
+    pub fn with_defaults(self) -> EnvironmentBuilder {
+        self.with_compositor()
+            .with_shm()
+            .with_outputs()
+            .with_seats()
+            .with_data_device()
+            .with_primary_selection()
+    }
+
+    /// Add `wl_compositor` and `wl_subcompositor` as [`SimpleGlobal`](struct.SimpleGlobal.html)s
+    pub fn with_compositor(self) -> EnvironmentBuilder {
+        if self.has_module::<SimpleGlobal<wl_compositor::WlCompositor>>() {
+            return self;
+        }
+        self.with_single::<wl_compositor::WlCompositor, _>(SimpleGlobal::new())
+            .with_single::<wl_subcompositor::WlSubcompositor, _>(SimpleGlobal::new())
+    }
+
+    /// Add `wl_shm` with the [`ShmHandler`](../shm/struct.ShmHandler.html)
+    pub fn with_shm(self) -> EnvironmentBuilder {
+        if self.has_module::<ShmHandler>() {
+            return self;
+        }
+        self.with_single::<wl_shm::WlShm, _>(ShmHandler::new())
+    }
+
+    /// Add `wl_output` with the [`OutputHandler`](../output/struct.OutputHandler.html)
+    pub fn with_outputs(self) -> EnvironmentBuilder {
+        if self.has_module::<OutputHandler>() {
+            return self;
+        }
+        self.with_multi::<wl_output::WlOutput, _>(OutputHandler::new())
+    }
+
+    /// Add `wl_seat` with the [`SeatHandler`](../seat/struct.SeatHandler.html)
+    pub fn with_seats(self) -> EnvironmentBuilder {
+        if self.has_module::<SeatHandler>() {
+            return self;
+        }
+        self.with_multi::<wl_seat::WlSeat, _>(SeatHandler::new())
+    }
+
+    /// Add the shells with the [`ShellHandler`](../shell/struct.ShellHandler.html)
+    pub fn with_shell(self) -> EnvironmentBuilder {
+        if self.has_module::<ShellHandler>() {
+            return self;
+        }
+        self.with_module(ShellHandler::new())
+            .route_single::<wl_shell::WlShell, ShellHandler>()
+            .route_single::<xdg_wm_base::XdgWmBase, ShellHandler>()
+            .route_single::<zxdg_shell_v6::ZxdgShellV6, ShellHandler>()
+    }
+
+    /// Add `zxdg_decoration_manager_v1` as a [`SimpleGlobal`](struct.SimpleGlobal.html)
+    pub fn with_decorations(self) -> EnvironmentBuilder {
+        if self.has_module::<SimpleGlobal<ZxdgDecorationManagerV1>>() {
+            return self;
+        }
+        self.with_single::<ZxdgDecorationManagerV1, _>(SimpleGlobal::new())
+    }
+
+    /// Add `wl_data_device_manager` with the
+    /// [`DataDeviceHandler`](../data_device/struct.DataDeviceHandler.html)
+    ///
+    /// This also adds the seats module if needed.
+    pub fn with_data_device(self) -> EnvironmentBuilder {
+        if self.has_module::<DataDeviceHandler>() {
+            return self;
+        }
+        let builder = self.with_seats();
+        let seats = builder.env.require_module::<SeatHandler>("with_seats");
+        let handler = DataDeviceHandler::init(&mut *seats.borrow_mut());
+        builder.with_single::<wl_data_device_manager::WlDataDeviceManager, _>(handler)
+    }
+
+    /// Add the primary selection device managers with the
+    /// [`PrimarySelectionHandler`](../primary_selection/struct.PrimarySelectionHandler.html)
+    ///
+    /// This also adds the seats module if needed.
+    pub fn with_primary_selection(self) -> EnvironmentBuilder {
+        if self.has_module::<PrimarySelectionHandler>() {
+            return self;
+        }
+        let builder = self.with_seats();
+        let seats = builder.env.require_module::<SeatHandler>("with_seats");
+        let handler = PrimarySelectionHandler::init(&mut *seats.borrow_mut());
+        builder
+            .with_module(handler)
+            .route_single::<ZwpPrimarySelectionDeviceManagerV1, PrimarySelectionHandler>()
+            .route_single::<GtkPrimarySelectionDeviceManager, PrimarySelectionHandler>()
+    }
+
+    /// Create the environment
+    ///
+    /// This attaches the environment to the event queue and does the two initial roundtrips,
+    /// forwarding any error that occurs during them.
+    pub fn build(
+        self,
+        queue: &mut EventQueue,
+    ) -> Result<Environment<DynamicEnv>, EnvironmentError> {
+        let has_primary_selection = self.has_module::<PrimarySelectionHandler>();
+        let EnvironmentBuilder { display, env } = self;
+        let env = Environment::init(&Proxy::clone(&display).attach(queue.token()), env);
+
+        // two roundtrips to init the environment
+        env.roundtrip(queue)
+            .and_then(|_| env.roundtrip(queue))
+            .map_err(|err| EnvironmentError::from_io(&display, err.into()))?;
+
+        if has_primary_selection {
+            // Bind primary selection manager
+            let _psm = env.get_primary_selection_manager();
+        }
+
+        Ok(env)
+    }
+
+    fn has_module<H: 'static>(&self) -> bool {
+        self.env.module::<H>().is_some()
+    }
+
+    fn add_route(&mut self, route: Route) {
+        if self.env.handles(route.interface) {
+            panic!(
+                "[SCTK] The global {} is already routed to a module",
+                route.interface
+            );
+        }
+        self.env.routes.push(route);
+    }
+}
+
+/*
+ * Module traits, checked at runtime
+ */
+
+impl ShmHandling for DynamicEnv {
+    fn shm_formats(&self) -> Vec<wl_shm::Format> {
+        self.module::<ShmHandler>()
+            .map(|shm| shm.borrow().shm_formats())
+            .unwrap_or_default()
+    }
+}
+
+impl SeatHandling for DynamicEnv {
+    fn listen<F: FnMut(Attached<wl_seat::WlSeat>, &SeatData, DispatchData) + 'static>(
+        &mut self,
+        f: F,
+    ) -> SeatListener {
+        self.require_module::<SeatHandler>("with_seats")
+            .borrow_mut()
+            .listen(f)
+    }
+}
+
+impl OutputHandling for DynamicEnv {
+    fn listen<F: FnMut(wl_output::WlOutput, &OutputInfo, DispatchData) + 'static>(
+        &mut self,
+        f: F,
+    ) -> OutputStatusListener {
+        self.require_module::<OutputHandler>("with_outputs")
+            .borrow_mut()
+            .listen(f)
+    }
+}
+
+impl ShellHandling for DynamicEnv {
+    fn get_shell(&self) -> Option<Shell> {
+        self.module::<ShellHandler>()
+            .and_then(|shell| shell.borrow().get_shell())
+    }
+}
+
+impl DataDeviceHandling for DynamicEnv {
+    fn set_callback<F: FnMut(wl_seat::WlSeat, DndEvent, DispatchData) + 'static>(
+        &mut self,
+        callback: F,
+    ) -> Result<(), ()> {
+        match self.module::<DataDeviceHandler>() {
+            Some(handler) => handler.borrow_mut().set_callback(callback),
+            None => Err(()),
+        }
+    }
+
+    fn with_device<F: FnOnce(&DataDevice)>(&self, seat: &wl_seat::WlSeat, f: F) -> Result<(), ()> {
+        match self.module::<DataDeviceHandler>() {
+            Some(handler) => handler.borrow().with_device(seat, f),
+            None => Err(()),
+        }
+    }
+
+    fn register_drop_target<F: FnMut(wl_seat::WlSeat, DropEvent, DispatchData) + 'static>(
+        &mut self,
+        surface: wl_surface::WlSurface,
+        callback: F,
+    ) -> Result<DropTarget, DataDeviceError> {
+        match self.module::<DataDeviceHandler>() {
+            Some(handler) => handler.borrow_mut().register_drop_target(surface, callback),
+            None => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+
+    fn set_selection_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, Option<&[String]>, DispatchData) + 'static,
+    {
+        match self.module::<DataDeviceHandler>() {
+            Some(handler) => handler.borrow_mut().set_selection_callback(callback),
+            None => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+
+    fn set_selection_lost_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DispatchData) + 'static,
+    {
+        match self.module::<DataDeviceHandler>() {
+            Some(handler) => handler.borrow_mut().set_selection_lost_callback(callback),
+            None => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+}
+
+impl PrimarySelectionHandling for DynamicEnv {
+    fn with_primary_selection<F: FnOnce(&PrimarySelectionDevice)>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        f: F,
+    ) -> Result<(), ()> {
+        match self.module::<PrimarySelectionHandler>() {
+            Some(handler) => handler.borrow().with_primary_selection(seat, f),
+            None => Err(()),
+        }
+    }
+
+    fn get_primary_selection_manager(&self) -> Option<PrimarySelectionDeviceManager> {
+        self.module::<PrimarySelectionHandler>()
+            .and_then(|handler| handler.borrow().get_primary_selection_manager())
+    }
+
+    fn set_primary_selection_lost_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(wl_seat::WlSeat, DispatchData) + 'static,
+    {
+        match self.module::<PrimarySelectionHandler>() {
+            Some(handler) => handler
+                .borrow_mut()
+                .set_primary_selection_lost_callback(callback),
+            None => Err(DataDeviceError::NoDataDevice),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+macro_rules! capabilities {
+    ($($(#[$attr:meta])* $field:ident => $interface:literal,)*) => {
+        /// The protocols supported by the compositor
+        ///
+        /// This reports, for every protocol SCTK provides helpers for, the version of its global
+        /// advertised in the registry, or `None` if it is not available, as well as the number of
+        /// seats and outputs. It is obtained with
+        /// [`Environment::capabilities`](struct.Environment.html#method.capabilities), and
+        /// [`Environment::listen_for_capabilities`](struct.Environment.html#method.listen_for_capabilities)
+        /// reports its changes.
+        ///
+        /// It is displayed as a single line, suitable for logs and bug reports.
+        ///
+        /// The protocols not known by SCTK, like the fractional scaling which is not available
+        /// in the `wayland-protocols` version it uses, are not reported, list them with
+        /// [`Environment::globals`](struct.Environment.html#method.globals) instead.
+        #[derive(Debug, Clone, Default, PartialEq, Eq)]
+        pub struct Capabilities {
+            $(
+                $(#[$attr])*
+                pub $field: Option<u32>,
+            )*
+            /// The number of seats
+            pub seats: usize,
+            /// The number of outputs
+            pub outputs: usize,
+        }
+
+        impl Capabilities {
+            pub(crate) fn from_globals<'a, I>(globals: I) -> Capabilities
+            where
+                I: IntoIterator<Item = (&'a str, u32)>,
+            {
+                let mut caps = Capabilities::default();
+                for (interface, version) in globals {
+                    match interface {
+                        $(
+                            $interface => {
+                                caps.$field = caps.$field.max(Some(version));
+                            }
+                        )*
+                        "wl_seat" => caps.seats += 1,
+                        "wl_output" => caps.outputs += 1,
+                        _ => {}
+                    }
+                }
+                caps
+            }
+
+            fn protocols(&self) -> Vec<(&'static str, Option<u32>)> {
+                vec![$((stringify!($field), self.$field),)*]
+            }
+        }
+    };
+}
+
+capabilities!(
+    /// `wl_compositor`
+    compositor => "wl_compositor",
+    /// `wl_subcompositor`
+    subcompositor => "wl_subcompositor",
+    /// `wl_shm`
+    shm => "wl_shm",
+    /// `wl_data_device_manager`
+    data_device => "wl_data_device_manager",
+    /// `xdg_wm_base`, the stable xdg shell
+    xdg_shell => "xdg_wm_base",
+    /// `zxdg_shell_v6`, the unstable xdg shell
+    zxdg_shell => "zxdg_shell_v6",
+    /// `wl_shell`
+    wl_shell => "wl_shell",
+    /// `zxdg_decoration_manager_v1`, the server-side decorations
+    decoration => "zxdg_decoration_manager_v1",
+    /// `zwlr_layer_shell_v1`
+    layer_shell => "zwlr_layer_shell_v1",
+    /// `zwp_primary_selection_device_manager_v1`
+    primary_selection => "zwp_primary_selection_device_manager_v1",
+    /// `gtk_primary_selection_device_manager`
+    gtk_primary_selection => "gtk_primary_selection_device_manager",
+    /// `zwlr_data_control_manager_v1`
+    data_control => "zwlr_data_control_manager_v1",
+    /// `zwp_text_input_manager_v3`
+    text_input => "zwp_text_input_manager_v3",
+    /// `zwp_tablet_manager_v2`
+    tablet => "zwp_tablet_manager_v2",
+    /// `wp_presentation`
+    presentation => "wp_presentation",
+    /// `zwp_linux_dmabuf_v1`
+    dmabuf => "zwp_linux_dmabuf_v1",
+    /// `zwp_idle_inhibit_manager_v1`
+    idle_inhibit => "zwp_idle_inhibit_manager_v1",
+    /// `zxdg_exporter_v2`, to export surfaces to other clients
+    xdg_foreign => "zxdg_exporter_v2",
+    /// `zwlr_screencopy_manager_v1`
+    screencopy => "zwlr_screencopy_manager_v1",
+    /// `zwlr_foreign_toplevel_manager_v1`
+    foreign_toplevel => "zwlr_foreign_toplevel_manager_v1",
+    /// `zwlr_gamma_control_manager_v1`
+    gamma_control => "zwlr_gamma_control_manager_v1",
+);
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "seats={} outputs={}", self.seats, self.outputs)?;
+        let protocols = self.protocols();
+        for (name, version) in &protocols {
+            if let Some(version) = version {
+                write!(f, " {}={}", name, version)?;
+            }
+        }
+        let missing = protocols
+            .iter()
+            .filter(|(_, version)| version.is_none())
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            write!(f, " missing=[{}]", missing.join(","))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1691 @@
+//! Environment management utilities
+//!
+//! This module provide the tools to automatically bind the wayland global objects you need in your program.
+//!
+//! At the heart of this is the `environment!` macro, which allows you to signal the globals you need
+//! and a struct to manage them as they are signaled in the registry.
+//!
+//! ## Global handlers
+//!
+//! Wayland globals are split in two kinds, that we will call here "single" globals and "multi" globals.
+//!
+//! - "single" globals represent a capability of the server. They are generally signaled in the registry
+//!   from the start and never removed. They are signaled a single time. Examples of these globals are
+//!   `wl_compositor`, `wl_shm` or `xdg_wm_base`.
+//! - "multi" globals represent a resource that the server gives you access to. These globals can be
+//!   created or removed during the run of the program, and may exist as more than one instance, each
+//!   representing a different physical resource. Examples of such globals are `wl_output` or `wl_seat`.
+//!
+//! The objects you need to handle these globals must implement one the two traits
+//! [`GlobalHandler<I>`](trait.GlobalHandler.html) or [`MultiGlobalHandler<I>`](trait.MultiGlobalHandler.html),
+//! depending on the kind of globals it will handle. These objects are responsible for binding the globals
+//! from the registry, and assigning them to filters to receive their events as necessary.
+//!
+//! This module provides a generic implementation of the [`GlobalHandler<I>`](trait.GlobalHandler.html) trait
+//! as [`SimpleGlobal<I>`](struct.SimpleGlobal.html). It can manage "single" globals that do not generate
+//! events, and thus require no filter.
+//!
+//! ## the  `environment!` macro
+//!
+//! This macro is at the core of this module. See its documentation for details about how to
+//! use it: [`environment!`](../macro.environment.html). You can alternatively use the
+//! [`default_environment!`](../macro.default_environment.html) macro to quickly setup things and bring
+//! in all SCTK modules.
+//!
+//! ## Multiple event queues
+//!
+//! The environment, and the objects created by its modules (windows, seats, outputs, data devices...),
+//! live on the event queue the environment was initialized with, and cannot be sent to other threads.
+//!
+//! The objects created from an `Attached` proxy are however assigned to the event queue of this
+//! proxy. You can thus handle some objects on another queue, and even another thread, by attaching
+//! the global creating them to this queue: for example a [`MemPool`](../shm/struct.MemPool.html)
+//! created from a `wl_shm` attached to the event queue of a rendering thread receives the release
+//! events of its buffers on this queue, without depending on the main thread dispatching its events.
+//! The same goes for data sources or frame callbacks, created from an attached data device manager
+//! or surface.
+//!
+//! On the same thread, [`Environment::attach_global`](struct.Environment.html#method.attach_global)
+//! directly gives you a global attached to another queue. To use a global from another thread,
+//! send it detached (the proxies are `Send`) and attach it to the event queue of this thread.
+//!
+//! ## The `EnvironmentBuilder`
+//!
+//! If you need to choose the modules of your environment at runtime, or cannot declare a type for
+//! it, the [`EnvironmentBuilder`](struct.EnvironmentBuilder.html) assembles an environment
+//! from the same global handlers without any macro.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt, io,
+    rc::{self, Rc},
+};
+
+use wayland_client::{
+    protocol::{wl_display, wl_registry},
+    Attached, ConnectError, DispatchData, Display, EventQueue, GlobalEvent, GlobalManager,
+    Interface, Main, ProtocolError, Proxy, QueueToken,
+};
+
+use crate::lazy_global::LazyGlobal;
+
+mod builder;
+mod capabilities;
+mod protocol_errors;
+
+pub use self::builder::{DynamicEnv, EnvironmentBuilder};
+pub use self::capabilities::Capabilities;
+
+/*
+ * Traits definitions
+ */
+
+/// Required trait for implementing a handler for "single" globals
+pub trait GlobalHandler<I: Interface> {
+    /// This global was created and signaled in the registry with given id and version
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        ddata: DispatchData,
+    );
+    /// Access the global if it was signaled
+    fn get(&self) -> Option<Attached<I>>;
+    /// Access the global, reporting why it is not available
+    ///
+    /// The default implementation reports any missing global as
+    /// [`GlobalError::Missing`](enum.GlobalError.html). Handlers binding their global
+    /// lazily can override it to report bind failures.
+    fn try_get(&self) -> Result<Attached<I>, GlobalError> {
+        self.get().ok_or(GlobalError::Missing(I::NAME))
+    }
+    /// The global with given id was removed
+    ///
+    /// This is rarely done by compositors for "single" globals, so the default
+    /// implementation does nothing. Handlers that can recover from it should stop
+    /// using the global when receiving this.
+    fn removed(&mut self, id: u32, ddata: DispatchData) {
+        let _ = (id, ddata);
+    }
+}
+
+/// Required trait for implementing a handler for "multi" globals
+pub trait MultiGlobalHandler<I: Interface> {
+    /// A new instance of this global was created with given id and version
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        ddata: DispatchData,
+    );
+    /// The instance with given id was removed
+    fn removed(&mut self, id: u32, ddata: DispatchData);
+    /// Access all the currently existing instances
+    fn get_all(&self) -> Vec<Attached<I>>;
+}
+
+/*
+ * General Environment<E>
+ */
+
+/// Possible errors when accessing a global
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobalError {
+    /// The global was not advertised by the compositor
+    Missing(&'static str),
+    /// The global was advertised, but has since been removed by the compositor
+    Removed(&'static str),
+    /// The global was advertised, but binding it failed
+    ///
+    /// This happens if the connection to the compositor was lost.
+    BindFailed(&'static str),
+    /// The global or object has a lower version than required
+    TooOld {
+        /// The name of the interface
+        interface: &'static str,
+        /// The required version
+        needed: u32,
+        /// The available version
+        got: u32,
+    },
+}
+
+impl fmt::Display for GlobalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GlobalError::Missing(name) => write!(f, "the global {} is missing", name),
+            GlobalError::Removed(name) => {
+                write!(f, "the global {} was removed by the compositor", name)
+            }
+            GlobalError::BindFailed(name) => write!(f, "failed to bind the global {}", name),
+            GlobalError::TooOld {
+                interface,
+                needed,
+                got,
+            } => write!(
+                f,
+                "{} version {} is required, but only version {} is available",
+                interface, needed, got
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GlobalError {}
+
+/// Possible errors when initializing the environment or SCTK modules
+#[derive(Debug)]
+pub enum EnvironmentError {
+    /// A required global was not advertised by the compositor
+    MissingGlobal {
+        /// The name of the interface of the global
+        interface: &'static str,
+    },
+    /// A required global was advertised with a lower version than needed
+    GlobalVersionTooOld {
+        /// The name of the interface of the global
+        interface: &'static str,
+        /// The required version
+        needed: u32,
+        /// The advertised version
+        got: u32,
+    },
+    /// Connecting to the compositor failed, or the connection was lost
+    ConnectFailed(io::Error),
+    /// The compositor raised a protocol error
+    Protocol(ProtocolError),
+}
+
+impl EnvironmentError {
+    /// Interpret an I/O error of the wayland connection
+    ///
+    /// If the connection was lost because of a protocol error, it is reported as
+    /// `EnvironmentError::Protocol`.
+    pub fn from_io(display: &Display, error: io::Error) -> EnvironmentError {
+        match display.protocol_error() {
+            Some(perr) => EnvironmentError::Protocol(perr),
+            None => EnvironmentError::ConnectFailed(error),
+        }
+    }
+}
+
+/// An error which killed the connection to the compositor
+///
+/// The dispatch helpers of SCTK, like the [`WaylandSource`](../struct.WaylandSource.html),
+/// [`dispatch_with_timeout`](../fn.dispatch_with_timeout.html) and the
+/// [`AsyncEventQueue`](../struct.AsyncEventQueue.html), return I/O errors carrying a
+/// `ConnectionError` when the compositor raised a protocol error or closed the connection.
+/// Get it back with [`from_io`](#method.from_io) to report it:
+///
+/// ```no_run
+/// # use smithay_client_toolkit::environment::ConnectionError;
+/// # fn report(err: std::io::Error) {
+/// match ConnectionError::from_io(&err) {
+///     Some(err) => eprintln!("Disconnected by the compositor: {}", err),
+///     None => eprintln!("Connection lost: {}", err),
+/// }
+/// # }
+/// ```
+///
+/// It is also the error of [`Environment::roundtrip`](struct.Environment.html#method.roundtrip).
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// The compositor raised a protocol error
+    Protocol {
+        /// The name of the interface of the object the error was raised on, like `"wl_surface"`
+        interface: &'static str,
+        /// The id of the object the error was raised on
+        object_id: u32,
+        /// The error code, whose meaning depends on the interface
+        code: u32,
+        /// A description of the error code, if it belongs to a protocol known by SCTK
+        description: Option<&'static str>,
+        /// The message sent by the compositor
+        message: String,
+    },
+    /// The compositor closed the connection, for example because it crashed or restarted
+    ///
+    /// Connect to it again with
+    /// [`Environment::reconnect`](struct.Environment.html#method.reconnect).
+    Disconnected,
+    /// The connection was lost without a protocol error
+    Io(io::Error),
+    /// A roundtrip was requested while another one was dispatching its events
+    Reentrant,
+}
+
+impl ConnectionError {
+    /// The protocol error of a connection, if it was lost because of one
+    pub fn of_display(display: &Display) -> Option<ConnectionError> {
+        display
+            .protocol_error()
+            .map(|err| ConnectionError::from_protocol_error(&err))
+    }
+
+    /// Describe a protocol error
+    pub fn from_protocol_error(err: &ProtocolError) -> ConnectionError {
+        ConnectionError::Protocol {
+            interface: err.object_interface,
+            object_id: err.object_id,
+            code: err.code,
+            description: protocol_errors::describe(err.object_interface, err.code),
+            message: err.message.clone(),
+        }
+    }
+
+    /// The connection error carried by an I/O error returned by the dispatch helpers of SCTK
+    pub fn from_io(err: &io::Error) -> Option<&ConnectionError> {
+        err.get_ref()?.downcast_ref::<ConnectionError>()
+    }
+
+    // Describe an I/O error of a connection without any protocol error
+    fn from_lost_connection(err: io::Error) -> ConnectionError {
+        if is_disconnection(&err) {
+            ConnectionError::Disconnected
+        } else {
+            ConnectionError::Io(err)
+        }
+    }
+}
+
+// libwayland reports EPIPE once the compositor closed its end of the socket
+fn is_disconnection(err: &io::Error) -> bool {
+    let kind = err.kind();
+    kind == io::ErrorKind::BrokenPipe || kind == io::ErrorKind::ConnectionReset
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionError::Protocol {
+                interface,
+                object_id,
+                code,
+                description,
+                message,
+            } => {
+                write!(f, "protocol error {} on {}@{}", code, interface, object_id)?;
+                if let Some(description) = description {
+                    write!(f, " ({})", description)?;
+                }
+                write!(f, ": {}", message)
+            }
+            ConnectionError::Disconnected => write!(f, "the compositor closed the connection"),
+            ConnectionError::Io(err) => write!(f, "connection lost: {}", err),
+            ConnectionError::Reentrant => {
+                write!(f, "roundtrip requested while dispatching another one")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectionError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConnectionError> for io::Error {
+    fn from(err: ConnectionError) -> io::Error {
+        match err {
+            ConnectionError::Io(err) => err,
+            ConnectionError::Disconnected => io::Error::new(io::ErrorKind::BrokenPipe, err),
+            err => io::Error::new(io::ErrorKind::Other, err),
+        }
+    }
+}
+
+// Attach the protocol error of the connection, if any, to an I/O error, or
+// `ConnectionError::Disconnected` if the compositor closed the connection
+pub(crate) fn with_protocol_error(display: &Display, err: io::Error) -> io::Error {
+    match ConnectionError::of_display(display) {
+        Some(connection_error) => io::Error::new(err.kind(), connection_error),
+        None if is_disconnection(&err) => io::Error::new(err.kind(), ConnectionError::Disconnected),
+        None => err,
+    }
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnvironmentError::MissingGlobal { interface } => {
+                write!(f, "the compositor does not support {}", interface)
+            }
+            EnvironmentError::GlobalVersionTooOld {
+                interface,
+                needed,
+                got,
+            } => write!(
+                f,
+                "{} version {} is required, but the compositor only supports version {}",
+                interface, needed, got
+            ),
+            EnvironmentError::ConnectFailed(err) => {
+                write!(f, "failed to connect to the compositor: {}", err)
+            }
+            EnvironmentError::Protocol(err) => {
+                write!(f, "{}", ConnectionError::from_protocol_error(err))
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnvironmentError::ConnectFailed(err) => Some(err),
+            EnvironmentError::Protocol(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<GlobalError> for EnvironmentError {
+    fn from(err: GlobalError) -> EnvironmentError {
+        match err {
+            GlobalError::Missing(interface) | GlobalError::Removed(interface) => {
+                EnvironmentError::MissingGlobal { interface }
+            }
+            GlobalError::BindFailed(_) => EnvironmentError::ConnectFailed(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                err.to_string(),
+            )),
+            GlobalError::TooOld {
+                interface,
+                needed,
+                got,
+            } => EnvironmentError::GlobalVersionTooOld {
+                interface,
+                needed,
+                got,
+            },
+        }
+    }
+}
+
+impl From<ConnectError> for EnvironmentError {
+    fn from(err: ConnectError) -> EnvironmentError {
+        EnvironmentError::ConnectFailed(io::Error::new(io::ErrorKind::ConnectionRefused, err))
+    }
+}
+
+/// A global advertised in the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalInfo {
+    /// The name of the global in the registry
+    pub name: u32,
+    /// The interface of the global
+    pub interface: String,
+    /// The version of the global advertised by the compositor
+    pub version: u32,
+}
+
+/// The globals advertised in the registry
+///
+/// This dereferences to a slice of [`GlobalInfo`](struct.GlobalInfo.html), and is
+/// displayed as a table, suitable for diagnostics and bug reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalList(pub Vec<GlobalInfo>);
+
+impl std::ops::Deref for GlobalList {
+    type Target = [GlobalInfo];
+    fn deref(&self) -> &[GlobalInfo] {
+        &self.0
+    }
+}
+
+impl IntoIterator for GlobalList {
+    type Item = GlobalInfo;
+    type IntoIter = std::vec::IntoIter<GlobalInfo>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for GlobalList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|global| global.interface.len())
+            .chain(Some("interface".len()))
+            .max()
+            .unwrap_or(0);
+        writeln!(
+            f,
+            "{:>6}  {:<width$}  version",
+            "name",
+            "interface",
+            width = width
+        )?;
+        for global in &self.0 {
+            writeln!(
+                f,
+                "{:>6}  {:<width$}  {}",
+                global.name,
+                global.interface,
+                global.version,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The range of versions of an interface supported by a handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRange {
+    /// The minimum required version
+    pub min: u32,
+    /// The maximum supported version
+    pub max: u32,
+}
+
+impl VersionRange {
+    /// Create a new version range
+    pub fn new(min: u32, max: u32) -> VersionRange {
+        VersionRange { min, max }
+    }
+
+    /// The version to bind a global of given interface advertised with given version
+    ///
+    /// This is the lowest of the advertised version and of the maximum version, or a
+    /// `GlobalError::TooOld` error if the advertised version is lower than the minimum.
+    pub fn bind_version(
+        &self,
+        interface: &'static str,
+        advertised: u32,
+    ) -> Result<u32, GlobalError> {
+        if advertised < self.min {
+            Err(GlobalError::TooOld {
+                interface,
+                needed: self.min,
+                got: advertised,
+            })
+        } else {
+            Ok(std::cmp::min(advertised, self.max))
+        }
+    }
+}
+
+/// Whether a wayland object has at least given version
+///
+/// This is useful to fallback on older requests when a newer one is not available.
+pub fn has_version<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>>(
+    object: &I,
+    version: u32,
+) -> bool {
+    object.as_ref().version() >= version
+}
+
+/// Check that a wayland object has at least given version
+///
+/// Returns a `GlobalError::TooOld` error otherwise, which is useful to report a
+/// feature that cannot be used instead of provoking a protocol error.
+pub fn require_version<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>>(
+    object: &I,
+    needed: u32,
+) -> Result<(), GlobalError> {
+    let got = object.as_ref().version();
+    if got >= needed {
+        Ok(())
+    } else {
+        Err(GlobalError::TooOld {
+            interface: I::NAME,
+            needed,
+            got,
+        })
+    }
+}
+
+type NewGlobalCallback = dyn FnMut(u32, u32, DispatchData);
+
+type RemovalCallback = dyn FnMut(u32, DispatchData);
+
+type UnknownCallback = dyn FnMut(&Attached<wl_registry::WlRegistry>, &GlobalEvent, DispatchData);
+
+type CapabilitiesCallback = dyn FnMut(&Capabilities, DispatchData);
+
+/// A handle to a new global listener callback
+///
+/// Dropping it disables the associated callback and frees the closure.
+pub struct NewGlobalListener {
+    _cb: Rc<RefCell<NewGlobalCallback>>,
+}
+
+/// A handle to a global removal listener callback
+///
+/// Dropping it disables the associated callback and frees the closure.
+pub struct RemovalListener {
+    _cb: Rc<RefCell<RemovalCallback>>,
+}
+
+/// A handle to an unknown globals listener callback
+///
+/// Dropping it disables the associated callback and frees the closure.
+pub struct UnknownGlobalListener {
+    _cb: Rc<RefCell<UnknownCallback>>,
+}
+
+/// A handle to a capabilities listener callback
+///
+/// Dropping it disables the associated callback and frees the closure.
+pub struct CapabilitiesListener {
+    _cb: Rc<RefCell<CapabilitiesCallback>>,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    registry: Option<Attached<wl_registry::WlRegistry>>,
+    // the globals not handled by the environment, tracked here as the global manager
+    // cannot be accessed while it dispatches an event
+    unknown_globals: Vec<(u32, String, u32)>,
+    // interfaces with a removed global, and not advertised again since
+    removed: Vec<String>,
+    new_listeners: Vec<(&'static str, rc::Weak<RefCell<NewGlobalCallback>>)>,
+    removal_listeners: Vec<(&'static str, rc::Weak<RefCell<RemovalCallback>>)>,
+    unknown_listeners: Vec<rc::Weak<RefCell<UnknownCallback>>>,
+    // all the globals of the registry, to compute the capabilities
+    globals: Vec<(u32, String, u32)>,
+    capabilities_listeners: Vec<rc::Weak<RefCell<CapabilitiesCallback>>>,
+    // whether `Environment::roundtrip` is dispatching events
+    in_roundtrip: bool,
+    // weak handles to the windows and layer surfaces created through the environment
+    tracked: Vec<Box<dyn Any>>,
+    // whether the connection was replaced by `Environment::reconnect`
+    defunct: bool,
+}
+
+impl RegistryState {
+    fn unknown_listeners(&mut self) -> Vec<Rc<RefCell<UnknownCallback>>> {
+        self.unknown_listeners.retain(|cb| cb.upgrade().is_some());
+        self.unknown_listeners
+            .iter()
+            .filter_map(|cb| cb.upgrade())
+            .collect()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::from_globals(
+            self.globals
+                .iter()
+                .map(|&(_, ref interface, version)| (&interface[..], version)),
+        )
+    }
+
+    // Record a global event, returning the new capabilities and the listeners to notify
+    // if they changed
+    fn track_capabilities(
+        &mut self,
+        event: &GlobalEvent,
+    ) -> Option<(Capabilities, Vec<Rc<RefCell<CapabilitiesCallback>>>)> {
+        self.capabilities_listeners
+            .retain(|cb| cb.upgrade().is_some());
+        let before = if self.capabilities_listeners.is_empty() {
+            None
+        } else {
+            Some(self.capabilities())
+        };
+        match *event {
+            GlobalEvent::New {
+                id,
+                ref interface,
+                version,
+            } => self.globals.push((id, interface.clone(), version)),
+            GlobalEvent::Removed { id, .. } => self.globals.retain(|&(i, _, _)| i != id),
+        }
+        let before = before?;
+        let after = self.capabilities();
+        if after == before {
+            return None;
+        }
+        let listeners = self
+            .capabilities_listeners
+            .iter()
+            .filter_map(|cb| cb.upgrade())
+            .collect();
+        Some((after, listeners))
+    }
+
+    fn process_event(&mut self, event: &GlobalEvent) -> Notifications {
+        match event {
+            GlobalEvent::New { interface, .. } => {
+                self.removed.retain(|name| name != interface);
+                self.new_listeners.retain(|(_, cb)| cb.upgrade().is_some());
+                Notifications::New(
+                    self.new_listeners
+                        .iter()
+                        .filter(|(name, _)| name == interface)
+                        .filter_map(|(_, cb)| cb.upgrade())
+                        .collect(),
+                )
+            }
+            GlobalEvent::Removed { interface, .. } => {
+                if !self.removed.contains(interface) {
+                    self.removed.push(interface.clone());
+                }
+                self.removal_listeners
+                    .retain(|(_, cb)| cb.upgrade().is_some());
+                Notifications::Removed(
+                    self.removal_listeners
+                        .iter()
+                        .filter(|(name, _)| name == interface)
+                        .filter_map(|(_, cb)| cb.upgrade())
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+// The listeners to notify once the handlers have processed a global event
+enum Notifications {
+    New(Vec<Rc<RefCell<NewGlobalCallback>>>),
+    Removed(Vec<Rc<RefCell<RemovalCallback>>>),
+}
+
+/// A Wayland Environment
+///
+/// This struct is generated by the `environment!` macro, see module-level documentation
+/// for more details about this.
+///
+/// This is the central point for accessing globals for your Wayland app. Any global that has
+/// previously been declared in the `environment!` macro can be access from this type via the
+/// `get_global`, `required_global` and `get_all_globals` methods.
+///
+/// This `Environment` is a handle that can be cloned.
+pub struct Environment<E> {
+    /// The underlying `GlobalManager`, if you need to do manual interaction with the
+    /// registry. See `wayland-client` documentation for details.
+    pub manager: GlobalManager,
+    inner: Rc<RefCell<E>>,
+    state: Rc<RefCell<RegistryState>>,
+}
+
+impl<E: InnerEnv + 'static> Environment<E> {
+    /// Initialize the `Environment`
+    ///
+    /// This requires access to a `wl_display` attached to an event queue (on which the main SCTK logic
+    /// will be attached). You also need to provide an instance of the inner environment type declared
+    /// using the [`environment!`](../macro.environment.html) macro.
+    ///
+    /// If you instead used the [`default_environment!`](../macro.default_environment.html), then you need
+    /// to initialize your `Environment` using the
+    /// [`init_default_environment!`](../macro.init_default_environment.html) macro.
+    ///
+    /// You will need to do two roundtrips of the event queue afterward to filly initialize the environment,
+    /// see [`roundtrip`](#method.roundtrip).
+    pub fn init(display: &Attached<wl_display::WlDisplay>, env: E) -> Environment<E> {
+        let inner = Rc::new(RefCell::new(env));
+        let state = Rc::new(RefCell::new(RegistryState::default()));
+
+        let my_inner = inner.clone();
+        let my_state = state.clone();
+        let my_cb = move |event: GlobalEvent,
+                          registry: Attached<wl_registry::WlRegistry>,
+                          mut ddata: DispatchData| {
+            let (id, version, handled) = match event {
+                GlobalEvent::New {
+                    id,
+                    ref interface,
+                    version,
+                } => (id, version, my_inner.borrow().handles(interface)),
+                GlobalEvent::Removed { id, ref interface } => {
+                    (id, 0, my_inner.borrow().handles(interface))
+                }
+            };
+            let (listeners, unknown_listeners, capabilities) = {
+                let mut state = my_state.borrow_mut();
+                if state.registry.is_none() {
+                    state.registry = Some(registry.clone());
+                }
+                let unknown_listeners = if handled {
+                    Vec::new()
+                } else {
+                    match event {
+                        GlobalEvent::New {
+                            id,
+                            ref interface,
+                            version,
+                        } => state.unknown_globals.push((id, interface.clone(), version)),
+                        GlobalEvent::Removed { id, .. } => {
+                            state.unknown_globals.retain(|&(i, _, _)| i != id)
+                        }
+                    }
+                    state.unknown_listeners()
+                };
+                let capabilities = state.track_capabilities(&event);
+                (state.process_event(&event), unknown_listeners, capabilities)
+            };
+            if handled {
+                my_inner
+                    .borrow_mut()
+                    .process_event(event, registry, ddata.reborrow());
+            } else {
+                for listener in unknown_listeners {
+                    (*listener.borrow_mut())(&registry, &event, ddata.reborrow());
+                }
+            }
+            // the handlers have processed the event, notify the listeners
+            match listeners {
+                Notifications::New(listeners) => {
+                    for listener in listeners {
+                        (*listener.borrow_mut())(id, version, ddata.reborrow());
+                    }
+                }
+                Notifications::Removed(listeners) => {
+                    for listener in listeners {
+                        (*listener.borrow_mut())(id, ddata.reborrow());
+                    }
+                }
+            }
+            if let Some((capabilities, listeners)) = capabilities {
+                for listener in listeners {
+                    (*listener.borrow_mut())(&capabilities, ddata.reborrow());
+                }
+            }
+        };
+
+        let manager = GlobalManager::new_with_cb(&display, my_cb);
+
+        Environment {
+            manager,
+            inner,
+            state,
+        }
+    }
+
+    /// Register a callback invoked for the globals this environment does not handle
+    ///
+    /// The callback is given the registry and the `GlobalEvent` of every global whose
+    /// interface is not declared in your environment, and of their removal. This lets you
+    /// bind globals of protocols this crate does not know about, see also
+    /// [`new_extra_global`](#method.new_extra_global).
+    ///
+    /// The globals the registry already advertised are replayed to the callback when
+    /// registering it, with an empty `DispatchData`.
+    ///
+    /// Dropping the returned [`UnknownGlobalListener`](struct.UnknownGlobalListener.html)
+    /// unregisters the callback.
+    pub fn listen_for_unknown_globals<F>(&self, mut callback: F) -> UnknownGlobalListener
+    where
+        F: FnMut(&Attached<wl_registry::WlRegistry>, &GlobalEvent, DispatchData) + 'static,
+    {
+        let (registry, globals) = {
+            let state = self.state.borrow();
+            (state.registry.clone(), state.unknown_globals.clone())
+        };
+        if let Some(registry) = registry {
+            for (id, interface, version) in globals {
+                let event = GlobalEvent::New {
+                    id,
+                    interface,
+                    version,
+                };
+                callback(&registry, &event, DispatchData::wrap(&mut ()));
+            }
+        }
+        let rc = Rc::new(RefCell::new(callback)) as Rc<RefCell<UnknownCallback>>;
+        self.state
+            .borrow_mut()
+            .unknown_listeners
+            .push(Rc::downgrade(&rc));
+        UnknownGlobalListener { _cb: rc }
+    }
+
+    /// Track a "single" global that is not declared in this environment
+    ///
+    /// The returned [`ExtraGlobal`](struct.ExtraGlobal.html) binds and caches the global
+    /// using the provided handler, as if it was declared in the environment. The global
+    /// must not be declared in the environment, as it would then not be forwarded to it.
+    pub fn new_extra_global<I>(&self, handler: SimpleGlobal<I>) -> ExtraGlobal<I>
+    where
+        I: Interface + Clone + From<Proxy<I>> + AsRef<Proxy<I>> + 'static,
+    {
+        let handler = Rc::new(RefCell::new(handler));
+        let my_handler = handler.clone();
+        let listener =
+            self.listen_for_unknown_globals(move |registry, event, ddata| match *event {
+                GlobalEvent::New {
+                    id,
+                    ref interface,
+                    version,
+                } if interface == I::NAME => {
+                    my_handler
+                        .borrow_mut()
+                        .created(registry.clone(), id, version, ddata)
+                }
+                GlobalEvent::Removed { id, ref interface } if interface == I::NAME => {
+                    my_handler.borrow_mut().removed(id, ddata)
+                }
+                _ => {}
+            });
+        ExtraGlobal {
+            handler,
+            _listener: listener,
+        }
+    }
+}
+
+impl<E> Environment<E> {
+    /// Access a "single" global
+    ///
+    /// This method allows you to access any "single" global that has previously
+    /// been declared in the `environment!` macro. It is forwarded to the `get()`
+    /// method of the appropriate `GlobalHandler`.
+    ///
+    /// It returns `None` if the global has not (yet) been signaled by the registry.
+    pub fn get_global<I: Interface>(&self) -> Option<Attached<I>>
+    where
+        E: GlobalHandler<I>,
+    {
+        if self.is_defunct() {
+            return None;
+        }
+        self.inner.borrow().get()
+    }
+
+    /// Access a "single" global, reporting why it is not available
+    ///
+    /// This method is similar to `get_global`, but returns an error distinguishing a global
+    /// that was never advertised by the server from one that has since been removed, or
+    /// that could not be bound.
+    pub fn try_get_global<I: Interface>(&self) -> Result<Attached<I>, GlobalError>
+    where
+        E: GlobalHandler<I>,
+    {
+        if self.is_defunct() {
+            return Err(GlobalError::Removed(I::NAME));
+        }
+        match self.inner.borrow().try_get() {
+            Err(GlobalError::Missing(name))
+                if self.state.borrow().removed.iter().any(|n| n == name) =>
+            {
+                Err(GlobalError::Removed(name))
+            }
+            result => result,
+        }
+    }
+
+    /// Access a "single" global, attached to another event queue
+    ///
+    /// This is similar to `get_global`, but the objects created from the returned global
+    /// are assigned to the event queue of `token` rather than the one of the environment.
+    pub fn attach_global<I>(&self, token: QueueToken) -> Option<Attached<I>>
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+        E: GlobalHandler<I>,
+    {
+        self.get_global::<I>()
+            .map(|global| global.as_ref().attach(token))
+    }
+
+    /// The version with which a "single" global was bound
+    ///
+    /// Returns `None` if the global is not available.
+    pub fn global_version<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>>(&self) -> Option<u32>
+    where
+        E: GlobalHandler<I>,
+    {
+        self.get_global::<I>()
+            .map(|global| global.as_ref().version())
+    }
+
+    /// Access a "single" global or panic
+    ///
+    /// This method is similar to `get_global`, but will panic with a detailed error
+    /// message if the requested global was not advertized by the server, or was removed.
+    pub fn require_global<I: Interface>(&self) -> Attached<I>
+    where
+        E: GlobalHandler<I>,
+    {
+        match self.try_get_global() {
+            Ok(g) => g,
+            Err(GlobalError::Missing(name)) => {
+                panic!("[SCTK] A missing global was required: {}", name)
+            }
+            Err(err) => panic!("[SCTK] A required global is unavailable: {}", err),
+        }
+    }
+
+    /// Register a callback invoked when a global of given interface is advertised
+    ///
+    /// The callback is given the id and version of the new global, and is invoked after
+    /// the handler of this global recorded it: the global can thus be retrieved from the
+    /// callback with `get_global` or `get_all_globals`.
+    ///
+    /// This allows features depending on an optional global to engage once it becomes
+    /// available, even if it was not present during the initial roundtrips. It is not
+    /// invoked for the globals that were already advertised when it is registered.
+    ///
+    /// Dropping the returned [`NewGlobalListener`](struct.NewGlobalListener.html)
+    /// unregisters the callback.
+    pub fn listen_for_new_globals<I, F>(&self, callback: F) -> NewGlobalListener
+    where
+        I: Interface,
+        F: FnMut(u32, u32, DispatchData) + 'static,
+    {
+        let rc = Rc::new(RefCell::new(callback)) as Rc<_>;
+        self.state
+            .borrow_mut()
+            .new_listeners
+            .push((I::NAME, Rc::downgrade(&rc)));
+        NewGlobalListener { _cb: rc }
+    }
+
+    /// Register a callback invoked when a global of given interface is removed
+    ///
+    /// The callback is given the id of the removed global, and is invoked after the
+    /// handler of this global processed its removal (for example marking the
+    /// corresponding `wl_output` as obsolete).
+    ///
+    /// Dropping the returned [`RemovalListener`](struct.RemovalListener.html) unregisters
+    /// the callback.
+    pub fn listen_for_removals<I, F>(&self, callback: F) -> RemovalListener
+    where
+        I: Interface,
+        F: FnMut(u32, DispatchData) + 'static,
+    {
+        let rc = Rc::new(RefCell::new(callback)) as Rc<_>;
+        self.state
+            .borrow_mut()
+            .removal_listeners
+            .push((I::NAME, Rc::downgrade(&rc)));
+        RemovalListener { _cb: rc }
+    }
+
+    /// Access all instances of a "multi" global
+    ///
+    /// This will return a `Vec` containing all currently existing instances of the
+    /// requested "multi" global that has been previously declared in the `environment!`
+    /// macro. It is forwarded to the `get_all()` method of the appropriate
+    /// `MultiGlobalHandler`.
+    pub fn get_all_globals<I: Interface>(&self) -> Vec<Attached<I>>
+    where
+        E: MultiGlobalHandler<I>,
+    {
+        if self.is_defunct() {
+            return Vec::new();
+        }
+        self.inner.borrow().get_all()
+    }
+
+    /// List all the globals currently advertised in the registry
+    ///
+    /// This includes the globals the environment does not handle, and reflects the
+    /// globals added or removed since the environment was initialized. The list is
+    /// sorted by name, and is displayed as a table.
+    pub fn globals(&self) -> GlobalList {
+        let mut globals = self
+            .manager
+            .list()
+            .into_iter()
+            .map(|(name, interface, version)| GlobalInfo {
+                name,
+                interface,
+                version,
+            })
+            .collect::<Vec<_>>();
+        globals.sort_by_key(|global| global.name);
+        GlobalList(globals)
+    }
+
+    /// The protocols supported by the compositor
+    ///
+    /// This reflects the globals added or removed since the environment was initialized,
+    /// see [`Capabilities`](struct.Capabilities.html).
+    pub fn capabilities(&self) -> Capabilities {
+        self.state.borrow().capabilities()
+    }
+
+    /// Register a callback invoked when the protocols supported by the compositor change
+    ///
+    /// The callback is given the new capabilities each time a global they account for is
+    /// added or removed, after the handler of this global processed the event. It is not
+    /// invoked for the current capabilities when it is registered.
+    ///
+    /// Dropping the returned [`CapabilitiesListener`](struct.CapabilitiesListener.html)
+    /// unregisters the callback.
+    pub fn listen_for_capabilities<F>(&self, callback: F) -> CapabilitiesListener
+    where
+        F: FnMut(&Capabilities, DispatchData) + 'static,
+    {
+        let rc = Rc::new(RefCell::new(callback)) as Rc<_>;
+        self.state
+            .borrow_mut()
+            .capabilities_listeners
+            .push(Rc::downgrade(&rc));
+        CapabilitiesListener { _cb: rc }
+    }
+
+    // Keep a weak handle to an object created through this environment
+    pub(crate) fn track<T: 'static>(&self, handle: T) {
+        self.state.borrow_mut().tracked.push(Box::new(handle));
+    }
+
+    // Upgrade the tracked handles of a type, dropping the ones of the dead objects
+    //
+    // The state is not borrowed once this returns, so the objects can be used to create
+    // or destroy others.
+    pub(crate) fn tracked<T: 'static, R, F>(&self, mut upgrade: F) -> Vec<R>
+    where
+        F: FnMut(&T) -> Option<R>,
+    {
+        let mut live = Vec::new();
+        self.state
+            .borrow_mut()
+            .tracked
+            .retain(|handle| match handle.downcast_ref::<T>() {
+                Some(handle) => match upgrade(handle) {
+                    Some(object) => {
+                        live.push(object);
+                        true
+                    }
+                    None => false,
+                },
+                None => true,
+            });
+        live
+    }
+
+    /// Do a roundtrip of an event queue
+    ///
+    /// This blocks until the compositor has processed all the requests sent so far, and
+    /// dispatches the events of the queue it sent in the meantime. Once it returns:
+    ///
+    /// - the events sent by the compositor in response to the requests made before the call,
+    ///   like the `done` event of a `wl_callback` or the initial state of a newly bound global,
+    ///   have all been processed by their callbacks, in the order the compositor sent them;
+    /// - the events for requests made by these callbacks may not have been received yet, a second
+    ///   roundtrip is needed to process them. This is why the environment is initialized with
+    ///   two roundtrips: the first one receives the globals, the second one the events sent
+    ///   by the globals bound in response.
+    ///
+    /// The events of the other event queues are not dispatched, and the events without a
+    /// callback are logged and dropped.
+    ///
+    /// The queue is borrowed for the whole roundtrip, so it cannot be dispatched again from one
+    /// of its callbacks. Calling this method from a callback dispatched by a roundtrip, even on
+    /// another queue, fails with `ConnectionError::Reentrant`, as waiting on the compositor from
+    /// there could block forever. If the connection is lost, the protocol error that killed it
+    /// is returned if there is one, and `ConnectionError::Disconnected` if the compositor closed
+    /// it. The roundtrips of an environment replaced by [`reconnect`](#method.reconnect) fail
+    /// with `ConnectionError::Disconnected` too.
+    pub fn roundtrip(&self, queue: &mut EventQueue) -> Result<u32, ConnectionError> {
+        {
+            let mut state = self.state.borrow_mut();
+            if state.defunct {
+                return Err(ConnectionError::Disconnected);
+            }
+            if state.in_roundtrip {
+                return Err(ConnectionError::Reentrant);
+            }
+            state.in_roundtrip = true;
+        }
+        let ret = queue.sync_roundtrip(&mut (), |evt, _, _| {
+            log::warn!(
+                "[SCTK] Orphan event during a roundtrip: {}.{}",
+                evt.interface,
+                evt.name
+            );
+        });
+        self.state.borrow_mut().in_roundtrip = false;
+        ret.map_err(|err| {
+            ConnectionError::of_display(queue.display())
+                .unwrap_or_else(|| ConnectionError::from_lost_connection(err))
+        })
+    }
+
+    /// Connect again to the compositor, once the connection of this environment was lost
+    ///
+    /// When the compositor crashes or restarts, it closes the connection: the dispatch helpers
+    /// of SCTK and [`roundtrip`](#method.roundtrip) then fail with
+    /// `ConnectionError::Disconnected`. This method dials the compositor of `$WAYLAND_DISPLAY`
+    /// again, and gives the new `Display` and `EventQueue` to `init`, which creates a new
+    /// environment with new modules the same way you created the first one. `rebuild` is then
+    /// invoked with the new environment, for your app to create its windows, pools and pointers
+    /// again.
+    ///
+    /// None of the objects of the old connection can be used with the new one. Drop the old
+    /// `Display` and `EventQueue`, remove the `WaylandSource` of the old queue from your event
+    /// loop, and drop everything created from the old environment: the globals and other
+    /// proxies, the windows, layer surfaces and popups, the memory pools, the `ThemeManager`
+    /// and its pointers, the data devices and the output and seat listeners.
+    ///
+    /// This environment and its clones are marked defunct, even if the old connection is
+    /// still alive: they no longer give access to any global, their roundtrips fail with
+    /// `ConnectionError::Disconnected`, and the globals, listeners and windows they tracked are
+    /// forgotten. The old modules, which can still be accessed with
+    /// [`with_inner`](#method.with_inner), must not be used either.
+    ///
+    /// ```no_run
+    /// # use smithay_client_toolkit::{default_environment, init_default_environment};
+    /// # use smithay_client_toolkit::environment::Environment;
+    /// # default_environment!(MyEnv, desktop);
+    /// # fn create_windows(env: &Environment<MyEnv>) {}
+    /// # let (env, display, queue) = init_default_environment!(MyEnv, desktop).unwrap();
+    /// // once the connection was lost with `ConnectionError::Disconnected`
+    /// drop((display, queue));
+    /// let (env, display, queue) = env
+    ///     .reconnect(
+    ///         |display, queue| init_default_environment!(MyEnv, desktop, with = (*display, *queue)),
+    ///         |env| create_windows(env),
+    ///     )
+    ///     .expect("Failed to connect again to the compositor");
+    /// ```
+    pub fn reconnect<I, R>(
+        &self,
+        init: I,
+        rebuild: R,
+    ) -> Result<(Environment<E>, Display, EventQueue), EnvironmentError>
+    where
+        I: FnOnce(&Display, &mut EventQueue) -> Result<Environment<E>, EnvironmentError>,
+        R: FnOnce(&Environment<E>),
+    {
+        self.reconnect_with(Display::connect_to_env, init, rebuild)
+    }
+
+    /// Connect again to the compositor through a custom connection
+    ///
+    /// This is like [`reconnect`](#method.reconnect), for apps which do not connect to the
+    /// compositor of `$WAYLAND_DISPLAY`: the new `Display` is created by `connect`, for example
+    /// with `Display::connect_to_name`.
+    pub fn reconnect_with<C, I, R>(
+        &self,
+        connect: C,
+        init: I,
+        rebuild: R,
+    ) -> Result<(Environment<E>, Display, EventQueue), EnvironmentError>
+    where
+        C: FnOnce() -> Result<Display, ConnectError>,
+        I: FnOnce(&Display, &mut EventQueue) -> Result<Environment<E>, EnvironmentError>,
+        R: FnOnce(&Environment<E>),
+    {
+        // forget everything bound to the old connection
+        *self.state.borrow_mut() = RegistryState {
+            defunct: true,
+            ..RegistryState::default()
+        };
+        let display = connect()?;
+        let mut queue = display.create_event_queue();
+        let env = init(&display, &mut queue)?;
+        rebuild(&env);
+        Ok((env, display, queue))
+    }
+
+    /// Whether this environment was replaced by [`reconnect`](#method.reconnect)
+    pub fn is_defunct(&self) -> bool {
+        self.state.borrow().defunct
+    }
+
+    /// Access the inner environment
+    ///
+    /// This gives your access, via a closure, to the inner type you declared
+    /// via the [`environment!`](../macro.environment.html) or
+    /// [`default_environment!`](../macro.default_environment.html) macro.
+    ///
+    /// This method returns the return value of your closure.
+    pub fn with_inner<T, F: FnOnce(&mut E) -> T>(&self, f: F) -> T {
+        let mut inner = self.inner.borrow_mut();
+        f(&mut *inner)
+    }
+}
+
+impl<E> Clone for Environment<E> {
+    fn clone(&self) -> Environment<E> {
+        Environment {
+            manager: self.manager.clone(),
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Internal trait for the `Environment` logic
+///
+/// This trait is automatically implemented by the [`environment!`](../macro.environment.html)
+/// macro, you should not implement it manually unless you seriously want to.
+pub trait InnerEnv {
+    /// Process a `GlobalEvent`
+    fn process_event(
+        &mut self,
+        event: GlobalEvent,
+        registry: Attached<wl_registry::WlRegistry>,
+        data: DispatchData,
+    );
+    /// Whether this environment handles the globals of given interface
+    ///
+    /// The globals it does not handle are forwarded to the callbacks registered with
+    /// `Environment::listen_for_unknown_globals()`. The default implementation handles
+    /// all globals.
+    fn handles(&self, interface: &str) -> bool {
+        let _ = interface;
+        true
+    }
+}
+
+/*
+ * Simple handlers
+ */
+
+/// A minimalist global handler for "single" globals
+///
+/// This handler will simply record the global when the registry signals it, and
+/// bind it the first time it is accessed. The bound global is then cached.
+///
+/// It is appropriate for globals that never generate events, like `wl_compositor`
+/// or `wl_data_device_manager`.
+///
+/// If the global is removed, it is forgotten and `get()` returns `None` afterwards.
+///
+/// The global is bound with the highest version supported by both the compositor and
+/// this handler, which by default supports all the versions known to `wayland-client`.
+/// Use `with_versions()` to state the versions you actually support: if the compositor
+/// advertises an older version than needed, `try_get()` reports it as
+/// `GlobalError::TooOld` and `get()` returns `None`.
+pub struct SimpleGlobal<I: Interface> {
+    versions: VersionRange,
+    inner: RefCell<SimpleInner<I>>,
+}
+
+struct SimpleInner<I: Interface> {
+    registry: Option<Attached<wl_registry::WlRegistry>>,
+    id: u32,
+    global: LazyGlobal<I>,
+}
+
+impl<I: Interface> SimpleGlobal<I> {
+    /// Create a new handler
+    pub fn new() -> SimpleGlobal<I> {
+        SimpleGlobal::with_versions(1, I::VERSION)
+    }
+
+    /// Create a new handler supporting given versions of the global
+    ///
+    /// `max` is clamped to the highest version known to `wayland-client`.
+    pub fn with_versions(min: u32, max: u32) -> SimpleGlobal<I> {
+        SimpleGlobal {
+            versions: VersionRange::new(min, std::cmp::min(max, I::VERSION)),
+            inner: RefCell::new(SimpleInner {
+                registry: None,
+                id: 0,
+                global: LazyGlobal::Unknown,
+            }),
+        }
+    }
+}
+
+impl<I: Interface + Clone + From<Proxy<I>> + AsRef<Proxy<I>>> GlobalHandler<I> for SimpleGlobal<I> {
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        _: DispatchData,
+    ) {
+        let inner = self.inner.get_mut();
+        if let LazyGlobal::Unknown = inner.global {
+            inner.registry = Some(registry);
+            inner.id = id;
+            inner.global = LazyGlobal::Seen { id, version };
+        } else {
+            log::warn!(
+                "[SCTK] Compositor advertised {} multiple times, ignoring. \
+                If it can have multiple instances, use a multi global handler.",
+                I::NAME
+            );
+        }
+    }
+    fn get(&self) -> Option<Attached<I>> {
+        self.try_get().ok()
+    }
+    fn try_get(&self) -> Result<Attached<I>, GlobalError> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.global {
+            LazyGlobal::Bound(ref global) => Ok(global.clone()),
+            LazyGlobal::Unknown => Err(GlobalError::Missing(I::NAME)),
+            LazyGlobal::Seen { id, version } => {
+                // registry cannot be None if we have seen the global
+                let registry = inner.registry.as_ref().unwrap();
+                let version = self.versions.bind_version(I::NAME, version)?;
+                let global = (*registry.bind::<I>(version, id)).clone();
+                if !AsRef::<Proxy<I>>::as_ref(&*global).is_alive() {
+                    return Err(GlobalError::BindFailed(I::NAME));
+                }
+                log::debug!("[SCTK] Bound {} version {}", I::NAME, version);
+                inner.global = LazyGlobal::Bound(global.clone());
+                Ok(global)
+            }
+        }
+    }
+    fn removed(&mut self, id: u32, _: DispatchData) {
+        let inner = self.inner.get_mut();
+        if inner.id == id {
+            inner.global = LazyGlobal::Unknown;
+        }
+    }
+}
+
+type MultiInit<I> = dyn FnMut(Main<I>, DispatchData);
+
+/// A minimalist global handler for "multi" globals
+///
+/// This handler binds every instance of the global as soon as the registry signals it,
+/// and forgets it once it is removed.
+///
+/// It is appropriate for globals that can be advertised several times and need no
+/// processing beyond their binding. The initialization callback is invoked with each
+/// instance right after it is bound, which lets you set its user data or assign it, so
+/// that they are present on all the instances returned by `get_all()`.
+///
+/// Instances advertised with a version lower than the minimum required one are ignored.
+pub struct SimpleMultiGlobal<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>> {
+    versions: VersionRange,
+    init: Box<MultiInit<I>>,
+    globals: Vec<(u32, Attached<I>)>,
+}
+
+impl<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>> SimpleMultiGlobal<I> {
+    /// Create a new handler
+    pub fn new<F>(init: F) -> SimpleMultiGlobal<I>
+    where
+        F: FnMut(Main<I>, DispatchData) + 'static,
+    {
+        SimpleMultiGlobal::with_versions(1, I::VERSION, init)
+    }
+
+    /// Create a new handler supporting given versions of the global
+    ///
+    /// `max` is clamped to the highest version known to `wayland-client`.
+    pub fn with_versions<F>(min: u32, max: u32, init: F) -> SimpleMultiGlobal<I>
+    where
+        F: FnMut(Main<I>, DispatchData) + 'static,
+    {
+        SimpleMultiGlobal {
+            versions: VersionRange::new(min, std::cmp::min(max, I::VERSION)),
+            init: Box::new(init),
+            globals: Vec::new(),
+        }
+    }
+}
+
+impl<I: Interface + Clone + From<Proxy<I>> + AsRef<Proxy<I>>> MultiGlobalHandler<I>
+    for SimpleMultiGlobal<I>
+{
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        ddata: DispatchData,
+    ) {
+        let version = match self.versions.bind_version(I::NAME, version) {
+            Ok(version) => version,
+            Err(err) => {
+                log::warn!("[SCTK] Ignoring an instance of {}: {}", I::NAME, err);
+                return;
+            }
+        };
+        log::debug!("[SCTK] Bound {} version {}", I::NAME, version);
+        let global = registry.bind::<I>(version, id);
+        let attached = (*global).clone();
+        (self.init)(global, ddata);
+        self.globals.push((id, attached));
+    }
+    fn removed(&mut self, id: u32, _: DispatchData) {
+        self.globals.retain(|&(i, _)| i != id);
+    }
+    fn get_all(&self) -> Vec<Attached<I>> {
+        self.globals.iter().map(|(_, g)| g.clone()).collect()
+    }
+}
+
+/// A "single" global that is not declared in the environment
+///
+/// It is created by [`Environment::new_extra_global`](struct.Environment.html#method.new_extra_global),
+/// and binds and caches its global like a [`SimpleGlobal`](struct.SimpleGlobal.html).
+pub struct ExtraGlobal<I: Interface> {
+    handler: Rc<RefCell<SimpleGlobal<I>>>,
+    _listener: UnknownGlobalListener,
+}
+
+impl<I: Interface + Clone + From<Proxy<I>> + AsRef<Proxy<I>>> ExtraGlobal<I> {
+    /// Access the global if it was signaled
+    pub fn get(&self) -> Option<Attached<I>> {
+        self.handler.borrow().get()
+    }
+
+    /// Access the global, reporting why it is not available
+    pub fn try_get(&self) -> Result<Attached<I>, GlobalError> {
+        self.handler.borrow().try_get()
+    }
+}
+
+/*
+ * environment! macro
+ */
+
+/// Macro for declaring an environment
+///
+/// It needs to be used in conjunction with a a `struct` you declared, which will serve as the inner
+/// environment and hold the handlers for your globals.
+///
+/// The macro is invoked as such:
+///
+/// ```no_run
+/// # extern crate smithay_client_toolkit as sctk;
+/// # use sctk::reexports::client::protocol::{wl_compositor::WlCompositor, wl_subcompositor::WlSubcompositor, wl_output::WlOutput};
+/// # use sctk::environment::SimpleGlobal;
+/// # use sctk::environment;
+/// # use sctk::output::OutputHandler;
+/// struct MyEnv {
+///     compositor: SimpleGlobal<WlCompositor>,
+///     subcompositor: SimpleGlobal<WlSubcompositor>,
+///     outputs: OutputHandler
+/// }
+///
+/// environment!(MyEnv,
+///     singles = [
+///         WlCompositor => compositor,
+///         WlSubcompositor => subcompositor,
+///     ],
+///     multis = [
+///         WlOutput => outputs,
+///     ]
+/// );
+/// ```
+///
+/// This will define how your `MyEnv` struct is able to manage the `WlCompositor`, `WlSubcompositor` and
+/// `WlOutput` globals. For each global, you need to provide a pattern
+/// `$type => $name` where:
+///
+/// - `$type` is the type (implementing the `Interface` trait from `wayland-client`) representing a global
+/// - `$name` is the name of the field of `MyEnv` that is in charge of managing this global, implementing the
+///   appropriate `GlobalHandler` or `MultiGlobalHandler` trait
+///
+/// It is possible to route several globals to the same field as long as it implements all the appropriate traits.
+///
+/// Globals listed in `singles` are accessed with `Environment::get_global()`, and globals listed in
+/// `multis` with `Environment::get_all_globals()`: using the wrong accessor is a compile-time error.
+/// Globals that can be advertised several times, like `wl_output`, must be listed in `multis`; the
+/// [`SimpleMultiGlobal`](environment/struct.SimpleMultiGlobal.html) handler is available for those
+/// which need no specific processing.
+#[macro_export]
+macro_rules! environment {
+    ($env_name:ident,
+        singles = [$($sty:ty => $sname:ident),* $(,)?],
+        multis = [$($mty:ty => $mname:ident),* $(,)?]$(,)?
+    ) => {
+        impl $crate::environment::InnerEnv for $env_name {
+            fn process_event(
+                &mut self,
+                event: $crate::reexports::client::GlobalEvent,
+                registry: $crate::reexports::client::Attached<$crate::reexports::client::protocol::wl_registry::WlRegistry>,
+                ddata: $crate::reexports::client::DispatchData,
+            ) {
+                match event {
+                    $crate::reexports::client::GlobalEvent::New { id, interface, version } => match &interface[..] {
+                        $(
+                            <$sty as $crate::reexports::client::Interface>::NAME => $crate::environment::GlobalHandler::<$sty>::created(&mut self.$sname, registry, id, version, ddata),
+                        )*
+                        $(
+                            <$mty as $crate::reexports::client::Interface>::NAME => $crate::environment::MultiGlobalHandler::<$mty>::created(&mut self.$mname, registry, id, version, ddata),
+                        )*
+                        _ => { /* ignore unkown globals */ }
+                    },
+                    $crate::reexports::client::GlobalEvent::Removed { id, interface } => match &interface[..] {
+                        $(
+                            <$sty as $crate::reexports::client::Interface>::NAME => $crate::environment::GlobalHandler::<$sty>::removed(&mut self.$sname, id, ddata),
+                        )*
+                        $(
+                            <$mty as $crate::reexports::client::Interface>::NAME => $crate::environment::MultiGlobalHandler::<$mty>::removed(&mut self.$mname, id, ddata),
+                        )*
+                        _ => { /* ignore unknown globals */ }
+                    }
+                }
+            }
+
+            fn handles(&self, interface: &str) -> bool {
+                match interface {
+                    $(
+                        <$sty as $crate::reexports::client::Interface>::NAME => true,
+                    )*
+                    $(
+                        <$mty as $crate::reexports::client::Interface>::NAME => true,
+                    )*
+                    _ => false,
+                }
+            }
+        }
+
+        $(
+            impl $crate::environment::GlobalHandler<$sty> for $env_name {
+                fn created(&mut self, registry: $crate::reexports::client::Attached<$crate::reexports::client::protocol::wl_registry::WlRegistry>, id: u32, version: u32, ddata: $crate::reexports::client::DispatchData) {
+                    $crate::environment::GlobalHandler::<$sty>::created(&mut self.$sname, registry, id, version, ddata)
+                }
+                fn get(&self) -> Option<$crate::reexports::client::Attached<$sty>> {
+                    $crate::environment::GlobalHandler::<$sty>::get(&self.$sname)
+                }
+                fn try_get(&self) -> Result<$crate::reexports::client::Attached<$sty>, $crate::environment::GlobalError> {
+                    $crate::environment::GlobalHandler::<$sty>::try_get(&self.$sname)
+                }
+                fn removed(&mut self, id: u32, ddata: $crate::reexports::client::DispatchData) {
+                    $crate::environment::GlobalHandler::<$sty>::removed(&mut self.$sname, id, ddata)
+                }
+            }
+        )*
+
+        $(
+            impl $crate::environment::MultiGlobalHandler<$mty> for $env_name {
+                fn created(&mut self, registry: $crate::reexports::client::Attached<$crate::reexports::client::protocol::wl_registry::WlRegistry>, id: u32, version: u32, ddata: $crate::reexports::client::DispatchData) {
+                    $crate::environment::MultiGlobalHandler::<$mty>::created(&mut self.$mname, registry, id, version, ddata)
+                }
+                fn removed(&mut self, id: u32, ddata: $crate::reexports::client::DispatchData) {
+                    $crate::environment::MultiGlobalHandler::<$mty>::removed(&mut self.$mname, id, ddata)
+                }
+                fn get_all(&self) -> Vec<$crate::reexports::client::Attached<$mty>> {
+                    $crate::environment::MultiGlobalHandler::<$mty>::get_all(&self.$mname)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{count, MockCompositor};
+    use std::os::unix::{io::IntoRawFd, net::UnixStream};
+    use wayland_client::protocol::wl_compositor;
+
+    const GLOBALS: &[(&str, u32)] = &[("wl_compositor", 4), ("wl_shm", 1)];
+
+    fn init(
+        display: &Display,
+        queue: &mut EventQueue,
+    ) -> Result<Environment<DynamicEnv>, EnvironmentError> {
+        EnvironmentBuilder::new(display)
+            .with_compositor()
+            .with_shm()
+            .build(queue)
+    }
+
+    fn assert_disconnected(result: Result<u32, ConnectionError>) {
+        match result {
+            Err(ConnectionError::Disconnected) => {}
+            other => panic!("expected a disconnection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reconnect_after_a_compositor_restart() {
+        let socket = std::env::temp_dir().join(format!("sctk-wayland-{}", std::process::id()));
+        // the socket is given explicitly, the environment of the process is shared by the tests
+        let connect = || {
+            let stream =
+                UnixStream::connect(&socket).map_err(|_| ConnectError::NoCompositorListening)?;
+            unsafe { Display::from_fd(stream.into_raw_fd()) }
+        };
+
+        let compositor = MockCompositor::start(&socket, GLOBALS);
+        let display = connect().unwrap();
+        let mut queue = display.create_event_queue();
+        let env = init(&display, &mut queue).unwrap();
+        let old_compositor = env.require_global::<wl_compositor::WlCompositor>();
+        let _surface = old_compositor.create_surface();
+        env.roundtrip(&mut queue).unwrap();
+
+        // the compositor crashes
+        let requests = compositor.kill();
+        assert_eq!(count(&requests, old_compositor.as_ref(), 0), 1);
+        assert_disconnected(env.roundtrip(&mut queue));
+        let err = queue.dispatch(&mut (), |_, _, _| {}).unwrap_err();
+        let err = with_protocol_error(&display, err);
+        match ConnectionError::from_io(&err) {
+            Some(ConnectionError::Disconnected) => {}
+            other => panic!("expected a disconnection, got {:?}", other),
+        }
+
+        // and restarts
+        let compositor = MockCompositor::start(&socket, GLOBALS);
+        let old_env = env.clone();
+        let mut rebuilt = Vec::new();
+        let (env, _display, mut queue) = env
+            .reconnect_with(connect, init, |env| {
+                let compositor = env.require_global::<wl_compositor::WlCompositor>();
+                rebuilt.push(compositor.create_surface());
+            })
+            .unwrap();
+        assert_eq!(rebuilt.len(), 1);
+        env.roundtrip(&mut queue).unwrap();
+        let new_compositor = env.require_global::<wl_compositor::WlCompositor>();
+        assert!(!env.is_defunct());
+        assert!(!new_compositor.as_ref().equals(old_compositor.as_ref()));
+
+        // the old environment cannot be used anymore
+        assert!(old_env.is_defunct());
+        assert!(old_env
+            .get_global::<wl_compositor::WlCompositor>()
+            .is_none());
+        assert_eq!(
+            old_env
+                .try_get_global::<wl_compositor::WlCompositor>()
+                .err(),
+            Some(GlobalError::Removed("wl_compositor"))
+        );
+        assert_eq!(old_env.capabilities(), Capabilities::default());
+        let mut old_queue = display.create_event_queue();
+        assert_disconnected(old_env.roundtrip(&mut old_queue));
+
+        // the surface of the rebuild callback went to the new compositor
+        let requests = compositor.kill();
+        assert_eq!(count(&requests, new_compositor.as_ref(), 0), 1);
+        let _ = std::fs::remove_file(&socket);
+    }
+}
@@ -0,0 +1,49 @@
+// Descriptions of the error codes of the core and common protocols
+
+/// The description of an error code of an interface, if known
+pub(crate) fn describe(interface: &str, code: u32) -> Option<&'static str> {
+    let description = match (interface, code) {
+        ("wl_display", 0) => "invalid object",
+        ("wl_display", 1) => "invalid method or arguments",
+        ("wl_display", 2) => "the compositor is out of memory",
+        ("wl_display", 3) => "implementation error in the compositor",
+        ("wl_shm", 0) => "invalid buffer format",
+        ("wl_shm", 1) => "invalid buffer size or stride",
+        ("wl_shm", 2) => "invalid file descriptor for the memory pool",
+        ("wl_surface", 0) => "invalid buffer scale",
+        ("wl_surface", 1) => "invalid buffer transform",
+        ("wl_surface", 2) => "buffer size is not a multiple of the buffer scale",
+        ("wl_surface", 3) => "invalid buffer offset",
+        ("wl_shell", 0) => "the surface already has another role",
+        ("wl_subcompositor", 0) => "the surface cannot be made a subsurface of this parent",
+        ("wl_subsurface", 0) => "the parent surface was destroyed or is invalid",
+        ("wl_seat", 0) => "the seat does not have this capability",
+        ("wl_pointer", 0) => "the cursor surface already has another role",
+        ("wl_data_device", 0) => "the drag icon surface already has another role",
+        ("wl_data_source", 0) => "invalid drag'n'drop action mask",
+        ("wl_data_source", 1) => "the data source was already used",
+        ("wl_data_offer", 0) => "the drag'n'drop offer cannot be finished yet",
+        ("wl_data_offer", 1) => "invalid drag'n'drop action mask",
+        ("wl_data_offer", 2) => "invalid drag'n'drop action",
+        ("wl_data_offer", 3) => "the offer is not a drag'n'drop offer",
+        ("xdg_wm_base", 0) => "the surface already has another role",
+        ("xdg_wm_base", 1) => "xdg_wm_base was destroyed before its surfaces",
+        ("xdg_wm_base", 2) => "the popup is not the topmost one",
+        ("xdg_wm_base", 3) => "invalid popup parent",
+        ("xdg_wm_base", 4) => "invalid surface state",
+        ("xdg_wm_base", 5) => "incomplete positioner",
+        ("xdg_surface", 1) => "the xdg_surface has no role yet",
+        ("xdg_surface", 2) => "the xdg_surface already has a role",
+        ("xdg_surface", 3) => "a buffer was attached before the first configure",
+        ("xdg_positioner", 0) => "invalid size or anchor rectangle",
+        ("xdg_popup", 0) => "invalid popup grab",
+        ("zwlr_layer_shell_v1", 0) => "the surface already has another role",
+        ("zwlr_layer_shell_v1", 1) => "invalid layer",
+        ("zwlr_layer_shell_v1", 2) => "a buffer was attached before the first configure",
+        ("zwlr_layer_surface_v1", 0) => "invalid surface state",
+        ("zwlr_layer_surface_v1", 1) => "invalid size",
+        ("zwlr_layer_surface_v1", 2) => "invalid anchor",
+        _ => return None,
+    };
+    Some(description)
+}
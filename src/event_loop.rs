@@ -1,11 +1,23 @@
-use std::io;
+use std::{
+    cell::RefCell,
+    io,
+    rc::{Rc, Weak},
+};
 
 use calloop::{
     generic::{Fd, Generic},
     EventSource, InsertError, Interest, LoopHandle, Mode, Source,
 };
 
-use wayland_client::EventQueue;
+use wayland_client::{Display, EventQueue};
+
+use crate::environment::with_protocol_error;
+
+thread_local! {
+    // the connections of the live `WaylandSource`s of this thread, flushed
+    // by the other event sources of SCTK once they invoked their callbacks
+    static CONNECTIONS: RefCell<Vec<Weak<Display>>> = RefCell::default();
+}
 
 /// An adapter to insert a Wayland `EventQueue` into a calloop event loop
 ///
@@ -17,18 +29,59 @@ use wayland_client::EventQueue;
 /// If you don't use orphan events, the `quick_insert` method will firectly
 /// insert the source into a provided `LoopHandle` with an adapter which will panic
 /// whenever an oprhan event is encountered.
+///
+/// Once the events are dispatched, the requests they generated are flushed to the
+/// compositor. If its socket is full, the flush is resumed as soon as it becomes
+/// writable again, rather than waiting for the next events from the compositor. The
+/// other event sources of SCTK (like the keyboard repetition or the pipe transfers)
+/// flush the connection too after invoking their callbacks.
+///
+/// Events can also be queued without the connection becoming readable, typically if
+/// the callback of another event source does a roundtrip. Call
+/// [`dispatch_pending`](#method.dispatch_pending) before each dispatch of your event
+/// loop if that can happen in your app.
+///
+/// If the connection fails because of a protocol error, the error returned by the
+/// source carries a [`ConnectionError`](environment/enum.ConnectionError.html) describing
+/// it, with the interface of the faulty object.
 pub struct WaylandSource {
     queue: EventQueue,
+    display: Rc<Display>,
     fd: Generic<Fd>,
+    // a duplicate of the connection fd, polled for writability after a flush failed
+    // because the socket was full
+    write_fd: Generic<Fd>,
 }
 
 impl WaylandSource {
     /// Wrap an `EventQueue` as a `WaylandSource`.
     pub fn new(queue: EventQueue) -> WaylandSource {
-        let fd = queue.display().get_connection_fd();
+        let display = Rc::new(queue.display().clone());
+        let fd = display.get_connection_fd();
+        // epoll does not accept the same fd twice, the writability is polled on a
+        // duplicate. It is edge-triggered, so that a writable socket does not wake up
+        // the event loop continuously.
+        let write_fd = match nix::unistd::dup(fd) {
+            Ok(write_fd) => write_fd,
+            Err(err) => {
+                log::warn!(
+                    "[SCTK] Failed to duplicate the Wayland connection fd, \
+                    flushes will only be resumed on new events: {}",
+                    err
+                );
+                -1
+            }
+        };
+        CONNECTIONS.with(|connections| {
+            let mut connections = connections.borrow_mut();
+            connections.retain(|display| display.upgrade().is_some());
+            connections.push(Rc::downgrade(&display));
+        });
         WaylandSource {
             queue,
+            display,
             fd: Generic::from_fd(fd, Interest::Readable, Mode::Level),
+            write_fd: Generic::from_fd(write_fd, Interest::Writable, Mode::Edge),
         }
     }
 
@@ -48,6 +101,63 @@ impl WaylandSource {
             })
         })
     }
+
+    /// Dispatch the events already queued and flush the connection
+    ///
+    /// This does not read the connection, but processes the events that were queued
+    /// without it becoming readable, for example by a roundtrip done in the callback
+    /// of another event source. The orphan events are given to the `fallback` closure.
+    ///
+    /// Once this source is inserted in your event loop, you can access it with
+    /// `LoopHandle::with_source`:
+    ///
+    /// ```no_run
+    /// # use smithay_client_toolkit::{reexports::calloop, WaylandSource};
+    /// # fn run(mut event_loop: calloop::EventLoop<()>, source: calloop::Source<WaylandSource>) {
+    /// loop {
+    ///     event_loop
+    ///         .handle()
+    ///         .with_source(&source, |source| source.dispatch_pending(&mut (), |_, _, _| {}))
+    ///         .unwrap();
+    ///     event_loop.dispatch(None, &mut ()).unwrap();
+    /// }
+    /// # }
+    /// ```
+    pub fn dispatch_pending<T, F>(&mut self, data: &mut T, fallback: F) -> io::Result<u32>
+    where
+        T: std::any::Any,
+        F: FnMut(
+            wayland_client::RawEvent,
+            wayland_client::Main<wayland_client::AnonymousObject>,
+            wayland_client::DispatchData,
+        ),
+    {
+        let ret = self
+            .queue
+            .dispatch_pending(data, fallback)
+            .map_err(|err| with_protocol_error(&self.display, err))?;
+        self.flush()?;
+        Ok(ret)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        match self.display.flush() {
+            // WouldBlock error means the compositor could not process all our messages
+            // quickly. Either it is slowed down or we are a spammer. The flush is resumed
+            // once the socket is writable again.
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(with_protocol_error(&self.display, err)),
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+impl Drop for WaylandSource {
+    fn drop(&mut self) {
+        if self.write_fd.file.0 >= 0 {
+            let _ = nix::unistd::close(self.write_fd.file.0);
+        }
+    }
 }
 
 impl EventSource for WaylandSource {
@@ -57,56 +167,54 @@ impl EventSource for WaylandSource {
 
     fn process_events<F>(
         &mut self,
-        _: calloop::Readiness,
+        readiness: calloop::Readiness,
         _: calloop::Token,
         mut callback: F,
     ) -> std::io::Result<()>
     where
         F: FnMut((), &mut EventQueue) -> std::io::Result<u32>,
     {
-        // in case of readiness of the wayland socket we do the following in a loop, until nothing
-        // more can be read:
-        loop {
-            // 1. read events from the socket if any are available
-            if let Some(guard) = self.queue.prepare_read() {
-                // might be None if some other thread read events before us, concurently
-                if let Err(e) = guard.read_events() {
-                    if e.kind() != io::ErrorKind::WouldBlock {
-                        return Err(e);
+        // nothing to read if the socket only became writable
+        if readiness.readable || readiness.error {
+            // in case of readiness of the wayland socket we do the following in a loop, until
+            // nothing more can be read:
+            loop {
+                // 1. read events from the socket if any are available
+                if let Some(guard) = self.queue.prepare_read() {
+                    // might be None if some other thread read events before us, concurently
+                    if let Err(e) = guard.read_events() {
+                        if e.kind() != io::ErrorKind::WouldBlock {
+                            return Err(with_protocol_error(&self.display, e));
+                        }
                     }
                 }
-            }
-            // 2. dispatch any pending event in the queue
-            // propagate orphan events to the user
-            let ret = callback((), &mut self.queue);
-            match ret {
-                Ok(0) => {
-                    // no events were dispatched even after reading the socket,
-                    // nothing more to do, stop here
-                    break;
-                }
-                Ok(_) => {}
-                Err(e) => {
-                    // in case of error, forward it and fast-exit
-                    return Err(e);
+                // 2. dispatch any pending event in the queue
+                // propagate orphan events to the user
+                let ret = callback((), &mut self.queue);
+                match ret {
+                    Ok(0) => {
+                        // no events were dispatched even after reading the socket,
+                        // nothing more to do, stop here
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // in case of error, forward it and fast-exit
+                        return Err(with_protocol_error(&self.display, e));
+                    }
                 }
             }
         }
         // 3. Once dispatching is finished, flush the responses to the compositor
-        if let Err(e) = self.queue.display().flush() {
-            if e.kind() != io::ErrorKind::WouldBlock {
-                // in case of error, forward it and fast-exit
-                return Err(e);
-            }
-            // WouldBlock error means the compositor could not process all our messages
-            // quickly. Either it is slowed down or we are a spammer.
-            // Should not really happen, if it does we do nothing and will flush again later
-        }
-        Ok(())
+        self.flush()
     }
 
     fn register(&mut self, poll: &mut calloop::Poll, token: calloop::Token) -> std::io::Result<()> {
-        self.fd.register(poll, token)
+        self.fd.register(poll, token)?;
+        if self.write_fd.file.0 >= 0 {
+            self.write_fd.register(poll, token)?;
+        }
+        Ok(())
     }
 
     fn reregister(
@@ -114,10 +222,40 @@ impl EventSource for WaylandSource {
         poll: &mut calloop::Poll,
         token: calloop::Token,
     ) -> std::io::Result<()> {
-        self.fd.reregister(poll, token)
+        self.fd.reregister(poll, token)?;
+        if self.write_fd.file.0 >= 0 {
+            self.write_fd.reregister(poll, token)?;
+        }
+        Ok(())
     }
 
     fn unregister(&mut self, poll: &mut calloop::Poll) -> std::io::Result<()> {
-        self.fd.unregister(poll)
+        self.fd.unregister(poll)?;
+        if self.write_fd.file.0 >= 0 {
+            self.write_fd.unregister(poll)?;
+        }
+        Ok(())
     }
 }
+
+/// Flush the connections of the `WaylandSource`s of this thread
+///
+/// This is used by the other event sources of SCTK once they invoked their callbacks,
+/// so that the requests sent by these callbacks do not wait for the next Wayland events.
+pub(crate) fn flush_connections() {
+    CONNECTIONS.with(|connections| {
+        connections
+            .borrow_mut()
+            .retain(|display| match display.upgrade() {
+                Some(display) => {
+                    if let Err(err) = display.flush() {
+                        if err.kind() != io::ErrorKind::WouldBlock {
+                            log::warn!("[SCTK] Failed to flush the Wayland connection: {}", err);
+                        }
+                    }
+                    true
+                }
+                None => false,
+            })
+    });
+}
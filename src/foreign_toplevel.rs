@@ -0,0 +1,479 @@
+//! Helpers to list and control the toplevels of all clients with `zwlr_foreign_toplevel_manager_v1`
+//!
+//! This protocol is meant for privileged clients like taskbars and docks. The
+//! [`ForeignToplevelHandler`](struct.ForeignToplevelHandler.html) tracks the toplevels of
+//! all the clients of the compositor, with their title, app id, state and outputs, and you can
+//! control them with the methods of [`ForeignToplevel`](struct.ForeignToplevel.html).
+//!
+//! It is not part of the [`default_environment!`](../macro.default_environment.html), you need to
+//! add the handler to your environment, and implement
+//! [`ForeignToplevelHandling`](trait.ForeignToplevelHandling.html) by delegating to it:
+//!
+//! ```no_run
+//! # use smithay_client_toolkit::default_environment;
+//! use smithay_client_toolkit::foreign_toplevel::{
+//!     ForeignToplevel, ForeignToplevelHandler, ForeignToplevelHandling, ToplevelEvent,
+//!     ToplevelListener,
+//! };
+//! use smithay_client_toolkit::reexports::client::DispatchData;
+//! use smithay_client_toolkit::reexports::protocols::wlr::unstable::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1;
+//!
+//! default_environment!(MyEnv,
+//!     fields=[toplevels: ForeignToplevelHandler],
+//!     singles=[ZwlrForeignToplevelManagerV1 => toplevels],
+//! );
+//!
+//! impl ForeignToplevelHandling for MyEnv {
+//!     fn listen<F: FnMut(ToplevelEvent, DispatchData) + 'static>(&mut self, f: F) -> ToplevelListener {
+//!         self.toplevels.listen(f)
+//!     }
+//!     fn get_all_toplevels(&self) -> Vec<ForeignToplevel> {
+//!         self.toplevels.get_all_toplevels()
+//!     }
+//!     fn is_finished(&self) -> bool {
+//!         self.toplevels.is_finished()
+//!     }
+//! }
+//! ```
+//!
+//! The `ext_foreign_toplevel_list_v1` protocol is not supported yet.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::{self, Rc},
+    sync::Mutex,
+};
+
+use wayland_client::{
+    protocol::{wl_output, wl_registry, wl_seat, wl_surface},
+    Attached, DispatchData, Main,
+};
+
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+use crate::environment::{Environment, GlobalHandler};
+
+/// The information about a toplevel
+#[derive(Clone, Default)]
+pub struct ToplevelInfo {
+    /// The title of the toplevel
+    pub title: String,
+    /// The app id of the toplevel
+    pub app_id: String,
+    /// Whether the toplevel is maximized
+    pub maximized: bool,
+    /// Whether the toplevel is minimized
+    pub minimized: bool,
+    /// Whether the toplevel is active
+    pub activated: bool,
+    /// Whether the toplevel is fullscreen
+    pub fullscreen: bool,
+    /// The outputs the toplevel is visible on
+    pub outputs: Vec<wl_output::WlOutput>,
+    /// Whether the toplevel was closed
+    ///
+    /// Once closed, the toplevel is forgotten by the handler and can no longer be controlled.
+    pub closed: bool,
+}
+
+struct ToplevelData {
+    // the events received since the last `done`
+    pending: ToplevelInfo,
+    // `None` until the first `done`
+    current: Option<ToplevelInfo>,
+}
+
+/// A toplevel of any client of the compositor
+#[derive(Clone, PartialEq)]
+pub struct ForeignToplevel {
+    handle: ZwlrForeignToplevelHandleV1,
+}
+
+impl ForeignToplevel {
+    /// Access the information about this toplevel
+    ///
+    /// Returns `None` if the compositor has not sent it yet.
+    pub fn with_info<T, F: FnOnce(&ToplevelInfo) -> T>(&self, f: F) -> Option<T> {
+        let data = self
+            .handle
+            .as_ref()
+            .user_data()
+            .get::<Mutex<ToplevelData>>()?;
+        let data = data.lock().unwrap();
+        data.current.as_ref().map(f)
+    }
+
+    /// Request the toplevel to be activated, on behalf of this seat
+    pub fn activate(&self, seat: &wl_seat::WlSeat) {
+        if self.handle.as_ref().is_alive() {
+            self.handle.activate(seat);
+        }
+    }
+
+    /// Request the toplevel to be maximized or unmaximized
+    pub fn set_maximized(&self, maximized: bool) {
+        if !self.handle.as_ref().is_alive() {
+            return;
+        }
+        if maximized {
+            self.handle.set_maximized();
+        } else {
+            self.handle.unset_maximized();
+        }
+    }
+
+    /// Request the toplevel to be minimized or unminimized
+    pub fn set_minimized(&self, minimized: bool) {
+        if !self.handle.as_ref().is_alive() {
+            return;
+        }
+        if minimized {
+            self.handle.set_minimized();
+        } else {
+            self.handle.unset_minimized();
+        }
+    }
+
+    /// Request the toplevel to be fullscreen, on this output or one chosen by the compositor
+    ///
+    /// Does nothing if the compositor does not support version 2 of the protocol.
+    pub fn set_fullscreen(&self, output: Option<&wl_output::WlOutput>) {
+        if self.handle.as_ref().is_alive() && self.handle.as_ref().version() >= 2 {
+            self.handle.set_fullscreen(output);
+        }
+    }
+
+    /// Request the toplevel to leave fullscreen
+    ///
+    /// Does nothing if the compositor does not support version 2 of the protocol.
+    pub fn unset_fullscreen(&self) {
+        if self.handle.as_ref().is_alive() && self.handle.as_ref().version() >= 2 {
+            self.handle.unset_fullscreen();
+        }
+    }
+
+    /// Request the toplevel to be closed
+    pub fn close(&self) {
+        if self.handle.as_ref().is_alive() {
+            self.handle.close();
+        }
+    }
+
+    /// Tell the compositor where this toplevel is represented on one of your surfaces
+    ///
+    /// This is typically the rectangle of its taskbar entry, which the compositor can use
+    /// for its minimizing animation.
+    pub fn set_rectangle(
+        &self,
+        surface: &wl_surface::WlSurface,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) {
+        if self.handle.as_ref().is_alive() {
+            self.handle.set_rectangle(surface, x, y, width, height);
+        }
+    }
+
+    /// Access the underlying protocol object
+    pub fn handle(&self) -> &ZwlrForeignToplevelHandleV1 {
+        &self.handle
+    }
+}
+
+/// An event about the toplevels
+pub enum ToplevelEvent<'a> {
+    /// A new toplevel was created
+    New {
+        /// The toplevel
+        toplevel: ForeignToplevel,
+        /// Its information
+        info: &'a ToplevelInfo,
+    },
+    /// The information about a toplevel changed
+    Changed {
+        /// The toplevel
+        toplevel: ForeignToplevel,
+        /// Its new information
+        info: &'a ToplevelInfo,
+    },
+    /// A toplevel was closed
+    Closed {
+        /// The toplevel
+        toplevel: ForeignToplevel,
+        /// Its last information
+        info: &'a ToplevelInfo,
+    },
+    /// The compositor stopped sending toplevel events
+    ///
+    /// No new toplevel will be reported afterwards.
+    Finished,
+}
+
+type ToplevelCallback = dyn FnMut(ToplevelEvent, DispatchData) + 'static;
+
+type Listeners = Rc<RefCell<Vec<rc::Weak<RefCell<ToplevelCallback>>>>>;
+
+/// A handle to a toplevel listener callback
+///
+/// Dropping it disables the associated callback and frees the closure.
+pub struct ToplevelListener {
+    _cb: Rc<RefCell<ToplevelCallback>>,
+}
+
+/// A handler for the `zwlr_foreign_toplevel_manager_v1` global
+///
+/// It binds the global as soon as it is advertised, and tracks the toplevels it reports.
+pub struct ForeignToplevelHandler {
+    manager: Option<Attached<ZwlrForeignToplevelManagerV1>>,
+    toplevels: Rc<RefCell<Vec<ForeignToplevel>>>,
+    listeners: Listeners,
+    finished: Rc<Cell<bool>>,
+}
+
+impl ForeignToplevelHandler {
+    /// Create a new handler
+    pub fn new() -> ForeignToplevelHandler {
+        ForeignToplevelHandler {
+            manager: None,
+            toplevels: Rc::new(RefCell::new(Vec::new())),
+            listeners: Rc::new(RefCell::new(Vec::new())),
+            finished: Rc::new(Cell::new(false)),
+        }
+    }
+}
+
+impl GlobalHandler<ZwlrForeignToplevelManagerV1> for ForeignToplevelHandler {
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        version: u32,
+        _: DispatchData,
+    ) {
+        if self.manager.is_some() {
+            return;
+        }
+        // We currently support the manager up to version 2
+        let version = std::cmp::min(version, 2);
+        let manager = registry.bind::<ZwlrForeignToplevelManagerV1>(version, id);
+        let toplevels = self.toplevels.clone();
+        let listeners = self.listeners.clone();
+        let finished = self.finished.clone();
+        manager.quick_assign(move |_, event, ddata| {
+            use self::zwlr_foreign_toplevel_manager_v1::Event;
+            match event {
+                Event::Toplevel { toplevel } => {
+                    init_toplevel(toplevel, &toplevels, &listeners);
+                }
+                Event::Finished => {
+                    // the compositor destroys the manager after this event
+                    finished.set(true);
+                    notify(&listeners, ToplevelEvent::Finished, ddata);
+                }
+                _ => unreachable!(),
+            }
+        });
+        self.manager = Some((*manager).clone());
+    }
+    fn get(&self) -> Option<Attached<ZwlrForeignToplevelManagerV1>> {
+        if self.finished.get() {
+            None
+        } else {
+            self.manager.clone()
+        }
+    }
+}
+
+fn init_toplevel(
+    handle: Main<ZwlrForeignToplevelHandleV1>,
+    toplevels: &Rc<RefCell<Vec<ForeignToplevel>>>,
+    listeners: &Listeners,
+) {
+    handle.as_ref().user_data().set_threadsafe(|| {
+        Mutex::new(ToplevelData {
+            pending: ToplevelInfo::default(),
+            current: None,
+        })
+    });
+    let my_toplevels = toplevels.clone();
+    let listeners = listeners.clone();
+    handle.quick_assign(move |handle, event, ddata| {
+        process_toplevel_event(handle.detach(), event, ddata, &my_toplevels, &listeners)
+    });
+    toplevels.borrow_mut().push(ForeignToplevel {
+        handle: handle.detach(),
+    });
+}
+
+fn process_toplevel_event(
+    handle: ZwlrForeignToplevelHandleV1,
+    event: zwlr_foreign_toplevel_handle_v1::Event,
+    ddata: DispatchData,
+    toplevels: &RefCell<Vec<ForeignToplevel>>,
+    listeners: &Listeners,
+) {
+    use self::zwlr_foreign_toplevel_handle_v1::{Event, State};
+    let data = handle
+        .as_ref()
+        .user_data()
+        .get::<Mutex<ToplevelData>>()
+        .expect("SCTK: foreign toplevel has invalid UserData");
+    let mut data = data.lock().unwrap();
+    let toplevel = ForeignToplevel {
+        handle: handle.clone(),
+    };
+    match event {
+        Event::Title { title } => data.pending.title = title,
+        Event::AppId { app_id } => data.pending.app_id = app_id,
+        Event::OutputEnter { output } => data.pending.outputs.push(output),
+        Event::OutputLeave { output } => data.pending.outputs.retain(|o| *o != output),
+        Event::State { state } => {
+            let pending = &mut data.pending;
+            pending.maximized = false;
+            pending.minimized = false;
+            pending.activated = false;
+            pending.fullscreen = false;
+            for chunk in state.chunks_exact(4) {
+                let raw = u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                match State::from_raw(raw) {
+                    Some(State::Maximized) => pending.maximized = true,
+                    Some(State::Minimized) => pending.minimized = true,
+                    Some(State::Activated) => pending.activated = true,
+                    Some(State::Fullscreen) => pending.fullscreen = true,
+                    _ => {}
+                }
+            }
+        }
+        Event::Done => {
+            // the changes are published all at once
+            let info = data.pending.clone();
+            let is_new = data.current.is_none();
+            data.current = Some(info.clone());
+            drop(data);
+            let event = if is_new {
+                ToplevelEvent::New {
+                    toplevel,
+                    info: &info,
+                }
+            } else {
+                ToplevelEvent::Changed {
+                    toplevel,
+                    info: &info,
+                }
+            };
+            notify(listeners, event, ddata);
+        }
+        Event::Closed => {
+            let mut info = data.current.take().unwrap_or_else(|| data.pending.clone());
+            info.closed = true;
+            data.current = Some(info.clone());
+            drop(data);
+            toplevels.borrow_mut().retain(|t| t.handle != handle);
+            handle.destroy();
+            notify(
+                listeners,
+                ToplevelEvent::Closed {
+                    toplevel,
+                    info: &info,
+                },
+                ddata,
+            );
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn notify(listeners: &Listeners, event: ToplevelEvent, mut ddata: DispatchData) {
+    // the event is given to each listener, rebuild it for each of them
+    let callbacks = listeners
+        .borrow_mut()
+        .iter()
+        .filter_map(rc::Weak::upgrade)
+        .collect::<Vec<_>>();
+    listeners
+        .borrow_mut()
+        .retain(|weak| rc::Weak::upgrade(weak).is_some());
+    for cb in callbacks {
+        let event = match event {
+            ToplevelEvent::New { ref toplevel, info } => ToplevelEvent::New {
+                toplevel: toplevel.clone(),
+                info,
+            },
+            ToplevelEvent::Changed { ref toplevel, info } => ToplevelEvent::Changed {
+                toplevel: toplevel.clone(),
+                info,
+            },
+            ToplevelEvent::Closed { ref toplevel, info } => ToplevelEvent::Closed {
+                toplevel: toplevel.clone(),
+                info,
+            },
+            ToplevelEvent::Finished => ToplevelEvent::Finished,
+        };
+        (*cb.borrow_mut())(event, ddata.reborrow());
+    }
+}
+
+/// An interface trait to forward the foreign toplevel handler capability
+///
+/// You need to implement this trait for your environment struct, by delegating it
+/// to its `ForeignToplevelHandler` field in order to get the associated methods on
+/// your [`Environment`](../environment/struct.Environment.html).
+pub trait ForeignToplevelHandling {
+    /// Insert a listener for the toplevel events
+    fn listen<F: FnMut(ToplevelEvent, DispatchData) + 'static>(&mut self, f: F)
+        -> ToplevelListener;
+    /// The toplevels currently known
+    fn get_all_toplevels(&self) -> Vec<ForeignToplevel>;
+    /// Whether the compositor stopped sending toplevel events
+    fn is_finished(&self) -> bool;
+}
+
+impl ForeignToplevelHandling for ForeignToplevelHandler {
+    fn listen<F: FnMut(ToplevelEvent, DispatchData) + 'static>(
+        &mut self,
+        f: F,
+    ) -> ToplevelListener {
+        let rc = Rc::new(RefCell::new(f)) as Rc<_>;
+        self.listeners.borrow_mut().push(Rc::downgrade(&rc));
+        ToplevelListener { _cb: rc }
+    }
+    fn get_all_toplevels(&self) -> Vec<ForeignToplevel> {
+        self.toplevels.borrow().clone()
+    }
+    fn is_finished(&self) -> bool {
+        self.finished.get()
+    }
+}
+
+impl<E: ForeignToplevelHandling> Environment<E> {
+    /// Insert a new listener for the toplevels
+    ///
+    /// The provided closure is invoked when a toplevel is created, changed or closed, and
+    /// when the compositor stops sending toplevel events.
+    ///
+    /// The toplevels that already exist when the listener is inserted are not reported to
+    /// it, you can get them with `get_all_toplevels()`.
+    pub fn listen_for_toplevels<F: FnMut(ToplevelEvent, DispatchData) + 'static>(
+        &self,
+        f: F,
+    ) -> ToplevelListener {
+        self.with_inner(move |inner| ForeignToplevelHandling::listen(inner, f))
+    }
+
+    /// The toplevels currently known
+    pub fn get_all_toplevels(&self) -> Vec<ForeignToplevel> {
+        self.with_inner(|inner| inner.get_all_toplevels())
+    }
+
+    /// Whether the compositor stopped sending toplevel events
+    ///
+    /// Once this happens, the toplevel tracking is defunct: no new toplevel is reported.
+    pub fn foreign_toplevels_finished(&self) -> bool {
+        self.with_inner(|inner| inner.is_finished())
+    }
+}
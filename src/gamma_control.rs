@@ -0,0 +1,409 @@
+//! Helpers to change the gamma of outputs with `zwlr_gamma_control_manager_v1`
+//!
+//! A [`GammaControl`](struct.GammaControl.html) sets the gamma ramps of an output, and a
+//! [`GammaSession`](struct.GammaSession.html) sets them on all outputs, including the ones
+//! plugged while it is running. This is what night-light tools need, and
+//! [`GammaRamp::from_temperature`](struct.GammaRamp.html#method.from_temperature) computes the
+//! ramps of a color temperature for them.
+//!
+//! Only one client can control the gamma of an output at a time, and the original gamma is
+//! restored once the control is dropped.
+//!
+//! This protocol is not part of the [`default_environment!`](../macro.default_environment.html),
+//! you need to add the `zwlr_gamma_control_manager_v1` global to your environment, for example as
+//! a [`SimpleGlobal`](../environment/struct.SimpleGlobal.html).
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    rc::{Rc, Weak},
+};
+
+use wayland_client::{protocol::wl_output, DispatchData};
+
+use wayland_protocols::wlr::unstable::gamma_control::v1::client::{
+    zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
+    zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler, MultiGlobalHandler};
+use crate::output::{OutputHandling, OutputStatusListener};
+
+/// The gamma ramps of an output
+///
+/// Each channel maps the input values of the output, evenly spread from black to full
+/// intensity, to the values it displays.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GammaRamp {
+    /// The ramp of the red channel
+    pub red: Vec<u16>,
+    /// The ramp of the green channel
+    pub green: Vec<u16>,
+    /// The ramp of the blue channel
+    pub blue: Vec<u16>,
+}
+
+impl GammaRamp {
+    /// The ramps displaying the input values unchanged
+    pub fn identity(size: u32) -> GammaRamp {
+        GammaRamp::with_factors(size, 1.0, 1.0, 1.0)
+    }
+
+    /// The ramps tinting the output with a color temperature, in Kelvin
+    ///
+    /// A temperature of 6500K leaves the output nearly unchanged, lower temperatures
+    /// make it warmer. The temperature is clamped between 1000K and 40000K.
+    pub fn from_temperature(size: u32, kelvin: u32) -> GammaRamp {
+        let (r, g, b) = temperature_to_rgb(kelvin);
+        GammaRamp::with_factors(size, r, g, b)
+    }
+
+    fn with_factors(size: u32, r: f64, g: f64, b: f64) -> GammaRamp {
+        let channel = |factor: f64| {
+            (0..size)
+                .map(|i| {
+                    let input = if size > 1 {
+                        f64::from(i) / f64::from(size - 1)
+                    } else {
+                        1.0
+                    };
+                    (input * factor * f64::from(std::u16::MAX)).round() as u16
+                })
+                .collect()
+        };
+        GammaRamp {
+            red: channel(r),
+            green: channel(g),
+            blue: channel(b),
+        }
+    }
+
+    fn size(&self) -> Option<u32> {
+        if self.red.len() == self.green.len() && self.red.len() == self.blue.len() {
+            Some(self.red.len() as u32)
+        } else {
+            None
+        }
+    }
+
+    // the three channels, one after the other, in native endianness
+    fn to_bytes(&self) -> Vec<u8> {
+        self.red
+            .iter()
+            .chain(&self.green)
+            .chain(&self.blue)
+            .flat_map(|value| value.to_ne_bytes().to_vec())
+            .collect()
+    }
+}
+
+// An approximation of the color of a black body, scaled to the 0..1 range
+fn temperature_to_rgb(kelvin: u32) -> (f64, f64, f64) {
+    let t = f64::from(kelvin.max(1000).min(40000)) / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2)
+    };
+    let g = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+    let scale = |value: f64| (value / 255.0).max(0.0).min(1.0);
+    (scale(r), scale(g), scale(b))
+}
+
+/// Possible errors when setting the gamma of an output
+#[derive(Debug)]
+pub enum GammaError {
+    /// The compositor has not sent the size of the gamma ramps yet
+    NotReady,
+    /// The ramps do not have the size expected by the compositor
+    SizeMismatch {
+        /// The size expected by the compositor
+        expected: u32,
+    },
+    /// The control failed, see [`GammaEvent::Failed`](enum.GammaEvent.html#variant.Failed)
+    Failed,
+    /// Writing the ramps failed
+    Io(io::Error),
+}
+
+impl fmt::Display for GammaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GammaError::NotReady => write!(f, "the size of the gamma ramps is not known yet"),
+            GammaError::SizeMismatch { expected } => {
+                write!(f, "the gamma ramps must have {} entries", expected)
+            }
+            GammaError::Failed => write!(f, "the gamma control failed"),
+            GammaError::Io(err) => write!(f, "failed to write the gamma ramps: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GammaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GammaError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GammaError {
+    fn from(err: io::Error) -> GammaError {
+        GammaError::Io(err)
+    }
+}
+
+/// An event of a gamma control
+pub enum GammaEvent {
+    /// The compositor sent the size of the gamma ramps, they can now be set
+    Ready {
+        /// The number of entries of each ramp
+        size: u32,
+    },
+    /// The control failed
+    ///
+    /// Another client controls the gamma of the output, the output was removed, or the
+    /// compositor rejected the ramps. The control can no longer be used.
+    Failed,
+}
+
+struct ControlInner {
+    control: ZwlrGammaControlV1,
+    size: Cell<Option<u32>>,
+    failed: Cell<bool>,
+}
+
+impl Drop for ControlInner {
+    fn drop(&mut self) {
+        if self.control.as_ref().is_alive() {
+            self.control.destroy();
+        }
+    }
+}
+
+/// A control of the gamma of an output
+///
+/// Dropping it restores the original gamma of the output.
+pub struct GammaControl {
+    inner: Rc<ControlInner>,
+}
+
+impl GammaControl {
+    /// The number of entries of each gamma ramp
+    ///
+    /// Returns `None` until the compositor sends it.
+    pub fn gamma_size(&self) -> Option<u32> {
+        self.inner.size.get()
+    }
+
+    /// Set the gamma ramps of the output
+    pub fn set_gamma(&self, ramp: &GammaRamp) -> Result<(), GammaError> {
+        if self.inner.failed.get() {
+            return Err(GammaError::Failed);
+        }
+        let expected = self.inner.size.get().ok_or(GammaError::NotReady)?;
+        if ramp.size() != Some(expected) {
+            return Err(GammaError::SizeMismatch { expected });
+        }
+        let mut file = unsafe { File::from_raw_fd(crate::shm::create_shm_fd()?) };
+        file.write_all(&ramp.to_bytes())?;
+        file.seek(SeekFrom::Start(0))?;
+        // the file is closed once the request is sent
+        self.inner.control.set_gamma(file.as_raw_fd());
+        Ok(())
+    }
+}
+
+type ControlCallback = dyn FnMut(GammaEvent, &GammaControl, DispatchData);
+
+fn create_control(
+    manager: &ZwlrGammaControlManagerV1,
+    output: &wl_output::WlOutput,
+    mut callback: Box<ControlCallback>,
+) -> GammaControl {
+    let control = manager.get_gamma_control(output);
+    let inner = Rc::new(ControlInner {
+        control: control.detach(),
+        size: Cell::new(None),
+        failed: Cell::new(false),
+    });
+    let weak = Rc::downgrade(&inner);
+    control.quick_assign(move |_, event, ddata| {
+        use self::zwlr_gamma_control_v1::Event;
+        let inner = match weak.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let event = match event {
+            Event::GammaSize { size } => {
+                inner.size.set(Some(size));
+                GammaEvent::Ready { size }
+            }
+            Event::Failed => {
+                inner.failed.set(true);
+                GammaEvent::Failed
+            }
+            _ => unreachable!(),
+        };
+        callback(event, &GammaControl { inner }, ddata);
+    });
+    GammaControl { inner }
+}
+
+type RampFn = dyn FnMut(&wl_output::WlOutput, u32) -> GammaRamp;
+
+struct SessionInner {
+    manager: ZwlrGammaControlManagerV1,
+    ramp: Box<RampFn>,
+    controls: Vec<(wl_output::WlOutput, GammaControl)>,
+}
+
+impl SessionInner {
+    fn apply(&mut self, output: &wl_output::WlOutput, control: &GammaControl) {
+        let size = match control.gamma_size() {
+            Some(size) => size,
+            None => return,
+        };
+        let ramp = (self.ramp)(output, size);
+        if let Err(err) = control.set_gamma(&ramp) {
+            log::warn!("[SCTK] Failed to set the gamma of an output: {}", err);
+        }
+    }
+}
+
+fn add_output(inner: &Rc<RefCell<SessionInner>>, output: &wl_output::WlOutput) {
+    let weak: Weak<RefCell<SessionInner>> = Rc::downgrade(inner);
+    let my_output = output.clone();
+    let mut guard = inner.borrow_mut();
+    let control = create_control(
+        &guard.manager,
+        output,
+        Box::new(move |event, control, _| {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let mut inner = inner.borrow_mut();
+            match event {
+                GammaEvent::Ready { .. } => inner.apply(&my_output, control),
+                GammaEvent::Failed => {
+                    log::warn!("[SCTK] Lost the control of the gamma of an output");
+                    inner
+                        .controls
+                        .retain(|(output, _)| !output.as_ref().equals(my_output.as_ref()));
+                }
+            }
+        }),
+    );
+    guard.controls.push((output.clone(), control));
+}
+
+/// A session setting the gamma of all outputs
+///
+/// The outputs plugged while it is running are controlled as well. Dropping it restores
+/// the original gamma of all outputs.
+pub struct GammaSession {
+    inner: Rc<RefCell<SessionInner>>,
+    _output_listener: OutputStatusListener,
+}
+
+impl GammaSession {
+    /// Change the function computing the gamma ramps of the outputs
+    ///
+    /// The new ramps are immediately set on all controlled outputs.
+    pub fn set_ramp<F>(&self, ramp: F)
+    where
+        F: FnMut(&wl_output::WlOutput, u32) -> GammaRamp + 'static,
+    {
+        let mut inner = self.inner.borrow_mut();
+        inner.ramp = Box::new(ramp);
+        let controls = std::mem::replace(&mut inner.controls, Vec::new());
+        for (output, control) in &controls {
+            inner.apply(output, control);
+        }
+        inner.controls = controls;
+    }
+}
+
+impl<E> Environment<E>
+where
+    E: GlobalHandler<ZwlrGammaControlManagerV1>,
+{
+    /// Take the control of the gamma of an output
+    ///
+    /// The callback is invoked once the compositor sent the size of the gamma ramps, and
+    /// if the control fails.
+    pub fn get_gamma_control<F>(
+        &self,
+        output: &wl_output::WlOutput,
+        callback: F,
+    ) -> Result<GammaControl, GlobalError>
+    where
+        F: FnMut(GammaEvent, &GammaControl, DispatchData) + 'static,
+    {
+        let manager = self.try_get_global::<ZwlrGammaControlManagerV1>()?;
+        Ok(create_control(&manager, output, Box::new(callback)))
+    }
+}
+
+impl<E> Environment<E>
+where
+    E: GlobalHandler<ZwlrGammaControlManagerV1>
+        + MultiGlobalHandler<wl_output::WlOutput>
+        + OutputHandling,
+{
+    /// Set the gamma of all outputs, including the ones plugged later
+    ///
+    /// The ramps of each output are computed by `ramp`, from the output and the number of
+    /// entries the compositor expects, for example with `GammaRamp::from_temperature`.
+    pub fn start_gamma_session<F>(&self, ramp: F) -> Result<GammaSession, GlobalError>
+    where
+        F: FnMut(&wl_output::WlOutput, u32) -> GammaRamp + 'static,
+    {
+        let manager = self.try_get_global::<ZwlrGammaControlManagerV1>()?;
+        let inner = Rc::new(RefCell::new(SessionInner {
+            manager: manager.detach(),
+            ramp: Box::new(ramp),
+            controls: Vec::new(),
+        }));
+        for output in self.get_all_outputs() {
+            add_output(&inner, &output);
+        }
+
+        let weak = Rc::downgrade(&inner);
+        let output_listener = self.listen_for_outputs(move |output, info, _| {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            if info.obsolete {
+                inner
+                    .borrow_mut()
+                    .controls
+                    .retain(|(o, _)| !o.as_ref().equals(output.as_ref()));
+            } else {
+                add_output(&inner, &output);
+            }
+        });
+
+        Ok(GammaSession {
+            inner,
+            _output_listener: output_listener,
+        })
+    }
+}
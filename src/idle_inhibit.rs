@@ -0,0 +1,112 @@
+//! Helpers to prevent the screen from blanking with `zwp_idle_inhibit_manager_v1`
+//!
+//! While an [`IdleInhibitor`](struct.IdleInhibitor.html) of a visible surface is alive, the
+//! compositor does not blank the screen, lock it, or start its screensaver. This is typically
+//! what video players need while playing.
+//!
+//! This protocol is not part of the [`default_environment!`](../macro.default_environment.html),
+//! you need to add the `zwp_idle_inhibit_manager_v1` global to your environment, for example as a
+//! [`SimpleGlobal`](../environment/struct.SimpleGlobal.html). Not all compositors support it: if
+//! [`IdleInhibitor::is_supported`](struct.IdleInhibitor.html#method.is_supported) returns `false`,
+//! you can still fall back to the inhibition portal of your desktop over D-Bus.
+
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use wayland_client::protocol::wl_surface;
+
+use wayland_protocols::unstable::idle_inhibit::v1::client::{
+    zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1, zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler};
+
+// The inhibitors currently alive, stored in the user data of the manager
+type Inhibitors = RefCell<Vec<Weak<Inhibitor>>>;
+
+struct Inhibitor {
+    surface: wl_surface::WlSurface,
+    // `None` if the surface was already destroyed when the inhibitor was requested
+    inhibitor: Option<ZwpIdleInhibitorV1>,
+}
+
+impl Drop for Inhibitor {
+    fn drop(&mut self) {
+        if let Some(ref inhibitor) = self.inhibitor {
+            if inhibitor.as_ref().is_alive() {
+                inhibitor.destroy();
+            }
+        }
+    }
+}
+
+/// A guard preventing the screen from idling while a surface is visible
+///
+/// Dropping it allows the screen to idle again. Several guards can be created for the same
+/// surface by independent parts of your app: they share a single protocol inhibitor, which
+/// is destroyed once all of them are dropped.
+///
+/// The surface may be destroyed while guards are alive, the compositor then simply stops
+/// inhibiting idling for it.
+#[derive(Clone)]
+pub struct IdleInhibitor {
+    _inner: Rc<Inhibitor>,
+}
+
+impl IdleInhibitor {
+    /// Inhibit idling while this surface is visible
+    ///
+    /// Fails if the compositor does not support `zwp_idle_inhibit_manager_v1`.
+    pub fn new<E>(
+        env: &Environment<E>,
+        surface: &wl_surface::WlSurface,
+    ) -> Result<IdleInhibitor, GlobalError>
+    where
+        E: GlobalHandler<ZwpIdleInhibitManagerV1>,
+    {
+        let manager = env.try_get_global::<ZwpIdleInhibitManagerV1>()?;
+        manager.as_ref().user_data().set(Inhibitors::default);
+        let inhibitors = match manager.as_ref().user_data().get::<Inhibitors>() {
+            Some(inhibitors) => inhibitors,
+            // the manager was bound by another thread, don't share the inhibitors with it
+            None => return Ok(IdleInhibitor::create(&manager, surface)),
+        };
+
+        let mut inhibitors = inhibitors.borrow_mut();
+        inhibitors.retain(|inhibitor| inhibitor.upgrade().is_some());
+        let existing = inhibitors
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|inhibitor| inhibitor.surface.as_ref().equals(surface.as_ref()));
+        if let Some(inner) = existing {
+            return Ok(IdleInhibitor { _inner: inner });
+        }
+        let inhibitor = IdleInhibitor::create(&manager, surface);
+        inhibitors.push(Rc::downgrade(&inhibitor._inner));
+        Ok(inhibitor)
+    }
+
+    fn create(manager: &ZwpIdleInhibitManagerV1, surface: &wl_surface::WlSurface) -> IdleInhibitor {
+        let inhibitor = if surface.as_ref().is_alive() {
+            Some(manager.create_inhibitor(surface).detach())
+        } else {
+            None
+        };
+        IdleInhibitor {
+            _inner: Rc::new(Inhibitor {
+                surface: surface.clone(),
+                inhibitor,
+            }),
+        }
+    }
+
+    /// Check whether the compositor supports idle inhibition
+    pub fn is_supported<E>(env: &Environment<E>) -> bool
+    where
+        E: GlobalHandler<ZwpIdleInhibitManagerV1>,
+    {
+        env.get_global::<ZwpIdleInhibitManagerV1>().is_some()
+    }
+}
@@ -0,0 +1,166 @@
+//! Helpers to shape the input region of surfaces
+//!
+//! The input region of a surface is the part of it receiving the pointer and touch events,
+//! the events of the rest of it going to the surfaces below. This is what overlays, like
+//! on-screen displays or annotations drawn on a layer surface, use to let the clicks go
+//! through them, except maybe on a few interactive elements.
+//!
+//! An [`InputShape`](struct.InputShape.html) maintains the interactive rectangles of a
+//! surface, and sets its input region from them when they change.
+
+use wayland_client::{
+    protocol::{wl_compositor, wl_surface},
+    Attached,
+};
+
+use crate::environment::{Environment, GlobalHandler};
+
+/// The interactive parts of a surface
+///
+/// By default, the whole surface accepts input. Once rectangles are added, only them do, and
+/// [`set_click_through`](#method.set_click_through) makes the whole surface ignore input.
+///
+/// The rectangles are in surface-local coordinates. A negative position is relative to the
+/// right or bottom edge of the surface: a rectangle at `x = -40` starts 40 units left of the
+/// right edge. Such rectangles follow the edges when the surface is resized, once its new size
+/// is given to [`set_size`](#method.set_size).
+///
+/// The changes are applied to the surface by [`apply`](#method.apply) or
+/// [`commit`](#method.commit), and take effect on the next commit of the surface.
+pub struct InputShape {
+    compositor: Attached<wl_compositor::WlCompositor>,
+    surface: wl_surface::WlSurface,
+    rects: Vec<(i32, i32, i32, i32)>,
+    click_through: bool,
+    size: (u32, u32),
+    dirty: bool,
+}
+
+impl InputShape {
+    /// Create the input shape of a surface
+    ///
+    /// The whole surface accepts input until rectangles are added.
+    pub fn new(
+        compositor: &Attached<wl_compositor::WlCompositor>,
+        surface: wl_surface::WlSurface,
+    ) -> InputShape {
+        InputShape {
+            compositor: compositor.clone(),
+            surface,
+            rects: Vec::new(),
+            click_through: false,
+            size: (0, 0),
+            dirty: false,
+        }
+    }
+
+    /// Add an interactive rectangle
+    pub fn add_rect(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        self.rects.push((x, y, width, height));
+        self.dirty = true;
+    }
+
+    /// Remove all the interactive rectangles
+    ///
+    /// The whole surface accepts input again, unless it is click-through.
+    pub fn clear_rects(&mut self) {
+        if !self.rects.is_empty() {
+            self.rects.clear();
+            self.dirty = true;
+        }
+    }
+
+    /// Set whether the whole surface ignores input
+    ///
+    /// The interactive rectangles are kept, and used again once the surface is no longer
+    /// click-through.
+    pub fn set_click_through(&mut self, click_through: bool) {
+        if self.click_through != click_through {
+            self.click_through = click_through;
+            self.dirty = true;
+        }
+    }
+
+    /// Whether the whole surface ignores input
+    pub fn is_click_through(&self) -> bool {
+        self.click_through
+    }
+
+    /// Set the size of the surface, to place the rectangles relative to its right and bottom edges
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        if self.size == (width, height) {
+            return;
+        }
+        self.size = (width, height);
+        if self.rects.iter().any(|&(x, y, _, _)| x < 0 || y < 0) {
+            self.dirty = true;
+        }
+    }
+
+    /// Whether a position of the surface accepts input
+    pub fn accepts_input(&self, x: f64, y: f64) -> bool {
+        if self.click_through {
+            return false;
+        }
+        self.rects.is_empty()
+            || self.rects.iter().any(|&rect| {
+                let (rx, ry, width, height) = self.resolve(rect);
+                x >= f64::from(rx)
+                    && x < f64::from(rx) + f64::from(width)
+                    && y >= f64::from(ry)
+                    && y < f64::from(ry) + f64::from(height)
+            })
+    }
+
+    /// Set the input region of the surface, if it changed
+    ///
+    /// It takes effect on the next commit of the surface.
+    pub fn apply(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        if !self.click_through && self.rects.is_empty() {
+            self.surface.set_input_region(None);
+            return;
+        }
+        // an empty region if the surface is click-through
+        let region = self.compositor.create_region();
+        if !self.click_through {
+            for &rect in &self.rects {
+                let (x, y, width, height) = self.resolve(rect);
+                region.add(x, y, width, height);
+            }
+        }
+        self.surface.set_input_region(Some(&region));
+        region.destroy();
+    }
+
+    /// Set the input region of the surface if it changed, and commit the surface
+    pub fn commit(&mut self) {
+        self.apply();
+        self.surface.commit();
+    }
+
+    /// Access the underlying `wl_surface`
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    // the position of a rectangle relative to the top-left corner of the surface
+    fn resolve(&self, (x, y, width, height): (i32, i32, i32, i32)) -> (i32, i32, i32, i32) {
+        let x = if x < 0 { self.size.0 as i32 + x } else { x };
+        let y = if y < 0 { self.size.1 as i32 + y } else { y };
+        (x, y, width, height)
+    }
+}
+
+impl<E: GlobalHandler<wl_compositor::WlCompositor>> Environment<E> {
+    /// Create the input shape of a surface
+    ///
+    /// See [`InputShape`](../input_shape/struct.InputShape.html) for details.
+    pub fn create_input_shape(&self, surface: wl_surface::WlSurface) -> InputShape {
+        let compositor = self.require_global::<wl_compositor::WlCompositor>();
+        InputShape::new(&compositor, surface)
+    }
+}
@@ -23,6 +23,11 @@
 //! [`WaylandSource`](struct.WaylandSource.html) is an adapter to insert a Wayland `EventQueue` into
 //! a calloop event loop. And some of the modules of SCTK will provide you with other event sources
 //! that you need to insert into calloop for them to work correctly.
+//!
+//! If you don't need other event sources, [`dispatch_with_timeout`](fn.dispatch_with_timeout.html)
+//! dispatches the Wayland events but returns in time for your app to, for example, render its
+//! next frame. Alternatively, with the `async` cargo feature, [`AsyncEventQueue`](struct.AsyncEventQueue.html)
+//! lets you dispatch the Wayland events from the async runtime of your choice.
 #![warn(missing_docs)]
 #![allow(clippy::new_without_default)]
 
@@ -37,23 +42,49 @@ pub mod reexports {
     pub use wayland_protocols as protocols;
 }
 
+pub mod data_control;
 pub mod data_device;
+pub mod dmabuf;
+#[cfg(feature = "egl")]
+pub mod egl;
 pub mod environment;
+pub mod foreign_toplevel;
+pub mod gamma_control;
+pub mod idle_inhibit;
+pub mod input_shape;
 mod lazy_global;
 pub mod output;
+pub mod presentation;
 pub mod primary_selection;
+#[cfg(feature = "raw_handle")]
+pub mod raw_handle;
+pub mod screencopy;
 pub mod seat;
 pub mod shell;
 pub mod shm;
+pub mod solid_surface;
 pub mod window;
+pub mod xdg_foreign;
 
+#[cfg(feature = "async")]
+mod async_queue;
+mod dispatch;
 #[cfg(feature = "calloop")]
 mod event_loop;
 mod surface;
+#[cfg(test)]
+mod test_server;
 
+#[cfg(feature = "async")]
+pub use async_queue::{AsyncEventQueue, FdReadiness};
+pub use dispatch::{dispatch_with_timeout, TimedDispatch};
 #[cfg(feature = "calloop")]
 pub use event_loop::WaylandSource;
-pub use surface::{get_surface_outputs, get_surface_scale_factor};
+pub use surface::{
+    get_surface_outputs, get_surface_role, get_surface_scale_factor, set_surface_role,
+    BufferTransform, CommitTransaction, CompositorCaps, RoleConflict, ScaleFactor,
+    SurfaceOutputEvent, SurfaceRole,
+};
 
 #[macro_export]
 /// Declare a batteries-included SCTK environment
@@ -225,6 +256,45 @@ macro_rules! default_environment {
             ) -> Result<(), ()> {
                 self.sctk_data_device_manager.with_device(seat, f)
             }
+
+            fn register_drop_target<F>(
+                &mut self,
+                surface: $crate::reexports::client::protocol::wl_surface::WlSurface,
+                callback: F
+            ) -> Result<$crate::data_device::DropTarget, $crate::data_device::DataDeviceError>
+            where F: FnMut(
+                $crate::reexports::client::protocol::wl_seat::WlSeat,
+                $crate::data_device::DropEvent,
+                $crate::reexports::client::DispatchData
+            ) + 'static
+            {
+                self.sctk_data_device_manager.register_drop_target(surface, callback)
+            }
+
+            fn set_selection_callback<F>(
+                &mut self,
+                callback: F
+            ) -> Result<(), $crate::data_device::DataDeviceError>
+            where F: FnMut(
+                $crate::reexports::client::protocol::wl_seat::WlSeat,
+                Option<&[String]>,
+                $crate::reexports::client::DispatchData
+            ) + 'static
+            {
+                self.sctk_data_device_manager.set_selection_callback(callback)
+            }
+
+            fn set_selection_lost_callback<F>(
+                &mut self,
+                callback: F
+            ) -> Result<(), $crate::data_device::DataDeviceError>
+            where F: FnMut(
+                $crate::reexports::client::protocol::wl_seat::WlSeat,
+                $crate::reexports::client::DispatchData
+            ) + 'static
+            {
+                self.sctk_data_device_manager.set_selection_lost_callback(callback)
+            }
         }
 
         // Primary selection utility
@@ -242,6 +312,18 @@ macro_rules! default_environment {
             fn get_primary_selection_manager(&self) -> Option<$crate::primary_selection::PrimarySelectionDeviceManager> {
                 self.sctk_primary_selection_manager.get_primary_selection_manager()
             }
+
+            fn set_primary_selection_lost_callback<F>(
+                &mut self,
+                callback: F
+            ) -> Result<(), $crate::data_device::DataDeviceError>
+            where F: FnMut(
+                $crate::reexports::client::protocol::wl_seat::WlSeat,
+                $crate::reexports::client::DispatchData
+            ) + 'static
+            {
+                self.sctk_primary_selection_manager.set_primary_selection_lost_callback(callback)
+            }
         }
 
         //
@@ -286,8 +368,8 @@ macro_rules! default_environment {
 ///
 /// The macro will automatically setup a Wayland connection and evaluate to a `Result`
 /// containing either `Ok((env, display, queue))`, providing you the initialized `Environment`
-/// as well as the wayland `Display` and `EventQueue` associated to it, or to an error
-/// if the connection failed.
+/// as well as the wayland `Display` and `EventQueue` associated to it, or to an
+/// [`EnvironmentError`](environment/enum.EnvironmentError.html) if the connection failed.
 ///
 /// ```no_run
 /// # use smithay_client_toolkit::{default_environment, init_default_environment};
@@ -304,7 +386,7 @@ macro_rules! default_environment {
 ///
 /// If you instead want the macro to use some pre-existing display and event queue, you can
 /// add the `with` argument providing them. In that case the macro will evaluate to
-/// a `Result<Environment, io::Error>`, forwarding to you any error that may have occured
+/// a `Result<Environment, EnvironmentError>`, forwarding to you any error that may have occured
 /// during the initial roundtrips.
 ///
 /// ```no_run
@@ -348,7 +430,8 @@ macro_rules! init_default_environment {
             let sctk_data_device_manager = $crate::data_device::DataDeviceHandler::init(&mut sctk_seats);
             let sctk_primary_selection_manager = $crate::primary_selection::PrimarySelectionHandler::init(&mut sctk_seats);
 
-            let display = $crate::reexports::client::Proxy::clone(&$display);
+            let sctk_display: &$crate::reexports::client::Display = &$display;
+            let display = $crate::reexports::client::Proxy::clone(sctk_display);
             let env = $crate::environment::Environment::init(&display.attach($queue.token()), $env_name {
                 sctk_compositor: $crate::environment::SimpleGlobal::new(),
                 sctk_subcompositor: $crate::environment::SimpleGlobal::new(),
@@ -363,39 +446,30 @@ macro_rules! init_default_environment {
             });
 
             // two roundtrips to init the environment
-            let ret = $queue .sync_roundtrip(&mut (), |_, _, _| unreachable!());
-            let ret = ret.and_then(|_| $queue.sync_roundtrip(&mut (), |_, _, _| unreachable!()));
+            let ret = env.roundtrip(&mut $queue);
+            let ret = ret.and_then(|_| env.roundtrip(&mut $queue));
 
             // Bind primary selection manager
             let _psm = env.get_primary_selection_manager();
 
             ret.map(|_| env)
+                .map_err(|e| $crate::environment::EnvironmentError::from_io(sctk_display, e.into()))
         }
     };
     ($env_name:ident
         $(,fields = [$($fname:ident : $fval:expr),* $(,)?])?
         $(,)?
     ) => {
-        $crate::reexports::client::Display::connect_to_env().and_then(|display| {
-            let mut queue = display.create_event_queue();
-            let ret = $crate::init_default_environment!(
-                $env_name,
-                with=(display, queue),
-                fields=[$($($fname: $fval),*)?],
-            );
-            match ret {
-                Ok(env) => Ok((env, display, queue)),
-                Err(e) => {
-                    if let Some(perr) = display.protocol_error() {
-                        panic!("[SCTK] A protocol error occured during initial setup: {}", perr);
-                    } else {
-                        // For some other reason the connection with the compositor was lost
-                        // This should not arrive unless maybe the compositor was shutdown during
-                        // the initial setup...
-                        Err($crate::reexports::client::ConnectError::NoCompositorListening)
-                    }
-                }
-            }
-        })
+        $crate::reexports::client::Display::connect_to_env()
+            .map_err($crate::environment::EnvironmentError::from)
+            .and_then(|display| {
+                let mut queue = display.create_event_queue();
+                let env = $crate::init_default_environment!(
+                    $env_name,
+                    with=(display, queue),
+                    fields=[$($($fname: $fval),*)?],
+                )?;
+                Ok((env, display, queue))
+            })
     };
 }
@@ -23,6 +23,8 @@ use wayland_client::{
     Attached, DispatchData, Main,
 };
 
+use crate::surface::ScaleFactor;
+
 pub use wayland_client::protocol::wl_output::{Subpixel, Transform};
 
 /// A possible mode for an output
@@ -72,7 +74,7 @@ pub struct OutputInfo {
     ///
     /// For example, a buffer of scaling factor 1 will be doubled in
     /// size if the output scaling factor is 2.
-    pub scale_factor: i32,
+    pub scale_factor: ScaleFactor,
     /// Possible modes for an output
     pub modes: Vec<Mode>,
     /// Has this output been unadvertized by the registry
@@ -93,7 +95,7 @@ impl OutputInfo {
             physical_size: (0, 0),
             subpixel: Subpixel::Unknown,
             transform: Transform::Normal,
-            scale_factor: 1,
+            scale_factor: ScaleFactor::ONE,
             modes: Vec::new(),
             obsolete: false,
         }
@@ -149,6 +151,7 @@ impl crate::environment::MultiGlobalHandler<WlOutput> for OutputHandler {
     ) {
         // We currently support wl_output up to version 3
         let version = std::cmp::min(version, 3);
+        log::debug!("[SCTK] Bound wl_output {} version {}", id, version);
         let output = registry.bind::<WlOutput>(version, id);
         if version > 1 {
             // wl_output.done event was only added at version 2
@@ -180,6 +183,7 @@ impl crate::environment::MultiGlobalHandler<WlOutput> for OutputHandler {
             if *i != id {
                 true
             } else {
+                log::debug!("[SCTK] wl_output {} was removed", id);
                 make_obsolete(o, ddata.reborrow(), &status_listeners_handle);
                 false
             }
@@ -295,7 +299,7 @@ fn merge_event(info: &mut OutputInfo, event: Event) {
             info.make = make;
         }
         Event::Scale { factor } => {
-            info.scale_factor = factor;
+            info.scale_factor = ScaleFactor::from_protocol(factor);
         }
         Event::Mode {
             width,
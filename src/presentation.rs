@@ -0,0 +1,339 @@
+//! Helpers to get accurate frame timings with `wp_presentation`
+//!
+//! The presentation-time protocol tells you when the content of each commit of a surface
+//! was actually shown to the user, on which output, and when the next refresh of this
+//! output is expected. This is what smooth animations and audio/video synchronization need.
+//!
+//! It is not part of the [`default_environment!`](../macro.default_environment.html), you need
+//! to add the `wp_presentation` global to your environment with a
+//! [`PresentationHandler`](struct.PresentationHandler.html), which records the presentation
+//! clock advertised by the compositor:
+//!
+//! ```no_run
+//! # use smithay_client_toolkit::default_environment;
+//! use smithay_client_toolkit::presentation::PresentationHandler;
+//! use smithay_client_toolkit::reexports::protocols::presentation_time::client::wp_presentation::WpPresentation;
+//!
+//! default_environment!(MyEnv,
+//!     fields=[presentation: PresentationHandler],
+//!     singles=[WpPresentation => presentation],
+//! );
+//! ```
+//!
+//! You then request the feedback of a commit with
+//! [`Environment::request_presentation_feedback`](../environment/struct.Environment.html#method.request_presentation_feedback)
+//! right before committing the surface, and can feed the results to a
+//! [`FramePacer`](struct.FramePacer.html) to schedule your next renders.
+
+use std::{io, sync::Mutex, time::Duration};
+
+use wayland_client::{
+    protocol::{wl_output, wl_registry, wl_surface},
+    Attached, DispatchData,
+};
+
+use wayland_protocols::presentation_time::client::{wp_presentation, wp_presentation_feedback};
+
+pub use wayland_protocols::presentation_time::client::wp_presentation_feedback::Kind;
+
+use crate::environment::{Environment, GlobalHandler};
+
+/// The feedback of a commit that was presented to the user
+#[derive(Clone)]
+pub struct PresentedFeedback {
+    /// When the content was shown, as a duration since the epoch of the presentation clock
+    pub time: Duration,
+    /// The expected duration until the next refresh of the output
+    ///
+    /// It is zero if the output does not have a constant refresh rate.
+    pub refresh: Duration,
+    /// The vertical retrace counter of the output, zero if it does not have one
+    pub seq: u64,
+    /// How the presentation was done
+    pub flags: Kind,
+    /// The output the presentation was synchronized to
+    ///
+    /// It is `None` if you did not bind this output.
+    pub output: Option<wl_output::WlOutput>,
+}
+
+/// The outcome of a presentation feedback request
+#[derive(Clone)]
+pub enum PresentationEvent {
+    /// The content of the commit was presented
+    Presented(PresentedFeedback),
+    /// The content of the commit was never shown, for example because it was replaced by a
+    /// newer commit before the output refreshed, or because the surface is not visible
+    Discarded,
+}
+
+/// A handler for the `wp_presentation` global
+///
+/// It binds the global as soon as it is advertised, to receive the clock identifier the
+/// compositor sends right after the binding.
+pub struct PresentationHandler {
+    presentation: Option<Attached<wp_presentation::WpPresentation>>,
+    id: u32,
+}
+
+impl PresentationHandler {
+    /// Create a new handler
+    pub fn new() -> PresentationHandler {
+        PresentationHandler {
+            presentation: None,
+            id: 0,
+        }
+    }
+}
+
+impl GlobalHandler<wp_presentation::WpPresentation> for PresentationHandler {
+    fn created(
+        &mut self,
+        registry: Attached<wl_registry::WlRegistry>,
+        id: u32,
+        _version: u32,
+        _: DispatchData,
+    ) {
+        // only version 1 exists
+        let presentation = registry.bind::<wp_presentation::WpPresentation>(1, id);
+        presentation
+            .as_ref()
+            .user_data()
+            .set_threadsafe(|| Mutex::new(None::<u32>));
+        presentation.quick_assign(|presentation, event, _| match event {
+            wp_presentation::Event::ClockId { clk_id } => {
+                if let Some(clock) = presentation
+                    .as_ref()
+                    .user_data()
+                    .get::<Mutex<Option<u32>>>()
+                {
+                    *clock.lock().unwrap() = Some(clk_id);
+                }
+            }
+            _ => unreachable!(),
+        });
+        self.presentation = Some((*presentation).clone());
+        self.id = id;
+    }
+    fn get(&self) -> Option<Attached<wp_presentation::WpPresentation>> {
+        self.presentation.clone()
+    }
+    fn removed(&mut self, id: u32, _: DispatchData) {
+        if self.id == id {
+            if let Some(presentation) = self.presentation.take() {
+                presentation.destroy();
+            }
+        }
+    }
+}
+
+/// The identifier of the presentation clock
+///
+/// On Linux, it is one of the `clockid_t` values accepted by `clock_gettime()`, see
+/// [`clock_now`](fn.clock_now.html). Returns `None` if this `wp_presentation` was not bound by a
+/// [`PresentationHandler`](struct.PresentationHandler.html), or if the compositor has not sent
+/// it yet.
+pub fn clock_id(presentation: &wp_presentation::WpPresentation) -> Option<u32> {
+    presentation
+        .as_ref()
+        .user_data()
+        .get::<Mutex<Option<u32>>>()
+        .and_then(|clock| *clock.lock().unwrap())
+}
+
+/// The current time of a clock, as a duration since its epoch
+///
+/// Given the [`clock_id`](fn.clock_id.html) of the presentation clock, this gives you
+/// a time you can compare with the timestamps of the presentation feedbacks.
+pub fn clock_now(clock_id: u32) -> io::Result<Duration> {
+    let mut time = nix::libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let ret = unsafe { nix::libc::clock_gettime(clock_id as nix::libc::clockid_t, &mut time) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+/// Request the presentation feedback of the next commit of a surface
+///
+/// This must be called right before committing the surface. The callback is invoked once,
+/// when the content of this commit is presented or discarded.
+///
+/// The feedback object is destroyed by the compositor once it sent the outcome, and the
+/// callback is dropped with it. Do not keep the returned proxy alive in the callback.
+pub fn request_feedback<F>(
+    presentation: &Attached<wp_presentation::WpPresentation>,
+    surface: &wl_surface::WlSurface,
+    callback: F,
+) -> wp_presentation_feedback::WpPresentationFeedback
+where
+    F: FnOnce(PresentationEvent, DispatchData) + 'static,
+{
+    let feedback = presentation.feedback(surface);
+    let mut output = None;
+    let mut callback = Some(callback);
+    feedback.quick_assign(move |_, event, ddata| {
+        use self::wp_presentation_feedback::Event;
+        let event = match event {
+            Event::SyncOutput { output: synced } => {
+                output = Some(synced);
+                return;
+            }
+            Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                flags,
+            } => PresentationEvent::Presented(PresentedFeedback {
+                time: Duration::new(((tv_sec_hi as u64) << 32) | tv_sec_lo as u64, tv_nsec),
+                refresh: Duration::from_nanos(refresh as u64),
+                seq: ((seq_hi as u64) << 32) | seq_lo as u64,
+                flags: Kind::from_bits_truncate(flags),
+                output: output.take(),
+            }),
+            Event::Discarded => PresentationEvent::Discarded,
+            _ => unreachable!(),
+        };
+        if let Some(callback) = callback.take() {
+            callback(event, ddata);
+        }
+    });
+    feedback.detach()
+}
+
+impl<E: GlobalHandler<wp_presentation::WpPresentation>> Environment<E> {
+    /// Request the presentation feedback of the next commit of a surface
+    ///
+    /// See [`request_feedback`](../presentation/fn.request_feedback.html) for details.
+    ///
+    /// Returns `None` if the compositor does not support `wp_presentation`.
+    pub fn request_presentation_feedback<F>(
+        &self,
+        surface: &wl_surface::WlSurface,
+        callback: F,
+    ) -> Option<wp_presentation_feedback::WpPresentationFeedback>
+    where
+        F: FnOnce(PresentationEvent, DispatchData) + 'static,
+    {
+        let presentation = self.get_global::<wp_presentation::WpPresentation>()?;
+        Some(request_feedback(&presentation, surface, callback))
+    }
+
+    /// The identifier of the presentation clock
+    ///
+    /// See [`clock_id`](../presentation/fn.clock_id.html) for details.
+    pub fn presentation_clock_id(&self) -> Option<u32> {
+        self.get_global::<wp_presentation::WpPresentation>()
+            .and_then(|presentation| clock_id(&presentation))
+    }
+}
+
+/// When to start rendering a frame, and when it is expected to be presented
+///
+/// Both times are in the presentation clock domain.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RenderDeadline {
+    /// When to start rendering the frame
+    pub start: Duration,
+    /// The vertical blank at which the frame is expected to be presented
+    pub presentation: Duration,
+}
+
+/// An estimation of the next refreshes of an output, to schedule the renders
+///
+/// Give it the feedbacks of your commits, it then predicts the time of the next vertical
+/// blanks of the output your surface is presented on, in the presentation clock domain.
+/// Each feedback replaces the previous estimation, so the prediction follows the surface
+/// when it moves to an output with another refresh rate.
+///
+/// When the refresh rate of the output is unknown or variable, or if the compositor does not
+/// support `wp_presentation` so that no feedback is given, no prediction is made: you then
+/// need to pace your renders with the frame callbacks of your surface instead.
+#[derive(Clone, Debug, Default)]
+pub struct FramePacer {
+    last: Option<(Duration, Duration)>,
+}
+
+impl FramePacer {
+    /// Create a new pacer, without any prediction
+    pub fn new() -> FramePacer {
+        FramePacer { last: None }
+    }
+
+    /// Record the outcome of a presentation feedback
+    ///
+    /// Discarded commits do not change the prediction.
+    pub fn feedback(&mut self, event: &PresentationEvent) {
+        if let PresentationEvent::Presented(ref feedback) = *event {
+            self.presented(feedback);
+        }
+    }
+
+    /// Record a presented commit
+    pub fn presented(&mut self, feedback: &PresentedFeedback) {
+        self.last = if feedback.refresh > Duration::from_secs(0) {
+            Some((feedback.time, feedback.refresh))
+        } else {
+            None
+        };
+    }
+
+    /// The refresh period of the output, if known
+    pub fn refresh(&self) -> Option<Duration> {
+        self.last.map(|(_, refresh)| refresh)
+    }
+
+    /// The time of the first vertical blank after `now`, if it can be predicted
+    ///
+    /// `now` is the current time in the presentation clock domain, as given by
+    /// [`clock_now`](fn.clock_now.html).
+    pub fn next_vblank(&self, now: Duration) -> Option<Duration> {
+        let (last, refresh) = self.last?;
+        if now < last {
+            return Some(last);
+        }
+        let refresh_nanos = refresh.as_nanos();
+        let elapsed = (now - last).as_nanos();
+        let cycles = elapsed / refresh_nanos + 1;
+        let offset = refresh_nanos * cycles;
+        Some(
+            last + Duration::new(
+                (offset / 1_000_000_000) as u64,
+                (offset % 1_000_000_000) as u32,
+            ),
+        )
+    }
+
+    /// The time left until the next vertical blank, if it can be predicted
+    pub fn time_until_next_vblank(&self, now: Duration) -> Option<Duration> {
+        self.next_vblank(now).map(|vblank| vblank - now)
+    }
+
+    /// When to start rendering the next frame, if it can be predicted
+    ///
+    /// The render starts at the next vertical blank, and the frame is expected to be presented
+    /// `target_latency_frames` refreshes later (at least one), leaving the remaining time to
+    /// render and to the compositor. A game loop can sleep until `start`, and animate the frame
+    /// for its `presentation` time.
+    ///
+    /// Returns `None` when no prediction can be made, your renders then need to be paced with
+    /// frame callbacks.
+    pub fn next_render_deadline(
+        &self,
+        now: Duration,
+        target_latency_frames: u32,
+    ) -> Option<RenderDeadline> {
+        let refresh = self.refresh()?;
+        let start = self.next_vblank(now)?;
+        Some(RenderDeadline {
+            start,
+            presentation: start + refresh * std::cmp::max(target_latency_frames, 1),
+        })
+    }
+}
@@ -0,0 +1,175 @@
+//! High-level primary selection helpers
+//!
+//! These mirror the clipboard helpers of the data device module, for the primary
+//! selection (typically pasted with a middle click). The transfers are done in a
+//! non-blocking way from your calloop event loop.
+
+#[cfg(feature = "calloop")]
+use std::{io, rc::Rc};
+
+use wayland_client::protocol::wl_seat;
+
+use super::source::PrimarySelectionSourceImpl;
+use super::{PrimarySelectionHandling, PrimarySelectionSource, PrimarySelectionSourceEvent};
+#[cfg(feature = "calloop")]
+use crate::data_device::{select_mime_type, PlainText};
+use crate::data_device::{DataDeviceError, WritePipe};
+use crate::environment::Environment;
+
+impl<E: PrimarySelectionHandling> Environment<E> {
+    /// Set the primary selection of a seat
+    ///
+    /// The data is offered with all the given mime types, and will be sent to any
+    /// client requesting it until another client (or you) replaces the primary
+    /// selection. The transfers are done in a non-blocking way from the provided
+    /// event loop.
+    ///
+    /// The serial must be the one of the input event that triggered the selection
+    /// (typically the pointer button release ending it), the compositor will ignore
+    /// the request otherwise.
+    ///
+    /// Returns `DataDeviceError::NoDataDevice` if the compositor supports neither
+    /// `zwp_primary_selection_device_manager_v1` nor `gtk_primary_selection_device_manager`,
+    /// or if there is no primary selection device for this seat.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn set_primary_selection<Data: 'static>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        mime_types: Vec<String>,
+        data: Vec<u8>,
+    ) -> Result<(), DataDeviceError> {
+        let data: Rc<[u8]> = data.into();
+        let handle = loop_handle.clone();
+        self.set_primary_selection_with(seat, serial, mime_types, move |_, pipe| {
+            let ret = pipe.write_all_async(&handle, data.clone(), |ret, _| {
+                if let Err(err) = ret {
+                    log::warn!("[SCTK] Failed to send primary selection data: {}", err);
+                }
+            });
+            if let Err(err) = ret {
+                log::warn!("[SCTK] Failed to send primary selection data: {}", err);
+            }
+        })
+    }
+
+    /// Set the primary selection of a seat with lazily produced data
+    ///
+    /// The `provider` is invoked each time a client requests the primary selection, see
+    /// [`Environment::copy_with`](../environment/struct.Environment.html#method.copy_with)
+    /// for details.
+    ///
+    /// Returns the same errors as [`set_primary_selection`](#method.set_primary_selection).
+    pub fn set_primary_selection_with<F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        serial: u32,
+        mime_types: Vec<String>,
+        mut provider: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(&str, WritePipe) + 'static,
+    {
+        let manager = self
+            .get_primary_selection_manager()
+            .ok_or(DataDeviceError::NoDataDevice)?;
+        let source = PrimarySelectionSource::new(&manager, mime_types, move |event, _| {
+            if let PrimarySelectionSourceEvent::Send { mime_type, pipe } = event {
+                provider(&mime_type, pipe);
+            }
+        });
+        let source = Some(source);
+        self.with_primary_selection(seat, |device| device.set_selection(&source, serial))
+            .map_err(|()| {
+                if let Some(source) = source {
+                    match source.source {
+                        PrimarySelectionSourceImpl::Zwp(ref source) => source.destroy(),
+                        PrimarySelectionSourceImpl::Gtk(ref source) => source.destroy(),
+                    }
+                }
+                DataDeviceError::NoDataDevice
+            })
+    }
+
+    /// Request the contents of the primary selection of a seat
+    ///
+    /// The primary selection offers of each seat are tracked automatically, so this
+    /// always requests the current one. The first of the provided mime types it offers
+    /// is requested, and the callback is invoked with this mime type and the received
+    /// data once the transfer is complete.
+    ///
+    /// Returns an error without invoking the callback if the primary selection is empty,
+    /// if it does not offer any of the requested mime types, or if primary selection is
+    /// not supported (see [`set_primary_selection`](#method.set_primary_selection)).
+    /// If the transfer itself fails, the callback is invoked with an error.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn request_primary_paste<Data, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        mime_types: &[&str],
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        Data: 'static,
+        F: FnOnce(Result<(String, Vec<u8>), DataDeviceError>, &mut Data) + 'static,
+    {
+        let mut result = Err(DataDeviceError::NoDataDevice);
+        let _ = self.with_primary_selection(seat, |device| {
+            result = device.with_selection(|offer| {
+                let offer = offer.ok_or(DataDeviceError::NoSelection)?;
+                let mime_type = offer
+                    .with_mime_types(|offered| select_mime_type(offered, mime_types))
+                    .ok_or(DataDeviceError::UnsupportedMimeType)?;
+                let pipe = offer
+                    .receive(mime_type.clone())
+                    .map_err(|()| DataDeviceError::Io(io::Error::last_os_error()))?;
+                Ok((mime_type, pipe))
+            });
+        });
+        let (mime_type, pipe) = result?;
+        pipe.read_to_end_async(loop_handle, None, move |contents, data| {
+            callback(
+                contents
+                    .map(|c| (mime_type, c))
+                    .map_err(DataDeviceError::Io),
+                data,
+            )
+        })
+        .map_err(DataDeviceError::Io)
+    }
+
+    /// Request the text contents of the primary selection of a seat
+    ///
+    /// The preferred [`PlainText`](../data_device/struct.PlainText.html) mime type
+    /// offered by the primary selection is requested and decoded, see
+    /// [`request_primary_paste`](#method.request_primary_paste) for details.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn request_primary_paste_text<Data, F>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        seat: &wl_seat::WlSeat,
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        Data: 'static,
+        F: FnOnce(Result<String, DataDeviceError>, &mut Data) + 'static,
+    {
+        self.request_primary_paste(
+            loop_handle,
+            seat,
+            PlainText::MIME_TYPES,
+            move |result, data| {
+                let text = result.map(|(mime, contents)| PlainText::decode(&contents, &mime).0);
+                callback(text, data)
+            },
+        )
+    }
+}
@@ -1,4 +1,8 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
 
 use wayland_protocols::{
     misc::gtk_primary_selection::client::gtk_primary_selection_device::{
@@ -9,7 +13,7 @@ use wayland_protocols::{
     },
 };
 
-use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{protocol::wl_seat::WlSeat, DispatchData};
 
 use crate::primary_selection::offer::PrimarySelectionOfferImpl;
 use crate::primary_selection::source::PrimarySelectionSourceImpl;
@@ -18,12 +22,15 @@ use super::PrimarySelectionDeviceManager;
 use super::PrimarySelectionOffer;
 use super::PrimarySelectionSource;
 
+type LostCallback = dyn FnMut(DispatchData);
+
 /// Handle to support primary selection on a given seat.
 ///
 /// This type provides you with copy/paste actions. It is associated with a seat upon creation.
 pub struct PrimarySelectionDevice {
     device: PrimarySelectionDeviceImpl,
     inner: Arc<Mutex<PrimarySelectionDeviceInner>>,
+    lost_callback: Rc<RefCell<LostCallback>>,
 }
 
 /// Possible supported primary selection devices.
@@ -39,6 +46,12 @@ struct PrimarySelectionDeviceInner {
 
     /// List of known offers.
     know_offers: Vec<PrimarySelectionOffer>,
+
+    /// Generation of the selection we set, until its source is cancelled.
+    owned_selection: Option<u64>,
+
+    /// Incremented for each selection we set.
+    selection_generation: u64,
 }
 
 impl PrimarySelectionDeviceInner {
@@ -76,9 +89,24 @@ impl Drop for PrimarySelectionDevice {
 impl PrimarySelectionDevice {
     /// Create the `PrimarySelectionDevice` helper for this seat.
     pub fn init_for_seat(manager: &PrimarySelectionDeviceManager, seat: &WlSeat) -> Self {
+        Self::init_with_lost_callback(manager, seat, |_| {})
+    }
+
+    /// Create the `PrimarySelectionDevice` helper for this seat, with a callback invoked when
+    /// another client replaces a selection set by this device.
+    pub(crate) fn init_with_lost_callback<L>(
+        manager: &PrimarySelectionDeviceManager,
+        seat: &WlSeat,
+        lost_callback: L,
+    ) -> Self
+    where
+        L: FnMut(DispatchData) + 'static,
+    {
         let inner = Arc::new(Mutex::new(PrimarySelectionDeviceInner {
             selection: None,
             know_offers: Vec::new(),
+            owned_selection: None,
+            selection_generation: 0,
         }));
 
         let inner2 = inner.clone();
@@ -127,14 +155,46 @@ impl PrimarySelectionDevice {
             }
         };
 
-        Self { device, inner }
+        Self {
+            device,
+            inner,
+            lost_callback: Rc::new(RefCell::new(lost_callback)),
+        }
     }
 
     /// Provide a primary selection source as the new content for the primary selection.
     ///
     /// Correspond to traditional copy/paste behavior. Setting the source to `None` will clear
     /// the selection.
+    ///
+    /// While the source is not cancelled, this device [owns](#method.owns_selection) the
+    /// primary selection.
     pub fn set_selection(&self, source: &Option<PrimarySelectionSource>, serial: u32) {
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.selection_generation += 1;
+            inner.owned_selection = source.as_ref().map(|_| inner.selection_generation);
+            inner.selection_generation
+        };
+        if let Some(ref source) = *source {
+            let inner = self.inner.clone();
+            let lost_callback = self.lost_callback.clone();
+            *source.cancel_hook.borrow_mut() = Some(Box::new(move |ddata| {
+                let lost = {
+                    let mut inner = inner.lock().unwrap();
+                    // a newer selection of ours may have replaced this one
+                    if inner.owned_selection == Some(generation) {
+                        inner.owned_selection = None;
+                        true
+                    } else {
+                        false
+                    }
+                };
+                if lost {
+                    (&mut *lost_callback.borrow_mut())(ddata);
+                }
+            }));
+        }
         match self.device {
             PrimarySelectionDeviceImpl::Zwp(ref device) => {
                 let source = source.as_ref().map(|source| match source.source {
@@ -155,6 +215,15 @@ impl PrimarySelectionDevice {
         }
     }
 
+    /// Whether the primary selection of this seat is the one set by this device.
+    ///
+    /// This is the case from the moment a source is given to
+    /// [`set_selection`](#method.set_selection), until this source is cancelled because
+    /// another client (or this device) replaced the primary selection.
+    pub fn owns_selection(&self) -> bool {
+        self.inner.lock().unwrap().owned_selection.is_some()
+    }
+
     /// Access the `PrimarySelectionOffer` currently associated with the primary selection buffer.
     pub fn with_selection<F: FnOnce(Option<&PrimarySelectionOffer>) -> T, T>(&self, f: F) -> T {
         let inner = self.inner.lock().unwrap();
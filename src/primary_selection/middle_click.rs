@@ -0,0 +1,209 @@
+//! Pasting of the primary selection with a middle click
+
+use std::{cell::RefCell, rc::Rc};
+
+use wayland_client::protocol::{wl_pointer, wl_seat, wl_surface};
+
+use super::PrimarySelectionHandling;
+use crate::environment::Environment;
+
+// linux input event code of the middle button
+const BTN_MIDDLE: u32 = 0x112;
+// distance the pointer can move while the button is held before the press
+// is considered to start a drag
+const DRAG_THRESHOLD: f64 = 4.0;
+
+type PasteCallback<Data> = dyn FnMut(Option<String>, &wl_surface::WlSurface, (f64, f64), &mut Data);
+
+struct Press {
+    surface: wl_surface::WlSurface,
+    position: (f64, f64),
+}
+
+/// A helper pasting the primary selection when the middle button is clicked
+///
+/// This provides the classic behavior of X11 terminals: a middle click on one of
+/// the registered surfaces requests the text of the primary selection, and your
+/// callback is invoked with it and the surface-local position of the pointer
+/// when the button was pressed.
+///
+/// It is driven by the events of a pointer, which you need to forward to
+/// [`handle_event`](#method.handle_event). You need one helper per pointer.
+///
+/// The paste is triggered when the button is released, and nothing happens if
+/// the pointer moved more than a few pixels while it was held (the press started
+/// a drag), if another button was held when it was pressed (an implicit grab is
+/// active), or if a grab was signaled with
+/// [`set_grab_active`](#method.set_grab_active).
+///
+/// If the primary selection is empty, does not offer any text, or is not supported
+/// by the compositor, the callback is invoked with `None`.
+///
+/// **Note:** This requires the `calloop` cargo feature.
+pub struct MiddleClickPaste<Data> {
+    loop_handle: calloop::LoopHandle<Data>,
+    surfaces: Vec<wl_surface::WlSurface>,
+    focus: Option<(wl_surface::WlSurface, (f64, f64))>,
+    held_buttons: Vec<u32>,
+    press: Option<Press>,
+    grab_active: bool,
+    callback: Rc<RefCell<PasteCallback<Data>>>,
+}
+
+impl<Data: 'static> MiddleClickPaste<Data> {
+    /// Create a new middle click paste helper
+    ///
+    /// The transfers of the primary selection are done from the provided event loop,
+    /// which invokes the callback once they are complete.
+    pub fn new<F>(loop_handle: &calloop::LoopHandle<Data>, callback: F) -> MiddleClickPaste<Data>
+    where
+        F: FnMut(Option<String>, &wl_surface::WlSurface, (f64, f64), &mut Data) + 'static,
+    {
+        MiddleClickPaste {
+            loop_handle: loop_handle.clone(),
+            surfaces: Vec::new(),
+            focus: None,
+            held_buttons: Vec::new(),
+            press: None,
+            grab_active: false,
+            callback: Rc::new(RefCell::new(callback)),
+        }
+    }
+
+    /// Paste the primary selection on middle clicks on this surface
+    pub fn register_surface(&mut self, surface: &wl_surface::WlSurface) {
+        if !self.surfaces.contains(surface) {
+            self.surfaces.push(surface.clone());
+        }
+    }
+
+    /// Stop pasting the primary selection on middle clicks on this surface
+    pub fn unregister_surface(&mut self, surface: &wl_surface::WlSurface) {
+        self.surfaces.retain(|s| s != surface);
+        if self
+            .press
+            .as_ref()
+            .map(|p| &p.surface == surface)
+            .unwrap_or(false)
+        {
+            self.press = None;
+        }
+    }
+
+    /// Signal whether your application has a grab active
+    ///
+    /// This is typically the case while a popup menu is open. No paste is done
+    /// while a grab is active, and a pending click is forgotten.
+    pub fn set_grab_active(&mut self, active: bool) {
+        self.grab_active = active;
+        if active {
+            self.press = None;
+        }
+    }
+
+    /// Process an event of the pointer
+    ///
+    /// The primary selection is requested from the given seat, which should be the
+    /// seat of the pointer.
+    pub fn handle_event<E: PrimarySelectionHandling>(
+        &mut self,
+        env: &Environment<E>,
+        seat: &wl_seat::WlSeat,
+        event: &wl_pointer::Event,
+    ) {
+        match *event {
+            wl_pointer::Event::Enter {
+                ref surface,
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                self.focus = Some((surface.clone(), (surface_x, surface_y)));
+            }
+            wl_pointer::Event::Leave { .. } => {
+                self.focus = None;
+                self.held_buttons.clear();
+                self.press = None;
+            }
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                if let Some((_, ref mut position)) = self.focus {
+                    *position = (surface_x, surface_y);
+                }
+                let dragged = self.press.as_ref().map(|press| {
+                    let (dx, dy) = (surface_x - press.position.0, surface_y - press.position.1);
+                    dx * dx + dy * dy > DRAG_THRESHOLD * DRAG_THRESHOLD
+                });
+                if dragged == Some(true) {
+                    self.press = None;
+                }
+            }
+            wl_pointer::Event::Button { button, state, .. } => {
+                if state == wl_pointer::ButtonState::Pressed {
+                    // a press while other buttons are held is part of an implicit grab
+                    let grabbed = self.grab_active || !self.held_buttons.is_empty();
+                    if !self.held_buttons.contains(&button) {
+                        self.held_buttons.push(button);
+                    }
+                    if button != BTN_MIDDLE || grabbed {
+                        self.press = None;
+                        return;
+                    }
+                    self.press = match self.focus {
+                        Some((ref surface, position)) if self.surfaces.contains(surface) => {
+                            Some(Press {
+                                surface: surface.clone(),
+                                position,
+                            })
+                        }
+                        _ => None,
+                    };
+                } else if state == wl_pointer::ButtonState::Released {
+                    self.held_buttons.retain(|&b| b != button);
+                    if button == BTN_MIDDLE {
+                        if let Some(press) = self.press.take() {
+                            self.paste(env, seat, press);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn paste<E: PrimarySelectionHandling>(
+        &self,
+        env: &Environment<E>,
+        seat: &wl_seat::WlSeat,
+        press: Press,
+    ) {
+        let callback = self.callback.clone();
+        let Press { surface, position } = press;
+        let my_surface = surface.clone();
+        let ret = env.request_primary_paste_text(&self.loop_handle, seat, move |text, data| {
+            let text = match text {
+                Ok(text) => Some(text),
+                Err(err) => {
+                    log::warn!("[SCTK] Failed to paste the primary selection: {}", err);
+                    None
+                }
+            };
+            (&mut *callback.borrow_mut())(text, &my_surface, position, data);
+        });
+        if let Err(err) = ret {
+            // nothing to paste, the callback is still invoked from the event loop
+            log::debug!(
+                "[SCTK] Nothing to paste from the primary selection: {}",
+                err
+            );
+            let callback = self.callback.clone();
+            self.loop_handle.insert_idle(move |data| {
+                (&mut *callback.borrow_mut())(None, &surface, position, data);
+                crate::event_loop::flush_connections();
+            });
+        }
+    }
+}
@@ -1,7 +1,14 @@
 //! Helpers to handle primary selection related actions.
 //!
-//! If you're not using [`default_environment!`](../macro.default_environment.html) you should
-//! call `get_primary_selection_manager` to bind proper primary selection manager.
+//! Two protocols provide the primary selection: the standard
+//! `zwp_primary_selection_device_manager_v1`, and the older
+//! `gtk_primary_selection_device_manager`, which is the only one exposed by older versions of
+//! Mutter and some embedded compositors. Both are handled behind the same devices, offers and
+//! sources, the standard one being used when the compositor advertises both.
+//!
+//! The manager is bound on first use, by `get_primary_selection_manager` or
+//! `with_primary_selection`. The [`default_environment!`](../macro.default_environment.html)
+//! binds it right away, so that the selection of each seat is tracked from the start.
 
 use std::{cell::RefCell, rc::Rc};
 
@@ -13,15 +20,21 @@ use wayland_client::{
     Attached, DispatchData,
 };
 
+use crate::data_device::DataDeviceError;
 use crate::environment::GlobalHandler;
 use crate::lazy_global::LazyGlobal;
 use crate::seat::{SeatHandling, SeatListener};
 
+mod clipboard;
 mod device;
+#[cfg(feature = "calloop")]
+mod middle_click;
 mod offer;
 mod source;
 
 pub use self::device::PrimarySelectionDevice;
+#[cfg(feature = "calloop")]
+pub use self::middle_click::MiddleClickPaste;
 pub use self::offer::PrimarySelectionOffer;
 pub use self::source::{PrimarySelectionSource, PrimarySelectionSourceEvent};
 
@@ -55,6 +68,7 @@ impl PrimarySelectionHandler {
             zwp_mgr: LazyGlobal::Unknown,
             gtk_mgr: LazyGlobal::Unknown,
             state: PrimarySelectionDeviceManagerInitState::Pending { seats: Vec::new() },
+            lost_callback: Rc::new(RefCell::new(Box::new(|_, _| {}))),
         }));
 
         // Listen for a new seat events to add new primary selection devices on the fly.
@@ -95,6 +109,16 @@ pub trait PrimarySelectionHandling {
     ///
     /// Returns `None` if no primary selection device manager was advertised.
     fn get_primary_selection_manager(&self) -> Option<PrimarySelectionDeviceManager>;
+
+    /// Set the callback invoked when another client replaces the primary selection you set.
+    ///
+    /// Returns an error if primary selection is not handled by this environment.
+    fn set_primary_selection_lost_callback<F>(
+        &mut self,
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(WlSeat, DispatchData) + 'static;
 }
 
 impl<E: PrimarySelectionHandling> crate::environment::Environment<E> {
@@ -141,6 +165,33 @@ impl<E: PrimarySelectionHandling> crate::environment::Environment<E> {
 
         PrimarySelectionSource::new(&manager, mime_types, callback)
     }
+
+    /// Set the callback invoked when you lose the ownership of the primary selection of a seat.
+    ///
+    /// This is the counterpart of
+    /// [`set_clipboard_lost_callback`](#method.set_clipboard_lost_callback) for the primary
+    /// selection: it is invoked once another client replaces the primary selection you set,
+    /// and you can set it again from the callback.
+    ///
+    /// Returns an error if primary selection is not handled by this environment.
+    pub fn set_primary_selection_lost_callback<F>(
+        &mut self,
+        callback: F,
+    ) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(WlSeat, DispatchData) + 'static,
+    {
+        self.with_inner(|inner| inner.set_primary_selection_lost_callback(callback))
+    }
+
+    /// Whether the primary selection of a seat currently holds data you put there.
+    ///
+    /// Returns `false` if primary selection is not supported.
+    pub fn owns_primary_selection(&self, seat: &WlSeat) -> bool {
+        let mut owned = false;
+        let _ = self.with_primary_selection(seat, |device| owned = device.owns_selection());
+        owned
+    }
 }
 
 impl PrimarySelectionHandling for PrimarySelectionHandler {
@@ -167,8 +218,18 @@ impl PrimarySelectionHandling for PrimarySelectionHandler {
         seat: &WlSeat,
         f: F,
     ) -> Result<(), ()> {
+        // bind the manager if it was not done yet, the devices are created with it
+        self.get_primary_selection_manager();
         self.inner.borrow().with_primary_selection(seat, f)
     }
+
+    fn set_primary_selection_lost_callback<F>(&mut self, callback: F) -> Result<(), DataDeviceError>
+    where
+        F: FnMut(WlSeat, DispatchData) + 'static,
+    {
+        *self.inner.borrow().lost_callback.borrow_mut() = Box::new(callback);
+        Ok(())
+    }
 }
 
 /// Initialization phase of `PrimarySelectionDeviceManagerInner`.
@@ -188,6 +249,22 @@ struct PrimarySelectionDeviceManagerInner {
     zwp_mgr: LazyGlobal<ZwpPrimarySelectionDeviceManagerV1>,
     gtk_mgr: LazyGlobal<GtkPrimarySelectionDeviceManager>,
     pub state: PrimarySelectionDeviceManagerInitState,
+    lost_callback: Rc<RefCell<Box<LostCallback>>>,
+}
+
+type LostCallback = dyn FnMut(WlSeat, DispatchData);
+
+/// Create the primary selection device of a seat, reporting its lost selections.
+fn init_device(
+    manager: &PrimarySelectionDeviceManager,
+    seat: &WlSeat,
+    lost_callback: &Rc<RefCell<Box<LostCallback>>>,
+) -> PrimarySelectionDevice {
+    let lost_seat = seat.clone();
+    let lost_callback = lost_callback.clone();
+    PrimarySelectionDevice::init_with_lost_callback(manager, seat, move |dispatch_data| {
+        (&mut *lost_callback.borrow_mut())(lost_seat.clone(), dispatch_data);
+    })
 }
 
 impl PrimarySelectionDeviceManagerInner {
@@ -206,7 +283,7 @@ impl PrimarySelectionDeviceManagerInner {
 
         // Create primary selection devices for each seat.
         for seat in seats {
-            let device = PrimarySelectionDevice::init_for_seat(&manager, &seat);
+            let device = init_device(&manager, &seat, &self.lost_callback);
             devices.push((seat.clone(), device));
         }
 
@@ -224,7 +301,7 @@ impl PrimarySelectionDeviceManagerInner {
                 }
 
                 // Initialize primary selection device for a new seat.
-                let device = PrimarySelectionDevice::init_for_seat(&manager, seat);
+                let device = init_device(&manager, seat, &self.lost_callback);
 
                 devices.push((seat.clone(), device));
             }
@@ -1,4 +1,3 @@
-use std::os::unix::io::FromRawFd;
 use std::sync::{Arc, Mutex};
 
 use wayland_client::Main;
@@ -12,7 +11,7 @@ use wayland_protocols::{
     },
 };
 
-use crate::data_device::ReadPipe;
+use crate::data_device::{receive_pipe, ReadPipe};
 
 /// A primary selection offer for receiving data through copy/paste.
 pub struct PrimarySelectionOffer {
@@ -35,25 +34,11 @@ impl PrimarySelectionOffer {
     /// Note that you should **not** read the contents right away in a blocking way,
     /// as you may deadlock your application.
     pub fn receive(&self, mime_type: String) -> Result<ReadPipe, ()> {
-        use nix::fcntl::OFlag;
-        use nix::unistd::{close, pipe2};
-        // create a pipe
-        let (readfd, writefd) = pipe2(OFlag::O_CLOEXEC).map_err(|_| ())?;
-
-        match &self.offer {
-            PrimarySelectionOfferImpl::Zwp(offer) => {
-                offer.receive(mime_type, writefd);
-            }
-            PrimarySelectionOfferImpl::Gtk(offer) => {
-                offer.receive(mime_type, writefd);
-            }
-        }
-
-        if let Err(err) = close(writefd) {
-            log::warn!("Failed to close write pipe: {}", err);
-        }
-
-        Ok(unsafe { FromRawFd::from_raw_fd(readfd) })
+        receive_pipe(|fd| match &self.offer {
+            PrimarySelectionOfferImpl::Zwp(offer) => offer.receive(mime_type, fd),
+            PrimarySelectionOfferImpl::Gtk(offer) => offer.receive(mime_type, fd),
+        })
+        .map_err(|_| ())
     }
 
     /// Initialize `PrimarySelectionOffer` from the `Zwp` offer.
@@ -6,9 +6,9 @@ use wayland_protocols::misc::gtk_primary_selection::client::gtk_primary_selectio
     self, GtkPrimarySelectionSource,
 };
 
-use crate::data_device::WritePipe;
+use crate::data_device::{CancelHook, WritePipe};
 
-use std::os::unix::io::FromRawFd;
+use std::{cell::RefCell, os::unix::io::FromRawFd, rc::Rc};
 
 use wayland_client::DispatchData;
 
@@ -17,6 +17,7 @@ use super::PrimarySelectionDeviceManager;
 /// A primary selection source for sending data through copy/paste.
 pub struct PrimarySelectionSource {
     pub(crate) source: PrimarySelectionSourceImpl,
+    pub(crate) cancel_hook: CancelHook,
 }
 
 /// Possible events a primary selection source needs to react to.
@@ -52,11 +53,19 @@ impl PrimarySelectionSource {
         S: Into<String>,
         It: IntoIterator<Item = S>,
     {
+        let cancel_hook: CancelHook = Rc::new(RefCell::new(None));
+        let cancel_hook_2 = cancel_hook.clone();
         match manager {
             PrimarySelectionDeviceManager::Zwp(ref manager) => {
                 let source = manager.create_source();
                 source.quick_assign(move |source, event, dispatch_data| {
-                    zwp_primary_source_imp(&source, event, dispatch_data, &mut callback);
+                    zwp_primary_source_imp(
+                        &source,
+                        event,
+                        &cancel_hook_2,
+                        dispatch_data,
+                        &mut callback,
+                    );
                 });
 
                 for mime in mime_types {
@@ -65,12 +74,19 @@ impl PrimarySelectionSource {
 
                 Self {
                     source: PrimarySelectionSourceImpl::Zwp(source.detach()),
+                    cancel_hook,
                 }
             }
             PrimarySelectionDeviceManager::Gtk(ref manager) => {
                 let source = manager.create_source();
                 source.quick_assign(move |source, event, dispatch_data| {
-                    gtk_primary_source_imp(&source, event, dispatch_data, &mut callback);
+                    gtk_primary_source_imp(
+                        &source,
+                        event,
+                        &cancel_hook_2,
+                        dispatch_data,
+                        &mut callback,
+                    );
                 });
 
                 for mime in mime_types {
@@ -79,6 +95,7 @@ impl PrimarySelectionSource {
 
                 Self {
                     source: PrimarySelectionSourceImpl::Gtk(source.detach()),
+                    cancel_hook,
                 }
             }
         }
@@ -94,7 +111,8 @@ pub(crate) enum PrimarySelectionSourceImpl {
 fn gtk_primary_source_imp<Impl>(
     source: &GtkPrimarySelectionSource,
     event: gtk_primary_selection_source::Event,
-    dispatch_data: DispatchData,
+    cancel_hook: &CancelHook,
+    mut dispatch_data: DispatchData,
     implem: &mut Impl,
 ) where
     Impl: FnMut(PrimarySelectionSourceEvent, DispatchData),
@@ -107,6 +125,11 @@ fn gtk_primary_source_imp<Impl>(
         },
         Event::Cancelled => {
             source.destroy();
+            // the hook is taken out first, so that it can set a new selection
+            let hook = cancel_hook.borrow_mut().take();
+            if let Some(hook) = hook {
+                hook(dispatch_data.reborrow());
+            }
             PrimarySelectionSourceEvent::Cancelled
         }
         _ => unreachable!(),
@@ -118,7 +141,8 @@ fn gtk_primary_source_imp<Impl>(
 fn zwp_primary_source_imp<Impl>(
     source: &ZwpPrimarySelectionSourceV1,
     event: zwp_primary_selection_source_v1::Event,
-    dispatch_data: DispatchData,
+    cancel_hook: &CancelHook,
+    mut dispatch_data: DispatchData,
     implem: &mut Impl,
 ) where
     Impl: FnMut(PrimarySelectionSourceEvent, DispatchData),
@@ -131,6 +155,11 @@ fn zwp_primary_source_imp<Impl>(
         },
         Event::Cancelled => {
             source.destroy();
+            // the hook is taken out first, so that it can set a new selection
+            let hook = cancel_hook.borrow_mut().take();
+            if let Some(hook) = hook {
+                hook(dispatch_data.reborrow());
+            }
             PrimarySelectionSourceEvent::Cancelled
         }
         _ => unreachable!(),
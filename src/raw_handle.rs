@@ -0,0 +1,57 @@
+//! Raw handles to the surfaces, for graphics APIs
+//!
+//! Graphics libraries like wgpu, glutin or Vulkan loaders create their swapchains from the
+//! `wl_display` and `wl_surface` pointers of libwayland, which they get through the
+//! [`raw-window-handle`](https://crates.io/crates/raw-window-handle) traits.
+//! [`RawSurfaceHandle`](struct.RawSurfaceHandle.html) implements them for any surface,
+//! and [`Window::raw_handle`](../window/struct.Window.html#method.raw_handle) for the
+//! content surface of a window.
+//!
+//! SCTK never commits the surfaces you give it the handle of: the decorations of a window
+//! are drawn in synchronized subsurfaces, which are applied when your renderer commits the
+//! content surface. The renderer thus owns the commits of the surface, and the state changes
+//! requested through SCTK (like `Window::resize`) are applied on its next commit.
+//!
+//! **Note:** This requires the `raw_handle` cargo feature, which makes `wayland-client` use
+//! the system libwayland.
+
+use std::os::raw::c_void;
+
+use raw_window_handle::{unix::WaylandHandle, HasRawWindowHandle, RawWindowHandle};
+use wayland_client::protocol::{wl_display, wl_surface};
+
+/// A raw handle to a surface
+///
+/// This holds the `wl_display` and `wl_surface` pointers of a surface, and implements
+/// `HasRawWindowHandle`. The handle keeps the proxies alive, but the surface must not
+/// be destroyed, and the connection must stay open, for as long as the graphics API
+/// uses the handle.
+pub struct RawSurfaceHandle {
+    display: wl_display::WlDisplay,
+    surface: wl_surface::WlSurface,
+}
+
+impl RawSurfaceHandle {
+    /// Create the raw handle of a surface of this display
+    pub fn new(display: &wl_display::WlDisplay, surface: &wl_surface::WlSurface) -> Self {
+        RawSurfaceHandle {
+            display: display.clone(),
+            surface: surface.clone(),
+        }
+    }
+
+    /// Access the surface
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+}
+
+unsafe impl HasRawWindowHandle for RawSurfaceHandle {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        RawWindowHandle::Wayland(WaylandHandle {
+            surface: self.surface.as_ref().c_ptr() as *mut c_void,
+            display: self.display.as_ref().c_ptr() as *mut c_void,
+            ..WaylandHandle::empty()
+        })
+    }
+}
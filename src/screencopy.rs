@@ -0,0 +1,459 @@
+//! Helpers to capture the content of outputs with `zwlr_screencopy_manager_v1`
+//!
+//! A [`CaptureSession`](struct.CaptureSession.html) copies the content of an output, or of a
+//! region of it, into a [`MemPool`](../shm/struct.MemPool.html), and gives you the resulting
+//! [`Frame`](struct.Frame.html). In the `CaptureMode::WithDamage` mode, it keeps capturing the
+//! output each time its content changes, which is what screen recorders need.
+//!
+//! This protocol is not part of the [`default_environment!`](../macro.default_environment.html),
+//! you need to add the `zwlr_screencopy_manager_v1` global to your environment, for example as a
+//! [`SimpleGlobal`](../environment/struct.SimpleGlobal.html).
+//!
+//! Only the shared memory buffers of versions 1 and 2 of the protocol are supported.
+
+use std::{
+    cell::RefCell,
+    fmt, io,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use wayland_client::{
+    protocol::{wl_buffer, wl_output, wl_shm},
+    Attached, DispatchData,
+};
+
+use wayland_protocols::wlr::unstable::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler};
+use crate::output::{with_output_info, OutputHandling, OutputStatusListener};
+use crate::shm::MemPool;
+
+/// How the output is captured
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// Capture a single frame
+    Once,
+    /// Capture a new frame each time the content of the output changes
+    ///
+    /// The captures reuse the same buffer, and the damage of each frame tells you which
+    /// areas changed since the previous one. This requires version 2 of the
+    /// `zwlr_screencopy_manager_v1` global, with version 1 the frames are captured
+    /// continuously and entirely damaged.
+    WithDamage,
+}
+
+/// A damaged area of a frame, in buffer coordinates
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Damage {
+    /// The x coordinate of the area
+    pub x: u32,
+    /// The y coordinate of the area
+    pub y: u32,
+    /// The width of the area
+    pub width: u32,
+    /// The height of the area
+    pub height: u32,
+}
+
+/// A captured frame
+pub struct Frame<'a> {
+    /// The content of the frame, `stride * height` bytes
+    pub data: &'a [u8],
+    /// The width of the frame, in pixels
+    pub width: u32,
+    /// The height of the frame, in pixels
+    pub height: u32,
+    /// The distance between the beginning of two rows, in bytes
+    pub stride: u32,
+    /// The encoding of the pixels
+    pub format: wl_shm::Format,
+    /// The transform of the output
+    pub transform: wl_output::Transform,
+    /// Whether the rows of the frame are in bottom-to-top order
+    pub y_inverted: bool,
+    /// The areas that changed since the previous frame of the session
+    pub damage: &'a [Damage],
+    /// When the content was captured, as a duration since the epoch of `CLOCK_MONOTONIC`
+    pub time: Duration,
+}
+
+/// An event of a capture session
+pub enum CaptureEvent<'a> {
+    /// A frame was captured
+    Frame(Frame<'a>),
+    /// The compositor failed to capture the output, the session stopped
+    Failed,
+    /// The output was removed, the session stopped
+    OutputRemoved,
+}
+
+/// Possible errors when starting a capture session
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The compositor does not support screen capture, or sharing memory
+    Global(GlobalError),
+    /// Creating the memory pool failed
+    Io(io::Error),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::Global(err) => write!(f, "screen capture is not available: {}", err),
+            CaptureError::Io(err) => write!(f, "failed to create the memory pool: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CaptureError::Global(err) => Some(err),
+            CaptureError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<GlobalError> for CaptureError {
+    fn from(err: GlobalError) -> CaptureError {
+        CaptureError::Global(err)
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(err: io::Error) -> CaptureError {
+        CaptureError::Io(err)
+    }
+}
+
+type CaptureCallback = dyn FnMut(CaptureEvent, DispatchData);
+
+#[derive(Copy, Clone, PartialEq)]
+struct BufferInfo {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+struct Inner {
+    manager: Attached<ZwlrScreencopyManagerV1>,
+    output: wl_output::WlOutput,
+    overlay_cursor: bool,
+    region: Option<(i32, i32, i32, i32)>,
+    mode: CaptureMode,
+    // taken out while the callback reads a frame
+    pool: Option<MemPool>,
+    buffer: Option<(BufferInfo, wl_buffer::WlBuffer)>,
+    frame: Option<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1>,
+    y_inverted: bool,
+    damage: Vec<Damage>,
+    stopped: bool,
+    callback: Rc<RefCell<CaptureCallback>>,
+}
+
+impl Inner {
+    fn stop(&mut self) {
+        self.stopped = true;
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+    }
+
+    // Create a buffer for this frame, reusing the previous one if it matches
+    fn buffer(&mut self, info: BufferInfo) -> io::Result<wl_buffer::WlBuffer> {
+        match self.buffer {
+            Some((ref current, ref buffer)) if *current == info => return Ok(buffer.clone()),
+            _ => {}
+        }
+        if let Some((_, buffer)) = self.buffer.take() {
+            buffer.destroy();
+        }
+        let pool = self
+            .pool
+            .as_mut()
+            .expect("The pool is only taken while delivering a frame.");
+        pool.resize((info.stride * info.height) as usize)?;
+        let buffer = pool.buffer(
+            0,
+            info.width as i32,
+            info.height as i32,
+            info.stride as i32,
+            info.format,
+        );
+        self.buffer = Some((info, buffer.clone()));
+        Ok(buffer)
+    }
+}
+
+/// A running capture of an output
+///
+/// Dropping it stops the capture.
+pub struct CaptureSession {
+    inner: Rc<RefCell<Inner>>,
+    _output_listener: OutputStatusListener,
+}
+
+impl Drop for CaptureSession {
+    fn drop(&mut self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.stop();
+        if let Some((_, buffer)) = inner.buffer.take() {
+            buffer.destroy();
+        }
+    }
+}
+
+fn request_frame(inner: &Rc<RefCell<Inner>>) {
+    let mut guard = inner.borrow_mut();
+    let frame = match guard.region {
+        Some((x, y, width, height)) => guard.manager.capture_output_region(
+            guard.overlay_cursor as i32,
+            &guard.output,
+            x,
+            y,
+            width,
+            height,
+        ),
+        None => guard
+            .manager
+            .capture_output(guard.overlay_cursor as i32, &guard.output),
+    };
+    guard.y_inverted = false;
+    guard.damage.clear();
+    let weak = Rc::downgrade(inner);
+    frame.quick_assign(move |frame, event, ddata| {
+        if let Some(inner) = weak.upgrade() {
+            frame_event(&inner, &frame, event, ddata);
+        }
+    });
+    guard.frame = Some(frame.detach());
+}
+
+fn frame_event(
+    inner: &Rc<RefCell<Inner>>,
+    frame: &zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+    event: zwlr_screencopy_frame_v1::Event,
+    ddata: DispatchData,
+) {
+    use self::zwlr_screencopy_frame_v1::Event;
+    let mut guard = inner.borrow_mut();
+    if guard.stopped {
+        return;
+    }
+    match event {
+        Event::Buffer {
+            format,
+            width,
+            height,
+            stride,
+        } => {
+            let format = wl_shm::Format::from_raw(format);
+            let buffer = format.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Unknown buffer format.")
+            });
+            let buffer = buffer.and_then(|format| {
+                guard.buffer(BufferInfo {
+                    format,
+                    width,
+                    height,
+                    stride,
+                })
+            });
+            match buffer {
+                Ok(buffer) => {
+                    if guard.mode == CaptureMode::WithDamage && frame.as_ref().version() >= 2 {
+                        frame.copy_with_damage(&buffer);
+                    } else {
+                        frame.copy(&buffer);
+                    }
+                }
+                Err(err) => {
+                    log::warn!(
+                        "[SCTK] Failed to allocate a buffer for screen capture: {}",
+                        err
+                    );
+                    guard.stop();
+                    let callback = guard.callback.clone();
+                    drop(guard);
+                    (*callback.borrow_mut())(CaptureEvent::Failed, ddata);
+                }
+            }
+        }
+        Event::Flags { flags } => {
+            guard.y_inverted = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert);
+        }
+        Event::Damage {
+            x,
+            y,
+            width,
+            height,
+        } => guard.damage.push(Damage {
+            x,
+            y,
+            width,
+            height,
+        }),
+        Event::Ready {
+            tv_sec_hi,
+            tv_sec_lo,
+            tv_nsec,
+        } => {
+            if let Some(frame) = guard.frame.take() {
+                frame.destroy();
+            }
+            let info = match guard.buffer {
+                Some((info, _)) => info,
+                None => return,
+            };
+            if frame.as_ref().version() < 2 || guard.mode == CaptureMode::Once {
+                // the whole frame is new
+                guard.damage = vec![Damage {
+                    x: 0,
+                    y: 0,
+                    width: info.width,
+                    height: info.height,
+                }];
+            }
+            let mut pool = guard
+                .pool
+                .take()
+                .expect("The pool is only taken while delivering a frame.");
+            let damage = std::mem::take(&mut guard.damage);
+            let transform = with_output_info(&guard.output, |info| info.transform)
+                .unwrap_or(wl_output::Transform::Normal);
+            let y_inverted = guard.y_inverted;
+            let callback = guard.callback.clone();
+            // the callback may drop the session
+            drop(guard);
+            {
+                let len = (info.stride * info.height) as usize;
+                let data = &pool.mmap()[..len];
+                let frame = Frame {
+                    data,
+                    width: info.width,
+                    height: info.height,
+                    stride: info.stride,
+                    format: info.format,
+                    transform,
+                    y_inverted,
+                    damage: &damage,
+                    time: Duration::new(((tv_sec_hi as u64) << 32) | tv_sec_lo as u64, tv_nsec),
+                };
+                (*callback.borrow_mut())(CaptureEvent::Frame(frame), ddata);
+            }
+            let mut guard = inner.borrow_mut();
+            guard.pool = Some(pool);
+            let restart = !guard.stopped && guard.mode == CaptureMode::WithDamage;
+            drop(guard);
+            if restart {
+                request_frame(inner);
+            } else {
+                inner.borrow_mut().stopped = true;
+            }
+        }
+        Event::Failed => {
+            guard.stop();
+            let callback = guard.callback.clone();
+            drop(guard);
+            (*callback.borrow_mut())(CaptureEvent::Failed, ddata);
+        }
+        _ => unreachable!(),
+    }
+}
+
+impl<E> Environment<E>
+where
+    E: GlobalHandler<ZwlrScreencopyManagerV1> + GlobalHandler<wl_shm::WlShm> + OutputHandling,
+{
+    /// Capture the content of an output
+    ///
+    /// The callback receives the captured frames, or the reason why the session stopped. If
+    /// `overlay_cursor` is `true`, the cursor is included in the frames.
+    pub fn capture_output<F>(
+        &self,
+        output: &wl_output::WlOutput,
+        overlay_cursor: bool,
+        mode: CaptureMode,
+        callback: F,
+    ) -> Result<CaptureSession, CaptureError>
+    where
+        F: FnMut(CaptureEvent, DispatchData) + 'static,
+    {
+        self.start_capture(output, overlay_cursor, None, mode, callback)
+    }
+
+    /// Capture the content of a region of an output
+    ///
+    /// This is similar to [`capture_output`](#method.capture_output), the region being given
+    /// as `(x, y, width, height)` in the logical coordinates of the output.
+    pub fn capture_output_region<F>(
+        &self,
+        output: &wl_output::WlOutput,
+        overlay_cursor: bool,
+        region: (i32, i32, i32, i32),
+        mode: CaptureMode,
+        callback: F,
+    ) -> Result<CaptureSession, CaptureError>
+    where
+        F: FnMut(CaptureEvent, DispatchData) + 'static,
+    {
+        self.start_capture(output, overlay_cursor, Some(region), mode, callback)
+    }
+
+    fn start_capture<F>(
+        &self,
+        output: &wl_output::WlOutput,
+        overlay_cursor: bool,
+        region: Option<(i32, i32, i32, i32)>,
+        mode: CaptureMode,
+        callback: F,
+    ) -> Result<CaptureSession, CaptureError>
+    where
+        F: FnMut(CaptureEvent, DispatchData) + 'static,
+    {
+        let manager = self.try_get_global::<ZwlrScreencopyManagerV1>()?;
+        let shm = self.try_get_global::<wl_shm::WlShm>()?;
+        let pool = MemPool::new(shm, |_| {})?;
+        let inner = Rc::new(RefCell::new(Inner {
+            manager,
+            output: output.clone(),
+            overlay_cursor,
+            region,
+            mode,
+            pool: Some(pool),
+            buffer: None,
+            frame: None,
+            y_inverted: false,
+            damage: Vec::new(),
+            stopped: false,
+            callback: Rc::new(RefCell::new(callback)),
+        }));
+
+        // the compositor may not fail the capture when the output is removed
+        let weak: Weak<RefCell<Inner>> = Rc::downgrade(&inner);
+        let output_listener = self.listen_for_outputs(move |output, info, ddata| {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let mut guard = inner.borrow_mut();
+            if !info.obsolete || guard.stopped || !guard.output.as_ref().equals(output.as_ref()) {
+                return;
+            }
+            guard.stop();
+            let callback = guard.callback.clone();
+            drop(guard);
+            (*callback.borrow_mut())(CaptureEvent::OutputRemoved, ddata);
+        });
+
+        request_frame(&inner);
+        Ok(CaptureSession {
+            inner,
+            _output_listener: output_listener,
+        })
+    }
+}
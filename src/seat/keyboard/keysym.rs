@@ -0,0 +1,182 @@
+use std::{ffi::CString, fmt};
+
+#[allow(deprecated)]
+use super::{
+    ffi::{xkb_keysym_flags, XKBCOMMON_OPTION},
+    keysyms,
+};
+
+/// A keysym, the symbol produced by a key according to the keymap
+///
+/// The keys used by most applications are available as associated constants, like
+/// `Keysym::ESCAPE`. Other keysyms can be obtained from their name with
+/// [`from_name`](#method.from_name), or from their raw value, as defined by the
+/// `xkbcommon-keysyms.h` header of libxkbcommon.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Keysym(u32);
+
+#[allow(deprecated)]
+impl Keysym {
+    /// The absence of keysym, reported for keys not producing any
+    pub const NO_SYMBOL: Keysym = Keysym(0);
+
+    /// Escape
+    pub const ESCAPE: Keysym = Keysym(keysyms::XKB_KEY_Escape);
+    /// Return, or Enter on the main block of the keyboard
+    pub const RETURN: Keysym = Keysym(keysyms::XKB_KEY_Return);
+    /// Tab
+    pub const TAB: Keysym = Keysym(keysyms::XKB_KEY_Tab);
+    /// Backspace
+    pub const BACKSPACE: Keysym = Keysym(keysyms::XKB_KEY_BackSpace);
+    /// Delete
+    pub const DELETE: Keysym = Keysym(keysyms::XKB_KEY_Delete);
+    /// Insert
+    pub const INSERT: Keysym = Keysym(keysyms::XKB_KEY_Insert);
+    /// Space
+    pub const SPACE: Keysym = Keysym(keysyms::XKB_KEY_space);
+    /// Home
+    pub const HOME: Keysym = Keysym(keysyms::XKB_KEY_Home);
+    /// End
+    pub const END: Keysym = Keysym(keysyms::XKB_KEY_End);
+    /// Page up
+    pub const PAGE_UP: Keysym = Keysym(keysyms::XKB_KEY_Page_Up);
+    /// Page down
+    pub const PAGE_DOWN: Keysym = Keysym(keysyms::XKB_KEY_Page_Down);
+    /// Left arrow
+    pub const LEFT: Keysym = Keysym(keysyms::XKB_KEY_Left);
+    /// Right arrow
+    pub const RIGHT: Keysym = Keysym(keysyms::XKB_KEY_Right);
+    /// Up arrow
+    pub const UP: Keysym = Keysym(keysyms::XKB_KEY_Up);
+    /// Down arrow
+    pub const DOWN: Keysym = Keysym(keysyms::XKB_KEY_Down);
+    /// Menu
+    pub const MENU: Keysym = Keysym(keysyms::XKB_KEY_Menu);
+    /// Print screen
+    pub const PRINT: Keysym = Keysym(keysyms::XKB_KEY_Print);
+    /// Pause
+    pub const PAUSE: Keysym = Keysym(keysyms::XKB_KEY_Pause);
+    /// Enter of the keypad
+    pub const KP_ENTER: Keysym = Keysym(keysyms::XKB_KEY_KP_Enter);
+
+    /// F1
+    pub const F1: Keysym = Keysym(keysyms::XKB_KEY_F1);
+    /// F2
+    pub const F2: Keysym = Keysym(keysyms::XKB_KEY_F2);
+    /// F3
+    pub const F3: Keysym = Keysym(keysyms::XKB_KEY_F3);
+    /// F4
+    pub const F4: Keysym = Keysym(keysyms::XKB_KEY_F4);
+    /// F5
+    pub const F5: Keysym = Keysym(keysyms::XKB_KEY_F5);
+    /// F6
+    pub const F6: Keysym = Keysym(keysyms::XKB_KEY_F6);
+    /// F7
+    pub const F7: Keysym = Keysym(keysyms::XKB_KEY_F7);
+    /// F8
+    pub const F8: Keysym = Keysym(keysyms::XKB_KEY_F8);
+    /// F9
+    pub const F9: Keysym = Keysym(keysyms::XKB_KEY_F9);
+    /// F10
+    pub const F10: Keysym = Keysym(keysyms::XKB_KEY_F10);
+    /// F11
+    pub const F11: Keysym = Keysym(keysyms::XKB_KEY_F11);
+    /// F12
+    pub const F12: Keysym = Keysym(keysyms::XKB_KEY_F12);
+
+    /// Left shift
+    pub const SHIFT_L: Keysym = Keysym(keysyms::XKB_KEY_Shift_L);
+    /// Right shift
+    pub const SHIFT_R: Keysym = Keysym(keysyms::XKB_KEY_Shift_R);
+    /// Left control
+    pub const CONTROL_L: Keysym = Keysym(keysyms::XKB_KEY_Control_L);
+    /// Right control
+    pub const CONTROL_R: Keysym = Keysym(keysyms::XKB_KEY_Control_R);
+    /// Left alt
+    pub const ALT_L: Keysym = Keysym(keysyms::XKB_KEY_Alt_L);
+    /// Right alt
+    pub const ALT_R: Keysym = Keysym(keysyms::XKB_KEY_Alt_R);
+    /// Left super, the "logo" key
+    pub const SUPER_L: Keysym = Keysym(keysyms::XKB_KEY_Super_L);
+    /// Right super, the "logo" key
+    pub const SUPER_R: Keysym = Keysym(keysyms::XKB_KEY_Super_R);
+    /// AltGr on most layouts
+    pub const ISO_LEVEL3_SHIFT: Keysym = Keysym(keysyms::XKB_KEY_ISO_Level3_Shift);
+    /// Caps lock
+    pub const CAPS_LOCK: Keysym = Keysym(keysyms::XKB_KEY_Caps_Lock);
+    /// Num lock
+    pub const NUM_LOCK: Keysym = Keysym(keysyms::XKB_KEY_Num_Lock);
+    /// Scroll lock
+    pub const SCROLL_LOCK: Keysym = Keysym(keysyms::XKB_KEY_Scroll_Lock);
+
+    /// The keysym of this raw value
+    pub const fn from_raw(raw: u32) -> Keysym {
+        Keysym(raw)
+    }
+
+    /// The raw value of this keysym
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    /// The keysym of this name, like `"Escape"` or `"KP_7"`
+    ///
+    /// These are the names of the keysyms in the keymaps, without the `XKB_KEY_` prefix of
+    /// the raw constants. Returns `None` if the name is unknown or if libxkbcommon could
+    /// not be loaded.
+    pub fn from_name(name: &str) -> Option<Keysym> {
+        let xkb = XKBCOMMON_OPTION.as_ref()?;
+        let name = CString::new(name).ok()?;
+        let raw = unsafe {
+            (xkb.xkb_keysym_from_name)(name.as_ptr(), xkb_keysym_flags::XKB_KEYSYM_NO_FLAGS)
+        };
+        Some(Keysym(raw)).filter(|&sym| sym != Keysym::NO_SYMBOL)
+    }
+
+    /// Whether this keysym is the one of a modifier key
+    ///
+    /// This includes the shift, control, alt, super, hyper and meta keys, the locks and
+    /// the level and group switches.
+    pub fn is_modifier(self) -> bool {
+        match self.0 {
+            keysyms::XKB_KEY_Shift_L..=keysyms::XKB_KEY_Hyper_R
+            | keysyms::XKB_KEY_ISO_Lock..=keysyms::XKB_KEY_ISO_Level5_Lock
+            | keysyms::XKB_KEY_Mode_switch
+            | keysyms::XKB_KEY_Num_Lock => true,
+            _ => false,
+        }
+    }
+
+    /// The character produced by this keysym, if any
+    ///
+    /// This is the Unicode character the keysym stands for, independently of the state of
+    /// the keyboard: prefer the `utf8` text of the key events to handle text input, which
+    /// accounts for dead keys and compose sequences. Returns `None` for keysyms not
+    /// producing characters, like the arrows, or if libxkbcommon could not be loaded.
+    pub fn to_char(self) -> Option<char> {
+        let xkb = XKBCOMMON_OPTION.as_ref()?;
+        match unsafe { (xkb.xkb_keysym_to_utf32)(self.0) } {
+            0 => None,
+            c => std::char::from_u32(c),
+        }
+    }
+}
+
+impl From<u32> for Keysym {
+    fn from(raw: u32) -> Keysym {
+        Keysym(raw)
+    }
+}
+
+impl From<Keysym> for u32 {
+    fn from(sym: Keysym) -> u32 {
+        sym.0
+    }
+}
+
+impl fmt::Debug for Keysym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keysym({:#x})", self.0)
+    }
+}
@@ -11,10 +11,20 @@
 //! Key repetition relies on an event source, that needs to be inserted in your
 //! calloop event loop. Not doing so will prevent key repetition to work
 //! (but the rest of the functionnality will not be affected).
+//!
+//! If you don't use calloop, [`map_keyboard_repeat_thread`](fn.map_keyboard_repeat_thread.html)
+//! instead handles key repetition with a dedicated thread.
+//!
+//! The text composed by input methods is not delivered to the keyboard callback, see the
+//! [`text_input`](../text_input/index.html) module to receive it.
 
 #[cfg(feature = "calloop")]
 use std::time::Duration;
-use std::{cell::RefCell, os::unix::io::RawFd, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    os::unix::io::RawFd,
+    rc::Rc,
+};
 
 use byteorder::{ByteOrder, NativeEndian};
 
@@ -25,11 +35,19 @@ use wayland_client::{
 };
 
 mod ffi;
-mod state;
+mod keysym;
+#[deprecated(
+    since = "0.10.0",
+    note = "use `Keysym`, with its constants or `Keysym::from_name`"
+)]
 pub mod keysyms;
+mod state;
+mod thread_repeat;
 
+pub use self::keysym::Keysym;
 use self::state::KbState;
 pub use self::state::{ModifiersState, RMLVO};
+pub use self::thread_repeat::RepeatThread;
 
 /// Possible kinds of key repetition
 pub enum RepeatKind {
@@ -53,10 +71,49 @@ pub enum Error {
     BadNames,
     /// The provided seat does not have the keyboard capability
     NoKeyboard,
-    /// Failed to init timers for repetition
+    /// Failed to init timers or the thread for repetition
     TimerError(std::io::Error),
 }
 
+/// An error with the keymap sent by the compositor
+#[derive(Debug)]
+pub enum KeymapError {
+    /// The keymap could not be read from its file descriptor
+    Io(std::io::Error),
+    /// The size advertised by the compositor does not fit in the keymap file
+    BadSize {
+        /// size advertised with the keymap
+        advertised: usize,
+        /// actual size of the keymap file
+        actual: usize,
+    },
+    /// libxkbcommon failed to compile the keymap
+    Invalid,
+}
+
+impl std::fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KeymapError::Io(err) => write!(f, "failed to read the keymap: {}", err),
+            KeymapError::BadSize { advertised, actual } => write!(
+                f,
+                "the keymap size {} does not fit in its file of {} bytes",
+                advertised, actual
+            ),
+            KeymapError::Invalid => write!(f, "the keymap could not be compiled"),
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KeymapError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
 /// Events received from a mapped keyboard
 pub enum Event<'a> {
     /// The keyboard focus has entered a surface
@@ -68,7 +125,7 @@ pub enum Event<'a> {
         /// raw values of the currently pressed keys
         rawkeys: &'a [u32],
         /// interpreted symbols of the currently pressed keys
-        keysyms: &'a [u32],
+        keysyms: &'a [Keysym],
     },
     /// The keyboard focus has left a surface
     Leave {
@@ -91,13 +148,25 @@ pub enum Event<'a> {
         /// raw value of the key
         rawkey: u32,
         /// interpreted symbol of the key
-        keysym: u32,
+        ///
+        /// This is the keysym of the level selected by the current modifiers: for example a
+        /// keypad key gives `KP_7` when num lock is active and `KP_Home` otherwise, or when
+        /// shift is held along num lock.
+        keysym: Keysym,
         /// new state of the key
         state: KeyState,
         /// utf8 interpretation of the entered text
         ///
+        /// Like `keysym`, it depends on the current modifiers, a keypad key giving a digit
+        /// only when num lock is active.
+        ///
         /// will always be `None` on key release events
         utf8: Option<String>,
+        /// whether this event was synthesized when the keyboard focus changed
+        ///
+        /// See [`set_replay_keys_on_focus`](fn.set_replay_keys_on_focus.html). These events
+        /// have a time of 0 and no utf8 text.
+        from_enter: bool,
     },
     /// A key repetition event
     Repeat {
@@ -106,10 +175,75 @@ pub enum Event<'a> {
         /// raw value of the key
         rawkey: u32,
         /// interpreted symbol of the key
-        keysym: u32,
+        keysym: Keysym,
         /// utf8 interpretation of the entered text
         utf8: Option<String>,
     },
+    /// The keymap sent by the compositor could not be loaded
+    ///
+    /// Keys are not interpreted until a valid keymap is received, their events have a
+    /// keysym of `Keysym::NO_SYMBOL` and no utf8 text.
+    KeymapError {
+        /// the reason the keymap was rejected
+        error: KeymapError,
+    },
+}
+
+/// The layouts of the keymap of a keyboard
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyboardLayouts {
+    /// The names of the layouts, such as "English (US)"
+    ///
+    /// They are indexed like the groups sent by the compositor, a layout without a
+    /// name is `None`.
+    pub names: Vec<Option<String>>,
+    /// The index of the currently effective layout
+    pub active: u32,
+}
+
+// The data of the keyboards created by the `map_keyboard` functions
+struct KeyboardData {
+    state: Rc<RefCell<KbState>>,
+    replay_keys: Rc<Cell<bool>>,
+}
+
+/// Access the layouts of a keyboard created by one of the `map_keyboard` functions
+///
+/// The layouts change when the compositor sends a new keymap, and the active layout is
+/// updated before the `Event::Modifiers` event is sent to your callback.
+///
+/// Returns `None` if the keyboard was not created by SCTK or has not received a valid
+/// keymap yet.
+pub fn keyboard_layouts(keyboard: &wl_keyboard::WlKeyboard) -> Option<KeyboardLayouts> {
+    let state = keyboard
+        .as_ref()
+        .user_data()
+        .get::<KeyboardData>()?
+        .state
+        .borrow();
+    if !state.ready() {
+        return None;
+    }
+    Some(KeyboardLayouts {
+        names: state.layout_names(),
+        active: state.active_layout(),
+    })
+}
+
+/// Replay the keys held when the keyboard focus changes
+///
+/// When enabled, the keys already held when the keyboard enters a surface are sent to the
+/// callback as `Event::Key` press events after the `Event::Enter` event, and the keys still
+/// held when it leaves the surface are sent as release events before the `Event::Leave`
+/// event. These events have `from_enter` set to `true`. This way, the state of the keys
+/// tracked by your application cannot get stuck when the focus changes while keys are held.
+///
+/// This is disabled by default, and does nothing if the keyboard was not created by one of
+/// the `map_keyboard` functions.
+pub fn set_replay_keys_on_focus(keyboard: &wl_keyboard::WlKeyboard, enabled: bool) {
+    if let Some(data) = keyboard.as_ref().user_data().get::<KeyboardData>() {
+        data.replay_keys.set(enabled);
+    }
 }
 
 /// Implement a keyboard for keymap translation with key repetition
@@ -145,14 +279,25 @@ where
             .unwrap_or_else(KbState::new)?,
     ));
 
+    let replay_keys = Rc::new(Cell::new(false));
+    let data = KeyboardData {
+        state: state.clone(),
+        replay_keys: replay_keys.clone(),
+    };
+    keyboard.as_ref().user_data().set(move || data);
+
     let callback = Rc::new(RefCell::new(callback));
 
     // prepare the handler
     let mut kbd_handler = KbdHandler {
+        seat: seat.detach(),
+        pressed: HeldKeys::default(),
+        replay_keys,
         callback,
         state,
         #[cfg(feature = "calloop")]
         repeat: None,
+        thread_repeat: None,
     };
 
     keyboard.quick_assign(move |keyboard, event, data| {
@@ -198,24 +343,20 @@ where
             .unwrap_or_else(KbState::new)?,
     ));
 
+    let replay_keys = Rc::new(Cell::new(false));
+    let data = KeyboardData {
+        state: state.clone(),
+        replay_keys: replay_keys.clone(),
+    };
+    keyboard.as_ref().user_data().set(move || data);
+
     let callback = Rc::new(RefCell::new(callback));
 
-    let repeat = match repeatkind {
-        RepeatKind::System => RepeatDetails {
-            locked: false,
-            gap: 100,
-            delay: 300,
-        },
-        RepeatKind::Fixed { rate, delay } => RepeatDetails {
-            locked: true,
-            gap: 1000 / rate,
-            delay,
-        },
-    };
+    let repeat = RepeatDetails::new(repeatkind);
 
     // prepare the repetition handling
     let (mut kbd_handler, source) = {
-        let current_repeat = Rc::new(RefCell::new(None));
+        let current_repeat = Rc::new(RefCell::new(RepeatData::default()));
 
         let source = RepeatSource {
             timer: calloop::timer::Timer::new().map_err(Error::TimerError)?,
@@ -226,6 +367,9 @@ where
         let timer_handle = source.timer.handle();
 
         let handler = KbdHandler {
+            seat: seat.detach(),
+            pressed: HeldKeys::default(),
+            replay_keys,
             callback: callback.clone(),
             state,
             repeat: Some(KbdRepeat {
@@ -233,6 +377,7 @@ where
                 current_repeat,
                 details: repeat,
             }),
+            thread_repeat: None,
         };
         (handler, source)
     };
@@ -243,7 +388,10 @@ where
                 event,
                 kbd.clone(),
                 wayland_client::DispatchData::wrap(ddata),
-            )
+            );
+            // the repetitions are not triggered by Wayland events, send the requests
+            // of the callback right away
+            crate::event_loop::flush_connections();
         })
         .map_err(|e| Error::TimerError(e.error))?;
 
@@ -254,61 +402,293 @@ where
     Ok((keyboard.detach(), source))
 }
 
+/// Implement a keyboard for keymap translation with key repetition handled by a thread
+///
+/// This is similar to [`map_keyboard_repeat`](fn.map_keyboard_repeat.html), but rather than
+/// relying on calloop, the key repetition is driven by a dedicated thread. Each time a key
+/// repetition is due, the thread invokes `waker`, so that you know you need to call
+/// [`RepeatThread::dispatch_pending`](struct.RepeatThread.html#method.dispatch_pending)
+/// from the thread running your Wayland event queue, which then invokes your callback with
+/// the `Event::Repeat` events. The waker is invoked from the repetition thread, it can
+/// for example write to an eventfd polled by your event loop.
+///
+/// Dropping the returned `RepeatThread` stops the key repetition and joins the thread.
+///
+/// Returns an error if xkbcommon could not be initialized, the RMLVO specification
+/// contained invalid values, if the provided seat does not have keyboard capability,
+/// or if the repetition thread could not be spawned.
+pub fn map_keyboard_repeat_thread<F, W>(
+    seat: &Attached<wl_seat::WlSeat>,
+    rmlvo: Option<RMLVO>,
+    repeatkind: RepeatKind,
+    callback: F,
+    waker: W,
+) -> Result<(wl_keyboard::WlKeyboard, RepeatThread), Error>
+where
+    F: FnMut(Event<'_>, wl_keyboard::WlKeyboard, wayland_client::DispatchData<'_>) + 'static,
+    W: Fn() + Send + 'static,
+{
+    let has_kbd = super::with_seat_data(seat, |data| data.has_keyboard).unwrap_or(false);
+    let keyboard = if has_kbd {
+        seat.get_keyboard()
+    } else {
+        return Err(Error::NoKeyboard);
+    };
+
+    let state = Rc::new(RefCell::new(
+        rmlvo
+            .map(KbState::from_rmlvo)
+            .unwrap_or_else(KbState::new)?,
+    ));
+
+    let replay_keys = Rc::new(Cell::new(false));
+    let data = KeyboardData {
+        state: state.clone(),
+        replay_keys: replay_keys.clone(),
+    };
+    keyboard.as_ref().user_data().set(move || data);
+
+    let callback = Rc::new(RefCell::new(callback)) as Rc<RefCell<KbdCallback>>;
+
+    let (repeat_thread, handle) = RepeatThread::spawn(
+        state.clone(),
+        callback.clone(),
+        keyboard.clone().detach(),
+        waker,
+    )
+    .map_err(Error::TimerError)?;
+
+    let mut kbd_handler = KbdHandler {
+        seat: seat.detach(),
+        pressed: HeldKeys::default(),
+        replay_keys,
+        callback,
+        state,
+        #[cfg(feature = "calloop")]
+        repeat: None,
+        thread_repeat: Some((handle, RepeatDetails::new(repeatkind))),
+    };
+
+    keyboard.quick_assign(move |keyboard, event, data| {
+        kbd_handler.event(keyboard.detach(), event, data)
+    });
+
+    Ok((keyboard.detach(), repeat_thread))
+}
+
 /*
  * Classic handling
  */
 
 type KbdCallback = dyn FnMut(Event<'_>, wl_keyboard::WlKeyboard, wayland_client::DispatchData<'_>);
 
-#[cfg(feature = "calloop")]
 struct RepeatDetails {
     locked: bool,
     gap: u32,
     delay: u32,
 }
 
+impl RepeatDetails {
+    fn new(repeatkind: RepeatKind) -> RepeatDetails {
+        match repeatkind {
+            RepeatKind::System => RepeatDetails {
+                locked: false,
+                gap: 100,
+                delay: 300,
+            },
+            RepeatKind::Fixed { rate, delay } => RepeatDetails {
+                locked: true,
+                gap: 1000 / rate,
+                delay,
+            },
+        }
+    }
+
+    // Apply the repeat info sent by the server, unless the repetition is fixed
+    fn set_info(&mut self, rate: i32, delay: i32) {
+        if !self.locked {
+            // a rate of 0 disables the repetition
+            self.gap = if rate > 0 { 1000 / (rate as u32) } else { 0 };
+            self.delay = std::cmp::max(delay, 0) as u32;
+        }
+    }
+}
+
+// A key repetition, independent of how it is timed
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct RepeatingKey {
+    keycode: u32,
+    // repeat gap, in ms
+    gap: u32,
+    // time of the next event, in ms
+    time: u32,
+}
+
+// The rules of the key repetition, shared by the calloop and thread backends, which
+// schedule the repetitions it returns
+//
+// The last pressed key repeats, after the full delay. Releasing it stops the repetition,
+// but releasing another key does not resume a previously pressed one.
+#[derive(Default)]
+struct KeyRepeat {
+    current: Option<RepeatingKey>,
+}
+
+impl KeyRepeat {
+    // Start the repetition of a pressed key, replacing the current one
+    //
+    // Returns the delay before the first repetition, or `None` if the repetition is
+    // disabled.
+    fn press(&mut self, keycode: u32, time: u32, details: &RepeatDetails) -> Option<u32> {
+        if details.gap == 0 {
+            // a repeat rate of 0 disables the repetition
+            self.current = None;
+            return None;
+        }
+        self.current = Some(RepeatingKey {
+            keycode,
+            gap: details.gap,
+            time: time.wrapping_add(details.delay),
+        });
+        Some(details.delay)
+    }
+
+    // Stop the repetition if the released key is the repeating one
+    //
+    // Returns whether the repetition was stopped.
+    fn release(&mut self, keycode: u32) -> bool {
+        if self.current.map(|key| key.keycode) == Some(keycode) {
+            self.current = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Apply new repeat details
+    //
+    // The delay of a pending repetition is kept, but the next repetitions use the new rate.
+    // Returns whether the repetition was stopped, by a rate of 0.
+    fn update(&mut self, details: &RepeatDetails) -> bool {
+        match self.current {
+            Some(_) if details.gap == 0 => {
+                self.current = None;
+                true
+            }
+            Some(ref mut key) => {
+                key.gap = details.gap;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn stop(&mut self) {
+        self.current = None;
+    }
+
+    fn current(&self) -> Option<RepeatingKey> {
+        self.current
+    }
+
+    // Take the repetition which is due, scheduling the next one after its gap
+    fn next(&mut self) -> Option<RepeatingKey> {
+        let key = self.current?;
+        if let Some(ref mut next) = self.current {
+            next.time = next.time.wrapping_add(next.gap);
+        }
+        Some(key)
+    }
+}
+
+// The keys held on the focused surface, replayed when the keyboard focus changes
+//
+// The compositor sends the keys already held when the focus enters a surface, these
+// replace the previous ones, so that a missed `leave` cannot leave a key stuck.
+#[derive(Default)]
+struct HeldKeys {
+    keys: Vec<u32>,
+}
+
+impl HeldKeys {
+    // Replace the held keys with the array of `wl_keyboard::enter`, and return them
+    fn enter(&mut self, keys: &[u8]) -> Vec<u32> {
+        self.keys.clear();
+        for key in keys.chunks_exact(4).map(NativeEndian::read_u32) {
+            self.press(key);
+        }
+        self.keys.clone()
+    }
+
+    fn press(&mut self, key: u32) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    fn release(&mut self, key: u32) {
+        self.keys.retain(|&k| k != key);
+    }
+
+    // Take the keys still held when the focus leaves the surface
+    fn leave(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.keys)
+    }
+}
+
 struct KbdHandler {
+    // the key presses are recorded in the serials of the seat
+    seat: wl_seat::WlSeat,
+    // the keys currently held, and whether to replay them on focus changes
+    pressed: HeldKeys,
+    replay_keys: Rc<Cell<bool>>,
     state: Rc<RefCell<KbState>>,
     callback: Rc<RefCell<KbdCallback>>,
     #[cfg(feature = "calloop")]
     repeat: Option<KbdRepeat>,
+    thread_repeat: Option<(thread_repeat::RepeatHandle, RepeatDetails)>,
 }
 
 #[cfg(feature = "calloop")]
 struct KbdRepeat {
     timer_handle: calloop::timer::TimerHandle<()>,
-    current_repeat: Rc<RefCell<Option<RepeatData>>>,
+    current_repeat: Rc<RefCell<RepeatData>>,
     details: RepeatDetails,
 }
 
 #[cfg(feature = "calloop")]
 impl KbdRepeat {
     fn start_repeat(&self, key: u32, keyboard: wl_keyboard::WlKeyboard, time: u32) {
-        // start a new repetition, overwriting the previous ones
+        // the previous repetitions are overwritten
         self.timer_handle.cancel_all_timeouts();
-        *self.current_repeat.borrow_mut() = Some(RepeatData {
-            keyboard,
-            keycode: key,
-            gap: self.details.gap,
-            time: time + self.details.delay,
-        });
-        self.timer_handle
-            .add_timeout(Duration::from_millis(self.details.delay as u64), ());
+        let mut data = self.current_repeat.borrow_mut();
+        data.keyboard = Some(keyboard);
+        if let Some(delay) = data.repeat.press(key, time, &self.details) {
+            self.timer_handle
+                .add_timeout(Duration::from_millis(delay as u64), ());
+        }
+    }
+
+    fn update_repeat(&self) {
+        if self
+            .current_repeat
+            .borrow_mut()
+            .repeat
+            .update(&self.details)
+        {
+            self.timer_handle.cancel_all_timeouts();
+        }
     }
 
     fn stop_repeat(&self, key: u32) {
-        // only cancel if the released key is the currently repeating key
-        let mut guard = self.current_repeat.borrow_mut();
-        let stop = (*guard).as_ref().map(|d| d.keycode == key).unwrap_or(false);
-        if stop {
+        if self.current_repeat.borrow_mut().repeat.release(key) {
             self.timer_handle.cancel_all_timeouts();
-            *guard = None;
         }
     }
 
     fn stop_all_repeat(&self) {
         self.timer_handle.cancel_all_timeouts();
-        *self.current_repeat.borrow_mut() = None;
+        self.current_repeat.borrow_mut().repeat.stop();
     }
 }
 
@@ -321,8 +701,11 @@ impl KbdHandler {
     ) {
         use wl_keyboard::Event;
 
+        #[cfg(feature = "recording")]
+        super::recording::record_keyboard(&kbd, &event);
+
         match event {
-            Event::Keymap { format, fd, size } => self.keymap(kbd, format, fd, size),
+            Event::Keymap { format, fd, size } => self.keymap(kbd, format, fd, size, dispatch_data),
             Event::Enter {
                 serial,
                 surface,
@@ -334,7 +717,12 @@ impl KbdHandler {
                 time,
                 key,
                 state,
-            } => self.key(kbd, serial, time, key, state, dispatch_data),
+            } => {
+                if state == KeyState::Pressed {
+                    super::record_serial(&self.seat, super::SerialKind::KeyPress, serial);
+                }
+                self.key(kbd, serial, time, key, state, dispatch_data)
+            }
             Event::Modifiers {
                 mods_depressed,
                 mods_latched,
@@ -356,14 +744,16 @@ impl KbdHandler {
 
     fn keymap(
         &mut self,
-        _: wl_keyboard::WlKeyboard,
+        object: wl_keyboard::WlKeyboard,
         format: wl_keyboard::KeymapFormat,
         fd: RawFd,
         size: u32,
+        dispatch_data: wayland_client::DispatchData,
     ) {
         let mut state = self.state.borrow_mut();
         if state.locked() {
             // state is locked, ignore keymap updates
+            let _ = nix::unistd::close(fd);
             return;
         }
         if state.ready() {
@@ -372,14 +762,22 @@ impl KbdHandler {
                 state.de_init();
             }
         }
-        match format {
-            wl_keyboard::KeymapFormat::XkbV1 => unsafe {
-                state.init_with_fd(fd, size as usize);
-            },
+        let result = match format {
+            wl_keyboard::KeymapFormat::XkbV1 => unsafe { state.init_with_fd(fd, size as usize) },
             wl_keyboard::KeymapFormat::NoKeymap => {
                 // TODO: how to handle this (hopefully never occuring) case?
+                let _ = nix::unistd::close(fd);
+                Ok(())
             }
             _ => unreachable!(),
+        };
+        drop(state);
+        if let Err(error) = result {
+            log::warn!(
+                "[SCTK] Invalid keymap received from the compositor: {}",
+                error
+            );
+            (&mut *self.callback.borrow_mut())(Event::KeymapError { error }, object, dispatch_data);
         }
     }
 
@@ -389,24 +787,44 @@ impl KbdHandler {
         serial: u32,
         surface: wl_surface::WlSurface,
         keys: Vec<u8>,
-        dispatch_data: wayland_client::DispatchData,
+        mut dispatch_data: wayland_client::DispatchData,
     ) {
-        let mut state = self.state.borrow_mut();
-        let rawkeys = keys
-            .chunks_exact(4)
-            .map(NativeEndian::read_u32)
-            .collect::<Vec<_>>();
-        let keys: Vec<u32> = rawkeys.iter().map(|k| state.get_one_sym_raw(*k)).collect();
-        (&mut *self.callback.borrow_mut())(
+        let rawkeys = self.pressed.enter(&keys);
+        let keys: Vec<Keysym> = {
+            let mut state = self.state.borrow_mut();
+            rawkeys
+                .iter()
+                .map(|k| Keysym::from_raw(state.get_one_sym_raw(*k)))
+                .collect()
+        };
+        let mut callback = self.callback.borrow_mut();
+        (&mut *callback)(
             Event::Enter {
                 serial,
                 surface,
                 rawkeys: &rawkeys,
                 keysyms: &keys,
             },
-            object,
-            dispatch_data,
+            object.clone(),
+            dispatch_data.reborrow(),
         );
+        if self.replay_keys.get() {
+            for (&rawkey, &keysym) in rawkeys.iter().zip(&keys) {
+                (&mut *callback)(
+                    Event::Key {
+                        serial,
+                        time: 0,
+                        rawkey,
+                        keysym,
+                        state: KeyState::Pressed,
+                        utf8: None,
+                        from_enter: true,
+                    },
+                    object.clone(),
+                    dispatch_data.reborrow(),
+                );
+            }
+        }
     }
 
     fn leave(
@@ -414,7 +832,7 @@ impl KbdHandler {
         object: wl_keyboard::WlKeyboard,
         serial: u32,
         surface: wl_surface::WlSurface,
-        dispatch_data: wayland_client::DispatchData,
+        mut dispatch_data: wayland_client::DispatchData,
     ) {
         #[cfg(feature = "calloop")]
         {
@@ -422,10 +840,32 @@ impl KbdHandler {
                 repeat.stop_all_repeat();
             }
         }
-        (&mut *self.callback.borrow_mut())(Event::Leave { serial, surface }, object, dispatch_data);
+        if let Some((ref handle, _)) = self.thread_repeat {
+            handle.stop_all_repeat();
+        }
+        let pressed = self.pressed.leave();
+        let mut callback = self.callback.borrow_mut();
+        if self.replay_keys.get() {
+            for rawkey in pressed {
+                let keysym = Keysym::from_raw(self.state.borrow_mut().get_one_sym_raw(rawkey));
+                (&mut *callback)(
+                    Event::Key {
+                        serial,
+                        time: 0,
+                        rawkey,
+                        keysym,
+                        state: KeyState::Released,
+                        utf8: None,
+                        from_enter: true,
+                    },
+                    object.clone(),
+                    dispatch_data.reborrow(),
+                );
+            }
+        }
+        (&mut *callback)(Event::Leave { serial, surface }, object, dispatch_data);
     }
 
-    #[cfg_attr(not(feature = "calloop"), allow(unused_variables))]
     fn key(
         &mut self,
         object: wl_keyboard::WlKeyboard,
@@ -472,16 +912,32 @@ impl KbdHandler {
             (sym, utf8, repeats)
         };
 
+        if key_state == wl_keyboard::KeyState::Pressed {
+            self.pressed.press(key);
+        } else {
+            self.pressed.release(key);
+        }
+
         #[cfg(feature = "calloop")]
         {
             if let Some(ref mut repeat_handle) = self.repeat {
-                if repeats {
-                    if key_state == wl_keyboard::KeyState::Pressed {
+                if key_state == wl_keyboard::KeyState::Pressed {
+                    if repeats {
                         repeat_handle.start_repeat(key, object.clone(), time);
-                    } else {
-                        repeat_handle.stop_repeat(key);
                     }
+                } else {
+                    // releasing another key does not resume a previously pressed one
+                    repeat_handle.stop_repeat(key);
+                }
+            }
+        }
+        if let Some((ref handle, ref details)) = self.thread_repeat {
+            if key_state == wl_keyboard::KeyState::Pressed {
+                if repeats {
+                    handle.start_repeat(key, time, details);
                 }
+            } else {
+                handle.stop_repeat(key);
             }
         }
 
@@ -490,9 +946,10 @@ impl KbdHandler {
                 serial,
                 time,
                 rawkey: key,
-                keysym: sym,
+                keysym: Keysym::from_raw(sym),
                 state: key_state,
                 utf8,
+                from_enter: false,
             },
             object,
             dispatch_data,
@@ -508,30 +965,26 @@ impl KbdHandler {
         group: u32,
         dispatch_data: wayland_client::DispatchData,
     ) {
-        {
+        let modifiers = {
             let mut state = self.state.borrow_mut();
             state.update_modifiers(mods_depressed, mods_latched, mods_locked, group);
-            (&mut *self.callback.borrow_mut())(
-                Event::Modifiers {
-                    modifiers: state.mods_state(),
-                },
-                object,
-                dispatch_data,
-            );
-        }
+            state.mods_state()
+        };
+        (&mut *self.callback.borrow_mut())(Event::Modifiers { modifiers }, object, dispatch_data);
     }
 
-    #[cfg_attr(not(feature = "calloop"), allow(unused_variables))]
     fn repeat_info(&mut self, _: wl_keyboard::WlKeyboard, rate: i32, delay: i32) {
         #[cfg(feature = "calloop")]
         {
             if let Some(ref mut repeat_handle) = self.repeat {
-                if !repeat_handle.details.locked {
-                    repeat_handle.details.gap = 1000 / (rate as u32);
-                    repeat_handle.details.delay = delay as u32;
-                }
+                repeat_handle.details.set_info(rate, delay);
+                repeat_handle.update_repeat();
             }
         }
+        if let Some((ref handle, ref mut details)) = self.thread_repeat {
+            details.set_info(rate, delay);
+            handle.update_repeat(details);
+        }
     }
 }
 
@@ -540,13 +993,11 @@ impl KbdHandler {
  */
 
 #[cfg(feature = "calloop")]
+#[derive(Default)]
 struct RepeatData {
-    keyboard: wl_keyboard::WlKeyboard,
-    keycode: u32,
-    // repeat gap, in ms
-    gap: u32,
-    // time of the last event, in ms
-    time: u32,
+    // the keyboard of the repeating key
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    repeat: KeyRepeat,
 }
 
 /// An event source managing the key repetition of a keyboard
@@ -563,7 +1014,7 @@ struct RepeatData {
 pub struct RepeatSource {
     timer: calloop::timer::Timer<()>,
     state: Rc<RefCell<KbState>>,
-    current_repeat: Rc<RefCell<Option<RepeatData>>>,
+    current_repeat: Rc<RefCell<RepeatData>>,
 }
 
 #[cfg(feature = "calloop")]
@@ -585,26 +1036,29 @@ impl calloop::EventSource for RepeatSource {
         let state = &self.state;
         self.timer
             .process_events(readiness, token, |(), timer_handle| {
-                if let Some(ref mut data) = *current_repeat.borrow_mut() {
+                let mut data = current_repeat.borrow_mut();
+                let data = &mut *data;
+                if let (Some(key), Some(keyboard)) = (data.repeat.next(), data.keyboard.as_mut()) {
                     // there is something to repeat
-                    let mut state = state.borrow_mut();
-                    let keysym = state.get_one_sym_raw(data.keycode);
-                    let utf8 = state.get_utf8_raw(data.keycode);
-                    let new_time = data.gap + data.time;
+                    let (keysym, utf8) = {
+                        let mut state = state.borrow_mut();
+                        (
+                            Keysym::from_raw(state.get_one_sym_raw(key.keycode)),
+                            state.get_utf8_raw(key.keycode),
+                        )
+                    };
                     // notify the callback
                     callback(
                         Event::Repeat {
-                            time: new_time,
-                            rawkey: data.keycode,
+                            time: key.time,
+                            rawkey: key.keycode,
                             keysym,
                             utf8,
                         },
-                        &mut data.keyboard,
+                        keyboard,
                     );
-                    // update the time of last event
-                    data.time = new_time;
                     // schedule the next timeout
-                    timer_handle.add_timeout(Duration::from_millis(data.gap as u64), ());
+                    timer_handle.add_timeout(Duration::from_millis(key.gap as u64), ());
                 }
             })
     }
@@ -625,3 +1079,222 @@ impl calloop::EventSource for RepeatSource {
         self.timer.unregister(poll)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u32 = 30;
+    const B: u32 = 48;
+
+    fn details(gap: u32, delay: u32) -> RepeatDetails {
+        RepeatDetails {
+            locked: false,
+            gap,
+            delay,
+        }
+    }
+
+    fn key(keycode: u32, gap: u32, time: u32) -> Option<RepeatingKey> {
+        Some(RepeatingKey { keycode, gap, time })
+    }
+
+    #[test]
+    fn pressed_key_repeats_after_the_delay() {
+        let mut repeat = KeyRepeat::default();
+        assert_eq!(repeat.press(A, 1000, &details(40, 300)), Some(300));
+        assert_eq!(repeat.next(), key(A, 40, 1300));
+        assert_eq!(repeat.next(), key(A, 40, 1340));
+        assert_eq!(repeat.next(), key(A, 40, 1380));
+    }
+
+    #[test]
+    fn repeat_time_wraps() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, u32::max_value() - 100, &details(40, 300));
+        assert_eq!(repeat.next(), key(A, 40, 199));
+    }
+
+    #[test]
+    fn releasing_the_repeating_key_stops() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.next();
+        assert!(repeat.release(A));
+        assert_eq!(repeat.next(), None);
+        // releasing it again has no effect
+        assert!(!repeat.release(A));
+    }
+
+    #[test]
+    fn press_and_release_in_one_batch_never_repeats() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.release(A);
+        // the timer of the press fires afterwards
+        assert_eq!(repeat.next(), None);
+    }
+
+    #[test]
+    fn last_pressed_key_repeats() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.next();
+        // B replaces A, after the full delay
+        assert_eq!(repeat.press(B, 500, &details(40, 300)), Some(300));
+        assert_eq!(repeat.next(), key(B, 40, 800));
+    }
+
+    #[test]
+    fn releasing_another_key_keeps_the_repetition() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.press(B, 100, &details(40, 300));
+        // A was replaced by B, its release does not stop B
+        assert!(!repeat.release(A));
+        assert_eq!(repeat.next(), key(B, 40, 400));
+    }
+
+    #[test]
+    fn releasing_the_last_key_does_not_resume_a_held_one() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.press(B, 100, &details(40, 300));
+        // A is still held, but does not repeat again
+        assert!(repeat.release(B));
+        assert_eq!(repeat.next(), None);
+    }
+
+    #[test]
+    fn stop_cancels_the_repetition() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.stop();
+        assert_eq!(repeat.current(), None);
+        assert_eq!(repeat.next(), None);
+    }
+
+    #[test]
+    fn new_rate_applies_after_the_pending_delay() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        // the repeat info changes between the delay and the first repetition
+        assert!(!repeat.update(&details(20, 600)));
+        assert_eq!(repeat.next(), key(A, 20, 300));
+        assert_eq!(repeat.next(), key(A, 20, 320));
+    }
+
+    #[test]
+    fn new_rate_applies_during_the_repetition() {
+        let mut repeat = KeyRepeat::default();
+        repeat.press(A, 0, &details(40, 300));
+        repeat.next();
+        repeat.update(&details(10, 300));
+        assert_eq!(repeat.next(), key(A, 10, 340));
+        assert_eq!(repeat.next(), key(A, 10, 350));
+    }
+
+    #[test]
+    fn rate_of_zero_disables_the_repetition() {
+        let mut repeat = KeyRepeat::default();
+        assert_eq!(repeat.press(A, 0, &details(0, 300)), None);
+        assert_eq!(repeat.next(), None);
+
+        repeat.press(A, 0, &details(40, 300));
+        assert!(repeat.update(&details(0, 300)));
+        assert_eq!(repeat.next(), None);
+        // without a repetition, there is nothing to stop
+        assert!(!repeat.update(&details(0, 300)));
+    }
+
+    #[test]
+    fn repeat_info_is_converted_to_a_gap() {
+        let mut details = RepeatDetails::new(RepeatKind::System);
+        details.set_info(25, 600);
+        assert_eq!((details.gap, details.delay), (40, 600));
+        details.set_info(0, -1);
+        assert_eq!((details.gap, details.delay), (0, 0));
+    }
+
+    #[test]
+    fn fixed_repeat_ignores_repeat_info() {
+        let mut details = RepeatDetails::new(RepeatKind::Fixed {
+            rate: 50,
+            delay: 200,
+        });
+        details.set_info(10, 900);
+        assert_eq!((details.gap, details.delay), (20, 200));
+    }
+
+    const C: u32 = 46;
+
+    // the keys array of a `wl_keyboard::enter` event
+    fn keys_array(keys: &[u32]) -> Vec<u8> {
+        let mut array = vec![0; keys.len() * 4];
+        for (chunk, &key) in array.chunks_exact_mut(4).zip(keys) {
+            NativeEndian::write_u32(chunk, key);
+        }
+        array
+    }
+
+    #[test]
+    fn keys_held_on_enter_are_released_on_leave() {
+        let mut held = HeldKeys::default();
+        assert_eq!(held.enter(&keys_array(&[A, B])), vec![A, B]);
+        assert_eq!(held.leave(), vec![A, B]);
+        assert_eq!(held.leave(), vec![]);
+    }
+
+    #[test]
+    fn keys_pressed_while_focused_are_released_on_leave() {
+        let mut held = HeldKeys::default();
+        assert_eq!(held.enter(&keys_array(&[A])), vec![A]);
+        held.press(B);
+        held.press(C);
+        held.release(A);
+        // releasing a key which is not held changes nothing
+        held.release(A);
+        assert_eq!(held.leave(), vec![B, C]);
+    }
+
+    #[test]
+    fn repeated_presses_are_held_once() {
+        let mut held = HeldKeys::default();
+        assert_eq!(held.enter(&keys_array(&[A, A])), vec![A]);
+        held.press(A);
+        held.release(A);
+        assert_eq!(held.leave(), vec![]);
+    }
+
+    #[test]
+    fn rapid_focus_bouncing() {
+        let mut held = HeldKeys::default();
+        for _ in 0..10 {
+            assert_eq!(held.enter(&keys_array(&[A, B])), vec![A, B]);
+            assert_eq!(held.leave(), vec![A, B]);
+        }
+        // the keys released while unfocused are no longer sent on enter
+        assert_eq!(held.enter(&keys_array(&[B])), vec![B]);
+        assert_eq!(held.leave(), vec![B]);
+        assert_eq!(held.enter(&keys_array(&[])), vec![]);
+        assert_eq!(held.leave(), vec![]);
+    }
+
+    #[test]
+    fn enter_without_leave_replaces_the_held_keys() {
+        let mut held = HeldKeys::default();
+        held.enter(&keys_array(&[A]));
+        held.press(B);
+        // a key released while the focus bounced must not stay stuck
+        assert_eq!(held.enter(&keys_array(&[C])), vec![C]);
+        assert_eq!(held.leave(), vec![C]);
+    }
+
+    #[test]
+    fn truncated_keys_array() {
+        let mut held = HeldKeys::default();
+        let mut array = keys_array(&[A, B]);
+        array.pop();
+        assert_eq!(held.enter(&array), vec![A]);
+    }
+}
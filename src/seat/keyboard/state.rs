@@ -1,6 +1,6 @@
 use std::{
     env,
-    ffi::CString,
+    ffi::{CStr, CString},
     fs::File,
     os::raw::c_char,
     os::unix::{
@@ -13,7 +13,7 @@ use std::{
 use memmap::MmapOptions;
 
 use super::ffi::{self, xkb_state_component, XKBCOMMON_HANDLE as XKBH};
-use super::Error;
+use super::{Error, KeymapError};
 
 pub(crate) struct KbState {
     xkb_context: *mut ffi::xkb_context,
@@ -152,7 +152,25 @@ impl KbState {
         if !self.ready() {
             return 0;
         }
-        unsafe { (XKBH.xkb_state_key_get_one_sym)(self.xkb_state, keycode + 8) }
+        let sym = unsafe { (XKBH.xkb_state_key_get_one_sym)(self.xkb_state, keycode + 8) };
+        if sym != 0 {
+            return sym;
+        }
+        // keys producing several keysyms at their current level have no single keysym,
+        // report the first one of this level rather than nothing
+        let mut syms: *mut ffi::xkb_keysym_t = ptr::null_mut();
+        let count = unsafe {
+            (XKBH.xkb_state_key_get_syms)(
+                self.xkb_state,
+                keycode + 8,
+                &mut syms as *mut *mut ffi::xkb_keysym_t as *const *mut ffi::xkb_keysym_t,
+            )
+        };
+        if count > 0 && !syms.is_null() {
+            unsafe { *syms }
+        } else {
+            0
+        }
     }
 
     pub(crate) fn get_utf8_raw(&mut self, keycode: u32) -> Option<String> {
@@ -195,6 +213,39 @@ impl KbState {
         Some(unsafe { (XKBH.xkb_compose_state_get_status)(self.xkb_compose_state) })
     }
 
+    pub(crate) fn layout_names(&self) -> Vec<Option<String>> {
+        if !self.ready() {
+            return Vec::new();
+        }
+        let count = unsafe { (XKBH.xkb_keymap_num_layouts)(self.xkb_keymap) };
+        (0..count)
+            .map(|idx| {
+                let name = unsafe { (XKBH.xkb_keymap_layout_get_name)(self.xkb_keymap, idx) };
+                if name.is_null() {
+                    None
+                } else {
+                    Some(
+                        unsafe { CStr::from_ptr(name) }
+                            .to_string_lossy()
+                            .into_owned(),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn active_layout(&self) -> u32 {
+        if !self.ready() {
+            return 0;
+        }
+        unsafe {
+            (XKBH.xkb_state_serialize_layout)(
+                self.xkb_state,
+                xkb_state_component::XKB_STATE_LAYOUT_EFFECTIVE,
+            )
+        }
+    }
+
     pub(crate) fn compose_get_utf8(&mut self) -> Option<String> {
         if !self.ready() || self.xkb_compose_state.is_null() {
             return None;
@@ -263,11 +314,11 @@ impl KbState {
         let options = to_cstring(rmlvo.options)?;
 
         let xkb_names = ffi::xkb_rule_names {
-            rules: rules.map_or(ptr::null(), |s| s.as_ptr()),
-            model: model.map_or(ptr::null(), |s| s.as_ptr()),
-            layout: layout.map_or(ptr::null(), |s| s.as_ptr()),
-            variant: variant.map_or(ptr::null(), |s| s.as_ptr()),
-            options: options.map_or(ptr::null(), |s| s.as_ptr()),
+            rules: rules.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            model: model.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            layout: layout.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            variant: variant.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            options: options.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
         };
 
         unsafe {
@@ -328,24 +379,52 @@ impl KbState {
         self.xkb_keymap = ptr::null_mut();
     }
 
-    pub(crate) unsafe fn init_with_fd(&mut self, fd: RawFd, size: usize) {
+    pub(crate) unsafe fn init_with_fd(
+        &mut self,
+        fd: RawFd,
+        size: usize,
+    ) -> Result<(), KeymapError> {
+        // the fd is closed when the file is dropped, on every path
+        let file = File::from_raw_fd(fd);
+        // a mapping larger than the file would raise SIGBUS when read
+        let actual = file.metadata().map_err(KeymapError::Io)?.len() as usize;
+        if size == 0 || size > actual {
+            return Err(KeymapError::BadSize {
+                advertised: size,
+                actual,
+            });
+        }
+        // the fd must be mapped privately since version 7 of wl_seat
         let map = MmapOptions::new()
             .len(size)
-            .map(&File::from_raw_fd(fd))
-            .unwrap();
+            .map_copy(&file)
+            .map_err(KeymapError::Io)?;
+
+        // the keymap must be NUL-terminated for libxkbcommon, copy it if it is not
+        let owned;
+        let string = if map.contains(&0) {
+            map.as_ptr()
+        } else {
+            let mut buf = Vec::with_capacity(size + 1);
+            buf.extend_from_slice(&map);
+            buf.push(0);
+            owned = buf;
+            owned.as_ptr()
+        };
 
         let xkb_keymap = (XKBH.xkb_keymap_new_from_string)(
             self.xkb_context,
-            map.as_ptr() as *const _,
+            string as *const _,
             ffi::xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
             ffi::xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
         );
 
         if xkb_keymap.is_null() {
-            panic!("Received invalid keymap from compositor.");
+            return Err(KeymapError::Invalid);
         }
 
         self.post_init(xkb_keymap);
+        Ok(())
     }
 
     pub(crate) unsafe fn init_with_rmlvo(
@@ -368,6 +447,9 @@ impl KbState {
     }
 
     pub(crate) unsafe fn key_repeats(&mut self, xkb_keycode_t: ffi::xkb_keycode_t) -> bool {
+        if !self.ready() {
+            return false;
+        }
         (XKBH.xkb_keymap_key_repeats)(self.xkb_keymap, xkb_keycode_t) == 1
     }
 
@@ -398,3 +480,188 @@ impl Drop for KbState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seat::keyboard::Keysym;
+    use std::{io::Write, os::unix::io::IntoRawFd};
+
+    // real modifiers of the core protocol, used by the keymaps of xkeyboard-config
+    const SHIFT: u32 = 1;
+    const NUM_LOCK: u32 = 1 << 4;
+
+    // evdev keycodes
+    const KEY_KP7: u32 = 71;
+    const KEY_KPDOT: u32 = 83;
+
+    fn us_keymap() -> KbState {
+        KbState::from_rmlvo(RMLVO {
+            rules: None,
+            model: None,
+            layout: Some("us".into()),
+            variant: None,
+            options: None,
+        })
+        .expect("the us keymap of xkeyboard-config is required for these tests")
+    }
+
+    fn sym(name: &str) -> u32 {
+        Keysym::from_name(name).unwrap().raw()
+    }
+
+    #[test]
+    fn keypad_without_num_lock() {
+        let mut state = us_keymap();
+        state.update_modifiers(0, 0, 0, 0);
+        assert!(!state.mods_state().num_lock);
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_Home"));
+        assert_eq!(state.get_utf8_raw(KEY_KP7), None);
+    }
+
+    #[test]
+    fn keypad_with_num_lock() {
+        let mut state = us_keymap();
+        state.update_modifiers(0, 0, NUM_LOCK, 0);
+        assert!(state.mods_state().num_lock);
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_7"));
+        assert_eq!(state.get_utf8_raw(KEY_KP7).as_deref(), Some("7"));
+        assert_eq!(state.get_one_sym_raw(KEY_KPDOT), sym("KP_Decimal"));
+    }
+
+    #[test]
+    fn shift_without_num_lock() {
+        let mut state = us_keymap();
+        state.update_modifiers(SHIFT, 0, 0, 0);
+        assert!(state.mods_state().shift);
+        // shift only cancels num lock, it does not select the digits on its own
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_Home"));
+        assert_eq!(state.get_utf8_raw(KEY_KP7), None);
+    }
+
+    #[test]
+    fn shift_with_num_lock() {
+        let mut state = us_keymap();
+        state.update_modifiers(SHIFT, 0, NUM_LOCK, 0);
+        assert!(state.mods_state().shift && state.mods_state().num_lock);
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_Home"));
+        assert_eq!(state.get_utf8_raw(KEY_KP7), None);
+    }
+
+    #[test]
+    fn releasing_num_lock_restores_the_navigation_keysyms() {
+        let mut state = us_keymap();
+        state.update_modifiers(0, 0, NUM_LOCK, 0);
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_7"));
+        state.update_modifiers(0, 0, 0, 0);
+        assert!(!state.mods_state().num_lock);
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_Home"));
+    }
+
+    #[test]
+    fn keypad_keys_repeat() {
+        let mut state = us_keymap();
+        for &num_lock in &[0, NUM_LOCK] {
+            state.update_modifiers(0, 0, num_lock, 0);
+            assert!(unsafe { state.key_repeats(KEY_KP7 + 8) });
+        }
+    }
+
+    const KEYMAP: &[u8] = b"xkb_keymap {
+        xkb_keycodes { include \"evdev+aliases(qwerty)\" };
+        xkb_types { include \"complete\" };
+        xkb_compat { include \"complete\" };
+        xkb_symbols { include \"pc+us\" };
+    };";
+
+    // a keymap file as sent by a compositor, the fd being consumed by `init_with_fd`
+    fn keymap_fd(contents: &[u8]) -> RawFd {
+        let mut file = unsafe { File::from_raw_fd(crate::shm::create_shm_fd().unwrap()) };
+        file.write_all(contents).unwrap();
+        file.into_raw_fd()
+    }
+
+    fn keymap_with_nul() -> Vec<u8> {
+        let mut keymap = KEYMAP.to_vec();
+        keymap.push(0);
+        keymap
+    }
+
+    #[test]
+    fn keymap_from_fd() {
+        let keymap = keymap_with_nul();
+        let mut state = KbState::new().unwrap();
+        unsafe { state.init_with_fd(keymap_fd(&keymap), keymap.len()) }.unwrap();
+        assert!(state.ready());
+        assert_eq!(state.get_one_sym_raw(KEY_KP7), sym("KP_Home"));
+    }
+
+    #[test]
+    fn keymap_without_nul() {
+        let mut state = KbState::new().unwrap();
+        unsafe { state.init_with_fd(keymap_fd(KEYMAP), KEYMAP.len()) }.unwrap();
+        assert!(state.ready());
+    }
+
+    #[test]
+    fn truncated_keymap_file() {
+        let keymap = keymap_with_nul();
+        let truncated = &keymap[..keymap.len() / 2];
+        let mut state = KbState::new().unwrap();
+        match unsafe { state.init_with_fd(keymap_fd(truncated), keymap.len()) } {
+            Err(KeymapError::BadSize { advertised, actual }) => {
+                assert_eq!(advertised, keymap.len());
+                assert_eq!(actual, truncated.len());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!state.ready());
+    }
+
+    #[test]
+    fn empty_keymap_file() {
+        let mut state = KbState::new().unwrap();
+        match unsafe { state.init_with_fd(keymap_fd(&[]), 4096) } {
+            Err(KeymapError::BadSize {
+                advertised: 4096,
+                actual: 0,
+            }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!state.ready());
+    }
+
+    #[test]
+    fn zero_keymap_size() {
+        let keymap = keymap_with_nul();
+        let mut state = KbState::new().unwrap();
+        match unsafe { state.init_with_fd(keymap_fd(&keymap), 0) } {
+            Err(KeymapError::BadSize { advertised: 0, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!state.ready());
+    }
+
+    #[test]
+    fn keymap_size_cutting_the_keymap() {
+        // the advertised size fits in the file, but only covers a part of the keymap
+        let keymap = keymap_with_nul();
+        let mut state = KbState::new().unwrap();
+        match unsafe { state.init_with_fd(keymap_fd(&keymap), keymap.len() / 2) } {
+            Err(KeymapError::Invalid) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!state.ready());
+    }
+
+    #[test]
+    fn garbage_keymap() {
+        let garbage = [0xffu8; 64];
+        let mut state = KbState::new().unwrap();
+        match unsafe { state.init_with_fd(keymap_fd(&garbage), garbage.len()) } {
+            Err(KeymapError::Invalid) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!state.ready());
+    }
+}
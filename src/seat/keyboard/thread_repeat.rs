@@ -0,0 +1,232 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use wayland_client::{protocol::wl_keyboard, DispatchData};
+
+use super::{state::KbState, Event, KbdCallback, KeyRepeat, Keysym, RepeatDetails};
+
+struct Shared {
+    state: Mutex<ThreadState>,
+    condvar: Condvar,
+}
+
+struct ThreadState {
+    repeat: KeyRepeat,
+    // when the next repetition is due
+    deadline: Instant,
+    // incremented for each new repetition, to recognize the ticks of a cancelled one
+    generation: u64,
+    shutdown: bool,
+}
+
+impl ThreadState {
+    // The generation of the current repetition, if any
+    fn current_generation(&self) -> Option<u64> {
+        self.repeat.current().map(|_| self.generation)
+    }
+}
+
+struct Tick {
+    keycode: u32,
+    time: u32,
+    generation: u64,
+}
+
+// What the thread needs to do next
+enum Action {
+    Wait,
+    WaitUntil(Instant),
+    Send(Tick),
+    Exit,
+}
+
+/// A key repetition driven by a dedicated thread
+///
+/// It is given to you by [`map_keyboard_repeat_thread`](fn.map_keyboard_repeat_thread.html)
+/// if you don't use calloop. A thread sleeps until the next repetition of the pressed key,
+/// and notifies you with the waker you provided. You then need to call
+/// [`dispatch_pending`](#method.dispatch_pending) from the thread running your Wayland event
+/// queue, which invokes your keyboard callback with the `Event::Repeat` events.
+///
+/// The repetitions sent by the thread for a key that has since been released, or while
+/// the keyboard focus was lost, are discarded.
+///
+/// Dropping it stops the key repetition, and joins the thread.
+pub struct RepeatThread {
+    shared: Arc<Shared>,
+    receiver: mpsc::Receiver<Tick>,
+    thread: Option<thread::JoinHandle<()>>,
+    state: Rc<RefCell<KbState>>,
+    callback: Rc<RefCell<KbdCallback>>,
+    keyboard: wl_keyboard::WlKeyboard,
+}
+
+impl RepeatThread {
+    pub(super) fn spawn<W>(
+        state: Rc<RefCell<KbState>>,
+        callback: Rc<RefCell<KbdCallback>>,
+        keyboard: wl_keyboard::WlKeyboard,
+        waker: W,
+    ) -> std::io::Result<(RepeatThread, RepeatHandle)>
+    where
+        W: Fn() + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(ThreadState {
+                repeat: KeyRepeat::default(),
+                deadline: Instant::now(),
+                generation: 0,
+                shutdown: false,
+            }),
+            condvar: Condvar::new(),
+        });
+        let (sender, receiver) = mpsc::channel();
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("sctk-key-repeat".into())
+            .spawn(move || run(&thread_shared, &sender, &waker))?;
+        let handle = RepeatHandle {
+            shared: shared.clone(),
+        };
+        Ok((
+            RepeatThread {
+                shared,
+                receiver,
+                thread: Some(thread),
+                state,
+                callback,
+                keyboard,
+            },
+            handle,
+        ))
+    }
+
+    /// Invoke your keyboard callback with the pending key repetitions
+    ///
+    /// The callback is given a `DispatchData` wrapping `data`. Returns the number of
+    /// `Event::Repeat` events delivered.
+    ///
+    /// This must not be called from within your keyboard callback.
+    pub fn dispatch_pending<T: std::any::Any>(&self, data: &mut T) -> u32 {
+        let mut count = 0;
+        while let Ok(tick) = self.receiver.try_recv() {
+            // the key may have been released after this tick was sent
+            let current = self.shared.state.lock().unwrap().current_generation();
+            if current != Some(tick.generation) {
+                continue;
+            }
+            let (keysym, utf8) = {
+                let mut state = self.state.borrow_mut();
+                (
+                    Keysym::from_raw(state.get_one_sym_raw(tick.keycode)),
+                    state.get_utf8_raw(tick.keycode),
+                )
+            };
+            (*self.callback.borrow_mut())(
+                Event::Repeat {
+                    time: tick.time,
+                    rawkey: tick.keycode,
+                    keysym,
+                    utf8,
+                },
+                self.keyboard.clone(),
+                DispatchData::wrap(data),
+            );
+            count += 1;
+        }
+        count
+    }
+}
+
+impl Drop for RepeatThread {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::warn!("[SCTK] The key repetition thread panicked");
+            }
+        }
+    }
+}
+
+// The side of the repetition controlled by the keyboard handler
+pub(super) struct RepeatHandle {
+    shared: Arc<Shared>,
+}
+
+impl RepeatHandle {
+    pub(super) fn start_repeat(&self, key: u32, time: u32, details: &RepeatDetails) {
+        let mut state = self.shared.state.lock().unwrap();
+        // the previous repetitions are overwritten
+        state.generation += 1;
+        if let Some(delay) = state.repeat.press(key, time, details) {
+            state.deadline = Instant::now() + Duration::from_millis(delay as u64);
+        }
+        self.shared.condvar.notify_one();
+    }
+
+    pub(super) fn stop_repeat(&self, key: u32) {
+        if self.shared.state.lock().unwrap().repeat.release(key) {
+            self.shared.condvar.notify_one();
+        }
+    }
+
+    pub(super) fn update_repeat(&self, details: &RepeatDetails) {
+        if self.shared.state.lock().unwrap().repeat.update(details) {
+            self.shared.condvar.notify_one();
+        }
+    }
+
+    pub(super) fn stop_all_repeat(&self) {
+        self.shared.state.lock().unwrap().repeat.stop();
+        self.shared.condvar.notify_one();
+    }
+}
+
+fn run<W: Fn()>(shared: &Shared, sender: &mpsc::Sender<Tick>, waker: &W) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let action = match state.repeat.current() {
+            _ if state.shutdown => Action::Exit,
+            None => Action::Wait,
+            Some(_) if state.deadline > now => Action::WaitUntil(state.deadline),
+            Some(_) => {
+                // the current repetition is due
+                let key = state.repeat.next().unwrap();
+                state.deadline += Duration::from_millis(key.gap as u64);
+                Action::Send(Tick {
+                    keycode: key.keycode,
+                    time: key.time,
+                    generation: state.generation,
+                })
+            }
+        };
+        state = match action {
+            Action::Exit => return,
+            Action::Wait => shared.condvar.wait(state).unwrap(),
+            Action::WaitUntil(deadline) => {
+                shared
+                    .condvar
+                    .wait_timeout(state, deadline - now)
+                    .unwrap()
+                    .0
+            }
+            Action::Send(tick) => {
+                // don't hold the lock while the app is notified
+                drop(state);
+                if sender.send(tick).is_err() {
+                    return;
+                }
+                waker();
+                shared.state.lock().unwrap()
+            }
+        };
+    }
+}
@@ -16,16 +16,22 @@
 
 use std::{
     cell::RefCell,
+    fmt,
     rc::{Rc, Weak},
     sync::Mutex,
 };
 use wayland_client::{
-    protocol::{wl_registry, wl_seat},
+    protocol::{wl_pointer, wl_registry, wl_seat, wl_surface},
     Attached, DispatchData, Main,
 };
 
 pub mod keyboard;
 pub mod pointer;
+#[cfg(feature = "recording")]
+pub mod recording;
+pub mod tablet;
+pub mod text_input;
+pub mod touch;
 
 type SeatCallback = dyn FnMut(Attached<wl_seat::WlSeat>, &SeatData, DispatchData) + 'static;
 
@@ -49,6 +55,12 @@ pub struct SeatData {
     ///
     /// You can thus cleanup all your state associated with this seat.
     pub defunct: bool,
+    /// The latest serials of the input events of this seat
+    pub serials: SerialTracker,
+    /// The surface the pointer of this seat is over
+    ///
+    /// See [`pointer_focus`](fn.pointer_focus.html).
+    pub pointer_focus: Option<PointerFocus>,
 }
 
 impl SeatData {
@@ -59,8 +71,92 @@ impl SeatData {
             has_keyboard: false,
             has_touch: false,
             defunct: false,
+            serials: SerialTracker::default(),
+            pointer_focus: None,
+        }
+    }
+}
+
+/// The surface a pointer is over, and its position
+#[derive(Clone)]
+pub struct PointerFocus {
+    /// The surface the pointer is over
+    pub surface: wl_surface::WlSurface,
+    /// The position of the pointer, in surface-local coordinates
+    pub position: (f64, f64),
+}
+
+impl fmt::Debug for PointerFocus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PointerFocus")
+            .field("surface", &self.surface.as_ref().id())
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+/// The kinds of input events whose serials are tracked
+///
+/// The compositor checks the serial of the requests triggered by user input against the
+/// input events of the seat:
+///
+/// - setting the cursor image requires the serial of the latest `PointerEnter`
+/// - moving or resizing a window, grabbing a popup and starting a drag'n'drop require the
+///   serial of a `PointerButton` or `TouchDown`
+/// - setting the selection requires the serial of a `KeyPress`, `PointerButton` or `TouchDown`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SerialKind {
+    /// The pointer entered a surface
+    PointerEnter,
+    /// A pointer button was pressed
+    PointerButton,
+    /// A key was pressed
+    KeyPress,
+    /// A touch point went down
+    TouchDown,
+    /// Any of the above
+    Any,
+}
+
+/// The latest serials of the input events of a seat
+///
+/// SCTK records the serials of the events received by its helpers: the key presses of
+/// [`map_keyboard`](keyboard/fn.map_keyboard.html) and its variants, and the pointer
+/// enter and button presses of
+/// [`ThemeManager::theme_pointer_with_impl`](pointer/struct.ThemeManager.html#method.theme_pointer_with_impl).
+/// If you handle the devices of the seat yourself, you can record their serials with
+/// [`record_serial`](fn.record_serial.html).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SerialTracker {
+    pointer_enter: Option<u32>,
+    pointer_button: Option<u32>,
+    key_press: Option<u32>,
+    touch_down: Option<u32>,
+    any: Option<u32>,
+}
+
+impl SerialTracker {
+    /// The serial of the latest event of this kind
+    pub fn latest(&self, kind: SerialKind) -> Option<u32> {
+        match kind {
+            SerialKind::PointerEnter => self.pointer_enter,
+            SerialKind::PointerButton => self.pointer_button,
+            SerialKind::KeyPress => self.key_press,
+            SerialKind::TouchDown => self.touch_down,
+            SerialKind::Any => self.any,
         }
     }
+
+    fn record(&mut self, kind: SerialKind, serial: u32) {
+        match kind {
+            SerialKind::PointerEnter => self.pointer_enter = Some(serial),
+            SerialKind::PointerButton => self.pointer_button = Some(serial),
+            SerialKind::KeyPress => self.key_press = Some(serial),
+            SerialKind::TouchDown => self.touch_down = Some(serial),
+            SerialKind::Any => {}
+        }
+        self.any = Some(serial);
+    }
 }
 
 /// A simple handler for seats
@@ -101,12 +197,15 @@ impl crate::environment::MultiGlobalHandler<wl_seat::WlSeat> for SeatHandler {
     ) {
         // Seat is supported up to version 6
         let version = std::cmp::min(version, 6);
+        log::debug!("[SCTK] Bound wl_seat version {}", version);
         let seat = registry.bind::<wl_seat::WlSeat>(version, id);
         seat.as_ref()
             .user_data()
             .set_threadsafe(|| Mutex::new(SeatData::new()));
         let cb_listeners = self.listeners.clone();
         seat.quick_assign(move |seat, event, ddata| {
+            #[cfg(feature = "recording")]
+            recording::record_seat(&seat, &event);
             process_seat_event(seat, event, &cb_listeners, ddata)
         });
         self.seats.push((id, (*seat).clone()));
@@ -120,6 +219,7 @@ impl crate::environment::MultiGlobalHandler<wl_seat::WlSeat> for SeatHandler {
                 // This data must be `Mutex<SeatData>` if this seat is in our vec
                 let data = seat.as_ref().user_data().get::<Mutex<SeatData>>().unwrap();
                 let mut guard = data.lock().unwrap();
+                log::debug!("[SCTK] Seat {:?} was removed", guard.name);
                 guard.defunct = true;
                 // notify the listeners that the seat is dead
                 listeners.retain(|lst| {
@@ -151,7 +251,15 @@ fn process_seat_event(
         match event {
             wl_seat::Event::Name { name } => guard.name = name,
             wl_seat::Event::Capabilities { capabilities } => {
+                log::debug!(
+                    "[SCTK] Capabilities of seat {:?} changed to {:?}",
+                    guard.name,
+                    capabilities
+                );
                 guard.has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+                if !guard.has_pointer {
+                    guard.pointer_focus = None;
+                }
                 guard.has_keyboard = capabilities.contains(wl_seat::Capability::Keyboard);
                 guard.has_touch = capabilities.contains(wl_seat::Capability::Touch);
             }
@@ -207,6 +315,92 @@ pub fn with_seat_data<T, F: FnOnce(&SeatData) -> T>(seat: &wl_seat::WlSeat, f: F
     }
 }
 
+/// Record the serial of an input event of this seat
+///
+/// Recording it with `SerialKind::Any` only updates the latest serial of any kind.
+///
+/// Does nothing if the provided `WlSeat` has not yet been initialized or is not managed by SCTK.
+pub fn record_serial(seat: &wl_seat::WlSeat, kind: SerialKind, serial: u32) {
+    if let Some(ref udata_mutex) = seat.as_ref().user_data().get::<Mutex<SeatData>>() {
+        udata_mutex.lock().unwrap().serials.record(kind, serial);
+    }
+}
+
+/// The serial of the latest input event of this kind on this seat
+///
+/// Returns `None` if no such event was recorded, or if the provided `WlSeat` has not yet been
+/// initialized or is not managed by SCTK.
+pub fn latest_serial(seat: &wl_seat::WlSeat, kind: SerialKind) -> Option<u32> {
+    with_seat_data(seat, |data| data.serials.latest(kind)).flatten()
+}
+
+/// Track the surface the pointer of this seat is over from one of its events
+///
+/// SCTK tracks it from the events received by
+/// [`ThemeManager::theme_pointer_with_impl`](pointer/struct.ThemeManager.html#method.theme_pointer_with_impl)
+/// and its owned variant. If you handle the pointer of the seat yourself, you can forward its
+/// events to this function to make [`pointer_focus`](fn.pointer_focus.html) available. Events
+/// other than `Enter`, `Leave` and `Motion` are ignored.
+///
+/// Does nothing if the provided `WlSeat` has not yet been initialized or is not managed by SCTK.
+pub fn track_pointer_focus(seat: &wl_seat::WlSeat, event: &wl_pointer::Event) {
+    if let Some(ref udata_mutex) = seat.as_ref().user_data().get::<Mutex<SeatData>>() {
+        let mut udata = udata_mutex.lock().unwrap();
+        match *event {
+            wl_pointer::Event::Enter {
+                ref surface,
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                udata.pointer_focus = Some(PointerFocus {
+                    surface: surface.clone(),
+                    position: (surface_x, surface_y),
+                });
+            }
+            wl_pointer::Event::Leave { ref surface, .. } => {
+                let left = udata
+                    .pointer_focus
+                    .as_ref()
+                    .map(|focus| &focus.surface == surface)
+                    .unwrap_or(false);
+                if left {
+                    udata.pointer_focus = None;
+                }
+            }
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                if let Some(ref mut focus) = udata.pointer_focus {
+                    focus.position = (surface_x, surface_y);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The surface the pointer of this seat is over, and its surface-local position
+///
+/// Each surface is reported as itself: when the pointer is over the decorations of a
+/// [`Window`](../window/struct.Window.html), this is the subsurface of the decoration rather
+/// than the content surface. The focus is cleared when the pointer leaves all the surfaces
+/// of your application, or when the seat loses its pointer.
+///
+/// Returns `None` if the pointer is not over any of your surfaces, if its events are not
+/// tracked (see [`track_pointer_focus`](fn.track_pointer_focus.html)), or if the provided
+/// `WlSeat` has not yet been initialized or is not managed by SCTK.
+pub fn pointer_focus(seat: &wl_seat::WlSeat) -> Option<(wl_surface::WlSurface, (f64, f64))> {
+    with_seat_data(seat, |data| {
+        data.pointer_focus
+            .as_ref()
+            .map(|focus| (focus.surface.clone(), focus.position))
+    })
+    .flatten()
+}
+
 /// Trait representing the SeatHandler functions
 ///
 /// Implementing this trait on your inner environment struct used with the
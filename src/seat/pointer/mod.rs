@@ -1,5 +1,12 @@
 //! Utilities to work with pointers and their icons
 
 mod theme;
+mod xcursor;
 
-pub use self::theme::{ThemeManager, ThemeSpec, ThemedPointer};
+pub use self::theme::{
+    CursorImage, CursorPolicy, SizeRounding, ThemeError, ThemeManager, ThemeSpec, ThemedPointer,
+    ThemedTool,
+};
+
+#[cfg(feature = "calloop")]
+pub use self::theme::AnimationScheduling;
@@ -1,13 +1,33 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    fmt,
     ops::Deref,
+    path::Path,
     rc::{Rc, Weak},
 };
+#[cfg(feature = "calloop")]
+use std::{
+    io,
+    time::{Duration, Instant},
+};
 use wayland_client::{
-    protocol::{wl_compositor, wl_pointer, wl_seat, wl_shm, wl_surface},
+    protocol::{wl_buffer, wl_compositor, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
     Attached, DispatchData,
 };
-use wayland_cursor::{Cursor, CursorTheme};
+use wayland_cursor::CursorTheme;
+
+use wayland_protocols::unstable::tablet::v2::client::zwp_tablet_tool_v2;
+use wayland_protocols::viewporter::client::{wp_viewport, wp_viewporter::WpViewporter};
+
+use crate::environment::{has_version, Environment, EnvironmentError, GlobalHandler};
+use crate::output::with_output_info;
+use crate::seat::{record_serial, track_pointer_focus, SerialKind};
+use crate::surface::{
+    get_cursor_policy, get_surface_outputs, get_surface_scale_factor, set_cursor_policy,
+    CompositorCaps, ScaleFactor,
+};
+
+use super::xcursor::{nearest_size, CursorBuffer, Image, XcursorTheme};
 
 /// The specification of a cursor theme to be used by the ThemeManager
 pub enum ThemeSpec<'a> {
@@ -28,8 +48,156 @@ pub enum ThemeSpec<'a> {
     /// `XCURSOR_SIZE` environment variables to figure out the
     /// theme to use.
     System,
+    /// Load the theme stored in this directory
+    ///
+    /// The directory is expected to have the layout of an installed Xcursor
+    /// theme, with the cursor files in its `cursors` subdirectory. The themes
+    /// listed in the `Inherits` key of its `index.theme` file are looked up
+    /// next to it. Cursors which do not exist in the requested size are scaled
+    /// from the nearest available one.
+    Path {
+        /// Path of the theme directory
+        path: &'a Path,
+        /// Base size of the cursor images
+        size: u32,
+    },
+}
+
+/// The pixels of a cursor image
+///
+/// This is a copy of an image of a cursor theme, which can be drawn by your app, for example
+/// for a cursor rendered in software or streamed to a remote desktop client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CursorImage {
+    /// The width of the image, in pixels
+    pub width: u32,
+    /// The height of the image, in pixels
+    pub height: u32,
+    /// The position of the hotspot in the image, in pixels
+    pub hotspot: (u32, u32),
+    /// The duration of this frame of an animated cursor, in milliseconds
+    pub delay: u32,
+    /// The pixels, as 8 bits red, green, blue and alpha components in this order
+    ///
+    /// The rows go from top to bottom without padding, and the color components are
+    /// premultiplied by alpha.
+    pub rgba: Vec<u8>,
+}
+
+impl CursorImage {
+    fn from_image(image: Image) -> CursorImage {
+        let mut rgba = Vec::with_capacity(image.pixels.len() * 4);
+        for pixel in image.pixels {
+            rgba.push((pixel >> 16) as u8);
+            rgba.push((pixel >> 8) as u8);
+            rgba.push(pixel as u8);
+            rgba.push((pixel >> 24) as u8);
+        }
+        CursorImage {
+            width: image.width,
+            height: image.height,
+            hotspot: (image.xhot, image.yhot),
+            delay: image.delay,
+            rgba,
+        }
+    }
+}
+
+/// Possible errors when setting a cursor
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ThemeError {
+    /// The cursor theme does not contain a cursor with this name
+    CursorNotFound,
+    /// Neither the cursor theme nor the fallback themes could be loaded
+    ThemeLoadFailed,
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThemeError::CursorNotFound => write!(f, "the cursor is not part of the theme"),
+            ThemeError::ThemeLoadFailed => write!(f, "failed to load the cursor theme"),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+/// How themed pointers handle the cursor while over a surface
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CursorPolicy {
+    /// The themed pointers set the cursor, this is the default
+    Managed,
+    /// The themed pointers never set the cursor over this surface
+    ///
+    /// Use it for surfaces whose cursor is set by other means. Setting the cursor is
+    /// resumed once the pointer enters a managed surface.
+    Untouched,
+}
+
+impl Default for CursorPolicy {
+    fn default() -> CursorPolicy {
+        CursorPolicy::Managed
+    }
 }
 
+/// How the pixel size of the cursor images is chosen for a fractional scale factor
+///
+/// The base size of the theme multiplied by a fractional scale is usually not a size the theme
+/// ships, the images of the nearest size it has are then scaled to it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SizeRounding {
+    /// Round the scaled size down
+    RoundDown,
+    /// Round the scaled size up
+    RoundUp,
+    /// Round the scaled size to the nearest integer, this is the default
+    Nearest,
+    /// Use the size shipped by the theme nearest to the scaled size, so that the images are
+    /// never scaled
+    ///
+    /// The sizes of the `left_ptr` cursor are used for all the cursors of the theme.
+    SnapToAvailable,
+}
+
+impl Default for SizeRounding {
+    fn default() -> SizeRounding {
+        SizeRounding::Nearest
+    }
+}
+
+/// How the frames of animated cursors are scheduled
+///
+/// See [`ThemeManager::animate_cursors`](struct.ThemeManager.html#method.animate_cursors).
+///
+/// **Note:** This requires the `calloop` cargo feature.
+#[cfg(feature = "calloop")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnimationScheduling {
+    /// Draw the next frame once the compositor sent the frame callback of the current one
+    /// and its delay expired
+    ///
+    /// The compositor sends no frame callback while the cursor is not displayed, so your app
+    /// is not woken up for frames nobody sees, and the frames follow the refresh of the outputs.
+    /// If the frame callbacks stop arriving while the cursor is still set, the frames are drawn
+    /// on a timer until they resume.
+    FrameCallbacks,
+    /// Draw the next frame once the delay of the current one expired
+    Timer,
+}
+
+// The themes tried when the requested one cannot be loaded
+const FALLBACK_THEMES: [&str; 2] = ["default", "Adwaita"];
+
+// The tolerance on fractional scales, so that their rounding errors do not change the size
+// of the cursors
+const SCALE_EPSILON: f64 = 1e-6;
+
+// How long after the delay of a frame its frame callback is waited for, before drawing the
+// next frames on the timer
+#[cfg(feature = "calloop")]
+const FRAME_CALLBACK_GRACE: Duration = Duration::from_millis(200);
+
 /// Wrapper managing a system theme for pointer images
 ///
 /// You can use it to initialize new pointers in order
@@ -42,6 +210,11 @@ pub enum ThemeSpec<'a> {
 pub struct ThemeManager {
     themes: Rc<RefCell<ScaledThemeList>>,
     compositor: Attached<wl_compositor::WlCompositor>,
+    // draw the cursor of a themed device again, returning `false` once it is dropped
+    refreshers: Rc<RefCell<Vec<Box<dyn Fn() -> bool>>>>,
+    surfaces: Rc<RefCell<SurfacePool>>,
+    #[cfg(feature = "calloop")]
+    animator: Rc<RefCell<Option<Animator>>>,
 }
 
 impl ThemeManager {
@@ -56,37 +229,234 @@ impl ThemeManager {
         shm: Attached<wl_shm::WlShm>,
     ) -> ThemeManager {
         ThemeManager {
+            surfaces: Rc::new(RefCell::new(SurfacePool::new(compositor.clone()))),
             compositor,
             themes: Rc::new(RefCell::new(ScaledThemeList::new(theme, shm))),
+            refreshers: Rc::new(RefCell::new(Vec::new())),
+            #[cfg(feature = "calloop")]
+            animator: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Change the cursor theme
+    ///
+    /// The cursors of all the pointers and tools themed by this manager are drawn again with
+    /// the new theme, without waiting for them to be set. Use it when the cursor settings of the
+    /// session change, for example with `ThemeSpec::System` after `XCURSOR_THEME` or
+    /// `XCURSOR_SIZE` were updated, or with the theme and size read from the settings of
+    /// your desktop environment.
+    ///
+    /// The previous themes are unloaded once the compositor no longer uses their images.
+    pub fn set_theme(&self, theme: ThemeSpec) {
+        self.themes.borrow_mut().set_theme(theme);
+        self.refresh_cursors();
+    }
+
+    // Draw the cursors of all the themed devices again
+    fn refresh_cursors(&self) {
+        // no borrow is held while drawing, new devices may be themed from the callbacks
+        let refreshers = std::mem::replace(&mut *self.refreshers.borrow_mut(), Vec::new());
+        let alive = refreshers
+            .into_iter()
+            .filter(|refresh| refresh())
+            .collect::<Vec<_>>();
+        self.refreshers.borrow_mut().extend(alive);
+    }
+
+    fn register_refresh<F>(&self, inner: &Rc<CursorInner>, set_cursor: F)
+    where
+        F: Fn(u32, Option<&wl_surface::WlSurface>, i32, i32) + 'static,
+    {
+        let weak = Rc::downgrade(inner);
+        self.refreshers
+            .borrow_mut()
+            .push(Box::new(move || match weak.upgrade() {
+                Some(inner) => {
+                    inner.refresh(&set_cursor);
+                    true
+                }
+                None => false,
+            }));
+    }
+
+    /// Animate the cursors of the pointers and tools themed by this manager
+    ///
+    /// Only the first frame of the animated cursors, like the `wait` cursor of most themes, is
+    /// drawn until this is called. Their frames are then drawn according to `scheduling`, using
+    /// a timer inserted in the given event loop. The cursors already displayed are animated
+    /// once they are drawn again. This is meant to be called once.
+    ///
+    /// Returns an error if the timer could not be created.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn animate_cursors<Data: 'static>(
+        &self,
+        loop_handle: &calloop::LoopHandle<Data>,
+        scheduling: AnimationScheduling,
+    ) -> io::Result<()> {
+        let timer = calloop::timer::Timer::new()?;
+        let timer_handle = timer.handle();
+        loop_handle
+            .insert_source(timer, |cursor: Weak<CursorInner>, _, _| {
+                if let Some(cursor) = cursor.upgrade() {
+                    cursor.frame_timeout();
+                    // the frames drawn by the timer are not triggered by Wayland events,
+                    // send them right away
+                    crate::event_loop::flush_connections();
+                }
+            })
+            .map_err(|e| e.error)?;
+        *self.animator.borrow_mut() = Some(Animator {
+            scheduling,
+            timer: timer_handle,
+        });
+        Ok(())
+    }
+
+    /// Unload the cursor themes not used by any cursor
+    ///
+    /// The themes of each scale factor are loaded when a cursor is first displayed with it,
+    /// and kept loaded afterwards. The themes whose images may still be displayed by the
+    /// compositor are never unloaded.
+    pub fn release_unused_themes(&self) {
+        self.themes.borrow_mut().release_unused();
+    }
+
+    /// Draw the cursors at the fractional scale factors of the outputs
+    ///
+    /// The cursors displayed on outputs with a fractional scale set by
+    /// [`set_output_scale`](#method.set_output_scale) are drawn with images of the pixel size
+    /// chosen by `rounding` for this scale, and scaled to their logical size with a
+    /// `wp_viewport`. The cursors on the other outputs are drawn at their integer scale factor
+    /// as usual.
+    pub fn set_fractional_scaling(
+        &self,
+        viewporter: Attached<WpViewporter>,
+        rounding: SizeRounding,
+    ) {
+        {
+            let mut themes = self.themes.borrow_mut();
+            themes.viewporter = Some(viewporter);
+            themes.rounding = rounding;
+        }
+        self.refresh_cursors();
+    }
+
+    /// Set the fractional scale factor of an output, `None` to use its integer scale factor
+    ///
+    /// This is the scale factor your app got for this output, for example from the
+    /// fractional scaling protocol or from the settings of your desktop environment, as it
+    /// is not provided by `wl_output`. It is only used once
+    /// [`set_fractional_scaling`](#method.set_fractional_scaling) was called, the cursors
+    /// being drawn again with it.
+    pub fn set_output_scale(&self, output: &wl_output::WlOutput, scale: Option<f64>) {
+        {
+            let mut themes = self.themes.borrow_mut();
+            themes.output_scales.retain(|(o, _)| o != output);
+            if let Some(scale) = scale.filter(|&scale| scale > 0.0) {
+                themes.output_scales.push((output.clone(), scale));
+            }
         }
+        self.refresh_cursors();
+    }
+
+    /// The pixel size of the cursor images drawn for a scale factor
+    ///
+    /// This follows the `SizeRounding` of
+    /// [`set_fractional_scaling`](#method.set_fractional_scaling) for fractional scale
+    /// factors, which is useful to debug the choice of the images. The sizes of the theme are
+    /// read when needed by `SizeRounding::SnapToAvailable`.
+    pub fn cursor_size(&self, scale: f64) -> u32 {
+        let mut themes = self.themes.borrow_mut();
+        themes.pixel_size(CursorScale::new(scale))
+    }
+
+    /// Copy the first image of a cursor of the theme, for a scale factor
+    ///
+    /// The image is read from the files of the theme when calling this, so it stays valid
+    /// regardless of the themes loaded or unloaded afterwards. The themes used in place of
+    /// a theme which could not be loaded are used here as well.
+    ///
+    /// Returns `None` if the cursor or the theme could not be found.
+    pub fn get_cursor_image(&self, name: &str, scale: u32) -> Option<CursorImage> {
+        self.get_cursor_frames(name, scale)?.into_iter().next()
+    }
+
+    /// Copy all the frames of a cursor of the theme, for a scale factor
+    ///
+    /// This is like [`get_cursor_image`](#method.get_cursor_image), for animated cursors.
+    /// The images are in the order of the animation, each displayed for its `delay`.
+    pub fn get_cursor_frames(&self, name: &str, scale: u32) -> Option<Vec<CursorImage>> {
+        let themes = self.themes.borrow();
+        let images = themes.read_cursor(name, themes.size * std::cmp::max(scale, 1))?;
+        Some(images.into_iter().map(CursorImage::from_image).collect())
     }
 
     /// Wrap a pointer to theme it
     pub fn theme_pointer(&self, pointer: wl_pointer::WlPointer) -> ThemedPointer {
-        let surface = self.compositor.create_surface();
-        let inner = Rc::new(RefCell::new(PointerInner {
-            surface: surface.detach(),
+        let inner = self.pointer_inner(&pointer, None);
+        let my_pointer = pointer.clone();
+        self.register_refresh(&inner, move |serial, surface, hx, hy| {
+            my_pointer.set_cursor(serial, surface, hx, hy)
+        });
+        ThemedPointer { pointer, inner }
+    }
+
+    /// Set the maximum number of cursor surfaces kept for reuse
+    ///
+    /// The cursor surface of a themed pointer is kept when the pointer is dropped, and reused
+    /// for the next pointer themed by this manager, rather than destroyed. This avoids creating
+    /// new surfaces when the pointers are themed again after the capabilities of a seat changed.
+    /// Up to 4 surfaces are kept by default, 0 disables the reuse.
+    pub fn set_surface_pool_size(&self, size: usize) {
+        self.surfaces.borrow_mut().set_capacity(size);
+    }
+
+    // The cursor state of a pointer, using a surface of the pool
+    fn pointer_inner(
+        &self,
+        pointer: &wl_pointer::WlPointer,
+        owned_pointer: Option<wl_pointer::WlPointer>,
+    ) -> Rc<CursorInner> {
+        let cursor_surface = SurfacePool::take(&self.surfaces);
+        let inner = Rc::new(CursorInner {
+            surface: cursor_surface.surface.clone(),
             themes: self.themes.clone(),
-            last_serial: 0,
-            current_cursor: "left_ptr".into(),
-            scale_factor: 1,
-        }));
+            last_serial: Cell::new(0),
+            current_cursor: RefCell::new("left_ptr".into()),
+            // a reused surface may already be on outputs
+            scale_factor: Cell::new(get_surface_scale_factor(&cursor_surface.surface)),
+            up_to_date: Cell::new(false),
+            drawn_scale: Cell::new(None),
+            viewport: RefCell::new(None),
+            frame: Cell::new(0),
+            hotspot: Cell::new((0, 0)),
+            attached: Cell::new(None),
+            previous: Cell::new(None),
+            owned_pointer,
+            displayed: RefCell::new(None),
+            change_callback: RefCell::new(None),
+            focus: RefCell::new(None),
+            pooled: RefCell::new(Some((cursor_surface.clone(), pointer.clone()))),
+            motion: RefCell::new(MotionQueue::default()),
+            #[cfg(feature = "calloop")]
+            animation: RefCell::new(Animation::new(self.animator.clone())),
+        });
+        #[cfg(feature = "calloop")]
+        {
+            inner.animation.borrow_mut().cursor = Rc::downgrade(&inner);
+        }
         let my_pointer = pointer.clone();
         let winner = Rc::downgrade(&inner);
-        crate::surface::setup_surface(
-            surface,
-            Some(move |scale_factor, _, _: DispatchData| {
-                if let Some(inner) = Weak::upgrade(&winner) {
-                    let mut inner = inner.borrow_mut();
-                    inner.scale_factor = scale_factor;
-                    // we can't handle errors here, so ignore it
-                    // worst that can happen is cursor drawn with the wrong
-                    // scale factor
-                    let _ = inner.update_cursor(&my_pointer);
-                }
-            }),
-        );
-        ThemedPointer { pointer, inner }
+        cursor_surface.set_scale_handler(Some(Rc::new(move |scale_factor| {
+            if let Some(inner) = Weak::upgrade(&winner) {
+                inner.rescale(scale_factor, |serial, surface, hx, hy| {
+                    my_pointer.set_cursor(serial, surface, hx, hy)
+                });
+            }
+        })));
+        inner
     }
 
     /// Initialize a new pointer as a ThemedPointer with an adapter implementation
@@ -94,70 +464,279 @@ impl ThemeManager {
     /// You need to provide an implementation as if implementing a `wl_pointer`, but
     /// it will receive as `meta` argument a `ThemedPointer` wrapping your pointer,
     /// rather than a `WlPointer`.
+    ///
+    /// The serials of the pointer are recorded in the data of the seat, and the cursor
+    /// uses the serial of the latest `enter` event, so you can set it without providing one.
+    /// The surface the pointer is over is also tracked, see
+    /// [`pointer_focus`](../fn.pointer_focus.html).
+    ///
+    /// The pointer is never released, see `theme_pointer_with_impl_owned` to release it
+    /// once you no longer use it.
     pub fn theme_pointer_with_impl<F>(
         &self,
         seat: &Attached<wl_seat::WlSeat>,
-        mut callback: F,
+        callback: F,
     ) -> ThemedPointer
     where
         F: FnMut(wl_pointer::Event, ThemedPointer, DispatchData) + 'static,
     {
-        let surface = self.compositor.create_surface();
-        let inner = Rc::new(RefCell::new(PointerInner {
-            surface: surface.detach(),
-            themes: self.themes.clone(),
-            last_serial: 0,
-            current_cursor: "left_ptr".into(),
-            scale_factor: 1,
-        }));
+        self.create_pointer(seat, false, callback)
+    }
+
+    /// Initialize a new pointer as a ThemedPointer with an adapter implementation, releasing
+    /// it once all its handles are dropped
+    ///
+    /// This is like `theme_pointer_with_impl`, except that the pointer is released and the
+    /// cursor surface destroyed when the last clone of the returned `ThemedPointer` is dropped,
+    /// or when calling `ThemedPointer::release`. The callback is not invoked afterwards, you
+    /// need to keep a handle to the pointer as long as you use it, typically until the seat
+    /// loses its pointer capability.
+    pub fn theme_pointer_with_impl_owned<F>(
+        &self,
+        seat: &Attached<wl_seat::WlSeat>,
+        callback: F,
+    ) -> ThemedPointer
+    where
+        F: FnMut(wl_pointer::Event, ThemedPointer, DispatchData) + 'static,
+    {
+        self.create_pointer(seat, true, callback)
+    }
 
-        let inner2 = inner.clone();
+    fn create_pointer<F>(
+        &self,
+        seat: &Attached<wl_seat::WlSeat>,
+        owned: bool,
+        callback: F,
+    ) -> ThemedPointer
+    where
+        F: FnMut(wl_pointer::Event, ThemedPointer, DispatchData) + 'static,
+    {
         let pointer = seat.get_pointer();
+        let inner = self.pointer_inner(&pointer, if owned { Some(pointer.detach()) } else { None });
+        let callback = Rc::new(RefCell::new(callback)) as Rc<RefCell<PointerCallback>>;
+        inner.motion.borrow_mut().callback = Some(Rc::downgrade(&callback));
+
+        // an owned pointer is released once the app drops its handles, the callback must
+        // not keep it alive
+        let strong = if owned { None } else { Some(inner.clone()) };
+        let weak = Rc::downgrade(&inner);
+        let my_seat = seat.detach();
         pointer.quick_assign(move |ptr, event, ddata| {
-            callback(
-                event,
-                ThemedPointer {
-                    pointer: ptr.detach(),
-                    inner: inner2.clone(),
-                },
-                ddata,
-            )
+            #[cfg(feature = "recording")]
+            crate::seat::recording::record_pointer(&ptr, &event);
+            let inner2 = match strong.clone().or_else(|| weak.upgrade()) {
+                Some(inner) => inner,
+                None => return,
+            };
+            track_pointer_focus(&my_seat, &event);
+            match event {
+                wl_pointer::Event::Enter {
+                    serial,
+                    ref surface,
+                    ..
+                } => {
+                    record_serial(&my_seat, SerialKind::PointerEnter, serial);
+                    // the cursor image is undefined when entering a surface, it must be
+                    // set again with this serial
+                    inner2.last_serial.set(serial);
+                    inner2.up_to_date.set(false);
+                    *inner2.focus.borrow_mut() = Some(surface.clone());
+                }
+                wl_pointer::Event::Leave { ref surface, .. } => {
+                    let mut focus = inner2.focus.borrow_mut();
+                    if focus.as_ref() == Some(surface) {
+                        *focus = None;
+                        // the cursor is no longer displayed
+                        #[cfg(feature = "calloop")]
+                        inner2.stop_animation();
+                    }
+                }
+                wl_pointer::Event::Button {
+                    serial,
+                    state: wl_pointer::ButtonState::Pressed,
+                    ..
+                } => record_serial(&my_seat, SerialKind::PointerButton, serial),
+                _ => {}
+            }
+            let themed = ThemedPointer {
+                pointer: ptr.detach(),
+                inner: inner2,
+            };
+            process_pointer_event(&themed, &callback, event, ddata);
         });
 
-        let winner = Rc::downgrade(&inner);
         let my_pointer = pointer.clone();
-        crate::surface::setup_surface(
+        self.register_refresh(&inner, move |serial, surface, hx, hy| {
+            my_pointer.set_cursor(serial, surface, hx, hy)
+        });
+
+        ThemedPointer {
+            pointer: pointer.detach(),
+            inner,
+        }
+    }
+
+    /// Wrap a tablet tool to theme it
+    ///
+    /// The cursor is shown while the tool is in proximity of one of your surfaces, provide the
+    /// serial of its `proximity_in` event when setting it.
+    pub fn theme_tablet_tool(&self, tool: zwp_tablet_tool_v2::ZwpTabletToolV2) -> ThemedTool {
+        let surface = self.compositor.create_surface();
+        let inner = Rc::new(CursorInner {
+            surface: (*surface).clone(),
+            themes: self.themes.clone(),
+            last_serial: Cell::new(0),
+            current_cursor: RefCell::new("left_ptr".into()),
+            scale_factor: Cell::new(ScaleFactor::ONE),
+            up_to_date: Cell::new(false),
+            drawn_scale: Cell::new(None),
+            viewport: RefCell::new(None),
+            frame: Cell::new(0),
+            hotspot: Cell::new((0, 0)),
+            attached: Cell::new(None),
+            previous: Cell::new(None),
+            owned_pointer: None,
+            displayed: RefCell::new(None),
+            change_callback: RefCell::new(None),
+            focus: RefCell::new(None),
+            pooled: RefCell::new(None),
+            motion: RefCell::new(MotionQueue::default()),
+            #[cfg(feature = "calloop")]
+            animation: RefCell::new(Animation::new(self.animator.clone())),
+        });
+        #[cfg(feature = "calloop")]
+        {
+            inner.animation.borrow_mut().cursor = Rc::downgrade(&inner);
+        }
+        let my_tool = tool.clone();
+        self.register_refresh(&inner, move |serial, surface, hx, hy| {
+            my_tool.set_cursor(serial, surface, hx, hy)
+        });
+        let my_tool = tool.clone();
+        let winner = Rc::downgrade(&inner);
+        crate::surface::setup_surface_with_outputs_callback(
             surface,
-            Some(move |scale_factor, _, _: DispatchData| {
+            move |scale_factor, _: DispatchData| {
                 if let Some(inner) = Weak::upgrade(&winner) {
-                    let mut inner = inner.borrow_mut();
-                    inner.scale_factor = scale_factor;
-                    // we can't handle errors here, so ignore it
-                    // worst that can happen is cursor drawn with the wrong
-                    // scale factor
-                    let _ = inner.update_cursor(&my_pointer);
+                    inner.rescale(scale_factor, |serial, surface, hx, hy| {
+                        my_tool.set_cursor(serial, surface, hx, hy)
+                    });
                 }
-            }),
+            },
         );
+        ThemedTool { tool, inner }
+    }
+}
 
-        ThemedPointer {
-            pointer: pointer.detach(),
-            inner,
+impl<E> Environment<E>
+where
+    E: GlobalHandler<wl_compositor::WlCompositor> + GlobalHandler<wl_shm::WlShm>,
+{
+    /// Load a system pointer theme using the globals of this environment
+    ///
+    /// See [`ThemeManager::init`](struct.ThemeManager.html#method.init). Fails if the
+    /// `wl_compositor` or `wl_shm` global is missing.
+    pub fn init_theme_manager(&self, theme: ThemeSpec) -> Result<ThemeManager, EnvironmentError> {
+        let compositor = self.try_get_global::<wl_compositor::WlCompositor>()?;
+        let shm = self.try_get_global::<wl_shm::WlShm>()?;
+        Ok(ThemeManager::init(theme, compositor, shm))
+    }
+}
+
+// Where the cursor theme is loaded from
+enum ThemeSource {
+    Named(String),
+    Path(XcursorTheme),
+}
+
+impl fmt::Display for ThemeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThemeSource::Named(name) => f.write_str(name),
+            ThemeSource::Path(theme) => write!(f, "{}", theme.path().display()),
+        }
+    }
+}
+
+// A theme loaded for a given cursor size
+enum LoadedTheme {
+    Named(CursorTheme),
+    // the theme the cursors are read from, with their frames, loaded on first use, `None` if
+    // they do not exist
+    Path(XcursorTheme, Vec<(String, Option<Vec<CursorBuffer>>)>),
+}
+
+// A frame of a cursor, with its dimensions, hotspot and delay in milliseconds
+type CursorFrame = (wl_buffer::WlBuffer, (u32, u32), (u32, u32), u32);
+
+// The pixel size of a loaded theme, the generation of the theme it was loaded for, and whether
+// its images are scaled to exactly this size
+type ThemeKey = (u32, u32, bool);
+
+// The scale a cursor is drawn at: an integer buffer scale, or a fractional scale applied with
+// a viewport
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum CursorScale {
+    Buffer(u32),
+    Viewport(f64),
+}
+
+impl CursorScale {
+    fn new(scale: f64) -> CursorScale {
+        if scale.is_nan() || scale <= 0.0 {
+            CursorScale::Buffer(1)
+        } else if (scale - scale.round()).abs() < SCALE_EPSILON {
+            CursorScale::Buffer(std::cmp::max(scale.round() as u32, 1))
+        } else {
+            CursorScale::Viewport(scale)
         }
     }
 }
 
+// The dimensions of each frame of an animated cursor, with the span of rows which differ from the
+// previous frame, `None` if the whole frame must be damaged
+type FrameChanges = Vec<((u32, u32), Option<(u32, u32)>)>;
+
 struct ScaledThemeList {
     shm: Attached<wl_shm::WlShm>,
-    name: String,
+    source: ThemeSource,
     size: u32,
-    themes: Vec<(u32, CursorTheme)>,
+    // bumped when the theme changes, the themes of the previous generations are only kept
+    // while their buffers are in use
+    generation: u32,
+    // the themes with their key, and the number of cursor surfaces using their buffers,
+    // `None` if loading them failed
+    themes: Vec<(ThemeKey, Option<LoadedTheme>, usize)>,
+    // the changes between the frames of the animated cursors of the loaded themes
+    changes: Vec<(ThemeKey, String, FrameChanges)>,
+    // how fractional sizes are rounded, with the sizes of the theme once read
+    rounding: SizeRounding,
+    sizes: Option<Vec<u32>>,
+    // the cursors are only drawn at fractional scales once a viewporter is set
+    viewporter: Option<Attached<WpViewporter>>,
+    output_scales: Vec<(wl_output::WlOutput, f64)>,
 }
 
 impl ScaledThemeList {
     fn new(theme: ThemeSpec, shm: Attached<wl_shm::WlShm>) -> ScaledThemeList {
-        let (name, size) = match theme {
-            ThemeSpec::Precise { name, size } => (name.into(), size),
+        let (source, size) = ScaledThemeList::resolve(theme);
+        ScaledThemeList {
+            shm,
+            source,
+            size,
+            generation: 0,
+            themes: vec![],
+            changes: vec![],
+            rounding: SizeRounding::default(),
+            sizes: None,
+            viewporter: None,
+            output_scales: vec![],
+        }
+    }
+
+    fn resolve(theme: ThemeSpec) -> (ThemeSource, u32) {
+        match theme {
+            ThemeSpec::Precise { name, size } => (ThemeSource::Named(name.into()), size),
             ThemeSpec::System => {
                 let name = std::env::var("XCURSOR_THEME")
                     .ok()
@@ -166,117 +745,2222 @@ impl ScaledThemeList {
                     .ok()
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(24);
-                (name, size)
+                (ThemeSource::Named(name), size)
             }
-        };
-        ScaledThemeList {
-            shm,
-            name,
-            size,
-            themes: vec![],
+            ThemeSpec::Path { path, size } => (ThemeSource::Path(XcursorTheme::open(path)), size),
         }
     }
 
-    fn get_cursor(&mut self, name: &str, scale: u32) -> Option<&Cursor> {
-        // Check if we already loaded the theme for this scale factor
-        let opt_index = self.themes.iter().position(|&(s, _)| s == scale);
-        if let Some(idx) = opt_index {
-            self.themes[idx].1.get_cursor(name)
-        } else {
-            let new_theme = CursorTheme::load_from_name(&self.name, self.size * scale, &self.shm);
-            self.themes.push((scale, new_theme));
-            self.themes.last_mut().unwrap().1.get_cursor(name)
-        }
+    fn set_theme(&mut self, theme: ThemeSpec) {
+        let (source, size) = ScaledThemeList::resolve(theme);
+        log::debug!("[SCTK] Changing the cursor theme to {}", source);
+        self.source = source;
+        self.size = size;
+        self.sizes = None;
+        self.generation = self.generation.wrapping_add(1);
+        self.release_unused();
     }
-}
 
-struct PointerInner {
-    surface: wl_surface::WlSurface,
-    themes: Rc<RefCell<ScaledThemeList>>,
-    current_cursor: String,
-    last_serial: u32,
-    scale_factor: i32,
-}
+    // The key of the current theme for this pixel size
+    fn key(&self, size: u32, exact: bool) -> ThemeKey {
+        (size, self.generation, exact)
+    }
 
-impl PointerInner {
-    fn update_cursor(&self, pointer: &wl_pointer::WlPointer) -> Result<(), ()> {
-        let mut themes = self.themes.borrow_mut();
-        let scale = self.scale_factor as u32;
-        let cursor = themes.get_cursor(&self.current_cursor, scale).ok_or(())?;
-        let image = &cursor[0];
-        let (w, h) = image.dimensions();
-        let (hx, hy) = image.hotspot();
-        self.surface.set_buffer_scale(scale as i32);
-        self.surface.attach(Some(&image), 0, 0);
-        if self.surface.as_ref().version() >= 4 {
-            self.surface.damage_buffer(0, 0, w as i32, h as i32);
-        } else {
-            // surface is old and does not support damage_buffer, so we damage
-            // in surface coordinates and hope it is not rescaled
-            self.surface
-                .damage(0, 0, w as i32 / scale as i32, h as i32 / scale as i32);
+    // The pixel size of the cursor images for this scale
+    fn pixel_size(&mut self, scale: CursorScale) -> u32 {
+        let exact = match scale {
+            CursorScale::Buffer(scale) => return self.size * scale,
+            CursorScale::Viewport(scale) => self.size as f64 * scale,
+        };
+        let size = match self.rounding {
+            SizeRounding::RoundDown => (exact + SCALE_EPSILON).floor(),
+            SizeRounding::RoundUp => (exact - SCALE_EPSILON).ceil(),
+            SizeRounding::Nearest => exact.round(),
+            SizeRounding::SnapToAvailable => {
+                if self.sizes.is_none() {
+                    let sizes = self.files().map(|theme| theme.sizes("left_ptr"));
+                    self.sizes = Some(sizes.unwrap_or_default());
+                }
+                let sizes = self.sizes.iter().flatten().cloned();
+                match nearest_size(sizes, exact.round() as u32) {
+                    Some(size) => return size,
+                    // the theme could not be read, its sizes are unknown
+                    None => exact.round(),
+                }
+            }
+        };
+        std::cmp::max(size as u32, 1)
+    }
+
+    // The given frame of a cursor, wrapping around its frame count, with this count
+    //
+    // The images of `exact` sizes are scaled to exactly this size, the others are the images
+    // of the theme nearest to it.
+    fn get_cursor(
+        &mut self,
+        name: &str,
+        size: u32,
+        exact: bool,
+        frame: usize,
+    ) -> Result<(CursorFrame, usize), ThemeError> {
+        // Check if we already loaded the theme for this size
+        let key = self.key(size, exact);
+        let opt_index = self.themes.iter().position(|&(k, _, _)| k == key);
+        let idx = match opt_index {
+            Some(idx) => idx,
+            None => {
+                // failures are kept too, so that loading is not retried on each cursor change
+                let new_theme = self.load(size, exact);
+                self.themes.push((key, new_theme, 0));
+                self.themes.len() - 1
+            }
+        };
+        match self.themes[idx].1 {
+            Some(LoadedTheme::Named(ref mut theme)) => {
+                let cursor = theme.get_cursor(name).ok_or(ThemeError::CursorNotFound)?;
+                let count = cursor.image_count();
+                let image = &cursor[frame % count];
+                Ok((
+                    (
+                        wl_buffer::WlBuffer::clone(image),
+                        image.dimensions(),
+                        image.hotspot(),
+                        image.delay(),
+                    ),
+                    count,
+                ))
+            }
+            Some(LoadedTheme::Path(ref theme, ref mut cursors)) => {
+                let pos = match cursors.iter().position(|(n, _)| n == name) {
+                    Some(pos) => pos,
+                    None => {
+                        let cursor = load_path_cursor(theme, name, size, &self.shm);
+                        cursors.push((name.into(), cursor));
+                        cursors.len() - 1
+                    }
+                };
+                let frames = cursors[pos].1.as_ref().ok_or(ThemeError::CursorNotFound)?;
+                let cursor = &frames[frame % frames.len()];
+                Ok((
+                    (
+                        cursor.buffer().clone(),
+                        cursor.dimensions(),
+                        cursor.hotspot(),
+                        cursor.delay(),
+                    ),
+                    frames.len(),
+                ))
+            }
+            None => Err(ThemeError::ThemeLoadFailed),
         }
-        self.surface.commit();
-        pointer.set_cursor(
-            self.last_serial,
-            Some(&self.surface),
-            hx as i32 / scale as i32,
-            hy as i32 / scale as i32,
-        );
-        Ok(())
     }
-}
 
-/// Wrapper of a themed pointer
-///
-/// You can access the underlying `wl_pointer::WlPointer` via
-/// deref. It will *not* release the proxy when dropped.
-///
-/// Just like `Proxy`, this is a `Rc`-like wrapper. You can clone it
-/// to have several handles to the same theming machinery of a pointer.
-pub struct ThemedPointer {
-    pointer: wl_pointer::WlPointer,
-    inner: Rc<RefCell<PointerInner>>,
-}
+    // The span of rows of a frame of an animated cursor which differ from the previous frame,
+    // `None` if the whole frame must be damaged
+    //
+    // The frames are read from the files of the theme once, and only used if they match the
+    // frame count and the dimensions of the loaded cursor.
+    fn frame_damage(
+        &mut self,
+        name: &str,
+        size: u32,
+        exact: bool,
+        frame: usize,
+        count: usize,
+        dimensions: (u32, u32),
+    ) -> Option<(u32, u32)> {
+        let key = self.key(size, exact);
+        let pos = match self
+            .changes
+            .iter()
+            .position(|&(k, ref n, _)| k == key && n == name)
+        {
+            Some(pos) => pos,
+            None => {
+                let changes = self
+                    .read_cursor(name, size)
+                    .map(|images| frame_changes(&images))
+                    .unwrap_or_default();
+                self.changes.push((key, name.into(), changes));
+                self.changes.len() - 1
+            }
+        };
+        let changes = &self.changes[pos].2;
+        if changes.len() != count {
+            return None;
+        }
+        match changes[frame % count] {
+            (dims, Some((y, rows))) if dims == dimensions && rows * 2 <= dims.1 => Some((y, rows)),
+            // the whole frame is damaged when most of its rows changed
+            _ => None,
+        }
+    }
 
-// load_theme(name, 16, &shm)
+    // Read the frames of a cursor from the files of the theme
+    fn read_cursor(&self, name: &str, size: u32) -> Option<Vec<Image>> {
+        self.files()?.load_images(name, std::cmp::max(size, 1))
+    }
 
-impl ThemedPointer {
-    /// Change the cursor to the given cursor name
-    ///
-    /// Possible names depend on the theme. Does nothing and returns
-    /// `Err(())` if given name is not available.
-    ///
-    /// If this is done as an answer to an input event, you need to provide
-    /// the associated serial otherwise the server may ignore the request.
-    pub fn set_cursor(&self, name: &str, serial: Option<u32>) -> Result<(), ()> {
-        let mut inner = self.inner.borrow_mut();
-        if let Some(s) = serial {
-            inner.last_serial = s;
+    // The files of the theme `load` would use, the first one with a `left_ptr` cursor
+    fn files(&self) -> Option<XcursorTheme> {
+        let has_default = |theme: &XcursorTheme| !theme.sizes("left_ptr").is_empty();
+        let requested = match self.source {
+            ThemeSource::Path(ref theme) if has_default(theme) => return Some(theme.clone()),
+            ThemeSource::Path(_) => None,
+            ThemeSource::Named(ref requested) => Some(requested.as_str()),
+        };
+        requested
+            .into_iter()
+            .chain(
+                FALLBACK_THEMES
+                    .iter()
+                    .cloned()
+                    .filter(|&n| Some(n) != requested),
+            )
+            .filter_map(XcursorTheme::find)
+            .find(has_default)
+    }
+
+    fn load(&self, size: u32, exact: bool) -> Option<LoadedTheme> {
+        let requested = match self.source {
+            ThemeSource::Named(ref name) if !exact => Some(name.as_str()),
+            ref source => {
+                // the images of wayland-cursor are not scaled to the size of fractional scales,
+                // so named themes are read from their files for them too
+                let theme = match *source {
+                    ThemeSource::Path(ref theme) if !exact => Some(theme.clone()),
+                    _ => self.files(),
+                };
+                let cursor = theme.and_then(|theme| {
+                    let cursor = load_path_cursor(&theme, "left_ptr", size, &self.shm)?;
+                    Some((theme, cursor))
+                });
+                if let Some((theme, cursor)) = cursor {
+                    log::debug!(
+                        "[SCTK] Loaded cursor theme {} with size {}",
+                        theme.path().display(),
+                        size
+                    );
+                    return Some(LoadedTheme::Path(
+                        theme,
+                        vec![("left_ptr".into(), Some(cursor))],
+                    ));
+                }
+                match *source {
+                    ThemeSource::Named(ref name) => Some(name.as_str()),
+                    ThemeSource::Path(_) => None,
+                }
+            }
+        };
+        let names = requested.into_iter().chain(
+            FALLBACK_THEMES
+                .iter()
+                .cloned()
+                .filter(|&n| Some(n) != requested),
+        );
+        for name in names {
+            // a theme which cannot be found loads without any cursor
+            let mut theme = CursorTheme::load_from_name(name, size, &self.shm);
+            if theme.get_cursor("left_ptr").is_some() {
+                log::debug!("[SCTK] Loaded cursor theme {} with size {}", name, size);
+                if Some(name) != requested {
+                    log::warn!(
+                        "[SCTK] Failed to load the cursor theme {}, using {} instead",
+                        self.source,
+                        name
+                    );
+                }
+                return Some(LoadedTheme::Named(theme));
+            }
         }
-        inner.current_cursor = name.into();
-        inner.update_cursor(&self.pointer)
+        log::warn!("[SCTK] Failed to load the cursor theme {}", self.source);
+        None
     }
-}
 
-impl Clone for ThemedPointer {
-    fn clone(&self) -> ThemedPointer {
-        ThemedPointer {
-            pointer: self.pointer.clone(),
-            inner: self.inner.clone(),
+    fn acquire(&mut self, key: ThemeKey) {
+        if let Some(theme) = self.themes.iter_mut().find(|&&mut (k, _, _)| k == key) {
+            theme.2 += 1;
         }
     }
-}
 
-impl Deref for ThemedPointer {
+    fn release(&mut self, key: ThemeKey) {
+        if let Some(theme) = self.themes.iter_mut().find(|&&mut (k, _, _)| k == key) {
+            theme.2 -= 1;
+        }
+        // the themes replaced by `set_theme` are unloaded as soon as possible
+        let generation = self.generation;
+        self.themes
+            .retain(|&((_, g, _), _, users)| g == generation || users > 0);
+        self.forget_unloaded_changes();
+    }
+
+    fn release_unused(&mut self) {
+        self.themes.retain(|&(_, _, users)| users > 0);
+        self.forget_unloaded_changes();
+    }
+
+    fn forget_unloaded_changes(&mut self) {
+        let themes = &self.themes;
+        self.changes
+            .retain(|&(key, _, _)| themes.iter().any(|&(k, _, _)| k == key));
+    }
+}
+
+// The changes of each frame of an animated cursor from the previous one, the first frame
+// following the last one
+fn frame_changes(images: &[Image]) -> FrameChanges {
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let previous = &images[(i + images.len() - 1) % images.len()];
+            ((image.width, image.height), image.changed_rows(previous))
+        })
+        .collect()
+}
+
+fn load_path_cursor(
+    theme: &XcursorTheme,
+    name: &str,
+    size: u32,
+    shm: &Attached<wl_shm::WlShm>,
+) -> Option<Vec<CursorBuffer>> {
+    let images = theme.load_images(name, size)?;
+    match images
+        .iter()
+        .map(|image| CursorBuffer::new(image, shm))
+        .collect()
+    {
+        Ok(frames) => Some(frames),
+        Err(err) => {
+            log::warn!("[SCTK] Failed to upload the cursor {}: {}", name, err);
+            None
+        }
+    }
+}
+
+// The cursor can be set from the callbacks of the pointer while its scale factor is being
+// updated, so no borrow is held while sending requests
+struct CursorInner {
+    // attached to request the frame callbacks of animated cursors
+    surface: Attached<wl_surface::WlSurface>,
+    themes: Rc<RefCell<ScaledThemeList>>,
+    current_cursor: RefCell<String>,
+    last_serial: Cell<u32>,
+    scale_factor: Cell<ScaleFactor>,
+    // whether the surface displays `current_cursor` at `scale_factor`
+    up_to_date: Cell<bool>,
+    // the scale the cursor was last drawn at, and the viewport scaling it to a fractional scale
+    drawn_scale: Cell<Option<CursorScale>>,
+    viewport: RefCell<Option<wp_viewport::WpViewport>>,
+    // the frame of `current_cursor` displayed, and its hotspot in surface coordinates
+    frame: Cell<usize>,
+    hotspot: Cell<(i32, i32)>,
+    // the keys of the themes owning the attached buffer, and the previously attached one
+    // which the compositor may still be reading until the next commit is processed
+    attached: Cell<Option<ThemeKey>>,
+    previous: Cell<Option<ThemeKey>>,
+    // the pointer to release when dropped, if SCTK created it for an owned themed pointer
+    owned_pointer: Option<wl_pointer::WlPointer>,
+    // the cursor last displayed, and the callback notified when it changes
+    displayed: RefCell<Option<(String, ScaleFactor)>>,
+    change_callback: RefCell<Option<Box<dyn FnMut(&str, ScaleFactor)>>>,
+    // the surface the pointer is over, only tracked for the pointers whose events SCTK receives
+    focus: RefCell<Option<wl_surface::WlSurface>>,
+    // the pooled surface of a pointer, returned to its pool when dropped, with the pointer
+    pooled: RefCell<Option<(CursorSurface, wl_pointer::WlPointer)>>,
+    // the motion events held back to be coalesced, only used by pointers
+    motion: RefCell<MotionQueue>,
+    #[cfg(feature = "calloop")]
+    animation: RefCell<Animation>,
+}
+
+type PointerCallback = dyn FnMut(wl_pointer::Event, ThemedPointer, DispatchData);
+
+// Schedules a closure to be invoked once the pending events are dispatched
+#[cfg(feature = "calloop")]
+type IdleScheduler = Box<dyn Fn(Box<dyn FnOnce(DispatchData)>)>;
+
+enum MotionMode {
+    Disabled,
+    UntilFlush,
+    #[cfg(feature = "calloop")]
+    Idle(IdleScheduler),
+}
+
+// The latest of the motion events received since the last one delivered, when they are
+// coalesced, and the number of events it replaces
+struct MotionQueue {
+    mode: MotionMode,
+    pending: Option<wl_pointer::Event>,
+    pending_count: u32,
+    // whether a `frame` event ending the pending motion was held back as well
+    pending_frame: bool,
+    // whether a flush is scheduled for the idle mode
+    #[cfg(feature = "calloop")]
+    scheduled: bool,
+    // the number of events coalesced in the event being delivered
+    delivered_count: u32,
+    callback: Option<Weak<RefCell<PointerCallback>>>,
+}
+
+impl Default for MotionQueue {
+    fn default() -> MotionQueue {
+        MotionQueue {
+            mode: MotionMode::Disabled,
+            pending: None,
+            pending_count: 0,
+            pending_frame: false,
+            #[cfg(feature = "calloop")]
+            scheduled: false,
+            delivered_count: 1,
+            callback: None,
+        }
+    }
+}
+
+impl CursorInner {
+    fn set_cursor<F>(
+        &self,
+        name: &str,
+        serial: Option<u32>,
+        set_cursor: F,
+    ) -> Result<(), ThemeError>
+    where
+        F: FnOnce(u32, Option<&wl_surface::WlSurface>, i32, i32),
+    {
+        if let Some(s) = serial {
+            if s != self.last_serial.get() {
+                self.last_serial.set(s);
+                self.up_to_date.set(false);
+            }
+        }
+        {
+            let mut current_cursor = self.current_cursor.borrow_mut();
+            if *current_cursor != name {
+                *current_cursor = name.into();
+                self.frame.set(0);
+                self.up_to_date.set(false);
+            }
+        }
+        if self.up_to_date.get() {
+            return Ok(());
+        }
+        self.update_cursor(set_cursor)
+    }
+
+    // `set_cursor` is the request of the themed device, receiving the serial, the surface
+    // and the hotspot
+    fn update_cursor<F>(&self, set_cursor: F) -> Result<(), ThemeError>
+    where
+        F: FnOnce(u32, Option<&wl_surface::WlSurface>, i32, i32),
+    {
+        // cleared until the cursor is successfully updated
+        self.up_to_date.set(false);
+        #[cfg(feature = "calloop")]
+        self.stop_animation();
+        let untouched = match *self.focus.borrow() {
+            Some(ref surface) => get_cursor_policy(surface) == CursorPolicy::Untouched,
+            None => false,
+        };
+        if untouched {
+            // the cursor is drawn once the pointer enters a managed surface
+            return Ok(());
+        }
+        let (hx, hy) = self.draw_frame(self.frame.get(), false)?;
+        set_cursor(self.last_serial.get(), Some(&self.surface), hx, hy);
+        self.up_to_date.set(true);
+        self.notify_change();
+        Ok(())
+    }
+
+    // Attach a frame of the current cursor and commit it, returning its hotspot in surface
+    // coordinates
+    //
+    // The cursor is not set again for the next frames of an animation, their hotspot is moved
+    // by the offset of the attach instead, and only the rows which changed since the previous
+    // frame are damaged.
+    #[cfg_attr(not(feature = "calloop"), allow(unused_variables))]
+    fn draw_frame(&self, frame: usize, animating: bool) -> Result<(i32, i32), ThemeError> {
+        let cursor_scale = self.cursor_scale();
+        let ((buffer, (w, h), (hx, hy), delay), count, damage, viewport_scale) = {
+            let name = self.current_cursor.borrow();
+            let mut themes = self.themes.borrow_mut();
+            let size = themes.pixel_size(cursor_scale);
+            // the images of fractional scales are scaled to exactly their size
+            let exact = match cursor_scale {
+                CursorScale::Viewport(_) => true,
+                CursorScale::Buffer(_) => false,
+            };
+            let (image, count) = themes.get_cursor(&name, size, exact, frame)?;
+            let damage = if animating && count > 1 {
+                themes.frame_damage(&name, size, exact, frame, count, image.1)
+            } else {
+                None
+            };
+            // keep the themes of the buffers the compositor may use loaded
+            let key = themes.key(size, exact);
+            themes.acquire(key);
+            if let Some(old) = self.previous.replace(self.attached.replace(Some(key))) {
+                themes.release(old);
+            }
+            // the ratio of the images to the surface, with the viewporter creating the viewport
+            let viewport_scale = match (cursor_scale, themes.viewporter.clone()) {
+                (CursorScale::Viewport(_), Some(viewporter)) => Some((
+                    size as f64 / std::cmp::max(themes.size, 1) as f64,
+                    viewporter,
+                )),
+                _ => None,
+            };
+            (image, count, damage, viewport_scale)
+        };
+        self.frame.set(frame % count);
+        self.drawn_scale.set(Some(cursor_scale));
+        let (scale, hotspot, destination) = match viewport_scale {
+            Some((factor, viewporter)) => {
+                let logical = |v: u32| (v as f64 / factor).round() as i32;
+                let mut viewport = self.viewport.borrow_mut();
+                let viewport =
+                    viewport.get_or_insert_with(|| viewporter.get_viewport(&self.surface).detach());
+                // the viewport does not accept an empty destination
+                let destination = (std::cmp::max(logical(w), 1), std::cmp::max(logical(h), 1));
+                viewport.set_destination(destination.0, destination.1);
+                (1, (logical(hx), logical(hy)), Some(destination))
+            }
+            None => {
+                let scale = match cursor_scale {
+                    CursorScale::Buffer(scale) => scale,
+                    CursorScale::Viewport(_) => self.scale_factor.get().get(),
+                };
+                if let Some(ref viewport) = *self.viewport.borrow() {
+                    viewport.set_destination(-1, -1);
+                }
+                let hotspot = (hx as i32 / scale as i32, hy as i32 / scale as i32);
+                (scale, hotspot, None)
+            }
+        };
+        let previous = self.hotspot.replace(hotspot);
+        let (x, y) = if animating {
+            (previous.0 - hotspot.0, previous.1 - hotspot.1)
+        } else {
+            (0, 0)
+        };
+        self.surface.set_buffer_scale(scale as i32);
+        self.surface.attach(Some(&buffer), x, y);
+        let (y, rows) = damage.unwrap_or((0, h));
+        let caps = CompositorCaps::of_surface(&self.surface);
+        match destination {
+            // without `damage_buffer`, the damage cannot be converted through the viewport
+            Some((dw, dh)) if !caps.has_damage_buffer() => self.surface.damage(0, 0, dw, dh),
+            _ if rows > 0 => {
+                caps.damage_buffer(&self.surface, 0, y as i32, w as i32, rows as i32, scale)
+            }
+            _ => {}
+        }
+        #[cfg(feature = "calloop")]
+        self.schedule_frame(count, delay);
+        self.surface.commit();
+        Ok(hotspot)
+    }
+
+    // The scale to draw the cursor at, the largest of the outputs of its surface
+    //
+    // The fractional scales set with `ThemeManager::set_output_scale` are only used once a
+    // viewporter is set, the integer scale factor of the surface is used otherwise.
+    fn cursor_scale(&self) -> CursorScale {
+        let integer = CursorScale::Buffer(self.scale_factor.get().get());
+        let themes = self.themes.borrow();
+        if themes.viewporter.is_none() || themes.output_scales.is_empty() {
+            return integer;
+        }
+        let output_scale = |output: &wl_output::WlOutput| {
+            let scale = themes.output_scales.iter().find(|(o, _)| o == output);
+            match scale {
+                Some(&(_, scale)) => scale,
+                None => {
+                    with_output_info(output, |info| info.scale_factor.get()).unwrap_or(1) as f64
+                }
+            }
+        };
+        get_surface_outputs(&self.surface)
+            .iter()
+            .map(output_scale)
+            .fold(None, |max: Option<f64>, scale| {
+                Some(max.map_or(scale, |max| max.max(scale)))
+            })
+            .map_or(integer, CursorScale::new)
+    }
+
+    // Draw the cursor again when the scale of its surface changed
+    fn rescale<F>(&self, scale_factor: ScaleFactor, set_cursor: F)
+    where
+        F: FnOnce(u32, Option<&wl_surface::WlSurface>, i32, i32),
+    {
+        let changed = self.scale_factor.replace(scale_factor) != scale_factor;
+        // the fractional scale may change when the surface moves between outputs of the
+        // same integer scale factor
+        let rescaled = match self.drawn_scale.get() {
+            Some(drawn) => drawn != self.cursor_scale(),
+            None => false,
+        };
+        if !changed && !rescaled {
+            return;
+        }
+        // we can't handle errors here, so only log them
+        // worst that can happen is cursor drawn with the wrong
+        // scale factor
+        if let Err(err) = self.update_cursor(set_cursor) {
+            log::warn!(
+                "[SCTK] Failed to update the cursor for the new scale: {}",
+                err
+            );
+        }
+    }
+
+    // Draw the cursor with the new theme, if it was already drawn
+    fn refresh<F>(&self, set_cursor: &F)
+    where
+        F: Fn(u32, Option<&wl_surface::WlSurface>, i32, i32),
+    {
+        if !self.surface.as_ref().is_alive() {
+            return;
+        }
+        if self.displayed.borrow().is_none() {
+            self.up_to_date.set(false);
+            return;
+        }
+        let ret = self.update_cursor(set_cursor);
+        if let Err(err) = ret {
+            log::warn!(
+                "[SCTK] Failed to update the cursor for the new theme: {}",
+                err
+            );
+        }
+    }
+
+    // Notify the change callback if the displayed cursor changed
+    fn notify_change(&self) {
+        let name = self.current_cursor.borrow().clone();
+        let scale = self.scale_factor.get();
+        {
+            let mut displayed = self.displayed.borrow_mut();
+            if displayed.as_ref() == Some(&(name.clone(), scale)) {
+                return;
+            }
+            *displayed = Some((name.clone(), scale));
+        }
+        // the callback is taken out while it runs, as it may set the cursor
+        let callback = self.change_callback.borrow_mut().take();
+        if let Some(mut callback) = callback {
+            callback(&name, scale);
+            let mut slot = self.change_callback.borrow_mut();
+            // keep the callback set from within this one, if any
+            if slot.is_none() {
+                *slot = Some(callback);
+            }
+        }
+    }
+}
+
+// The timer drawing the frames of the animated cursors of a manager
+#[cfg(feature = "calloop")]
+struct Animator {
+    scheduling: AnimationScheduling,
+    timer: calloop::timer::TimerHandle<Weak<CursorInner>>,
+}
+
+// The scheduling of the next frame of an animated cursor
+#[cfg(feature = "calloop")]
+struct Animation {
+    animator: Rc<RefCell<Option<Animator>>>,
+    // the animated cursor, given to the timer and the frame callbacks
+    cursor: Weak<CursorInner>,
+    // when the delay of the displayed frame expires
+    deadline: Option<Instant>,
+    // the frame callback of the displayed frame, until it is done, numbered by `frames`
+    callback: Option<u32>,
+    frames: u32,
+    timeout: Option<calloop::timer::Timeout>,
+    // whether the frames are drawn on the timer because the frame callbacks stopped arriving
+    fallback: bool,
+}
+
+#[cfg(feature = "calloop")]
+impl Animation {
+    fn new(animator: Rc<RefCell<Option<Animator>>>) -> Animation {
+        Animation {
+            animator,
+            cursor: Weak::new(),
+            deadline: None,
+            callback: None,
+            frames: 0,
+            timeout: None,
+            fallback: false,
+        }
+    }
+}
+
+#[cfg(feature = "calloop")]
+impl CursorInner {
+    // Schedule the next frame of the cursor being drawn, before its frame is committed
+    fn schedule_frame(&self, count: usize, delay: u32) {
+        self.stop_animation();
+        let mut animation = self.animation.borrow_mut();
+        let animator = animation.animator.clone();
+        let animator = animator.borrow();
+        let animator = match *animator {
+            Some(ref animator) if count > 1 => animator,
+            _ => return,
+        };
+        let delay = Duration::from_millis(delay.into());
+        animation.deadline = Some(Instant::now() + delay);
+        let timeout = match animator.scheduling {
+            AnimationScheduling::Timer => delay,
+            AnimationScheduling::FrameCallbacks => {
+                let cursor = animation.cursor.clone();
+                let number = animation.frames.wrapping_add(1);
+                animation.frames = number;
+                animation.callback = Some(number);
+                self.surface.frame().quick_assign(move |_, _, _| {
+                    if let Some(cursor) = cursor.upgrade() {
+                        cursor.frame_done(number);
+                    }
+                });
+                // the timer only draws the frames when the frame callbacks stopped arriving
+                if animation.fallback {
+                    delay
+                } else {
+                    delay + FRAME_CALLBACK_GRACE
+                }
+            }
+        };
+        let cursor = animation.cursor.clone();
+        animation.timeout = Some(animator.timer.add_timeout(timeout, cursor));
+    }
+
+    // The frame callback of a frame is done, the next one is drawn once its delay expired
+    fn frame_done(&self, number: u32) {
+        let remaining = {
+            let mut animation = self.animation.borrow_mut();
+            // the callbacks of the previous frames are ignored
+            if animation.callback != Some(number) {
+                return;
+            }
+            animation.callback = None;
+            animation.fallback = false;
+            animation
+                .deadline
+                .and_then(|deadline| deadline.checked_duration_since(Instant::now()))
+        };
+        match remaining {
+            Some(remaining) if remaining > Duration::from_millis(0) => {
+                self.set_frame_timeout(remaining)
+            }
+            _ => self.next_frame(),
+        }
+    }
+
+    // Draw the next frame after this delay, rather than on the pending timeout
+    fn set_frame_timeout(&self, delay: Duration) {
+        let mut animation = self.animation.borrow_mut();
+        let animator = animation.animator.clone();
+        let animator = animator.borrow();
+        if let Some(ref animator) = *animator {
+            if let Some(timeout) = animation.timeout.take() {
+                animator.timer.cancel_timeout(&timeout);
+            }
+            let cursor = animation.cursor.clone();
+            animation.timeout = Some(animator.timer.add_timeout(delay, cursor));
+        }
+    }
+
+    // The delay of the displayed frame expired, or its frame callback did not arrive in time
+    fn frame_timeout(&self) {
+        {
+            let mut animation = self.animation.borrow_mut();
+            animation.timeout = None;
+            if animation.callback.is_some() {
+                // the compositor stopped asking for frames, keep drawing them on the timer
+                // until it resumes
+                animation.fallback = true;
+            }
+        }
+        self.next_frame();
+    }
+
+    fn next_frame(&self) {
+        if !self.up_to_date.get() || !self.surface.as_ref().is_alive() {
+            return;
+        }
+        if let Err(err) = self.draw_frame(self.frame.get() + 1, true) {
+            log::warn!(
+                "[SCTK] Failed to draw the next frame of the cursor: {}",
+                err
+            );
+        }
+    }
+
+    // Cancel the drawing of the next frame, if the cursor is animated
+    fn stop_animation(&self) {
+        let mut animation = self.animation.borrow_mut();
+        animation.deadline = None;
+        animation.callback = None;
+        let animator = animation.animator.clone();
+        let animator = animator.borrow();
+        if let (Some(timeout), Some(ref animator)) = (animation.timeout.take(), &*animator) {
+            animator.timer.cancel_timeout(&timeout);
+        }
+    }
+}
+
+/// Wrapper of a themed pointer
+///
+/// You can access the underlying `wl_pointer::WlPointer` via
+/// deref. It will *not* release the proxy when dropped.
+///
+/// Just like `Proxy`, this is a `Rc`-like wrapper. You can clone it
+/// to have several handles to the same theming machinery of a pointer.
+pub struct ThemedPointer {
+    pointer: wl_pointer::WlPointer,
+    inner: Rc<CursorInner>,
+}
+
+// load_theme(name, 16, &shm)
+
+impl ThemedPointer {
+    /// Change the cursor to the given cursor name
+    ///
+    /// Possible names depend on the theme. Does nothing and returns an error
+    /// if given name is not available, or if the theme could not be loaded.
+    ///
+    /// If this is done as an answer to an input event, you need to provide
+    /// the associated serial otherwise the server may ignore the request.
+    ///
+    /// Does nothing if the pointer already displays this cursor and the serial
+    /// did not change, so it can be called on each pointer motion.
+    pub fn set_cursor(&self, name: &str, serial: Option<u32>) -> Result<(), ThemeError> {
+        self.inner
+            .set_cursor(name, serial, |serial, surface, hx, hy| {
+                self.pointer.set_cursor(serial, surface, hx, hy)
+            })
+    }
+
+    /// Draw the current cursor again, even if it did not change
+    ///
+    /// Setting the cursor does nothing when it already displays the same cursor.
+    pub fn force_update(&self) -> Result<(), ThemeError> {
+        self.inner.update_cursor(|serial, surface, hx, hy| {
+            self.pointer.set_cursor(serial, surface, hx, hy)
+        })
+    }
+
+    /// Set how themed pointers handle the cursor while over this surface
+    ///
+    /// The policy is stored in the surface, so that all themed pointers agree on it. It
+    /// is only followed by the pointers created with `ThemeManager::theme_pointer_with_impl`
+    /// or `ThemeManager::theme_pointer_with_impl_owned`, which know the surface they are over.
+    ///
+    /// The surface must have been created by SCTK, with `Environment::create_surface` or
+    /// `Environment::create_surface_with_scale_callback`, the policy of other surfaces cannot
+    /// be stored and is ignored.
+    pub fn set_cursor_policy(&self, surface: &wl_surface::WlSurface, policy: CursorPolicy) {
+        if !set_cursor_policy(surface, policy) {
+            log::warn!("[SCTK] Cannot set the cursor policy of a surface not created by SCTK");
+        }
+    }
+
+    /// The name of the cursor last set on this pointer
+    ///
+    /// This is the name given to the last `set_cursor` call, even if it failed.
+    pub fn current_cursor(&self) -> String {
+        self.inner.current_cursor.borrow().clone()
+    }
+
+    /// Set a callback notified when the displayed cursor changes
+    ///
+    /// It is invoked with the name of the cursor and the scale factor of its images each time
+    /// a different cursor is drawn, be it because it was set by `set_cursor` or because the
+    /// pointer moved to an output with another scale factor. It replaces any previously set
+    /// callback.
+    pub fn set_cursor_change_callback<F>(&self, callback: F)
+    where
+        F: FnMut(&str, ScaleFactor) + 'static,
+    {
+        *self.inner.change_callback.borrow_mut() = Some(Box::new(callback));
+    }
+
+    /// Release the pointer and destroy its cursor surface
+    ///
+    /// This is only possible for the pointers created with
+    /// `ThemeManager::theme_pointer_with_impl_owned`, and does nothing for the others.
+    /// The pointer can no longer be used afterwards.
+    pub fn release(&self) {
+        if self.inner.owned_pointer.is_some() {
+            self.inner.release();
+        }
+    }
+
+    /// Coalesce the motion events received during a dispatch
+    ///
+    /// The consecutive `Motion` events are merged, and only the latest one is delivered to the
+    /// callback once the pending events have been dispatched by the event loop, or before the
+    /// next event of another kind, so that the order of the events is preserved. The
+    /// `Frame` event ending the motion is delivered right after it.
+    ///
+    /// This saves processing positions that will never be drawn with high polling rate mice,
+    /// but loses the intermediate positions that drawing apps need, so it is disabled by
+    /// default. It only affects the pointers created with
+    /// `ThemeManager::theme_pointer_with_impl` or `ThemeManager::theme_pointer_with_impl_owned`.
+    ///
+    /// **Note:** This requires the `calloop` cargo feature.
+    #[cfg(feature = "calloop")]
+    pub fn coalesce_motion<Data: 'static>(&self, handle: &calloop::LoopHandle<Data>) {
+        let handle = handle.clone();
+        self.inner.motion.borrow_mut().mode = MotionMode::Idle(Box::new(move |flush| {
+            handle.insert_idle(move |data| flush(DispatchData::wrap(data)));
+        }));
+    }
+
+    /// Coalesce the motion events until they are flushed
+    ///
+    /// This is like [`coalesce_motion`](#method.coalesce_motion), except that the latest
+    /// motion is only delivered by [`flush_motion`](#method.flush_motion), typically from the
+    /// frame callback of your surface, or before the next event of another kind.
+    pub fn coalesce_motion_until_flush(&self) {
+        self.inner.motion.borrow_mut().mode = MotionMode::UntilFlush;
+    }
+
+    /// Stop coalescing the motion events
+    ///
+    /// A motion already held back is delivered before the next event, or by
+    /// [`flush_motion`](#method.flush_motion).
+    pub fn disable_motion_coalescing(&self) {
+        self.inner.motion.borrow_mut().mode = MotionMode::Disabled;
+    }
+
+    /// Deliver the motion held back by the coalescing to the callback of the pointer
+    ///
+    /// Does nothing if no motion is pending, or if called from the callback itself.
+    pub fn flush_motion(&self, ddata: DispatchData) {
+        let callback = self
+            .inner
+            .motion
+            .borrow()
+            .callback
+            .as_ref()
+            .and_then(Weak::upgrade);
+        if let Some(callback) = callback {
+            flush_pending_motion(self, &callback, ddata);
+        }
+    }
+
+    /// The number of `Motion` events merged into the event being delivered
+    ///
+    /// This is meant to be called from the callback of the pointer, and is 1 for the events
+    /// which were not coalesced.
+    pub fn events_coalesced(&self) -> u32 {
+        self.inner.motion.borrow().delivered_count
+    }
+}
+
+// Deliver an event to the callback of a pointer, holding back the motion events to
+// coalesce them if enabled
+fn process_pointer_event(
+    pointer: &ThemedPointer,
+    callback: &Rc<RefCell<PointerCallback>>,
+    event: wl_pointer::Event,
+    mut ddata: DispatchData,
+) {
+    let mut queue = pointer.inner.motion.borrow_mut();
+    match event {
+        wl_pointer::Event::Motion { .. } => {
+            let coalesce = match queue.mode {
+                MotionMode::Disabled => false,
+                MotionMode::UntilFlush => true,
+                #[cfg(feature = "calloop")]
+                MotionMode::Idle(_) => true,
+            };
+            if coalesce {
+                queue.pending_count = if queue.pending.is_some() {
+                    queue.pending_count + 1
+                } else {
+                    1
+                };
+                queue.pending = Some(event);
+                #[cfg(feature = "calloop")]
+                schedule_idle_flush(&mut queue, pointer, callback);
+                return;
+            }
+        }
+        wl_pointer::Event::Frame if queue.pending.is_some() => {
+            queue.pending_frame = true;
+            return;
+        }
+        _ => {}
+    }
+    drop(queue);
+    // any other event is delivered after the pending motion
+    flush_pending_motion(pointer, callback, ddata.reborrow());
+    (&mut *callback.borrow_mut())(event, pointer.clone(), ddata);
+}
+
+// Schedule the delivery of the pending motion once the pending events are dispatched, in the
+// idle mode
+#[cfg(feature = "calloop")]
+fn schedule_idle_flush(
+    queue: &mut MotionQueue,
+    pointer: &ThemedPointer,
+    callback: &Rc<RefCell<PointerCallback>>,
+) {
+    if let MotionMode::Idle(ref scheduler) = queue.mode {
+        if queue.scheduled {
+            return;
+        }
+        queue.scheduled = true;
+        let pointer = pointer.clone();
+        let callback = Rc::downgrade(callback);
+        scheduler(Box::new(move |ddata| {
+            pointer.inner.motion.borrow_mut().scheduled = false;
+            if let Some(callback) = callback.upgrade() {
+                flush_pending_motion(&pointer, &callback, ddata);
+            }
+        }));
+    }
+}
+
+fn flush_pending_motion(
+    pointer: &ThemedPointer,
+    callback: &RefCell<PointerCallback>,
+    mut ddata: DispatchData,
+) {
+    let mut callback = match callback.try_borrow_mut() {
+        Ok(callback) => callback,
+        Err(_) => return,
+    };
+    let mut queue = pointer.inner.motion.borrow_mut();
+    let event = match queue.pending.take() {
+        Some(event) => event,
+        None => return,
+    };
+    let frame = std::mem::replace(&mut queue.pending_frame, false);
+    queue.delivered_count = queue.pending_count;
+    drop(queue);
+    (&mut *callback)(event, pointer.clone(), ddata.reborrow());
+    pointer.inner.motion.borrow_mut().delivered_count = 1;
+    if frame {
+        (&mut *callback)(wl_pointer::Event::Frame, pointer.clone(), ddata);
+    }
+}
+
+impl Clone for ThemedPointer {
+    fn clone(&self) -> ThemedPointer {
+        ThemedPointer {
+            pointer: self.pointer.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Deref for ThemedPointer {
     type Target = wl_pointer::WlPointer;
     fn deref(&self) -> &wl_pointer::WlPointer {
         &self.pointer
     }
 }
 
-impl Drop for PointerInner {
+/// Wrapper of a themed tablet tool
+///
+/// You can access the underlying `zwp_tablet_tool_v2::ZwpTabletToolV2` via
+/// deref. It will *not* release the proxy when dropped.
+///
+/// Just like `ThemedPointer`, you can clone it to have several handles to
+/// the same theming machinery of a tool.
+pub struct ThemedTool {
+    tool: zwp_tablet_tool_v2::ZwpTabletToolV2,
+    inner: Rc<CursorInner>,
+}
+
+impl ThemedTool {
+    /// Change the cursor to the given cursor name
+    ///
+    /// Possible names depend on the theme. Does nothing and returns an error
+    /// if given name is not available, or if the theme could not be loaded.
+    ///
+    /// The serial is the one of the last `proximity_in` event of the tool,
+    /// otherwise the server may ignore the request.
+    ///
+    /// Does nothing if the tool already displays this cursor and the serial
+    /// did not change.
+    pub fn set_cursor(&self, name: &str, serial: Option<u32>) -> Result<(), ThemeError> {
+        self.inner
+            .set_cursor(name, serial, |serial, surface, hx, hy| {
+                self.tool.set_cursor(serial, surface, hx, hy)
+            })
+    }
+
+    /// Draw the current cursor again, even if it did not change
+    ///
+    /// Setting the cursor does nothing when it already displays the same cursor.
+    pub fn force_update(&self) -> Result<(), ThemeError> {
+        self.inner
+            .update_cursor(|serial, surface, hx, hy| self.tool.set_cursor(serial, surface, hx, hy))
+    }
+}
+
+impl Clone for ThemedTool {
+    fn clone(&self) -> ThemedTool {
+        ThemedTool {
+            tool: self.tool.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Deref for ThemedTool {
+    type Target = zwp_tablet_tool_v2::ZwpTabletToolV2;
+    fn deref(&self) -> &zwp_tablet_tool_v2::ZwpTabletToolV2 {
+        &self.tool
+    }
+}
+
+impl CursorInner {
+    fn release(&self) {
+        self.release_pointer();
+        // a released surface is not reused
+        self.pooled.borrow_mut().take();
+        self.destroy_viewport();
+        if self.surface.as_ref().is_alive() {
+            self.surface.destroy();
+        }
+    }
+
+    // The viewport must be destroyed before its surface, or before the surface is reused
+    fn destroy_viewport(&self) {
+        if let Some(viewport) = self.viewport.borrow_mut().take() {
+            if viewport.as_ref().is_alive() {
+                viewport.destroy();
+            }
+        }
+    }
+
+    fn release_pointer(&self) {
+        if let Some(ref pointer) = self.owned_pointer {
+            // wl_pointer.release was added in version 3
+            if pointer.as_ref().is_alive() && has_version(pointer, 3) {
+                pointer.release();
+            }
+        }
+    }
+}
+
+type ScaleHandler = Rc<dyn Fn(ScaleFactor)>;
+
+// A cursor surface of a pool, whose scale factor changes are forwarded to the pointer
+// currently using it
+#[derive(Clone)]
+struct CursorSurface {
+    surface: Attached<wl_surface::WlSurface>,
+    scale_handler: Rc<RefCell<Option<ScaleHandler>>>,
+    pool: Weak<RefCell<SurfacePool>>,
+}
+
+impl CursorSurface {
+    fn set_scale_handler(&self, handler: Option<ScaleHandler>) {
+        *self.scale_handler.borrow_mut() = handler;
+    }
+}
+
+// The cursor surfaces kept for reuse
+struct SurfacePool {
+    compositor: Attached<wl_compositor::WlCompositor>,
+    surfaces: Vec<CursorSurface>,
+    capacity: usize,
+}
+
+impl SurfacePool {
+    fn new(compositor: Attached<wl_compositor::WlCompositor>) -> SurfacePool {
+        SurfacePool {
+            compositor,
+            surfaces: Vec::new(),
+            capacity: 4,
+        }
+    }
+
+    // Take a surface of the pool, or create one if it is empty
+    fn take(pool: &Rc<RefCell<SurfacePool>>) -> CursorSurface {
+        if let Some(cursor_surface) = pool.borrow_mut().surfaces.pop() {
+            return cursor_surface;
+        }
+        let scale_handler = Rc::new(RefCell::new(None::<ScaleHandler>));
+        let my_handler = scale_handler.clone();
+        let surface = pool.borrow().compositor.create_surface();
+        let attached = (*surface).clone();
+        // the handler is also notified when the surface enters or leaves an output, as the
+        // fractional scale of the cursor may change
+        crate::surface::setup_surface_with_outputs_callback(
+            surface,
+            move |scale_factor, _: DispatchData| {
+                // not borrowed while running, the handler may replace itself
+                let handler = my_handler.borrow().clone();
+                if let Some(handler) = handler {
+                    handler(scale_factor);
+                }
+            },
+        );
+        CursorSurface {
+            surface: attached,
+            scale_handler,
+            pool: Rc::downgrade(pool),
+        }
+    }
+
+    // Return a surface no longer used by any pointer to its pool, destroying it if the
+    // pool is full
+    fn put(cursor_surface: CursorSurface) {
+        cursor_surface.set_scale_handler(None);
+        let pool = match cursor_surface.pool.upgrade() {
+            Some(pool) => pool,
+            None => {
+                cursor_surface.surface.destroy();
+                return;
+            }
+        };
+        let mut pool = pool.borrow_mut();
+        if pool.surfaces.len() >= pool.capacity {
+            cursor_surface.surface.destroy();
+            return;
+        }
+        // detach the previous cursor, its buffer may be released with its theme
+        cursor_surface.surface.attach(None, 0, 0);
+        cursor_surface.surface.commit();
+        pool.surfaces.push(cursor_surface);
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if self.surfaces.len() > capacity {
+            for cursor_surface in self.surfaces.drain(capacity..) {
+                cursor_surface.surface.destroy();
+            }
+        }
+    }
+}
+
+impl Drop for SurfacePool {
+    fn drop(&mut self) {
+        for cursor_surface in self.surfaces.drain(..) {
+            cursor_surface.surface.destroy();
+        }
+    }
+}
+
+impl Drop for CursorInner {
     fn drop(&mut self) {
-        self.surface.destroy();
+        #[cfg(feature = "calloop")]
+        self.stop_animation();
+        match self.pooled.get_mut().take() {
+            Some((cursor_surface, pointer)) if self.surface.as_ref().is_alive() => {
+                // the surface must no longer be the cursor of this pointer before being reused
+                if self.owned_pointer.is_some() {
+                    self.release_pointer();
+                } else if pointer.as_ref().is_alive() {
+                    pointer.set_cursor(self.last_serial.get(), None, 0, 0);
+                }
+                self.destroy_viewport();
+                SurfacePool::put(cursor_surface);
+            }
+            _ => self.release(),
+        }
+        let mut themes = self.themes.borrow_mut();
+        for key in self.attached.get().into_iter().chain(self.previous.get()) {
+            themes.release(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::MultiGlobalHandler;
+    use crate::output::OutputHandler;
+    use crate::test_server::{count, count_id, Request, TestClient};
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::{
+        path::PathBuf,
+        sync::{Mutex, MutexGuard},
+    };
+    use wayland_client::{protocol::wl_output, Main};
+
+    // opcodes of the requests
+    const COMPOSITOR_CREATE_SURFACE: u16 = 0;
+    const SEAT_GET_POINTER: u16 = 0;
+    const SURFACE_DESTROY: u16 = 0;
+    const SURFACE_ATTACH: u16 = 1;
+    const SURFACE_FRAME: u16 = 3;
+    const SURFACE_COMMIT: u16 = 6;
+    const SURFACE_SET_BUFFER_SCALE: u16 = 8;
+    const POINTER_SET_CURSOR: u16 = 0;
+    const POINTER_RELEASE: u16 = 1;
+
+    lazy_static::lazy_static! {
+        // the themes are looked up in `XCURSOR_PATH`, the tests loading one must not run
+        // concurrently
+        static ref XCURSOR_PATH_LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    fn lock_xcursor_path() -> MutexGuard<'static, ()> {
+        XCURSOR_PATH_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // An Xcursor theme written in a temporary directory, which is the search path of the
+    // themes until it is dropped
+    struct TestTheme {
+        dir: PathBuf,
+        name: String,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl TestTheme {
+        // Write a theme with these cursors, each a single image of 24x24 pixels
+        fn new(name: &str, cursors: &[&str]) -> TestTheme {
+            let lock = lock_xcursor_path();
+            let dir =
+                std::env::temp_dir().join(format!("sctk-theme-{}-{}", std::process::id(), name));
+            std::fs::create_dir_all(dir.join(name).join("cursors")).unwrap();
+            for cursor in cursors {
+                std::fs::write(dir.join(name).join("cursors").join(cursor), xcursor_file(24))
+                    .unwrap();
+            }
+            std::env::set_var("XCURSOR_PATH", &dir);
+            TestTheme {
+                dir,
+                name: name.into(),
+                _lock: lock,
+            }
+        }
+
+        // Add a cursor with the content of this Xcursor file
+        fn add_cursor(&self, cursor: &str, file: Vec<u8>) {
+            std::fs::write(self.dir.join(&self.name).join("cursors").join(cursor), file).unwrap();
+        }
+
+        fn spec(&self) -> ThemeSpec<'_> {
+            ThemeSpec::Precise {
+                name: &self.name,
+                size: 24,
+            }
+        }
+    }
+
+    impl Drop for TestTheme {
+        fn drop(&mut self) {
+            std::env::remove_var("XCURSOR_PATH");
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // An Xcursor file with a single image of this nominal size
+    fn xcursor_file(size: u32) -> Vec<u8> {
+        xcursor_animation(size, &[0])
+    }
+
+    // An Xcursor file with an image of this nominal size for each frame, displayed for the
+    // given delays
+    fn xcursor_animation(size: u32, delays: &[u32]) -> Vec<u8> {
+        let black = vec![0xff00_0000; (size * size) as usize];
+        let frames = delays
+            .iter()
+            .map(|&delay| (delay, black.clone()))
+            .collect::<Vec<_>>();
+        xcursor_frames(size, &frames)
+    }
+
+    // An Xcursor file with a frame of this nominal size for each delay and pixels
+    fn xcursor_frames(size: u32, frames: &[(u32, Vec<u32>)]) -> Vec<u8> {
+        let images = frames
+            .iter()
+            .map(|&(delay, ref pixels)| (size, delay, pixels.clone()))
+            .collect::<Vec<_>>();
+        xcursor_images(&images)
+    }
+
+    // An Xcursor file with a single black image of each of these nominal sizes
+    fn xcursor_sizes(sizes: &[u32]) -> Vec<u8> {
+        let images = sizes
+            .iter()
+            .map(|&size| (size, 0, vec![0xff00_0000; (size * size) as usize]))
+            .collect::<Vec<_>>();
+        xcursor_images(&images)
+    }
+
+    // An Xcursor file with these images, given by their nominal size, delay and pixels
+    fn xcursor_images(images: &[(u32, u32, Vec<u32>)]) -> Vec<u8> {
+        let count = images.len() as u32;
+        // header: magic, header size, version, number of toc entries
+        let mut words = vec![LittleEndian::read_u32(b"Xcur"), 16, 0x1_0000, count];
+        let mut position = 16 + count * 12;
+        for &(size, _, _) in images {
+            // toc entry: type, nominal size, position
+            words.extend_from_slice(&[0xfffd_0002, size, position]);
+            position += 36 + size * size * 4;
+        }
+        for &(size, delay, ref pixels) in images {
+            // image chunk: header size, type, nominal size, version,
+            // width, height, xhot, yhot, delay
+            words.extend_from_slice(&[36, 0xfffd_0002, size, 1, size, size, 0, 0, delay]);
+            words.extend_from_slice(pixels);
+        }
+        let mut data = vec![0; words.len() * 4];
+        LittleEndian::write_u32_into(&words, &mut data);
+        data
+    }
+
+    struct Setup {
+        client: TestClient,
+        manager: ThemeManager,
+        compositor: Main<wl_compositor::WlCompositor>,
+        seat: Main<wl_seat::WlSeat>,
+    }
+
+    fn setup(theme: ThemeSpec) -> Setup {
+        let mut client = TestClient::new();
+        let compositor = client.bind::<wl_compositor::WlCompositor>(4);
+        let shm = client.bind::<wl_shm::WlShm>(1);
+        let seat = client.bind::<wl_seat::WlSeat>(7);
+        let manager = ThemeManager::init(theme, (*compositor).clone(), (*shm).clone());
+        client.requests();
+        Setup {
+            client,
+            manager,
+            compositor,
+            seat,
+        }
+    }
+
+    // An output with this scale factor, tracked by SCTK
+    fn scaled_output(client: &mut TestClient, factor: i32) -> wl_output::WlOutput {
+        let mut outputs = OutputHandler::new();
+        outputs.created(client.registry(), 100, 3, DispatchData::wrap(&mut ()));
+        let output = outputs.get_all().remove(0).detach();
+        // wl_output.scale and wl_output.done
+        client.send(output.as_ref(), 3, &[factor as u32]);
+        client.send(output.as_ref(), 2, &[]);
+        client.dispatch();
+        output
+    }
+
+    #[test]
+    fn scale_change_and_set_cursor_in_the_same_dispatch() {
+        let theme = TestTheme::new("same-dispatch", &["left_ptr", "text"]);
+        let mut setup = setup(theme.spec());
+        let output = scaled_output(&mut setup.client, 2);
+        let surface = setup.compositor.create_surface();
+        let seat = (*setup.seat).clone();
+        let pointer = setup
+            .manager
+            .theme_pointer_with_impl(&seat, |event, pointer, _| {
+                if let wl_pointer::Event::Enter { serial, .. } = event {
+                    pointer.set_cursor("text", Some(serial)).unwrap();
+                }
+            });
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        setup.client.requests();
+
+        // the cursor surface enters a HiDPI output in the same batch of events as the
+        // pointer entering a surface, whose callback sets the cursor
+        setup
+            .client
+            .send(pointer.inner.surface.as_ref(), 0, &[output.as_ref().id()]);
+        setup
+            .client
+            .send(pointer.as_ref(), 0, &[2, surface.as_ref().id(), 0, 0]);
+        setup.client.dispatch();
+
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 2);
+        let scales = requests
+            .iter()
+            .filter(|r| r.opcode == SURFACE_SET_BUFFER_SCALE)
+            .map(|r| r.args[0])
+            .collect::<Vec<_>>();
+        assert_eq!(scales, vec![2, 2]);
+        assert_eq!(pointer.inner.scale_factor.get().get(), 2);
+        assert_eq!(pointer.inner.last_serial.get(), 2);
+        assert_eq!(&*pointer.inner.current_cursor.borrow(), "text");
+    }
+
+    #[test]
+    fn cursor_follows_the_scale_of_its_outputs() {
+        let theme = TestTheme::new("scale-switch", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let lodpi = scaled_output(&mut setup.client, 1);
+        let hidpi = scaled_output(&mut setup.client, 2);
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        setup.client.requests();
+
+        // the cursor moves from one output to the other and back, the surface keeps its
+        // scale factor when it leaves all the outputs so it is always on one of them
+        let moves = [
+            (0, &lodpi),
+            (0, &hidpi),
+            (1, &lodpi),
+            (0, &lodpi),
+            (1, &hidpi),
+        ];
+        for &(opcode, output) in &moves {
+            // wl_surface.enter or wl_surface.leave
+            setup.client.send(
+                pointer.inner.surface.as_ref(),
+                opcode,
+                &[output.as_ref().id()],
+            );
+            setup.client.dispatch();
+        }
+
+        let requests = setup.client.requests();
+        let scales = requests
+            .iter()
+            .filter(|r| r.opcode == SURFACE_SET_BUFFER_SCALE)
+            .map(|r| r.args[0])
+            .collect::<Vec<_>>();
+        assert_eq!(scales, vec![2, 1]);
+        // the cursor is drawn again with the images of each scale
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_ATTACH),
+            2
+        );
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 2);
+        assert_eq!(pointer.inner.scale_factor.get().get(), 1);
+        assert_eq!(
+            setup
+                .manager
+                .themes
+                .borrow()
+                .themes
+                .iter()
+                .map(|&((size, _, _), _, _)| size)
+                .collect::<Vec<_>>(),
+            vec![24, 48]
+        );
+    }
+
+    #[test]
+    fn fractional_sizes_follow_the_rounding() {
+        let theme = TestTheme::new("rounding", &[]);
+        theme.add_cursor("left_ptr", xcursor_sizes(&[24, 48]));
+        let mut setup = setup(theme.spec());
+        let viewporter = setup.client.bind::<WpViewporter>(1);
+
+        let roundings = [
+            (SizeRounding::RoundDown, 36, 26),
+            (SizeRounding::RoundUp, 36, 27),
+            (SizeRounding::Nearest, 36, 26),
+            // ties are broken towards the larger size
+            (SizeRounding::SnapToAvailable, 48, 24),
+        ];
+        for &(rounding, at_1_5, at_1_1) in &roundings {
+            setup
+                .manager
+                .set_fractional_scaling((*viewporter).clone(), rounding);
+            assert_eq!(setup.manager.cursor_size(1.5), at_1_5);
+            assert_eq!(setup.manager.cursor_size(1.1), at_1_1);
+            // integer scales are not rounded
+            assert_eq!(setup.manager.cursor_size(2.0), 48);
+        }
+    }
+
+    #[test]
+    fn fractional_scale_is_drawn_with_a_viewport() {
+        const VIEWPORTER_GET_VIEWPORT: u16 = 1;
+        const VIEWPORT_SET_DESTINATION: u16 = 2;
+
+        let theme = TestTheme::new("viewport", &[]);
+        theme.add_cursor("left_ptr", xcursor_sizes(&[24, 48]));
+        let mut setup = setup(theme.spec());
+        let viewporter = setup.client.bind::<WpViewporter>(1);
+        let output = scaled_output(&mut setup.client, 2);
+        setup
+            .manager
+            .set_fractional_scaling((*viewporter).clone(), SizeRounding::SnapToAvailable);
+        setup.manager.set_output_scale(&output, Some(1.5));
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        setup.client.requests();
+
+        // wl_surface.enter
+        setup
+            .client
+            .send(pointer.inner.surface.as_ref(), 0, &[output.as_ref().id()]);
+        setup.client.dispatch();
+
+        let requests = setup.client.requests();
+        let get_viewport = requests
+            .iter()
+            .filter(|r| r.object == viewporter.as_ref().id() && r.opcode == VIEWPORTER_GET_VIEWPORT)
+            .collect::<Vec<_>>();
+        assert_eq!(get_viewport.len(), 1);
+        let viewport = get_viewport[0].args[0];
+        let destinations = |requests: &[Request]| {
+            requests
+                .iter()
+                .filter(|r| r.object == viewport && r.opcode == VIEWPORT_SET_DESTINATION)
+                .map(|r| (r.args[0] as i32, r.args[1] as i32))
+                .collect::<Vec<_>>()
+        };
+        let scales = |requests: &[Request]| {
+            requests
+                .iter()
+                .filter(|r| r.opcode == SURFACE_SET_BUFFER_SCALE)
+                .map(|r| r.args[0])
+                .collect::<Vec<_>>()
+        };
+        // the images of 48 pixels are scaled to the logical size of the cursor
+        assert_eq!(destinations(&requests), vec![(24, 24)]);
+        assert_eq!(scales(&requests), vec![1]);
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+        let loaded = |setup: &Setup| {
+            setup
+                .manager
+                .themes
+                .borrow()
+                .themes
+                .iter()
+                .map(|&((size, _, exact), _, _)| (size, exact))
+                .collect::<Vec<_>>()
+        };
+        assert!(loaded(&setup).contains(&(48, true)));
+
+        // the images are scaled to 36 pixels when rounding down
+        setup
+            .manager
+            .set_fractional_scaling((*viewporter).clone(), SizeRounding::RoundDown);
+        let requests = setup.client.requests();
+        assert_eq!(destinations(&requests), vec![(24, 24)]);
+        assert!(loaded(&setup).contains(&(36, true)));
+
+        // the integer scale factor of the output is used once its fractional scale is unset
+        setup.manager.set_output_scale(&output, None);
+        let requests = setup.client.requests();
+        assert_eq!(destinations(&requests), vec![(-1, -1)]);
+        assert_eq!(scales(&requests), vec![2]);
+        assert!(loaded(&setup).contains(&(48, false)));
+    }
+
+    #[test]
+    fn set_cursor_from_the_pointer_callback() {
+        let theme = TestTheme::new("in-callback", &["left_ptr", "text"]);
+        let mut setup = setup(theme.spec());
+        let surface = setup.compositor.create_surface();
+        let seat = (*setup.seat).clone();
+        let pointer = setup
+            .manager
+            .theme_pointer_with_impl(&seat, |event, pointer, _| match event {
+                wl_pointer::Event::Enter { serial, .. } => {
+                    pointer.set_cursor("text", Some(serial)).unwrap()
+                }
+                wl_pointer::Event::Leave { .. } => pointer.set_cursor("left_ptr", None).unwrap(),
+                _ => {}
+            });
+        setup.client.requests();
+
+        // wl_pointer.enter and wl_pointer.leave
+        setup
+            .client
+            .send(pointer.as_ref(), 0, &[3, surface.as_ref().id(), 0, 0]);
+        setup
+            .client
+            .send(pointer.as_ref(), 1, &[4, surface.as_ref().id()]);
+        setup.client.dispatch();
+
+        let requests = setup.client.requests();
+        let serials = requests
+            .iter()
+            .filter(|r| r.object == pointer.as_ref().id() && r.opcode == POINTER_SET_CURSOR)
+            .map(|r| r.args[0])
+            .collect::<Vec<_>>();
+        assert_eq!(serials, vec![3, 3]);
+        assert_eq!(&*pointer.inner.current_cursor.borrow(), "left_ptr");
+    }
+
+    #[test]
+    fn redundant_set_cursor_sends_no_request() {
+        let theme = TestTheme::new("redundant", &["left_ptr", "text"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("text", Some(1)).unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_COMMIT),
+            1
+        );
+
+        for _ in 0..10 {
+            pointer.set_cursor("text", Some(1)).unwrap();
+            pointer.set_cursor("text", None).unwrap();
+        }
+        assert_eq!(setup.client.requests(), vec![]);
+        assert_eq!(&*pointer.inner.current_cursor.borrow(), "text");
+    }
+
+    #[test]
+    fn changed_cursor_or_serial_is_sent() {
+        let theme = TestTheme::new("changed", &["left_ptr", "text"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("text", Some(1)).unwrap();
+        setup.client.requests();
+
+        // another cursor
+        pointer.set_cursor("left_ptr", None).unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_ATTACH),
+            1
+        );
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+
+        // the same cursor with a new serial must be set again, but not redrawn
+        pointer.set_cursor("left_ptr", Some(2)).unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+        assert_eq!(
+            requests
+                .iter()
+                .find(|r| r.opcode == POINTER_SET_CURSOR)
+                .unwrap()
+                .args[0],
+            2
+        );
+    }
+
+    #[test]
+    fn force_update_sends_the_same_cursor_again() {
+        let theme = TestTheme::new("force", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        setup.client.requests();
+
+        pointer.force_update().unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_ATTACH),
+            1
+        );
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_COMMIT),
+            1
+        );
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+    }
+
+    #[test]
+    fn missing_cursor_is_retried_once_set_again() {
+        let theme = TestTheme::new("missing", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        assert_eq!(
+            pointer.set_cursor("text", Some(1)),
+            Err(ThemeError::CursorNotFound)
+        );
+        let requests = setup.client.requests();
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_ATTACH),
+            0
+        );
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 0);
+        // the failed cursor is not considered displayed
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+    }
+
+    #[test]
+    fn bogus_xcursor_path() {
+        let _lock = lock_xcursor_path();
+        std::env::set_var("XCURSOR_PATH", "/nonexistent");
+        let mut setup = setup(ThemeSpec::Precise {
+            name: "nonexistent",
+            size: 24,
+        });
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        for _ in 0..3 {
+            assert_eq!(
+                pointer.set_cursor("left_ptr", Some(1)),
+                Err(ThemeError::ThemeLoadFailed)
+            );
+        }
+        assert_eq!(setup.manager.get_cursor_image("left_ptr", 1), None);
+        std::env::remove_var("XCURSOR_PATH");
+        // the failure is cached rather than retried on each call
+        assert_eq!(setup.manager.themes.borrow().themes.len(), 1);
+        let requests = setup.client.requests();
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_ATTACH),
+            0
+        );
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 0);
+    }
+
+    #[test]
+    fn missing_theme_falls_back_to_default() {
+        let _default = TestTheme::new("default", &["left_ptr"]);
+        let mut setup = setup(ThemeSpec::Precise {
+            name: "nonexistent",
+            size: 24,
+        });
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        assert_eq!(
+            pointer.set_cursor("text", Some(1)),
+            Err(ThemeError::CursorNotFound)
+        );
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+        // the images are read from the same theme
+        let image = setup.manager.get_cursor_image("left_ptr", 2).unwrap();
+        assert_eq!((image.width, image.height, image.hotspot), (48, 48, (0, 0)));
+        assert_eq!(image.rgba.len(), 48 * 48 * 4);
+        assert_eq!(&image.rgba[..4], &[0, 0, 0, 0xff]);
+        assert_eq!(setup.manager.get_cursor_image("text", 1), None);
+    }
+
+    #[test]
+    fn theme_loaded_from_a_directory() {
+        let theme = TestTheme::new("by-path", &["left_ptr", "text"]);
+        let mut setup = setup(ThemeSpec::Path {
+            path: &theme.dir.join(&theme.name),
+            size: 24,
+        });
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        pointer.set_cursor("text", Some(1)).unwrap();
+        assert_eq!(
+            pointer.set_cursor("wait", Some(1)),
+            Err(ThemeError::CursorNotFound)
+        );
+        let requests = setup.client.requests();
+        assert_eq!(
+            count(&requests, pointer.inner.surface.as_ref(), SURFACE_ATTACH),
+            2
+        );
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 2);
+    }
+
+    #[test]
+    fn missing_theme_directory_falls_back_to_default() {
+        let _default = TestTheme::new("default", &["left_ptr"]);
+        let mut setup = setup(ThemeSpec::Path {
+            path: Path::new("/nonexistent"),
+            size: 24,
+        });
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+        assert!(setup.manager.get_cursor_image("left_ptr", 1).is_some());
+    }
+
+    #[test]
+    fn owned_pointer_is_released_with_its_last_handle() {
+        let theme = TestTheme::new("owned", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer_with_impl_owned(&setup.seat, |_, _, _| {});
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        let wl_pointer = (*pointer).clone();
+        let id = wl_pointer.as_ref().id();
+        let clone = pointer.clone();
+        drop(pointer);
+        let requests = setup.client.requests();
+        assert_eq!(count_id(&requests, id, POINTER_RELEASE), 0);
+
+        drop(clone);
+        let requests = setup.client.requests();
+        assert_eq!(count_id(&requests, id, POINTER_RELEASE), 1);
+        assert!(!wl_pointer.as_ref().is_alive());
+    }
+
+    #[test]
+    fn explicit_release_of_an_owned_pointer() {
+        let theme = TestTheme::new("release", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer_with_impl_owned(&setup.seat, |_, _, _| {});
+        let pointer_id = pointer.as_ref().id();
+        let surface_id = pointer.inner.surface.as_ref().id();
+        pointer.release();
+        let requests = setup.client.requests();
+        assert_eq!(count_id(&requests, pointer_id, POINTER_RELEASE), 1);
+        assert_eq!(count_id(&requests, surface_id, SURFACE_DESTROY), 1);
+
+        // nothing is sent twice once the handles are dropped
+        drop(pointer);
+        let requests = setup.client.requests();
+        assert_eq!(count_id(&requests, pointer_id, POINTER_RELEASE), 0);
+        assert_eq!(count_id(&requests, surface_id, SURFACE_DESTROY), 0);
+    }
+
+    #[test]
+    fn borrowed_pointer_is_not_released() {
+        let theme = TestTheme::new("borrowed", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer_with_impl(&setup.seat, |_, _, _| {});
+        let wl_pointer = (*pointer).clone();
+        pointer.release();
+        drop(pointer);
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, wl_pointer.as_ref(), POINTER_RELEASE), 0);
+        assert!(wl_pointer.as_ref().is_alive());
+    }
+
+    #[test]
+    fn toggling_the_pointer_capability() {
+        let theme = TestTheme::new("capability", &["left_ptr"]);
+        let mut setup = setup(theme.spec());
+        let manager = setup.manager;
+        let seat_id = setup.seat.as_ref().id();
+        let compositor_id = setup.compositor.as_ref().id();
+        // the pointers created so far, and the current one
+        let pointers = Rc::new(RefCell::new(Vec::new()));
+        let current = Rc::new(RefCell::new(None));
+        let my_pointers = pointers.clone();
+        let my_current = current.clone();
+        setup.seat.quick_assign(move |seat, event, _| {
+            if let wl_seat::Event::Capabilities { capabilities } = event {
+                let mut current = my_current.borrow_mut();
+                if capabilities.contains(wl_seat::Capability::Pointer) {
+                    if current.is_none() {
+                        let pointer = manager.theme_pointer_with_impl_owned(&seat, |_, _, _| {});
+                        pointer.set_cursor("left_ptr", None).unwrap();
+                        my_pointers.borrow_mut().push((*pointer).clone());
+                        *current = Some(pointer);
+                    }
+                } else {
+                    *current = None;
+                }
+            }
+        });
+
+        let mut requests = Vec::new();
+        for _ in 0..5 {
+            for &capabilities in &[wl_seat::Capability::Pointer, wl_seat::Capability::empty()] {
+                // wl_seat.capabilities
+                setup
+                    .client
+                    .send(setup.seat.as_ref(), 0, &[capabilities.to_raw()]);
+                setup.client.dispatch();
+                requests.extend(setup.client.requests());
+            }
+        }
+        assert!(current.borrow().is_none());
+        let get_pointers = requests
+            .iter()
+            .filter(|r| r.object == seat_id && r.opcode == SEAT_GET_POINTER)
+            .collect::<Vec<_>>();
+        assert_eq!(get_pointers.len(), 5);
+        for request in get_pointers {
+            // the id of the new pointer
+            assert_eq!(count_id(&requests, request.args[0], POINTER_RELEASE), 1);
+        }
+        for pointer in pointers.borrow().iter() {
+            assert!(!pointer.as_ref().is_alive());
+        }
+        // the cursor surface is reused by the next pointers
+        assert_eq!(count_id(&requests, compositor_id, COMPOSITOR_CREATE_SURFACE), 1);
+    }
+
+    #[test]
+    fn scale_change_while_setting_the_cursor() {
+        let theme = TestTheme::new("scale-in-set", &["left_ptr", "text"]);
+        let setup = setup(theme.spec());
+        let client = Rc::new(RefCell::new(setup.client));
+        let output = scaled_output(&mut client.borrow_mut(), 2);
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        client.borrow_mut().requests();
+
+        // the cursor surface enters a HiDPI output while the cursor is being set
+        let surface = pointer.inner.surface.clone();
+        let my_client = client.clone();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let my_changes = changes.clone();
+        pointer.set_cursor_change_callback(move |name, scale| {
+            my_changes.borrow_mut().push((name.to_owned(), scale.get()));
+            if name == "text" && scale.get() == 1 {
+                let mut client = my_client.borrow_mut();
+                // wl_surface.enter
+                client.send(surface.as_ref(), 0, &[output.as_ref().id()]);
+                client.dispatch();
+            }
+        });
+        pointer.set_cursor("text", Some(2)).unwrap();
+
+        let requests = client.borrow_mut().requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 2);
+        let scales = requests
+            .iter()
+            .filter(|r| r.opcode == SURFACE_SET_BUFFER_SCALE)
+            .map(|r| r.args[0])
+            .collect::<Vec<_>>();
+        assert_eq!(scales, vec![1, 2]);
+        assert_eq!(pointer.inner.scale_factor.get().get(), 2);
+        // the callback is not invoked from within itself
+        assert_eq!(&*changes.borrow(), &[("text".to_owned(), 1)]);
+        assert_eq!(
+            *pointer.inner.displayed.borrow(),
+            Some(("text".to_owned(), ScaleFactor::new(2).unwrap()))
+        );
+    }
+
+    #[test]
+    fn set_cursor_while_changing_the_scale() {
+        let theme = TestTheme::new("set-in-scale", &["left_ptr", "text"]);
+        let mut setup = setup(theme.spec());
+        let output = scaled_output(&mut setup.client, 2);
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("left_ptr", Some(1)).unwrap();
+        setup.client.requests();
+
+        // the app changes the cursor when notified of the new scale
+        let my_pointer = pointer.clone();
+        pointer.set_cursor_change_callback(move |_, scale| {
+            if scale.get() == 2 {
+                my_pointer.set_cursor("text", None).unwrap();
+            }
+        });
+        // wl_surface.enter
+        setup
+            .client
+            .send(pointer.inner.surface.as_ref(), 0, &[output.as_ref().id()]);
+        setup.client.dispatch();
+
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 2);
+        assert_eq!(pointer.current_cursor(), "text");
+        assert_eq!(
+            *pointer.inner.displayed.borrow(),
+            Some(("text".to_owned(), ScaleFactor::new(2).unwrap()))
+        );
+    }
+
+    // The ids of the frame callbacks requested on this surface
+    fn frame_callbacks(requests: &[Request], surface: &wl_surface::WlSurface) -> Vec<u32> {
+        requests
+            .iter()
+            .filter(|r| r.object == surface.as_ref().id() && r.opcode == SURFACE_FRAME)
+            .map(|r| r.args[0])
+            .collect()
+    }
+
+    #[cfg(feature = "calloop")]
+    fn animated_pointer(
+        name: &str,
+        file: Vec<u8>,
+        scheduling: AnimationScheduling,
+    ) -> (TestTheme, Setup, calloop::EventLoop<()>, ThemedPointer) {
+        let theme = TestTheme::new(name, &["left_ptr"]);
+        theme.add_cursor("wait", file);
+        let mut setup = setup(theme.spec());
+        let event_loop = calloop::EventLoop::<()>::new().unwrap();
+        setup
+            .manager
+            .animate_cursors(&event_loop.handle(), scheduling)
+            .unwrap();
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        setup.client.requests();
+        (theme, setup, event_loop, pointer)
+    }
+
+    #[test]
+    fn animated_cursor_is_not_animated_by_default() {
+        let theme = TestTheme::new("not-animated", &["left_ptr"]);
+        theme.add_cursor("wait", xcursor_animation(24, &[0, 0, 0]));
+        let mut setup = setup(theme.spec());
+        let pointer = setup
+            .manager
+            .theme_pointer(setup.seat.get_pointer().detach());
+        pointer.set_cursor("wait", Some(1)).unwrap();
+
+        let requests = setup.client.requests();
+        let surface = &pointer.inner.surface;
+        assert_eq!(count(&requests, surface.as_ref(), SURFACE_ATTACH), 1);
+        assert_eq!(frame_callbacks(&requests, surface), vec![]);
+        assert_eq!(pointer.inner.frame.get(), 0);
+    }
+
+    #[cfg(feature = "calloop")]
+    #[test]
+    fn frame_callbacks_draw_the_next_frames() {
+        let (_theme, mut setup, _event_loop, pointer) = animated_pointer(
+            "frame-callbacks",
+            xcursor_animation(24, &[0, 0, 0]),
+            AnimationScheduling::FrameCallbacks,
+        );
+        pointer.set_cursor("wait", Some(1)).unwrap();
+        let surface = pointer.inner.surface.clone();
+        let requests = setup.client.requests();
+        assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 1);
+        let mut callbacks = frame_callbacks(&requests, &surface);
+        assert_eq!(callbacks.len(), 1);
+
+        // the frames loop, without the event loop being dispatched
+        for &frame in &[1, 2, 0, 1] {
+            // wl_callback.done
+            setup.client.send_id(callbacks[0], 0, &[0]);
+            setup.client.dispatch();
+            let requests = setup.client.requests();
+            assert_eq!(count(&requests, surface.as_ref(), SURFACE_ATTACH), 1);
+            assert_eq!(count(&requests, surface.as_ref(), SURFACE_COMMIT), 1);
+            // the cursor is not set again for the next frames
+            assert_eq!(count(&requests, pointer.as_ref(), POINTER_SET_CURSOR), 0);
+            assert_eq!(pointer.inner.frame.get(), frame);
+            callbacks = frame_callbacks(&requests, &surface);
+            assert_eq!(callbacks.len(), 1);
+        }
+
+        // another cursor starts from its first frame, and a still cursor is not animated
+        pointer.set_cursor("left_ptr", None).unwrap();
+        let requests = setup.client.requests();
+        assert_eq!(frame_callbacks(&requests, &surface), vec![]);
+        assert_eq!(pointer.inner.frame.get(), 0);
+        // the callback of the previous cursor draws nothing
+        setup.client.send_id(callbacks[0], 0, &[0]);
+        setup.client.dispatch();
+        assert_eq!(setup.client.requests(), vec![]);
+    }
+
+    #[cfg(feature = "calloop")]
+    #[test]
+    fn timer_draws_the_frames_without_frame_callbacks() {
+        let (_theme, mut setup, mut event_loop, pointer) = animated_pointer(
+            "no-frame-callbacks",
+            xcursor_animation(24, &[10, 10, 10]),
+            AnimationScheduling::FrameCallbacks,
+        );
+        pointer.set_cursor("wait", Some(1)).unwrap();
+        let surface = pointer.inner.surface.clone();
+        setup.client.requests();
+
+        // the compositor never sends the frame callbacks, as for a hidden cursor
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut attached = 0;
+        while attached < 4 && Instant::now() < deadline {
+            event_loop
+                .dispatch(Some(Duration::from_millis(50)), &mut ())
+                .unwrap();
+            let requests = setup.client.requests();
+            let frames = count(&requests, surface.as_ref(), SURFACE_ATTACH);
+            // the frame callbacks are still requested, to resume once they arrive
+            assert_eq!(frame_callbacks(&requests, &surface).len(), frames);
+            attached += frames;
+        }
+        assert_eq!(attached, 4);
+        assert_eq!(pointer.inner.frame.get(), 1);
+        assert!(pointer.inner.animation.borrow().fallback);
+    }
+
+    #[cfg(feature = "calloop")]
+    #[test]
+    fn timer_scheduling_requests_no_frame_callbacks() {
+        let (_theme, mut setup, mut event_loop, pointer) = animated_pointer(
+            "timer-scheduling",
+            xcursor_animation(24, &[10, 10, 10]),
+            AnimationScheduling::Timer,
+        );
+        pointer.set_cursor("wait", Some(1)).unwrap();
+        let surface = pointer.inner.surface.clone();
+        let requests = setup.client.requests();
+        assert_eq!(frame_callbacks(&requests, &surface), vec![]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut attached = 0;
+        while attached < 3 && Instant::now() < deadline {
+            event_loop
+                .dispatch(Some(Duration::from_millis(50)), &mut ())
+                .unwrap();
+            let requests = setup.client.requests();
+            assert_eq!(frame_callbacks(&requests, &surface), vec![]);
+            attached += count(&requests, surface.as_ref(), SURFACE_ATTACH);
+        }
+        assert_eq!(attached, 3);
+        assert_eq!(pointer.inner.frame.get(), 0);
+    }
+
+    #[cfg(feature = "calloop")]
+    #[test]
+    fn animation_frames_damage_their_changed_rows() {
+        const SURFACE_DAMAGE_BUFFER: u16 = 9;
+        let black = vec![0xff00_0000; 24 * 24];
+        // rows 4 to 6 of the second frame are white, all the rows of the third one
+        let mut second = black.clone();
+        for pixel in &mut second[4 * 24..7 * 24] {
+            *pixel = 0xffff_ffff;
+        }
+        let third = vec![0xffff_ffff; 24 * 24];
+        let file = xcursor_frames(24, &[(0, black), (0, second), (0, third)]);
+        let (_theme, mut setup, _event_loop, pointer) =
+            animated_pointer("damage", file, AnimationScheduling::FrameCallbacks);
+        pointer.set_cursor("wait", Some(1)).unwrap();
+        let surface = pointer.inner.surface.clone();
+        let damage = |requests: &[Request]| {
+            requests
+                .iter()
+                .filter(|r| r.object == surface.as_ref().id() && r.opcode == SURFACE_DAMAGE_BUFFER)
+                .map(|r| r.args.clone())
+                .collect::<Vec<_>>()
+        };
+        // a new cursor is damaged in full
+        let requests = setup.client.requests();
+        assert_eq!(damage(&requests), vec![vec![0, 0, 24, 24]]);
+        let mut callbacks = frame_callbacks(&requests, &surface);
+
+        // the changed rows, then the whole frames which changed in most of their rows
+        let expected = [
+            vec![vec![0, 4, 24, 3]],
+            vec![vec![0, 0, 24, 24]],
+            vec![vec![0, 0, 24, 24]],
+            vec![vec![0, 4, 24, 3]],
+        ];
+        for expected in &expected {
+            // wl_callback.done
+            setup.client.send_id(callbacks[0], 0, &[0]);
+            setup.client.dispatch();
+            let requests = setup.client.requests();
+            assert_eq!(&damage(&requests), expected);
+            callbacks = frame_callbacks(&requests, &surface);
+        }
     }
 }
@@ -0,0 +1,344 @@
+//! Loading of Xcursor themes from a directory
+//!
+//! wayland-cursor only looks themes up by name in the `XCURSOR_PATH`, so themes
+//! given by path are parsed here. The images of named themes are parsed here as
+//! well when their pixels are needed, wayland-cursor only exposing their buffers.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::io::FromRawFd,
+    path::{Path, PathBuf},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use wayland_client::{
+    protocol::{wl_buffer, wl_shm, wl_shm_pool},
+    Attached, Main,
+};
+
+use crate::shm::create_shm_fd;
+
+const XCURSOR_MAGIC: &[u8] = b"Xcur";
+const XCURSOR_IMAGE_TYPE: u32 = 0xfffd_0002;
+// images larger than this are rejected by libXcursor too
+const XCURSOR_IMAGE_MAX_SIZE: u32 = 0x7fff;
+
+/// A cursor image, with its pixels in ARGB8888
+pub(super) struct Image {
+    nominal_size: u32,
+    pub(super) width: u32,
+    pub(super) height: u32,
+    pub(super) xhot: u32,
+    pub(super) yhot: u32,
+    // the duration of this frame of an animated cursor, in milliseconds
+    pub(super) delay: u32,
+    pub(super) pixels: Vec<u32>,
+}
+
+impl Image {
+    // Resize the image to a new nominal size, using nearest neighbor sampling
+    fn scaled(&self, size: u32) -> Image {
+        let scale = |v: u32| {
+            ((v as u64 * size as u64 + self.nominal_size as u64 / 2) / self.nominal_size as u64)
+                as u32
+        };
+        let width = std::cmp::max(scale(self.width), 1);
+        let height = std::cmp::max(scale(self.height), 1);
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let src_y = (y as u64 * self.height as u64 / height as u64) as u32;
+            for x in 0..width {
+                let src_x = (x as u64 * self.width as u64 / width as u64) as u32;
+                pixels.push(self.pixels[(src_y * self.width + src_x) as usize]);
+            }
+        }
+        Image {
+            nominal_size: size,
+            width,
+            height,
+            xhot: std::cmp::min(scale(self.xhot), width - 1),
+            yhot: std::cmp::min(scale(self.yhot), height - 1),
+            delay: self.delay,
+            pixels,
+        }
+    }
+
+    // The span of rows differing from the previous image, as its first row and its number of
+    // rows, `None` if the images do not have the same size
+    pub(super) fn changed_rows(&self, previous: &Image) -> Option<(u32, u32)> {
+        if (self.width, self.height) != (previous.width, previous.height) {
+            return None;
+        }
+        if self.width == 0 {
+            return Some((0, 0));
+        }
+        let width = self.width as usize;
+        let changed = self
+            .pixels
+            .chunks(width)
+            .zip(previous.pixels.chunks(width))
+            .map(|(row, previous_row)| row != previous_row)
+            .collect::<Vec<_>>();
+        match changed.iter().position(|&c| c) {
+            Some(first) => {
+                let last = changed.iter().rposition(|&c| c).unwrap_or(first);
+                Some((first as u32, (last - first + 1) as u32))
+            }
+            None => Some((0, 0)),
+        }
+    }
+}
+
+// Parse the images of an Xcursor file, `None` if it is malformed
+fn parse_images(data: &[u8]) -> Option<Vec<Image>> {
+    let read_u32 = |pos: usize| data.get(pos..pos + 4).map(LittleEndian::read_u32);
+    if data.get(0..4)? != XCURSOR_MAGIC {
+        return None;
+    }
+    let header_size = read_u32(4)? as usize;
+    let toc_count = read_u32(12)? as usize;
+    let mut images = Vec::new();
+    for i in 0..toc_count {
+        let entry = header_size + i * 12;
+        if read_u32(entry)? != XCURSOR_IMAGE_TYPE {
+            continue;
+        }
+        let pos = read_u32(entry + 8)? as usize;
+        // chunk header: header size, type, nominal size, version,
+        // then width, height, xhot, yhot and delay
+        let chunk_size = read_u32(pos)? as usize;
+        let width = read_u32(pos + 16)?;
+        let height = read_u32(pos + 20)?;
+        if width == 0
+            || height == 0
+            || width > XCURSOR_IMAGE_MAX_SIZE
+            || height > XCURSOR_IMAGE_MAX_SIZE
+        {
+            return None;
+        }
+        let start = pos + chunk_size;
+        let len = (width * height) as usize * 4;
+        images.push(Image {
+            nominal_size: read_u32(pos + 8)?,
+            width,
+            height,
+            xhot: std::cmp::min(read_u32(pos + 24)?, width - 1),
+            yhot: std::cmp::min(read_u32(pos + 28)?, height - 1),
+            delay: read_u32(pos + 32)?,
+            pixels: data
+                .get(start..start + len)?
+                .chunks(4)
+                .map(LittleEndian::read_u32)
+                .collect(),
+        });
+    }
+    Some(images)
+}
+
+// Read the themes listed in the `Inherits` key of an `index.theme` file
+fn parse_inherits(data: &str) -> Vec<String> {
+    let mut in_section = false;
+    for line in data.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_section = line == "[Icon Theme]";
+        } else if in_section {
+            let mut parts = line.splitn(2, '=');
+            if parts.next().map(str::trim) == Some("Inherits") {
+                return parts
+                    .next()
+                    .unwrap_or("")
+                    .split(&[',', ';'][..])
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(Into::into)
+                    .collect();
+            }
+        }
+    }
+    Vec::new()
+}
+
+/// An Xcursor theme stored in a directory
+#[derive(Clone)]
+pub(super) struct XcursorTheme {
+    // the directory of the theme followed by those of the themes it inherits from
+    dirs: Vec<PathBuf>,
+}
+
+impl XcursorTheme {
+    /// Open the theme in this directory
+    ///
+    /// The themes it inherits from are looked up next to it.
+    pub(super) fn open(path: &Path) -> XcursorTheme {
+        let mut dirs = vec![path.to_path_buf()];
+        let mut i = 0;
+        while i < dirs.len() {
+            let inherits = fs::read_to_string(dirs[i].join("index.theme"))
+                .map(|data| parse_inherits(&data))
+                .unwrap_or_default();
+            for name in inherits {
+                let dir = match dirs[i].parent() {
+                    Some(parent) => parent.join(name),
+                    None => continue,
+                };
+                // themes may inherit from each other, only visit each once
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+            i += 1;
+        }
+        XcursorTheme { dirs }
+    }
+
+    /// Find the theme with this name in the Xcursor search path
+    ///
+    /// The search path is read from `XCURSOR_PATH`, and defaults to the one of libXcursor.
+    pub(super) fn find(name: &str) -> Option<XcursorTheme> {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let search_path = match std::env::var("XCURSOR_PATH") {
+            Ok(path) => path,
+            Err(_) => {
+                let data_home =
+                    std::env::var("XDG_DATA_HOME").unwrap_or_else(|_| "~/.local/share".into());
+                format!(
+                    "{}/icons:~/.icons:/usr/share/icons:/usr/share/pixmaps",
+                    data_home
+                )
+            }
+        };
+        search_path
+            .split(':')
+            .filter_map(|dir| {
+                if dir.starts_with("~/") {
+                    home.as_ref().map(|home| home.join(&dir[2..]))
+                } else {
+                    Some(PathBuf::from(dir))
+                }
+            })
+            .map(|dir| dir.join(name))
+            .find(|dir| dir.join("cursors").is_dir() || dir.join("index.theme").is_file())
+            .map(|dir| XcursorTheme::open(&dir))
+    }
+
+    /// The directory of this theme
+    pub(super) fn path(&self) -> &Path {
+        &self.dirs[0]
+    }
+
+    /// Load all the frames of a cursor, at the size closest to `size`
+    ///
+    /// If the cursor does not have this size, the nearest one is scaled.
+    pub(super) fn load_images(&self, name: &str, size: u32) -> Option<Vec<Image>> {
+        let images = self.read_images(name)?;
+        let nearest = nearest_size(images.iter().map(|image| image.nominal_size), size)?;
+        let frames = images
+            .into_iter()
+            .filter(|image| image.nominal_size == nearest)
+            .map(|image| {
+                if nearest == size {
+                    image
+                } else {
+                    image.scaled(size)
+                }
+            })
+            .collect();
+        Some(frames)
+    }
+
+    /// The nominal sizes of the images of a cursor, in increasing order
+    pub(super) fn sizes(&self, name: &str) -> Vec<u32> {
+        let mut sizes = self
+            .read_images(name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|image| image.nominal_size)
+            .filter(|&s| s > 0)
+            .collect::<Vec<_>>();
+        sizes.sort();
+        sizes.dedup();
+        sizes
+    }
+
+    // Read all the images of a cursor, from the first directory which has it
+    fn read_images(&self, name: &str) -> Option<Vec<Image>> {
+        self.dirs.iter().find_map(|dir| {
+            let data = fs::read(dir.join("cursors").join(name)).ok()?;
+            parse_images(&data).filter(|images| !images.is_empty())
+        })
+    }
+}
+
+/// The size closest to `size` among these nominal sizes
+///
+/// The larger size is preferred on ties, as downscaling looks better.
+pub(super) fn nearest_size<I>(sizes: I, size: u32) -> Option<u32>
+where
+    I: IntoIterator<Item = u32>,
+{
+    sizes
+        .into_iter()
+        .filter(|&s| s > 0)
+        .min_by_key(|&s| ((s as i64 - size as i64).abs(), std::cmp::Reverse(s)))
+}
+
+/// A cursor image uploaded to a shm buffer
+pub(super) struct CursorBuffer {
+    pool: Main<wl_shm_pool::WlShmPool>,
+    buffer: Main<wl_buffer::WlBuffer>,
+    dimensions: (u32, u32),
+    hotspot: (u32, u32),
+    delay: u32,
+}
+
+impl CursorBuffer {
+    pub(super) fn new(image: &Image, shm: &Attached<wl_shm::WlShm>) -> io::Result<CursorBuffer> {
+        let fd = create_shm_fd()?;
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        let mut data = vec![0; image.pixels.len() * 4];
+        LittleEndian::write_u32_into(&image.pixels, &mut data);
+        file.write_all(&data)?;
+        file.flush()?;
+        let pool = shm.create_pool(fd, data.len() as i32);
+        let buffer = pool.create_buffer(
+            0,
+            image.width as i32,
+            image.height as i32,
+            image.width as i32 * 4,
+            wl_shm::Format::Argb8888,
+        );
+        // the buffer is reused for each attach, its release events are irrelevant
+        buffer.quick_assign(|_, _, _| {});
+        Ok(CursorBuffer {
+            pool,
+            buffer,
+            dimensions: (image.width, image.height),
+            hotspot: (image.xhot, image.yhot),
+            delay: image.delay,
+        })
+    }
+
+    pub(super) fn buffer(&self) -> &wl_buffer::WlBuffer {
+        &self.buffer
+    }
+
+    pub(super) fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    pub(super) fn hotspot(&self) -> (u32, u32) {
+        self.hotspot
+    }
+
+    pub(super) fn delay(&self) -> u32 {
+        self.delay
+    }
+}
+
+impl Drop for CursorBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+    }
+}
@@ -0,0 +1,643 @@
+//! Recording of the input events received by the seat helpers
+//!
+//! When a user reports input issues specific to a compositor, the exact events received
+//! from it are needed to understand them. Once a [`Recorder`](struct.Recorder.html) is
+//! started with [`start_recording`](fn.start_recording.html), the events of the seats, and
+//! of the pointers, keyboards and touches set up by the helpers of SCTK, are written to it,
+//! one per line, before being processed. The recording only covers the current thread, on
+//! which the Wayland events are dispatched.
+//!
+//! A line holds the milliseconds elapsed since the start of the recording, the interface
+//! and id of the object, the name of the event and its arguments:
+//!
+//! ```text
+//! 1520 wl_pointer@14 enter serial=2803 surface=21 surface_x=74.5 surface_y=12
+//! 1520 wl_pointer@14 frame
+//! 1631 wl_pointer@14 button serial=2810 time=982117 button=272 state=1
+//! ```
+//!
+//! The objects are given by their id, and the enums by their raw value. The text arguments
+//! are quoted. A trace is read back with [`parse_trace`](fn.parse_trace.html), for example
+//! to turn the events of a bug report into a test of your input handling.
+//!
+//! **Note:** This requires the `recording` cargo feature. When no recording is running,
+//! the helpers only check an atomic flag.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use wayland_client::{
+    protocol::{wl_keyboard, wl_pointer, wl_seat, wl_surface, wl_touch},
+    Interface, Proxy,
+};
+
+// the number of threads running a recording, to skip the thread local when none is
+static RECORDING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static RECORDER: RefCell<Option<Recorder>> = RefCell::new(None);
+}
+
+enum Sink {
+    Ring {
+        lines: VecDeque<String>,
+        capacity: usize,
+    },
+    Writer(Box<dyn Write>),
+}
+
+/// A destination for the recorded events
+///
+/// It either keeps the latest lines in memory, or writes them to a `Write` sink.
+pub struct Recorder {
+    sink: Sink,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Create a recorder keeping the latest `capacity` lines in memory
+    ///
+    /// This is cheap enough to stay enabled, so that the events preceding an issue are
+    /// available when it is noticed.
+    pub fn ring(capacity: usize) -> Recorder {
+        Recorder {
+            sink: Sink::Ring {
+                lines: VecDeque::with_capacity(capacity),
+                capacity,
+            },
+            start: Instant::now(),
+        }
+    }
+
+    /// Create a recorder writing the lines to a sink, like a file
+    ///
+    /// The recording stops if writing to the sink fails.
+    pub fn writer<W: Write + 'static>(writer: W) -> Recorder {
+        Recorder {
+            sink: Sink::Writer(Box::new(writer)),
+            start: Instant::now(),
+        }
+    }
+
+    /// The lines kept in memory, from the oldest to the newest
+    ///
+    /// This is empty for a recorder writing to a sink.
+    pub fn lines(&self) -> Vec<String> {
+        match self.sink {
+            Sink::Ring { ref lines, .. } => lines.iter().cloned().collect(),
+            Sink::Writer(_) => Vec::new(),
+        }
+    }
+
+    // Returns false if the line could not be written
+    fn push(&mut self, line: String) -> bool {
+        match self.sink {
+            Sink::Ring {
+                ref mut lines,
+                capacity,
+            } => {
+                if capacity == 0 {
+                    return true;
+                }
+                if lines.len() == capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+                true
+            }
+            Sink::Writer(ref mut writer) => match writeln!(writer, "{}", line) {
+                Ok(()) => true,
+                Err(err) => {
+                    log::error!("[SCTK] Stopping the recording of the events: {}", err);
+                    false
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sink = match self.sink {
+            Sink::Ring { .. } => "ring",
+            Sink::Writer(_) => "writer",
+        };
+        f.debug_struct("Recorder").field("sink", &sink).finish()
+    }
+}
+
+/// Start recording the events received on the current thread
+///
+/// This replaces the recording running on this thread, if any.
+pub fn start_recording(recorder: Recorder) {
+    let previous = RECORDER.with(|current| current.borrow_mut().replace(recorder));
+    if previous.is_none() {
+        RECORDING.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Stop recording the events received on the current thread
+///
+/// Returns the recorder, to access the lines it kept, or `None` if no recording was running
+/// on this thread. The writers are flushed.
+pub fn stop_recording() -> Option<Recorder> {
+    let mut recorder = RECORDER.with(|current| current.borrow_mut().take());
+    if recorder.is_some() {
+        RECORDING.fetch_sub(1, Ordering::Relaxed);
+    }
+    if let Some(Recorder {
+        sink: Sink::Writer(ref mut writer),
+        ..
+    }) = recorder
+    {
+        if let Err(err) = writer.flush() {
+            log::error!("[SCTK] Failed to flush the recorded events: {}", err);
+        }
+    }
+    recorder
+}
+
+/// Whether a recording is running on the current thread
+pub fn is_recording() -> bool {
+    RECORDING.load(Ordering::Relaxed) > 0 && RECORDER.with(|current| current.borrow().is_some())
+}
+
+/// The lines kept in memory by the recording running on the current thread
+///
+/// This is empty if no recording is running, or if it writes to a sink.
+pub fn recorded_lines() -> Vec<String> {
+    RECORDER.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(Recorder::lines)
+            .unwrap_or_default()
+    })
+}
+
+// The line of an event, built argument by argument
+struct Line(String);
+
+impl Line {
+    fn new<I>(object: &Proxy<I>, event: &str) -> Line
+    where
+        I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+    {
+        Line(format!("{}@{} {}", I::NAME, object.id(), event))
+    }
+
+    fn arg<T: fmt::Display>(mut self, name: &str, value: T) -> Line {
+        let _ = write!(self.0, " {}={}", name, value);
+        self
+    }
+
+    fn surface(self, name: &str, surface: &wl_surface::WlSurface) -> Line {
+        self.arg(name, surface.as_ref().id())
+    }
+
+    fn text(self, name: &str, text: &str) -> Line {
+        let mut quoted = String::with_capacity(text.len() + 2);
+        quoted.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        self.arg(name, quoted)
+    }
+}
+
+fn record<F: FnOnce() -> Option<Line>>(line: F) {
+    if RECORDING.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    RECORDER.with(|current| {
+        let mut current = current.borrow_mut();
+        let ok = match *current {
+            Some(ref mut recorder) => match line() {
+                Some(Line(line)) => {
+                    let elapsed = recorder.start.elapsed().as_millis();
+                    recorder.push(format!("{} {}", elapsed, line))
+                }
+                None => true,
+            },
+            None => true,
+        };
+        if !ok {
+            *current = None;
+            RECORDING.fetch_sub(1, Ordering::Relaxed);
+        }
+    });
+}
+
+pub(crate) fn record_seat(seat: &wl_seat::WlSeat, event: &wl_seat::Event) {
+    record(|| {
+        let seat = seat.as_ref();
+        Some(match *event {
+            wl_seat::Event::Capabilities { capabilities } => {
+                Line::new(seat, "capabilities").arg("capabilities", capabilities.to_raw())
+            }
+            wl_seat::Event::Name { ref name } => Line::new(seat, "name").text("name", name),
+            _ => return None,
+        })
+    })
+}
+
+pub(crate) fn record_pointer(pointer: &wl_pointer::WlPointer, event: &wl_pointer::Event) {
+    use wl_pointer::Event;
+    record(|| {
+        let pointer = pointer.as_ref();
+        Some(match *event {
+            Event::Enter {
+                serial,
+                ref surface,
+                surface_x,
+                surface_y,
+            } => Line::new(pointer, "enter")
+                .arg("serial", serial)
+                .surface("surface", surface)
+                .arg("surface_x", surface_x)
+                .arg("surface_y", surface_y),
+            Event::Leave {
+                serial,
+                ref surface,
+            } => Line::new(pointer, "leave")
+                .arg("serial", serial)
+                .surface("surface", surface),
+            Event::Motion {
+                time,
+                surface_x,
+                surface_y,
+            } => Line::new(pointer, "motion")
+                .arg("time", time)
+                .arg("surface_x", surface_x)
+                .arg("surface_y", surface_y),
+            Event::Button {
+                serial,
+                time,
+                button,
+                state,
+            } => Line::new(pointer, "button")
+                .arg("serial", serial)
+                .arg("time", time)
+                .arg("button", button)
+                .arg("state", state.to_raw()),
+            Event::Axis { time, axis, value } => Line::new(pointer, "axis")
+                .arg("time", time)
+                .arg("axis", axis.to_raw())
+                .arg("value", value),
+            Event::Frame => Line::new(pointer, "frame"),
+            Event::AxisSource { axis_source } => {
+                Line::new(pointer, "axis_source").arg("axis_source", axis_source.to_raw())
+            }
+            Event::AxisStop { time, axis } => Line::new(pointer, "axis_stop")
+                .arg("time", time)
+                .arg("axis", axis.to_raw()),
+            Event::AxisDiscrete { axis, discrete } => Line::new(pointer, "axis_discrete")
+                .arg("axis", axis.to_raw())
+                .arg("discrete", discrete),
+            _ => return None,
+        })
+    })
+}
+
+pub(crate) fn record_keyboard(keyboard: &wl_keyboard::WlKeyboard, event: &wl_keyboard::Event) {
+    use wl_keyboard::Event;
+    record(|| {
+        let keyboard = keyboard.as_ref();
+        Some(match *event {
+            // the keymap itself is not recorded, only its size
+            Event::Keymap { format, size, .. } => Line::new(keyboard, "keymap")
+                .arg("format", format.to_raw())
+                .arg("size", size),
+            Event::Enter {
+                serial,
+                ref surface,
+                ref keys,
+            } => {
+                // the keys are an array of native endian u32
+                let keys = keys
+                    .chunks_exact(4)
+                    .map(|k| u32::from_ne_bytes([k[0], k[1], k[2], k[3]]).to_string())
+                    .collect::<Vec<_>>();
+                Line::new(keyboard, "enter")
+                    .arg("serial", serial)
+                    .surface("surface", surface)
+                    .arg("keys", keys.join(","))
+            }
+            Event::Leave {
+                serial,
+                ref surface,
+            } => Line::new(keyboard, "leave")
+                .arg("serial", serial)
+                .surface("surface", surface),
+            Event::Key {
+                serial,
+                time,
+                key,
+                state,
+            } => Line::new(keyboard, "key")
+                .arg("serial", serial)
+                .arg("time", time)
+                .arg("key", key)
+                .arg("state", state.to_raw()),
+            Event::Modifiers {
+                serial,
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+            } => Line::new(keyboard, "modifiers")
+                .arg("serial", serial)
+                .arg("mods_depressed", mods_depressed)
+                .arg("mods_latched", mods_latched)
+                .arg("mods_locked", mods_locked)
+                .arg("group", group),
+            Event::RepeatInfo { rate, delay } => Line::new(keyboard, "repeat_info")
+                .arg("rate", rate)
+                .arg("delay", delay),
+            _ => return None,
+        })
+    })
+}
+
+pub(crate) fn record_touch(touch: &wl_touch::WlTouch, event: &wl_touch::Event) {
+    use wl_touch::Event;
+    record(|| {
+        let touch = touch.as_ref();
+        Some(match *event {
+            Event::Down {
+                serial,
+                time,
+                ref surface,
+                id,
+                x,
+                y,
+            } => Line::new(touch, "down")
+                .arg("serial", serial)
+                .arg("time", time)
+                .surface("surface", surface)
+                .arg("id", id)
+                .arg("x", x)
+                .arg("y", y),
+            Event::Up { serial, time, id } => Line::new(touch, "up")
+                .arg("serial", serial)
+                .arg("time", time)
+                .arg("id", id),
+            Event::Motion { time, id, x, y } => Line::new(touch, "motion")
+                .arg("time", time)
+                .arg("id", id)
+                .arg("x", x)
+                .arg("y", y),
+            Event::Frame => Line::new(touch, "frame"),
+            Event::Cancel => Line::new(touch, "cancel"),
+            Event::Shape { id, major, minor } => Line::new(touch, "shape")
+                .arg("id", id)
+                .arg("major", major)
+                .arg("minor", minor),
+            Event::Orientation { id, orientation } => Line::new(touch, "orientation")
+                .arg("id", id)
+                .arg("orientation", orientation),
+            _ => return None,
+        })
+    })
+}
+
+/// An event read from a trace
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// The milliseconds elapsed between the start of the recording and the event
+    pub elapsed: u64,
+    /// The interface of the object receiving the event, like `wl_pointer`
+    pub interface: String,
+    /// The id of the object receiving the event
+    pub object: u32,
+    /// The name of the event, like `enter`
+    pub event: String,
+    /// The arguments of the event, as their names and values, the text being unquoted
+    pub args: Vec<(String, String)>,
+}
+
+impl Record {
+    /// The value of an argument
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// The value of a numeric argument
+    ///
+    /// Returns `None` if the event has no such argument, or if its value is not a number of
+    /// this type.
+    pub fn parse_arg<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.arg(name).and_then(|value| value.parse().ok())
+    }
+}
+
+/// An error while reading a trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The number of the invalid line, starting at 1
+    pub line: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid event at line {} of the trace", self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Read the events of a trace
+///
+/// The empty lines are skipped. Replay the returned records through your own input
+/// handling to reproduce an issue: the events cannot be fed back to the helpers of SCTK,
+/// which need live Wayland objects.
+pub fn parse_trace(trace: &str) -> Result<Vec<Record>, ParseError> {
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| parse_line(line).ok_or(ParseError { line: i + 1 }))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Record> {
+    let mut rest = line.trim();
+    let elapsed = next_word(&mut rest)?.parse().ok()?;
+    let mut object = next_word(&mut rest)?.splitn(2, '@');
+    let interface = object.next()?.to_owned();
+    let object = object.next()?.parse().ok()?;
+    let event = next_word(&mut rest)?.to_owned();
+    let mut args = Vec::new();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let name = rest[..eq].to_owned();
+        rest = &rest[eq + 1..];
+        let value = if rest.starts_with('"') {
+            parse_text(&mut rest)?
+        } else {
+            next_word(&mut rest)?.to_owned()
+        };
+        args.push((name, value));
+        rest = rest.trim_start();
+    }
+    Some(Record {
+        elapsed,
+        interface,
+        object,
+        event,
+        args,
+    })
+}
+
+fn next_word<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let line: &'a str = rest;
+    let end = line.find(' ').unwrap_or(line.len());
+    let word = &line[..end];
+    *rest = line[end..].trim_start();
+    if word.is_empty() {
+        None
+    } else {
+        Some(word)
+    }
+}
+
+// Read a quoted text, undoing the escapes of `Line::text`
+fn parse_text<'a>(rest: &mut &'a str) -> Option<String> {
+    let line: &'a str = rest;
+    let mut text = String::new();
+    let mut chars = line.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                *rest = &line[i + 1..];
+                return Some(text);
+            }
+            '\\' => match chars.next()?.1 {
+                'n' => text.push('\n'),
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoted(text: &str) -> String {
+        Line("12 wl_keyboard@3 key".into()).text("utf8", text).0
+    }
+
+    #[test]
+    fn parse_event() {
+        let records = parse_trace("12 wl_pointer@7 motion time=34 x=1.5 y=-2\n").unwrap();
+        assert_eq!(
+            records,
+            vec![Record {
+                elapsed: 12,
+                interface: "wl_pointer".into(),
+                object: 7,
+                event: "motion".into(),
+                args: vec![
+                    ("time".into(), "34".into()),
+                    ("x".into(), "1.5".into()),
+                    ("y".into(), "-2".into()),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_event_without_args() {
+        let records = parse_trace("0 wl_pointer@7 frame").unwrap();
+        assert_eq!(records[0].event, "frame");
+        assert!(records[0].args.is_empty());
+    }
+
+    #[test]
+    fn empty_trace() {
+        assert_eq!(parse_trace(""), Ok(vec![]));
+        assert_eq!(parse_trace("\n  \n\t\n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn text_round_trip() {
+        for &text in &["", "a", "\"", "\\", "\n", "a \"b\" \\n c=d\n", "é ⌘"] {
+            let records = parse_trace(&quoted(text)).unwrap();
+            assert_eq!(records[0].arg("utf8"), Some(text));
+        }
+    }
+
+    #[test]
+    fn text_followed_by_args() {
+        let line = format!("{} serial=5", quoted("a b=\"c\""));
+        let records = parse_trace(&line).unwrap();
+        assert_eq!(records[0].arg("utf8"), Some("a b=\"c\""));
+        assert_eq!(records[0].parse_arg::<u32>("serial"), Some(5));
+    }
+
+    #[test]
+    fn line_numbers() {
+        let trace = "0 wl_pointer@7 frame\n\n1 wl_pointer@7 frame\nbogus\n";
+        assert_eq!(parse_trace(trace), Err(ParseError { line: 4 }));
+        assert_eq!(parse_trace("bogus"), Err(ParseError { line: 1 }));
+        assert_eq!(
+            ParseError { line: 4 }.to_string(),
+            "invalid event at line 4 of the trace"
+        );
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for &line in &[
+            // missing parts
+            "12",
+            "12 wl_pointer@7",
+            // bad timestamps
+            "-1 wl_pointer@7 frame",
+            "x wl_pointer@7 frame",
+            // bad objects
+            "12 wl_pointer frame",
+            "12 wl_pointer@ frame",
+            "12 wl_pointer@x frame",
+            // bad arguments
+            "12 wl_pointer@7 motion time",
+            "12 wl_pointer@7 motion time=",
+            "12 wl_keyboard@3 key utf8=\"abc",
+            "12 wl_keyboard@3 key utf8=\"abc\\",
+        ] {
+            assert_eq!(parse_trace(line), Err(ParseError { line: 1 }), "{}", line);
+        }
+    }
+
+    #[test]
+    fn args() {
+        let records = parse_trace("0 wl_keyboard@3 key serial=5 key=-1 state=1 key=2").unwrap();
+        let record = &records[0];
+        assert_eq!(record.arg("serial"), Some("5"));
+        assert_eq!(record.arg("time"), None);
+        // the first occurrence wins
+        assert_eq!(record.arg("key"), Some("-1"));
+        assert_eq!(record.parse_arg::<i32>("key"), Some(-1));
+        assert_eq!(record.parse_arg::<u32>("key"), None);
+        assert_eq!(record.parse_arg::<u32>("time"), None);
+        assert_eq!(record.parse_arg::<f64>("state"), Some(1.0));
+    }
+}
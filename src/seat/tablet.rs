@@ -0,0 +1,633 @@
+//! Utilities to handle the pads of drawing tablets with `zwp_tablet_manager_v2`
+//!
+//! Besides their pen, drawing tablets often have a pad: a set of buttons, rings and strips
+//! that artists map to actions of your application, like undo or changing the size of the
+//! brush. The buttons, rings and strips of a pad are organized in groups, each of which can
+//! have several modes: the same button then triggers a different action in each mode, and the
+//! compositor shows the current mode on the LEDs of the tablet.
+//!
+//! [`Environment::listen_for_tablet_pads`](../../environment/struct.Environment.html#method.listen_for_tablet_pads)
+//! gives you the events of the pads of a seat. Compositors can show what each button, ring and
+//! strip does in their on-screen displays, you can describe them for each mode with a
+//! [`PadFeedback`](struct.PadFeedback.html), which is sent again whenever the mode changes.
+//!
+//! Pads exist independently of the tools of the tablet, and are announced even if no tool was
+//! ever used on it. The tablets and tools of the seat are not handled here, you need to create
+//! your own `zwp_tablet_seat_v2` for them.
+//!
+//! The `zwp_tablet_manager_v2` global is not part of the
+//! [`default_environment!`](../../macro.default_environment.html), you need to add it to your
+//! environment, for example as a [`SimpleGlobal`](../../environment/struct.SimpleGlobal.html).
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use byteorder::{ByteOrder, NativeEndian};
+use wayland_client::{
+    protocol::{wl_seat, wl_surface},
+    DispatchData,
+};
+
+use wayland_protocols::unstable::tablet::v2::client::{
+    zwp_tablet_manager_v2::ZwpTabletManagerV2, zwp_tablet_pad_group_v2, zwp_tablet_pad_ring_v2,
+    zwp_tablet_pad_strip_v2, zwp_tablet_pad_v2, zwp_tablet_seat_v2, zwp_tablet_tool_v2,
+    zwp_tablet_v2,
+};
+
+pub use wayland_protocols::unstable::tablet::v2::client::{
+    zwp_tablet_pad_ring_v2::Source as RingSource, zwp_tablet_pad_strip_v2::Source as StripSource,
+    zwp_tablet_pad_v2::ButtonState,
+};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler};
+
+/// The changes of a ring since its last frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RingFrame {
+    /// Time of the frame, in milliseconds
+    pub time: u32,
+    /// What is interacting with the ring, if known
+    pub source: Option<RingSource>,
+    /// The new angle of the ring, in degrees clockwise from the logical north of the ring
+    pub angle: Option<f64>,
+    /// Whether the interaction stopped, for example because the finger was lifted
+    ///
+    /// This lets you start kinetic scrolling. The next interaction is not guaranteed to
+    /// start where this one stopped.
+    pub stop: bool,
+}
+
+/// The changes of a strip since its last frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StripFrame {
+    /// Time of the frame, in milliseconds
+    pub time: u32,
+    /// What is interacting with the strip, if known
+    pub source: Option<StripSource>,
+    /// The new position on the strip, normalized between 0 and 65535
+    pub position: Option<u32>,
+    /// Whether the interaction stopped, for example because the finger was lifted
+    pub stop: bool,
+}
+
+/// Possible events of a pad
+#[derive(Clone)]
+pub enum PadEvent {
+    /// A pad was added to the seat
+    ///
+    /// Its buttons, groups, rings and strips can be queried from the
+    /// [`TabletPad`](struct.TabletPad.html) given with this event.
+    Added,
+    /// The pad now sends its events to a surface of yours
+    Enter {
+        /// serial number of the event
+        serial: u32,
+        /// the tablet the pad belongs to
+        tablet: zwp_tablet_v2::ZwpTabletV2,
+        /// the focused surface
+        surface: wl_surface::WlSurface,
+    },
+    /// The pad no longer sends its events to this surface
+    Leave {
+        /// serial number of the event
+        serial: u32,
+        /// the surface that was left
+        surface: wl_surface::WlSurface,
+    },
+    /// A button was pressed or released
+    Button {
+        /// time of the event, in milliseconds
+        time: u32,
+        /// index of the button
+        button: u32,
+        /// new state of the button
+        state: ButtonState,
+    },
+    /// A ring was used
+    Ring {
+        /// index of the group of the ring
+        group: usize,
+        /// index of the ring in its group
+        ring: usize,
+        /// what changed
+        frame: RingFrame,
+    },
+    /// A strip was used
+    Strip {
+        /// index of the group of the strip
+        group: usize,
+        /// index of the strip in its group
+        strip: usize,
+        /// what changed
+        frame: StripFrame,
+    },
+    /// The mode of a group changed
+    ///
+    /// The buttons, rings and strips of the group should now trigger the actions of this mode.
+    /// The descriptions of the [`PadFeedback`](struct.PadFeedback.html) of the pad for this mode
+    /// were already sent to the compositor.
+    ModeSwitch {
+        /// index of the group
+        group: usize,
+        /// time of the event, in milliseconds
+        time: u32,
+        /// the new mode, between 0 and the number of modes of the group
+        mode: u32,
+    },
+    /// The pad was removed
+    ///
+    /// It is destroyed once this event is processed.
+    Removed,
+}
+
+/// The description of a group of buttons, rings and strips of a pad
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PadGroupInfo {
+    /// The indices of the buttons of the pad in this group
+    pub buttons: Vec<u32>,
+    /// The number of rings in this group
+    pub rings: usize,
+    /// The number of strips in this group
+    pub strips: usize,
+    /// The number of modes of the group, 0 or 1 if it has a single mode
+    pub modes: u32,
+    /// The current mode of the group
+    pub mode: u32,
+}
+
+/// The descriptions of the actions of the buttons, rings and strips of a pad, for each mode
+///
+/// Compositors may show them in their on-screen displays, for example when a button is
+/// pressed. Set them on a pad with [`TabletPad::set_feedback`](struct.TabletPad.html#method.set_feedback):
+/// they are sent for the current modes of its groups, and sent again whenever a mode changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PadFeedback {
+    buttons: HashMap<(u32, u32), String>,
+    rings: HashMap<(usize, usize, u32), String>,
+    strips: HashMap<(usize, usize, u32), String>,
+}
+
+impl PadFeedback {
+    /// Create empty descriptions
+    pub fn new() -> PadFeedback {
+        PadFeedback::default()
+    }
+
+    /// Describe the action of a button, when the mode of its group is `mode`
+    pub fn set_button(&mut self, mode: u32, button: u32, description: String) {
+        self.buttons.insert((mode, button), description);
+    }
+
+    /// Describe the action of a ring of a group, when the mode of the group is `mode`
+    pub fn set_ring(&mut self, group: usize, ring: usize, mode: u32, description: String) {
+        self.rings.insert((group, ring, mode), description);
+    }
+
+    /// Describe the action of a strip of a group, when the mode of the group is `mode`
+    pub fn set_strip(&mut self, group: usize, strip: usize, mode: u32, description: String) {
+        self.strips.insert((group, strip, mode), description);
+    }
+}
+
+struct Group {
+    group: zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2,
+    buttons: Vec<u32>,
+    rings: Vec<zwp_tablet_pad_ring_v2::ZwpTabletPadRingV2>,
+    strips: Vec<zwp_tablet_pad_strip_v2::ZwpTabletPadStripV2>,
+    modes: u32,
+    mode: u32,
+    // the serial of the last mode switch, needed to set the feedback
+    serial: Option<u32>,
+}
+
+struct Pad {
+    pad: zwp_tablet_pad_v2::ZwpTabletPadV2,
+    paths: Vec<String>,
+    buttons: u32,
+    groups: Vec<Group>,
+    focus: Option<wl_surface::WlSurface>,
+    feedback: PadFeedback,
+}
+
+impl Pad {
+    // send the descriptions of the current mode of a group
+    fn send_feedback(&self, index: usize) {
+        let group = &self.groups[index];
+        let serial = match group.serial {
+            Some(serial) => serial,
+            None => return,
+        };
+        for &button in &group.buttons {
+            if let Some(description) = self.feedback.buttons.get(&(group.mode, button)) {
+                self.pad.set_feedback(button, description.clone(), serial);
+            }
+        }
+        for (i, ring) in group.rings.iter().enumerate() {
+            if let Some(description) = self.feedback.rings.get(&(index, i, group.mode)) {
+                ring.set_feedback(description.clone(), serial);
+            }
+        }
+        for (i, strip) in group.strips.iter().enumerate() {
+            if let Some(description) = self.feedback.strips.get(&(index, i, group.mode)) {
+                strip.set_feedback(description.clone(), serial);
+            }
+        }
+    }
+
+    fn destroy(&mut self) {
+        for group in self.groups.drain(..) {
+            for ring in group.rings {
+                ring.destroy();
+            }
+            for strip in group.strips {
+                strip.destroy();
+            }
+            group.group.destroy();
+        }
+        if self.pad.as_ref().is_alive() {
+            self.pad.destroy();
+        }
+    }
+}
+
+type PadCallback = dyn FnMut(TabletPad, PadEvent, DispatchData);
+
+/// A pad of a drawing tablet
+///
+/// This is a handle to the pad, given with each of its events, which you can keep to query
+/// its description or set the descriptions of its actions.
+#[derive(Clone)]
+pub struct TabletPad {
+    inner: Rc<RefCell<Pad>>,
+}
+
+impl TabletPad {
+    /// Access the underlying `zwp_tablet_pad_v2`
+    pub fn pad(&self) -> zwp_tablet_pad_v2::ZwpTabletPadV2 {
+        self.inner.borrow().pad.clone()
+    }
+
+    /// The device paths of the pad, for example `/dev/input/event12`
+    pub fn paths(&self) -> Vec<String> {
+        self.inner.borrow().paths.clone()
+    }
+
+    /// The number of buttons of the pad
+    pub fn buttons(&self) -> u32 {
+        self.inner.borrow().buttons
+    }
+
+    /// The groups of buttons, rings and strips of the pad
+    pub fn groups(&self) -> Vec<PadGroupInfo> {
+        self.inner
+            .borrow()
+            .groups
+            .iter()
+            .map(|group| PadGroupInfo {
+                buttons: group.buttons.clone(),
+                rings: group.rings.len(),
+                strips: group.strips.len(),
+                modes: group.modes,
+                mode: group.mode,
+            })
+            .collect()
+    }
+
+    /// The surface the pad currently sends its events to, if any
+    pub fn focus(&self) -> Option<wl_surface::WlSurface> {
+        self.inner.borrow().focus.clone()
+    }
+
+    /// Set the descriptions of the actions of the pad
+    ///
+    /// They are sent for the current mode of each group, and then whenever a mode changes. The
+    /// descriptions of a group are only sent once the compositor reported its mode.
+    pub fn set_feedback(&self, feedback: PadFeedback) {
+        let mut pad = self.inner.borrow_mut();
+        pad.feedback = feedback;
+        for index in 0..pad.groups.len() {
+            pad.send_feedback(index);
+        }
+    }
+}
+
+struct Inner {
+    tablet_seat: zwp_tablet_seat_v2::ZwpTabletSeatV2,
+    pads: Vec<TabletPad>,
+    // the tablets and tools of the seat, only kept to be destroyed
+    tablets: Vec<zwp_tablet_v2::ZwpTabletV2>,
+    tools: Vec<zwp_tablet_tool_v2::ZwpTabletToolV2>,
+    callback: Rc<RefCell<PadCallback>>,
+}
+
+fn notify(inner: &Weak<RefCell<Inner>>, pad: &TabletPad, event: PadEvent, ddata: DispatchData) {
+    let callback = match inner.upgrade() {
+        Some(inner) => inner.borrow().callback.clone(),
+        None => return,
+    };
+    (&mut *callback.borrow_mut())(pad.clone(), event, ddata);
+}
+
+fn setup_group(
+    inner: &Weak<RefCell<Inner>>,
+    pad: &TabletPad,
+    group: wayland_client::Main<zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2>,
+) {
+    let index = {
+        let mut state = pad.inner.borrow_mut();
+        state.groups.push(Group {
+            group: group.detach(),
+            buttons: Vec::new(),
+            rings: Vec::new(),
+            strips: Vec::new(),
+            modes: 0,
+            mode: 0,
+            serial: None,
+        });
+        state.groups.len() - 1
+    };
+    let inner = inner.clone();
+    let pad = pad.clone();
+    group.quick_assign(move |_, event, ddata| {
+        use self::zwp_tablet_pad_group_v2::Event;
+        match event {
+            Event::Buttons { buttons } => {
+                pad.inner.borrow_mut().groups[index].buttons = buttons
+                    .chunks_exact(4)
+                    .map(NativeEndian::read_u32)
+                    .collect();
+            }
+            Event::Ring { ring } => setup_ring(&inner, &pad, index, ring),
+            Event::Strip { strip } => setup_strip(&inner, &pad, index, strip),
+            Event::Modes { modes } => pad.inner.borrow_mut().groups[index].modes = modes,
+            Event::Done => {}
+            Event::ModeSwitch { time, serial, mode } => {
+                {
+                    let mut state = pad.inner.borrow_mut();
+                    state.groups[index].mode = mode;
+                    state.groups[index].serial = Some(serial);
+                    state.send_feedback(index);
+                }
+                let event = PadEvent::ModeSwitch {
+                    group: index,
+                    time,
+                    mode,
+                };
+                notify(&inner, &pad, event, ddata);
+            }
+            _ => unreachable!(),
+        }
+    });
+}
+
+fn setup_ring(
+    inner: &Weak<RefCell<Inner>>,
+    pad: &TabletPad,
+    group: usize,
+    ring: wayland_client::Main<zwp_tablet_pad_ring_v2::ZwpTabletPadRingV2>,
+) {
+    let index = {
+        let mut state = pad.inner.borrow_mut();
+        let rings = &mut state.groups[group].rings;
+        rings.push(ring.detach());
+        rings.len() - 1
+    };
+    let inner = inner.clone();
+    let pad = pad.clone();
+    let mut frame = RingFrame::default();
+    ring.quick_assign(move |_, event, ddata| {
+        use self::zwp_tablet_pad_ring_v2::Event;
+        match event {
+            Event::Source { source } => frame.source = Some(source),
+            Event::Angle { degrees } => frame.angle = Some(degrees),
+            Event::Stop => frame.stop = true,
+            Event::Frame { time } => {
+                frame.time = time;
+                let event = PadEvent::Ring {
+                    group,
+                    ring: index,
+                    frame: std::mem::take(&mut frame),
+                };
+                notify(&inner, &pad, event, ddata);
+            }
+            _ => unreachable!(),
+        }
+    });
+}
+
+fn setup_strip(
+    inner: &Weak<RefCell<Inner>>,
+    pad: &TabletPad,
+    group: usize,
+    strip: wayland_client::Main<zwp_tablet_pad_strip_v2::ZwpTabletPadStripV2>,
+) {
+    let index = {
+        let mut state = pad.inner.borrow_mut();
+        let strips = &mut state.groups[group].strips;
+        strips.push(strip.detach());
+        strips.len() - 1
+    };
+    let inner = inner.clone();
+    let pad = pad.clone();
+    let mut frame = StripFrame::default();
+    strip.quick_assign(move |_, event, ddata| {
+        use self::zwp_tablet_pad_strip_v2::Event;
+        match event {
+            Event::Source { source } => frame.source = Some(source),
+            Event::Position { position } => frame.position = Some(position),
+            Event::Stop => frame.stop = true,
+            Event::Frame { time } => {
+                frame.time = time;
+                let event = PadEvent::Strip {
+                    group,
+                    strip: index,
+                    frame: std::mem::take(&mut frame),
+                };
+                notify(&inner, &pad, event, ddata);
+            }
+            _ => unreachable!(),
+        }
+    });
+}
+
+fn setup_pad(
+    inner: &Weak<RefCell<Inner>>,
+    pad: wayland_client::Main<zwp_tablet_pad_v2::ZwpTabletPadV2>,
+) -> TabletPad {
+    let handle = TabletPad {
+        inner: Rc::new(RefCell::new(Pad {
+            pad: pad.detach(),
+            paths: Vec::new(),
+            buttons: 0,
+            groups: Vec::new(),
+            focus: None,
+            feedback: PadFeedback::new(),
+        })),
+    };
+    let inner = inner.clone();
+    let my_handle = handle.clone();
+    pad.quick_assign(move |_, event, ddata| {
+        use self::zwp_tablet_pad_v2::Event;
+        let pad = &my_handle;
+        let event = match event {
+            Event::Group { pad_group } => {
+                setup_group(&inner, pad, pad_group);
+                return;
+            }
+            Event::Path { path } => {
+                pad.inner.borrow_mut().paths.push(path);
+                return;
+            }
+            Event::Buttons { buttons } => {
+                pad.inner.borrow_mut().buttons = buttons;
+                return;
+            }
+            Event::Done => PadEvent::Added,
+            Event::Button {
+                time,
+                button,
+                state,
+            } => PadEvent::Button {
+                time,
+                button,
+                state,
+            },
+            Event::Enter {
+                serial,
+                tablet,
+                surface,
+            } => {
+                pad.inner.borrow_mut().focus = Some(surface.clone());
+                PadEvent::Enter {
+                    serial,
+                    tablet,
+                    surface,
+                }
+            }
+            Event::Leave { serial, surface } => {
+                pad.inner.borrow_mut().focus = None;
+                PadEvent::Leave { serial, surface }
+            }
+            Event::Removed => {
+                notify(&inner, pad, PadEvent::Removed, ddata);
+                pad.inner.borrow_mut().destroy();
+                if let Some(inner) = inner.upgrade() {
+                    inner
+                        .borrow_mut()
+                        .pads
+                        .retain(|p| !Rc::ptr_eq(&p.inner, &pad.inner));
+                }
+                return;
+            }
+            _ => unreachable!(),
+        };
+        notify(&inner, pad, event, ddata);
+    });
+    handle
+}
+
+/// The pads of a seat
+///
+/// It is given to you by
+/// [`Environment::listen_for_tablet_pads`](../../environment/struct.Environment.html#method.listen_for_tablet_pads).
+///
+/// Dropping it destroys the pads and stops the delivery of their events.
+pub struct TabletPads {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl TabletPads {
+    /// The pads currently available on the seat
+    ///
+    /// Pads are listed as soon as they are announced by the compositor, their description is
+    /// complete once their `Added` event was delivered.
+    pub fn pads(&self) -> Vec<TabletPad> {
+        self.inner.borrow().pads.clone()
+    }
+}
+
+impl Drop for TabletPads {
+    fn drop(&mut self) {
+        let mut inner = self.inner.borrow_mut();
+        for pad in inner.pads.drain(..) {
+            pad.inner.borrow_mut().destroy();
+        }
+        for tablet in inner.tablets.drain(..) {
+            if tablet.as_ref().is_alive() {
+                tablet.destroy();
+            }
+        }
+        for tool in inner.tools.drain(..) {
+            if tool.as_ref().is_alive() {
+                tool.destroy();
+            }
+        }
+        inner.tablet_seat.destroy();
+    }
+}
+
+impl<E: GlobalHandler<ZwpTabletManagerV2>> Environment<E> {
+    /// Listen for the pads of the drawing tablets of a seat
+    ///
+    /// The callback is invoked with the events of the pads, the first one of each pad being
+    /// [`PadEvent::Added`](../seat/tablet/enum.PadEvent.html) once its description is complete.
+    ///
+    /// Fails if the compositor does not support `zwp_tablet_manager_v2`.
+    pub fn listen_for_tablet_pads<F>(
+        &self,
+        seat: &wl_seat::WlSeat,
+        callback: F,
+    ) -> Result<TabletPads, GlobalError>
+    where
+        F: FnMut(TabletPad, PadEvent, DispatchData) + 'static,
+    {
+        let manager = self.try_get_global::<ZwpTabletManagerV2>()?;
+        let tablet_seat = manager.get_tablet_seat(seat);
+        let inner = Rc::new(RefCell::new(Inner {
+            tablet_seat: tablet_seat.detach(),
+            pads: Vec::new(),
+            tablets: Vec::new(),
+            tools: Vec::new(),
+            callback: Rc::new(RefCell::new(callback)),
+        }));
+        let weak = Rc::downgrade(&inner);
+        tablet_seat.quick_assign(move |_, event, _| {
+            use self::zwp_tablet_seat_v2::Event;
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            match event {
+                Event::PadAdded { id } => {
+                    let pad = setup_pad(&weak, id);
+                    inner.borrow_mut().pads.push(pad);
+                }
+                Event::TabletAdded { id } => {
+                    id.quick_assign(|tablet, event, _| {
+                        if let zwp_tablet_v2::Event::Removed = event {
+                            tablet.destroy();
+                        }
+                    });
+                    let mut inner = inner.borrow_mut();
+                    inner.tablets.retain(|tablet| tablet.as_ref().is_alive());
+                    inner.tablets.push(id.detach());
+                }
+                Event::ToolAdded { id } => {
+                    id.quick_assign(|tool, event, _| {
+                        if let zwp_tablet_tool_v2::Event::Removed = event {
+                            tool.destroy();
+                        }
+                    });
+                    let mut inner = inner.borrow_mut();
+                    inner.tools.retain(|tool| tool.as_ref().is_alive());
+                    inner.tools.push(id.detach());
+                }
+                _ => unreachable!(),
+            }
+        });
+        Ok(TabletPads { inner })
+    }
+}
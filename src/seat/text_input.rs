@@ -0,0 +1,387 @@
+//! Utilities to receive text from input methods with `zwp_text_input_v3`
+//!
+//! Input methods let users type text that cannot be typed directly with their keyboard, like
+//! CJK characters or emoji. While a [`TextInput`](struct.TextInput.html) is enabled on the
+//! focused surface, the input method composes the text from the keys pressed by the user, and
+//! sends it to you as [`TextUpdate`](struct.TextUpdate.html)s: the text being composed is shown
+//! as a pre-edit string, and the text committed by the input method is inserted in your text.
+//!
+//! The text committed by the input method is only delivered through the `TextInput`, never
+//! through the keyboard callbacks of [`map_keyboard`](../keyboard/fn.map_keyboard.html): the
+//! key events you still receive are the ones the input method did not consume, that you can
+//! interpret as direct key input.
+//!
+//! The `zwp_text_input_manager_v3` global is not part of the
+//! [`default_environment!`](../../macro.default_environment.html), you need to add it to your
+//! environment, for example as a [`SimpleGlobal`](../../environment/struct.SimpleGlobal.html).
+
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use wayland_client::{
+    protocol::{wl_seat, wl_surface},
+    Attached, DispatchData,
+};
+
+use wayland_protocols::unstable::text_input::v3::client::{
+    zwp_text_input_manager_v3::ZwpTextInputManagerV3, zwp_text_input_v3,
+};
+
+pub use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
+    ChangeCause, ContentHint, ContentPurpose,
+};
+
+use crate::environment::{Environment, GlobalHandler, NewGlobalListener, RemovalListener};
+
+/// The text being composed by the input method
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preedit {
+    /// The text to display at the cursor position, which is not part of your text yet
+    pub text: String,
+    /// The cursor inside the pre-edit text, as two byte offsets
+    ///
+    /// When both offsets are equal, the cursor is at this offset, otherwise the text between
+    /// them should be highlighted. The cursor should be hidden if it is `None`.
+    pub cursor: Option<(usize, usize)>,
+}
+
+/// A change of the text requested by the input method
+///
+/// The changes must be applied in this order: the previous pre-edit text is removed, the
+/// surrounding text is deleted, the committed text is inserted at the cursor, and the new
+/// pre-edit text is shown at the cursor. [`apply`](#method.apply) does this for you.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextUpdate {
+    /// The text to show as being composed, `None` if no text is being composed
+    pub preedit: Option<Preedit>,
+    /// The text to insert at the cursor position
+    pub commit: Option<String>,
+    /// The number of bytes to delete before the cursor
+    pub delete_before: u32,
+    /// The number of bytes to delete after the cursor
+    pub delete_after: u32,
+}
+
+impl TextUpdate {
+    /// Apply this update to a text, given the byte offset of the cursor in it
+    ///
+    /// The text is expected not to contain the previous pre-edit text. The surrounding
+    /// text is deleted, the committed text inserted, and the cursor moved to the end of the
+    /// inserted text. The new pre-edit text is then yours to display at the cursor.
+    ///
+    /// Deletions are extended to the nearest character boundaries, so that the text stays
+    /// valid UTF-8 even if the input method got the surrounding text wrong.
+    pub fn apply(&self, text: &mut String, cursor: &mut usize) {
+        let mut position = std::cmp::min(*cursor, text.len());
+        while !text.is_char_boundary(position) {
+            position -= 1;
+        }
+        let mut start = position.saturating_sub(self.delete_before as usize);
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = std::cmp::min(position + self.delete_after as usize, text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        text.replace_range(start..end, "");
+        if let Some(ref commit) = self.commit {
+            text.insert_str(start, commit);
+            start += commit.len();
+        }
+        *cursor = start;
+    }
+}
+
+/// Possible events of a text input
+pub enum TextInputEvent {
+    /// A surface of yours got the text input focus of the seat
+    ///
+    /// You should enable the text input if this surface expects text.
+    Enter {
+        /// The focused surface
+        surface: wl_surface::WlSurface,
+    },
+    /// The surface lost the text input focus
+    Leave {
+        /// The surface that lost the focus
+        surface: wl_surface::WlSurface,
+    },
+    /// The input method requested a change of the text
+    Update {
+        /// The requested change
+        update: TextUpdate,
+        /// Whether the input method has processed all your commits
+        ///
+        /// If `false`, the update was made from a state you already changed: you should still
+        /// apply it to your text, but expect another update to follow.
+        in_sync: bool,
+    },
+    /// The compositor started or stopped supporting text inputs
+    ///
+    /// This happens when the `zwp_text_input_manager_v3` global is advertised or removed
+    /// after your `TextInput` was created. The focus is lost when it becomes unavailable.
+    Available(bool),
+}
+
+type TextInputCallback = dyn FnMut(TextInputEvent, DispatchData);
+
+struct Inner {
+    seat: wl_seat::WlSeat,
+    text_input: Option<zwp_text_input_v3::ZwpTextInputV3>,
+    focus: Option<wl_surface::WlSurface>,
+    // the number of commits sent to the current text input object
+    commits: u32,
+    pending: TextUpdate,
+    callback: Rc<RefCell<TextInputCallback>>,
+}
+
+impl Inner {
+    fn bind(inner: &Rc<RefCell<Inner>>, manager: &Attached<ZwpTextInputManagerV3>) {
+        let mut guard = inner.borrow_mut();
+        let text_input = manager.get_text_input(&guard.seat);
+        let weak = Rc::downgrade(inner);
+        text_input.quick_assign(move |_, event, ddata| {
+            if let Some(inner) = weak.upgrade() {
+                process_event(&inner, event, ddata);
+            }
+        });
+        guard.text_input = Some(text_input.detach());
+        guard.commits = 0;
+        guard.pending = TextUpdate::default();
+    }
+
+    fn unbind(&mut self) -> Option<wl_surface::WlSurface> {
+        if let Some(text_input) = self.text_input.take() {
+            text_input.destroy();
+        }
+        self.focus.take()
+    }
+
+    // the text input object, if it can receive requests
+    fn object(&self) -> Option<&zwp_text_input_v3::ZwpTextInputV3> {
+        self.text_input
+            .as_ref()
+            .filter(|text_input| text_input.as_ref().is_alive())
+    }
+}
+
+fn process_event(inner: &Rc<RefCell<Inner>>, event: zwp_text_input_v3::Event, ddata: DispatchData) {
+    use self::zwp_text_input_v3::Event;
+    let mut guard = inner.borrow_mut();
+    let event = match event {
+        Event::Enter { surface } => {
+            guard.focus = Some(surface.clone());
+            TextInputEvent::Enter { surface }
+        }
+        Event::Leave { surface } => {
+            guard.focus = None;
+            TextInputEvent::Leave { surface }
+        }
+        Event::PreeditString {
+            text,
+            cursor_begin,
+            cursor_end,
+        } => {
+            guard.pending.preedit = text.map(|text| Preedit {
+                text,
+                cursor: if cursor_begin < 0 || cursor_end < 0 {
+                    None
+                } else {
+                    Some((cursor_begin as usize, cursor_end as usize))
+                },
+            });
+            return;
+        }
+        Event::CommitString { text } => {
+            guard.pending.commit = text;
+            return;
+        }
+        Event::DeleteSurroundingText {
+            before_length,
+            after_length,
+        } => {
+            guard.pending.delete_before = before_length;
+            guard.pending.delete_after = after_length;
+            return;
+        }
+        Event::Done { serial } => TextInputEvent::Update {
+            // the changes are double-buffered, and reset after each `done`
+            update: std::mem::take(&mut guard.pending),
+            in_sync: serial == guard.commits,
+        },
+        _ => unreachable!(),
+    };
+    let callback = guard.callback.clone();
+    drop(guard);
+    (*callback.borrow_mut())(event, ddata);
+}
+
+/// A text input of a seat
+///
+/// It is given to you by
+/// [`Environment::new_text_input`](../../environment/struct.Environment.html#method.new_text_input).
+/// The state you set with its methods is double-buffered: it is only applied by the next call
+/// to [`commit`](#method.commit).
+///
+/// Dropping it destroys the text input.
+pub struct TextInput {
+    inner: Rc<RefCell<Inner>>,
+    _listeners: (NewGlobalListener, RemovalListener),
+}
+
+impl TextInput {
+    /// Whether the compositor currently supports text inputs
+    pub fn is_available(&self) -> bool {
+        self.inner.borrow().text_input.is_some()
+    }
+
+    /// The surface that currently has the text input focus, if any
+    pub fn focus(&self) -> Option<wl_surface::WlSurface> {
+        self.inner.borrow().focus.clone()
+    }
+
+    /// Enable the text input on the focused surface
+    ///
+    /// This resets the state of the text input, you should set the surrounding text and
+    /// content type again before committing.
+    pub fn enable(&self) {
+        if let Some(text_input) = self.inner.borrow().object() {
+            text_input.enable();
+        }
+    }
+
+    /// Disable the text input
+    pub fn disable(&self) {
+        if let Some(text_input) = self.inner.borrow().object() {
+            text_input.disable();
+        }
+    }
+
+    /// Set the text around the cursor
+    ///
+    /// `cursor` and `anchor` are byte offsets in `text`, the selection lying between them.
+    /// The text should only contain a reasonable amount of text around the cursor, the
+    /// protocol limits it to 4000 bytes.
+    pub fn set_surrounding_text(&self, text: String, cursor: i32, anchor: i32) {
+        if let Some(text_input) = self.inner.borrow().object() {
+            text_input.set_surrounding_text(text, cursor, anchor);
+        }
+    }
+
+    /// Tell whether the last change of the text was made by the input method or not
+    pub fn set_text_change_cause(&self, cause: ChangeCause) {
+        if let Some(text_input) = self.inner.borrow().object() {
+            text_input.set_text_change_cause(cause);
+        }
+    }
+
+    /// Set the kind of text expected, to adapt the behavior of the input method
+    pub fn set_content_type(&self, hint: ContentHint, purpose: ContentPurpose) {
+        if let Some(text_input) = self.inner.borrow().object() {
+            text_input.set_content_type(hint, purpose);
+        }
+    }
+
+    /// Set the area of the cursor, in surface-local coordinates
+    ///
+    /// The input method uses it to place its candidate window next to the cursor.
+    pub fn set_cursor_rectangle(&self, x: i32, y: i32, width: i32, height: i32) {
+        if let Some(text_input) = self.inner.borrow().object() {
+            text_input.set_cursor_rectangle(x, y, width, height);
+        }
+    }
+
+    /// Apply the state set since the last commit
+    pub fn commit(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let committed = match inner.object() {
+            Some(text_input) => {
+                text_input.commit();
+                true
+            }
+            None => false,
+        };
+        if committed {
+            inner.commits = inner.commits.wrapping_add(1);
+        }
+    }
+}
+
+impl Drop for TextInput {
+    fn drop(&mut self) {
+        self.inner.borrow_mut().unbind();
+    }
+}
+
+impl<E: GlobalHandler<ZwpTextInputManagerV3> + 'static> Environment<E> {
+    /// Create a text input for this seat
+    ///
+    /// The callback is invoked with the events of the text input. If the compositor does not
+    /// support `zwp_text_input_manager_v3`, the text input is unavailable until the global is
+    /// advertised.
+    pub fn new_text_input<F>(&self, seat: &wl_seat::WlSeat, callback: F) -> TextInput
+    where
+        F: FnMut(TextInputEvent, DispatchData) + 'static,
+    {
+        let inner = Rc::new(RefCell::new(Inner {
+            seat: seat.clone(),
+            text_input: None,
+            focus: None,
+            commits: 0,
+            pending: TextUpdate::default(),
+            callback: Rc::new(RefCell::new(callback)),
+        }));
+        if let Some(manager) = self.get_global::<ZwpTextInputManagerV3>() {
+            Inner::bind(&inner, &manager);
+        }
+
+        let weak: Weak<RefCell<Inner>> = Rc::downgrade(&inner);
+        let env = self.clone();
+        let new_listener =
+            self.listen_for_new_globals::<ZwpTextInputManagerV3, _>(move |_, _, ddata| {
+                let inner = match weak.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                if inner.borrow().text_input.is_some() {
+                    return;
+                }
+                let manager = match env.get_global::<ZwpTextInputManagerV3>() {
+                    Some(manager) => manager,
+                    None => return,
+                };
+                Inner::bind(&inner, &manager);
+                let callback = inner.borrow().callback.clone();
+                (*callback.borrow_mut())(TextInputEvent::Available(true), ddata);
+            });
+
+        let weak = Rc::downgrade(&inner);
+        let removal_listener =
+            self.listen_for_removals::<ZwpTextInputManagerV3, _>(move |_, mut ddata| {
+                let inner = match weak.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                let (focus, callback) = {
+                    let mut guard = inner.borrow_mut();
+                    if guard.text_input.is_none() {
+                        return;
+                    }
+                    (guard.unbind(), guard.callback.clone())
+                };
+                let mut callback = callback.borrow_mut();
+                if let Some(surface) = focus {
+                    (*callback)(TextInputEvent::Leave { surface }, ddata.reborrow());
+                }
+                (*callback)(TextInputEvent::Available(false), ddata);
+            });
+
+        TextInput {
+            inner,
+            _listeners: (new_listener, removal_listener),
+        }
+    }
+}
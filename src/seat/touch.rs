@@ -0,0 +1,403 @@
+//! Utilities to track the touch points of a seat
+//!
+//! The [`map_touch`](fn.map_touch.html) function sets up the `wl_touch` of a seat and delivers
+//! its events for each touch point. Compositors cancel the touch sequences they take over,
+//! for example for an edge swipe, after having already sent their `down` and `motion` events
+//! to your surfaces. Each point still active is then terminated by an `Event::Cancelled`
+//! rather than an `Event::Up`, so that you can undo the actions in progress instead of
+//! committing them, and the events of these points are dropped until they touch down again.
+//!
+//! With [`map_touch_with_grace`](fn.map_touch_with_grace.html), the `down` of each point is
+//! held back for a short delay, so that a point cancelled right away, like a palm rejected by
+//! the compositor, is never reported at all.
+
+use std::{cell::RefCell, fmt};
+#[cfg(feature = "calloop")]
+use std::{rc::Rc, time::Duration};
+
+use wayland_client::{
+    protocol::{wl_seat, wl_surface, wl_touch},
+    Attached, DispatchData,
+};
+
+use super::{record_serial, SerialKind};
+
+/// Possible errors when setting up a touch
+#[derive(Debug)]
+pub enum Error {
+    /// The provided seat does not have the touch capability
+    NoTouch,
+    /// The timer delaying the `down` events could not be created
+    TimerError(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoTouch => f.write_str("the seat does not have the touch capability"),
+            Error::TimerError(err) => write!(f, "failed to create the timer: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NoTouch => None,
+            Error::TimerError(err) => Some(err),
+        }
+    }
+}
+
+/// Events of the touch points of a seat
+#[derive(Clone)]
+pub enum Event {
+    /// A point touched a surface
+    Down {
+        /// Serial of the `down` event
+        serial: u32,
+        /// Timestamp of the `down` event, in milliseconds
+        time: u32,
+        /// The surface which was touched
+        surface: wl_surface::WlSurface,
+        /// The identifier of the point, until it is lifted or cancelled
+        id: i32,
+        /// The position of the point, in surface-local coordinates
+        position: (f64, f64),
+    },
+    /// A point was lifted
+    Up {
+        /// Serial of the `up` event
+        serial: u32,
+        /// Timestamp of the `up` event, in milliseconds
+        time: u32,
+        /// The identifier of the point
+        id: i32,
+    },
+    /// A point moved
+    Motion {
+        /// Timestamp of the event, in milliseconds
+        time: u32,
+        /// The identifier of the point
+        id: i32,
+        /// The new position of the point, in surface-local coordinates
+        position: (f64, f64),
+    },
+    /// A point was cancelled by the compositor
+    ///
+    /// This terminates the point instead of `Up`: the gesture it was part of should be
+    /// undone rather than committed.
+    Cancelled {
+        /// The identifier of the point
+        id: i32,
+    },
+    /// The end of a group of events happening at the same time
+    Frame,
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Down {
+                serial,
+                time,
+                surface,
+                id,
+                position,
+            } => f
+                .debug_struct("Down")
+                .field("serial", serial)
+                .field("time", time)
+                .field("surface", &surface.as_ref().id())
+                .field("id", id)
+                .field("position", position)
+                .finish(),
+            Event::Up { serial, time, id } => f
+                .debug_struct("Up")
+                .field("serial", serial)
+                .field("time", time)
+                .field("id", id)
+                .finish(),
+            Event::Motion { time, id, position } => f
+                .debug_struct("Motion")
+                .field("time", time)
+                .field("id", id)
+                .field("position", position)
+                .finish(),
+            Event::Cancelled { id } => f.debug_struct("Cancelled").field("id", id).finish(),
+            Event::Frame => f.write_str("Frame"),
+        }
+    }
+}
+
+type TouchCallback = dyn FnMut(Event, wl_touch::WlTouch, DispatchData);
+
+// A `down` event held back until the grace delay expires
+struct PendingDown {
+    serial: u32,
+    time: u32,
+    surface: wl_surface::WlSurface,
+    position: (f64, f64),
+    // the latest motion received in the meantime
+    motion: Option<(u32, (f64, f64))>,
+}
+
+struct TouchPoint {
+    id: i32,
+    pending: Option<PendingDown>,
+}
+
+struct TouchState {
+    // the active points, the events of the others are dropped until they touch down again
+    points: Vec<TouchPoint>,
+    // whether events were delivered since the last `frame`
+    dirty: bool,
+    // schedules the expiration of the grace delay of a point, given its id and the serial
+    // of its `down` event
+    schedule_grace: Option<Box<dyn Fn(i32, u32)>>,
+}
+
+impl TouchState {
+    fn new(schedule_grace: Option<Box<dyn Fn(i32, u32)>>) -> TouchState {
+        TouchState {
+            points: Vec::new(),
+            dirty: false,
+            schedule_grace,
+        }
+    }
+
+    fn process(&mut self, event: wl_touch::Event) -> Vec<Event> {
+        let mut events = Vec::new();
+        match event {
+            wl_touch::Event::Down {
+                serial,
+                time,
+                surface,
+                id,
+                x,
+                y,
+            } => {
+                self.points.retain(|p| p.id != id);
+                match self.schedule_grace {
+                    Some(ref schedule) => {
+                        schedule(id, serial);
+                        self.points.push(TouchPoint {
+                            id,
+                            pending: Some(PendingDown {
+                                serial,
+                                time,
+                                surface,
+                                position: (x, y),
+                                motion: None,
+                            }),
+                        });
+                    }
+                    None => {
+                        self.points.push(TouchPoint { id, pending: None });
+                        events.push(Event::Down {
+                            serial,
+                            time,
+                            surface,
+                            id,
+                            position: (x, y),
+                        });
+                    }
+                }
+            }
+            wl_touch::Event::Motion { time, id, x, y } => {
+                if let Some(point) = self.points.iter_mut().find(|p| p.id == id) {
+                    match point.pending {
+                        Some(ref mut down) => down.motion = Some((time, (x, y))),
+                        None => events.push(Event::Motion {
+                            time,
+                            id,
+                            position: (x, y),
+                        }),
+                    }
+                }
+            }
+            wl_touch::Event::Up { serial, time, id } => {
+                if let Some(index) = self.points.iter().position(|p| p.id == id) {
+                    // a tap shorter than the grace delay is still reported
+                    let point = self.points.remove(index);
+                    if let Some(down) = point.pending {
+                        push_down(&mut events, id, down);
+                    }
+                    events.push(Event::Up { serial, time, id });
+                }
+            }
+            wl_touch::Event::Cancel => {
+                for point in self.points.drain(..) {
+                    // the points still in their grace delay are never reported
+                    if point.pending.is_none() {
+                        events.push(Event::Cancelled { id: point.id });
+                    }
+                }
+                // the compositor does not send a `frame` after a `cancel`
+                if !events.is_empty() {
+                    self.dirty = false;
+                    events.push(Event::Frame);
+                }
+                return events;
+            }
+            wl_touch::Event::Frame => {
+                if self.dirty {
+                    self.dirty = false;
+                    events.push(Event::Frame);
+                }
+                return events;
+            }
+            _ => {}
+        }
+        self.dirty |= !events.is_empty();
+        events
+    }
+
+    // Report a point whose grace delay expired, if it is still pending
+    #[cfg(feature = "calloop")]
+    fn expire(&mut self, id: i32, serial: u32) -> Vec<Event> {
+        let mut events = Vec::new();
+        let point = self.points.iter_mut().find(|p| p.id == id);
+        if let Some(point) = point {
+            if point.pending.as_ref().map(|down| down.serial) == Some(serial) {
+                push_down(&mut events, id, point.pending.take().unwrap());
+                events.push(Event::Frame);
+            }
+        }
+        events
+    }
+}
+
+fn push_down(events: &mut Vec<Event>, id: i32, down: PendingDown) {
+    events.push(Event::Down {
+        serial: down.serial,
+        time: down.time,
+        surface: down.surface,
+        id,
+        position: down.position,
+    });
+    if let Some((time, position)) = down.motion {
+        events.push(Event::Motion { time, id, position });
+    }
+}
+
+fn deliver(
+    callback: &RefCell<TouchCallback>,
+    touch: &wl_touch::WlTouch,
+    events: Vec<Event>,
+    mut ddata: DispatchData,
+) {
+    let mut callback = callback.borrow_mut();
+    for event in events {
+        (&mut *callback)(event, touch.clone(), ddata.reborrow());
+    }
+}
+
+fn get_touch(
+    seat: &Attached<wl_seat::WlSeat>,
+) -> Result<wayland_client::Main<wl_touch::WlTouch>, Error> {
+    let has_touch = super::with_seat_data(seat, |data| data.has_touch).unwrap_or(false);
+    if has_touch {
+        Ok(seat.get_touch())
+    } else {
+        Err(Error::NoTouch)
+    }
+}
+
+/// Implement the touch of a seat, tracking its touch points
+///
+/// The callback is invoked with the events of the touch points, the points cancelled by the
+/// compositor being terminated by an `Event::Cancelled`. The serials of the `down` events are
+/// recorded in the data of the seat.
+///
+/// Returns an error if the provided seat does not have the touch capability.
+pub fn map_touch<F>(
+    seat: &Attached<wl_seat::WlSeat>,
+    callback: F,
+) -> Result<wl_touch::WlTouch, Error>
+where
+    F: FnMut(Event, wl_touch::WlTouch, DispatchData) + 'static,
+{
+    let touch = get_touch(seat)?;
+    let mut state = TouchState::new(None);
+    let callback = RefCell::new(callback);
+    let my_seat = seat.detach();
+    touch.quick_assign(move |touch, event, ddata| {
+        #[cfg(feature = "recording")]
+        super::recording::record_touch(&touch, &event);
+        if let wl_touch::Event::Down { serial, .. } = event {
+            record_serial(&my_seat, SerialKind::TouchDown, serial);
+        }
+        let events = state.process(event);
+        deliver(&callback, &touch.detach(), events, ddata);
+    });
+    Ok(touch.detach())
+}
+
+/// Implement the touch of a seat, delaying the `down` events by a grace delay
+///
+/// This is like [`map_touch`](fn.map_touch.html), except that the `down` event of each point
+/// is only delivered once the grace delay expired, typically a few milliseconds, followed by
+/// its latest motion in the meantime. A point cancelled by the compositor during its grace
+/// delay is not reported at all, while a point lifted during it is reported right away.
+///
+/// The delay is handled by a timer inserted in the given event loop.
+///
+/// Returns an error if the provided seat does not have the touch capability, or if the timer
+/// could not be created.
+///
+/// **Note:** This requires the `calloop` cargo feature.
+#[cfg(feature = "calloop")]
+pub fn map_touch_with_grace<F, Data>(
+    seat: &Attached<wl_seat::WlSeat>,
+    loop_handle: &calloop::LoopHandle<Data>,
+    grace: Duration,
+    callback: F,
+) -> Result<wl_touch::WlTouch, Error>
+where
+    F: FnMut(Event, wl_touch::WlTouch, DispatchData) + 'static,
+    Data: 'static,
+{
+    let timer = calloop::timer::Timer::new().map_err(Error::TimerError)?;
+    let timer_handle = timer.handle();
+    let touch = get_touch(seat)?;
+    let state = Rc::new(RefCell::new(TouchState::new(Some(Box::new(
+        move |id, serial| {
+            timer_handle.add_timeout(grace, (id, serial));
+        },
+    )))));
+    let callback = Rc::new(RefCell::new(callback)) as Rc<RefCell<TouchCallback>>;
+
+    let timer_state = state.clone();
+    let timer_callback = callback.clone();
+    let timer_touch = touch.detach();
+    loop_handle
+        .insert_source(timer, move |(id, serial), _, ddata| {
+            let events = timer_state.borrow_mut().expire(id, serial);
+            if events.is_empty() {
+                return;
+            }
+            deliver(
+                &timer_callback,
+                &timer_touch,
+                events,
+                DispatchData::wrap(ddata),
+            );
+            // the expirations are not triggered by Wayland events, send the requests
+            // of the callback right away
+            crate::event_loop::flush_connections();
+        })
+        .map_err(|e| Error::TimerError(e.error))?;
+
+    let my_seat = seat.detach();
+    touch.quick_assign(move |touch, event, ddata| {
+        #[cfg(feature = "recording")]
+        super::recording::record_touch(&touch, &event);
+        if let wl_touch::Event::Down { serial, .. } = event {
+            record_serial(&my_seat, SerialKind::TouchDown, serial);
+        }
+        let events = state.borrow_mut().process(event);
+        deliver(&callback, &touch.detach(), events, ddata);
+    });
+    Ok(touch.detach())
+}
@@ -0,0 +1,744 @@
+//! Layer shell surfaces
+//!
+//! This module provides a wrapper around the `wlr-layer-shell` protocol, used
+//! by desktop components like panels, docks, notifications or wallpapers.
+//!
+//! To use it, you need to route the `zwlr_layer_shell_v1` global in your
+//! environment (a [`SimpleGlobal`](../../environment/struct.SimpleGlobal.html) is
+//! enough), you can then create layer surfaces using
+//! [`Environment::create_layer_surface`](../../environment/struct.Environment.html#method.create_layer_surface).
+//!
+//! Components displayed on every output, like a status bar, can use
+//! [`PerOutputLayerSurfaces`](struct.PerOutputLayerSurfaces.html), which creates a layer
+//! surface for each output as they are plugged in and removes it once they are unplugged.
+use std::{
+    cell::RefCell,
+    rc::{self, Rc},
+};
+
+use wayland_client::{
+    protocol::{wl_compositor, wl_output, wl_surface},
+    Attached, DispatchData,
+};
+
+use wayland_protocols::wlr::unstable::layer_shell::v1::client::{
+    zwlr_layer_shell_v1, zwlr_layer_surface_v1,
+};
+pub use wayland_protocols::wlr::unstable::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1::Anchor,
+};
+
+use crate::{
+    environment::{has_version, Environment, GlobalHandler, MultiGlobalHandler},
+    output::{with_output_info, OutputHandling, OutputInfo, OutputStatusListener},
+    surface::{assign_role, RoleConflict, SurfaceRole},
+};
+
+/// Possible events generated by a layer surface
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// The compositor has configured the surface
+    ///
+    /// The configure has already been acknowledged, you need to draw your surface at
+    /// the given size and commit it. If one of the dimensions is 0, it means the
+    /// compositor leaves this dimension up to you.
+    Configure {
+        /// The size suggested by the compositor
+        new_size: (u32, u32),
+    },
+    /// The surface has been closed by the compositor
+    ///
+    /// This typically happens when the output it was displayed on was removed. The
+    /// surface will not be displayed again, and you should drop it.
+    Closed,
+}
+
+/// Exclusive zone policy of a layer surface
+///
+/// The exclusive zone is the area that the compositor should not cover with other
+/// surfaces (typically, the space reserved by a panel).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExclusiveZone {
+    /// Reserve the thickness of the surface along its anchored edge
+    ///
+    /// After each configure, the exclusive zone is set to the size of the surface
+    /// along the axis perpendicular to the edge it is anchored to, plus the margin
+    /// facing the rest of the output. The margin on the anchored edge itself is
+    /// already accounted for by the compositor.
+    ///
+    /// If the surface is not anchored to a single edge (for example if it is anchored
+    /// to a corner or to all edges), this behaves like `Ignore`.
+    Auto,
+    /// Reserve a fixed amount of space from the anchored edge
+    Fixed(i32),
+    /// Don't reserve any space, and don't be moved by the exclusive zones of
+    /// other surfaces (zone of `-1`)
+    DontCare,
+    /// Don't reserve any space, but be moved to avoid the exclusive zones of
+    /// other surfaces (zone of `0`)
+    Ignore,
+}
+
+impl ExclusiveZone {
+    fn compute(self, anchor: Anchor, size: (u32, u32), margin: [i32; 4]) -> i32 {
+        match self {
+            ExclusiveZone::Fixed(zone) => zone,
+            ExclusiveZone::DontCare => -1,
+            ExclusiveZone::Ignore => 0,
+            ExclusiveZone::Auto => {
+                let [top, right, bottom, left] = margin;
+                let horizontal = Anchor::Left | Anchor::Right;
+                let vertical = Anchor::Top | Anchor::Bottom;
+                let h = anchor & horizontal;
+                let v = anchor & vertical;
+                // the surface must be anchored to exactly one edge, optionally stretched
+                // along that edge by being anchored to both perpendicular ones
+                let spans_h = h.is_empty() || h == horizontal;
+                let spans_v = v.is_empty() || v == vertical;
+                let thickness = if v == Anchor::Top && spans_h {
+                    size.1 as i32 + bottom
+                } else if v == Anchor::Bottom && spans_h {
+                    size.1 as i32 + top
+                } else if h == Anchor::Left && spans_v {
+                    size.0 as i32 + right
+                } else if h == Anchor::Right && spans_v {
+                    size.0 as i32 + left
+                } else {
+                    0
+                };
+                std::cmp::max(thickness, 0)
+            }
+        }
+    }
+}
+
+struct Inner {
+    anchor: Anchor,
+    margin: [i32; 4],
+    size: (u32, u32),
+    exclusive_zone: ExclusiveZone,
+    last_zone: Option<i32>,
+}
+
+impl Inner {
+    fn update_zone(&mut self, layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1) {
+        let zone = self
+            .exclusive_zone
+            .compute(self.anchor, self.size, self.margin);
+        if self.last_zone != Some(zone) {
+            layer_surface.set_exclusive_zone(zone);
+            self.last_zone = Some(zone);
+        }
+    }
+}
+
+/// A layer surface
+///
+/// This wraps a `zwlr_layer_surface_v1` and the `wl_surface` it was created for,
+/// and keeps track of the state needed to manage its exclusive zone. All the setters
+/// are double-buffered: they take effect on the next commit of the surface.
+///
+/// The layer surface is destroyed when this handle is dropped, the `wl_surface`
+/// is not.
+pub struct LayerSurface {
+    surface: wl_surface::WlSurface,
+    layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+    inner: Rc<RefCell<Inner>>,
+    owner: Rc<LayerSurfaceOwner>,
+}
+
+// Shared by a layer surface and the handles given by `Environment::for_each_layer_surface`,
+// the layer surface is destroyed once all of them are dropped
+struct LayerSurfaceOwner(zwlr_layer_surface_v1::ZwlrLayerSurfaceV1);
+
+impl Drop for LayerSurfaceOwner {
+    fn drop(&mut self) {
+        self.0.destroy();
+    }
+}
+
+// The weak handle to a layer surface tracked by the environment
+struct TrackedLayerSurface {
+    surface: wl_surface::WlSurface,
+    layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+    inner: rc::Weak<RefCell<Inner>>,
+    owner: rc::Weak<LayerSurfaceOwner>,
+}
+
+impl TrackedLayerSurface {
+    fn upgrade(&self) -> Option<LayerSurface> {
+        Some(LayerSurface {
+            surface: self.surface.clone(),
+            layer_surface: self.layer_surface.clone(),
+            owner: self.owner.upgrade()?,
+            inner: self.inner.upgrade()?,
+        })
+    }
+}
+
+impl LayerSurface {
+    /// Create a new layer surface for this `wl_surface`
+    ///
+    /// The surface must not have any role nor any buffer attached. You then need to
+    /// set up its size and anchor, and commit the `wl_surface` without any buffer
+    /// to request the initial configure from the compositor. You must not attach a
+    /// buffer before having received the first `Configure` event.
+    ///
+    /// Fails if the surface was given another role by SCTK.
+    pub fn new<F>(
+        layer_shell: &Attached<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+        surface: wl_surface::WlSurface,
+        output: Option<&wl_output::WlOutput>,
+        layer: Layer,
+        namespace: String,
+        mut callback: F,
+    ) -> Result<LayerSurface, RoleConflict>
+    where
+        F: FnMut(Event, DispatchData) + 'static,
+    {
+        assign_role(&surface, SurfaceRole::LayerSurface)?;
+        let inner = Rc::new(RefCell::new(Inner {
+            anchor: Anchor::empty(),
+            margin: [0; 4],
+            size: (0, 0),
+            exclusive_zone: ExclusiveZone::Ignore,
+            last_zone: None,
+        }));
+        let layer_surface = layer_shell.get_layer_surface(&surface, output, layer, namespace);
+        let inner_2 = inner.clone();
+        layer_surface.quick_assign(move |layer_surface, event, ddata| match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                layer_surface.ack_configure(serial);
+                {
+                    let mut inner = inner_2.borrow_mut();
+                    inner.size = (width, height);
+                    inner.update_zone(&layer_surface);
+                }
+                callback(
+                    Event::Configure {
+                        new_size: (width, height),
+                    },
+                    ddata,
+                );
+            }
+            zwlr_layer_surface_v1::Event::Closed => callback(Event::Closed, ddata),
+            _ => unreachable!(),
+        });
+        Ok(LayerSurface {
+            surface,
+            layer_surface: layer_surface.detach(),
+            inner,
+            owner: Rc::new(LayerSurfaceOwner(layer_surface.detach())),
+        })
+    }
+
+    /// Access the underlying `wl_surface`
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    /// Access the underlying `zwlr_layer_surface_v1`
+    pub fn layer_surface(&self) -> &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1 {
+        &self.layer_surface
+    }
+
+    /// Set the size of the surface
+    ///
+    /// A dimension of 0 means you let the compositor choose it, in which case
+    /// the surface must be anchored to both edges along this dimension.
+    pub fn set_size(&self, width: u32, height: u32) {
+        self.layer_surface.set_size(width, height);
+    }
+
+    /// Set the edges of the output the surface is anchored to
+    pub fn set_anchor(&self, anchor: Anchor) {
+        self.layer_surface.set_anchor(anchor);
+        let mut inner = self.inner.borrow_mut();
+        inner.anchor = anchor;
+        inner.update_zone(&self.layer_surface);
+    }
+
+    /// Set the margins of the surface, relative to the edges it is anchored to
+    pub fn set_margin(&self, top: i32, right: i32, bottom: i32, left: i32) {
+        self.layer_surface.set_margin(top, right, bottom, left);
+        let mut inner = self.inner.borrow_mut();
+        inner.margin = [top, right, bottom, left];
+        inner.update_zone(&self.layer_surface);
+    }
+
+    /// Set the exclusive zone policy of the surface
+    ///
+    /// The new policy takes effect on the next commit of the surface. With
+    /// `ExclusiveZone::Auto`, the zone is computed from the size given in the last
+    /// configure, and updated on each subsequent configure.
+    pub fn set_exclusive_zone(&self, zone: ExclusiveZone) {
+        let mut inner = self.inner.borrow_mut();
+        inner.exclusive_zone = zone;
+        inner.update_zone(&self.layer_surface);
+    }
+
+    /// Get the current exclusive zone policy of the surface
+    pub fn exclusive_zone(&self) -> ExclusiveZone {
+        self.inner.borrow().exclusive_zone
+    }
+
+    /// Set whether this surface wants to receive keyboard input
+    pub fn set_keyboard_interactivity(&self, interactivity: bool) {
+        self.layer_surface
+            .set_keyboard_interactivity(if interactivity { 1 } else { 0 });
+    }
+
+    /// Move the surface to another layer
+    ///
+    /// This requires version 2 of the layer shell protocol, and does nothing
+    /// on older compositors.
+    pub fn set_layer(&self, layer: Layer) {
+        if has_version(&self.layer_surface, 2) {
+            self.layer_surface.set_layer(layer);
+        }
+    }
+}
+
+/// A helper negotiating the size of a layer surface
+///
+/// When a dimension of the requested size is 0, the compositor chooses it and
+/// sends it in the configure events. This helper does the initial commit without
+/// buffer, tracks the size negotiated through the configure events, and invokes a
+/// `resized` callback whenever it actually changes, which is when your buffers need
+/// to be reallocated.
+///
+/// Configure events with a dimension of 0 that the compositor should have chosen,
+/// such as the 0x0 configure some compositors send while the output is removed,
+/// are ignored and the current size is kept.
+pub struct LayerSurfaceSizer {
+    requested: (u32, u32),
+    size: Option<(u32, u32)>,
+    resized: Box<dyn FnMut(u32, u32)>,
+}
+
+impl LayerSurfaceSizer {
+    /// Create a new sizer for a surface of the requested size
+    ///
+    /// A dimension of 0 is left to the compositor. The callback is invoked with the
+    /// new size when the negotiated size changes, including on the first configure.
+    pub fn new<F>(width: u32, height: u32, resized: F) -> LayerSurfaceSizer
+    where
+        F: FnMut(u32, u32) + 'static,
+    {
+        LayerSurfaceSizer {
+            requested: (width, height),
+            size: None,
+            resized: Box::new(resized),
+        }
+    }
+
+    /// Set the requested size and do the initial commit of the surface
+    ///
+    /// This must be done once the surface is set up (anchor, margins, ...), and before
+    /// any buffer is attached. The compositor replies with the first configure.
+    pub fn initial_commit(&self, layer_surface: &LayerSurface) {
+        let (width, height) = self.requested;
+        layer_surface.set_size(width, height);
+        layer_surface.surface().commit();
+    }
+
+    /// Change the requested size of the surface
+    ///
+    /// Like the other setters of `LayerSurface`, this takes effect on the next commit,
+    /// and the compositor then sends a configure with the new size.
+    pub fn set_requested_size(&mut self, layer_surface: &LayerSurface, width: u32, height: u32) {
+        self.requested = (width, height);
+        layer_surface.set_size(width, height);
+    }
+
+    /// The negotiated size, `None` until the first configure was received
+    ///
+    /// You must not attach any buffer to the surface before it is configured.
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+
+    /// Process an event of the layer surface
+    ///
+    /// Returns `true` if the negotiated size changed, in which case the `resized`
+    /// callback was invoked and the surface needs to be drawn at the new size.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let (width, height) = match *event {
+            Event::Configure { new_size } => new_size,
+            Event::Closed => {
+                self.size = None;
+                return false;
+            }
+        };
+        // the dimensions the compositor did not choose are the requested ones
+        let width = if width == 0 { self.requested.0 } else { width };
+        let height = if height == 0 {
+            self.requested.1
+        } else {
+            height
+        };
+        if width == 0 || height == 0 {
+            log::debug!(
+                "[SCTK] Ignoring a degenerate configure of size {}x{} of a layer surface",
+                width,
+                height
+            );
+            return false;
+        }
+        if self.size == Some((width, height)) {
+            return false;
+        }
+        self.size = Some((width, height));
+        (self.resized)(width, height);
+        true
+    }
+}
+
+impl<E> Environment<E>
+where
+    E: GlobalHandler<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+{
+    /// Create a new layer surface for this surface
+    ///
+    /// See [`LayerSurface::new`](../shell/layer/struct.LayerSurface.html#method.new) for details.
+    ///
+    /// *Panic*
+    ///
+    /// This function will panic if the compositor did not advertise the layer shell global.
+    pub fn create_layer_surface<F>(
+        &self,
+        surface: wl_surface::WlSurface,
+        output: Option<&wl_output::WlOutput>,
+        layer: Layer,
+        namespace: String,
+        callback: F,
+    ) -> Result<LayerSurface, RoleConflict>
+    where
+        F: FnMut(Event, DispatchData) + 'static,
+    {
+        let layer_shell = self.require_global::<zwlr_layer_shell_v1::ZwlrLayerShellV1>();
+        let layer_surface =
+            LayerSurface::new(&layer_shell, surface, output, layer, namespace, callback)?;
+        self.track(TrackedLayerSurface {
+            surface: layer_surface.surface.clone(),
+            layer_surface: layer_surface.layer_surface.clone(),
+            inner: Rc::downgrade(&layer_surface.inner),
+            owner: Rc::downgrade(&layer_surface.owner),
+        });
+        Ok(layer_surface)
+    }
+
+    /// Invoke a closure on each layer surface created by this environment
+    ///
+    /// This only visits the layer surfaces created by
+    /// [`create_layer_surface`](#method.create_layer_surface). The closure is given a handle
+    /// sharing the state of the `LayerSurface`, and a handle kept beyond the closure keeps the
+    /// layer surface alive. The layer surfaces are listed before the closure is invoked, so it
+    /// can create or drop layer surfaces, the new ones are not visited. The order of the layer
+    /// surfaces is unspecified.
+    pub fn for_each_layer_surface<G>(&self, mut f: G)
+    where
+        G: FnMut(&LayerSurface),
+    {
+        for layer_surface in self.tracked(TrackedLayerSurface::upgrade) {
+            f(&layer_surface);
+        }
+    }
+}
+
+type InstanceCallback<T> =
+    dyn FnMut(&wl_output::WlOutput, &LayerSurface, &mut T, Event, DispatchData);
+
+// The layer surface of an output, with its `wl_surface` and the state of the application
+struct Instance<T> {
+    output: wl_output::WlOutput,
+    layer_surface: Option<LayerSurface>,
+    state: T,
+}
+
+impl<T> Drop for Instance<T> {
+    fn drop(&mut self) {
+        // the role object must be destroyed before its surface
+        if let Some(layer_surface) = self.layer_surface.take() {
+            let surface = layer_surface.surface().clone();
+            drop(layer_surface);
+            surface.destroy();
+        }
+    }
+}
+
+struct PerOutputInner<T> {
+    compositor: Attached<wl_compositor::WlCompositor>,
+    layer_shell: Attached<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    layer: Layer,
+    namespace: String,
+    instances: Vec<Instance<T>>,
+    factory: Box<dyn FnMut(&wl_output::WlOutput, &OutputInfo, &LayerSurface) -> T>,
+    callback: Rc<RefCell<InstanceCallback<T>>>,
+}
+
+impl<T: 'static> PerOutputInner<T> {
+    fn add_output(
+        &mut self,
+        me: &rc::Weak<RefCell<PerOutputInner<T>>>,
+        output: &wl_output::WlOutput,
+        info: &OutputInfo,
+    ) {
+        // an output is only announced once, but it may already have been listed when
+        // the manager was created
+        if info.obsolete || self.instances.iter().any(|i| &i.output == output) {
+            return;
+        }
+        let surface = crate::surface::setup_surface(
+            self.compositor.create_surface(),
+            None::<fn(_, _, DispatchData)>,
+        );
+        let me = me.clone();
+        let my_output = output.clone();
+        let layer_surface = LayerSurface::new(
+            &self.layer_shell,
+            surface,
+            Some(output),
+            self.layer,
+            self.namespace.clone(),
+            move |event, ddata| {
+                if let Some(inner) = me.upgrade() {
+                    PerOutputInner::dispatch(&inner, &my_output, event, ddata);
+                }
+            },
+        )
+        .expect("[SCTK] A new surface has no role");
+        let state = (self.factory)(output, info, &layer_surface);
+        self.instances.push(Instance {
+            output: output.clone(),
+            layer_surface: Some(layer_surface),
+            state,
+        });
+    }
+
+    fn remove_output(&mut self, output: &wl_output::WlOutput) {
+        self.instances.retain(|i| &i.output != output);
+    }
+
+    fn dispatch(
+        inner: &RefCell<PerOutputInner<T>>,
+        output: &wl_output::WlOutput,
+        event: Event,
+        ddata: DispatchData,
+    ) {
+        let closed = if let Event::Closed = event {
+            true
+        } else {
+            false
+        };
+        let mut inner = inner.borrow_mut();
+        let callback = inner.callback.clone();
+        if let Some(instance) = inner.instances.iter_mut().find(|i| &i.output == output) {
+            if let Some(ref layer_surface) = instance.layer_surface {
+                (&mut *callback.borrow_mut())(
+                    output,
+                    layer_surface,
+                    &mut instance.state,
+                    event,
+                    ddata,
+                );
+            }
+        }
+        if closed {
+            inner.remove_output(output);
+        }
+    }
+}
+
+/// A set of layer surfaces, one per output
+///
+/// This creates a layer surface on each output, and keeps the set up to date as outputs
+/// are added and removed: for each new output, a `wl_surface` and its layer surface are
+/// created, and a factory closure creates your state for it (your buffer pools, what is
+/// displayed on this output...). When an output is removed, or when the compositor closes
+/// its layer surface, your state is dropped and then the layer surface and its `wl_surface`
+/// are destroyed. An output that is unplugged and plugged back is advertised again as a
+/// new global, and thus gets a new layer surface.
+///
+/// The events of all the layer surfaces are given to a single callback, along with the
+/// output, the layer surface and your state for it. The callback receives the `Closed`
+/// event before the instance is dropped. It must not access this `PerOutputLayerSurfaces`,
+/// which would panic.
+///
+/// Dropping it destroys all the layer surfaces and stops tracking the outputs.
+pub struct PerOutputLayerSurfaces<T> {
+    inner: Rc<RefCell<PerOutputInner<T>>>,
+    _listener: OutputStatusListener,
+}
+
+impl<T: 'static> PerOutputLayerSurfaces<T> {
+    /// Create a layer surface on each current and future output
+    ///
+    /// The factory is invoked with the output, its information and the newly created layer
+    /// surface, which it needs to set up (size, anchor...) and commit without any buffer, as
+    /// described in [`LayerSurface::new`](struct.LayerSurface.html#method.new). It must not
+    /// access this `PerOutputLayerSurfaces`, nor the information of the outputs through
+    /// [`with_output_info`](../../output/fn.with_output_info.html), which would deadlock.
+    ///
+    /// *Panic*
+    ///
+    /// This function will panic if the compositor did not advertise the compositor or
+    /// the layer shell globals.
+    pub fn new<E, F, C>(
+        env: &Environment<E>,
+        layer: Layer,
+        namespace: String,
+        factory: F,
+        callback: C,
+    ) -> PerOutputLayerSurfaces<T>
+    where
+        E: GlobalHandler<wl_compositor::WlCompositor>
+            + GlobalHandler<zwlr_layer_shell_v1::ZwlrLayerShellV1>
+            + MultiGlobalHandler<wl_output::WlOutput>
+            + OutputHandling,
+        F: FnMut(&wl_output::WlOutput, &OutputInfo, &LayerSurface) -> T + 'static,
+        C: FnMut(&wl_output::WlOutput, &LayerSurface, &mut T, Event, DispatchData) + 'static,
+    {
+        let inner = Rc::new(RefCell::new(PerOutputInner {
+            compositor: env.require_global(),
+            layer_shell: env.require_global(),
+            layer,
+            namespace,
+            instances: Vec::new(),
+            factory: Box::new(factory),
+            callback: Rc::new(RefCell::new(callback)),
+        }));
+        let me = Rc::downgrade(&inner);
+        for output in env.get_all_outputs() {
+            // outputs not yet ready are announced to the listener once they are
+            let info = match with_output_info(&output, |info| info.clone()) {
+                Some(info) => info,
+                None => continue,
+            };
+            inner.borrow_mut().add_output(&me, &output, &info);
+        }
+        let listener = env.listen_for_outputs(move |output, info, _| {
+            if let Some(inner) = me.upgrade() {
+                let mut inner = inner.borrow_mut();
+                if info.obsolete {
+                    inner.remove_output(&output);
+                } else {
+                    inner.add_output(&me, &output, info);
+                }
+            }
+        });
+        PerOutputLayerSurfaces {
+            inner,
+            _listener: listener,
+        }
+    }
+
+    /// Access the layer surfaces and your state for each of them
+    pub fn with_instances<F>(&self, mut f: F)
+    where
+        F: FnMut(&wl_output::WlOutput, &LayerSurface, &mut T),
+    {
+        for instance in &mut self.inner.borrow_mut().instances {
+            if let Some(ref layer_surface) = instance.layer_surface {
+                f(&instance.output, layer_surface, &mut instance.state);
+            }
+        }
+    }
+
+    /// Access the layer surface and your state for this output
+    ///
+    /// Returns `None` if there is no layer surface for this output.
+    pub fn with_instance<R, F>(&self, output: &wl_output::WlOutput, f: F) -> Option<R>
+    where
+        F: FnOnce(&LayerSurface, &mut T) -> R,
+    {
+        let mut inner = self.inner.borrow_mut();
+        let instance = inner.instances.iter_mut().find(|i| &i.output == output)?;
+        let layer_surface = instance.layer_surface.as_ref()?;
+        Some(f(layer_surface, &mut instance.state))
+    }
+
+    /// The number of layer surfaces, which is the number of outputs they are displayed on
+    pub fn len(&self) -> usize {
+        self.inner.borrow().instances.len()
+    }
+
+    /// Whether there is no layer surface
+    pub fn is_empty(&self) -> bool {
+        self.inner.borrow().instances.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // top, right, bottom and left margins
+    const MARGIN: [i32; 4] = [1, 2, 3, 4];
+
+    fn auto(anchor: Anchor) -> i32 {
+        ExclusiveZone::Auto.compute(anchor, (100, 30), MARGIN)
+    }
+
+    #[test]
+    fn explicit_zones_ignore_the_geometry() {
+        for &anchor in &[Anchor::empty(), Anchor::Top, Anchor::all()] {
+            assert_eq!(
+                ExclusiveZone::Fixed(42).compute(anchor, (100, 30), MARGIN),
+                42
+            );
+            assert_eq!(
+                ExclusiveZone::DontCare.compute(anchor, (100, 30), MARGIN),
+                -1
+            );
+            assert_eq!(ExclusiveZone::Ignore.compute(anchor, (100, 30), MARGIN), 0);
+        }
+    }
+
+    #[test]
+    fn auto_zone_of_an_edge() {
+        // the height plus the margin facing the rest of the output
+        assert_eq!(auto(Anchor::Top), 30 + 3);
+        assert_eq!(auto(Anchor::Bottom), 30 + 1);
+        // the width for vertical edges
+        assert_eq!(auto(Anchor::Left), 100 + 2);
+        assert_eq!(auto(Anchor::Right), 100 + 4);
+    }
+
+    #[test]
+    fn auto_zone_of_an_edge_stretched_along_it() {
+        let horizontal = Anchor::Left | Anchor::Right;
+        let vertical = Anchor::Top | Anchor::Bottom;
+        assert_eq!(auto(Anchor::Top | horizontal), 30 + 3);
+        assert_eq!(auto(Anchor::Bottom | horizontal), 30 + 1);
+        assert_eq!(auto(Anchor::Left | vertical), 100 + 2);
+        assert_eq!(auto(Anchor::Right | vertical), 100 + 4);
+    }
+
+    #[test]
+    fn auto_zone_without_a_single_edge() {
+        assert_eq!(auto(Anchor::empty()), 0);
+        assert_eq!(auto(Anchor::all()), 0);
+        // corners
+        assert_eq!(auto(Anchor::Top | Anchor::Left), 0);
+        assert_eq!(auto(Anchor::Bottom | Anchor::Right), 0);
+        // centered along an axis
+        assert_eq!(auto(Anchor::Top | Anchor::Bottom), 0);
+        assert_eq!(auto(Anchor::Left | Anchor::Right), 0);
+    }
+
+    #[test]
+    fn auto_zone_is_never_negative() {
+        let zone = ExclusiveZone::Auto.compute(Anchor::Top, (100, 30), [0, 0, -50, 0]);
+        assert_eq!(zone, 0);
+        // a zone of -1 would be mistaken for `DontCare`
+        let zone = ExclusiveZone::Auto.compute(Anchor::Top, (100, 30), [0, 0, -31, 0]);
+        assert_eq!(zone, 0);
+        let zone = ExclusiveZone::Auto.compute(Anchor::Left, (0, 0), [0, 0, 0, 0]);
+        assert_eq!(zone, 0);
+    }
+}
@@ -17,11 +17,13 @@ use wayland_client::{
 pub use wayland_protocols::xdg_shell::client::xdg_toplevel::State;
 use wayland_protocols::{
     unstable::xdg_shell::v6::client::zxdg_shell_v6,
-    xdg_shell::client::{xdg_toplevel, xdg_wm_base},
+    xdg_shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base},
 };
 
 use crate::environment::{Environment, GlobalHandler};
 
+pub mod layer;
+pub mod popup;
 mod wl;
 mod xdg;
 mod zxdg;
@@ -109,6 +111,12 @@ pub trait ShellSurface: Send + Sync {
     fn resize(&self, seat: &wl_seat::WlSeat, serial: u32, edges: xdg_toplevel::ResizeEdge);
     /// Moves the shell surface
     fn move_(&self, seat: &wl_seat::WlSeat, serial: u32);
+    /// Shows the window menu of the compositor at a position relative to the window geometry
+    ///
+    /// This does nothing with the `wl_shell` protocol, which has no window menu.
+    fn show_window_menu(&self, seat: &wl_seat::WlSeat, serial: u32, x: i32, y: i32) {
+        let _ = (seat, serial, x, y);
+    }
     /// Set the title of the shell surface
     fn set_title(&self, title: String);
     /// Set the app id of the shell surface
@@ -135,6 +143,13 @@ pub trait ShellSurface: Send + Sync {
     /// This allows interactions with other protocol extensions, like
     /// `xdg_decoratins` for example.
     fn get_xdg(&self) -> Option<&xdg_toplevel::XdgToplevel>;
+    /// Retrieve the `XdgSurface` proxy if the underlying shell surface
+    /// uses the `xdg_shell` protocol.
+    ///
+    /// This is notably needed to create popups parented to this surface.
+    fn get_xdg_surface(&self) -> Option<&xdg_surface::XdgSurface> {
+        None
+    }
 }
 
 struct ShellInner {
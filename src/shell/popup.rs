@@ -0,0 +1,218 @@
+//! Popup surfaces
+//!
+//! This module provides a wrapper around `xdg_popup`, used for menus, tooltips and
+//! other transient surfaces. A popup can either be parented to an `xdg_surface`
+//! (a window or another popup) or to a [`LayerSurface`](../layer/struct.LayerSurface.html).
+//!
+//! The position of the popup is described by an `xdg_positioner`, whose anchor rectangle
+//! is expressed in the surface-local coordinates of the parent surface, whatever its kind.
+use std::{cell::RefCell, rc::Rc};
+
+use wayland_client::{
+    protocol::{wl_seat, wl_surface},
+    Attached, DispatchData, Main,
+};
+
+use wayland_protocols::xdg_shell::client::{xdg_popup, xdg_positioner, xdg_surface, xdg_wm_base};
+
+use super::{layer::LayerSurface, Shell, ShellHandling};
+use crate::{
+    environment::Environment,
+    surface::{assign_role, RoleConflict, SurfaceRole},
+};
+
+/// Possible events generated by a popup
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// The compositor has configured the popup
+    ///
+    /// The configure has already been acknowledged, you need to draw your popup at
+    /// the given size and commit it.
+    Configure {
+        /// Position of the popup relative to its parent surface
+        position: (i32, i32),
+        /// Size of the popup
+        size: (u32, u32),
+    },
+    /// The popup has been dismissed by the compositor
+    ///
+    /// This typically happens when the user clicked outside of the popup holding a
+    /// grab. The popup will not be displayed again, and you should drop it.
+    Done,
+}
+
+/// The parent of a popup
+#[derive(Copy, Clone)]
+pub enum PopupParent<'a> {
+    /// An `xdg_surface`, like a toplevel window or another popup
+    Xdg(&'a xdg_surface::XdgSurface),
+    /// A layer surface
+    Layer(&'a LayerSurface),
+}
+
+impl<'a> From<&'a xdg_surface::XdgSurface> for PopupParent<'a> {
+    fn from(surface: &'a xdg_surface::XdgSurface) -> PopupParent<'a> {
+        PopupParent::Xdg(surface)
+    }
+}
+
+impl<'a> From<&'a LayerSurface> for PopupParent<'a> {
+    fn from(surface: &'a LayerSurface) -> PopupParent<'a> {
+        PopupParent::Layer(surface)
+    }
+}
+
+impl<'a> From<&'a Popup> for PopupParent<'a> {
+    fn from(popup: &'a Popup) -> PopupParent<'a> {
+        PopupParent::Xdg(&popup.xdg_surface)
+    }
+}
+
+/// A popup surface
+///
+/// The popup is destroyed when this handle is dropped, the `wl_surface` is not.
+/// If this popup is the parent of other popups, they must be dropped first.
+pub struct Popup {
+    surface: wl_surface::WlSurface,
+    xdg_surface: xdg_surface::XdgSurface,
+    popup: xdg_popup::XdgPopup,
+}
+
+impl Popup {
+    /// Create a new popup for this `wl_surface`
+    ///
+    /// The surface must not have any role nor any buffer attached. It is committed by
+    /// this function to request the initial configure from the compositor, you must
+    /// not attach a buffer before having received the first `Configure` event.
+    ///
+    /// If `grab` is provided, the popup takes an explicit grab on this seat, using the
+    /// serial of the input event that triggered the popup (typically the click on the
+    /// menu button). The compositor will then dismiss it when the user clicks outside
+    /// of the popup. Nested popups of a grabbing popup must grab as well.
+    ///
+    /// Fails if the surface was given another role by SCTK.
+    pub fn new<'a, P, F>(
+        shell: &Attached<xdg_wm_base::XdgWmBase>,
+        surface: wl_surface::WlSurface,
+        parent: P,
+        positioner: &xdg_positioner::XdgPositioner,
+        grab: Option<(&wl_seat::WlSeat, u32)>,
+        callback: F,
+    ) -> Result<Popup, RoleConflict>
+    where
+        P: Into<PopupParent<'a>>,
+        F: FnMut(Event, DispatchData) + 'static,
+    {
+        assign_role(&surface, SurfaceRole::Popup)?;
+        let pending_configure = Rc::new(RefCell::new(None));
+        let pending_configure_2 = pending_configure.clone();
+
+        let implementation = Rc::new(RefCell::new(callback));
+        let implementation_2 = implementation.clone();
+        let xdgs = shell.get_xdg_surface(&surface);
+        xdgs.quick_assign(move |xdgs, evt, ddata| match evt {
+            xdg_surface::Event::Configure { serial } => {
+                xdgs.ack_configure(serial);
+                if let Some((position, size)) = pending_configure_2.borrow_mut().take() {
+                    (*implementation_2.borrow_mut())(Event::Configure { position, size }, ddata);
+                }
+            }
+            _ => unreachable!(),
+        });
+
+        let popup = match parent.into() {
+            PopupParent::Xdg(parent) => xdgs.get_popup(Some(parent), positioner),
+            PopupParent::Layer(parent) => {
+                let popup = xdgs.get_popup(None, positioner);
+                parent.layer_surface().get_popup(&popup);
+                popup
+            }
+        };
+        popup.quick_assign(move |_, evt, ddata| match evt {
+            xdg_popup::Event::Configure {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                use std::cmp::max;
+                *pending_configure.borrow_mut() =
+                    Some(((x, y), (max(width, 1) as u32, max(height, 1) as u32)));
+            }
+            xdg_popup::Event::PopupDone => (*implementation.borrow_mut())(Event::Done, ddata),
+            _ => unreachable!(),
+        });
+
+        if let Some((seat, serial)) = grab {
+            popup.grab(seat, serial);
+        }
+        surface.commit();
+
+        Ok(Popup {
+            surface,
+            xdg_surface: xdgs.detach(),
+            popup: popup.detach(),
+        })
+    }
+
+    /// Access the underlying `wl_surface`
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    /// Access the underlying `xdg_surface`
+    pub fn xdg_surface(&self) -> &xdg_surface::XdgSurface {
+        &self.xdg_surface
+    }
+
+    /// Access the underlying `xdg_popup`
+    pub fn xdg_popup(&self) -> &xdg_popup::XdgPopup {
+        &self.popup
+    }
+}
+
+impl Drop for Popup {
+    fn drop(&mut self) {
+        self.popup.destroy();
+        self.xdg_surface.destroy();
+    }
+}
+
+impl<E: ShellHandling> Environment<E> {
+    /// Create a new positioner, to describe the placement of a popup
+    ///
+    /// Returns `None` if the compositor does not support `xdg_shell`.
+    pub fn create_positioner(&self) -> Option<Main<xdg_positioner::XdgPositioner>> {
+        match self.get_shell() {
+            Some(Shell::Xdg(shell)) => Some(shell.create_positioner()),
+            _ => None,
+        }
+    }
+
+    /// Create a new popup for this surface
+    ///
+    /// See [`Popup::new`](../shell/popup/struct.Popup.html#method.new) for details.
+    ///
+    /// Returns `None` if the compositor does not support `xdg_shell`, which is the
+    /// only shell protocol with popup support, and an error if the surface was given
+    /// another role by SCTK.
+    pub fn create_popup<'a, P, F>(
+        &self,
+        surface: wl_surface::WlSurface,
+        parent: P,
+        positioner: &xdg_positioner::XdgPositioner,
+        grab: Option<(&wl_seat::WlSeat, u32)>,
+        callback: F,
+    ) -> Result<Option<Popup>, RoleConflict>
+    where
+        P: Into<PopupParent<'a>>,
+        F: FnMut(Event, DispatchData) + 'static,
+    {
+        match self.get_shell() {
+            Some(Shell::Xdg(shell)) => {
+                Popup::new(&shell, surface, parent, positioner, grab, callback).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
@@ -89,6 +89,10 @@ impl ShellSurface for Xdg {
         self.toplevel._move(seat, serial);
     }
 
+    fn show_window_menu(&self, seat: &wl_seat::WlSeat, serial: u32, x: i32, y: i32) {
+        self.toplevel.show_window_menu(seat, serial, x, y);
+    }
+
     fn set_title(&self, title: String) {
         self.toplevel.set_title(title);
     }
@@ -140,6 +144,10 @@ impl ShellSurface for Xdg {
     fn get_xdg(&self) -> Option<&xdg_toplevel::XdgToplevel> {
         Some(&self.toplevel)
     }
+
+    fn get_xdg_surface(&self) -> Option<&xdg_surface::XdgSurface> {
+        Some(&self.surface)
+    }
 }
 
 impl Drop for Xdg {
@@ -148,3 +156,152 @@ impl Drop for Xdg {
         self.surface.destroy();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{array, count, TestClient};
+    use wayland_client::{protocol::wl_compositor, Main};
+
+    // opcodes of the requests and events
+    const XDG_SURFACE_ACK_CONFIGURE: u16 = 4;
+    const XDG_SURFACE_CONFIGURE: u16 = 0;
+    const XDG_TOPLEVEL_CONFIGURE: u16 = 0;
+    const XDG_TOPLEVEL_CLOSE: u16 = 1;
+    // values of the toplevel states
+    const MAXIMIZED: u32 = 1;
+    const ACTIVATED: u32 = 4;
+
+    struct Setup {
+        client: TestClient,
+        xdg: Xdg,
+        events: Rc<RefCell<Vec<Event>>>,
+    }
+
+    fn setup() -> Setup {
+        let mut client = TestClient::new();
+        let compositor = client.bind::<wl_compositor::WlCompositor>(4);
+        let shell: Main<xdg_wm_base::XdgWmBase> = client.bind(1);
+        let surface = compositor.create_surface();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let my_events = events.clone();
+        let xdg = Xdg::create(&surface, &shell, move |event, _| {
+            my_events.borrow_mut().push(event)
+        });
+        client.requests();
+        Setup {
+            client,
+            xdg,
+            events,
+        }
+    }
+
+    fn toplevel_configure(setup: &mut Setup, width: u32, height: u32, states: &[u32]) {
+        let mut args = vec![width, height];
+        args.extend(array(states));
+        setup
+            .client
+            .send(setup.xdg.toplevel.as_ref(), XDG_TOPLEVEL_CONFIGURE, &args);
+    }
+
+    fn configured(event: &Event) -> (Option<(u32, u32)>, &[xdg_toplevel::State]) {
+        match *event {
+            Event::Configure {
+                new_size,
+                ref states,
+            } => (new_size, states),
+            Event::Close => panic!("unexpected close event"),
+        }
+    }
+
+    #[test]
+    fn configure_is_acked_and_delivered_on_the_surface_configure() {
+        let mut setup = setup();
+        toplevel_configure(&mut setup, 800, 600, &[MAXIMIZED, ACTIVATED]);
+        setup.client.dispatch();
+        // the toplevel configure is only applied with the surface configure
+        assert!(setup.events.borrow().is_empty());
+        assert_eq!(setup.client.requests(), vec![]);
+
+        setup
+            .client
+            .send(setup.xdg.surface.as_ref(), XDG_SURFACE_CONFIGURE, &[42]);
+        setup.client.dispatch();
+        let requests = setup.client.requests();
+        assert_eq!(
+            count(
+                &requests,
+                setup.xdg.surface.as_ref(),
+                XDG_SURFACE_ACK_CONFIGURE
+            ),
+            1
+        );
+        assert_eq!(requests[0].args, vec![42]);
+        let events = setup.events.borrow();
+        assert_eq!(events.len(), 1);
+        let (new_size, states) = configured(&events[0]);
+        assert_eq!(new_size, Some((800, 600)));
+        assert_eq!(
+            states,
+            &[
+                xdg_toplevel::State::Maximized,
+                xdg_toplevel::State::Activated
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_latest_toplevel_configure_is_delivered() {
+        let mut setup = setup();
+        toplevel_configure(&mut setup, 800, 600, &[]);
+        toplevel_configure(&mut setup, 0, 0, &[ACTIVATED]);
+        setup
+            .client
+            .send(setup.xdg.surface.as_ref(), XDG_SURFACE_CONFIGURE, &[1]);
+        setup.client.dispatch();
+        let events = setup.events.borrow();
+        assert_eq!(events.len(), 1);
+        // a size of zero lets the client choose
+        let (new_size, states) = configured(&events[0]);
+        assert_eq!(new_size, None);
+        assert_eq!(states, &[xdg_toplevel::State::Activated]);
+    }
+
+    #[test]
+    fn every_surface_configure_is_acked() {
+        let mut setup = setup();
+        toplevel_configure(&mut setup, 800, 600, &[]);
+        for serial in 1..4 {
+            setup
+                .client
+                .send(setup.xdg.surface.as_ref(), XDG_SURFACE_CONFIGURE, &[serial]);
+        }
+        setup.client.dispatch();
+        let serials = setup
+            .client
+            .requests()
+            .iter()
+            .filter(|r| r.opcode == XDG_SURFACE_ACK_CONFIGURE)
+            .map(|r| r.args[0])
+            .collect::<Vec<_>>();
+        assert_eq!(serials, vec![1, 2, 3]);
+        // the configure of the toplevel is not delivered again
+        assert_eq!(setup.events.borrow().len(), 1);
+    }
+
+    #[test]
+    fn close_is_delivered() {
+        let mut setup = setup();
+        setup
+            .client
+            .send(setup.xdg.toplevel.as_ref(), XDG_TOPLEVEL_CLOSE, &[]);
+        setup.client.dispatch();
+        let events = setup.events.borrow();
+        assert_eq!(events.len(), 1);
+        assert!(match events[0] {
+            Event::Close => true,
+            _ => false,
+        });
+        assert_eq!(setup.client.requests(), vec![]);
+    }
+}
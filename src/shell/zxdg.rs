@@ -92,6 +92,10 @@ impl ShellSurface for Zxdg {
         self.toplevel._move(seat, serial);
     }
 
+    fn show_window_menu(&self, seat: &wl_seat::WlSeat, serial: u32, x: i32, y: i32) {
+        self.toplevel.show_window_menu(seat, serial, x, y);
+    }
+
     fn set_title(&self, title: String) {
         self.toplevel.set_title(title);
     }
@@ -0,0 +1,367 @@
+use std::io;
+
+use wayland_client::protocol::wl_surface;
+
+use super::MemPool;
+
+/// A rectangle of a buffer, in buffer pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    /// Horizontal position of the left edge
+    pub x: u32,
+    /// Vertical position of the top edge
+    pub y: u32,
+    /// Width of the rectangle
+    pub width: u32,
+    /// Height of the rectangle
+    pub height: u32,
+}
+
+impl Rect {
+    /// Create a new rectangle
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Whether the rectangle does not contain any pixel
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Damage this rectangle of the buffer attached to a surface
+    ///
+    /// This is `wl_surface::damage_buffer`, which requires version 4 of the compositor.
+    pub fn damage_buffer(&self, surface: &wl_surface::WlSurface) {
+        surface.damage_buffer(
+            self.x as i32,
+            self.y as i32,
+            self.width as i32,
+            self.height as i32,
+        );
+    }
+
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let x = std::cmp::min(self.x, other.x);
+        let y = std::cmp::min(self.y, other.y);
+        Rect {
+            x,
+            y,
+            width: std::cmp::max(self.right(), other.right()) - x,
+            height: std::cmp::max(self.bottom(), other.bottom()) - y,
+        }
+    }
+
+    // clip the rectangle to a buffer of this size
+    fn clip(&self, (width, height): (u32, u32)) -> Rect {
+        let x = std::cmp::min(self.x, width);
+        let y = std::cmp::min(self.y, height);
+        Rect {
+            x,
+            y,
+            width: std::cmp::min(self.width, width - x),
+            height: std::cmp::min(self.height, height - y),
+        }
+    }
+}
+
+// Clip the regions to the buffer and merge the overlapping ones, as long as their bounding
+// box is not larger than the two regions, so that no pixel is copied twice without making
+// the copy larger
+fn coalesce(regions: &[Rect], size: (u32, u32)) -> Vec<Rect> {
+    let mut rects: Vec<Rect> = regions
+        .iter()
+        .map(|r| r.clip(size))
+        .filter(|r| !r.is_empty())
+        .collect();
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'outer: for i in 0..rects.len() {
+            for j in (i + 1)..rects.len() {
+                if !rects[i].intersects(&rects[j]) {
+                    continue;
+                }
+                let union = rects[i].union(&rects[j]);
+                if union.area() <= rects[i].area() + rects[j].area() {
+                    rects[i] = union;
+                    rects.swap_remove(j);
+                    merged = true;
+                    break 'outer;
+                }
+            }
+        }
+    }
+    rects
+}
+
+/// Copy the damaged regions of an image to a buffer
+///
+/// `src` and `dst` contain images of the given size in a 32 bits format (like the `Argb8888`
+/// and `Xrgb8888` formats of `wl_shm`), with the given strides. Only the pixels of the damaged
+/// regions are copied, which is much cheaper than copying the whole image when only a small
+/// part of it changed, like a blinking cursor.
+///
+/// The regions are clipped to the image, and overlapping regions are merged when that does not
+/// make the copy larger. The regions actually copied are returned, they can be given to
+/// [`Rect::damage_buffer`](struct.Rect.html#method.damage_buffer) when committing the buffer.
+///
+/// Returns an error if `src` or `dst` is too small for the image, or if a stride is smaller
+/// than a row.
+pub fn copy_damaged(
+    src: &[u8],
+    src_stride: usize,
+    dst: &mut [u8],
+    dst_stride: usize,
+    size: (u32, u32),
+    regions: &[Rect],
+) -> io::Result<Vec<Rect>> {
+    let (width, height) = size;
+    if width == 0 || height == 0 {
+        return Ok(Vec::new());
+    }
+    let row_len = width as usize * 4;
+    let needed = |stride: usize| stride * (height as usize - 1) + row_len;
+    if src_stride < row_len
+        || dst_stride < row_len
+        || src.len() < needed(src_stride)
+        || dst.len() < needed(dst_stride)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the buffers are too small for the image",
+        ));
+    }
+    let rects = coalesce(regions, size);
+    for rect in &rects {
+        let start = rect.x as usize * 4;
+        let end = rect.right() as usize * 4;
+        if rect.width == width && src_stride == dst_stride {
+            // full rows are contiguous, they can be copied at once
+            let src_start = rect.y as usize * src_stride;
+            let len = (rect.height as usize - 1) * src_stride + row_len;
+            dst[src_start..src_start + len].copy_from_slice(&src[src_start..src_start + len]);
+            continue;
+        }
+        for row in rect.y as usize..rect.bottom() as usize {
+            let s = row * src_stride;
+            let d = row * dst_stride;
+            dst[d + start..d + end].copy_from_slice(&src[s + start..s + end]);
+        }
+    }
+    Ok(rects)
+}
+
+impl MemPool {
+    /// Copy the damaged regions of an image to the buffer at `offset` in the pool
+    ///
+    /// The buffer has the given size and stride, in a 32 bits format. See
+    /// [`copy_damaged`](fn.copy_damaged.html) for the meaning of the other parameters.
+    ///
+    /// Returns the regions actually copied, or an error if the buffer does not fit in the pool.
+    pub fn copy_damaged(
+        &mut self,
+        offset: usize,
+        stride: usize,
+        size: (u32, u32),
+        src: &[u8],
+        src_stride: usize,
+        regions: &[Rect],
+    ) -> io::Result<Vec<Rect>> {
+        let len = stride * size.1 as usize;
+        let mmap = self.mmap();
+        if offset + len > mmap.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the buffer does not fit in the pool",
+            ));
+        }
+        copy_damaged(
+            src,
+            src_stride,
+            &mut mmap[offset..offset + len],
+            stride,
+            size,
+            regions,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: (u32, u32) = (8, 6);
+
+    // An image whose pixels all differ, with `stride - width * 4` bytes of padding
+    fn image(stride: usize) -> Vec<u8> {
+        let mut data = vec![0xaa; stride * SIZE.1 as usize];
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                let pos = y as usize * stride + x as usize * 4;
+                let value = y * SIZE.0 + x + 1;
+                data[pos..pos + 4].copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+        data
+    }
+
+    fn pixel(data: &[u8], stride: usize, x: u32, y: u32) -> u32 {
+        let pos = y as usize * stride + x as usize * 4;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[pos..pos + 4]);
+        u32::from_ne_bytes(bytes)
+    }
+
+    // Check that exactly the pixels of the rectangles were copied
+    fn check_copied(dst: &[u8], stride: usize, rects: &[Rect]) {
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                let inside = rects
+                    .iter()
+                    .any(|r| x >= r.x && x < r.right() && y >= r.y && y < r.bottom());
+                let expected = if inside { y * SIZE.0 + x + 1 } else { 0 };
+                assert_eq!(pixel(dst, stride, x, y), expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn only_damaged_pixels_are_copied() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        let regions = [Rect::new(1, 1, 2, 2), Rect::new(5, 3, 3, 1)];
+        let copied = copy_damaged(&src, 32, &mut dst, 32, SIZE, &regions).unwrap();
+        assert_eq!(copied.len(), 2);
+        check_copied(&dst, 32, &regions);
+    }
+
+    #[test]
+    fn regions_are_clipped_to_the_image() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        let regions = [
+            Rect::new(6, 4, 10, 10),
+            Rect::new(100, 0, 1, 1),
+            Rect::new(0, 6, 8, 1),
+            Rect::new(2, 0, std::u32::MAX, 1),
+        ];
+        let copied = copy_damaged(&src, 32, &mut dst, 32, SIZE, &regions).unwrap();
+        assert_eq!(copied, vec![Rect::new(6, 4, 2, 2), Rect::new(2, 0, 6, 1)]);
+        check_copied(&dst, 32, &copied);
+    }
+
+    #[test]
+    fn empty_regions_copy_nothing() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        let regions = [Rect::new(1, 1, 0, 5), Rect::new(1, 1, 5, 0)];
+        let copied = copy_damaged(&src, 32, &mut dst, 32, SIZE, &regions).unwrap();
+        assert!(copied.is_empty());
+        assert!(dst.iter().all(|&b| b == 0));
+        assert!(copy_damaged(&src, 32, &mut dst, 32, SIZE, &[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn overlapping_regions_are_merged_when_not_larger() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        // a region inside another, and two regions overlapping along a whole side
+        let regions = [
+            Rect::new(0, 0, 4, 4),
+            Rect::new(1, 1, 2, 2),
+            Rect::new(5, 0, 2, 3),
+            Rect::new(5, 2, 2, 3),
+        ];
+        let copied = copy_damaged(&src, 32, &mut dst, 32, SIZE, &regions).unwrap();
+        assert_eq!(copied, vec![Rect::new(0, 0, 4, 4), Rect::new(5, 0, 2, 5)]);
+        check_copied(&dst, 32, &copied);
+    }
+
+    #[test]
+    fn overlapping_regions_are_kept_when_merging_is_larger() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        // the bounding box of this cross covers its corners too
+        let regions = [Rect::new(3, 0, 2, 6), Rect::new(0, 2, 8, 2)];
+        let copied = copy_damaged(&src, 32, &mut dst, 32, SIZE, &regions).unwrap();
+        assert_eq!(copied, regions.to_vec());
+        check_copied(&dst, 32, &regions);
+    }
+
+    #[test]
+    fn different_strides() {
+        let src = image(40);
+        let mut dst = vec![0; 36 * 6];
+        let regions = [Rect::new(0, 1, 8, 2), Rect::new(3, 4, 2, 2)];
+        copy_damaged(&src, 40, &mut dst, 36, SIZE, &regions).unwrap();
+        check_copied(&dst, 36, &regions);
+        // the padding of the destination is left untouched
+        for y in 0..6 {
+            assert_eq!(&dst[y * 36 + 32..y * 36 + 36], &[0; 4]);
+        }
+    }
+
+    #[test]
+    fn full_rows() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        let regions = [Rect::new(0, 2, 8, 3)];
+        copy_damaged(&src, 32, &mut dst, 32, SIZE, &regions).unwrap();
+        check_copied(&dst, 32, &regions);
+    }
+
+    #[test]
+    fn last_row_without_padding() {
+        let mut src = image(40);
+        src.truncate(40 * 5 + 32);
+        let mut dst = vec![0; 40 * 5 + 32];
+        let regions = [Rect::new(0, 0, 8, 6)];
+        copy_damaged(&src, 40, &mut dst, 40, SIZE, &regions).unwrap();
+        check_copied(&dst, 40, &regions);
+    }
+
+    #[test]
+    fn invalid_buffers() {
+        let src = image(32);
+        let mut dst = vec![0; 32 * 6];
+        let regions = [Rect::new(0, 0, 1, 1)];
+        // stride smaller than a row
+        assert!(copy_damaged(&src, 28, &mut dst, 32, SIZE, &regions).is_err());
+        assert!(copy_damaged(&src, 32, &mut dst, 28, SIZE, &regions).is_err());
+        // buffers too small
+        assert!(copy_damaged(&src[..32 * 5], 32, &mut dst, 32, SIZE, &regions).is_err());
+        assert!(copy_damaged(&src, 32, &mut dst[..32 * 6 - 1], 32, SIZE, &regions).is_err());
+        assert!(dst.iter().all(|&b| b == 0));
+        // nothing to copy for an empty image
+        assert_eq!(
+            copy_damaged(&[], 0, &mut [], 0, (0, 6), &regions).unwrap(),
+            vec![]
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::{
     ffi::CStr,
     fs::File,
     io,
-    os::unix::io::{FromRawFd, RawFd},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
     rc::Rc,
     time::SystemTime,
     time::UNIX_EPOCH,
@@ -42,6 +42,8 @@ pub struct DoubleMemPool {
 
 impl DoubleMemPool {
     /// Create a double memory pool
+    ///
+    /// The pools and their buffers are assigned to the event queue `shm` is attached to.
     pub fn new<F>(shm: Attached<wl_shm::WlShm>, callback: F) -> io::Result<DoubleMemPool>
     where
         F: FnMut(wayland_client::DispatchData) + 'static,
@@ -115,6 +117,11 @@ impl DoubleMemPool {
 ///
 /// Mempool requires a callback that will be called when the pool becomes free, this
 /// happens when all the pools buffers are released by the server.
+///
+/// If the `wl_shm` global was bound by a [`ShmHandler`](struct.ShmHandler.html), the shared
+/// memory files of the dropped pools are kept in a small cache, and reused by the next pools
+/// instead of creating new ones. A file is only reused once all the buffers created from its
+/// pool were released. A reused pool starts with the size and the contents the file had.
 pub struct MemPool {
     file: File,
     len: usize,
@@ -122,29 +129,45 @@ pub struct MemPool {
     buffer_count: Rc<RefCell<u32>>,
     mmap: MmapMut,
     callback: Rc<RefCell<dyn FnMut(wayland_client::DispatchData)>>,
+    cache: Option<FileCache>,
+    // the file of the dropped pool, waiting for its buffers to be released
+    retired: Rc<RefCell<Option<(File, usize, FileCache)>>>,
+    format: wl_shm::Format,
 }
 
 impl MemPool {
     /// Create a new memory pool associated with given shm
+    ///
+    /// The pool and its buffers are assigned to the event queue `shm` is attached to,
+    /// which receives the release events of the buffers and thus invokes the callback.
     pub fn new<F>(shm: Attached<wl_shm::WlShm>, callback: F) -> io::Result<MemPool>
     where
         F: FnMut(wayland_client::DispatchData) + 'static,
     {
-        let mem_fd = create_shm_fd()?;
-        let mem_file = unsafe { File::from_raw_fd(mem_fd) };
-        mem_file.set_len(128)?;
+        let cache = FileCache::of_shm(&shm);
+        let (mem_file, len) = match cache.as_ref().and_then(FileCache::take) {
+            Some(cached) => cached,
+            None => {
+                let mem_file = unsafe { File::from_raw_fd(create_shm_fd()?) };
+                mem_file.set_len(128)?;
+                (mem_file, 128)
+            }
+        };
 
-        let pool = shm.create_pool(mem_fd, 128);
+        let pool = shm.create_pool(mem_file.as_raw_fd(), len as i32);
 
         let mmap = unsafe { MmapMut::map_mut(&mem_file).unwrap() };
 
         Ok(MemPool {
             file: mem_file,
-            len: 128,
+            len,
             pool,
             buffer_count: Rc::new(RefCell::new(0)),
             mmap,
             callback: Rc::new(RefCell::new(callback)),
+            cache,
+            retired: Rc::new(RefCell::new(None)),
+            format: wl_shm::Format::Argb8888,
         })
     }
 
@@ -161,6 +184,11 @@ impl MemPool {
     /// hold what you want to write to it.
     pub fn resize(&mut self, newsize: usize) -> io::Result<()> {
         if newsize > self.len {
+            log::trace!(
+                "[SCTK] Resizing memory pool from {} to {} bytes",
+                self.len,
+                newsize
+            );
             self.file.set_len(newsize as u64)?;
             self.pool.resize(newsize as i32);
             self.len = newsize;
@@ -169,6 +197,25 @@ impl MemPool {
         Ok(())
     }
 
+    /// Set the format of the buffers created from pixels by this pool
+    ///
+    /// This is the format used by [`buffer_from_pixels`](#method.buffer_from_pixels), which
+    /// writes the pixels in the byte order of both `Argb8888` and `Xrgb8888`, the latter
+    /// letting the compositor skip blending for opaque images. See
+    /// [`preferred_format`](fn.preferred_format.html) to choose it. Other formats are refused
+    /// by `buffer_from_pixels`.
+    ///
+    /// The format of each buffer created with [`buffer`](#method.buffer) is given to it
+    /// instead. The default is `Argb8888`.
+    pub fn set_format(&mut self, format: wl_shm::Format) {
+        self.format = format;
+    }
+
+    /// The format of the buffers created from pixels by this pool
+    pub fn format(&self) -> wl_shm::Format {
+        self.format
+    }
+
     /// Create a new buffer to this pool
     ///
     /// The parameters are:
@@ -192,6 +239,7 @@ impl MemPool {
         *self.buffer_count.borrow_mut() += 1;
         let my_buffer_count = self.buffer_count.clone();
         let my_callback = self.callback.clone();
+        let my_retired = self.retired.clone();
         let buffer = self
             .pool
             .create_buffer(offset, width, height, stride, format);
@@ -206,6 +254,10 @@ impl MemPool {
                     *my_buffer_count
                 };
                 if new_count == 0 {
+                    // the compositor no longer uses the file of a dropped pool
+                    if let Some((file, len, cache)) = my_retired.borrow_mut().take() {
+                        cache.put(file, len);
+                    }
                     (&mut *my_callback.borrow_mut())(dispatch_data);
                 }
             }
@@ -228,6 +280,79 @@ impl MemPool {
 impl Drop for MemPool {
     fn drop(&mut self) {
         self.pool.destroy();
+        let cache = match self.cache.take() {
+            Some(cache) => cache,
+            None => return,
+        };
+        if let Ok(file) = self.file.try_clone() {
+            if self.is_used() {
+                *self.retired.borrow_mut() = Some((file, self.len, cache));
+            } else {
+                cache.put(file, self.len);
+            }
+        }
+    }
+}
+
+/// The default maximum number of files kept by the cache of a `ShmHandler`
+pub(crate) const CACHE_MAX_FILES: usize = 4;
+/// The default maximum total size of the files kept by the cache of a `ShmHandler`
+pub(crate) const CACHE_MAX_BYTES: usize = 32 * 1024 * 1024;
+
+struct FileCacheInner {
+    // the oldest files first
+    files: Vec<(File, usize)>,
+    max_files: usize,
+    max_bytes: usize,
+}
+
+// The shared memory files of the dropped pools, stored in the user data of the `wl_shm`
+// they were created from
+#[derive(Clone)]
+pub(crate) struct FileCache {
+    inner: Rc<RefCell<FileCacheInner>>,
+}
+
+impl FileCache {
+    pub(crate) fn new(max_files: usize, max_bytes: usize) -> FileCache {
+        FileCache {
+            inner: Rc::new(RefCell::new(FileCacheInner {
+                files: Vec::new(),
+                max_files,
+                max_bytes,
+            })),
+        }
+    }
+
+    // the user data is only accessible from the thread which bound the global, the pools
+    // created from other threads do not use the cache
+    fn of_shm(shm: &wl_shm::WlShm) -> Option<FileCache> {
+        shm.as_ref()
+            .user_data()
+            .get::<super::ShmData>()
+            .map(|data| data.cache.clone())
+    }
+
+    // The largest file, the pools can only grow
+    fn take(&self) -> Option<(File, usize)> {
+        let mut inner = self.inner.borrow_mut();
+        let index = (0..inner.files.len()).max_by_key(|&i| inner.files[i].1)?;
+        Some(inner.files.remove(index))
+    }
+
+    fn put(&self, file: File, len: usize) {
+        let mut inner = self.inner.borrow_mut();
+        inner.files.push((file, len));
+        // forget the oldest files beyond the limits
+        while inner.files.len() > inner.max_files
+            || inner.files.iter().map(|&(_, len)| len).sum::<usize>() > inner.max_bytes
+        {
+            inner.files.remove(0);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.inner.borrow_mut().files.clear();
     }
 }
 
@@ -246,7 +371,7 @@ impl io::Seek for MemPool {
     }
 }
 
-fn create_shm_fd() -> io::Result<RawFd> {
+pub(crate) fn create_shm_fd() -> io::Result<RawFd> {
     // Only try memfd on linux
     #[cfg(target_os = "linux")]
     loop {
@@ -305,6 +430,20 @@ impl<E> crate::environment::Environment<E>
 where
     E: crate::environment::GlobalHandler<wl_shm::WlShm>,
 {
+    /// Close the shared memory files kept for reuse by the memory pools
+    ///
+    /// The files of the pools dropped while the compositor still uses some of their buffers
+    /// are not affected, they are kept once released. See
+    /// [`MemPool`](../shm/struct.MemPool.html) for details.
+    pub fn clear_shm_cache(&self) {
+        let cache = self
+            .get_global::<wl_shm::WlShm>()
+            .and_then(|shm| FileCache::of_shm(&shm));
+        if let Some(cache) = cache {
+            cache.clear();
+        }
+    }
+
     /// Create a simple memory pool
     ///
     /// This memory pool track the usage of the buffers created from it,
@@ -7,26 +7,49 @@ use wayland_client::{
     Attached, DispatchData,
 };
 
+use self::mempool::{FileCache, CACHE_MAX_BYTES, CACHE_MAX_FILES};
+
+mod damage;
 mod mempool;
+mod pixels;
 
+pub use self::damage::{copy_damaged, Rect};
+pub(crate) use self::mempool::create_shm_fd;
 pub use self::mempool::{DoubleMemPool, MemPool};
+pub use self::pixels::{convert_to_argb8888, PixelFormat, PixelLayout};
 pub use wl_shm::Format;
 
 /// A handler for the `wl_shm` global
 ///
 /// This handler is automatically included in the
 /// [`default_environment!`](../macro.default_environment.html).
+///
+/// It keeps the shared memory files of the dropped memory pools for reuse by the next ones,
+/// see [`MemPool`](struct.MemPool.html).
 pub struct ShmHandler {
     shm: Option<Attached<wl_shm::WlShm>>,
     formats: Rc<RefCell<Vec<wl_shm::Format>>>,
+    cache: FileCache,
 }
 
 impl ShmHandler {
     /// Create a new ShmHandler
+    ///
+    /// Up to 4 files totalling 32 MiB are kept for reuse by the memory pools.
     pub fn new() -> ShmHandler {
+        ShmHandler::with_cache_limits(CACHE_MAX_FILES, CACHE_MAX_BYTES)
+    }
+
+    /// Create a new ShmHandler, with limits for its cache of shared memory files
+    ///
+    /// At most `max_files` files totalling at most `max_bytes` bytes are kept for reuse by
+    /// the memory pools, the oldest ones being closed first. A `max_files` of 0 disables
+    /// the cache.
+    pub fn with_cache_limits(max_files: usize, max_bytes: usize) -> ShmHandler {
         ShmHandler {
             shm: None,
             formats: Rc::new(RefCell::new(vec![])),
+            cache: FileCache::new(max_files, max_bytes),
         }
     }
 }
@@ -41,9 +64,15 @@ impl crate::environment::GlobalHandler<wl_shm::WlShm> for ShmHandler {
     ) {
         // only shm verison 1 is supported
         let shm = registry.bind::<wl_shm::WlShm>(1, id);
+        let data = ShmData {
+            cache: self.cache.clone(),
+            formats: self.formats.clone(),
+        };
+        shm.as_ref().user_data().set(move || data);
         let my_formats = self.formats.clone();
         shm.quick_assign(move |_, event, _| match event {
             wl_shm::Event::Format { format } => {
+                log::trace!("[SCTK] wl_shm supports format {:?}", format);
                 my_formats.borrow_mut().push(format);
             }
             _ => unreachable!(),
@@ -55,6 +84,39 @@ impl crate::environment::GlobalHandler<wl_shm::WlShm> for ShmHandler {
     }
 }
 
+// The user data of the `wl_shm` bound by a `ShmHandler`
+pub(crate) struct ShmData {
+    cache: FileCache,
+    formats: Rc<RefCell<Vec<wl_shm::Format>>>,
+}
+
+/// Choose the format of the buffers of a surface among the formats supported by the compositor
+///
+/// This is `Xrgb8888` for the opaque surfaces, which lets the compositor skip blending them,
+/// and `Argb8888` otherwise. Both formats are supported by all compositors, and their pixels
+/// have the same bytes, so the pixels drawn for one can be used with the other, see
+/// [`PixelFormat`](trait.PixelFormat.html).
+pub fn preferred_format(formats: &[wl_shm::Format], opaque: bool) -> wl_shm::Format {
+    if opaque && formats.contains(&wl_shm::Format::Xrgb8888) {
+        wl_shm::Format::Xrgb8888
+    } else {
+        wl_shm::Format::Argb8888
+    }
+}
+
+/// Choose the format of the buffers of a surface for a `wl_shm` global
+///
+/// This is [`preferred_format`](fn.preferred_format.html) with the formats advertised to
+/// this global, for the code given a `wl_shm` rather than an `Environment`, like frames.
+/// It falls back to `Argb8888` if the global was not bound by a `ShmHandler`, or from
+/// another thread.
+pub fn preferred_format_of(shm: &wl_shm::WlShm, opaque: bool) -> wl_shm::Format {
+    match shm.as_ref().user_data().get::<ShmData>() {
+        Some(data) => preferred_format(&data.formats.borrow(), opaque),
+        None => wl_shm::Format::Argb8888,
+    }
+}
+
 /// An interface trait to forward the shm handler capability
 ///
 /// You need to implement this trait for you environment struct, by
@@ -79,4 +141,11 @@ where
     pub fn shm_formats(&self) -> Vec<wl_shm::Format> {
         self.with_inner(|inner| inner.shm_formats())
     }
+
+    /// Choose the format of the buffers of a surface
+    ///
+    /// See [`preferred_format`](../shm/fn.preferred_format.html).
+    pub fn preferred_shm_format(&self, opaque: bool) -> wl_shm::Format {
+        preferred_format(&self.shm_formats(), opaque)
+    }
 }
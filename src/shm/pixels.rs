@@ -0,0 +1,266 @@
+use std::io;
+
+use wayland_client::protocol::{wl_buffer, wl_shm};
+
+use super::MemPool;
+
+/// The layout of the pixels of an image in memory
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// Red, green, blue and alpha bytes, with the alpha not premultiplied
+    ///
+    /// This is what most image decoders produce.
+    Rgba8,
+    /// Red, green, blue and alpha bytes, with the color premultiplied by the alpha
+    Rgba8Premultiplied,
+    /// Red, green and blue bytes of an opaque image
+    Rgb8,
+}
+
+impl PixelLayout {
+    /// The number of bytes of a pixel in this layout
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelLayout::Rgba8 | PixelLayout::Rgba8Premultiplied => 4,
+            PixelLayout::Rgb8 => 3,
+        }
+    }
+}
+
+/// The packing of pixels in the formats of `wl_shm`
+///
+/// The `wl_shm` formats are little-endian: the channels of a pixel are laid out from its
+/// least significant bits, so writing pixels as native `u32` values gives wrong colors on
+/// big-endian hosts. This trait, implemented for `wl_shm::Format`, writes their bytes
+/// explicitly.
+pub trait PixelFormat {
+    /// Whether [`packed`](#tymethod.packed) supports this format
+    ///
+    /// This is the case of the 32 bits formats with 8 bits channels: `Argb8888`,
+    /// `Xrgb8888`, `Abgr8888`, `Xbgr8888`, `Rgba8888`, `Rgbx8888`, `Bgra8888` and
+    /// `Bgrx8888`.
+    fn is_packable(&self) -> bool;
+
+    /// Get the bytes of a pixel of this format, from its color as an `0xAARRGGBB` value
+    ///
+    /// The channels are reordered for the format. The formats without an alpha channel
+    /// ignore the byte which holds it, the pixels of `Argb8888` and `Xrgb8888` thus have the
+    /// same bytes.
+    ///
+    /// Panics if the format is not [packable](#tymethod.is_packable).
+    fn packed(self, argb: u32) -> [u8; 4];
+}
+
+impl PixelFormat for wl_shm::Format {
+    fn is_packable(&self) -> bool {
+        match *self {
+            wl_shm::Format::Argb8888
+            | wl_shm::Format::Xrgb8888
+            | wl_shm::Format::Abgr8888
+            | wl_shm::Format::Xbgr8888
+            | wl_shm::Format::Rgba8888
+            | wl_shm::Format::Rgbx8888
+            | wl_shm::Format::Bgra8888
+            | wl_shm::Format::Bgrx8888 => true,
+            _ => false,
+        }
+    }
+
+    fn packed(self, argb: u32) -> [u8; 4] {
+        let [b, g, r, a] = argb.to_le_bytes();
+        match self {
+            wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888 => [b, g, r, a],
+            wl_shm::Format::Abgr8888 | wl_shm::Format::Xbgr8888 => [r, g, b, a],
+            wl_shm::Format::Rgba8888 | wl_shm::Format::Rgbx8888 => [a, b, g, r],
+            wl_shm::Format::Bgra8888 | wl_shm::Format::Bgrx8888 => [a, r, g, b],
+            format => panic!("Cannot pack the pixels of the {:?} format", format),
+        }
+    }
+}
+
+// Multiply a color channel by an alpha value, rounding like an exact division by 255
+#[inline]
+fn premultiply(color: u8, alpha: u8) -> u8 {
+    let t = color as u32 * alpha as u32 + 128;
+    ((t + (t >> 8)) >> 8) as u8
+}
+
+/// Convert the pixels of an image to the `Argb8888` format of `wl_shm`
+///
+/// `src` contains `height` rows of `width` pixels in the given layout, each row starting
+/// `stride` bytes after the previous one, which allows converting a subregion of a larger
+/// image. The converted pixels are written to `dst`, whose rows are tightly packed.
+///
+/// Returns an error if `src` or `dst` is too small for the image, or if the stride is
+/// smaller than a row.
+pub fn convert_to_argb8888(
+    src: &[u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    layout: PixelLayout,
+    dst: &mut [u8],
+) -> io::Result<()> {
+    let bpp = layout.bytes_per_pixel();
+    let row_len = width as usize * bpp;
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    if stride < row_len
+        || src.len() < stride * (height as usize - 1) + row_len
+        || dst.len() < width as usize * height as usize * 4
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the buffers are too small for the image",
+        ));
+    }
+    let dst_rows = dst.chunks_exact_mut(width as usize * 4);
+    for (src_row, dst_row) in src.chunks(stride).zip(dst_rows).take(height as usize) {
+        let src_pixels = src_row[..row_len].chunks_exact(bpp);
+        // Argb8888 is little-endian, so the bytes are stored as blue, green, red, alpha
+        for (s, d) in src_pixels.zip(dst_row.chunks_exact_mut(4)) {
+            let (r, g, b, a) = match layout {
+                PixelLayout::Rgba8 => (
+                    premultiply(s[0], s[3]),
+                    premultiply(s[1], s[3]),
+                    premultiply(s[2], s[3]),
+                    s[3],
+                ),
+                PixelLayout::Rgba8Premultiplied => (s[0], s[1], s[2], s[3]),
+                PixelLayout::Rgb8 => (s[0], s[1], s[2], 0xFF),
+            };
+            d[0] = b;
+            d[1] = g;
+            d[2] = r;
+            d[3] = a;
+        }
+    }
+    Ok(())
+}
+
+impl MemPool {
+    /// Create a buffer containing an image
+    ///
+    /// The image is converted to the format of the pool, `Argb8888` by default, and written
+    /// at the beginning of the pool, resizing it if needed. See
+    /// [`convert_to_argb8888`](fn.convert_to_argb8888.html) for the meaning of the parameters.
+    ///
+    /// Returns an error if the image is empty, or if the format of the pool is neither
+    /// `Argb8888` nor `Xrgb8888`, see [`set_format`](#method.set_format). The previous
+    /// contents of the pool are overwritten, so it should not be in use.
+    pub fn buffer_from_pixels(
+        &mut self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        stride: usize,
+        layout: PixelLayout,
+    ) -> io::Result<wl_buffer::WlBuffer> {
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffers cannot be empty",
+            ));
+        }
+        let format = self.format();
+        if format != wl_shm::Format::Argb8888 && format != wl_shm::Format::Xrgb8888 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the pixels can only be converted to the Argb8888 and Xrgb8888 formats",
+            ));
+        }
+        let len = width as usize * height as usize * 4;
+        self.resize(len)?;
+        convert_to_argb8888(
+            pixels,
+            width,
+            height,
+            stride,
+            layout,
+            &mut self.mmap()[..len],
+        )?;
+        Ok(self.buffer(0, width as i32, height as i32, width as i32 * 4, format))
+    }
+
+    /// Create a buffer containing an image decoded by the `image` crate
+    ///
+    /// This is a shorthand for [`buffer_from_pixels`](#method.buffer_from_pixels).
+    ///
+    /// Requires the `image` cargo feature.
+    #[cfg(feature = "image")]
+    pub fn buffer_from_image(
+        &mut self,
+        image: &image::RgbaImage,
+    ) -> io::Result<wl_buffer::WlBuffer> {
+        let (width, height) = image.dimensions();
+        self.buffer_from_pixels(image, width, height, width as usize * 4, PixelLayout::Rgba8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a translucent color whose channels are all different
+    const COLOR: u32 = 0x80_11_22_33;
+
+    #[test]
+    fn packed_byte_layouts() {
+        let layouts = [
+            (wl_shm::Format::Argb8888, [0x33, 0x22, 0x11, 0x80]),
+            (wl_shm::Format::Xrgb8888, [0x33, 0x22, 0x11, 0x80]),
+            (wl_shm::Format::Abgr8888, [0x11, 0x22, 0x33, 0x80]),
+            (wl_shm::Format::Xbgr8888, [0x11, 0x22, 0x33, 0x80]),
+            (wl_shm::Format::Rgba8888, [0x80, 0x33, 0x22, 0x11]),
+            (wl_shm::Format::Rgbx8888, [0x80, 0x33, 0x22, 0x11]),
+            (wl_shm::Format::Bgra8888, [0x80, 0x11, 0x22, 0x33]),
+            (wl_shm::Format::Bgrx8888, [0x80, 0x11, 0x22, 0x33]),
+        ];
+        for &(format, bytes) in &layouts {
+            assert!(format.is_packable());
+            assert_eq!(format.packed(COLOR), bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn packed_matches_little_endian_values() {
+        // the pixel of a format is its channels packed in a u32, from the least significant
+        // bits, stored in little-endian
+        assert_eq!(wl_shm::Format::Argb8888.packed(COLOR), COLOR.to_le_bytes());
+        assert_eq!(
+            wl_shm::Format::Abgr8888.packed(COLOR),
+            0x80_33_22_11u32.to_le_bytes()
+        );
+        assert_eq!(
+            wl_shm::Format::Rgba8888.packed(COLOR),
+            0x11_22_33_80u32.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn other_formats_are_not_packable() {
+        assert!(!wl_shm::Format::Rgb565.is_packable());
+        assert!(!wl_shm::Format::Rgb888.is_packable());
+    }
+
+    #[test]
+    #[should_panic]
+    fn packing_other_formats_panics() {
+        wl_shm::Format::Rgb565.packed(COLOR);
+    }
+
+    #[test]
+    fn converted_pixels_are_premultiplied_argb8888() {
+        let src = [0xff, 0x80, 0x00, 0x80, 0x10, 0x20, 0x30, 0xff];
+        let mut dst = [0; 8];
+        convert_to_argb8888(&src, 2, 1, 8, PixelLayout::Rgba8, &mut dst).unwrap();
+        assert_eq!(
+            dst,
+            [
+                0x00, 0x40, 0x80, 0x80, // premultiplied by the alpha of 0x80
+                0x30, 0x20, 0x10, 0xff,
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Surfaces filled with a single color
+//!
+//! Solid backgrounds, dimming layers or letterboxing bars don't need a buffer of their size: a
+//! [`SolidSurface`](struct.SolidSurface.html) attaches a buffer of a single pixel to its surface,
+//! and scales it to the requested size with `wp_viewporter`.
+//!
+//! An [`InputOnlySurface`](struct.InputOnlySurface.html) is a transparent one receiving input
+//! without drawing anything, for resize handles outside of a window, drop zones along the edges
+//! of the screen or modal click-catchers.
+//!
+//! The `wp_viewporter` global is not part of the
+//! [`default_environment!`](../macro.default_environment.html), you need to add it to your
+//! environment, for example as a [`SimpleGlobal`](../environment/struct.SimpleGlobal.html).
+//!
+//! The pixel is drawn in a shared memory buffer, as the `wp_single_pixel_buffer_manager_v1`
+//! protocol is not available in the protocol bindings used by this crate yet.
+
+use std::{fmt, io};
+
+use wayland_client::protocol::{wl_seat, wl_shm, wl_surface};
+
+use wayland_protocols::viewporter::client::{wp_viewport, wp_viewporter::WpViewporter};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler};
+use crate::shm::{DoubleMemPool, PixelFormat};
+
+/// A color, with straight (not premultiplied) alpha
+///
+/// The components are stored with the full range of an `u32`, like the ones of the
+/// single-pixel buffer protocol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color {
+    /// The red component
+    pub r: u32,
+    /// The green component
+    pub g: u32,
+    /// The blue component
+    pub b: u32,
+    /// The alpha component
+    pub a: u32,
+}
+
+impl Color {
+    /// Create a color from components ranging from `0` to `u32::MAX`
+    pub fn from_u32(r: u32, g: u32, b: u32, a: u32) -> Color {
+        Color { r, g, b, a }
+    }
+
+    /// Create a color from components ranging from `0.0` to `1.0`
+    ///
+    /// The components are clamped to this range.
+    pub fn from_f32(r: f32, g: f32, b: f32, a: f32) -> Color {
+        fn convert(value: f32) -> u32 {
+            let value = if value > 0.0 { value } else { 0.0 };
+            let value = if value < 1.0 { value } else { 1.0 };
+            (f64::from(value) * f64::from(std::u32::MAX)).round() as u32
+        }
+        Color {
+            r: convert(r),
+            g: convert(g),
+            b: convert(b),
+            a: convert(a),
+        }
+    }
+
+    // The pixel in the ARGB8888 format, which is premultiplied
+    fn to_argb8888(self) -> u32 {
+        let a = self.a >> 24;
+        let premultiply = |value: u32| ((value >> 24) * a + 127) / 255;
+        (a << 24) | (premultiply(self.r) << 16) | (premultiply(self.g) << 8) | premultiply(self.b)
+    }
+}
+
+/// Possible errors when creating a `SolidSurface`
+#[derive(Debug)]
+pub enum SolidSurfaceError {
+    /// A required global is not available
+    Global(GlobalError),
+    /// Creating the memory pool failed
+    Io(io::Error),
+}
+
+impl fmt::Display for SolidSurfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolidSurfaceError::Global(err) => write!(f, "{}", err),
+            SolidSurfaceError::Io(err) => write!(f, "failed to create the memory pool: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SolidSurfaceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SolidSurfaceError::Global(err) => Some(err),
+            SolidSurfaceError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<GlobalError> for SolidSurfaceError {
+    fn from(err: GlobalError) -> SolidSurfaceError {
+        SolidSurfaceError::Global(err)
+    }
+}
+
+impl From<io::Error> for SolidSurfaceError {
+    fn from(err: io::Error) -> SolidSurfaceError {
+        SolidSurfaceError::Io(err)
+    }
+}
+
+/// A surface filled with a single color
+///
+/// Nothing is displayed until both a color and a size have been set. Setting the same
+/// color or size again does nothing, so they can be set each time your app updates its
+/// state.
+///
+/// Dropping it does not destroy the surface, but resets its scaling.
+pub struct SolidSurface {
+    surface: wl_surface::WlSurface,
+    viewport: wp_viewport::WpViewport,
+    pools: DoubleMemPool,
+    color: Option<Color>,
+    size: Option<(i32, i32)>,
+    // whether the last color was attached to the surface
+    attached: bool,
+}
+
+impl SolidSurface {
+    /// Fill this surface with a single color
+    pub fn new<E>(
+        env: &Environment<E>,
+        surface: wl_surface::WlSurface,
+    ) -> Result<SolidSurface, SolidSurfaceError>
+    where
+        E: GlobalHandler<WpViewporter> + GlobalHandler<wl_shm::WlShm>,
+    {
+        let viewporter = env.try_get_global::<WpViewporter>()?;
+        let shm = env.try_get_global::<wl_shm::WlShm>()?;
+        let pools = DoubleMemPool::new(shm, |_| {})?;
+        let viewport = viewporter.get_viewport(&surface).detach();
+        Ok(SolidSurface {
+            surface,
+            viewport,
+            pools,
+            color: None,
+            size: None,
+            attached: false,
+        })
+    }
+
+    /// Access the surface
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    /// Set the color of the surface
+    pub fn set_color(&mut self, color: Color) {
+        if self.color == Some(color) {
+            return;
+        }
+        self.color = Some(color);
+        self.attached = false;
+        self.update();
+    }
+
+    /// Set the size of the surface, in surface-local coordinates
+    ///
+    /// An empty size hides the surface.
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        let size = if width == 0 || height == 0 {
+            None
+        } else {
+            Some((width as i32, height as i32))
+        };
+        if self.size == size {
+            return;
+        }
+        self.size = size;
+        match size {
+            Some((width, height)) => {
+                self.viewport.set_destination(width, height);
+                self.update();
+            }
+            None => {
+                // the viewport does not accept an empty destination, unmap the surface instead
+                self.viewport.set_destination(-1, -1);
+                self.surface.attach(None, 0, 0);
+                self.surface.commit();
+                self.attached = false;
+            }
+        }
+    }
+
+    fn update(&mut self) {
+        let (color, (width, height)) = match (self.color, self.size) {
+            (Some(color), Some(size)) => (color, size),
+            _ => return,
+        };
+        if !self.attached {
+            // the buffers are destroyed once released, draw in a pool the compositor
+            // is not reading from
+            let pool = match self.pools.pool() {
+                Some(pool) => pool,
+                None => {
+                    // the color will be attached by the next update
+                    log::warn!("[SCTK] Both pools of a solid surface are in use, skipping update");
+                    return;
+                }
+            };
+            pool.mmap()[..4].copy_from_slice(&wl_shm::Format::Argb8888.packed(color.to_argb8888()));
+            if let Err(err) = pool.mmap().flush() {
+                log::warn!("[SCTK] Failed to draw a solid surface: {}", err);
+                return;
+            }
+            let buffer = pool.buffer(0, 1, 1, 4, wl_shm::Format::Argb8888);
+            self.surface.attach(Some(&buffer), 0, 0);
+            self.attached = true;
+        }
+        self.surface.damage(0, 0, width, height);
+        self.surface.commit();
+    }
+}
+
+impl Drop for SolidSurface {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+    }
+}
+
+/// A transparent surface receiving input
+///
+/// It is a [`SolidSurface`](struct.SolidSurface.html) of a fully transparent color, with an empty
+/// opaque region and an input region covering it entirely. Give its surface a role, like a
+/// subsurface or a layer surface, before creating it.
+///
+/// A surface must not have a buffer attached before some roles are configured, like layer
+/// surfaces: create it with an empty size, and set its size once the first configure is
+/// received.
+///
+/// Use [`pointer_position`](#method.pointer_position) or [`is_surface`](#method.is_surface) to
+/// tell the pointer events on it apart from the ones on your other surfaces.
+pub struct InputOnlySurface {
+    solid: SolidSurface,
+}
+
+impl InputOnlySurface {
+    /// Make this surface an input-only surface of the given size
+    ///
+    /// An empty size hides the surface.
+    pub fn new<E>(
+        env: &Environment<E>,
+        surface: wl_surface::WlSurface,
+        width: u32,
+        height: u32,
+    ) -> Result<InputOnlySurface, SolidSurfaceError>
+    where
+        E: GlobalHandler<WpViewporter> + GlobalHandler<wl_shm::WlShm>,
+    {
+        let mut solid = SolidSurface::new(env, surface)?;
+        // the regions take effect with the first buffer, committed by `set_size`
+        solid.surface.set_opaque_region(None);
+        solid.surface.set_input_region(None);
+        solid.set_color(Color::from_u32(0, 0, 0, 0));
+        solid.set_size(width, height);
+        Ok(InputOnlySurface { solid })
+    }
+
+    /// Access the surface
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        self.solid.surface()
+    }
+
+    /// Whether this is the given surface
+    pub fn is_surface(&self, surface: &wl_surface::WlSurface) -> bool {
+        self.solid.surface() == surface
+    }
+
+    /// Set the size of the surface, in surface-local coordinates
+    ///
+    /// An empty size hides the surface.
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.solid.set_size(width, height);
+    }
+
+    /// The position of the pointer of this seat over the surface
+    ///
+    /// Returns `None` if the pointer is not over this surface, see
+    /// [`seat::pointer_focus`](../seat/fn.pointer_focus.html).
+    pub fn pointer_position(&self, seat: &wl_seat::WlSeat) -> Option<(f64, f64)> {
+        match crate::seat::pointer_focus(seat) {
+            Some((ref surface, position)) if self.is_surface(surface) => Some(position),
+            _ => None,
+        }
+    }
+
+    /// Destroy the surface
+    ///
+    /// Its viewport is destroyed first.
+    pub fn destroy(self) {
+        let surface = self.solid.surface.clone();
+        drop(self.solid);
+        surface.destroy();
+    }
+}
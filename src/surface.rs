@@ -1,41 +1,459 @@
-use std::{cell::RefCell, rc::Rc, sync::Mutex};
+use std::{cell::RefCell, fmt, num::NonZeroU32, rc::Rc, sync::Mutex};
 
 use wayland_client::{
-    protocol::{wl_compositor, wl_output, wl_surface},
+    protocol::{wl_compositor, wl_output, wl_subsurface, wl_surface},
     DispatchData, Main,
 };
 
-use crate::output::{add_output_listener, with_output_info, OutputListener};
+use crate::output::{add_output_listener, with_output_info, OutputInfo, OutputListener};
+use crate::seat::pointer::CursorPolicy;
+
+/// An integer scale factor
+///
+/// This is the scale factor of outputs, and of the buffers attached to surfaces. It is
+/// never zero nor negative.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScaleFactor(NonZeroU32);
+
+impl ScaleFactor {
+    /// The scale factor of 1, drawing one pixel per surface coordinate
+    pub const ONE: ScaleFactor = ScaleFactor(unsafe { NonZeroU32::new_unchecked(1) });
+
+    /// Create a scale factor
+    ///
+    /// Returns `None` if `factor` is zero or too large to be sent to the compositor.
+    pub fn new(factor: u32) -> Option<ScaleFactor> {
+        if factor > std::i32::MAX as u32 {
+            return None;
+        }
+        NonZeroU32::new(factor).map(ScaleFactor)
+    }
+
+    /// Convert a scale factor received from the compositor
+    ///
+    /// Invalid values are replaced by a scale factor of 1.
+    pub(crate) fn from_protocol(factor: i32) -> ScaleFactor {
+        if factor > 0 {
+            ScaleFactor::new(factor as u32).unwrap_or(ScaleFactor::ONE)
+        } else {
+            log::warn!("[SCTK] Ignoring the invalid scale factor {}", factor);
+            ScaleFactor::ONE
+        }
+    }
+
+    /// The value of the scale factor
+    pub fn get(self) -> u32 {
+        self.0.get()
+    }
+
+    /// The value of the scale factor, as expected by `wl_surface::set_buffer_scale`
+    pub fn to_i32(self) -> i32 {
+        self.0.get() as i32
+    }
+}
+
+impl Default for ScaleFactor {
+    fn default() -> ScaleFactor {
+        ScaleFactor::ONE
+    }
+}
+
+impl fmt::Display for ScaleFactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A change of the outputs a surface is displayed on
+#[derive(Clone)]
+pub enum SurfaceOutputEvent {
+    /// The surface entered an output
+    Enter {
+        /// The output
+        output: wl_output::WlOutput,
+        /// The information about the output, `None` if it was not received yet
+        info: Option<OutputInfo>,
+    },
+    /// The surface left an output, or the output was removed
+    Leave {
+        /// The output
+        output: wl_output::WlOutput,
+        /// The information about the output, `None` if it was not received yet
+        info: Option<OutputInfo>,
+    },
+}
+
+type ScaleCallback = Rc<RefCell<dyn FnMut(ScaleFactor, wl_surface::WlSurface, DispatchData)>>;
+type OutputCallback =
+    Rc<RefCell<dyn FnMut(SurfaceOutputEvent, wl_surface::WlSurface, DispatchData)>>;
+
+#[derive(Clone)]
+struct SurfaceCallbacks {
+    scale: Option<ScaleCallback>,
+    output: Option<OutputCallback>,
+}
+
+/// The transform of the buffers of a surface, and the mapping of coordinates it implies
+///
+/// Drawing the contents already rotated to match the transform of the output saves the
+/// compositor from doing it. The buffers then need to be declared with
+/// `wl_surface::set_buffer_transform`, which this helper does in
+/// [`apply`](#method.apply), and their size, damage and input coordinates are in a
+/// different space than the surface. This helper converts them from surface coordinates,
+/// in which your application logic can remain, to buffer coordinates, in which you draw.
+///
+/// The transforms are the ones of `wl_output`, counter-clockwise. The transform of an
+/// output is given by its `OutputInfo`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferTransform {
+    transform: wl_output::Transform,
+    size: (u32, u32),
+    scale: ScaleFactor,
+}
+
+impl BufferTransform {
+    /// Create a buffer transform for a surface of this size
+    ///
+    /// The size is in surface coordinates, and the buffers are drawn at this scale factor.
+    pub fn new(transform: wl_output::Transform, size: (u32, u32), scale: ScaleFactor) -> Self {
+        BufferTransform {
+            transform,
+            size,
+            scale,
+        }
+    }
+
+    /// The transform of the buffers
+    pub fn transform(&self) -> wl_output::Transform {
+        self.transform
+    }
+
+    /// Change the transform of the buffers
+    pub fn set_transform(&mut self, transform: wl_output::Transform) {
+        self.transform = transform;
+    }
+
+    /// Change the size of the surface, in surface coordinates
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+    }
+
+    /// Change the scale factor of the buffers
+    pub fn set_scale_factor(&mut self, scale: ScaleFactor) {
+        self.scale = scale;
+    }
+
+    // whether the width and height are swapped by the transform
+    fn swaps_axes(&self) -> bool {
+        use wl_output::Transform;
+        [
+            Transform::_90,
+            Transform::_270,
+            Transform::Flipped90,
+            Transform::Flipped270,
+        ]
+        .contains(&self.transform)
+    }
+
+    /// The size of the buffers to draw, in buffer pixels
+    pub fn buffer_size(&self) -> (u32, u32) {
+        let scale = self.scale.get();
+        let (w, h) = (self.size.0 * scale, self.size.1 * scale);
+        if self.swaps_axes() {
+            (h, w)
+        } else {
+            (w, h)
+        }
+    }
+
+    /// Convert a point from surface coordinates to buffer coordinates
+    ///
+    /// This is typically used on the positions of pointer or touch events, to find what
+    /// was drawn under them.
+    pub fn map_pointer(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        use wl_output::Transform;
+        let (w, h) = (f64::from(self.size.0), f64::from(self.size.1));
+        let (bx, by) = match self.transform {
+            Transform::Flipped => (w - x, y),
+            Transform::_90 => (h - y, x),
+            Transform::Flipped90 => (h - y, w - x),
+            Transform::_180 => (w - x, h - y),
+            Transform::Flipped180 => (x, h - y),
+            Transform::_270 => (y, w - x),
+            Transform::Flipped270 => (y, x),
+            _ => (x, y),
+        };
+        let scale = f64::from(self.scale.get());
+        (bx * scale, by * scale)
+    }
+
+    /// Convert a rectangle from surface coordinates to buffer coordinates
+    ///
+    /// The rectangle is given as `(x, y, width, height)`. The result can be given to
+    /// `wl_surface::damage_buffer`.
+    pub fn map_damage(&self, (x, y, width, height): (i32, i32, i32, i32)) -> (i32, i32, i32, i32) {
+        let (x1, y1) = self.map_pointer((f64::from(x), f64::from(y)));
+        let (x2, y2) = self.map_pointer((f64::from(x + width), f64::from(y + height)));
+        let (left, top) = (x1.min(x2), y1.min(y2));
+        let (right, bottom) = (x1.max(x2), y1.max(y2));
+        (
+            left as i32,
+            top as i32,
+            (right - left) as i32,
+            (bottom - top) as i32,
+        )
+    }
+
+    /// Set the transform and scale factor of the buffers of a surface
+    ///
+    /// Like the other state of the surface, this takes effect on its next commit, which
+    /// must attach a buffer of the size given by [`buffer_size`](#method.buffer_size).
+    /// The buffer transform requires version 2 of `wl_compositor`, it is not set on
+    /// older surfaces.
+    pub fn apply(&self, surface: &wl_surface::WlSurface) {
+        surface.set_buffer_scale(self.scale.to_i32());
+        if CompositorCaps::of_surface(surface).has_buffer_transform() {
+            surface.set_buffer_transform(self.transform);
+        }
+    }
+}
+
+/// The features of `wl_compositor` available with its negotiated version
+///
+/// The surfaces have the version of the compositor that created them, so this can be
+/// obtained from any of them with [`of_surface`](#method.of_surface), or from the
+/// environment with
+/// [`Environment::compositor_caps`](../environment/struct.Environment.html#method.compositor_caps).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompositorCaps {
+    version: u32,
+}
+
+impl CompositorCaps {
+    /// The features of this version of `wl_compositor`
+    pub fn from_version(version: u32) -> CompositorCaps {
+        CompositorCaps { version }
+    }
+
+    /// The features available to this surface
+    pub fn of_surface(surface: &wl_surface::WlSurface) -> CompositorCaps {
+        CompositorCaps::from_version(surface.as_ref().version())
+    }
+
+    /// The negotiated version of `wl_compositor`
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Whether `wl_surface::set_buffer_transform` is available, from version 2
+    pub fn has_buffer_transform(&self) -> bool {
+        self.version >= 2
+    }
+
+    /// Whether `wl_surface::set_buffer_scale` is available, from version 3
+    pub fn has_buffer_scale(&self) -> bool {
+        self.version >= 3
+    }
+
+    /// Whether `wl_surface::damage_buffer` is available, from version 4
+    pub fn has_damage_buffer(&self) -> bool {
+        self.version >= 4
+    }
+
+    /// Damage a rectangle of the buffer of a surface, in buffer coordinates
+    ///
+    /// Without `damage_buffer`, the rectangle is converted to surface coordinates with
+    /// the scale of the buffer, rounding outwards. This assumes the buffer has no
+    /// transform.
+    pub fn damage_buffer(
+        &self,
+        surface: &wl_surface::WlSurface,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        scale: u32,
+    ) {
+        if self.has_damage_buffer() {
+            surface.damage_buffer(x, y, width, height);
+        } else {
+            let scale = std::cmp::max(scale, 1) as i32;
+            let (left, top) = (x / scale, y / scale);
+            let right = (x + width + scale - 1) / scale;
+            let bottom = (y + height + scale - 1) / scale;
+            surface.damage(left, top, right - left, bottom - top);
+        }
+    }
+}
+
+/// A batch of updates of a surface and its subsurfaces, applied in the same frame
+///
+/// The state of a synchronized subsurface is only applied when its parent is committed,
+/// so updating a surface tree atomically requires all its subsurfaces to be synchronized
+/// and committed before their parent. A transaction takes care of this: begin it on the
+/// root of the tree, add the subsurfaces you update (they are switched to synchronized mode
+/// right away), attach and damage any of the surfaces, and [`commit`](#method.commit) the
+/// transaction. The subsurfaces are committed in the reverse order of their addition, and
+/// then the root, so nested subsurfaces need to be added after their parent.
+///
+/// Each subsurface can be switched back to desynchronized mode once the transaction is
+/// committed, for example a video overlay which otherwise updates on its own.
+///
+/// A subsurface created by SCTK can only be part of one open transaction at a time, which
+/// is checked in debug builds. Transactions can however share their root: the decorations
+/// of a [`Window`](window/struct.Window.html) are updated with a transaction committing its
+/// subsurfaces only, which can happen within one of yours. You can also check with
+/// [`is_open_on`](#method.is_open_on) that you do not commit a surface while a transaction
+/// is open on it, as this would apply the root prematurely.
+pub struct CommitTransaction {
+    root: wl_surface::WlSurface,
+    // the subsurfaces and whether to desynchronize them once committed
+    subsurfaces: Vec<(wl_surface::WlSurface, wl_subsurface::WlSubsurface, bool)>,
+    open: bool,
+}
+
+impl CommitTransaction {
+    /// Begin a transaction updating the tree of this root surface
+    pub fn begin(root: &wl_surface::WlSurface) -> CommitTransaction {
+        track_transaction(root, true);
+        CommitTransaction {
+            root: root.clone(),
+            subsurfaces: Vec::new(),
+            open: true,
+        }
+    }
+
+    /// Add a subsurface of the tree to the transaction
+    ///
+    /// The subsurface is switched to synchronized mode, and back to desynchronized mode
+    /// once the transaction is committed if `desync` is `true`.
+    pub fn add_subsurface(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+        subsurface: &wl_subsurface::WlSubsurface,
+        desync: bool,
+    ) {
+        let open = track_transaction(surface, true);
+        debug_assert!(
+            open == 0,
+            "the subsurface is already part of an open transaction"
+        );
+        subsurface.set_sync();
+        self.subsurfaces
+            .push((surface.clone(), subsurface.clone(), desync));
+    }
+
+    /// Whether this surface is part of an open transaction
+    ///
+    /// This is always `false` for the surfaces which were not created by SCTK.
+    pub fn is_open_on(surface: &wl_surface::WlSurface) -> bool {
+        surface
+            .as_ref()
+            .user_data()
+            .get::<Mutex<SurfaceUserData>>()
+            .map(|data| data.lock().unwrap().transactions > 0)
+            .unwrap_or(false)
+    }
+
+    /// Commit the subsurfaces and the root, applying all the updates at once
+    pub fn commit(mut self) {
+        self.commit_subsurfaces_inner();
+        self.root.commit();
+        self.finish();
+    }
+
+    /// Commit the subsurfaces, but not the root
+    ///
+    /// This is for trees whose root is committed by someone else, like the content surface
+    /// of a window committed by your renderer: the updates are then applied on its next
+    /// commit. The subsurfaces to desynchronize are only desynchronized then.
+    pub fn commit_subsurfaces(mut self) {
+        self.commit_subsurfaces_inner();
+        self.finish();
+    }
+
+    fn commit_subsurfaces_inner(&mut self) {
+        for &(ref surface, _, _) in self.subsurfaces.iter().rev() {
+            track_transaction(surface, false);
+            surface.commit();
+        }
+    }
+
+    fn finish(&mut self) {
+        track_transaction(&self.root, false);
+        for &(_, ref subsurface, desync) in &self.subsurfaces {
+            if desync {
+                subsurface.set_desync();
+            }
+        }
+        self.open = false;
+    }
+}
+
+impl Drop for CommitTransaction {
+    fn drop(&mut self) {
+        if self.open {
+            // the updates stay pending until the surfaces are committed
+            log::warn!("[SCTK] A commit transaction was dropped without being committed");
+            track_transaction(&self.root, false);
+            for &(ref surface, _, _) in &self.subsurfaces {
+                track_transaction(surface, false);
+            }
+        }
+    }
+}
+
+// Count a transaction being opened or closed on a surface, returns the previous count
+fn track_transaction(surface: &wl_surface::WlSurface, open: bool) -> u32 {
+    match surface.as_ref().user_data().get::<Mutex<SurfaceUserData>>() {
+        Some(data) => {
+            let mut data = data.lock().unwrap();
+            let count = data.transactions;
+            data.transactions = if open {
+                count + 1
+            } else {
+                count.saturating_sub(1)
+            };
+            count
+        }
+        None => 0,
+    }
+}
 
 pub(crate) struct SurfaceUserData {
-    scale_factor: i32,
-    outputs: Vec<(wl_output::WlOutput, i32, OutputListener)>,
+    scale_factor: ScaleFactor,
+    // the scale factor of each output, `None` once the output no longer exists
+    outputs: Vec<(wl_output::WlOutput, Option<ScaleFactor>, OutputListener)>,
+    cursor_policy: CursorPolicy,
+    // the number of open `CommitTransaction` the surface is part of
+    transactions: u32,
+    role: Option<SurfaceRole>,
 }
 
 impl SurfaceUserData {
     fn new() -> Self {
         SurfaceUserData {
-            scale_factor: 1,
+            scale_factor: ScaleFactor::ONE,
             outputs: Vec::new(),
+            cursor_policy: CursorPolicy::Managed,
+            transactions: 0,
+            role: None,
         }
     }
 
-    pub(crate) fn enter<F>(
+    fn enter(
         &mut self,
         output: wl_output::WlOutput,
         surface: wl_surface::WlSurface,
-        callback: &Option<Rc<RefCell<F>>>,
-    ) where
-        F: FnMut(i32, wl_surface::WlSurface, DispatchData) + 'static,
-    {
-        let output_scale = with_output_info(&output, |info| info.scale_factor).unwrap_or(1);
+        callbacks: &SurfaceCallbacks,
+    ) {
+        let output_scale =
+            with_output_info(&output, |info| info.scale_factor).unwrap_or(ScaleFactor::ONE);
         let my_surface = surface.clone();
-        // Use a UserData to safely share the callback with the other thread
-        let my_callback = wayland_client::UserData::new();
-        if let Some(ref cb) = callback {
-            my_callback.set(|| cb.clone());
-        }
-        let listener = add_output_listener(&output, move |output, info, ddata| {
+        // Use a UserData to safely share the callbacks with the other thread
+        let my_callbacks = wayland_client::UserData::new();
+        my_callbacks.set(|| callbacks.clone());
+        let listener = add_output_listener(&output, move |output, info, mut ddata| {
             let mut user_data = my_surface
                 .as_ref()
                 .user_data()
@@ -44,40 +462,57 @@ impl SurfaceUserData {
                 .lock()
                 .unwrap();
             // update the scale factor of the relevant output
+            let mut removed = false;
             for (ref o, ref mut factor, _) in user_data.outputs.iter_mut() {
                 if o.as_ref().equals(output.as_ref()) {
                     if info.obsolete {
-                        // an output that no longer exists is marked by a scale factor of -1
-                        *factor = -1;
+                        removed = factor.is_some();
+                        *factor = None;
                     } else {
-                        *factor = info.scale_factor;
+                        *factor = Some(info.scale_factor);
                     }
                     break;
                 }
             }
-            // recompute the scale factor with the new info
-            let callback = my_callback.get::<Rc<RefCell<F>>>().cloned();
+            // recompute the scale factor with the new info, which also forgets a removed output
+            let callbacks = my_callbacks.get::<SurfaceCallbacks>().cloned();
             let old_scale_factor = user_data.scale_factor;
             let new_scale_factor = user_data.recompute_scale_factor();
             drop(user_data);
-            if let Some(ref cb) = callback {
+            let callbacks = match callbacks {
+                Some(callbacks) => callbacks,
+                None => return,
+            };
+            if let Some(ref cb) = callbacks.output {
+                if removed {
+                    let event = SurfaceOutputEvent::Leave {
+                        output,
+                        info: Some(info.clone()),
+                    };
+                    (&mut *cb.borrow_mut())(event, surface.clone(), ddata.reborrow());
+                }
+            }
+            if let Some(ref cb) = callbacks.scale {
                 if old_scale_factor != new_scale_factor {
                     (&mut *cb.borrow_mut())(new_scale_factor, surface.clone(), ddata);
                 }
             }
         });
-        self.outputs.push((output, output_scale, listener));
+        self.outputs.push((output, Some(output_scale), listener));
     }
 
-    pub(crate) fn leave(&mut self, output: &wl_output::WlOutput) {
+    // Returns whether the surface was on this output
+    fn leave(&mut self, output: &wl_output::WlOutput) -> bool {
+        let len = self.outputs.len();
         self.outputs
             .retain(|(ref output2, _, _)| !output.as_ref().equals(output2.as_ref()));
+        self.outputs.len() != len
     }
 
-    fn recompute_scale_factor(&mut self) -> i32 {
-        let mut new_scale_factor = 1;
+    fn recompute_scale_factor(&mut self) -> ScaleFactor {
+        let mut new_scale_factor = ScaleFactor::ONE;
         self.outputs.retain(|&(_, output_scale, _)| {
-            if output_scale > 0 {
+            if let Some(output_scale) = output_scale {
                 new_scale_factor = ::std::cmp::max(new_scale_factor, output_scale);
                 true
             } else {
@@ -94,15 +529,72 @@ impl SurfaceUserData {
     }
 }
 
+/// Set the cursor policy of a surface, returns `false` if it was not created by SCTK
+pub(crate) fn set_cursor_policy(surface: &wl_surface::WlSurface, policy: CursorPolicy) -> bool {
+    match surface.as_ref().user_data().get::<Mutex<SurfaceUserData>>() {
+        Some(data) => {
+            data.lock().unwrap().cursor_policy = policy;
+            true
+        }
+        None => false,
+    }
+}
+
+/// The cursor policy of a surface, surfaces not created by SCTK are managed
+pub(crate) fn get_cursor_policy(surface: &wl_surface::WlSurface) -> CursorPolicy {
+    surface
+        .as_ref()
+        .user_data()
+        .get::<Mutex<SurfaceUserData>>()
+        .map(|data| data.lock().unwrap().cursor_policy)
+        .unwrap_or_default()
+}
+
 pub(crate) fn setup_surface<F>(
     surface: Main<wl_surface::WlSurface>,
     callback: Option<F>,
 ) -> wl_surface::WlSurface
 where
-    F: FnMut(i32, wl_surface::WlSurface, DispatchData) + 'static,
+    F: FnMut(ScaleFactor, wl_surface::WlSurface, DispatchData) + 'static,
+{
+    let callbacks = SurfaceCallbacks {
+        scale: callback.map(|c| Rc::new(RefCell::new(c)) as ScaleCallback),
+        output: None,
+    };
+    setup_surface_with_callbacks(surface, callbacks)
+}
+
+/// Set up a surface created by SCTK, with a callback notified when its scale factor changes
+/// and when it enters or leaves an output, receiving its scale factor
+pub(crate) fn setup_surface_with_outputs_callback<F>(
+    surface: Main<wl_surface::WlSurface>,
+    callback: F,
+) -> wl_surface::WlSurface
+where
+    F: FnMut(ScaleFactor, DispatchData) + 'static,
 {
-    let callback = callback.map(|c| Rc::new(RefCell::new(c)));
-    surface.quick_assign(move |surface, event, ddata| {
+    let callback = Rc::new(RefCell::new(callback));
+    let my_callback = callback.clone();
+    let callbacks = SurfaceCallbacks {
+        scale: Some(Rc::new(RefCell::new(
+            move |scale_factor, _, ddata: DispatchData| {
+                (*my_callback.borrow_mut())(scale_factor, ddata)
+            },
+        ))),
+        output: Some(Rc::new(RefCell::new(
+            move |_, surface: wl_surface::WlSurface, ddata: DispatchData| {
+                (*callback.borrow_mut())(get_surface_scale_factor(&surface), ddata)
+            },
+        ))),
+    };
+    setup_surface_with_callbacks(surface, callbacks)
+}
+
+fn setup_surface_with_callbacks(
+    surface: Main<wl_surface::WlSurface>,
+    callbacks: SurfaceCallbacks,
+) -> wl_surface::WlSurface {
+    surface.quick_assign(move |surface, event, mut ddata| {
         let mut user_data = surface
             .as_ref()
             .user_data()
@@ -110,20 +602,36 @@ where
             .unwrap()
             .lock()
             .unwrap();
-        match event {
+        // update the outputs before notifying the callbacks
+        let output_event = match event {
             wl_surface::Event::Enter { output } => {
-                // Passing the callback to be added to output listener
-                user_data.enter(output, surface.detach(), &callback);
+                // Passing the callbacks to be added to output listener
+                user_data.enter(output.clone(), surface.detach(), &callbacks);
+                Some(SurfaceOutputEvent::Enter {
+                    info: with_output_info(&output, OutputInfo::clone),
+                    output,
+                })
             }
             wl_surface::Event::Leave { output } => {
-                user_data.leave(&output);
+                if user_data.leave(&output) {
+                    Some(SurfaceOutputEvent::Leave {
+                        info: with_output_info(&output, OutputInfo::clone),
+                        output,
+                    })
+                } else {
+                    // the output was removed, this was already notified
+                    None
+                }
             }
             _ => unreachable!(),
         };
         let old_scale_factor = user_data.scale_factor;
         let new_scale_factor = user_data.recompute_scale_factor();
         drop(user_data);
-        if let Some(ref cb) = callback {
+        if let (Some(ref cb), Some(event)) = (&callbacks.output, output_event) {
+            (&mut *cb.borrow_mut())(event, surface.detach(), ddata.reborrow());
+        }
+        if let Some(ref cb) = callbacks.scale {
             if old_scale_factor != new_scale_factor {
                 (&mut *cb.borrow_mut())(new_scale_factor, surface.detach(), ddata);
             }
@@ -139,6 +647,14 @@ where
 impl<E: crate::environment::GlobalHandler<wl_compositor::WlCompositor>>
     crate::environment::Environment<E>
 {
+    /// The features of the bound `wl_compositor`
+    ///
+    /// Returns an error if the compositor global is missing.
+    pub fn compositor_caps(&self) -> Result<CompositorCaps, crate::environment::GlobalError> {
+        let compositor = self.try_get_global::<wl_compositor::WlCompositor>()?;
+        Ok(CompositorCaps::from_version(compositor.as_ref().version()))
+    }
+
     /// Create a DPI-aware surface
     ///
     /// This surface will track the outputs it is being displayed on, and compute the
@@ -156,7 +672,7 @@ impl<E: crate::environment::GlobalHandler<wl_compositor::WlCompositor>>
     /// notified whenever the scale factor of this surface change, if you don't want to have to
     /// periodically check it.
     pub fn create_surface_with_scale_callback<
-        F: FnMut(i32, wl_surface::WlSurface, DispatchData) + 'static,
+        F: FnMut(ScaleFactor, wl_surface::WlSurface, DispatchData) + 'static,
     >(
         &self,
         f: F,
@@ -164,13 +680,34 @@ impl<E: crate::environment::GlobalHandler<wl_compositor::WlCompositor>>
         let compositor = self.require_global::<wl_compositor::WlCompositor>();
         setup_surface(compositor.create_surface(), Some(f))
     }
+
+    /// Create a DPI-aware surface with a callback for the outputs it is displayed on
+    ///
+    /// This method is like `create_surface`, but the provided callback will also be
+    /// notified whenever the surface enters or leaves an output, including when an
+    /// output it is displayed on is removed. The list returned by
+    /// [`get_surface_outputs`](../fn.get_surface_outputs.html) is already up to date
+    /// when the callback is invoked.
+    pub fn create_surface_with_output_callback<
+        F: FnMut(SurfaceOutputEvent, wl_surface::WlSurface, DispatchData) + 'static,
+    >(
+        &self,
+        f: F,
+    ) -> wl_surface::WlSurface {
+        let compositor = self.require_global::<wl_compositor::WlCompositor>();
+        let callbacks = SurfaceCallbacks {
+            scale: None,
+            output: Some(Rc::new(RefCell::new(f))),
+        };
+        setup_surface_with_callbacks(compositor.create_surface(), callbacks)
+    }
 }
 
 /// Returns the current suggested scale factor of a surface.
 ///
 /// Panics if the surface was not created using `Environment::create_surface` or
 /// `Environment::create_surface_with_dpi_callback`.
-pub fn get_surface_scale_factor(surface: &wl_surface::WlSurface) -> i32 {
+pub fn get_surface_scale_factor(surface: &wl_surface::WlSurface) -> ScaleFactor {
     surface
         .as_ref()
         .user_data()
@@ -183,6 +720,10 @@ pub fn get_surface_scale_factor(surface: &wl_surface::WlSurface) -> i32 {
 
 /// Returns a list of outputs the surface is displayed on.
 ///
+/// This is a snapshot of the outputs the compositor reported the surface entered,
+/// which is empty until the surface is mapped. Outputs that were removed are not
+/// listed.
+///
 /// Panics if the surface was not created using `Environment::create_surface` or
 /// `Environment::create_surface_with_dpi_callback`.
 pub fn get_surface_outputs(surface: &wl_surface::WlSurface) -> Vec<wl_output::WlOutput> {
@@ -195,6 +736,119 @@ pub fn get_surface_outputs(surface: &wl_surface::WlSurface) -> Vec<wl_output::Wl
         .unwrap()
         .outputs
         .iter()
+        .filter(|(_, factor, _)| factor.is_some())
         .map(|(ref output, _, _)| output.clone())
         .collect()
 }
+
+/// The role of a surface
+///
+/// A surface can only ever have one role: once it was used for a window, it cannot be used
+/// for a layer surface, even after the window was dropped. Giving it another role is a
+/// protocol error, which makes the compositor close the connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SurfaceRole {
+    /// The content of a [`Window`](window/struct.Window.html), or of another toplevel
+    /// shell surface
+    Toplevel,
+    /// A [`Popup`](shell/popup/struct.Popup.html)
+    Popup,
+    /// A [`LayerSurface`](shell/layer/struct.LayerSurface.html)
+    LayerSurface,
+    /// The surface of a cursor
+    Cursor,
+    /// A subsurface
+    Subsurface,
+    /// The icon of a drag'n'drop, given as a [`DragIcon`](data_device/struct.DragIcon.html)
+    DragIcon,
+    /// A role given outside of this crate
+    Other,
+}
+
+impl fmt::Display for SurfaceRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SurfaceRole::Toplevel => "toplevel",
+            SurfaceRole::Popup => "popup",
+            SurfaceRole::LayerSurface => "layer surface",
+            SurfaceRole::Cursor => "cursor",
+            SurfaceRole::Subsurface => "subsurface",
+            SurfaceRole::DragIcon => "drag icon",
+            SurfaceRole::Other => "other",
+        })
+    }
+}
+
+/// The error of giving a role to a surface that already has another one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RoleConflict {
+    /// The role the surface already has
+    pub existing_role: SurfaceRole,
+    /// The role that was requested
+    pub requested_role: SurfaceRole,
+}
+
+impl fmt::Display for RoleConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot use a surface as a {}, it already has the role of a {}",
+            self.requested_role, self.existing_role
+        )
+    }
+}
+
+impl std::error::Error for RoleConflict {}
+
+/// Returns the role of a surface, as known by SCTK
+///
+/// This is the role given to the surface by the helpers of SCTK, or with
+/// [`set_surface_role`](fn.set_surface_role.html). Returns `None` if the surface has no
+/// known role, or was not created by SCTK.
+pub fn get_surface_role(surface: &wl_surface::WlSurface) -> Option<SurfaceRole> {
+    surface
+        .as_ref()
+        .user_data()
+        .get::<Mutex<SurfaceUserData>>()
+        .and_then(|data| data.lock().unwrap().role)
+}
+
+/// Set the role of a surface, as known by SCTK
+///
+/// The helpers of SCTK check the role of the surfaces they are given, and fail with a
+/// [`RoleConflict`](struct.RoleConflict.html) rather than giving them a second role. If you
+/// give a role to a surface yourself, you can record it here so that this is detected as
+/// well. Setting it to `None` disables the check for this surface.
+///
+/// This does nothing if the surface was not created by SCTK, such surfaces are never
+/// checked.
+pub fn set_surface_role(surface: &wl_surface::WlSurface, role: Option<SurfaceRole>) {
+    if let Some(data) = surface.as_ref().user_data().get::<Mutex<SurfaceUserData>>() {
+        data.lock().unwrap().role = role;
+    }
+}
+
+// Record that a surface is given a role, failing if it already has another one
+//
+// Giving again the same role is allowed by the protocol once the previous role object
+// was destroyed.
+pub(crate) fn assign_role(
+    surface: &wl_surface::WlSurface,
+    role: SurfaceRole,
+) -> Result<(), RoleConflict> {
+    let data = match surface.as_ref().user_data().get::<Mutex<SurfaceUserData>>() {
+        Some(data) => data,
+        None => return Ok(()),
+    };
+    let mut data = data.lock().unwrap();
+    match data.role {
+        Some(existing_role) if existing_role != role => Err(RoleConflict {
+            existing_role,
+            requested_role: role,
+        }),
+        _ => {
+            data.role = Some(role);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! A fake compositor for the tests
+//!
+//! The client end of a socket pair is given to a `Display`, and the tests read the requests
+//! sent by SCTK and write the events it receives on the other end. The globals are bound
+//! without being advertised, libwayland-client does not check them.
+//!
+//! The `MockCompositor` instead listens on a socket and answers the roundtrips from its own
+//! thread, for the tests connecting through `$WAYLAND_DISPLAY`.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::{
+        io::IntoRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use byteorder::{ByteOrder, NativeEndian};
+use wayland_client::{
+    protocol::wl_registry, Attached, Display, EventQueue, Interface, Main, Proxy,
+};
+
+/// A request sent by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub(crate) object: u32,
+    pub(crate) opcode: u16,
+    pub(crate) args: Vec<u32>,
+}
+
+pub(crate) struct TestClient {
+    pub(crate) display: Display,
+    // the registry and the globals are attached to it
+    queue: EventQueue,
+    registry: Main<wl_registry::WlRegistry>,
+    // the name of the next global bound
+    next_name: u32,
+    socket: UnixStream,
+    // the bytes read after the last complete request
+    buffer: Vec<u8>,
+}
+
+impl TestClient {
+    pub(crate) fn new() -> TestClient {
+        let (client, server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let display = unsafe { Display::from_fd(client.into_raw_fd()) }.unwrap();
+        let queue = display.create_event_queue();
+        let registry = display.attach(queue.token()).get_registry();
+        let mut client = TestClient {
+            display,
+            queue,
+            registry,
+            next_name: 1,
+            socket: server,
+            buffer: Vec::new(),
+        };
+        // the registry is not part of the requests of the tests
+        client.requests();
+        client
+    }
+
+    /// Bind a global, which is assumed to exist
+    pub(crate) fn bind<I>(&mut self, version: u32) -> Main<I>
+    where
+        I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>,
+    {
+        let name = self.next_name;
+        self.next_name += 1;
+        self.registry.bind::<I>(version, name)
+    }
+
+    /// The registry, to bind globals with the handlers of SCTK
+    pub(crate) fn registry(&self) -> Attached<wl_registry::WlRegistry> {
+        (*self.registry).clone()
+    }
+
+    /// Take the requests sent since the last call
+    pub(crate) fn requests(&mut self) -> Vec<Request> {
+        self.display.flush().unwrap();
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.socket.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => panic!("failed to read the requests: {}", err),
+            }
+        }
+        parse_requests(&mut self.buffer)
+    }
+
+    /// Send an event to an object of the client, its arguments being given as words
+    pub(crate) fn send<I>(&mut self, object: &Proxy<I>, opcode: u16, args: &[u32])
+    where
+        I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>,
+    {
+        self.send_id(object.id(), opcode, args);
+    }
+
+    /// Send an event to the object with this id, for the objects created by SCTK
+    pub(crate) fn send_id(&mut self, id: u32, opcode: u16, args: &[u32]) {
+        self.socket.write_all(&message(id, opcode, args)).unwrap();
+    }
+
+    /// Dispatch the events sent to the client
+    pub(crate) fn dispatch(&mut self) {
+        self.queue.dispatch(&mut (), |_, _, _| {}).unwrap();
+    }
+}
+
+/// A compositor advertising some globals to the client connecting to its socket
+///
+/// It answers the `wl_display.sync` requests and advertises its globals in the registries,
+/// the other requests are recorded without any answer.
+pub(crate) struct MockCompositor {
+    connection: mpsc::Receiver<UnixStream>,
+    thread: JoinHandle<Vec<Request>>,
+}
+
+impl MockCompositor {
+    /// Listen on this socket, replacing any previous one, and serve the first client
+    pub(crate) fn start(socket: &Path, globals: &'static [(&'static str, u32)]) -> MockCompositor {
+        let _ = std::fs::remove_file(socket);
+        let listener = UnixListener::bind(socket).unwrap();
+        let (sender, connection) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            sender.send(stream.try_clone().unwrap()).unwrap();
+            serve(stream, globals)
+        });
+        MockCompositor { connection, thread }
+    }
+
+    /// Close the connection, as if the compositor crashed, and take the requests it recorded
+    pub(crate) fn kill(self) -> Vec<Request> {
+        let stream = self.connection.recv().unwrap();
+        stream.shutdown(std::net::Shutdown::Both).unwrap();
+        self.thread.join().unwrap()
+    }
+}
+
+fn serve(mut stream: UnixStream, globals: &[(&str, u32)]) -> Vec<Request> {
+    let mut buffer = Vec::new();
+    let mut recorded = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let mut serial = 0;
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return recorded,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+        for request in parse_requests(&mut buffer) {
+            let mut events = Vec::new();
+            match (request.object, request.opcode) {
+                // wl_display.sync, answered by wl_callback.done and wl_display.delete_id
+                (1, 0) => {
+                    serial += 1;
+                    events.extend(message(request.args[0], 0, &[serial]));
+                    events.extend(message(1, 1, &[request.args[0]]));
+                }
+                // wl_display.get_registry, answered by a wl_registry.global per global
+                (1, 1) => {
+                    for (name, &(interface, version)) in globals.iter().enumerate() {
+                        let mut args = vec![name as u32 + 1];
+                        args.extend(string(interface));
+                        args.push(version);
+                        events.extend(message(request.args[0], 0, &args));
+                    }
+                }
+                _ => recorded.push(request),
+            }
+            if stream.write_all(&events).is_err() {
+                return recorded;
+            }
+        }
+    }
+}
+
+// Take the complete requests at the start of a buffer
+fn parse_requests(buffer: &mut Vec<u8>) -> Vec<Request> {
+    let mut requests = Vec::new();
+    let mut pos = 0;
+    while buffer.len() >= pos + 8 {
+        let object = NativeEndian::read_u32(&buffer[pos..]);
+        let word = NativeEndian::read_u32(&buffer[pos + 4..]);
+        let size = (word >> 16) as usize;
+        if buffer.len() < pos + size {
+            break;
+        }
+        let args = buffer[pos + 8..pos + size]
+            .chunks_exact(4)
+            .map(NativeEndian::read_u32)
+            .collect();
+        requests.push(Request {
+            object,
+            opcode: word as u16,
+            args,
+        });
+        pos += size;
+    }
+    buffer.drain(..pos);
+    requests
+}
+
+// A wire message, its arguments being given as words
+fn message(object: u32, opcode: u16, args: &[u32]) -> Vec<u8> {
+    let size = 8 + args.len() * 4;
+    let mut message = vec![0u8; size];
+    NativeEndian::write_u32(&mut message[0..], object);
+    NativeEndian::write_u32(&mut message[4..], (size as u32) << 16 | opcode as u32);
+    for (chunk, &arg) in message[8..].chunks_exact_mut(4).zip(args) {
+        NativeEndian::write_u32(chunk, arg);
+    }
+    message
+}
+
+// The words of a string argument: its length with the terminating NUL, and its padded bytes
+fn string(text: &str) -> Vec<u32> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    while bytes.len() % 4 != 0 {
+        bytes.push(0);
+    }
+    let mut words = vec![text.len() as u32 + 1];
+    words.extend(bytes.chunks_exact(4).map(NativeEndian::read_u32));
+    words
+}
+
+/// The number of requests with this opcode sent to this object
+///
+/// The object must be alive, use `count_id` with the id it had otherwise.
+pub(crate) fn count<I>(requests: &[Request], object: &Proxy<I>, opcode: u16) -> usize
+where
+    I: Interface + From<Proxy<I>> + AsRef<Proxy<I>>,
+{
+    count_id(requests, object.id(), opcode)
+}
+
+/// The number of requests with this opcode sent to the object with this id
+pub(crate) fn count_id(requests: &[Request], id: u32, opcode: u16) -> usize {
+    requests
+        .iter()
+        .filter(|r| r.object == id && r.opcode == opcode)
+        .count()
+}
+
+/// The words of an array argument holding these values
+pub(crate) fn array(values: &[u32]) -> Vec<u32> {
+    let mut words = vec![values.len() as u32 * 4];
+    words.extend_from_slice(values);
+    words
+}
@@ -11,21 +11,25 @@ use andrew::{Canvas, Endian};
 
 use wayland_client::protocol::{
     wl_compositor, wl_pointer, wl_seat, wl_shm, wl_subcompositor, wl_subsurface, wl_surface,
+    wl_touch,
 };
 use wayland_client::{Attached, DispatchData};
 
 use super::{
-    ARGBColor, ButtonColorSpec, ButtonState, ColorSpec, Frame, FrameRequest, State, WindowState,
+    ARGBColor, ButtonColorSpec, ButtonState, ColorSpec, Frame, FrameKey, FramePart, FrameRequest,
+    ResizeEdge, State, WindowState,
 };
 use crate::seat::pointer::{ThemeManager, ThemeSpec, ThemedPointer};
+use crate::seat::{record_serial, SerialKind};
 use crate::shm::DoubleMemPool;
+use crate::surface::{CommitTransaction, CompositorCaps, ScaleFactor};
 
 /*
  * Drawing theme definitions
  */
 
-const BORDER_SIZE: u32 = 12;
-const HEADER_SIZE: u32 = 30;
+// the smallest buttons in which the icons fit
+const MIN_BUTTON_SIZE: u32 = 16;
 
 /// Configuration for ConceptFrame
 #[derive(Clone, Debug)]
@@ -60,6 +64,21 @@ pub struct ConceptConfig {
     pub title_font: Option<(String, f32)>,
     /// Color for drawing the title text
     pub title_color: ColorSpec,
+    /// Height of the titlebar, in logical pixels
+    ///
+    /// The titlebar is at least as high as the buttons.
+    pub header_height: u32,
+    /// Size of the side of the buttons, in logical pixels
+    ///
+    /// Buttons are at least 16 pixels large, so that their icons fit.
+    pub button_size: u32,
+    /// Space between the buttons, and between the first button and the right
+    /// edge of the titlebar, in logical pixels
+    pub button_spacing: u32,
+    /// Width of the invisible borders used to resize the window, in logical pixels
+    ///
+    /// Borders are at least 1 pixel wide.
+    pub border_width: u32,
 }
 
 impl Default for ConceptConfig {
@@ -111,6 +130,10 @@ impl Default for ConceptConfig {
             )),
             title_font: Some(("sans".into(), 17.0)),
             title_color: ColorSpec::identical([0xFF, 0x00, 0x00, 0x00].into()),
+            header_height: 30,
+            button_size: 30,
+            button_spacing: 0,
+            border_width: 12,
         }
     }
 }
@@ -119,6 +142,11 @@ impl Default for ConceptConfig {
  * Utilities
  */
 
+// distance a touch point on the titlebar moves before the window is dragged
+const TOUCH_DRAG_THRESHOLD: f64 = 8.0;
+// duration of a touch on the titlebar opening the window menu, in milliseconds
+const TOUCH_LONG_PRESS: u32 = 500;
+
 const HEAD: usize = 0;
 const TOP: usize = 1;
 const BOTTOM: usize = 2;
@@ -147,6 +175,45 @@ enum UIButton {
     Close,
 }
 
+// The buttons in the order of the button states
+const BUTTONS: [UIButton; 3] = [UIButton::Close, UIButton::Maximize, UIButton::Minimize];
+
+// The sizes of the elements of the frame, sanitized from the config
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Metrics {
+    header: u32,
+    button: u32,
+    spacing: u32,
+    border: u32,
+}
+
+impl Metrics {
+    fn new(config: &ConceptConfig) -> Metrics {
+        let button = max(config.button_size, MIN_BUTTON_SIZE);
+        Metrics {
+            header: max(config.header_height, button),
+            button,
+            spacing: config.button_spacing,
+            border: max(config.border_width, 1),
+        }
+    }
+
+    // distance between the right edge of a button and the right edge of the titlebar
+    fn button_offset(&self, index: u32) -> u32 {
+        self.spacing + index * (self.button + self.spacing)
+    }
+
+    // whether the button fits in a titlebar of this width
+    fn button_fits(&self, index: u32, width: u32) -> bool {
+        width >= self.button_offset(index) + self.button
+    }
+
+    // distance between the top of the buttons and the top of the titlebar
+    fn button_top(&self) -> u32 {
+        (self.header - self.button) / 2
+    }
+}
+
 struct Part {
     surface: wl_surface::WlSurface,
     subsurface: wl_subsurface::WlSubsurface,
@@ -163,8 +230,8 @@ impl Part {
             crate::surface::setup_surface(
                 compositor.create_surface(),
                 Some(
-                    move |dpi, surface: wl_surface::WlSurface, ddata: DispatchData| {
-                        surface.set_buffer_scale(dpi);
+                    move |dpi: ScaleFactor, surface: wl_surface::WlSurface, ddata: DispatchData| {
+                        surface.set_buffer_scale(dpi.to_i32());
                         surface.commit();
                         (&mut inner.borrow_mut().implem)(FrameRequest::Refresh, 0, ddata);
                     },
@@ -174,8 +241,10 @@ impl Part {
             crate::surface::setup_surface(
                 compositor.create_surface(),
                 Some(
-                    move |dpi, surface: wl_surface::WlSurface, _ddata: DispatchData| {
-                        surface.set_buffer_scale(dpi);
+                    move |dpi: ScaleFactor,
+                          surface: wl_surface::WlSurface,
+                          _ddata: DispatchData| {
+                        surface.set_buffer_scale(dpi.to_i32());
                         surface.commit();
                     },
                 ),
@@ -183,6 +252,7 @@ impl Part {
         };
 
         let subsurface = subcompositor.get_subsurface(&surface, parent);
+        crate::surface::set_surface_role(&surface, Some(crate::surface::SurfaceRole::Subsurface));
 
         Part {
             surface,
@@ -204,6 +274,18 @@ struct PointerUserData {
     seat: wl_seat::WlSeat,
 }
 
+// A touch point followed from its down event
+struct TouchPoint {
+    id: i32,
+    location: Location,
+    start: (f64, f64),
+    position: (f64, f64),
+    serial: u32,
+    time: u32,
+    // a request was already sent for this touch point
+    handled: bool,
+}
+
 /*
  * The core frame
  */
@@ -215,9 +297,19 @@ struct Inner {
     implem: Box<dyn FnMut(FrameRequest, u32, DispatchData)>,
     maximized: bool,
     buttons: (bool, bool, bool),
+    metrics: Metrics,
 }
 
 impl Inner {
+    // A transaction updating all the parts of the frame
+    fn transaction(&self, base_surface: &wl_surface::WlSurface) -> CommitTransaction {
+        let mut transaction = CommitTransaction::begin(base_surface);
+        for part in &self.parts {
+            transaction.add_subsurface(&part.surface, &part.subsurface, false);
+        }
+        transaction
+    }
+
     fn find_surface(&self, surface: &wl_surface::WlSurface) -> Location {
         if surface.as_ref().equals(&self.parts[HEAD].surface.as_ref()) {
             Location::Head
@@ -244,14 +336,16 @@ fn precise_location(
     x: f64,
     y: f64,
     buttons: (bool, bool, bool),
+    metrics: Metrics,
 ) -> Location {
+    let border = f64::from(metrics.border);
     match old {
-        Location::Head | Location::Button(_) => find_button(x, y, width, buttons),
+        Location::Head | Location::Button(_) => find_button(x, y, width, buttons, metrics),
 
         Location::Top | Location::TopLeft | Location::TopRight => {
-            if x <= f64::from(BORDER_SIZE) {
+            if x <= border {
                 Location::TopLeft
-            } else if x >= f64::from(width + BORDER_SIZE) {
+            } else if x >= f64::from(width) + border {
                 Location::TopRight
             } else {
                 Location::Top
@@ -259,9 +353,9 @@ fn precise_location(
         }
 
         Location::Bottom | Location::BottomLeft | Location::BottomRight => {
-            if x <= f64::from(BORDER_SIZE) {
+            if x <= border {
                 Location::BottomLeft
-            } else if x >= f64::from(width + BORDER_SIZE) {
+            } else if x >= f64::from(width) + border {
                 Location::BottomRight
             } else {
                 Location::Bottom
@@ -272,46 +366,42 @@ fn precise_location(
     }
 }
 
-fn find_button(x: f64, y: f64, w: u32, buttons: (bool, bool, bool)) -> Location {
-    if (w >= HEADER_SIZE)
-        && (x >= f64::from(w - HEADER_SIZE))
-        && (x <= f64::from(w))
-        && (y <= f64::from(HEADER_SIZE))
-        && (y >= f64::from(0))
-    {
-        // first button
-        match buttons {
-            (true, _, _) => Location::Button(UIButton::Close),
-            (false, true, _) => Location::Button(UIButton::Maximize),
-            (false, false, true) => Location::Button(UIButton::Minimize),
-            _ => Location::Head,
-        }
-    } else if (w >= 2 * HEADER_SIZE)
-        && (x >= f64::from(w - 2 * HEADER_SIZE))
-        && (x <= f64::from(w - HEADER_SIZE))
-        && (y <= f64::from(HEADER_SIZE))
-        && (y >= f64::from(0))
-    {
-        // second button
-        match buttons {
-            (true, true, _) => Location::Button(UIButton::Maximize),
-            (false, true, true) => Location::Button(UIButton::Minimize),
-            _ => Location::Head,
+fn find_button(x: f64, y: f64, w: u32, buttons: (bool, bool, bool), metrics: Metrics) -> Location {
+    // the buttons which are drawn, from right to left
+    let drawn = [
+        (buttons.0, UIButton::Close),
+        (buttons.1, UIButton::Maximize),
+        (buttons.2, UIButton::Minimize),
+    ];
+    let top = f64::from(metrics.button_top());
+    if y < top || y > top + f64::from(metrics.button) {
+        return Location::Head;
+    }
+    for (index, &(_, button)) in drawn.iter().filter(|&&(shown, _)| shown).enumerate() {
+        let index = index as u32;
+        if !metrics.button_fits(index, w) {
+            break;
         }
-    } else if (w >= 3 * HEADER_SIZE)
-        && (x >= f64::from(w - 3 * HEADER_SIZE))
-        && (x <= f64::from(w - 2 * HEADER_SIZE))
-        && (y <= f64::from(HEADER_SIZE))
-        && (y >= f64::from(0))
-    {
-        // third button
-        match buttons {
-            (true, true, true) => Location::Button(UIButton::Minimize),
-            _ => Location::Head,
+        let right = f64::from(w - metrics.button_offset(index));
+        if x >= right - f64::from(metrics.button) && x <= right {
+            return Location::Button(button);
         }
-    } else {
-        Location::Head
     }
+    Location::Head
+}
+
+// The header as it was last drawn, so that only the buttons whose state
+// changed are redrawn when the pointer moves over them
+struct HeaderCache {
+    pixels: Vec<u8>,
+    width: u32,
+    scale: u32,
+    active: WindowState,
+    maximized: bool,
+    // the states of the close, maximize and minimize buttons
+    buttons: [ButtonState; 3],
+    // the index of the button with the keyboard focus
+    focus: Option<usize>,
 }
 
 /// A clean, modern and stylish set of decorations
@@ -320,16 +410,23 @@ fn find_button(x: f64, y: f64, w: u32, buttons: (bool, bool, bool)) -> Location
 /// buttons inspired by breeze, material hover shade and
 /// a white header background
 pub struct ConceptFrame {
+    base_surface: wl_surface::WlSurface,
     inner: Rc<RefCell<Inner>>,
     pools: DoubleMemPool,
     active: WindowState,
     hidden: bool,
     pointers: Vec<ThemedPointer>,
+    touches: Vec<(wl_touch::WlTouch, wl_seat::WlSeat)>,
     themer: ThemeManager,
-    surface_version: u32,
+    caps: CompositorCaps,
     config: ConceptConfig,
     title: Option<String>,
     font_data: Option<Vec<u8>>,
+    header_cache: Option<HeaderCache>,
+    // the button with the keyboard focus, while navigating the buttons with the keyboard
+    key_focus: Option<UIButton>,
+    // the format of the header when its background is opaque
+    opaque_format: wl_shm::Format,
 }
 
 impl Frame for ConceptFrame {
@@ -349,6 +446,7 @@ impl Frame for ConceptFrame {
             implem: implementation,
             maximized: false,
             buttons: (true, true, true),
+            metrics: Metrics::new(&ConceptConfig::default()),
         }));
 
         inner.borrow_mut().parts = vec![
@@ -371,16 +469,21 @@ impl Frame for ConceptFrame {
             (&mut my_inner.borrow_mut().implem)(FrameRequest::Refresh, 0, ddata);
         })?;
         Ok(ConceptFrame {
+            base_surface: base_surface.clone(),
             inner,
             pools,
             active: WindowState::Inactive,
             hidden: false,
             pointers: Vec::new(),
+            touches: Vec::new(),
             themer: ThemeManager::init(ThemeSpec::System, compositor.clone(), shm.clone()),
-            surface_version: compositor.as_ref().version(),
+            caps: CompositorCaps::from_version(compositor.as_ref().version()),
             config: ConceptConfig::default(),
             title: None,
             font_data: None,
+            header_cache: None,
+            key_focus: None,
+            opaque_format: crate::shm::preferred_format_of(shm, true),
         })
     }
 
@@ -406,6 +509,7 @@ impl Frame for ConceptFrame {
                             surface_x,
                             surface_y,
                             inner.buttons,
+                            inner.metrics,
                         );
                         data.position = (surface_x, surface_y);
                         if inner.resizable {
@@ -429,6 +533,7 @@ impl Frame for ConceptFrame {
                             surface_x,
                             surface_y,
                             inner.buttons,
+                            inner.metrics,
                         );
                         if newpos != data.location {
                             match (newpos, data.location) {
@@ -496,6 +601,155 @@ impl Frame for ConceptFrame {
         });
     }
 
+    fn new_touch_seat(&mut self, seat: &Attached<wl_seat::WlSeat>) {
+        use self::wl_touch::Event;
+        let inner = self.inner.clone();
+        let my_seat = seat.detach();
+        // only the first touch point is followed, the other fingers do not start
+        // any other grab
+        let mut point: Option<TouchPoint> = None;
+        let touch = seat.get_touch();
+        touch.quick_assign(move |_, event, ddata| {
+            let mut inner = inner.borrow_mut();
+            match event {
+                Event::Down {
+                    serial,
+                    time,
+                    surface,
+                    id,
+                    x,
+                    y,
+                } => {
+                    record_serial(&my_seat, SerialKind::TouchDown, serial);
+                    if let Some(ref mut first) = point {
+                        // a second finger on the titlebar opens the window menu
+                        if !first.handled
+                            && first.location == Location::Head
+                            && inner.find_surface(&surface) == Location::Head
+                        {
+                            first.handled = true;
+                            let (x, y) = first.position;
+                            let req = FrameRequest::ShowMenu(my_seat.clone(), x as i32, y as i32);
+                            (&mut inner.implem)(req, first.serial, ddata);
+                        }
+                        return;
+                    }
+                    let location = precise_location(
+                        inner.find_surface(&surface),
+                        inner.size.0,
+                        x,
+                        y,
+                        inner.buttons,
+                        inner.metrics,
+                    );
+                    let handled = match location {
+                        Location::None => return,
+                        // wait to know whether the titlebar is dragged or long pressed, and
+                        // whether the finger is lifted over the button
+                        Location::Head | Location::Button(_) => false,
+                        _ => {
+                            let req = request_for_location(
+                                location,
+                                &my_seat,
+                                inner.maximized,
+                                inner.resizable,
+                            );
+                            if let Some(req) = req {
+                                (&mut inner.implem)(req, serial, ddata);
+                            }
+                            true
+                        }
+                    };
+                    point = Some(TouchPoint {
+                        id,
+                        location,
+                        start: (x, y),
+                        position: (x, y),
+                        serial,
+                        time,
+                        handled,
+                    });
+                }
+                Event::Motion { id, x, y, .. } => {
+                    let point = match point {
+                        Some(ref mut point) if point.id == id => point,
+                        _ => return,
+                    };
+                    point.position = (x, y);
+                    let (dx, dy) = (x - point.start.0, y - point.start.1);
+                    if point.location == Location::Head
+                        && !point.handled
+                        && dx.hypot(dy) > TOUCH_DRAG_THRESHOLD
+                    {
+                        point.handled = true;
+                        let req = FrameRequest::Move(my_seat.clone());
+                        (&mut inner.implem)(req, point.serial, ddata);
+                    }
+                }
+                Event::Up { id, time, .. } => {
+                    let point = match point.take() {
+                        Some(point) if point.id == id => point,
+                        other => {
+                            point = other;
+                            return;
+                        }
+                    };
+                    if point.handled {
+                        return;
+                    }
+                    let req = match point.location {
+                        Location::Head if time.wrapping_sub(point.time) >= TOUCH_LONG_PRESS => {
+                            let (x, y) = point.position;
+                            Some(FrameRequest::ShowMenu(my_seat.clone(), x as i32, y as i32))
+                        }
+                        Location::Button(_) => {
+                            // the button is only activated if the finger is lifted over it
+                            let (x, y) = point.position;
+                            let location = precise_location(
+                                point.location,
+                                inner.size.0,
+                                x,
+                                y,
+                                inner.buttons,
+                                inner.metrics,
+                            );
+                            if location == point.location {
+                                request_for_location(
+                                    location,
+                                    &my_seat,
+                                    inner.maximized,
+                                    inner.resizable,
+                                )
+                            } else {
+                                None
+                            }
+                        }
+                        _ => None,
+                    };
+                    if let Some(req) = req {
+                        (&mut inner.implem)(req, point.serial, ddata);
+                    }
+                }
+                Event::Cancel => point = None,
+                _ => {}
+            }
+        });
+        self.touches.push((touch.detach(), seat.detach()));
+    }
+
+    fn remove_touch_seat(&mut self, seat: &wl_seat::WlSeat) {
+        self.touches.retain(|(touch, touch_seat)| {
+            if touch_seat == seat {
+                if touch.as_ref().version() >= 3 {
+                    touch.release();
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+
     fn set_states(&mut self, states: &[State]) -> bool {
         let mut inner = self.inner.borrow_mut();
         let mut need_redraw = false;
@@ -507,6 +761,10 @@ impl Frame for ConceptFrame {
         };
         need_redraw |= new_active != self.active;
         self.active = new_active;
+        // the keyboard navigation ends with the keyboard focus
+        if new_active == WindowState::Inactive && self.key_focus.take().is_some() {
+            need_redraw = true;
+        }
         // process maximized
         let new_maximized = states.contains(&State::Maximized);
         need_redraw |= new_maximized != inner.maximized;
@@ -517,6 +775,9 @@ impl Frame for ConceptFrame {
 
     fn set_hidden(&mut self, hidden: bool) {
         self.hidden = hidden;
+        if hidden {
+            self.key_focus = None;
+        }
     }
 
     fn set_resizable(&mut self, resizable: bool) {
@@ -528,164 +789,96 @@ impl Frame for ConceptFrame {
     }
 
     fn redraw(&mut self) {
-        let inner = self.inner.borrow_mut();
+        let inner_rc = Rc::clone(&self.inner);
+        let inner = inner_rc.borrow_mut();
 
         if self.hidden {
             // don't draw the borders
+            let transaction = inner.transaction(&self.base_surface);
             for p in &inner.parts {
                 p.surface.attach(None, 0, 0);
-                p.surface.commit();
             }
+            transaction.commit_subsurfaces();
             return;
         }
 
         let scales: Vec<u32> = inner
             .parts
             .iter()
-            .map(|part| crate::surface::get_surface_scale_factor(&part.surface) as u32)
+            .map(|part| crate::surface::get_surface_scale_factor(&part.surface).get())
             .collect();
 
         let (width, height) = inner.size;
+        let metrics = inner.metrics;
 
         // Use header scale for all the thing.
         let header_scale = scales[HEAD];
 
-        let scaled_header_height = HEADER_SIZE * header_scale;
+        let scaled_header_height = metrics.header * header_scale;
         let scaled_header_width = width * header_scale;
 
+        // only draw if a pool is available, so that the header cache matches what is displayed
+        if self.pools.pool().is_none() {
+            return;
+        }
+
+        let mouses = self
+            .pointers
+            .iter()
+            .flat_map(|p| {
+                if p.as_ref().is_alive() {
+                    let data: &RefCell<PointerUserData> = p.as_ref().user_data().get().unwrap();
+                    Some(data.borrow().location)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<Location>>();
+        let focus = self
+            .key_focus
+            .and_then(|button| BUTTONS.iter().position(|&b| b == button));
+        let header_damage = self.draw_header(
+            width,
+            header_scale,
+            metrics,
+            button_states(true, &mouses),
+            focus,
+            inner.maximized,
+        );
+
         {
             // grab the current pool
             let pool = match self.pools.pool() {
                 Some(pool) => pool,
                 None => return,
             };
+            // the parts are applied together with the next commit of the content, so that
+            // they follow its size and scale
+            let transaction = inner.transaction(&self.base_surface);
             let lr_surfaces_scale = max(scales[LEFT], scales[RIGHT]);
             let tp_surfaces_scale = max(scales[TOP], scales[BOTTOM]);
 
             // resize the pool as appropriate
             let pxcount = (scaled_header_height * scaled_header_width)
                 + max(
-                    (width + 2 * BORDER_SIZE) * BORDER_SIZE * tp_surfaces_scale * tp_surfaces_scale,
-                    (height + HEADER_SIZE) * BORDER_SIZE * lr_surfaces_scale * lr_surfaces_scale,
+                    (width + 2 * metrics.border)
+                        * metrics.border
+                        * tp_surfaces_scale
+                        * tp_surfaces_scale,
+                    (height + metrics.header)
+                        * metrics.border
+                        * lr_surfaces_scale
+                        * lr_surfaces_scale,
                 );
 
             pool.resize(4 * pxcount as usize)
                 .expect("I/O Error while redrawing the borders");
 
-            // draw the white header bar
+            // copy the header, which is kept up to date in its cache
             {
                 let mmap = pool.mmap();
-                {
-                    let color = self.config.primary_color.get_for(self.active).into();
-
-                    let mut header_canvas = Canvas::new(
-                        &mut mmap
-                            [0..scaled_header_height as usize * scaled_header_width as usize * 4],
-                        scaled_header_width as usize,
-                        scaled_header_height as usize,
-                        scaled_header_width as usize * 4,
-                        Endian::native(),
-                    );
-                    header_canvas.clear();
-
-                    let header_bar = rectangle::Rectangle::new(
-                        (0, 0),
-                        (scaled_header_width as usize, scaled_header_height as usize),
-                        None,
-                        Some(color),
-                    );
-                    header_canvas.draw(&header_bar);
-
-                    draw_buttons(
-                        &mut header_canvas,
-                        width,
-                        header_scale,
-                        true,
-                        self.active,
-                        &self
-                            .pointers
-                            .iter()
-                            .flat_map(|p| {
-                                if p.as_ref().is_alive() {
-                                    let data: &RefCell<PointerUserData> =
-                                        p.as_ref().user_data().get().unwrap();
-                                    Some(data.borrow().location)
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect::<Vec<Location>>(),
-                        &self.config,
-                    );
-                    if let Some((ref font_face, font_size)) = self.config.title_font {
-                        if let Some(title) = self.title.clone() {
-                            // If theres no stored font data, find the first ttf regular sans font and
-                            // store it
-                            if self.font_data.is_none() {
-                                if let Some(font) = fontconfig::FontConfig::new()
-                                    .unwrap()
-                                    .get_regular_family_fonts(&font_face)
-                                    .unwrap()
-                                    .iter()
-                                    .find(|p| p.extension().map(|e| e == "ttf").unwrap_or(false))
-                                {
-                                    let mut font_data = Vec::new();
-                                    if let Ok(mut file) = ::std::fs::File::open(font) {
-                                        match file.read_to_end(&mut font_data) {
-                                            Ok(_) => self.font_data = Some(font_data),
-                                            Err(err) => {
-                                                log::error!("Could not read font file: {}", err)
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-
-                            // Create text from stored title and font data
-                            if let Some(ref font_data) = self.font_data {
-                                let title_color = self.config.title_color.get_for(self.active);
-                                let mut title_text = text::Text::new(
-                                    (
-                                        0,
-                                        (HEADER_SIZE as usize / 2)
-                                            .saturating_sub((font_size / 2.0).ceil() as usize)
-                                            * header_scale as usize,
-                                    ),
-                                    title_color.into(),
-                                    font_data,
-                                    font_size * header_scale as f32,
-                                    1.0,
-                                    title,
-                                );
-
-                                let mut button_count = 0isize;
-                                if self.config.close_button.is_some() {
-                                    button_count += 1;
-                                }
-                                if self.config.maximize_button.is_some() {
-                                    button_count += 1;
-                                }
-                                if self.config.minimize_button.is_some() {
-                                    button_count += 1;
-                                }
-
-                                let scaled_button_size =
-                                    HEADER_SIZE as isize * header_scale as isize;
-                                let button_space = button_count * scaled_button_size;
-                                let scaled_header_width = width as isize * header_scale as isize;
-
-                                // Check if text is bigger then the available width
-                                if (scaled_header_width - button_space)
-                                    > (title_text.get_width() as isize + scaled_button_size)
-                                {
-                                    title_text.pos.0 =
-                                        (scaled_header_width - button_space) as usize / 2
-                                            - (title_text.get_width() / 2);
-                                    header_canvas.draw(&title_text);
-                                }
-                            }
-                        }
-                    }
+                if let Some(ref cache) = self.header_cache {
+                    mmap[..cache.pixels.len()].copy_from_slice(&cache.pixels);
                 }
 
                 // For each pixel in borders
@@ -703,157 +896,125 @@ impl Frame for ConceptFrame {
 
             // Create the buffers
             // -> head-subsurface
+            // the borders are transparent, but the header is opaque unless its color is not
+            let header_format = if self.config.primary_color.get_for(self.active).a == 255 {
+                self.opaque_format
+            } else {
+                wl_shm::Format::Argb8888
+            };
             let buffer = pool.buffer(
                 0,
                 scaled_header_width as i32,
                 scaled_header_height as i32,
                 4 * scaled_header_width as i32,
-                wl_shm::Format::Argb8888,
+                header_format,
             );
             inner.parts[HEAD]
                 .subsurface
-                .set_position(0, -(HEADER_SIZE as i32));
+                .set_position(0, -(metrics.header as i32));
             inner.parts[HEAD].surface.attach(Some(&buffer), 0, 0);
-            if self.surface_version >= 4 {
-                inner.parts[HEAD].surface.damage_buffer(
+            if let Some(rects) = header_damage {
+                // only some buttons were redrawn
+                for (x, y, w, h) in rects {
+                    self.caps
+                        .damage_buffer(&inner.parts[HEAD].surface, x, y, w, h, header_scale);
+                }
+            } else {
+                self.caps.damage_buffer(
+                    &inner.parts[HEAD].surface,
                     0,
                     0,
                     scaled_header_width as i32,
                     scaled_header_height as i32,
+                    header_scale,
                 );
-            } else {
-                // surface is old and does not support damage_buffer, so we damage
-                // in surface coordinates and hope it is not rescaled
-                inner.parts[HEAD]
-                    .surface
-                    .damage(0, 0, width as i32, HEADER_SIZE as i32);
             }
-            inner.parts[HEAD].surface.commit();
 
             // -> top-subsurface
             let buffer = pool.buffer(
                 4 * (scaled_header_width * scaled_header_height) as i32,
-                ((width + 2 * BORDER_SIZE) * scales[TOP]) as i32,
-                (BORDER_SIZE * scales[TOP]) as i32,
-                (4 * scales[TOP] * (width + 2 * BORDER_SIZE)) as i32,
+                ((width + 2 * metrics.border) * scales[TOP]) as i32,
+                (metrics.border * scales[TOP]) as i32,
+                (4 * scales[TOP] * (width + 2 * metrics.border)) as i32,
                 wl_shm::Format::Argb8888,
             );
             inner.parts[TOP].subsurface.set_position(
-                -(BORDER_SIZE as i32),
-                -(HEADER_SIZE as i32 + BORDER_SIZE as i32),
+                -(metrics.border as i32),
+                -(metrics.header as i32 + metrics.border as i32),
             );
             inner.parts[TOP].surface.attach(Some(&buffer), 0, 0);
-            if self.surface_version >= 4 {
-                inner.parts[TOP].surface.damage_buffer(
-                    0,
-                    0,
-                    ((width + 2 * BORDER_SIZE) * scales[TOP]) as i32,
-                    (BORDER_SIZE * scales[TOP]) as i32,
-                );
-            } else {
-                // surface is old and does not support damage_buffer, so we damage
-                // in surface coordinates and hope it is not rescaled
-                inner.parts[TOP].surface.damage(
-                    0,
-                    0,
-                    (width + 2 * BORDER_SIZE) as i32,
-                    BORDER_SIZE as i32,
-                );
-            }
-            inner.parts[TOP].surface.commit();
+            self.caps.damage_buffer(
+                &inner.parts[TOP].surface,
+                0,
+                0,
+                ((width + 2 * metrics.border) * scales[TOP]) as i32,
+                (metrics.border * scales[TOP]) as i32,
+                scales[TOP],
+            );
 
             // -> bottom-subsurface
             let buffer = pool.buffer(
                 4 * (scaled_header_width * scaled_header_height) as i32,
-                ((width + 2 * BORDER_SIZE) * scales[BOTTOM]) as i32,
-                (BORDER_SIZE * scales[BOTTOM]) as i32,
-                (4 * scales[BOTTOM] * (width + 2 * BORDER_SIZE)) as i32,
+                ((width + 2 * metrics.border) * scales[BOTTOM]) as i32,
+                (metrics.border * scales[BOTTOM]) as i32,
+                (4 * scales[BOTTOM] * (width + 2 * metrics.border)) as i32,
                 wl_shm::Format::Argb8888,
             );
             inner.parts[BOTTOM]
                 .subsurface
-                .set_position(-(BORDER_SIZE as i32), height as i32);
+                .set_position(-(metrics.border as i32), height as i32);
             inner.parts[BOTTOM].surface.attach(Some(&buffer), 0, 0);
-            if self.surface_version >= 4 {
-                inner.parts[BOTTOM].surface.damage_buffer(
-                    0,
-                    0,
-                    ((width + 2 * BORDER_SIZE) * scales[BOTTOM]) as i32,
-                    (BORDER_SIZE * scales[BOTTOM]) as i32,
-                );
-            } else {
-                // surface is old and does not support damage_buffer, so we damage
-                // in surface coordinates and hope it is not rescaled
-                inner.parts[BOTTOM].surface.damage(
-                    0,
-                    0,
-                    (width + 2 * BORDER_SIZE) as i32,
-                    BORDER_SIZE as i32,
-                );
-            }
-            inner.parts[BOTTOM].surface.commit();
+            self.caps.damage_buffer(
+                &inner.parts[BOTTOM].surface,
+                0,
+                0,
+                ((width + 2 * metrics.border) * scales[BOTTOM]) as i32,
+                (metrics.border * scales[BOTTOM]) as i32,
+                scales[BOTTOM],
+            );
 
             // -> left-subsurface
             let buffer = pool.buffer(
                 4 * (scaled_header_width * scaled_header_height) as i32,
-                (BORDER_SIZE * scales[LEFT]) as i32,
-                ((height + HEADER_SIZE) * scales[LEFT]) as i32,
-                4 * (BORDER_SIZE * scales[LEFT]) as i32,
+                (metrics.border * scales[LEFT]) as i32,
+                ((height + metrics.header) * scales[LEFT]) as i32,
+                4 * (metrics.border * scales[LEFT]) as i32,
                 wl_shm::Format::Argb8888,
             );
             inner.parts[LEFT]
                 .subsurface
-                .set_position(-(BORDER_SIZE as i32), -(HEADER_SIZE as i32));
+                .set_position(-(metrics.border as i32), -(metrics.header as i32));
             inner.parts[LEFT].surface.attach(Some(&buffer), 0, 0);
-            if self.surface_version >= 4 {
-                inner.parts[LEFT].surface.damage_buffer(
-                    0,
-                    0,
-                    (BORDER_SIZE * scales[LEFT]) as i32,
-                    ((height + HEADER_SIZE) * scales[LEFT]) as i32,
-                );
-            } else {
-                // surface is old and does not support damage_buffer, so we damage
-                // in surface coordinates and hope it is not rescaled
-                inner.parts[LEFT].surface.damage(
-                    0,
-                    0,
-                    BORDER_SIZE as i32,
-                    (height + HEADER_SIZE) as i32,
-                );
-            }
-            inner.parts[LEFT].surface.commit();
+            self.caps.damage_buffer(
+                &inner.parts[LEFT].surface,
+                0,
+                0,
+                (metrics.border * scales[LEFT]) as i32,
+                ((height + metrics.header) * scales[LEFT]) as i32,
+                scales[LEFT],
+            );
 
             // -> right-subsurface
             let buffer = pool.buffer(
                 4 * (scaled_header_width * scaled_header_height) as i32,
-                (BORDER_SIZE * scales[RIGHT]) as i32,
-                ((height + HEADER_SIZE) * scales[RIGHT]) as i32,
-                4 * (BORDER_SIZE * scales[RIGHT]) as i32,
+                (metrics.border * scales[RIGHT]) as i32,
+                ((height + metrics.header) * scales[RIGHT]) as i32,
+                4 * (metrics.border * scales[RIGHT]) as i32,
                 wl_shm::Format::Argb8888,
             );
             inner.parts[RIGHT]
                 .subsurface
-                .set_position(width as i32, -(HEADER_SIZE as i32));
+                .set_position(width as i32, -(metrics.header as i32));
             inner.parts[RIGHT].surface.attach(Some(&buffer), 0, 0);
-            if self.surface_version >= 4 {
-                inner.parts[RIGHT].surface.damage_buffer(
-                    0,
-                    0,
-                    (BORDER_SIZE * scales[RIGHT]) as i32,
-                    ((height + HEADER_SIZE) * scales[RIGHT]) as i32,
-                );
-            } else {
-                // surface is old and does not support damage_buffer, so we damage
-                // in surface coordinates and hope it is not rescaled
-                inner.parts[RIGHT].surface.damage(
-                    0,
-                    0,
-                    BORDER_SIZE as i32,
-                    (height + HEADER_SIZE) as i32,
-                );
-            }
-            inner.parts[RIGHT].surface.commit();
+            self.caps.damage_buffer(
+                &inner.parts[RIGHT].surface,
+                0,
+                0,
+                (metrics.border * scales[RIGHT]) as i32,
+                ((height + metrics.header) * scales[RIGHT]) as i32,
+                scales[RIGHT],
+            );
+            transaction.commit_subsurfaces();
         }
     }
 
@@ -861,7 +1022,7 @@ impl Frame for ConceptFrame {
         if self.hidden {
             (width, height)
         } else {
-            (width, height - HEADER_SIZE as i32)
+            (width, height - Metrics::new(&self.config).header as i32)
         }
     }
 
@@ -869,7 +1030,7 @@ impl Frame for ConceptFrame {
         if self.hidden {
             (width, height)
         } else {
-            (width, height + HEADER_SIZE as i32)
+            (width, height + Metrics::new(&self.config).header as i32)
         }
     }
 
@@ -877,7 +1038,7 @@ impl Frame for ConceptFrame {
         if self.hidden {
             (0, 0)
         } else {
-            (0, -(HEADER_SIZE as i32))
+            (0, -(Metrics::new(&self.config).header as i32))
         }
     }
 
@@ -889,10 +1050,252 @@ impl Frame for ConceptFrame {
             self.config.maximize_button.is_some(),
             self.config.minimize_button.is_some(),
         );
+        inner.metrics = Metrics::new(&self.config);
+        self.header_cache = None;
     }
 
     fn set_title(&mut self, title: String) {
         self.title = Some(title);
+        self.header_cache = None;
+    }
+
+    fn on_key(&mut self, key: FrameKey, requests: &mut Vec<FrameRequest>) -> bool {
+        let inner = self.inner.borrow();
+        // the shown buttons, from left to right
+        let buttons = shown_buttons(inner.size.0, &self.config, inner.metrics)
+            .into_iter()
+            .rev()
+            .map(|(index, _)| BUTTONS[index])
+            .collect::<Vec<_>>();
+        let focus = match self.key_focus {
+            Some(focus) => focus,
+            None => {
+                if key != FrameKey::Navigate || self.hidden || self.active != WindowState::Active {
+                    return false;
+                }
+                // start on the rightmost button, the close button if it is shown
+                match buttons.last() {
+                    Some(&button) => {
+                        self.key_focus = Some(button);
+                        requests.push(FrameRequest::Refresh);
+                        return true;
+                    }
+                    None => return false,
+                }
+            }
+        };
+        if buttons.is_empty() {
+            // the window became too narrow to show any button
+            self.key_focus = None;
+            requests.push(FrameRequest::Refresh);
+            return true;
+        }
+        let position = buttons.iter().position(|&b| b == focus).unwrap_or(0);
+        match key {
+            FrameKey::Navigate | FrameKey::Exit => self.key_focus = None,
+            FrameKey::Previous => {
+                let position = (position + buttons.len() - 1) % buttons.len();
+                self.key_focus = Some(buttons[position]);
+            }
+            FrameKey::Next => {
+                let position = (position + 1) % buttons.len();
+                self.key_focus = Some(buttons[position]);
+            }
+            FrameKey::Activate => {
+                self.key_focus = None;
+                // the button may no longer be shown if the window was resized
+                if buttons.contains(&focus) {
+                    requests.push(button_request(focus, inner.maximized));
+                }
+            }
+        }
+        requests.push(FrameRequest::Refresh);
+        true
+    }
+
+    fn hit_test(&self, surface: &wl_surface::WlSurface, x: f64, y: f64) -> FramePart {
+        if surface.as_ref().equals(self.base_surface.as_ref()) {
+            return FramePart::Content;
+        }
+        if self.hidden {
+            return FramePart::None;
+        }
+        let inner = self.inner.borrow();
+        // the same lookup as the pointer handling of the frame
+        let location = precise_location(
+            inner.find_surface(surface),
+            inner.size.0,
+            x,
+            y,
+            inner.buttons,
+            inner.metrics,
+        );
+        frame_part(location, inner.resizable)
+    }
+}
+
+impl ConceptFrame {
+    // Draw the header in its cache, returning the damaged rectangles of the buffer
+    //
+    // When only the state of some buttons changed since the last draw, only these
+    // buttons are redrawn, otherwise the whole header is and `None` is returned.
+    fn draw_header(
+        &mut self,
+        width: u32,
+        header_scale: u32,
+        metrics: Metrics,
+        btn_states: [ButtonState; 3],
+        focus: Option<usize>,
+        maximized: bool,
+    ) -> Option<Vec<(i32, i32, i32, i32)>> {
+        let scaled_header_height = metrics.header * header_scale;
+        let scaled_header_width = width * header_scale;
+        let header_len = scaled_header_height as usize * scaled_header_width as usize * 4;
+
+        if let Some(ref mut cache) = self.header_cache {
+            if cache.width == width
+                && cache.scale == header_scale
+                && cache.active == self.active
+                && cache.maximized == maximized
+            {
+                let changed = |i| {
+                    cache.buttons[i] != btn_states[i]
+                        || (cache.focus == Some(i)) != (focus == Some(i))
+                };
+                let changed = [changed(0), changed(1), changed(2)];
+                cache.buttons = btn_states;
+                cache.focus = focus;
+                let mut header_canvas = Canvas::new(
+                    &mut cache.pixels,
+                    scaled_header_width as usize,
+                    scaled_header_height as usize,
+                    scaled_header_width as usize * 4,
+                    Endian::Little,
+                );
+                return Some(redraw_buttons(
+                    &mut header_canvas,
+                    header_scale,
+                    self.active,
+                    btn_states,
+                    focus,
+                    changed,
+                    maximized,
+                    &self.config,
+                    metrics,
+                ));
+            }
+        }
+
+        let mut pixels = vec![0; header_len];
+        {
+            let color = self.config.primary_color.get_for(self.active).into();
+
+            let mut header_canvas = Canvas::new(
+                &mut pixels,
+                scaled_header_width as usize,
+                scaled_header_height as usize,
+                scaled_header_width as usize * 4,
+                Endian::Little,
+            );
+
+            let header_bar = rectangle::Rectangle::new(
+                (0, 0),
+                (scaled_header_width as usize, scaled_header_height as usize),
+                None,
+                Some(color),
+            );
+            header_canvas.draw(&header_bar);
+
+            draw_buttons(
+                &mut header_canvas,
+                width,
+                header_scale,
+                self.active,
+                btn_states,
+                focus,
+                maximized,
+                &self.config,
+                metrics,
+            );
+            if let Some((ref font_face, font_size)) = self.config.title_font {
+                if let Some(ref title) = self.title {
+                    // If theres no stored font data, find the first ttf regular sans font and
+                    // store it
+                    if self.font_data.is_none() {
+                        if let Some(font) = fontconfig::FontConfig::new()
+                            .unwrap()
+                            .get_regular_family_fonts(&font_face)
+                            .unwrap()
+                            .iter()
+                            .find(|p| p.extension().map(|e| e == "ttf").unwrap_or(false))
+                        {
+                            let mut font_data = Vec::new();
+                            if let Ok(mut file) = ::std::fs::File::open(font) {
+                                match file.read_to_end(&mut font_data) {
+                                    Ok(_) => self.font_data = Some(font_data),
+                                    Err(err) => {
+                                        log::error!("Could not read font file: {}", err)
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    // Create text from stored title and font data
+                    if let Some(ref font_data) = self.font_data {
+                        let title_color = self.config.title_color.get_for(self.active);
+                        let mut title_text = text::Text::new(
+                            (
+                                0,
+                                (metrics.header as usize / 2)
+                                    .saturating_sub((font_size / 2.0).ceil() as usize)
+                                    * header_scale as usize,
+                            ),
+                            title_color.into(),
+                            font_data,
+                            font_size * header_scale as f32,
+                            1.0,
+                            title,
+                        );
+
+                        let mut button_count = 0isize;
+                        if self.config.close_button.is_some() {
+                            button_count += 1;
+                        }
+                        if self.config.maximize_button.is_some() {
+                            button_count += 1;
+                        }
+                        if self.config.minimize_button.is_some() {
+                            button_count += 1;
+                        }
+
+                        let scaled_button_size =
+                            (metrics.button + metrics.spacing) as isize * header_scale as isize;
+                        let button_space = button_count * scaled_button_size;
+                        let scaled_header_width = width as isize * header_scale as isize;
+
+                        // Check if text is bigger then the available width
+                        if (scaled_header_width - button_space)
+                            > (title_text.get_width() as isize + scaled_button_size)
+                        {
+                            title_text.pos.0 = (scaled_header_width - button_space) as usize / 2
+                                - (title_text.get_width() / 2);
+                            header_canvas.draw(&title_text);
+                        }
+                    }
+                }
+            }
+        }
+        self.header_cache = Some(HeaderCache {
+            pixels,
+            width,
+            scale: header_scale,
+            active: self.active,
+            maximized,
+            buttons: btn_states,
+            focus,
+        });
+        None
     }
 }
 
@@ -903,6 +1306,11 @@ impl Drop for ConceptFrame {
                 ptr.release();
             }
         }
+        for (touch, _) in self.touches.drain(..) {
+            if touch.as_ref().version() >= 3 {
+                touch.release();
+            }
+        }
     }
 }
 
@@ -918,8 +1326,26 @@ fn change_pointer(pointer: &ThemedPointer, location: Location, serial: Option<u3
         Location::TopLeft => "top_left_corner",
         _ => "left_ptr",
     };
-    if pointer.set_cursor(name, serial).is_err() {
-        log::error!("Failed to set cursor");
+    if let Err(err) = pointer.set_cursor(name, serial) {
+        log::warn!("[SCTK] Failed to set the cursor {}: {}", name, err);
+    }
+}
+
+fn frame_part(location: Location, resizable: bool) -> FramePart {
+    match location {
+        Location::Head => FramePart::Header,
+        Location::Button(UIButton::Close) => FramePart::CloseButton,
+        Location::Button(UIButton::Maximize) => FramePart::MaximizeButton,
+        Location::Button(UIButton::Minimize) => FramePart::MinimizeButton,
+        Location::Top if resizable => FramePart::Border(ResizeEdge::Top),
+        Location::TopLeft if resizable => FramePart::Border(ResizeEdge::TopLeft),
+        Location::Left if resizable => FramePart::Border(ResizeEdge::Left),
+        Location::BottomLeft if resizable => FramePart::Border(ResizeEdge::BottomLeft),
+        Location::Bottom if resizable => FramePart::Border(ResizeEdge::Bottom),
+        Location::BottomRight if resizable => FramePart::Border(ResizeEdge::BottomRight),
+        Location::Right if resizable => FramePart::Border(ResizeEdge::Right),
+        Location::TopRight if resizable => FramePart::Border(ResizeEdge::TopRight),
+        _ => FramePart::None,
     }
 }
 
@@ -929,7 +1355,6 @@ fn request_for_location(
     maximized: bool,
     resizable: bool,
 ) -> Option<FrameRequest> {
-    use wayland_protocols::xdg_shell::client::xdg_toplevel::ResizeEdge;
     match location {
         Location::Top if resizable => Some(FrameRequest::Resize(seat.clone(), ResizeEdge::Top)),
         Location::TopLeft if resizable => {
@@ -950,19 +1375,20 @@ fn request_for_location(
             Some(FrameRequest::Resize(seat.clone(), ResizeEdge::TopRight))
         }
         Location::Head => Some(FrameRequest::Move(seat.clone())),
-        Location::Button(UIButton::Close) => Some(FrameRequest::Close),
-        Location::Button(UIButton::Maximize) => {
-            if maximized {
-                Some(FrameRequest::UnMaximize)
-            } else {
-                Some(FrameRequest::Maximize)
-            }
-        }
-        Location::Button(UIButton::Minimize) => Some(FrameRequest::Minimize),
+        Location::Button(button) => Some(button_request(button, maximized)),
         _ => None,
     }
 }
 
+fn button_request(button: UIButton, maximized: bool) -> FrameRequest {
+    match button {
+        UIButton::Close => FrameRequest::Close,
+        UIButton::Maximize if maximized => FrameRequest::UnMaximize,
+        UIButton::Maximize => FrameRequest::Maximize,
+        UIButton::Minimize => FrameRequest::Minimize,
+    }
+}
+
 // average of the two colors, approximately taking into account gamma correction
 // result is as transparent as the most transparent color
 fn mix_colors(x: ARGBColor, y: ARGBColor) -> ARGBColor {
@@ -982,21 +1408,63 @@ fn mix_colors(x: ARGBColor, y: ARGBColor) -> ARGBColor {
     }
 }
 
+// The states of the close, maximize and minimize buttons
+fn button_states(maximizable: bool, mouses: &[Location]) -> [ButtonState; 3] {
+    let state = |button| {
+        if mouses.iter().any(|&l| l == Location::Button(button)) {
+            ButtonState::Hovered
+        } else {
+            ButtonState::Idle
+        }
+    };
+    [
+        state(UIButton::Close),
+        if maximizable {
+            state(UIButton::Maximize)
+        } else {
+            ButtonState::Disabled
+        },
+        state(UIButton::Minimize),
+    ]
+}
+
+// The buttons shown in a titlebar of this width, with their index in the button
+// states and their offset from the right edge
+fn shown_buttons(width: u32, config: &ConceptConfig, metrics: Metrics) -> Vec<(usize, u32)> {
+    let configured = [
+        config.close_button.is_some(),
+        config.maximize_button.is_some(),
+        config.minimize_button.is_some(),
+    ];
+    let mut shown = Vec::new();
+    for (i, _) in configured.iter().enumerate().filter(|&(_, &c)| c) {
+        let count = shown.len() as u32;
+        if !metrics.button_fits(count, width) {
+            break;
+        }
+        shown.push((i, metrics.button_offset(count)));
+    }
+    shown
+}
+
+#[allow(clippy::too_many_arguments)]
 fn draw_buttons(
     canvas: &mut Canvas,
     width: u32,
     scale: u32,
-    maximizable: bool,
     state: WindowState,
-    mouses: &[Location],
+    btn_states: [ButtonState; 3],
+    focus: Option<usize>,
+    maximized: bool,
     config: &ConceptConfig,
+    metrics: Metrics,
 ) {
     let scale = scale as usize;
 
     // Draw seperator between header and window contents
     let line_color = config.secondary_color.get_for(state);
     for i in 1..=scale {
-        let y = HEADER_SIZE as usize * scale - i;
+        let y = metrics.header as usize * scale - i;
         let division_line = line::Line::new(
             (0, y),
             (width as usize * scale, y),
@@ -1006,104 +1474,166 @@ fn draw_buttons(
         canvas.draw(&division_line);
     }
 
-    let mut drawn_buttons = 0usize;
-
-    if width >= HEADER_SIZE {
-        if let Some((ref icon_config, ref btn_config)) = config.close_button {
-            // Draw the close button
-            let btn_state = if mouses
-                .iter()
-                .any(|&l| l == Location::Button(UIButton::Close))
-            {
-                ButtonState::Hovered
-            } else {
-                ButtonState::Idle
-            };
-
-            let icon_color = icon_config.get_for(btn_state).get_for(state);
-            let button_color = btn_config.get_for(btn_state).get_for(state);
+    for (i, offset) in shown_buttons(width, config, metrics) {
+        draw_ui_button(
+            canvas,
+            (i, offset),
+            scale,
+            state,
+            btn_states[i],
+            focus == Some(i),
+            maximized,
+            config,
+            metrics,
+        );
+    }
+}
 
-            draw_button(
-                canvas,
-                0,
-                scale,
-                button_color,
-                mix_colors(button_color, line_color),
-            );
-            draw_icon(canvas, 0, scale, icon_color, Icon::Close);
-            drawn_buttons += 1;
+// Redraw the buttons whose state changed over a previously drawn header,
+// returning the rectangles of the buffer that were redrawn
+#[allow(clippy::too_many_arguments)]
+fn redraw_buttons(
+    canvas: &mut Canvas,
+    scale: u32,
+    state: WindowState,
+    btn_states: [ButtonState; 3],
+    focus: Option<usize>,
+    changed: [bool; 3],
+    maximized: bool,
+    config: &ConceptConfig,
+    metrics: Metrics,
+) -> Vec<(i32, i32, i32, i32)> {
+    let width = (canvas.width / scale as usize) as u32;
+    let scale = scale as usize;
+    let line_color = config.secondary_color.get_for(state);
+    let background = config.primary_color.get_for(state);
+    let h = metrics.button as usize;
+    let y_start = metrics.button_top() as usize;
+    let mut damage = Vec::new();
+    for (i, offset) in shown_buttons(width, config, metrics) {
+        if !changed[i] {
+            continue;
         }
+        let x_start = width as usize - h - offset as usize;
+        // restore the header background below the button
+        for y in y_start * scale..(y_start + h) * scale {
+            let row = y * canvas.stride + x_start * scale * 4;
+            for b in &mut canvas.buffer[row..row + h * scale * 4] {
+                *b = 0x00;
+            }
+        }
+        canvas.draw(&rectangle::Rectangle::new(
+            (x_start * scale, y_start * scale),
+            (h * scale, h * scale),
+            None,
+            Some(background.into()),
+        ));
+        if y_start + h == metrics.header as usize {
+            canvas.draw(&rectangle::Rectangle::new(
+                (x_start * scale, (y_start + h - 1) * scale),
+                (h * scale, scale),
+                None,
+                Some(line_color.into()),
+            ));
+        }
+        draw_ui_button(
+            canvas,
+            (i, offset),
+            scale,
+            state,
+            btn_states[i],
+            focus == Some(i),
+            maximized,
+            config,
+            metrics,
+        );
+        damage.push((
+            (x_start * scale) as i32,
+            (y_start * scale) as i32,
+            (h * scale) as i32,
+            (h * scale) as i32,
+        ));
     }
+    damage
+}
 
-    if width as usize >= (drawn_buttons + 1) * HEADER_SIZE as usize {
-        if let Some((ref icon_config, ref btn_config)) = config.maximize_button {
-            let btn_state = if !maximizable {
-                ButtonState::Disabled
-            } else if mouses
-                .iter()
-                .any(|&l| l == Location::Button(UIButton::Maximize))
-            {
-                ButtonState::Hovered
-            } else {
-                ButtonState::Idle
-            };
-
-            let icon_color = icon_config.get_for(btn_state).get_for(state);
-            let button_color = btn_config.get_for(btn_state).get_for(state);
-
-            draw_button(
-                canvas,
-                drawn_buttons * HEADER_SIZE as usize,
-                scale,
-                button_color,
-                mix_colors(button_color, line_color),
-            );
-            draw_icon(
-                canvas,
-                drawn_buttons * HEADER_SIZE as usize,
-                scale,
-                icon_color,
-                Icon::Maximize,
-            );
-            drawn_buttons += 1;
+// Draw a button given by its index in the button states and its offset, and its icon
+#[allow(clippy::too_many_arguments)]
+fn draw_ui_button(
+    canvas: &mut Canvas,
+    (index, x_offset): (usize, u32),
+    scale: usize,
+    state: WindowState,
+    btn_state: ButtonState,
+    focused: bool,
+    maximized: bool,
+    config: &ConceptConfig,
+    metrics: Metrics,
+) {
+    let (colors, icon) = match index {
+        0 => (&config.close_button, Icon::Close),
+        1 if maximized => (&config.maximize_button, Icon::Restore),
+        1 => (&config.maximize_button, Icon::Maximize),
+        _ => (&config.minimize_button, Icon::Minimize),
+    };
+    if let Some((ref icon_config, ref btn_config)) = *colors {
+        let line_color = config.secondary_color.get_for(state);
+        let icon_color = icon_config.get_for(btn_state).get_for(state);
+        let button_color = btn_config.get_for(btn_state).get_for(state);
+
+        let x_offset = x_offset as usize;
+        draw_button(
+            canvas,
+            x_offset,
+            scale,
+            metrics,
+            button_color,
+            mix_colors(button_color, line_color),
+        );
+        draw_icon(canvas, x_offset, scale, metrics, icon_color, icon);
+        if focused {
+            draw_focus_ring(canvas, x_offset, scale, metrics, icon_color);
         }
     }
+}
 
-    if width as usize >= (drawn_buttons + 1) * HEADER_SIZE as usize {
-        if let Some((ref icon_config, ref btn_config)) = config.minimize_button {
-            let btn_state = if mouses
-                .iter()
-                .any(|&l| l == Location::Button(UIButton::Minimize))
-            {
-                ButtonState::Hovered
-            } else {
-                ButtonState::Idle
-            };
-
-            let icon_color = icon_config.get_for(btn_state).get_for(state);
-            let button_color = btn_config.get_for(btn_state).get_for(state);
-
-            draw_button(
-                canvas,
-                drawn_buttons * HEADER_SIZE as usize,
-                scale,
-                button_color,
-                mix_colors(button_color, line_color),
-            );
-            draw_icon(
-                canvas,
-                drawn_buttons * HEADER_SIZE as usize,
-                scale,
-                icon_color,
-                Icon::Minimize,
-            );
-        }
+// Outline a button with the keyboard focus, inside its square
+fn draw_focus_ring(
+    canvas: &mut Canvas,
+    x_offset: usize,
+    scale: usize,
+    metrics: Metrics,
+    color: ARGBColor,
+) {
+    let h = metrics.button as usize;
+    let x_start = canvas.width / scale - h - x_offset;
+    let y_start = metrics.button_top() as usize;
+    // one logical pixel wide, two logical pixels away from the edges of the button
+    let (x0, y0, size) = (
+        (x_start + 2) * scale,
+        (y_start + 2) * scale,
+        (h - 4) * scale,
+    );
+    let edges = [
+        ((x0, y0), (size, scale)),
+        ((x0, y0 + size - scale), (size, scale)),
+        ((x0, y0), (scale, size)),
+        ((x0 + size - scale, y0), (scale, size)),
+    ];
+    for &(position, dimensions) in &edges {
+        canvas.draw(&rectangle::Rectangle::new(
+            position,
+            dimensions,
+            None,
+            Some(color.into()),
+        ));
     }
 }
 
 enum Icon {
     Close,
     Maximize,
+    Restore,
     Minimize,
 }
 
@@ -1111,100 +1641,382 @@ fn draw_button(
     canvas: &mut Canvas,
     x_offset: usize,
     scale: usize,
+    metrics: Metrics,
     btn_color: ARGBColor,
     line_color: ARGBColor,
 ) {
-    let h = HEADER_SIZE as usize;
+    let h = metrics.button as usize;
     let x_start = canvas.width / scale - h - x_offset;
+    let y_start = metrics.button_top() as usize;
+    if y_start + h < metrics.header as usize {
+        // the button does not reach the separation line
+        canvas.draw(&rectangle::Rectangle::new(
+            (x_start * scale, y_start * scale),
+            (h * scale, h * scale),
+            None,
+            Some(btn_color.into()),
+        ));
+        return;
+    }
     // main square
     canvas.draw(&rectangle::Rectangle::new(
-        (x_start * scale, 0),
+        (x_start * scale, y_start * scale),
         (h * scale, (h - 1) * scale),
         None,
         Some(btn_color.into()),
     ));
     // separation line
     canvas.draw(&rectangle::Rectangle::new(
-        (x_start * scale, (h - 1) * scale),
+        (x_start * scale, (y_start + h - 1) * scale),
         (h * scale, scale),
         None,
         Some(line_color.into()),
     ));
 }
 
+// half the size of the icons, and the thickness of their strokes, in logical pixels
+const ICON_RADIUS: f32 = 4.0;
+const ICON_STROKE: f32 = 1.0;
+
+// A shape of an icon, in buffer pixels
+enum Shape {
+    // an axis-aligned rectangle, given by its top left and bottom right corners
+    Rect(f32, f32, f32, f32),
+    // a segment of the given width, with round caps
+    Line((f32, f32), (f32, f32), f32),
+}
+
+impl Shape {
+    // the fraction of the pixel whose top left corner is at (x, y) covered by the shape
+    fn coverage(&self, x: f32, y: f32) -> f32 {
+        match *self {
+            Shape::Rect(x0, y0, x1, y1) => {
+                let w = (x1.min(x + 1.0) - x0.max(x)).max(0.0);
+                let h = (y1.min(y + 1.0) - y0.max(y)).max(0.0);
+                w * h
+            }
+            Shape::Line((x0, y0), (x1, y1), width) => {
+                // distance from the center of the pixel to the segment
+                let (px, py) = (x + 0.5 - x0, y + 0.5 - y0);
+                let (dx, dy) = (x1 - x0, y1 - y0);
+                let len2 = dx * dx + dy * dy;
+                let t = if len2 > 0.0 {
+                    ((px * dx + py * dy) / len2).max(0.0).min(1.0)
+                } else {
+                    0.0
+                };
+                let (ex, ey) = (px - t * dx, py - t * dy);
+                let dist = (ex * ex + ey * ey).sqrt();
+                (width / 2.0 - dist + 0.5).max(0.0).min(1.0)
+            }
+        }
+    }
+
+    fn bounds(&self) -> (f32, f32, f32, f32) {
+        match *self {
+            Shape::Rect(x0, y0, x1, y1) => (x0, y0, x1, y1),
+            Shape::Line((x0, y0), (x1, y1), width) => {
+                let r = width / 2.0 + 1.0;
+                (
+                    x0.min(x1) - r,
+                    y0.min(y1) - r,
+                    x0.max(x1) + r,
+                    y0.max(y1) + r,
+                )
+            }
+        }
+    }
+}
+
+// The outline of a square, as rectangles that do not overlap
+fn square_outline(x0: f32, y0: f32, x1: f32, y1: f32, t: f32) -> Vec<Shape> {
+    vec![
+        Shape::Rect(x0, y0, x1, y0 + t),
+        Shape::Rect(x0, y1 - t, x1, y1),
+        Shape::Rect(x0, y0 + t, x0 + t, y1 - t),
+        Shape::Rect(x1 - t, y0 + t, x1, y1 - t),
+    ]
+}
+
+// The shapes of an icon centered on (cx, cy), in buffer pixels
+fn icon_shapes(icon: Icon, cx: f32, cy: f32, scale: f32) -> Vec<Shape> {
+    let r = ICON_RADIUS * scale;
+    let t = ICON_STROKE * scale;
+    match icon {
+        Icon::Close => {
+            // the caps are round, keep their ends within the icon
+            let r = r - t / 2.0;
+            let w = 1.5 * t;
+            vec![
+                Shape::Line((cx - r, cy - r), (cx + r, cy + r), w),
+                Shape::Line((cx + r, cy - r), (cx - r, cy + r), w),
+            ]
+        }
+        Icon::Maximize => square_outline(cx - r, cy - r, cx + r, cy + r, t),
+        Icon::Restore => {
+            // a front square, and the visible parts of a square behind it, offset
+            // to the top right
+            let d = 2.0 * scale;
+            let mut shapes = square_outline(cx - r, cy - r + d, cx + r - d, cy + r, t);
+            shapes.push(Shape::Rect(cx - r + d, cy - r, cx + r, cy - r + t));
+            shapes.push(Shape::Rect(cx + r - t, cy - r + t, cx + r, cy + r - d));
+            shapes.push(Shape::Rect(
+                cx - r + d,
+                cy - r + t,
+                cx - r + d + t,
+                cy - r + d,
+            ));
+            shapes.push(Shape::Rect(
+                cx + r - d,
+                cy + r - d - t,
+                cx + r - t,
+                cy + r - d,
+            ));
+            shapes
+        }
+        Icon::Minimize => vec![Shape::Rect(cx - r, cy + r - t, cx + r, cy + r)],
+    }
+}
+
+// Draw an icon with antialiasing, at the exact size of the buffer
+//
+// The icons are made of strokes whose edges fall on pixel boundaries at integer
+// scales, so that they stay sharp.
 fn draw_icon(
     canvas: &mut Canvas,
     x_offset: usize,
     scale: usize,
+    metrics: Metrics,
     icon_color: ARGBColor,
     icon: Icon,
 ) {
-    let h = HEADER_SIZE as usize;
-    let cx = canvas.width / scale - h / 2 - x_offset;
-    let cy = h / 2;
-    let s = scale;
-
-    match icon {
-        Icon::Close => {
-            // Draw cross to represent the close button
-            for i in 0..2 * scale {
-                canvas.draw(&line::Line::new(
-                    ((cx - 4) * s + i, (cy - 4) * s),
-                    ((cx + 4) * s, (cy + 4) * s - i),
-                    icon_color.into(),
-                    true,
-                ));
-                canvas.draw(&line::Line::new(
-                    ((cx - 4) * s, (cy - 4) * s + i),
-                    ((cx + 4) * s - i, (cy + 4) * s),
-                    icon_color.into(),
-                    true,
-                ));
-                canvas.draw(&line::Line::new(
-                    ((cx + 4) * s - i, (cy - 4) * s),
-                    ((cx - 4) * s, (cy + 4) * s - i),
-                    icon_color.into(),
-                    true,
-                ));
-                canvas.draw(&line::Line::new(
-                    ((cx + 4) * s, (cy - 4) * s + i),
-                    ((cx - 4) * s + i, (cy + 4) * s),
-                    icon_color.into(),
-                    true,
-                ));
+    let h = metrics.button as usize;
+    let x_start = canvas.width / scale - h - x_offset;
+    let y_start = metrics.button_top() as usize;
+    // snap the center of the button to a pixel boundary of the buffer
+    let cx = ((2 * x_start + h) * scale / 2) as f32;
+    let cy = ((2 * y_start + h) * scale / 2) as f32;
+    let shapes = icon_shapes(icon, cx, cy, scale as f32);
+
+    let (mut x0, mut y0, mut x1, mut y1) = (std::f32::MAX, std::f32::MAX, 0.0f32, 0.0f32);
+    for shape in &shapes {
+        let (sx0, sy0, sx1, sy1) = shape.bounds();
+        x0 = x0.min(sx0);
+        y0 = y0.min(sy0);
+        x1 = x1.max(sx1);
+        y1 = y1.max(sy1);
+    }
+    let x_range = (x0.max(0.0) as usize)..std::cmp::min(x1.ceil() as usize, canvas.width);
+    let y_range = (y0.max(0.0) as usize)..std::cmp::min(y1.ceil() as usize, canvas.height);
+    for y in y_range {
+        for x in x_range.clone() {
+            // the shapes overlapping a pixel are not blended twice
+            let coverage = shapes
+                .iter()
+                .map(|shape| shape.coverage(x as f32, y as f32))
+                .fold(0.0f32, f32::max);
+            if coverage > 0.0 {
+                let alpha = (icon_color.a as f32 * coverage).round() as u8;
+                canvas.draw_point(x, y, [alpha, icon_color.r, icon_color.g, icon_color.b]);
             }
         }
-        Icon::Maximize => {
-            for i in 0..3 * scale {
-                canvas.draw(&line::Line::new(
-                    ((cx - 4) * s - i, (cy + 2) * s),
-                    (cx * s, (cy - 2) * s - i),
-                    icon_color.into(),
-                    true,
-                ));
-                canvas.draw(&line::Line::new(
-                    ((cx + 4) * s + i, (cy + 2) * s),
-                    (cx * s, (cy - 2) * s - i),
-                    icon_color.into(),
-                    true,
-                ));
-            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestClient;
+
+    // the buttons are 20 pixels wide, 4 pixels apart, and 10 pixels below the top
+    const WIDTH: u32 = 200;
+
+    fn config() -> ConceptConfig {
+        ConceptConfig {
+            header_height: 40,
+            button_size: 20,
+            button_spacing: 4,
+            border_width: 8,
+            ..ConceptConfig::default()
         }
-        Icon::Minimize => {
-            for i in 0..3 * scale {
-                canvas.draw(&line::Line::new(
-                    ((cx - 4) * s - i, (cy - 3) * s),
-                    (cx * s, (cy + 1) * s + i),
-                    icon_color.into(),
-                    true,
-                ));
-                canvas.draw(&line::Line::new(
-                    ((cx + 4) * s + i, (cy - 3) * s),
-                    (cx * s, (cy + 1) * s + i),
-                    icon_color.into(),
-                    true,
-                ));
-            }
+    }
+
+    struct Setup {
+        _client: TestClient,
+        base_surface: wl_surface::WlSurface,
+        frame: ConceptFrame,
+    }
+
+    impl Setup {
+        fn part(&self, index: usize) -> wl_surface::WlSurface {
+            self.frame.inner.borrow().parts[index].surface.clone()
         }
+
+        fn hit(&self, index: usize, x: f64, y: f64) -> FramePart {
+            self.frame.hit_test(&self.part(index), x, y)
+        }
+    }
+
+    fn setup(config: ConceptConfig) -> Setup {
+        let mut client = TestClient::new();
+        let compositor = client.bind::<wl_compositor::WlCompositor>(4);
+        let subcompositor = client.bind::<wl_subcompositor::WlSubcompositor>(1);
+        let shm = client.bind::<wl_shm::WlShm>(1);
+        let base_surface = compositor.create_surface().detach();
+        let mut frame = ConceptFrame::init(
+            &base_surface,
+            &compositor,
+            &subcompositor,
+            &shm,
+            Box::new(|_, _, _| {}),
+        )
+        .unwrap();
+        frame.set_config(config);
+        frame.resize((WIDTH, 100));
+        Setup {
+            _client: client,
+            base_surface,
+            frame,
+        }
+    }
+
+    #[test]
+    fn content() {
+        let mut setup = setup(config());
+        assert_eq!(
+            setup.frame.hit_test(&setup.base_surface, 0.0, 0.0),
+            FramePart::Content
+        );
+        // the contents are still there without the decorations
+        setup.frame.set_hidden(true);
+        assert_eq!(
+            setup.frame.hit_test(&setup.base_surface, 0.0, 0.0),
+            FramePart::Content
+        );
+    }
+
+    #[test]
+    fn hidden_frame() {
+        let mut setup = setup(config());
+        setup.frame.set_hidden(true);
+        assert_eq!(setup.hit(HEAD, 100.0, 20.0), FramePart::None);
+        assert_eq!(setup.hit(TOP, 0.0, 0.0), FramePart::None);
+        setup.frame.set_hidden(false);
+        assert_eq!(setup.hit(HEAD, 100.0, 20.0), FramePart::Header);
+    }
+
+    #[test]
+    fn unknown_surface() {
+        let mut client = TestClient::new();
+        let compositor = client.bind::<wl_compositor::WlCompositor>(4);
+        let other = compositor.create_surface().detach();
+        let setup = setup(config());
+        assert_eq!(setup.frame.hit_test(&other, 10.0, 10.0), FramePart::None);
+    }
+
+    #[test]
+    fn button_edges() {
+        let setup = setup(config());
+        let w = f64::from(WIDTH);
+        // both edges of a button belong to it
+        assert_eq!(setup.hit(HEAD, w - 4.0, 20.0), FramePart::CloseButton);
+        assert_eq!(setup.hit(HEAD, w - 24.0, 20.0), FramePart::CloseButton);
+        assert_eq!(setup.hit(HEAD, w - 3.5, 20.0), FramePart::Header);
+        assert_eq!(setup.hit(HEAD, w - 24.5, 20.0), FramePart::Header);
+        assert_eq!(setup.hit(HEAD, w - 28.0, 20.0), FramePart::MaximizeButton);
+        assert_eq!(setup.hit(HEAD, w - 48.0, 20.0), FramePart::MaximizeButton);
+        assert_eq!(setup.hit(HEAD, w - 52.0, 20.0), FramePart::MinimizeButton);
+        assert_eq!(setup.hit(HEAD, w - 72.0, 20.0), FramePart::MinimizeButton);
+        assert_eq!(setup.hit(HEAD, w - 72.5, 20.0), FramePart::Header);
+        // the rows above and below the buttons are part of the titlebar
+        assert_eq!(setup.hit(HEAD, w - 14.0, 10.0), FramePart::CloseButton);
+        assert_eq!(setup.hit(HEAD, w - 14.0, 30.0), FramePart::CloseButton);
+        assert_eq!(setup.hit(HEAD, w - 14.0, 9.5), FramePart::Header);
+        assert_eq!(setup.hit(HEAD, w - 14.0, 30.5), FramePart::Header);
+    }
+
+    #[test]
+    fn hidden_buttons_are_packed() {
+        let setup = setup(ConceptConfig {
+            close_button: None,
+            ..config()
+        });
+        let w = f64::from(WIDTH);
+        assert_eq!(setup.hit(HEAD, w - 14.0, 20.0), FramePart::MaximizeButton);
+        assert_eq!(setup.hit(HEAD, w - 38.0, 20.0), FramePart::MinimizeButton);
+        assert_eq!(setup.hit(HEAD, w - 62.0, 20.0), FramePart::Header);
+    }
+
+    #[test]
+    fn buttons_not_fitting() {
+        let mut setup = setup(config());
+        // only the close and maximize buttons fit
+        setup.frame.resize((60, 100));
+        assert_eq!(setup.hit(HEAD, 46.0, 20.0), FramePart::CloseButton);
+        assert_eq!(setup.hit(HEAD, 22.0, 20.0), FramePart::MaximizeButton);
+        assert_eq!(setup.hit(HEAD, 0.0, 20.0), FramePart::Header);
+        // the close button needs its spacing too
+        setup.frame.resize((23, 100));
+        assert_eq!(setup.hit(HEAD, 10.0, 20.0), FramePart::Header);
+        setup.frame.resize((24, 100));
+        assert_eq!(setup.hit(HEAD, 10.0, 20.0), FramePart::CloseButton);
+    }
+
+    #[test]
+    fn corners() {
+        let setup = setup(config());
+        // the top and bottom borders span the side borders
+        let right = f64::from(WIDTH + 8);
+        for &(index, left_corner, edge, right_corner) in &[
+            (
+                TOP,
+                ResizeEdge::TopLeft,
+                ResizeEdge::Top,
+                ResizeEdge::TopRight,
+            ),
+            (
+                BOTTOM,
+                ResizeEdge::BottomLeft,
+                ResizeEdge::Bottom,
+                ResizeEdge::BottomRight,
+            ),
+        ] {
+            assert_eq!(setup.hit(index, 0.0, 4.0), FramePart::Border(left_corner));
+            assert_eq!(setup.hit(index, 8.0, 4.0), FramePart::Border(left_corner));
+            assert_eq!(setup.hit(index, 8.5, 4.0), FramePart::Border(edge));
+            assert_eq!(setup.hit(index, right - 0.5, 4.0), FramePart::Border(edge));
+            assert_eq!(
+                setup.hit(index, right, 4.0),
+                FramePart::Border(right_corner)
+            );
+            assert_eq!(
+                setup.hit(index, right + 8.0, 4.0),
+                FramePart::Border(right_corner)
+            );
+        }
+        assert_eq!(
+            setup.hit(LEFT, 4.0, 0.0),
+            FramePart::Border(ResizeEdge::Left)
+        );
+        assert_eq!(
+            setup.hit(RIGHT, 4.0, 0.0),
+            FramePart::Border(ResizeEdge::Right)
+        );
+    }
+
+    #[test]
+    fn not_resizable() {
+        let mut setup = setup(config());
+        setup.frame.set_resizable(false);
+        for &index in &[TOP, BOTTOM, LEFT, RIGHT] {
+            assert_eq!(setup.hit(index, 0.0, 0.0), FramePart::None);
+            assert_eq!(setup.hit(index, 50.0, 4.0), FramePart::None);
+        }
+        // the titlebar and its buttons are still usable
+        assert_eq!(setup.hit(HEAD, 100.0, 20.0), FramePart::Header);
+        assert_eq!(
+            setup.hit(HEAD, f64::from(WIDTH) - 14.0, 20.0),
+            FramePart::CloseButton
+        );
     }
 }
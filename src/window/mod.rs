@@ -1,21 +1,34 @@
 //! Window abstraction
-use std::sync::{Arc, Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::{self, Rc},
+    sync::{self, Arc, Mutex},
+};
 
 use wayland_client::protocol::{
     wl_compositor, wl_output, wl_seat, wl_shm, wl_subcompositor, wl_surface,
 };
 use wayland_client::{Attached, DispatchData};
 
-use wayland_protocols::xdg_shell::client::xdg_toplevel::ResizeEdge;
-pub use wayland_protocols::xdg_shell::client::xdg_toplevel::State;
+pub use wayland_protocols::xdg_shell::client::xdg_toplevel::{ResizeEdge, State};
+use wayland_protocols::xdg_shell::client::{xdg_surface, xdg_toplevel};
 
 use wayland_protocols::unstable::xdg_decoration::v1::client::{
     zxdg_decoration_manager_v1, zxdg_toplevel_decoration_v1,
 };
 
 use crate::{
-    environment::{Environment, GlobalHandler, MultiGlobalHandler},
+    environment::{
+        Environment, EnvironmentError, GlobalHandler, MultiGlobalHandler, NewGlobalListener,
+        RemovalListener,
+    },
+    seat::{
+        keyboard::{Keysym, ModifiersState},
+        latest_serial, SerialKind,
+    },
     shell,
+    surface::{assign_role, RoleConflict, SurfaceRole},
 };
 
 #[cfg(feature = "frames")]
@@ -70,6 +83,9 @@ impl From<WindowState> for bool {
 #[derive(Clone, Debug)]
 pub enum Event {
     /// The state of your window has been changed
+    ///
+    /// The first configure is always forwarded, the following ones only if they change
+    /// something, see [`Window::suppressed_configures`](struct.Window.html#method.suppressed_configures).
     Configure {
         /// Optional new size for your *inner* surface
         ///
@@ -90,6 +106,12 @@ pub enum Event {
         /// Typically tells you if your surface is active/inactive, maximized,
         /// etc...
         states: Vec<State>,
+        /// Whether `new_size` is the remembered floating size of the window
+        ///
+        /// When the window leaves the maximized, fullscreen or tiled states, compositors
+        /// usually let it choose its size. SCTK then provides the last size the window
+        /// had while floating, see [`Window::floating_size`](struct.Window.html#method.floating_size).
+        restored: bool,
     },
     /// A close request has been received
     ///
@@ -120,6 +142,45 @@ pub enum Decorations {
     None,
 }
 
+/// Possible errors when creating a window
+#[derive(Debug)]
+pub enum CreateWindowError<E> {
+    /// A global needed to create a window is not available
+    ///
+    /// A window requires `wl_compositor`, `wl_subcompositor`, `wl_shm` and a shell.
+    Environment(EnvironmentError),
+    /// The initialization of the frame failed
+    Frame(E),
+    /// The surface was given another role by SCTK
+    Role(RoleConflict),
+}
+
+impl<E: fmt::Display> fmt::Display for CreateWindowError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateWindowError::Environment(err) => write!(f, "cannot create a window: {}", err),
+            CreateWindowError::Frame(err) => write!(f, "failed to initialize the frame: {}", err),
+            CreateWindowError::Role(err) => write!(f, "cannot create a window: {}", err),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for CreateWindowError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CreateWindowError::Environment(err) => Some(err),
+            CreateWindowError::Frame(err) => Some(err),
+            CreateWindowError::Role(err) => Some(err),
+        }
+    }
+}
+
+impl<E> From<EnvironmentError> for CreateWindowError<E> {
+    fn from(err: EnvironmentError) -> CreateWindowError<E> {
+        CreateWindowError::Environment(err)
+    }
+}
+
 struct WindowInner<F> {
     frame: Arc<Mutex<F>>,
     shell_surface: Arc<Box<dyn shell::ShellSurface>>,
@@ -127,12 +188,177 @@ struct WindowInner<F> {
     min_size: (u32, u32),
     max_size: Option<(u32, u32)>,
     current_size: (u32, u32),
-    old_size: Option<(u32, u32)>,
+    floating_size: (u32, u32),
+    states: Vec<State>,
+    resizable: bool,
     decorated: bool,
+    last_configure: Option<ConfigureKey>,
+    suppressed_configures: u64,
+}
+
+impl<F> WindowInner<F> {
+    fn process_frame_request(&mut self, req: FrameRequest, serial: u32, ddata: DispatchData) {
+        match req {
+            FrameRequest::Minimize => self.shell_surface.set_minimized(),
+            FrameRequest::Maximize => self.shell_surface.set_maximized(),
+            FrameRequest::UnMaximize => self.shell_surface.unset_maximized(),
+            FrameRequest::Move(seat) => self.shell_surface.move_(&seat, serial),
+            FrameRequest::Resize(seat, edges) => self.shell_surface.resize(&seat, serial, edges),
+            FrameRequest::ShowMenu(seat, x, y) => {
+                self.shell_surface.show_window_menu(&seat, serial, x, y)
+            }
+            FrameRequest::Close => (self.user_impl)(Event::Close, ddata),
+            FrameRequest::Refresh => (self.user_impl)(Event::Refresh, ddata),
+        }
+    }
+}
+
+// Everything a configure is processed with, to detect the ones which would not change
+// anything since the last one delivered to the user
+#[derive(PartialEq)]
+struct ConfigureKey {
+    new_size: Option<(u32, u32)>,
+    states: Vec<State>,
+    current_size: (u32, u32),
+    min_size: (u32, u32),
+    max_size: Option<(u32, u32)>,
+    borders: (i32, i32),
+}
+
+// Whether the compositor lets the window choose its size in these states
+fn is_floating(states: &[State]) -> bool {
+    !states.iter().any(|s| match *s {
+        State::Maximized
+        | State::Fullscreen
+        | State::TiledLeft
+        | State::TiledRight
+        | State::TiledTop
+        | State::TiledBottom => true,
+        _ => false,
+    })
+}
+
+impl<F: Frame> WindowInner<F> {
+    // Clamp a size of the contents to the min/max sizes of the window
+    fn clamp_size(&self, (w, h): (u32, u32)) -> (u32, u32) {
+        use std::cmp::{max, min};
+        let (minw, minh) = self.min_size;
+        let (mut w, mut h) = (max(w, minw), max(h, minh));
+        if let Some((maxw, maxh)) = self.max_size {
+            w = min(w, maxw);
+            h = min(h, maxh);
+        }
+        (max(w, 1), max(h, 1))
+    }
+
+    // Send the min/max sizes of the window, including the decorations
+    fn update_size_hints(&self, frame: &F) {
+        let add_borders = |(w, h): (u32, u32)| frame.add_borders(w as i32, h as i32);
+        if self.resizable {
+            self.shell_surface
+                .set_min_size(Some(add_borders(self.min_size)));
+            self.shell_surface
+                .set_max_size(self.max_size.map(add_borders));
+        } else {
+            // lock the min/max sizes to current size
+            self.shell_surface
+                .set_min_size(Some(add_borders(self.current_size)));
+            self.shell_surface
+                .set_max_size(Some(add_borders(self.current_size)));
+        }
+    }
+
+    // Match the window geometry and size hints with the borders of the frame,
+    // for the current size of the content
+    fn update_geometry(&self, frame: &F) {
+        let (w, h) = frame.add_borders(self.current_size.0 as i32, self.current_size.1 as i32);
+        let (x, y) = frame.location();
+        self.shell_surface.set_geometry(x, y, w, h);
+        self.update_size_hints(frame);
+    }
 }
 
 /// A window
 ///
+// The server-side decorations of a window
+//
+// This is shared with the listeners tracking the availability of the decoration
+// manager, so that they can be enabled or disabled whenever it is advertised or
+// removed by the compositor.
+struct ServerDecorations {
+    manager: Option<Attached<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>>,
+    decoration: Option<zxdg_toplevel_decoration_v1::ZxdgToplevelDecorationV1>,
+    requested: Decorations,
+}
+
+impl ServerDecorations {
+    // Create, update or destroy the toplevel decoration following the requested mode
+    fn update<F: Frame + 'static>(
+        &mut self,
+        shell_surface: &dyn shell::ShellSurface,
+        frame: &Arc<Mutex<F>>,
+        inner: &Arc<Mutex<Option<WindowInner<F>>>>,
+    ) {
+        use self::zxdg_toplevel_decoration_v1::{Event, Mode};
+
+        // destroy the decoration object, so that the server does not
+        // decorate us if we don't want to
+        if let Decorations::None | Decorations::ClientSide = self.requested {
+            if let Some(dec) = self.decoration.take() {
+                dec.destroy();
+            }
+            return;
+        }
+
+        let alive = match self.decoration {
+            Some(ref decoration) => decoration.as_ref().is_alive(),
+            None => false,
+        };
+        if !alive {
+            let decoration_frame = frame.clone();
+            let decoration_inner = inner.clone();
+            self.decoration = match (shell_surface.get_xdg(), &self.manager) {
+                (Some(toplevel), &Some(ref mgr)) => {
+                    let decoration = mgr.get_toplevel_decoration(toplevel);
+                    decoration.quick_assign(move |_, event, _| {
+                        if let Event::Configure { mode } = event {
+                            log::debug!("[SCTK] Decoration mode set to {:?}", mode);
+                            let hidden = match mode {
+                                Mode::ServerSide => true,
+                                Mode::ClientSide => {
+                                    let want_decorate = decoration_inner
+                                        .lock()
+                                        .unwrap()
+                                        .as_ref()
+                                        .map(|inner| inner.decorated)
+                                        .unwrap_or(false);
+                                    !want_decorate
+                                }
+                                _ => unreachable!(),
+                            };
+                            let mut frame = decoration_frame.lock().unwrap();
+                            frame.set_hidden(hidden);
+                            if let Some(ref inner) = *decoration_inner.lock().unwrap() {
+                                inner.update_geometry(&*frame);
+                            }
+                        }
+                    });
+                    Some(decoration.detach())
+                }
+                _ => None,
+            };
+        }
+
+        if let Some(ref dec) = self.decoration {
+            if let Decorations::ServerSide = self.requested {
+                dec.set_mode(Mode::ServerSide);
+            } else {
+                dec.unset_mode();
+            }
+        }
+    }
+}
+
 /// This wrapper handles for you the decoration of your window
 /// and the interaction with the server regarding the shell protocol.
 ///
@@ -143,31 +369,87 @@ struct WindowInner<F> {
 /// their attached buffer, you need to keep the decorations in sync with
 /// your contents via the `resize(..)` method.
 ///
+/// If the compositor advertises its decoration manager after the window was created, the
+/// window switches to server-side decorations as long as its decoration mode allows it.
+/// Conversely, if the decoration manager is removed, it falls back to drawing its `Frame`
+/// and sends you a `Refresh` event.
+///
 /// Different kind of decorations can be used by customizing the type
 /// parameter. A few are provided in this crate if the `frames` cargo feature
 /// is enabled, but any type implementing the `Frame` trait can do.
 pub struct Window<F: Frame> {
     frame: Arc<Mutex<F>>,
     surface: wl_surface::WlSurface,
-    decoration: Mutex<Option<zxdg_toplevel_decoration_v1::ZxdgToplevelDecorationV1>>,
-    decoration_mgr: Option<Attached<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>>,
+    decorations: Rc<RefCell<ServerDecorations>>,
     shell_surface: Arc<Box<dyn shell::ShellSurface>>,
     inner: Arc<Mutex<Option<WindowInner<F>>>>,
+    owner: Rc<WindowOwner<F>>,
+}
+
+// Shared by a window and the handles given by `Environment::for_each_window`, the window
+// stops processing events once all of them are dropped
+struct WindowOwner<F> {
+    inner: Arc<Mutex<Option<WindowInner<F>>>>,
+    navigation_key: Cell<Option<(Keysym, ModifiersState)>>,
+    session_tag: RefCell<Option<String>>,
     _seat_listener: crate::seat::SeatListener,
+    _decoration_listeners: (NewGlobalListener, RemovalListener),
+}
+
+impl<F> Drop for WindowOwner<F> {
+    fn drop(&mut self) {
+        self.inner.lock().unwrap().take();
+    }
+}
+
+// The weak handle to a window tracked by the environment
+struct TrackedWindow<F> {
+    frame: sync::Weak<Mutex<F>>,
+    surface: wl_surface::WlSurface,
+    decorations: rc::Weak<RefCell<ServerDecorations>>,
+    shell_surface: sync::Weak<Box<dyn shell::ShellSurface>>,
+    inner: sync::Weak<Mutex<Option<WindowInner<F>>>>,
+    owner: rc::Weak<WindowOwner<F>>,
+}
+
+impl<F: Frame> TrackedWindow<F> {
+    fn new(window: &Window<F>) -> TrackedWindow<F> {
+        TrackedWindow {
+            frame: Arc::downgrade(&window.frame),
+            surface: window.surface.clone(),
+            decorations: Rc::downgrade(&window.decorations),
+            shell_surface: Arc::downgrade(&window.shell_surface),
+            inner: Arc::downgrade(&window.inner),
+            owner: Rc::downgrade(&window.owner),
+        }
+    }
+
+    fn upgrade(&self) -> Option<Window<F>> {
+        // the other parts live as long as the owner
+        let owner = self.owner.upgrade()?;
+        Some(Window {
+            frame: self.frame.upgrade()?,
+            surface: self.surface.clone(),
+            decorations: self.decorations.upgrade()?,
+            shell_surface: self.shell_surface.upgrade()?,
+            inner: self.inner.upgrade()?,
+            owner,
+        })
+    }
 }
 
 impl<F: Frame + 'static> Window<F> {
     /// Create a new window wrapping a given wayland surface as its main content and
     /// following the compositor's preference regarding server-side decorations
     ///
-    /// It can fail if the initialization of the frame fails (for example if the
-    /// frame class fails to initialize its SHM).
+    /// It can fail if a required global is missing, or if the initialization of
+    /// the frame fails (for example if the frame class fails to initialize its SHM).
     fn init_with_decorations<Impl, E>(
         env: &crate::environment::Environment<E>,
         surface: wl_surface::WlSurface,
         initial_dims: (u32, u32),
         implementation: Impl,
-    ) -> Result<Window<F>, F::Error>
+    ) -> Result<Window<F>, CreateWindowError<F::Error>>
     where
         Impl: FnMut(Event, DispatchData) + 'static,
         E: GlobalHandler<wl_compositor::WlCompositor>
@@ -176,16 +458,27 @@ impl<F: Frame + 'static> Window<F> {
             + crate::shell::ShellHandling
             + MultiGlobalHandler<wl_seat::WlSeat>
             + GlobalHandler<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>
-            + crate::seat::SeatHandling,
+            + crate::seat::SeatHandling
+            + 'static,
     {
-        let compositor = env.require_global::<wl_compositor::WlCompositor>();
-        let subcompositor = env.require_global::<wl_subcompositor::WlSubcompositor>();
-        let shm = env.require_global::<wl_shm::WlShm>();
-        let shell = env
-            .get_shell()
-            .expect("[SCTK] Cannot create a window if the compositor advertized no shell.");
-        let decoration_mgr =
-            env.get_global::<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>();
+        let compositor = env
+            .try_get_global::<wl_compositor::WlCompositor>()
+            .map_err(EnvironmentError::from)?;
+        let subcompositor = env
+            .try_get_global::<wl_subcompositor::WlSubcompositor>()
+            .map_err(EnvironmentError::from)?;
+        let shm = env
+            .try_get_global::<wl_shm::WlShm>()
+            .map_err(EnvironmentError::from)?;
+        let shell = env.get_shell().ok_or(EnvironmentError::MissingGlobal {
+            interface: "xdg_wm_base",
+        })?;
+        assign_role(&surface, SurfaceRole::Toplevel).map_err(CreateWindowError::Role)?;
+        let decorations = Rc::new(RefCell::new(ServerDecorations {
+            manager: env.get_global::<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>(),
+            decoration: None,
+            requested: Decorations::FollowServer,
+        }));
 
         let inner = Arc::new(Mutex::new(None::<WindowInner<F>>));
         let frame_inner = inner.clone();
@@ -197,20 +490,11 @@ impl<F: Frame + 'static> Window<F> {
             &shm,
             Box::new(move |req, serial, ddata: DispatchData| {
                 if let Some(ref mut inner) = *shell_inner.lock().unwrap() {
-                    match req {
-                        FrameRequest::Minimize => inner.shell_surface.set_minimized(),
-                        FrameRequest::Maximize => inner.shell_surface.set_maximized(),
-                        FrameRequest::UnMaximize => inner.shell_surface.unset_maximized(),
-                        FrameRequest::Move(seat) => inner.shell_surface.move_(&seat, serial),
-                        FrameRequest::Resize(seat, edges) => {
-                            inner.shell_surface.resize(&seat, serial, edges)
-                        }
-                        FrameRequest::Close => (inner.user_impl)(Event::Close, ddata),
-                        FrameRequest::Refresh => (inner.user_impl)(Event::Refresh, ddata),
-                    }
+                    inner.process_frame_request(req, serial, ddata);
                 }
             }) as Box<_>,
-        )?;
+        )
+        .map_err(CreateWindowError::Frame)?;
         frame.resize(initial_dims);
         let frame = Arc::new(Mutex::new(frame));
         let shell_surface = Arc::new(shell::create_shell_surface(
@@ -223,42 +507,66 @@ impl<F: Frame + 'static> Window<F> {
                             states,
                             mut new_size,
                         } => {
+                            log::trace!(
+                                "[SCTK] Window configured with size {:?} and states {:?}",
+                                new_size,
+                                states
+                            );
                             let mut frame = inner.frame.lock().unwrap();
+                            // the configure was already acked by the shell surface, the ones
+                            // repeating the last delivered one can be dropped
+                            let key = ConfigureKey {
+                                new_size,
+                                states: states.clone(),
+                                current_size: inner.current_size,
+                                min_size: inner.min_size,
+                                max_size: inner.max_size,
+                                borders: frame.add_borders(0, 0),
+                            };
+                            if inner.last_configure.as_ref() == Some(&key) {
+                                inner.suppressed_configures += 1;
+                                log::trace!("[SCTK] Suppressed a repeated window configure");
+                                return;
+                            }
+                            inner.last_configure = Some(key);
                             // clamp size
                             new_size = new_size.map(|(w, h)| {
-                                use std::cmp::{max, min};
-                                let (mut w, mut h) = frame.subtract_borders(w as i32, h as i32);
-                                let (minw, minh) = inner.min_size;
-                                w = max(w, minw as i32);
-                                h = max(h, minh as i32);
-                                if let Some((maxw, maxh)) = inner.max_size {
-                                    w = min(w, maxw as i32);
-                                    h = min(h, maxh as i32);
-                                }
-                                (max(w, 1) as u32, max(h, 1) as u32)
+                                let (w, h) = frame.subtract_borders(w as i32, h as i32);
+                                inner.clamp_size((
+                                    std::cmp::max(w, 0) as u32,
+                                    std::cmp::max(h, 0) as u32,
+                                ))
                             });
                             // compute frame changes
                             let need_refresh = frame.set_states(&states);
-                            // check if the maximization state changed
-                            if states.contains(&State::Maximized) {
-                                if inner.old_size.is_none() {
-                                    // we are getting maximized, store the size for restoration
-                                    inner.old_size = Some(inner.current_size);
+                            let mut restored = false;
+                            if is_floating(&states) {
+                                match new_size {
+                                    // we are given a floating size, remember it
+                                    Some(size) => inner.floating_size = size,
+                                    // we are leaving a maximized, fullscreen or tiled state and
+                                    // get to choose our size, restore the floating one
+                                    None if !is_floating(&inner.states) => {
+                                        new_size = Some(inner.clamp_size(inner.floating_size));
+                                        restored = true;
+                                    }
+                                    None => {}
                                 }
-                            } else if new_size.is_none() {
-                                // we are getting de-maximized, restore the size
-                                // if we were not previously maximized, old_size is None and this does nothing
-                                new_size = inner.old_size.take();
-                            } else {
-                                // we are not maximized but are given a size, respect it
-                                // and forget about the old size
-                                inner.old_size = None;
                             }
 
+                            inner.states = states.clone();
+
                             if need_refresh {
                                 (inner.user_impl)(Event::Refresh, ddata.reborrow());
                             }
-                            (inner.user_impl)(Event::Configure { states, new_size }, ddata);
+                            (inner.user_impl)(
+                                Event::Configure {
+                                    states,
+                                    new_size,
+                                    restored,
+                                },
+                                ddata,
+                            );
                         }
                         shell::Event::Close => {
                             (inner.user_impl)(Event::Close, ddata);
@@ -281,12 +589,17 @@ impl<F: Frame + 'static> Window<F> {
 
         // initial seat setup
         let mut seats = Vec::<wl_seat::WlSeat>::new();
+        let mut touch_seats = Vec::<wl_seat::WlSeat>::new();
         for seat in env.get_all_seats() {
             crate::seat::with_seat_data(&seat, |seat_data| {
                 if seat_data.has_pointer && !seat_data.defunct {
                     seats.push(seat.detach());
                     frame.lock().unwrap().new_seat(&seat);
                 }
+                if seat_data.has_touch && !seat_data.defunct {
+                    touch_seats.push(seat.detach());
+                    frame.lock().unwrap().new_touch_seat(&seat);
+                }
             });
         }
 
@@ -301,6 +614,14 @@ impl<F: Frame + 'static> Window<F> {
                 seat_frame.lock().unwrap().remove_seat(&seat);
                 seats.retain(|s| s != &*seat);
             }
+            let is_known = touch_seats.contains(&seat);
+            if !is_known && seat_data.has_touch && !seat_data.defunct {
+                seat_frame.lock().unwrap().new_touch_seat(&seat);
+                touch_seats.push(seat.detach());
+            } else if is_known && ((!seat_data.has_touch) || seat_data.defunct) {
+                seat_frame.lock().unwrap().remove_touch_seat(&seat);
+                touch_seats.retain(|s| s != &*seat);
+            }
         });
 
         *(inner.lock().unwrap()) = Some(WindowInner {
@@ -310,77 +631,273 @@ impl<F: Frame + 'static> Window<F> {
             min_size: (MIN_WINDOW_SIZE.0, MIN_WINDOW_SIZE.1),
             max_size: None,
             current_size: initial_dims,
-            old_size: None,
+            floating_size: initial_dims,
+            states: Vec::new(),
+            resizable: true,
             decorated: true,
+            last_configure: None,
+            suppressed_configures: 0,
         });
 
+        // engage server-side decorations if the manager is advertised later on
+        let new_listener = {
+            let env = env.clone();
+            let decorations = decorations.clone();
+            let shell_surface = shell_surface.clone();
+            let frame = frame.clone();
+            let inner = inner.clone();
+            env.clone()
+                .listen_for_new_globals::<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1, _>(
+                    move |_, _, _| {
+                        let mut decorations = decorations.borrow_mut();
+                        if decorations.manager.is_some() {
+                            return;
+                        }
+                        decorations.manager =
+                            env.get_global::<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>();
+                        decorations.update(&**shell_surface, &frame, &inner);
+                    },
+                )
+        };
+
+        // fall back to client-side decorations if the manager is removed
+        let removal_listener = {
+            let decorations = decorations.clone();
+            let frame = frame.clone();
+            let inner = inner.clone();
+            env.listen_for_removals::<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1, _>(
+                move |_, ddata| {
+                    let decorated = {
+                        let mut decorations = decorations.borrow_mut();
+                        decorations.manager = None;
+                        match decorations.decoration.take() {
+                            Some(dec) => {
+                                dec.destroy();
+                                match decorations.requested {
+                                    Decorations::ServerSide | Decorations::FollowServer => true,
+                                    Decorations::ClientSide | Decorations::None => false,
+                                }
+                            }
+                            None => false,
+                        }
+                    };
+                    if decorated {
+                        let mut frame = frame.lock().unwrap();
+                        frame.set_hidden(false);
+                        if let Some(ref mut inner) = *inner.lock().unwrap() {
+                            inner.update_geometry(&*frame);
+                            drop(frame);
+                            (inner.user_impl)(Event::Refresh, ddata);
+                        }
+                    }
+                },
+            )
+        };
+
+        // init decoration if applicable
+        decorations
+            .borrow_mut()
+            .update(&**shell_surface, &frame, &inner);
+
+        let owner = Rc::new(WindowOwner {
+            inner: inner.clone(),
+            navigation_key: Cell::new(None),
+            session_tag: RefCell::new(None),
+            _seat_listener: seat_listener,
+            _decoration_listeners: (new_listener, removal_listener),
+        });
         let window = Window {
             frame,
             shell_surface,
-            decoration: Mutex::new(None),
-            decoration_mgr,
+            decorations,
             surface,
             inner,
-            _seat_listener: seat_listener,
+            owner,
         };
+        env.track(TrackedWindow::new(&window));
+        Ok(window)
+    }
 
-        // init decoration if applicable
-        {
-            let mut decoration = window.decoration.lock().unwrap();
-            window.ensure_decoration(&mut decoration);
+    /// Access the surface wrapped in this Window
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+
+    /// Get a raw handle to the content surface of this Window, for graphics APIs
+    ///
+    /// The display must be the one of the connection the window was created on. See
+    /// the [`raw_handle`](../raw_handle/index.html) module for details.
+    ///
+    /// **Note:** This requires the `raw_handle` cargo feature.
+    #[cfg(feature = "raw_handle")]
+    pub fn raw_handle(
+        &self,
+        display: &wayland_client::protocol::wl_display::WlDisplay,
+    ) -> crate::raw_handle::RawSurfaceHandle {
+        crate::raw_handle::RawSurfaceHandle::new(display, &self.surface)
+    }
+
+    /// Access the `xdg_surface` of this Window, if it uses the `xdg_shell` protocol
+    ///
+    /// This is notably needed to create popups parented to this window.
+    pub fn xdg_surface(&self) -> Option<&xdg_surface::XdgSurface> {
+        self.shell_surface.get_xdg_surface()
+    }
+
+    /// Access the `xdg_toplevel` of this Window, if it uses the `xdg_shell` protocol
+    pub fn xdg_toplevel(&self) -> Option<&xdg_toplevel::XdgToplevel> {
+        self.shell_surface.get_xdg()
+    }
+
+    /// Find the part of the window at a position
+    ///
+    /// `(x, y)` is a position in surface-local coordinates of `surface`, like the ones of the
+    /// pointer events, which are logical coordinates: they do not depend on the scale factor
+    /// of the surfaces. The surface can be the content surface of the window, or one of the
+    /// surfaces of its decorations. This is for example useful to show a tooltip over the
+    /// buttons of the titlebar.
+    ///
+    /// The parts are found in the same way the frame reacts to clicks, so the borders are
+    /// only reported while the window is resizable, and `FramePart::None` is returned for
+    /// surfaces not belonging to this window.
+    pub fn hit_test(&self, surface: &wl_surface::WlSurface, x: f64, y: f64) -> FramePart {
+        if surface.as_ref().equals(self.surface.as_ref()) {
+            return FramePart::Content;
         }
+        self.frame.lock().unwrap().hit_test(surface, x, y)
+    }
 
-        Ok(window)
+    /// Set the key starting the keyboard navigation of the buttons of the decorations
+    ///
+    /// Once the key is pressed, with exactly the control, alt, shift and logo modifiers of
+    /// the given state, the arrow keys move the focus across the buttons drawn by the frame,
+    /// Enter or Space activates the focused button and Escape leaves the navigation. This is
+    /// disabled by default, `Some((Keysym::SPACE, alt))` enables it with Alt+Space.
+    ///
+    /// The keys are given to the frame with [`handle_key`](#method.handle_key).
+    pub fn set_frame_navigation_key(&mut self, key: Option<(Keysym, ModifiersState)>) {
+        self.owner.navigation_key.set(key);
     }
 
-    fn ensure_decoration(
+    /// Forward a key press to the decorations
+    ///
+    /// Give the keys your window does not handle, as received from its keyboard while it has
+    /// the keyboard focus, to let the user reach the buttons of the decorations with the
+    /// keyboard, see [`set_frame_navigation_key`](#method.set_frame_navigation_key).
+    ///
+    /// Returns `true` if the key was consumed by the frame, in which case your window should
+    /// not process it. The keys are never consumed outside of the navigation, and the key
+    /// releases should not be forwarded.
+    pub fn handle_key(
         &self,
-        decoration: &mut Option<zxdg_toplevel_decoration_v1::ZxdgToplevelDecorationV1>,
-    ) {
-        if self.decoration_mgr.is_none() {
-            return;
+        keysym: Keysym,
+        modifiers: ModifiersState,
+        mut ddata: DispatchData,
+    ) -> bool {
+        let is_navigation_key = match self.owner.navigation_key.get() {
+            Some((key, mods)) => {
+                key == keysym
+                    && mods.ctrl == modifiers.ctrl
+                    && mods.alt == modifiers.alt
+                    && mods.shift == modifiers.shift
+                    && mods.logo == modifiers.logo
+            }
+            None => return false,
+        };
+        let key = if is_navigation_key {
+            FrameKey::Navigate
+        } else if keysym == Keysym::LEFT || keysym == Keysym::UP {
+            FrameKey::Previous
+        } else if keysym == Keysym::RIGHT || keysym == Keysym::DOWN || keysym == Keysym::TAB {
+            FrameKey::Next
+        } else if keysym == Keysym::RETURN || keysym == Keysym::KP_ENTER || keysym == Keysym::SPACE
+        {
+            FrameKey::Activate
+        } else if keysym == Keysym::ESCAPE {
+            FrameKey::Exit
+        } else {
+            return false;
+        };
+        let mut requests = Vec::new();
+        // the requests are processed once the frame is unlocked, as the user callback may
+        // access the window
+        if !self.frame.lock().unwrap().on_key(key, &mut requests) {
+            return false;
         }
-
-        if let Some(ref decoration) = *decoration {
-            if decoration.as_ref().is_alive() {
-                return;
+        for req in requests {
+            if let Some(ref mut inner) = *self.inner.lock().unwrap() {
+                inner.process_frame_request(req, 0, ddata.reborrow());
             }
         }
+        true
+    }
 
-        let decoration_frame = self.frame.clone();
-        let decoration_inner = self.inner.clone();
-        *decoration = match (self.shell_surface.get_xdg(), &self.decoration_mgr) {
-            (Some(toplevel), &Some(ref mgr)) => {
-                use self::zxdg_toplevel_decoration_v1::{Event, Mode};
-                let decoration = mgr.get_toplevel_decoration(toplevel);
-                decoration.quick_assign(move |_, event, _| {
-                    if let Event::Configure { mode } = event {
-                        match mode {
-                            Mode::ServerSide => {
-                                decoration_frame.lock().unwrap().set_hidden(true);
-                            }
-                            Mode::ClientSide => {
-                                let want_decorate = decoration_inner
-                                    .lock()
-                                    .unwrap()
-                                    .as_ref()
-                                    .map(|inner| inner.decorated)
-                                    .unwrap_or(false);
-                                decoration_frame.lock().unwrap().set_hidden(!want_decorate);
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
-                });
-                Some(decoration.detach())
-            }
-            _ => None,
-        };
+    /// The number of configures of the compositor which were not forwarded
+    ///
+    /// A `Configure` event is not generated when the compositor repeats the last configure,
+    /// if nothing affecting it, like the size of the window or of its decorations, changed
+    /// in the meantime. All the configures are still acknowledged. This is the number of
+    /// configures suppressed this way, for debugging purposes.
+    pub fn suppressed_configures(&self) -> u64 {
+        self.inner
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|inner| inner.suppressed_configures)
+            .unwrap_or(0)
     }
 
-    /// Access the surface wrapped in this Window
-    pub fn surface(&self) -> &wl_surface::WlSurface {
-        &self.surface
+    /// The last size of the contents of the window while it was floating
+    ///
+    /// This is the size the window had while it was neither maximized, fullscreen nor
+    /// tiled, and gets back when it leaves these states. It can be saved to restore the
+    /// window with the same size in a later session.
+    pub fn floating_size(&self) -> (u32, u32) {
+        self.inner
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|inner| inner.floating_size)
+            .unwrap_or((0, 0))
+    }
+
+    /// Set the size the window gets back when it leaves the maximized, fullscreen or tiled states
+    ///
+    /// This is typically a size saved from [`floating_size`](#method.floating_size) in a
+    /// previous session, for a window created maximized. It is updated again by the
+    /// following resizes of the floating window.
+    pub fn set_floating_size(&mut self, w: u32, h: u32) {
+        if let Some(ref mut inner) = *self.inner.lock().unwrap() {
+            inner.floating_size = (std::cmp::max(w, 1), std::cmp::max(h, 1));
+        }
+    }
+
+    /// Set the tag identifying this window across the sessions of your app
+    ///
+    /// It must be unique among the windows of your app, and stable across its runs, so that
+    /// the state of the window can be restored under it, for example its
+    /// [`floating_size`](#method.floating_size).
+    ///
+    /// The session management protocols letting the compositor restore the windows, like
+    /// `xdg_session_manager_v1`, are not available in the protocol bindings used by SCTK
+    /// yet, the tag is only stored for now and
+    /// [`session_restored`](#method.session_restored) always returns `false`.
+    pub fn set_session_tag(&mut self, tag: &str) {
+        *self.owner.session_tag.borrow_mut() = Some(tag.into());
+    }
+
+    /// The tag identifying this window across sessions, if set
+    pub fn session_tag(&self) -> Option<String> {
+        self.owner.session_tag.borrow().clone()
+    }
+
+    /// Whether the compositor restored the state of this window from a previous session
+    ///
+    /// If it did not, your app should restore the state saved under the tag of the window
+    /// itself. This is always the case when the compositor does not support session
+    /// management, so that apps can have a single code path.
+    pub fn session_restored(&self) -> bool {
+        false
     }
 
     /// Refreshes the frame
@@ -423,44 +940,46 @@ impl<F: Frame + 'static> Window<F> {
 
     /// Set whether the window should be decorated or not
     ///
+    /// This can be changed at any time: the frame is hidden or shown, and the window
+    /// geometry and size hints are updated right away to match it. The content keeps its
+    /// size, the window growing or shrinking by the size of the decorations, and the
+    /// `new_size` of the next [`Event::Configure`](enum.Event.html) is the size left to
+    /// the content by the new decorations. With `ServerSide` or `FollowServer`, the frame
+    /// is shown if the compositor does not support server-side decorations.
+    ///
+    /// Without client-side decorations the window has no resize borders: they are either
+    /// drawn by the compositor, or your application needs to start the resizes itself,
+    /// with [`resize_edge_at`](fn.resize_edge_at.html) and
+    /// [`start_interactive_resize`](#method.start_interactive_resize).
+    ///
     /// You need to call `refresh()` afterwards for this to properly
     /// take effect.
     pub fn set_decorate(&self, decorate: Decorations) {
-        use self::zxdg_toplevel_decoration_v1::Mode;
-        let mut decoration_guard = self.decoration.lock().unwrap();
+        let mut decorations = self.decorations.borrow_mut();
 
-        if let Decorations::ClientSide = decorate {
-            self.frame.lock().unwrap().set_hidden(false);
-        } else {
-            self.frame.lock().unwrap().set_hidden(true);
-        }
-
-        if let Some(ref mut inner) = *self.inner.lock().unwrap() {
-            if let Decorations::None = decorate {
-                inner.decorated = false;
-            } else {
-                inner.decorated = true;
-            }
-        }
+        // with server-side decorations, the frame is shown or hidden once the
+        // compositor chose the decoration mode
+        let hidden = match decorate {
+            Decorations::ClientSide => false,
+            Decorations::None => true,
+            Decorations::ServerSide | Decorations::FollowServer => decorations.manager.is_some(),
+        };
 
-        // destroy the decoration object, so that the server does not
-        // decorate us if we don't want to
-        if let Decorations::None | Decorations::ClientSide = decorate {
-            if let Some(ref dec) = decoration_guard.take() {
-                dec.destroy();
+        {
+            let mut frame = self.frame.lock().unwrap();
+            frame.set_hidden(hidden);
+            if let Some(ref mut inner) = *self.inner.lock().unwrap() {
+                if let Decorations::None = decorate {
+                    inner.decorated = false;
+                } else {
+                    inner.decorated = true;
+                }
+                inner.update_geometry(&*frame);
             }
-            return;
         }
 
-        // We didn't early exit, so we need to deal with server-side decorations
-        self.ensure_decoration(&mut decoration_guard);
-        if let Some(ref dec) = *decoration_guard {
-            if let Decorations::ServerSide = decorate {
-                dec.set_mode(Mode::ServerSide);
-            } else {
-                dec.unset_mode();
-            }
-        }
+        decorations.requested = decorate;
+        decorations.update(&**self.shell_surface, &self.frame, &self.inner);
     }
 
     /// Set whether the window should be resizeable by the user
@@ -480,24 +999,8 @@ impl<F: Frame + 'static> Window<F> {
         frame.set_resizable(resizable);
         let mut inner = self.inner.lock().unwrap();
         if let Some(ref mut inner) = *inner {
-            if resizable {
-                // restore the min/max sizes
-                self.shell_surface.set_min_size(
-                    Some(inner.min_size).map(|(w, h)| frame.add_borders(w as i32, h as i32)),
-                );
-                self.shell_surface.set_max_size(
-                    inner
-                        .max_size
-                        .map(|(w, h)| frame.add_borders(w as i32, h as i32)),
-                );
-            } else {
-                // lock the min/max sizes to current size
-                let (w, h) = inner.current_size;
-                self.shell_surface
-                    .set_min_size(Some(frame.add_borders(w as i32, h as i32)));
-                self.shell_surface
-                    .set_max_size(Some(frame.add_borders(w as i32, h as i32)));
-            }
+            inner.resizable = resizable;
+            inner.update_size_hints(&*frame);
         }
     }
 
@@ -517,6 +1020,9 @@ impl<F: Frame + 'static> Window<F> {
         let h = max(h, 1);
         if let Some(ref mut inner) = *self.inner.lock().unwrap() {
             inner.current_size = (w, h);
+            if is_floating(&inner.states) {
+                inner.floating_size = (w, h);
+            }
         }
         let mut frame = self.frame.lock().unwrap();
         frame.resize((w, h));
@@ -525,6 +1031,49 @@ impl<F: Frame + 'static> Window<F> {
         self.shell_surface.set_geometry(x, y, w, h);
     }
 
+    /// Start an interactive move of the window
+    ///
+    /// This is what the titlebar of the frame does when it is dragged, for applications
+    /// drawing their own headerbar in the content of the window. The `serial` must be the
+    /// one of the pointer button press or touch down starting the drag: if `None` is given,
+    /// the latest one recorded for this seat by SCTK is used (see
+    /// [`SerialTracker`](../seat/struct.SerialTracker.html)).
+    ///
+    /// Returns `false` if no serial was given and none is recorded for this seat.
+    pub fn start_interactive_move(&self, seat: &wl_seat::WlSeat, serial: Option<u32>) -> bool {
+        match serial.or_else(|| grab_serial(seat)) {
+            Some(serial) => {
+                self.shell_surface.move_(seat, serial);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start an interactive resize of the window from this edge
+    ///
+    /// This is what the borders of the frame do when they are dragged, for applications
+    /// drawing their own resize handles in the content of the window. The edge under the
+    /// pointer can be computed with [`resize_edge_at`](fn.resize_edge_at.html). The
+    /// `serial` is handled as in
+    /// [`start_interactive_move`](#method.start_interactive_move).
+    ///
+    /// Returns `false` if no serial was given and none is recorded for this seat.
+    pub fn start_interactive_resize(
+        &self,
+        seat: &wl_seat::WlSeat,
+        serial: Option<u32>,
+        edge: ResizeEdge,
+    ) -> bool {
+        match serial.or_else(|| grab_serial(seat)) {
+            Some(serial) => {
+                self.shell_surface.resize(seat, serial, edge);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Request the window to be maximized
     pub fn set_maximized(&self) {
         self.shell_surface.set_maximized();
@@ -536,6 +1085,18 @@ impl<F: Frame + 'static> Window<F> {
     }
 
     /// Request the window to be minimized
+    ///
+    /// This is also what the minimize button of the frame does.
+    ///
+    /// Unlike maximization, there is no request to unminimize a window, and
+    /// minimization is not reported in [`Event::Configure`](enum.Event.html):
+    /// the compositor restores the window when the user asks it to. While
+    /// the window is minimized the compositor will usually stop sending frame
+    /// callbacks, so you should pace your drawing on them rather than on a
+    /// timer to avoid rendering frames that are never shown.
+    ///
+    /// This request is ignored on the legacy `wl_shell`, which does not support
+    /// minimization.
     pub fn set_minimized(&self) {
         self.shell_surface.set_minimized();
     }
@@ -594,17 +1155,98 @@ impl<F: Frame + 'static> Window<F> {
     /// This allows to configure the frame at runtime if it supports
     /// it. See the documentation of your `Frame` implementation for
     /// details about what configuration it supports.
-    pub fn set_frame_config(&mut self, config: F::Config) {
-        self.frame.lock().unwrap().set_config(config)
+    ///
+    /// If the new configuration changes the size of the decorations, the
+    /// window geometry and size hints are updated right away. A floating
+    /// window keeps the size of its contents and grows or shrinks. A maximized
+    /// or fullscreen window keeps its size instead, and the new size of its
+    /// contents is returned: you should then draw your contents at this size,
+    /// and call `refresh()`. Otherwise `None` is returned.
+    pub fn set_frame_config(&mut self, config: F::Config) -> Option<(u32, u32)> {
+        let mut frame = self.frame.lock().unwrap();
+        let (old_w, old_h) = frame.add_borders(0, 0);
+        frame.set_config(config);
+        if frame.add_borders(0, 0) == (old_w, old_h) {
+            return None;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let inner = inner.as_mut()?;
+        let constrained =
+            inner.states.contains(&State::Maximized) || inner.states.contains(&State::Fullscreen);
+        let mut new_size = None;
+        if constrained {
+            // the compositor decided the window size, shrink or grow the contents
+            let (w, h) = inner.current_size;
+            let (w, h) = frame.subtract_borders(w as i32 + old_w, h as i32 + old_h);
+            let size = (std::cmp::max(w, 1) as u32, std::cmp::max(h, 1) as u32);
+            inner.current_size = size;
+            frame.resize(size);
+            new_size = Some(size);
+        }
+        let (w, h) = inner.current_size;
+        let (w, h) = frame.add_borders(w as i32, h as i32);
+        let (x, y) = frame.location();
+        self.shell_surface.set_geometry(x, y, w, h);
+        inner.update_size_hints(&*frame);
+        new_size
     }
 }
 
-impl<F: Frame> Drop for Window<F> {
-    fn drop(&mut self) {
-        self.inner.lock().unwrap().take();
+// The serial of the latest event of the seat that can start a grab
+fn grab_serial(seat: &wl_seat::WlSeat) -> Option<u32> {
+    let button = latest_serial(seat, SerialKind::PointerButton);
+    let touch = latest_serial(seat, SerialKind::TouchDown);
+    // use the touch point only if it is the latest event of the seat
+    match latest_serial(seat, SerialKind::Any) {
+        Some(serial) if touch == Some(serial) => touch,
+        _ => button.or(touch),
+    }
+}
+
+/// The resize edge of a window under a position of its content
+///
+/// `(x, y)` is a position in surface-local coordinates of a content of size
+/// `(width, height)`, and `band` the width of the edges in which a drag resizes the
+/// window. The corners are the intersections of two edges. Returns `ResizeEdge::None`
+/// if the position is not in any edge.
+pub fn resize_edge_at(x: f64, y: f64, width: u32, height: u32, band: u32) -> ResizeEdge {
+    let band = band as f64;
+    let left = x < band;
+    let right = x >= width as f64 - band;
+    let top = y < band;
+    let bottom = y >= height as f64 - band;
+    match (top, bottom, left, right) {
+        (true, _, true, _) => ResizeEdge::TopLeft,
+        (true, _, _, true) => ResizeEdge::TopRight,
+        (_, true, true, _) => ResizeEdge::BottomLeft,
+        (_, true, _, true) => ResizeEdge::BottomRight,
+        (true, _, _, _) => ResizeEdge::Top,
+        (_, true, _, _) => ResizeEdge::Bottom,
+        (_, _, true, _) => ResizeEdge::Left,
+        (_, _, _, true) => ResizeEdge::Right,
+        _ => ResizeEdge::None,
     }
 }
 
+/// A part of a window, as found by [`Window::hit_test`](struct.Window.html#method.hit_test)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FramePart {
+    /// The titlebar, dragging it moves the window
+    Header,
+    /// The close button
+    CloseButton,
+    /// The maximize button
+    MaximizeButton,
+    /// The minimize button
+    MinimizeButton,
+    /// A resize border, dragging it resizes the window from this edge
+    Border(ResizeEdge),
+    /// The contents of the window
+    Content,
+    /// No part of the window, or a part of the decorations without any action
+    None,
+}
+
 /// Request generated by a Frame
 ///
 /// These requests are generated by a Frame and the Window will
@@ -622,10 +1264,31 @@ pub enum FrameRequest {
     Move(wl_seat::WlSeat),
     /// An interactive resize should be started
     Resize(wl_seat::WlSeat, ResizeEdge),
+    /// The window menu of the compositor should be shown at this position, relative
+    /// to the window geometry
+    ShowMenu(wl_seat::WlSeat, i32, i32),
     /// The frame requests to be refreshed
     Refresh,
 }
 
+/// A key given to the frame for the keyboard navigation of its buttons
+///
+/// The keys of the user are translated to these by
+/// [`Window::handle_key`](struct.Window.html#method.handle_key).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameKey {
+    /// The navigation key was pressed, starting or leaving the navigation
+    Navigate,
+    /// Move the focus to the previous button
+    Previous,
+    /// Move the focus to the next button
+    Next,
+    /// Activate the focused button
+    Activate,
+    /// Leave the navigation
+    Exit,
+}
+
 /// Interface for defining the drawing of decorations
 ///
 /// A type implementing this trait can be used to define custom
@@ -667,6 +1330,17 @@ pub trait Frame: Sized {
     /// Notify that this seat has lost the pointer capability or
     /// has been lost
     fn remove_seat(&mut self, seat: &wl_seat::WlSeat);
+    /// Notify that a new wl_seat with touch capability should be handled
+    ///
+    /// The default implementation ignores the touch input.
+    fn new_touch_seat(&mut self, seat: &Attached<wl_seat::WlSeat>) {
+        let _ = seat;
+    }
+    /// Notify that this seat has lost the touch capability or
+    /// has been lost
+    fn remove_touch_seat(&mut self, seat: &wl_seat::WlSeat) {
+        let _ = seat;
+    }
     /// Change the size of the decorations
     ///
     /// Calling this should *not* trigger a redraw
@@ -688,6 +1362,30 @@ pub trait Frame: Sized {
 
     /// Sets the frames title
     fn set_title(&mut self, title: String);
+
+    /// Handle a key for the keyboard navigation of the buttons
+    ///
+    /// The frame enters the navigation on `FrameKey::Navigate`, showing which button has the
+    /// focus, and leaves it on `FrameKey::Exit` or once a button is activated. It pushes the
+    /// requests to process, like `FrameRequest::Refresh` to be redrawn, to `requests`. Returns
+    /// whether the key was consumed, the keys other than `FrameKey::Navigate` must not be
+    /// consumed outside of the navigation.
+    ///
+    /// The default implementation does not support the keyboard navigation.
+    fn on_key(&mut self, key: FrameKey, requests: &mut Vec<FrameRequest>) -> bool {
+        let _ = (key, requests);
+        false
+    }
+
+    /// Find the part of the decorations at a position
+    ///
+    /// `(x, y)` is a position in surface-local coordinates of `surface`, as given by the
+    /// pointer events. The result must match the action the frame takes when this position
+    /// is clicked. The default implementation finds no part.
+    fn hit_test(&self, surface: &wl_surface::WlSurface, x: f64, y: f64) -> FramePart {
+        let _ = (surface, x, y);
+        FramePart::None
+    }
 }
 
 impl<E> Environment<E>
@@ -698,7 +1396,8 @@ where
         + crate::shell::ShellHandling
         + MultiGlobalHandler<wl_seat::WlSeat>
         + GlobalHandler<zxdg_decoration_manager_v1::ZxdgDecorationManagerV1>
-        + crate::seat::SeatHandling,
+        + crate::seat::SeatHandling
+        + 'static,
 {
     /// Create a new window wrapping given surface
     ///
@@ -708,17 +1407,40 @@ where
     ///
     /// Many interactions still require your input, and are given to you via the
     /// callback you need to provide.
+    ///
+    /// Fails if the compositor lacks one of the globals required to create a window,
+    /// if the initialization of the frame fails, or if the surface was given another
+    /// role by SCTK.
     pub fn create_window<F: Frame + 'static, CB>(
         &self,
         surface: wl_surface::WlSurface,
         initial_dims: (u32, u32),
         callback: CB,
-    ) -> Result<Window<F>, F::Error>
+    ) -> Result<Window<F>, CreateWindowError<F::Error>>
     where
         CB: FnMut(Event, DispatchData) + 'static,
     {
         Window::<F>::init_with_decorations(self, surface, initial_dims, callback)
     }
+
+    /// Invoke a closure on each window with the frame `F` created by this environment
+    ///
+    /// The closure is given a handle to the window, sharing its state with the `Window`
+    /// returned by [`create_window`](#method.create_window): this is meant to apply a change
+    /// to all the windows of your app, like a new frame configuration. The windows are
+    /// listed before the closure is invoked, so it can create or drop windows, the new ones
+    /// are not visited. The order of the windows is unspecified.
+    ///
+    /// A handle kept beyond the closure keeps the window alive.
+    pub fn for_each_window<F, G>(&self, mut f: G)
+    where
+        F: Frame + 'static,
+        G: FnMut(&mut Window<F>),
+    {
+        for mut window in self.tracked(TrackedWindow::<F>::upgrade) {
+            f(&mut window);
+        }
+    }
 }
 
 //
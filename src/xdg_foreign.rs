@@ -0,0 +1,197 @@
+//! Helpers to parent windows across clients with `zxdg_exporter_v2` and `zxdg_importer_v2`
+//!
+//! A client can export one of its windows, and get an opaque string handle for it from the
+//! compositor. This handle can be passed to another client, typically over D-Bus, which imports
+//! it and sets the imported window as the parent of one of its own windows. This is how dialogs
+//! opened by a portal backend are attached to the window of the application that requested them.
+//!
+//! These globals are not part of the [`default_environment!`](../macro.default_environment.html),
+//! you need to add them to your environment, for example as
+//! [`SimpleGlobal`](../environment/struct.SimpleGlobal.html)s.
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use wayland_client::DispatchData;
+
+use wayland_protocols::unstable::xdg_foreign::v2::client::{
+    zxdg_exported_v2, zxdg_exporter_v2::ZxdgExporterV2, zxdg_imported_v2,
+    zxdg_importer_v2::ZxdgImporterV2,
+};
+
+use crate::environment::{Environment, GlobalError, GlobalHandler};
+use crate::window::{Frame, Window};
+
+/// Possible errors of the export and import helpers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForeignError {
+    /// The compositor does not support exporting or importing windows
+    Global(GlobalError),
+    /// The window does not use the `xdg_shell` protocol, and cannot be exported or parented
+    NotXdgToplevel,
+}
+
+impl fmt::Display for ForeignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForeignError::Global(err) => write!(f, "{}", err),
+            ForeignError::NotXdgToplevel => f.write_str("the window is not an xdg_toplevel"),
+        }
+    }
+}
+
+impl std::error::Error for ForeignError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ForeignError::Global(err) => Some(err),
+            ForeignError::NotXdgToplevel => None,
+        }
+    }
+}
+
+impl From<GlobalError> for ForeignError {
+    fn from(err: GlobalError) -> ForeignError {
+        ForeignError::Global(err)
+    }
+}
+
+/// An exported window
+///
+/// The handle of the window is sent by the compositor shortly after the export. Dropping
+/// the `ExportedHandle` revokes it: the clients that imported the window are notified, and
+/// the parent relationships they set are removed.
+pub struct ExportedHandle {
+    exported: zxdg_exported_v2::ZxdgExportedV2,
+    handle: Rc<RefCell<Option<String>>>,
+}
+
+impl ExportedHandle {
+    /// The handle of the window, if the compositor has sent it yet
+    pub fn handle(&self) -> Option<String> {
+        self.handle.borrow().clone()
+    }
+}
+
+impl Drop for ExportedHandle {
+    fn drop(&mut self) {
+        if self.exported.as_ref().is_alive() {
+            self.exported.destroy();
+        }
+    }
+}
+
+/// A window of another client
+///
+/// It is given to you by
+/// [`Environment::import_and_set_parent`](../environment/struct.Environment.html#method.import_and_set_parent).
+/// Dropping it removes the parent relationships it set.
+pub struct ImportedToplevel {
+    imported: zxdg_imported_v2::ZxdgImportedV2,
+}
+
+impl ImportedToplevel {
+    /// Set the imported window as the parent of another of your windows
+    pub fn set_parent_of<F: Frame + 'static>(
+        &self,
+        window: &Window<F>,
+    ) -> Result<(), ForeignError> {
+        if window.xdg_toplevel().is_none() {
+            return Err(ForeignError::NotXdgToplevel);
+        }
+        if self.imported.as_ref().is_alive() {
+            self.imported.set_parent_of(window.surface());
+        }
+        Ok(())
+    }
+
+    /// Whether the imported window is still valid
+    ///
+    /// It becomes invalid once the other client revoked the export, or destroyed its window.
+    pub fn is_valid(&self) -> bool {
+        self.imported.as_ref().is_alive()
+    }
+}
+
+impl Drop for ImportedToplevel {
+    fn drop(&mut self) {
+        if self.imported.as_ref().is_alive() {
+            self.imported.destroy();
+        }
+    }
+}
+
+impl<E: GlobalHandler<ZxdgExporterV2>> Environment<E> {
+    /// Export a window, to let other clients use it as the parent of their windows
+    ///
+    /// The callback is invoked with the handle of the window once the compositor sent it,
+    /// you can then pass it to the other client.
+    pub fn export_toplevel<F, C>(
+        &self,
+        window: &Window<F>,
+        callback: C,
+    ) -> Result<ExportedHandle, ForeignError>
+    where
+        F: Frame + 'static,
+        C: FnOnce(&str, DispatchData) + 'static,
+    {
+        if window.xdg_toplevel().is_none() {
+            return Err(ForeignError::NotXdgToplevel);
+        }
+        let exporter = self.try_get_global::<ZxdgExporterV2>()?;
+        let exported = exporter.export_toplevel(window.surface());
+        let handle = Rc::new(RefCell::new(None));
+        let my_handle = handle.clone();
+        let mut callback = Some(callback);
+        exported.quick_assign(move |_, event, ddata| match event {
+            zxdg_exported_v2::Event::Handle { handle } => {
+                if let Some(callback) = callback.take() {
+                    callback(&handle, ddata);
+                }
+                *my_handle.borrow_mut() = Some(handle);
+            }
+            _ => unreachable!(),
+        });
+        Ok(ExportedHandle {
+            exported: exported.detach(),
+            handle,
+        })
+    }
+}
+
+impl<E: GlobalHandler<ZxdgImporterV2>> Environment<E> {
+    /// Import a window of another client from its handle, and set it as the parent of your window
+    ///
+    /// The callback is invoked if the imported window becomes invalid, because the other
+    /// client revoked its export or destroyed its window. The parent relationship is then
+    /// removed.
+    pub fn import_and_set_parent<F, C>(
+        &self,
+        handle: &str,
+        window: &Window<F>,
+        on_destroyed: C,
+    ) -> Result<ImportedToplevel, ForeignError>
+    where
+        F: Frame + 'static,
+        C: FnOnce(DispatchData) + 'static,
+    {
+        if window.xdg_toplevel().is_none() {
+            return Err(ForeignError::NotXdgToplevel);
+        }
+        let importer = self.try_get_global::<ZxdgImporterV2>()?;
+        let imported = importer.import_toplevel(handle.into());
+        let mut on_destroyed = Some(on_destroyed);
+        imported.quick_assign(move |imported, event, ddata| match event {
+            zxdg_imported_v2::Event::Destroyed => {
+                // the imported object is inert, it must be destroyed
+                imported.destroy();
+                if let Some(callback) = on_destroyed.take() {
+                    callback(ddata);
+                }
+            }
+            _ => unreachable!(),
+        });
+        imported.set_parent_of(window.surface());
+        Ok(ImportedToplevel {
+            imported: imported.detach(),
+        })
+    }
+}